@@ -0,0 +1,81 @@
+// Times culling and ray casts through the BVH against brute force, over random boxes and the
+// degenerate scenes: every box the same, and every box a single point. That both agree is checked
+// by the tests in bvh.rs, this is only for the timings.
+//
+//   cargo run --release --example bench_bvh -- [count] [--seed n]
+
+use WGpuPlayground::bvh::{brute_force_frustum, brute_force_ray_cast, Bvh};
+use WGpuPlayground::camera::{Camera, Projection};
+use WGpuPlayground::math::{Aabb, Frustum, Rng, Vec3};
+
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    let count = args.get(1).and_then(|count| count.parse().ok()).unwrap_or(50_000usize);
+    let seed = args
+        .iter()
+        .position(|arg| arg == "--seed")
+        .and_then(|i| args.get(i + 1))
+        .map(|seed| seed.parse().expect("--seed needs a number"))
+        .unwrap_or(1);
+
+    let mut rng = Rng::new(seed);
+    let extent = (count as f32).sqrt() * 2.0;
+    let random = (0..count)
+        .map(|_| {
+            let center = Vec3::new(rng.range(-extent, extent), rng.range(-2.0, 2.0), rng.range(-extent, extent));
+            let half = Vec3::new(rng.range(0.1, 1.0), rng.range(0.1, 1.0), rng.range(0.1, 1.0));
+            Aabb { min: center - half, max: center + half }
+        })
+        .collect::<Vec<_>>();
+    let same = vec![Aabb { min: Vec3::new(-0.5, -0.5, -0.5), max: Vec3::new(0.5, 0.5, 0.5) }; count];
+    let points = random.iter().map(|aabb| Aabb { min: aabb.min, max: aabb.min }).collect::<Vec<_>>();
+
+    for (name, aabbs) in [("random", &random), ("identical", &same), ("points", &points)] {
+        let start = std::time::Instant::now();
+        let bvh = Bvh::build(aabbs);
+        let build = start.elapsed();
+
+        let cameras = (0..64)
+            .map(|_| Camera {
+                eye: Vec3::new(rng.range(-extent, extent), rng.range(1.0, 20.0), rng.range(-extent, extent)),
+                target: Vec3::new(rng.range(-extent, extent), 0.0, rng.range(-extent, extent)),
+                up: Vec3::Y,
+                aspect: 16.0 / 9.0,
+                fovy: rng.range(30.0, 90.0),
+                znear: 0.1,
+                zfar: rng.range(20.0, 500.0),
+                projection: Projection::Perspective,
+            })
+            .collect::<Vec<_>>();
+        let frustums = cameras.iter().map(|camera| Frustum::from_view_proj(&camera.build_view_projection_matrix())).collect::<Vec<_>>();
+        let rays = cameras.iter().map(|camera| (camera.eye, camera.target - camera.eye)).collect::<Vec<_>>();
+
+        let start = std::time::Instant::now();
+        for frustum in &frustums {
+            std::hint::black_box(brute_force_frustum(frustum, aabbs));
+        }
+        let brute_cull = start.elapsed();
+        let start = std::time::Instant::now();
+        let mut out = Vec::new();
+        for frustum in &frustums {
+            out.clear();
+            bvh.query_frustum(frustum, aabbs, &mut out);
+            std::hint::black_box(&out);
+        }
+        let bvh_cull = start.elapsed();
+
+        let start = std::time::Instant::now();
+        for &(origin, direction) in &rays {
+            std::hint::black_box(brute_force_ray_cast(origin, direction, 10.0, aabbs));
+        }
+        let brute_rays = start.elapsed();
+        let start = std::time::Instant::now();
+        let hits = rays.iter().filter(|&&(origin, direction)| bvh.ray_cast(origin, direction, 10.0, aabbs).is_some()).count();
+        let bvh_rays = start.elapsed();
+
+        println!(
+            "{:>9}: {} boxes, build {:.2?}, 64 frustums {:.2?} (brute force {:.2?}), 64 rays {:.2?} (brute force {:.2?}), {} hits",
+            name, count, build, bvh_cull, brute_cull, bvh_rays, brute_rays, hits,
+        );
+    }
+}
@@ -0,0 +1,186 @@
+// Times vertex cache and overdraw ordering on the primitives and a high poly sphere, and prints the
+// cache stats before and after. Then the GPU time of indexed draws of the sphere in its generated
+// order, shuffled and optimized (skipped without an adapter). That the triangles come back
+// unchanged is checked by the tests in optimize.rs, this is only for the numbers.
+//
+//   cargo run --release --example bench_mesh_optimize
+
+use wgpu::util::DeviceExt;
+use WGpuPlayground::math::Rng;
+use WGpuPlayground::optimize::{cache_stats, optimize, optimize_triangle_list, Indices, CACHE_SIZE};
+use WGpuPlayground::pipeline::PipelineConfig;
+use WGpuPlayground::{mesh, primitives, Vertex};
+
+const SHADER: &str = r#"
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+}
+
+// Every instance turned a bit further, all of them on top of each other
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, @location(1) color: vec3<f32>, @builtin(instance_index) instance: u32) -> VertexOutput {
+    let angle = f32(instance) * 0.7;
+    let p = vec3<f32>(position.x * cos(angle) - position.z * sin(angle), position.y, position.x * sin(angle) + position.z * cos(angle));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(p.xy * 1.8, 0.5 - p.z * 0.5, 1.0);
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color, 1.0);
+}
+"#;
+
+fn shuffled(vertices: &[Vertex], seed: u64) -> Vec<Vertex> {
+    let mut triangles = vertices.chunks_exact(3).collect::<Vec<_>>();
+    let mut rng = Rng::new(seed);
+    for i in (1..triangles.len()).rev() {
+        triangles.swap(i, rng.next_u32() as usize % (i + 1));
+    }
+    triangles.concat()
+}
+
+fn main() {
+    let sphere = primitives::uv_sphere(0.5, 512, 256);
+    let mut cases = primitives::NAMES
+        .iter()
+        .filter_map(|&name| primitives::by_name(name).map(|surface| (name.to_string(), surface.mesh())))
+        .collect::<Vec<_>>();
+    cases.push(("sphere 512 x 256".to_string(), sphere.clone()));
+    cases.push(("sphere shuffled".to_string(), shuffled(&sphere, 1)));
+    for (name, vertices) in cases {
+        let start = std::time::Instant::now();
+        let (_, optimized) = optimize_triangle_list(&vertices);
+        println!(
+            "{:<24} {:>7} triangles {:>7} vertices  {} -> {}  {:.2?}",
+            name,
+            vertices.len() / 3,
+            optimized.vertices.len(),
+            optimized.before,
+            optimized.after,
+            start.elapsed()
+        );
+    }
+
+    match pollster::block_on(request_device()) {
+        Some((device, queue)) => bench_gpu(&device, &queue, &sphere),
+        None => println!("No adapter, GPU timing skipped"),
+    }
+}
+
+async fn request_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions::default()).await?;
+    adapter.request_device(&wgpu::DeviceDescriptor::default(), None).await.ok()
+}
+
+// Measured around a blocking poll, so it includes submission
+fn bench_gpu(device: &wgpu::Device, queue: &wgpu::Queue, sphere: &[Vertex]) {
+    const SIZE: u32 = 512;
+    const INSTANCES: u32 = 4;
+    const FRAMES: u32 = 10;
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Mesh Optimize Bench Shader"),
+        source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+    });
+    let config = PipelineConfig {
+        color_format: wgpu::TextureFormat::Rgba8Unorm,
+        depth_format: wgpu::TextureFormat::Depth32Float,
+        depth_write: true,
+        sample_count: 1,
+        unclipped_depth: false,
+        overlay_depth_bias: wgpu::DepthBiasState::default(),
+    };
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Mesh Optimize Bench Pipeline"),
+        layout: None,
+        vertex: wgpu::VertexState { module: &shader, entry_point: "vs_main", buffers: &[Vertex::desc()] },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(config.color_format.into())],
+        }),
+        primitive: wgpu::PrimitiveState { cull_mode: Some(wgpu::Face::Back), ..Default::default() },
+        depth_stencil: Some(config.depth_state()),
+        multisample: config.multisample(),
+        multiview: None,
+    });
+    let target = |format, label| {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d { width: SIZE, height: SIZE, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        }).create_view(&wgpu::TextureViewDescriptor::default())
+    };
+    let color = target(config.color_format, "Mesh Optimize Bench Target");
+    let depth = target(config.depth_format, "Mesh Optimize Bench Depth");
+
+    let (generated, generated_indices) = mesh::index_vertices(sphere);
+    let (shuffled, shuffled_indices) = mesh::index_vertices(&shuffled(sphere, 1));
+    let optimized = optimize(&shuffled, &shuffled_indices);
+    println!("{} triangles x {} instances, {} x {}", sphere.len() / 3, INSTANCES, SIZE, SIZE);
+    for (name, vertices, indices) in [
+        ("generated", &generated, &generated_indices),
+        ("shuffled", &shuffled, &shuffled_indices),
+        ("optimized", &optimized.vertices, &optimized.indices),
+    ] {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mesh Optimize Bench Vertices"),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let indices = Indices::new(indices);
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mesh Optimize Bench Indices"),
+            contents: indices.bytes(),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let frame = || {
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Mesh Optimize Bench Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &color,
+                        resolve_target: None,
+                        ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::BLACK), store: true },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &depth,
+                        depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Clear(1.0), store: false }),
+                        stencil_ops: None,
+                    }),
+                });
+                render_pass.set_pipeline(&pipeline);
+                render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                render_pass.set_index_buffer(index_buffer.slice(..), indices.format());
+                render_pass.draw_indexed(0..indices.len() as u32, 0, 0..INSTANCES);
+            }
+            queue.submit(std::iter::once(encoder.finish()));
+            device.poll(wgpu::Maintain::Wait);
+        };
+        // First frames pay for pipeline and buffer setup
+        frame();
+        frame();
+        let start = std::time::Instant::now();
+        for _ in 0..FRAMES {
+            frame();
+        }
+        println!(
+            "{:<10} {:?} {}  {:.2} ms a frame",
+            name,
+            indices.format(),
+            cache_stats(&(0..indices.len()).map(|i| indices.get(i)).collect::<Vec<_>>(), vertices.len(), CACHE_SIZE),
+            start.elapsed().as_secs_f64() * 1000.0 / FRAMES as f64
+        );
+    }
+}
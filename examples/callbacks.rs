@@ -0,0 +1,31 @@
+// Drives State from outside the crate through run_with_callbacks: sets up a torus over the grid
+// with a crosshair cursor for placing decals, and fades the background every frame.
+//
+//   cargo run --example callbacks
+
+use winit::window::CursorIcon;
+use WGpuPlayground::grid::GridParams;
+use WGpuPlayground::mesh::MeshOptions;
+use WGpuPlayground::{primitives, run_with_callbacks, RunOptions};
+
+fn main() {
+    let start = std::time::Instant::now();
+    pollster::block_on(run_with_callbacks(
+        RunOptions::default(),
+        |state| {
+            // Left click places decals
+            state.set_cursor_icon(CursorIcon::Crosshair);
+            state.set_infinite_grid(true, GridParams::default());
+            if let Some(torus) = primitives::by_name("torus") {
+                if let Err(e) = state.set_surface_mesh(&torus, MeshOptions::default()) {
+                    eprintln!("{}", e);
+                }
+            }
+        },
+        move |state| {
+            let t = (start.elapsed().as_secs_f64() * 0.5).sin() * 0.5 + 0.5;
+            state.set_clear_color(wgpu::Color { r: 0.1, g: 0.1 + t * 0.2, b: 0.3, a: 1.0 });
+        },
+        |reason| reason.exit_code(),
+    ));
+}
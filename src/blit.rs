@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use crate::reflection::{ReflectedLayout, ShaderReflection};
+use crate::texture;
+
+// Fullscreen copy of one texture into another through a shader, works across formats and sizes.
+// Pipelines are created lazily, one per target format
+pub struct Blitter {
+    shader: wgpu::ShaderModule,
+    bind_group_layout: ReflectedLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    sampler: wgpu::Sampler,
+    pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+}
+
+impl Blitter {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("blit.wgsl"));
+
+        let bind_group_layout = ShaderReflection::new("Blit", include_str!("blit.wgsl"))
+            .and_then(|shader| shader.layout(device, 0))
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Blit Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout.layout],
+            push_constant_ranges: &[],
+        });
+
+        // Linear so blitting to a different size gets filtered instead of point sampled
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Blit Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            shader,
+            bind_group_layout,
+            pipeline_layout,
+            sampler,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    fn pipeline(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) -> &wgpu::RenderPipeline {
+        self.pipelines.entry(format).or_insert_with(|| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Blit Pipeline"),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &self.shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        })
+    }
+
+    // Builds the pipeline for `format` now instead of on the first blit into it
+    pub fn prewarm(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        self.pipeline(device, format);
+    }
+
+    // Source has to be a filterable float texture (no depth / integer formats)
+    pub fn blit(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        src: &wgpu::TextureView,
+        dst: &wgpu::TextureView,
+        dst_format: wgpu::TextureFormat,
+    ) {
+        self.pipeline(device, dst_format);
+        self.blit_prewarmed(device, encoder, src, dst, dst_format);
+    }
+
+    // Fills mips 1.. of every layer of `texture` from mip 0, each one a linear filtered blit of the
+    // one above. Halving sizes sample right between 2x2 texels, so that's a box filter; odd sizes
+    // lose a row or column's worth of weighting. The mip above is copied into a texture of its own
+    // first: GL (wgpu-hal 0.17) ignores a view's mip range when sampling, so sampling a mip view
+    // would pick the level from the derivatives and read the not yet filled mip below. Rendering
+    // into a mip view works everywhere. Needs RENDER_ATTACHMENT and COPY_SRC and a filterable,
+    // renderable color format
+    pub fn generate_mipmaps(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) {
+        for layer in 0..texture.depth_or_array_layers() {
+            for mip in 1..texture.mip_level_count() {
+                let mut size = texture.size().mip_level_size(mip - 1, texture.dimension());
+                size.depth_or_array_layers = 1;
+                let source = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Mipmap Source Texture"),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: texture.format(),
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    view_formats: &[],
+                });
+                encoder.copy_texture_to_texture(
+                    wgpu::ImageCopyTexture {
+                        texture,
+                        mip_level: mip - 1,
+                        origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    wgpu::ImageCopyTexture {
+                        texture: &source,
+                        mip_level: 0,
+                        origin: wgpu::Origin3d::ZERO,
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    size,
+                );
+                let src = source.create_view(&wgpu::TextureViewDescriptor::default());
+                let dst = texture::create_mip_view(texture, mip, layer);
+                self.blit(device, encoder, &src, &dst, texture.format());
+            }
+        }
+    }
+
+    // Same as blit for when only &self is around (e.g. inside a render graph pass).
+    // Panics unless prewarm / blit already ran for dst_format
+    pub fn blit_prewarmed(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        src: &wgpu::TextureView,
+        dst: &wgpu::TextureView,
+        dst_format: wgpu::TextureFormat,
+    ) {
+        let bind_group = self.bind_group_layout
+            .create_bind_group(device, &[
+                ("t_source".into(), wgpu::BindingResource::TextureView(src)),
+                ("s_source".into(), wgpu::BindingResource::Sampler(&self.sampler)),
+            ])
+            .unwrap_or_else(|e| panic!("{}", e));
+        let pipeline = self.pipelines.get(&dst_format).expect("Blit pipeline not prewarmed");
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: dst,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Every pixel gets overwritten anyway
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 64x64 1 pixel checkerboard mipmapped with generate_mipmaps has to average to grey on every
+    // mip below 0, then a pass into mip 2 alone has to leave its neighbours as they were
+    #[test]
+    fn mipmaps_average_and_mips_render_alone() {
+        let Some((device, queue)) = crate::shader_test::device() else {
+            return;
+        };
+        let size = 64;
+        let mip_count = texture::full_mip_count(size, size);
+        let format = wgpu::TextureFormat::Rgba8Unorm;
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Mip Check Texture"),
+            size: wgpu::Extent3d { width: size, height: size, depth_or_array_layers: 1 },
+            mip_level_count: mip_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture { texture: &target, mip_level: 0, origin: wgpu::Origin3d::ZERO, aspect: wgpu::TextureAspect::All },
+            // texture::checkerboard_rgba's squares are size / 8 across, this needs single pixels
+            &(0..size * size).flat_map(|i| if (i % size + i / size) % 2 == 0 { [0, 0, 0, 255] } else { [255; 4] }).collect::<Vec<u8>>(),
+            wgpu::ImageDataLayout { offset: 0, bytes_per_row: Some(4 * size), rows_per_image: Some(size) },
+            wgpu::Extent3d { width: size, height: size, depth_or_array_layers: 1 },
+        );
+
+        let mut blitter = Blitter::new(device);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Mip Check Encoder") });
+        blitter.generate_mipmaps(device, &mut encoder, &target);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let grey = |texels: &[u8]| texels.chunks_exact(4).all(|texel| texel[..3].iter().all(|&c| c.abs_diff(128) <= 1) && texel[3] == 255);
+        for mip in 1..mip_count {
+            let texels = crate::readback::read_texture_mip(device, queue, &target, mip);
+            assert!(grey(&texels), "Mip {} of the checkerboard isn't grey, starts with {:?}", mip, &texels[..4]);
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Mip Check Encoder") });
+        {
+            let view = texture::create_mip_view(&target, 2, 0);
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Mip Check Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::RED), store: true },
+                })],
+                depth_stencil_attachment: None,
+            });
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        for mip in 1..mip_count {
+            let texels = crate::readback::read_texture_mip(device, queue, &target, mip);
+            let red = texels.chunks_exact(4).all(|texel| texel == [255, 0, 0, 255]);
+            assert!((mip == 2) == red && (mip == 2 || grey(&texels)), "After rendering to mip 2, mip {} starts with {:?}", mip, &texels[..4]);
+        }
+    }
+}
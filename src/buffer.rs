@@ -0,0 +1,326 @@
+use std::sync::Arc;
+
+// GPU buffer that is rewritten from the CPU and reallocated (doubling) when the data outgrows it
+pub struct GrowableBuffer {
+    buffer: wgpu::Buffer,
+    capacity: wgpu::BufferAddress, // In bytes
+    usage: wgpu::BufferUsages,
+    label: &'static str,
+}
+
+impl GrowableBuffer {
+    pub fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        usage: wgpu::BufferUsages,
+        capacity: wgpu::BufferAddress,
+    ) -> Self {
+        // COPY_DST is what write_buffer needs
+        let usage = usage | wgpu::BufferUsages::COPY_DST;
+        let buffer = Self::create(device, label, usage, capacity);
+        Self { buffer, capacity, usage, label }
+    }
+
+    fn create(
+        device: &wgpu::Device,
+        label: &'static str,
+        usage: wgpu::BufferUsages,
+        size: wgpu::BufferAddress,
+    ) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Returns true when the buffer had to be reallocated (bind groups pointing to it are stale then)
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, data: &[u8]) -> bool {
+        let size = data.len() as wgpu::BufferAddress;
+        let grown = size > self.capacity;
+        if grown {
+            self.capacity = size.next_power_of_two();
+            self.buffer = Self::create(device, self.label, self.usage, self.capacity);
+        }
+        if !data.is_empty() {
+            queue.write_buffer(&self.buffer, 0, data);
+        }
+        grown
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn capacity(&self) -> wgpu::BufferAddress {
+        self.capacity
+    }
+}
+
+// Where FrameArena::alloc put some data: `size` bytes at `offset` into `buffer`. Holds on to the
+// buffer, so a renderer can keep it until its next upload, but the bytes are only valid until the
+// arena is reset
+#[derive(Clone, Debug)]
+pub struct ArenaSlice {
+    buffer: Arc<wgpu::Buffer>,
+    offset: wgpu::BufferAddress,
+    size: wgpu::BufferAddress,
+}
+
+impl ArenaSlice {
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn offset(&self) -> wgpu::BufferAddress {
+        self.offset
+    }
+
+    pub fn size(&self) -> wgpu::BufferAddress {
+        self.size
+    }
+
+    // For set_vertex_buffer / set_index_buffer
+    pub fn slice(&self) -> wgpu::BufferSlice<'_> {
+        self.buffer.slice(self.offset..self.offset + self.size)
+    }
+
+    pub fn binding(&self) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &self.buffer,
+            offset: self.offset,
+            size: wgpu::BufferSize::new(self.size),
+        })
+    }
+}
+
+// Transient per frame data (debug lines, overlay quads, ...) sub-allocated from one big buffer
+// instead of a buffer per feature. Everything a frame allocates is written with queue.write_buffer,
+// which wgpu orders after the previous frame's submissions, so the same bytes can be reused every
+// frame. When a frame needs more than fits, another chunk is added for the rest of the frame and
+// reset() replaces all of them with one chunk big enough for that frame
+pub struct FrameArena {
+    chunks: Vec<Arc<wgpu::Buffer>>,
+    // Bytes used in the last chunk
+    cursor: wgpu::BufferAddress,
+    // Bytes used this frame over all chunks, padding included
+    used: wgpu::BufferAddress,
+    peak: wgpu::BufferAddress,
+    uniform_alignment: wgpu::BufferAddress,
+}
+
+impl FrameArena {
+    // Vertex, index and uniform data can all come from it
+    const USAGE: wgpu::BufferUsages = wgpu::BufferUsages::VERTEX
+        .union(wgpu::BufferUsages::INDEX)
+        .union(wgpu::BufferUsages::UNIFORM)
+        .union(wgpu::BufferUsages::COPY_DST)
+        // For check_frame_arena
+        .union(wgpu::BufferUsages::COPY_SRC);
+
+    pub fn new(device: &wgpu::Device, capacity: wgpu::BufferAddress) -> Self {
+        Self {
+            chunks: vec![Self::create(device, capacity)],
+            cursor: 0,
+            used: 0,
+            peak: 0,
+            uniform_alignment: device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress,
+        }
+    }
+
+    fn create(device: &wgpu::Device, size: wgpu::BufferAddress) -> Arc<wgpu::Buffer> {
+        Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame Arena"),
+            size: size.max(wgpu::COPY_BUFFER_ALIGNMENT),
+            usage: Self::USAGE,
+            mapped_at_creation: false,
+        }))
+    }
+
+    // Call once per frame, before anything allocates. Everything handed out before is free again
+    pub fn reset(&mut self, device: &wgpu::Device) {
+        if self.chunks.len() > 1 {
+            let chunk = Self::create(device, self.used.next_power_of_two());
+            self.chunks = vec![chunk];
+        }
+        self.cursor = 0;
+        self.used = 0;
+    }
+
+    // Vertex and index data, 4 byte aligned
+    pub fn alloc(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, data: &[u8]) -> ArenaSlice {
+        self.alloc_aligned(device, queue, data, wgpu::COPY_BUFFER_ALIGNMENT)
+    }
+
+    // Uniform data, aligned for binding at its offset (256 bytes on most adapters)
+    pub fn alloc_uniform(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, data: &[u8]) -> ArenaSlice {
+        self.alloc_aligned(device, queue, data, self.uniform_alignment)
+    }
+
+    // `alignment` is a power of two
+    pub fn alloc_aligned(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[u8],
+        alignment: wgpu::BufferAddress,
+    ) -> ArenaSlice {
+        let alignment = alignment.max(wgpu::COPY_BUFFER_ALIGNMENT);
+        // write_buffer only takes whole words
+        let size = wgpu::util::align_to(data.len() as wgpu::BufferAddress, wgpu::COPY_BUFFER_ALIGNMENT);
+        let mut offset = wgpu::util::align_to(self.cursor, alignment);
+        if offset + size > self.capacity_of_last() {
+            // The rest of the full chunk counts as used, so reset() makes the merged one big enough
+            self.used += self.capacity_of_last().saturating_sub(self.cursor);
+            let capacity = (self.capacity_of_last() * 2).max(size.next_power_of_two());
+            self.chunks.push(Self::create(device, capacity));
+            self.cursor = 0;
+            offset = 0;
+        }
+        self.used += offset.saturating_sub(self.cursor) + size;
+        self.peak = self.peak.max(self.used);
+        self.cursor = offset + size;
+
+        let buffer = self.chunks.last().unwrap().clone();
+        if data.len() as wgpu::BufferAddress == size {
+            queue.write_buffer(&buffer, offset, data);
+        } else {
+            let mut padded = data.to_vec();
+            padded.resize(size as usize, 0);
+            queue.write_buffer(&buffer, offset, &padded);
+        }
+        ArenaSlice { buffer, offset, size: data.len() as wgpu::BufferAddress }
+    }
+
+    fn capacity_of_last(&self) -> wgpu::BufferAddress {
+        self.chunks.last().unwrap().size()
+    }
+
+    // Bytes handed out this frame so far
+    pub fn used(&self) -> wgpu::BufferAddress {
+        self.used
+    }
+
+    // Most bytes any frame used
+    pub fn peak(&self) -> wgpu::BufferAddress {
+        self.peak
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.size()).sum()
+    }
+}
+
+// Buffers that are done on the CPU side but may still be read by frames in flight. They're
+// destroyed (memory freed right away, not whenever wgpu gets to it) once `frames` more frames
+// have started, see State::update
+pub struct DeferredDestruction {
+    frames: u64,
+    frame: u64,
+    // Frame each buffer was retired in, oldest first
+    retired: std::collections::VecDeque<(u64, wgpu::Buffer)>,
+}
+
+impl DeferredDestruction {
+    pub fn new(frames: u64) -> Self {
+        Self { frames, frame: 0, retired: std::collections::VecDeque::new() }
+    }
+
+    pub fn retire(&mut self, buffer: wgpu::Buffer) {
+        self.retired.push_back((self.frame, buffer));
+    }
+
+    // Call once per frame, before anything is recorded. Returns how many buffers were destroyed
+    pub fn next_frame(&mut self) -> usize {
+        self.frame += 1;
+        let mut destroyed = 0;
+        while let Some((frame, _)) = self.retired.front() {
+            if frame + self.frames > self.frame {
+                break;
+            }
+            let (_, buffer) = self.retired.pop_front().unwrap();
+            buffer.destroy();
+            destroyed += 1;
+        }
+        destroyed
+    }
+
+    pub fn len(&self) -> usize {
+        self.retired.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.retired.is_empty()
+    }
+
+    // Still allocated until destroyed
+    pub fn gpu_memory(&self) -> u64 {
+        self.retired.iter().map(|(_, buffer)| buffer.size()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two frames of mixed vertex and uniform allocations through a deliberately small arena: the first
+    // has to spill into more chunks, the second has to fit the one reset() merged them into. Every
+    // allocation is read back and compared, offsets checked for alignment and overlap
+    #[test]
+    fn frame_arena_allocations_read_back() {
+        let Some((device, queue)) = crate::shader_test::device() else {
+            return;
+        };
+        let mut arena = FrameArena::new(device, 1024);
+        for frame in 0..2 {
+            arena.reset(device);
+            let mut allocations = Vec::new();
+            for i in 0..40u32 {
+                // Odd sizes to check the padding, every fourth one a uniform
+                let data = (0..i * 37 % 301 + 1).map(|j| (i * 31 + j) as u8).collect::<Vec<_>>();
+                let slice = if i % 4 == 0 {
+                    arena.alloc_uniform(device, queue, &data)
+                } else {
+                    arena.alloc(device, queue, &data)
+                };
+                let alignment = if i % 4 == 0 { arena.uniform_alignment } else { wgpu::COPY_BUFFER_ALIGNMENT };
+                assert_eq!(slice.offset() % alignment, 0, "Frame {} allocation {} at offset {}, not {} byte aligned", frame, i, slice.offset(), alignment);
+                allocations.push((slice, data));
+            }
+            for (i, (a, _)) in allocations.iter().enumerate() {
+                for (b, _) in &allocations[i + 1..] {
+                    let overlap = Arc::ptr_eq(&a.buffer, &b.buffer) && a.offset < b.offset + b.size && b.offset < a.offset + a.size;
+                    assert!(!overlap, "Frame {} allocations at {} and {} overlap", frame, a.offset, b.offset);
+                }
+            }
+            if frame == 0 {
+                assert!(arena.chunks.len() > 1, "First frame fit the small arena, nothing spilled");
+            } else {
+                assert_eq!(arena.chunks.len(), 1, "Second frame still needed more chunks");
+            }
+
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Frame Arena Check Encoder") });
+            let readbacks = arena.chunks.iter()
+                .map(|chunk| {
+                    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("Frame Arena Check Readback"),
+                        size: chunk.size(),
+                        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                        mapped_at_creation: false,
+                    });
+                    encoder.copy_buffer_to_buffer(chunk, 0, &readback, 0, chunk.size());
+                    (chunk.clone(), readback)
+                })
+                .collect::<Vec<_>>();
+            queue.submit(std::iter::once(encoder.finish()));
+            for (chunk, readback) in &readbacks {
+                let bytes = crate::readback::map_read(device, readback, |bytes| bytes.to_vec());
+                for (slice, data) in allocations.iter().filter(|(slice, _)| Arc::ptr_eq(&slice.buffer, chunk)) {
+                    let range = slice.offset as usize..(slice.offset + slice.size) as usize;
+                    assert!(bytes[range] == data[..], "Frame {} allocation at {} reads back different", frame, slice.offset);
+                }
+            }
+        }
+    }
+}
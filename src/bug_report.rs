@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::json::Json;
+
+// Bug report bundles: a directory with whatever could be collected about the app at the time.
+// Every piece is written on its own, one failing (lost device, full disk, ...) doesn't stop the
+// others. What's missing is listed in README.txt
+//
+// The pieces that don't need State (log, capabilities, last known config) live in statics here,
+// so the panic hook can write them too
+
+// Lines kept by the log sink
+const LOG_LINES: usize = 500;
+
+static LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+// Set by State, see set_capabilities / set_config
+static CAPABILITIES: Mutex<String> = Mutex::new(String::new());
+static CONFIG: Mutex<String> = Mutex::new(String::new());
+
+// Panics while holding the lock shouldn't make the panic hook panic too
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// env_logger plus a copy of the last LOG_LINES lines for bug reports
+struct RingLogger {
+    inner: env_logger::Logger,
+}
+
+impl log::Log for RingLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata) || metadata.level() <= log::Level::Info
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.inner.matches(record) {
+            self.inner.log(record);
+        }
+        if record.level() <= log::Level::Info || self.inner.matches(record) {
+            let mut log = lock(&LOG);
+            if log.len() == LOG_LINES {
+                log.pop_front();
+            }
+            log.push_back(format!("[{} {}] {}", record.level(), record.target(), record.args()));
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+// Replaces env_logger::init(). The console still only shows what RUST_LOG asks for, the report
+// always gets info and up
+pub fn init_logger() {
+    let inner = env_logger::Builder::from_default_env().build();
+    let max_level = inner.filter().max(log::LevelFilter::Info);
+    log::set_boxed_logger(Box::new(RingLogger { inner })).expect("Logger already set");
+    log::set_max_level(max_level);
+}
+
+pub fn set_capabilities(json: String) {
+    *lock(&CAPABILITIES) = json;
+}
+
+pub fn set_config(text: String) {
+    *lock(&CONFIG) = text;
+}
+
+pub fn capabilities_json(adapter: &wgpu::Adapter, surface_formats: &[wgpu::TextureFormat]) -> String {
+    let info = adapter.get_info();
+    let debug = |value: &dyn std::fmt::Debug| Json::String(format!("{:?}", value));
+    let fields = [
+        ("name", Json::String(info.name.clone())),
+        ("vendor", Json::Number(info.vendor as f64)),
+        ("device", Json::Number(info.device as f64)),
+        ("device_type", debug(&info.device_type)),
+        ("driver", Json::String(info.driver.clone())),
+        ("driver_info", Json::String(info.driver_info.clone())),
+        ("backend", debug(&info.backend)),
+        ("features", debug(&adapter.features())),
+        ("limits", debug(&adapter.limits())),
+        ("downlevel", debug(&adapter.get_downlevel_capabilities())),
+        ("surface_formats", debug(&surface_formats)),
+    ];
+    let json = Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect());
+    format!("{:#}\n", json)
+}
+
+// New directory under `root` named after the current time
+pub fn new_report_dir(root: &Path, prefix: &str) -> std::io::Result<PathBuf> {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let dir = root.join(format!("{}-{}", prefix, seconds));
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+// Pieces a report has (or is still waiting for) and the ones that couldn't be captured
+#[derive(Default)]
+pub struct ReportContents {
+    pub written: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+impl ReportContents {
+    pub fn add(&mut self, file: &str, result: std::io::Result<()>) {
+        match result {
+            Ok(()) => self.written.push(file.to_string()),
+            Err(e) => self.missing.push(format!("{}: {}", file, e)),
+        }
+    }
+
+    pub fn write_readme(&self, dir: &Path) -> std::io::Result<()> {
+        let mut text = String::from("WGpuPlayground bug report\n\nFiles:\n");
+        for file in &self.written {
+            text += &format!("  {}\n", file);
+        }
+        if !self.missing.is_empty() {
+            text += "\nCouldn't capture:\n";
+            for missing in &self.missing {
+                text += &format!("  {}\n", missing);
+            }
+        }
+        fs::write(dir.join("README.txt"), text)
+    }
+}
+
+// Log, capabilities and the last config State reported
+pub fn write_common(dir: &Path, contents: &mut ReportContents) {
+    let log = lock(&LOG).iter().map(|line| format!("{}\n", line)).collect::<String>();
+    contents.add("log.txt", fs::write(dir.join("log.txt"), log));
+
+    let capabilities = lock(&CAPABILITIES).clone();
+    let result = if capabilities.is_empty() {
+        Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no adapter yet"))
+    } else {
+        fs::write(dir.join("capabilities.json"), capabilities)
+    };
+    contents.add("capabilities.json", result);
+
+    let config = lock(&CONFIG).clone();
+    contents.add("config.txt", fs::write(dir.join("config.txt"), config));
+}
+
+// Writes a report (everything that doesn't need State, plus the panic message and a backtrace)
+// into a new directory under `root` on panic, then runs the previous hook
+pub fn install_panic_hook(root: PathBuf) {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // Best effort, a panic in here would abort
+        if let Ok(dir) = new_report_dir(&root, "panic") {
+            let mut contents = ReportContents::default();
+            write_common(&dir, &mut contents);
+            let panic = format!("{}\n\n{}\n", info, std::backtrace::Backtrace::force_capture());
+            contents.add("panic.txt", fs::write(dir.join("panic.txt"), panic));
+            contents.missing.push("screenshot.png: not captured on panic".to_string());
+            contents.missing.push("render_graph.txt: not captured on panic".to_string());
+            contents.missing.push("input.txt: not captured on panic".to_string());
+            if contents.write_readme(&dir).is_ok() {
+                log::error!("Bug report written to {}", dir.display());
+            }
+        }
+        previous(info);
+    }));
+}
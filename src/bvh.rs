@@ -0,0 +1,306 @@
+use crate::math::{Aabb, Frustum, Vec3};
+
+// Items per leaf, below this splitting costs more in traversal than it saves
+const LEAF_SIZE: usize = 4;
+// refit() rebuilds once the root has grown this much (surface area) since the last build, the
+// boxes overlap too much by then to cull anything
+const REBUILD_GROWTH: f32 = 2.0;
+
+#[derive(Copy, Clone, Debug)]
+struct Node {
+    aabb: Aabb,
+    // Leaf: first of `count` entries in `items`. Inner node: the left child, right is the next
+    // subtree after it (at `right`)
+    first: u32,
+    count: u32,
+    right: u32,
+}
+
+// Bounding volume hierarchy over item AABBs (instances in world space, see State), so frustum
+// culling and ray picking don't have to test every item. Built top down with median splits along
+// the longest axis of the centers, which always halves the items, also when all the boxes are the
+// same. Nodes are stored depth first, children after their parent
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    // Item indices, leaves point at ranges of this
+    items: Vec<u32>,
+    // Root surface area right after the last build
+    built_area: f32,
+}
+
+impl Bvh {
+    pub fn build(aabbs: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(aabbs.len() / LEAF_SIZE * 2 + 1),
+            items: (0..aabbs.len() as u32).collect(),
+            built_area: 0.0,
+        };
+        if !aabbs.is_empty() {
+            bvh.build_node(aabbs, 0, aabbs.len());
+            bvh.built_area = surface_area(&bvh.nodes[0].aabb);
+        }
+        bvh
+    }
+
+    fn build_node(&mut self, aabbs: &[Aabb], start: usize, end: usize) -> u32 {
+        let aabb = union(self.items[start..end].iter().map(|&i| aabbs[i as usize]));
+        let index = self.nodes.len() as u32;
+        self.nodes.push(Node { aabb, first: start as u32, count: (end - start) as u32, right: 0 });
+        if end - start <= LEAF_SIZE {
+            return index;
+        }
+
+        let centers = union(self.items[start..end].iter().map(|&i| {
+            let center = aabbs[i as usize].center();
+            Aabb { min: center, max: center }
+        }));
+        let extent = centers.max - centers.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        let key = |i: &u32| {
+            let center = aabbs[*i as usize].center();
+            [center.x, center.y, center.z][axis]
+        };
+        let middle = (start + end) / 2;
+        self.items[start..end].select_nth_unstable_by(middle - start, |a, b| key(a).total_cmp(&key(b)));
+
+        self.build_node(aabbs, start, middle);
+        let right = self.build_node(aabbs, middle, end);
+        let node = &mut self.nodes[index as usize];
+        node.count = 0;
+        node.first = index + 1;
+        node.right = right;
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    // Updates the boxes after items moved, `aabbs` has to be the same items as for build(). Rebuilds
+    // when that's cheaper in the long run (see REBUILD_GROWTH), returns true then
+    pub fn refit(&mut self, aabbs: &[Aabb]) -> bool {
+        if aabbs.len() != self.items.len() {
+            *self = Self::build(aabbs);
+            return true;
+        }
+        // Children come after their parent, so backwards has them done first
+        for index in (0..self.nodes.len()).rev() {
+            let node = self.nodes[index];
+            let aabb = if node.count > 0 {
+                let items = &self.items[node.first as usize..(node.first + node.count) as usize];
+                union(items.iter().map(|&i| aabbs[i as usize]))
+            } else {
+                union([self.nodes[node.first as usize].aabb, self.nodes[node.right as usize].aabb])
+            };
+            self.nodes[index].aabb = aabb;
+        }
+        if let Some(root) = self.nodes.first() {
+            if surface_area(&root.aabb) > self.built_area * REBUILD_GROWTH {
+                *self = Self::build(aabbs);
+                return true;
+            }
+        }
+        false
+    }
+
+    // Appends the items whose box intersects `frustum` (Frustum::intersects_aabb) to `out`, in no
+    // particular order
+    pub fn query_frustum(&self, frustum: &Frustum, aabbs: &[Aabb], out: &mut Vec<u32>) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![0u32];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            if !frustum.intersects_aabb(&node.aabb) {
+                continue;
+            }
+            if node.count > 0 {
+                let items = &self.items[node.first as usize..(node.first + node.count) as usize];
+                out.extend(items.iter().copied().filter(|&i| frustum.intersects_aabb(&aabbs[i as usize])));
+            } else {
+                stack.push(node.right);
+                stack.push(node.first);
+            }
+        }
+    }
+
+    // Closest item box hit by the ray and the distance along it (in units of `direction`), up to
+    // `max_distance`. Of boxes hit at the same distance the lowest index wins, like a front to back
+    // loop over all of them would
+    pub fn ray_cast(&self, origin: Vec3, direction: Vec3, max_distance: f32, aabbs: &[Aabb]) -> Option<(u32, f32)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inverse = Vec3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+        let mut best: Option<(u32, f32)> = None;
+        let mut stack = vec![0u32];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            let limit = best.map_or(max_distance, |(_, t)| t);
+            match node.aabb.ray_intersection(origin, inverse) {
+                Some(t) if t <= limit => {}
+                _ => continue,
+            }
+            if node.count > 0 {
+                for &item in &self.items[node.first as usize..(node.first + node.count) as usize] {
+                    let Some(t) = aabbs[item as usize].ray_intersection(origin, inverse) else { continue };
+                    let closer = match best {
+                        Some((best_item, best_t)) => t < best_t || (t == best_t && item < best_item),
+                        None => t <= max_distance,
+                    };
+                    if closer {
+                        best = Some((item, t));
+                    }
+                }
+            } else {
+                stack.push(node.right);
+                stack.push(node.first);
+            }
+        }
+        best
+    }
+}
+
+fn union(aabbs: impl IntoIterator<Item = Aabb>) -> Aabb {
+    aabbs
+        .into_iter()
+        .reduce(|a, b| a.including(b.min).including(b.max))
+        .unwrap_or(Aabb { min: Vec3::ZERO, max: Vec3::ZERO })
+}
+
+fn surface_area(aabb: &Aabb) -> f32 {
+    let size = aabb.max - aabb.min;
+    2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+}
+
+// Item indices a loop over every box would give for query_frustum, in index order. What the BVH
+// is checked and timed against, see the tests and examples/bench_bvh.rs
+pub fn brute_force_frustum(frustum: &Frustum, aabbs: &[Aabb]) -> Vec<u32> {
+    (0..aabbs.len() as u32).filter(|&i| frustum.intersects_aabb(&aabbs[i as usize])).collect()
+}
+
+// ray_cast without the BVH
+pub fn brute_force_ray_cast(origin: Vec3, direction: Vec3, max_distance: f32, aabbs: &[Aabb]) -> Option<(u32, f32)> {
+    let inverse = Vec3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+    let mut best: Option<(u32, f32)> = None;
+    for (item, aabb) in aabbs.iter().enumerate() {
+        let Some(t) = aabb.ray_intersection(origin, inverse) else { continue };
+        // Strictly closer, so ties keep the lower index
+        if t <= max_distance && best.is_none_or(|(_, best_t)| t < best_t) {
+            best = Some((item as u32, t));
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, Projection};
+    use crate::math::{Mat4, Rng};
+
+    const COUNT: usize = 2000;
+
+    fn extent() -> f32 {
+        (COUNT as f32).sqrt() * 2.0
+    }
+
+    fn random_boxes(rng: &mut Rng) -> Vec<Aabb> {
+        let extent = extent();
+        (0..COUNT)
+            .map(|_| {
+                let center = Vec3::new(rng.range(-extent, extent), rng.range(-2.0, 2.0), rng.range(-extent, extent));
+                let half = Vec3::new(rng.range(0.1, 1.0), rng.range(0.1, 1.0), rng.range(0.1, 1.0));
+                Aabb { min: center - half, max: center + half }
+            })
+            .collect()
+    }
+
+    // Culls with 64 random cameras and casts a ray from each, through the BVH and brute force.
+    // Every other ray goes at the origin, where the identical boxes are, so ties get hit too
+    fn assert_matches_brute_force(bvh: &Bvh, aabbs: &[Aabb], rng: &mut Rng) {
+        let extent = extent();
+        for i in 0..64 {
+            let camera = Camera {
+                eye: Vec3::new(rng.range(-extent, extent), rng.range(1.0, 20.0), rng.range(-extent, extent)),
+                target: Vec3::new(rng.range(-extent, extent), 0.0, rng.range(-extent, extent)),
+                up: Vec3::Y,
+                aspect: 16.0 / 9.0,
+                fovy: rng.range(30.0, 90.0),
+                znear: 0.1,
+                zfar: rng.range(20.0, 500.0),
+                projection: Projection::Perspective,
+            };
+            let frustum = Frustum::from_view_proj(&camera.build_view_projection_matrix());
+            let mut found = Vec::new();
+            bvh.query_frustum(&frustum, aabbs, &mut found);
+            found.sort_unstable();
+            assert_eq!(found, brute_force_frustum(&frustum, aabbs), "frustum {}", i);
+
+            let target = if i % 2 == 0 { Vec3::ZERO } else { camera.target };
+            let direction = target - camera.eye + Vec3::new(rng.range(-0.1, 0.1), 0.0, rng.range(-0.1, 0.1));
+            assert_eq!(
+                bvh.ray_cast(camera.eye, direction, 10.0, aabbs),
+                brute_force_ray_cast(camera.eye, direction, 10.0, aabbs),
+                "ray {}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn random_boxes_match_brute_force() {
+        let mut rng = Rng::new(1);
+        let aabbs = random_boxes(&mut rng);
+        assert_matches_brute_force(&Bvh::build(&aabbs), &aabbs, &mut rng);
+    }
+
+    // Median splits have to halve these too, and ray ties go to the lowest index
+    #[test]
+    fn identical_boxes_match_brute_force() {
+        let aabbs = vec![Aabb { min: Vec3::new(-0.5, -0.5, -0.5), max: Vec3::new(0.5, 0.5, 0.5) }; COUNT];
+        assert_matches_brute_force(&Bvh::build(&aabbs), &aabbs, &mut Rng::new(2));
+    }
+
+    #[test]
+    fn point_boxes_match_brute_force() {
+        let mut rng = Rng::new(3);
+        let aabbs = random_boxes(&mut rng).iter().map(|aabb| Aabb { min: aabb.min, max: aabb.min }).collect::<Vec<_>>();
+        assert_matches_brute_force(&Bvh::build(&aabbs), &aabbs, &mut rng);
+    }
+
+    // Small moves refit in place, the result has to be as good as a fresh build
+    #[test]
+    fn refit_matches_brute_force() {
+        let mut rng = Rng::new(4);
+        let mut aabbs = random_boxes(&mut rng);
+        let mut bvh = Bvh::build(&aabbs);
+        for aabb in &mut aabbs {
+            let offset = Vec3::new(rng.range(-1.0, 1.0), 0.0, rng.range(-1.0, 1.0));
+            *aabb = Aabb { min: aabb.min + offset, max: aabb.max + offset };
+        }
+        assert!(!bvh.refit(&aabbs), "Small moves shouldn't rebuild");
+        assert_matches_brute_force(&bvh, &aabbs, &mut rng);
+    }
+
+    #[test]
+    fn empty() {
+        let bvh = Bvh::build(&[]);
+        let mut found = Vec::new();
+        bvh.query_frustum(&Frustum::from_view_proj(&Mat4::IDENTITY), &[], &mut found);
+        assert!(found.is_empty());
+        assert_eq!(bvh.ray_cast(Vec3::ZERO, Vec3::X, 10.0, &[]), None);
+    }
+}
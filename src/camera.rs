@@ -0,0 +1,392 @@
+use crate::math::{perlin_1d, Aabb, Mat4, Vec3};
+use crate::pbr;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Camera {
+    pub eye: Vec3,
+    pub target: Vec3,
+    pub up: Vec3,
+    pub aspect: f32,
+    pub fovy: f32, // Degrees
+    pub znear: f32,
+    pub zfar: f32,
+    pub projection: Projection,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Projection {
+    Perspective,
+    // Sized so the plane through the target looks the same as in perspective, moving the eye
+    // closer zooms in like it would there
+    Orthographic,
+}
+
+impl Camera {
+    pub fn build_view_projection_matrix(&self) -> Mat4 {
+        let view = Mat4::look_at_rh(self.eye, self.target, self.up);
+        let proj = match self.projection {
+            Projection::Perspective => Mat4::perspective_rh(self.fovy.to_radians(), self.aspect, self.znear, self.zfar),
+            Projection::Orthographic => {
+                let half_height = (self.target - self.eye).length() * (self.fovy.to_radians() / 2.0).tan();
+                let half_width = half_height * self.aspect;
+                Mat4::orthographic_rh(-half_width, half_width, -half_height, half_height, self.znear, self.zfar)
+            }
+        };
+        proj * view
+    }
+}
+
+// Where a camera is and where it looks, without the projection. See State::set_home_camera
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraPose {
+    pub eye: Vec3,
+    pub target: Vec3,
+    pub up: Vec3,
+}
+
+impl CameraPose {
+    // Looks at the middle of `bounds` from `direction` (toward the eye), far enough away for its
+    // bounding sphere to fit both the vertical and the horizontal fov with a little margin
+    pub fn framing(bounds: &Aabb, direction: Vec3, fovy: f32, aspect: f32) -> Self {
+        let center = bounds.center();
+        let radius = ((bounds.max - bounds.min).length() / 2.0).max(0.001);
+        let half_fovy = fovy.to_radians() / 2.0;
+        let half_fovx = (half_fovy.tan() * aspect).atan();
+        let distance = radius / half_fovy.min(half_fovx).sin() * 1.1;
+        Self { eye: center + direction.normalize() * distance, target: center, up: Vec3::Y }
+    }
+}
+
+impl Camera {
+    pub fn pose(&self) -> CameraPose {
+        CameraPose { eye: self.eye, target: self.target, up: self.up }
+    }
+
+    pub fn set_pose(&mut self, pose: CameraPose) {
+        self.eye = pose.eye;
+        self.target = pose.target;
+        self.up = pose.up;
+    }
+}
+
+// Layout must match CameraUniform in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    view_proj: [[f32; 4]; 4],
+    // Clip space back to world, for shaders that need view rays (sky)
+    inv_view_proj: [[f32; 4]; 4],
+    // Linear color scale applied before output, see State::exposure
+    exposure: f32,
+    // FOG_OFF / FOG_LINEAR / FOG_EXPONENTIAL / FOG_EXPONENTIAL_SQUARED, the others are only read
+    // by the matching mode
+    fog_mode: u32,
+    fog_start: f32,
+    fog_end: f32,
+    fog_color: [f32; 3],
+    fog_density: f32,
+    // Fog goes by view space depth, the distance along forward, or for FOG_EXPONENTIAL_SQUARED the
+    // distance from the eye
+    eye: [f32; 3],
+    fog_height: f32,
+    forward: [f32; 3],
+    fog_height_falloff: f32,
+    // The one directional light, normalized and towards it. Color includes the intensity
+    sun_direction: [f32; 3],
+    // Scales image based lighting
+    ambient: f32,
+    sun_color: [f32; 3],
+    _padding3: f32,
+}
+
+// Fog modes in shader.wgsl
+const FOG_OFF: u32 = 0;
+const FOG_LINEAR: u32 = 1;
+const FOG_EXPONENTIAL: u32 = 2;
+const FOG_EXPONENTIAL_SQUARED: u32 = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FogFalloff {
+    // None before start, full fog from end on
+    Linear { start: f32, end: f32 },
+    // 1 - exp(-density * depth), never quite reaches full fog
+    Exponential { density: f32 },
+    // 1 - exp(-amount^2), amount being the density integrated along the view ray from `start` on.
+    // Density is `density` at `height` and scales by exp(-height_falloff * meters above it), so
+    // valleys fill up and the air thins out with altitude. Also fogs the sky, fully at the horizon
+    // and less the higher up it's looking. A height_falloff of 0 is plain exponential squared fog,
+    // which hides the sky entirely
+    ExponentialSquared { density: f32, start: f32, height: f32, height_falloff: f32 },
+}
+
+// Distance fog, fragments blend towards `color` with view space depth (distance from the eye for
+// ExponentialSquared). Color is linear and gets the exposure like the scene does
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FogParams {
+    pub color: [f32; 3],
+    pub falloff: FogFalloff,
+}
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CameraUniform {
+    pub fn new() -> Self {
+        Self {
+            view_proj: Mat4::IDENTITY.to_cols_array(),
+            inv_view_proj: Mat4::IDENTITY.to_cols_array(),
+            exposure: 1.0,
+            fog_mode: FOG_OFF,
+            fog_start: 0.0,
+            fog_end: 0.0,
+            fog_color: [0.0; 3],
+            fog_density: 0.0,
+            eye: [0.0; 3],
+            fog_height: 0.0,
+            forward: [0.0, 0.0, -1.0],
+            fog_height_falloff: 0.0,
+            sun_direction: Vec3::from(pbr::SUN_DIRECTION).normalize().to_array(),
+            ambient: 1.0,
+            sun_color: pbr::SUN_COLOR,
+            _padding3: 0.0,
+        }
+    }
+
+    pub fn update_view_proj(&mut self, camera: &Camera) {
+        self.set_view_proj(camera.build_view_projection_matrix());
+        self.eye = camera.eye.to_array();
+        self.forward = (camera.target - camera.eye).normalize().to_array();
+    }
+
+    // For matrices that aren't straight from a Camera (e.g. jittered for TAA)
+    pub fn set_view_proj(&mut self, view_proj: Mat4) {
+        self.view_proj = view_proj.to_cols_array();
+        self.inv_view_proj = view_proj.inverse().to_cols_array();
+    }
+
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure;
+    }
+
+    // `direction` is towards the light, doesn't need to be normalized
+    pub fn set_light(&mut self, direction: [f32; 3], color: [f32; 3], ambient: f32) {
+        self.sun_direction = Vec3::from(direction).normalize().to_array();
+        self.sun_color = color;
+        self.ambient = ambient;
+    }
+
+    // None turns fog off
+    pub fn set_fog(&mut self, fog: Option<&FogParams>) {
+        self.fog_mode = FOG_OFF;
+        let Some(fog) = fog else {
+            return;
+        };
+        self.fog_color = fog.color;
+        match fog.falloff {
+            FogFalloff::Linear { start, end } => {
+                self.fog_mode = FOG_LINEAR;
+                self.fog_start = start;
+                self.fog_end = end;
+            }
+            FogFalloff::Exponential { density } => {
+                self.fog_mode = FOG_EXPONENTIAL;
+                self.fog_density = density;
+            }
+            FogFalloff::ExponentialSquared { density, start, height, height_falloff } => {
+                self.fog_mode = FOG_EXPONENTIAL_SQUARED;
+                self.fog_density = density;
+                self.fog_start = start;
+                self.fog_height = height;
+                self.fog_height_falloff = height_falloff;
+            }
+        }
+    }
+}
+
+// Modifiers work on a copy of the camera right before the view matrix is built, so whatever drives
+// the base camera (fly, orbit, keyframes ...) never sees the offsets and they don't accumulate
+pub trait CameraModifier {
+    fn apply(&mut self, camera: &mut Camera, dt: f32);
+}
+
+// Exponential smoothing toward the incoming eye/target. Higher stiffness = snappier
+pub struct Smoothing {
+    pub stiffness: f32,
+    state: Option<(Vec3, Vec3)>,
+}
+
+impl Smoothing {
+    pub fn new(stiffness: f32) -> Self {
+        Self { stiffness, state: None }
+    }
+
+    // Jump straight to the current camera on the next frame (e.g. after teleporting)
+    pub fn reset(&mut self) {
+        self.state = None;
+    }
+}
+
+impl CameraModifier for Smoothing {
+    fn apply(&mut self, camera: &mut Camera, dt: f32) {
+        // Frame rate independent: 1 - e^(-k*dt) of the remaining distance is covered each frame
+        let t = 1.0 - (-self.stiffness * dt).exp();
+        let (eye, target) = match self.state {
+            Some((eye, target)) => (eye.lerp(camera.eye, t), target.lerp(camera.target, t)),
+            None => (camera.eye, camera.target),
+        };
+
+        self.state = Some((eye, target));
+        camera.eye = eye;
+        camera.target = target;
+    }
+}
+
+// Trauma based shake: trauma is 0..1, offsets scale with trauma^2 and trauma decays linearly
+pub struct Shake {
+    pub max_angle: f32, // Radians
+    pub max_offset: f32,
+    pub frequency: f32,
+    pub decay: f32, // Trauma lost per second
+    // Base noise seed, the channels use seed..seed + 6
+    pub seed: u32,
+    trauma: f32,
+    time: f32,
+}
+
+impl Default for Shake {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shake {
+    pub fn new() -> Self {
+        Self {
+            max_angle: 0.05,
+            max_offset: 0.1,
+            frequency: 15.0,
+            decay: 1.0,
+            trauma: 0.0,
+            time: 0.0,
+            seed: 0,
+        }
+    }
+
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).clamp(0.0, 1.0);
+    }
+
+    pub fn trauma(&self) -> f32 {
+        self.trauma
+    }
+}
+
+impl CameraModifier for Shake {
+    fn apply(&mut self, camera: &mut Camera, dt: f32) {
+        if self.trauma <= 0.0 {
+            return;
+        }
+
+        self.time += dt;
+        let amount = self.trauma * self.trauma;
+        let t = self.time * self.frequency;
+
+        let forward = camera.target - camera.eye;
+        let distance = forward.length();
+        let forward = forward.normalize();
+        let right = forward.cross(camera.up).normalize();
+        let up = right.cross(forward);
+
+        // Every channel gets its own noise seed so they don't move together
+        let noise = |channel: u32| perlin_1d(t, self.seed.wrapping_add(channel));
+        let yaw = self.max_angle * amount * noise(0);
+        let pitch = self.max_angle * amount * noise(1);
+        let roll = self.max_angle * amount * noise(2);
+        let offset = (right * noise(3) + up * noise(4) + forward * noise(5))
+            * (self.max_offset * amount);
+
+        camera.eye += offset;
+        camera.target += offset + right * (yaw.tan() * distance) + up * (pitch.tan() * distance);
+        camera.up = up * roll.cos() + right * roll.sin();
+
+        self.trauma = (self.trauma - self.decay * dt).max(0.0);
+    }
+}
+
+// Places the camera at an offset from a tracked position and looks slightly ahead of its motion
+pub struct Follow {
+    pub offset: Vec3,
+    pub look_ahead: f32, // Seconds of velocity to lead the target by
+    position: Vec3,
+    previous: Option<Vec3>,
+    velocity: Vec3,
+}
+
+impl Follow {
+    pub fn new(offset: Vec3, look_ahead: f32) -> Self {
+        Self {
+            offset,
+            look_ahead,
+            position: Vec3::ZERO,
+            previous: None,
+            velocity: Vec3::ZERO,
+        }
+    }
+
+    // Call every frame with the followed object's world position
+    pub fn track(&mut self, position: Vec3) {
+        self.position = position;
+    }
+}
+
+impl CameraModifier for Follow {
+    fn apply(&mut self, camera: &mut Camera, dt: f32) {
+        if let Some(previous) = self.previous {
+            if dt > 0.0 {
+                self.velocity = (self.position - previous) / dt;
+            }
+        }
+        self.previous = Some(self.position);
+
+        camera.eye = self.position + self.offset;
+        camera.target = self.position + self.velocity * self.look_ahead;
+    }
+}
+
+// Fixed order: follow decides where to be, smoothing eases there, shake goes on top
+pub struct CameraRig {
+    pub follow: Option<Follow>,
+    pub smoothing: Option<Smoothing>,
+    pub shake: Shake,
+}
+
+impl Default for CameraRig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CameraRig {
+    pub fn new() -> Self {
+        Self {
+            follow: None,
+            smoothing: Some(Smoothing::new(10.0)),
+            shake: Shake::new(),
+        }
+    }
+
+    pub fn apply(&mut self, camera: &Camera, dt: f32) -> Camera {
+        let mut camera = *camera;
+        if let Some(follow) = &mut self.follow {
+            follow.apply(&mut camera, dt);
+        }
+        if let Some(smoothing) = &mut self.smoothing {
+            smoothing.apply(&mut camera, dt);
+        }
+        self.shake.apply(&mut camera, dt);
+        camera
+    }
+}
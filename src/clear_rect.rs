@@ -0,0 +1,89 @@
+use crate::buffer::{ArenaSlice, FrameArena};
+use crate::pipeline::PipelineConfig;
+
+// wgpu clears always cover the whole attachment. This clears only the current scissor rect by
+// drawing a solid triangle over it, used to give split screen viewports their own background.
+// Color only, depth / stencil are left alone (the pass clears those fully anyway)
+pub struct ClearRects {
+    // This frame's colors in the frame arena
+    colors: Option<ArenaSlice>,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ClearRects {
+    pub fn new(device: &wgpu::Device, config: &PipelineConfig) -> Self {
+        Self {
+            colors: None,
+            pipeline: Self::create_pipeline(device, config),
+        }
+    }
+
+    // Call after the pipeline config changes
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device, config: &PipelineConfig) {
+        self.pipeline = Self::create_pipeline(device, config);
+    }
+
+    fn create_pipeline(device: &wgpu::Device, config: &PipelineConfig) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("clear_rect.wgsl"));
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Clear Rect Pipeline Layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Clear Rect Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x4],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Passes have a depth attachment, so the pipeline needs the format, but doesn't touch it
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: config.depth_format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: config.multisample(),
+            multiview: None,
+        })
+    }
+
+    // Colors for this frame, draw(index) clears with colors[index]. No colors still uploads a black
+    // one, for State::prewarm
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, arena: &mut FrameArena, colors: &[wgpu::Color]) {
+        let mut colors = colors.iter()
+            .map(|c| [c.r as f32, c.g as f32, c.b as f32, c.a as f32])
+            .collect::<Vec<_>>();
+        if colors.is_empty() {
+            colors.push([0.0, 0.0, 0.0, 1.0]);
+        }
+        self.colors = Some(arena.alloc(device, queue, bytemuck::cast_slice(&colors)));
+    }
+
+    // Clears the scissor rect currently set on the pass
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, index: u32) {
+        let colors = self.colors.as_ref().expect("ClearRects drawn before upload");
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, colors.slice());
+        render_pass.draw(0..3, index..index + 1);
+    }
+}
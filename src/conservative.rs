@@ -0,0 +1,264 @@
+use wgpu::util::DeviceExt;
+
+// Side length of the offscreen targets, small so single pixels are easy to see when upscaled
+const TARGET_SIZE: u32 = 24;
+const TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+// Radians per second
+const ROTATION_SPEED: f32 = 0.3;
+
+// Thin rotating triangle rendered into tiny targets with regular and conservative rasterization,
+// shown upscaled side by side (left regular, right conservative). Regular rasterization only
+// covers pixels whose center is inside the triangle, so a sliver breaks up into gaps. Conservative
+// covers every pixel the triangle touches at all
+pub struct ConservativeDemo {
+    pub visible: bool,
+    angle: f32,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    triangle_pipeline: wgpu::RenderPipeline,
+    // None without Features::CONSERVATIVE_RASTERIZATION
+    conservative_pipeline: Option<wgpu::RenderPipeline>,
+    display_pipeline: wgpu::RenderPipeline,
+    unsupported_pipeline: wgpu::RenderPipeline,
+    // Regular and conservative, (view, display bind group)
+    targets: [(wgpu::TextureView, wgpu::BindGroup); 2],
+}
+
+impl ConservativeDemo {
+    // Requested when the adapter has it, the demo shows a placeholder otherwise
+    pub const FEATURES: wgpu::Features = wgpu::Features::CONSERVATIVE_RASTERIZATION;
+
+    pub fn new(device: &wgpu::Device, surface_format: wgpu::TextureFormat) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("conservative.wgsl"));
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Conservative Demo Uniform Buffer"),
+            // Padded to the 16 byte minimum uniform size
+            contents: bytemuck::cast_slice(&[0.0f32; 4]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Conservative Demo Uniform Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
+        });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Conservative Demo Uniform Bind Group"),
+            layout: &uniform_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                }
+            ],
+        });
+        let display_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Conservative Demo Display Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let triangle_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Conservative Demo Triangle Layout"),
+            bind_group_layouts: &[&uniform_layout],
+            push_constant_ranges: &[],
+        });
+        let triangle_pipeline = |conservative| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(if conservative { "Conservative Triangle Pipeline" } else { "Triangle Pipeline" }),
+                layout: Some(&triangle_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_triangle",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_triangle",
+                    targets: &[Some(TARGET_FORMAT.into())],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    // Requires Features::CONSERVATIVE_RASTERIZATION
+                    conservative,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let conservative_pipeline = device.features()
+            .contains(Self::FEATURES)
+            .then(|| triangle_pipeline(true));
+        let triangle_pipeline = triangle_pipeline(false);
+
+        let display_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Conservative Demo Display Pipeline Layout"),
+            // Group 0 isn't used by the display entry points, but the shader numbers it that way
+            bind_group_layouts: &[&uniform_layout, &display_layout],
+            push_constant_ranges: &[],
+        });
+        let display_pipeline = |fs_entry| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(fs_entry),
+                layout: Some(&display_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_display",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: fs_entry,
+                    targets: &[Some(surface_format.into())],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Conservative Demo Sampler"),
+            // Upscaled pixels stay hard squares
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let target = |label| {
+            let view = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: TARGET_SIZE,
+                    height: TARGET_SIZE,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: TARGET_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            }).create_view(&wgpu::TextureViewDescriptor::default());
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout: &display_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                ],
+            });
+            (view, bind_group)
+        };
+
+        Self {
+            visible: false,
+            angle: 0.0,
+            uniform_buffer,
+            uniform_bind_group,
+            triangle_pipeline,
+            conservative_pipeline,
+            display_pipeline: display_pipeline("fs_display"),
+            unsupported_pipeline: display_pipeline("fs_unsupported"),
+            targets: [target("Regular Raster Target"), target("Conservative Raster Target")],
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.conservative_pipeline.is_some()
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: f32) {
+        self.angle = (self.angle + dt * ROTATION_SPEED) % std::f32::consts::TAU;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.angle]));
+    }
+
+    // Draws both halves over the whole of `view` (width x height pixels), which has the surface format
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, width: u32, height: u32) {
+        let pipelines = [Some(&self.triangle_pipeline), self.conservative_pipeline.as_ref()];
+        for ((target, _), pipeline) in self.targets.iter().zip(pipelines) {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Conservative Demo Target Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color { r: 0.05, g: 0.05, b: 0.1, a: 1.0 }),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            if let Some(pipeline) = pipeline {
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Conservative Demo Display Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        // Largest square that fits in each half, centered
+        let half = width / 2;
+        let size = half.min(height);
+        render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+        for (i, (_, bind_group)) in self.targets.iter().enumerate() {
+            if size == 0 {
+                break;
+            }
+            let x = half * i as u32 + (half - size) / 2;
+            let y = (height - size) / 2;
+            render_pass.set_viewport(x as f32, y as f32, size as f32, size as f32, 0.0, 1.0);
+            render_pass.set_pipeline(if i == 1 && !self.is_supported() {
+                &self.unsupported_pipeline
+            } else {
+                &self.display_pipeline
+            });
+            render_pass.set_bind_group(1, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// Conservative rasterization demo, see conservative.rs
+
+struct DemoUniform {
+    // Triangle rotation in radians
+    angle: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> demo: DemoUniform;
+
+// A long thin sliver, most of the pixels it touches don't have their center inside it
+@vertex
+fn vs_triangle(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    var positions = array<vec2<f32>, 3>(
+        vec2<f32>(-0.8, -0.06),
+        vec2<f32>(0.8, 0.0),
+        vec2<f32>(-0.8, 0.02),
+    );
+    let p = positions[in_vertex_index];
+    let c = cos(demo.angle);
+    let s = sin(demo.angle);
+    return vec4<f32>(c * p.x - s * p.y, s * p.x + c * p.y, 0.0, 1.0);
+}
+
+@fragment
+fn fs_triangle() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 0.6, 0.1, 1.0);
+}
+
+@group(1) @binding(0)
+var t_target: texture_2d<f32>;
+@group(1) @binding(1)
+var s_target: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// Same fullscreen triangle as blit.wgsl, the viewport picks the half of the screen
+@vertex
+fn vs_display(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// Nearest sampled, so every low resolution pixel is a big square. Faint lines mark the pixel grid
+@fragment
+fn fs_display(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_target));
+    let cell = fract(in.uv * size);
+    let grid = any(cell < vec2<f32>(0.04));
+    let color = textureSample(t_target, s_target, in.uv).rgb;
+    return vec4<f32>(select(color, color * 0.7 + 0.1, grid), 1.0);
+}
+
+// Shown instead of the conservative half when the adapter can't do it
+@fragment
+fn fs_unsupported(in: VertexOutput) -> @location(0) vec4<f32> {
+    let stripe = fract((in.uv.x + in.uv.y) * 12.0) < 0.5;
+    return select(vec4<f32>(0.2, 0.05, 0.05, 1.0), vec4<f32>(0.6, 0.1, 0.1, 1.0), stripe);
+}
@@ -0,0 +1,284 @@
+use crate::buffer::{ArenaSlice, FrameArena};
+use crate::math::{Aabb, Frustum, Mat4, Vec3};
+use crate::pipeline::PipelineConfig;
+use crate::primitives::SurfaceVertex;
+use crate::reflection::{ReflectedLayout, ShaderReflection};
+
+// Box edges as pairs of Aabb::corners() indices
+const BOX_EDGES: [(usize, usize); 12] = [
+    (0, 1), (2, 3), (4, 5), (6, 7), // Along x
+    (0, 2), (1, 3), (4, 6), (5, 7), // Along y
+    (0, 4), (1, 5), (2, 6), (3, 7), // Along z
+];
+
+// One segment, an instance of the quad line.wgsl makes
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineInstance {
+    pub start: [f32; 3],
+    pub end: [f32; 3],
+    pub color: [f32; 3],
+}
+
+impl LineInstance {
+    pub(crate) fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+            0 => Float32x3, 1 => Float32x3, 2 => Float32x3
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LineInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Layout must match LineUniform in line.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LineUniform {
+    width: f32,
+    // vec2 is 8 byte aligned
+    _padding: f32,
+    viewport: [f32; 2],
+}
+
+// World space debug lines, collected on the CPU every frame and drawn in one instanced call.
+// Hardware lines are 1 pixel wide on most backends, so every segment is a camera facing quad
+// `width` pixels across with a pixel of falloff on both sides, blended instead of aliased
+pub struct LineBatch {
+    lines: Vec<LineInstance>,
+    // This frame's lines in the frame arena, at least one so prewarm has something to bind
+    instances: Option<ArenaSlice>,
+    width: f32,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: ReflectedLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    // Drawn over everything instead of depth tested, see on_top
+    on_top: bool,
+}
+
+impl LineBatch {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        Self::with_depth_test(device, config, camera_bind_group_layout, false)
+    }
+
+    // Ignores the depth buffer, for handles that have to stay visible (see gizmo.rs). Drawn
+    // after the scene, they end up in front of it
+    pub fn on_top(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        Self::with_depth_test(device, config, camera_bind_group_layout, true)
+    }
+
+    fn with_depth_test(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        on_top: bool,
+    ) -> Self {
+        let bind_group_layout = ShaderReflection::new("Line", include_str!("line.wgsl"))
+            .and_then(|shader| shader.layout(device, 1))
+            .unwrap_or_else(|e| panic!("{}", e));
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Line Uniform Buffer"),
+            size: std::mem::size_of::<LineUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = bind_group_layout
+            .create_bind_group(device, &[("lines".into(), uniform_buffer.as_entire_binding())])
+            .unwrap_or_else(|e| panic!("{}", e));
+        let pipeline = Self::create_pipeline(device, config, camera_bind_group_layout, &bind_group_layout.layout, on_top);
+
+        Self {
+            lines: Vec::new(),
+            instances: None,
+            width: 1.0,
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+            on_top,
+        }
+    }
+
+    // Call after the pipeline config changes
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        self.pipeline = Self::create_pipeline(device, config, camera_bind_group_layout, &self.bind_group_layout.layout, self.on_top);
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        on_top: bool,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("line.wgsl"));
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Line Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Line Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[LineInstance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.color_format,
+                    // The falloff at the edges is in alpha
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                // Quads always face the camera
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: config.depth_format,
+                // Tested against the scene but not written, so the faded edge of one line doesn't
+                // cut into another one behind it
+                depth_write_enabled: false,
+                depth_compare: if on_top { wgpu::CompareFunction::Always } else { wgpu::CompareFunction::Less },
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: config.multisample(),
+            multiview: None,
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    // Pixels across on the render target, takes effect on the next upload
+    pub fn set_line_width(&mut self, px: f32) {
+        self.width = px.max(0.0);
+    }
+
+    pub fn line_width(&self) -> f32 {
+        self.width
+    }
+
+    pub fn line(&mut self, a: Vec3, b: Vec3, color: [f32; 3]) {
+        self.lines.push(LineInstance { start: a.to_array(), end: b.to_array(), color });
+    }
+
+    // Object space box moved into world by transform, so rotated instances get a rotated box
+    pub fn aabb(&mut self, aabb: &Aabb, transform: &Mat4, color: [f32; 3]) {
+        let corners = aabb.corners().map(|c| transform.transform_point(c));
+        for (a, b) in BOX_EDGES {
+            self.line(corners[a], corners[b], color);
+        }
+    }
+
+    // Outline of what a camera with `view_proj` sees, near to far plane
+    pub fn frustum(&mut self, view_proj: &Mat4, color: [f32; 3]) {
+        let corners = Frustum::corners(view_proj);
+        for (a, b) in BOX_EDGES {
+            self.line(corners[a], corners[b], color);
+        }
+    }
+
+    // Object space segments from normal_segments moved into world by transform
+    pub fn segments(&mut self, segments: &[(Vec3, Vec3)], transform: &Mat4) {
+        for &(a, b) in segments {
+            // Color is the direction, x = red, y = green, z = blue
+            let direction = (b - a).normalize();
+            let color = [direction.x.abs(), direction.y.abs(), direction.z.abs()];
+            self.line(transform.transform_point(a), transform.transform_point(b), color);
+        }
+    }
+
+    // Colored object space segments from frame_segments moved into world by transform
+    pub fn colored_segments(&mut self, segments: &[(Vec3, Vec3, [f32; 3])], transform: &Mat4) {
+        for &(a, b, color) in segments {
+            self.line(transform.transform_point(a), transform.transform_point(b), color);
+        }
+    }
+
+    // Copies collected lines and the width to the GPU, `width` and `height` are the render target's
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, arena: &mut FrameArena, width: u32, height: u32) {
+        let lines = if self.lines.is_empty() { &[bytemuck::Zeroable::zeroed()][..] } else { &self.lines };
+        self.instances = Some(arena.alloc(device, queue, bytemuck::cast_slice(lines)));
+        let uniform = LineUniform { width: self.width, _padding: 0.0, viewport: [width as f32, height as f32] };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        if self.lines.is_empty() || self.width <= 0.0 {
+            return;
+        }
+        self.set(render_pass, camera_bind_group);
+        render_pass.draw(0..6, 0..self.lines.len() as u32);
+    }
+
+    // Draws one line with whatever was uploaded, even if nothing was collected. For State::prewarm,
+    // needs an upload this frame
+    pub fn prewarm<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        self.set(render_pass, camera_bind_group);
+        render_pass.draw(0..6, 0..1);
+    }
+
+    fn set<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        let instances = self.instances.as_ref().expect("LineBatch drawn before upload");
+        render_pass.set_vertex_buffer(0, instances.slice());
+    }
+
+    // Instances are in the frame arena
+    pub fn gpu_memory(&self) -> u64 {
+        self.uniform_buffer.size()
+    }
+}
+
+// One segment per triangle of a triangle list, from its center along the face normal (counter
+// clockwise front faces). Vertices don't have their own normals, so this is what shading sees too
+pub fn normal_segments(positions: &[Vec3], length: f32) -> Vec<(Vec3, Vec3)> {
+    positions.chunks_exact(3)
+        .map(|triangle| {
+            let center = (triangle[0] + triangle[1] + triangle[2]) / 3.0;
+            let normal = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]).normalize();
+            (center, center + normal * length)
+        })
+        .collect()
+}
+
+// Tangent (red), bitangent (green) and normal (blue) of every vertex, `length` long. Flipped
+// normals point inside, mirrored UVs show as a bitangent on the wrong side
+pub fn frame_segments(vertices: &[SurfaceVertex], length: f32) -> Vec<(Vec3, Vec3, [f32; 3])> {
+    vertices
+        .iter()
+        .flat_map(|v| {
+            [(v.tangent, [1.0, 0.0, 0.0]), (v.bitangent(), [0.0, 1.0, 0.0]), (v.normal, [0.0, 0.0, 1.0])]
+                .map(|(direction, color)| (v.position, v.position + direction * length, color))
+        })
+        .collect()
+}
@@ -0,0 +1,342 @@
+use crate::buffer::{ArenaSlice, FrameArena};
+
+// Pixels, from the top left corner of the screen
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ScreenRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ScreenRect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    // width x height, `margin` pixels in from the bottom right corner of a screen that size
+    pub fn bottom_right(width: f32, height: f32, margin: f32, screen_width: u32, screen_height: u32) -> Self {
+        Self::new(screen_width as f32 - width - margin, screen_height as f32 - height - margin, width, height)
+    }
+
+    // Cut down to the part inside the screen, None if nothing is. Viewports outside the target are
+    // a validation error
+    fn clamped(&self, screen_width: u32, screen_height: u32) -> Option<Self> {
+        let left = self.x.max(0.0);
+        let top = self.y.max(0.0);
+        let right = (self.x + self.width).min(screen_width as f32);
+        let bottom = (self.y + self.height).min(screen_height as f32);
+        (right > left && bottom > top).then(|| Self::new(left, top, right - left, bottom - top))
+    }
+}
+
+// What of the texture a debug view shows. Values are clamped to 0..1 and shown as stored, an sRGB
+// target doesn't brighten them
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DebugChannels {
+    // Alpha ignored
+    Rgb,
+    // One channel as grey
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    // A depth texture (view with TextureAspect::DepthOnly when it has stencil) of a perspective
+    // projection with these planes, linearized so znear is black and zfar white
+    Depth { znear: f32, zfar: f32 },
+}
+
+// Must match the CHANNELS_ constants in debug_view.wgsl
+const CHANNELS_RGB: u32 = 0;
+const CHANNELS_RED: u32 = 1;
+const CHANNELS_GREEN: u32 = 2;
+const CHANNELS_BLUE: u32 = 3;
+const CHANNELS_ALPHA: u32 = 4;
+
+// Layout must match DebugViewUniform in debug_view.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugViewUniform {
+    channels: u32,
+    srgb_target: u32,
+    znear: f32,
+    zfar: f32,
+}
+
+struct QueuedView {
+    bind_group: wgpu::BindGroup,
+    depth: bool,
+    rect: ScreenRect,
+    // Keeps the uniform's arena chunk alive until the view is drawn
+    _uniform: ArenaSlice,
+}
+
+// Draws textures into rectangles of the screen, for looking at what a pass wrote (depth, lookup
+// tables, intermediate targets). Views are queued every frame and drawn once over everything else.
+// Any float or depth format works, shown nearest. Multisampled textures can't be shown
+pub struct DebugViews {
+    bind_group_layout: wgpu::BindGroupLayout,
+    color_pipeline: wgpu::RenderPipeline,
+    depth_pipeline: wgpu::RenderPipeline,
+    srgb_target: bool,
+    queued: Vec<QueuedView>,
+}
+
+impl DebugViews {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Debug View Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<DebugViewUniform>() as u64),
+                    },
+                    count: None,
+                },
+                // Unfilterable takes every float format and the depth aspect of depth formats,
+                // textureLoad doesn't filter anyway
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let (color_pipeline, depth_pipeline) = Self::create_pipelines(device, color_format, &bind_group_layout);
+
+        Self {
+            bind_group_layout,
+            color_pipeline,
+            depth_pipeline,
+            srgb_target: color_format.is_srgb(),
+            queued: Vec::new(),
+        }
+    }
+
+    // Call after the surface format changes
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device, color_format: wgpu::TextureFormat) {
+        (self.color_pipeline, self.depth_pipeline) = Self::create_pipelines(device, color_format, &self.bind_group_layout);
+        self.srgb_target = color_format.is_srgb();
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("debug_view.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug View Pipeline Layout"),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |label, fs_entry| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: fs_entry,
+                    targets: &[Some(color_format.into())],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        (
+            pipeline("Debug View Color Pipeline", "fs_color"),
+            pipeline("Debug View Depth Pipeline", "fs_depth"),
+        )
+    }
+
+    // Shows `view` in `rect` this frame. Uniforms come from the frame arena, so call it between
+    // the arena's reset and the frame's submit
+    #[allow(clippy::too_many_arguments)]
+    pub fn queue(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        arena: &mut FrameArena,
+        view: &wgpu::TextureView,
+        rect: ScreenRect,
+        channels: DebugChannels,
+    ) {
+        let depth = matches!(channels, DebugChannels::Depth { .. });
+        let (channels, znear, zfar) = match channels {
+            DebugChannels::Rgb => (CHANNELS_RGB, 0.0, 0.0),
+            DebugChannels::Red => (CHANNELS_RED, 0.0, 0.0),
+            DebugChannels::Green => (CHANNELS_GREEN, 0.0, 0.0),
+            DebugChannels::Blue => (CHANNELS_BLUE, 0.0, 0.0),
+            DebugChannels::Alpha => (CHANNELS_ALPHA, 0.0, 0.0),
+            // Equal planes would divide by zero
+            DebugChannels::Depth { znear, zfar } => (CHANNELS_RGB, znear, zfar.max(znear + 1e-4)),
+        };
+        let uniform = DebugViewUniform { channels, srgb_target: self.srgb_target as u32, znear, zfar };
+        let uniform = arena.alloc_uniform(device, queue, bytemuck::bytes_of(&uniform));
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Debug View Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform.binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+            ],
+        });
+        self.queued.push(QueuedView { bind_group, depth, rect, _uniform: uniform });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    // Every queued view, in queue order. Leaves the render pass's viewport on the last rect
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, screen_width: u32, screen_height: u32) {
+        for view in &self.queued {
+            let Some(rect) = view.rect.clamped(screen_width, screen_height) else {
+                continue;
+            };
+            render_pass.set_viewport(rect.x, rect.y, rect.width, rect.height, 0.0, 1.0);
+            render_pass.set_pipeline(if view.depth { &self.depth_pipeline } else { &self.color_pipeline });
+            render_pass.set_bind_group(0, &view.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+
+    // Call once the frame is submitted
+    pub fn clear(&mut self) {
+        self.queued.clear();
+    }
+}
+
+// What Action::CycleDebugView shows in the bottom right corner
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum BuiltinDebugView {
+    #[default]
+    Off,
+    // The main depth buffer, linearized with the camera's planes. Not with MSAA, a multisampled
+    // texture can't be shown
+    Depth,
+    // IBL split sum lookup table, only once there is one
+    BrdfLut,
+}
+
+impl BuiltinDebugView {
+    pub fn next(self) -> Self {
+        match self {
+            BuiltinDebugView::Off => BuiltinDebugView::Depth,
+            BuiltinDebugView::Depth => BuiltinDebugView::BrdfLut,
+            BuiltinDebugView::BrdfLut => BuiltinDebugView::Off,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Draws a 2x2 color texture as RGB and as its red channel, and a depth texture linearized, into an
+    // sRGB target with the depth rect hanging off its corner, then reads the target back. Pixels have
+    // to come out as the texels are stored, uncovered pixels keep the clear color
+    #[test]
+    fn views_draw_texels_as_stored() {
+        let Some((device, queue)) = crate::shader_test::device() else {
+            return;
+        };
+        const SIZE: u32 = 64;
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Debug View Check Target"),
+            size: wgpu::Extent3d { width: SIZE, height: SIZE, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let texels = [[255, 0, 0, 255], [0, 255, 0, 128], [0, 0, 255, 255], [255, 255, 255, 0]];
+        let color = crate::texture::Texture::from_rgba_linear(device, queue, &texels.concat(), 2, 2, "Debug View Check Color");
+        let depth = crate::texture::Texture::create_depth_texture_sized(
+            device, 4, 4, wgpu::TextureFormat::Depth32Float, 1, "Debug View Check Depth"
+        );
+        // Halfway between the planes once linearized
+        let (znear, zfar) = (1.0, 100.0);
+        let halfway = (zfar - znear * zfar / ((znear + zfar) * 0.5)) / (zfar - znear);
+
+        let mut arena = FrameArena::new(device, 1024);
+        let mut views = DebugViews::new(device, format);
+        views.queue(device, queue, &mut arena, &color.view, ScreenRect::new(0.0, 0.0, 32.0, 32.0), DebugChannels::Rgb);
+        views.queue(device, queue, &mut arena, &color.view, ScreenRect::new(32.0, 0.0, 32.0, 32.0), DebugChannels::Red);
+        views.queue(device, queue, &mut arena, &depth.view, ScreenRect::new(40.0, 40.0, 32.0, 32.0), DebugChannels::Depth { znear, zfar });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Debug View Check Encoder"),
+        });
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Debug View Check Depth Clear"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth.view,
+                depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Clear(halfway), store: true }),
+                stencil_ops: None,
+            }),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Debug View Check Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::BLACK), store: true },
+                })],
+                depth_stencil_attachment: None,
+            });
+            views.draw(&mut render_pass, SIZE, SIZE);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        let pixels = crate::readback::read_texture_rgba(device, queue, &target);
+        let pixel = |x: u32, y: u32| -> [u8; 3] {
+            let i = ((y * SIZE + x) * 4) as usize;
+            [pixels[i], pixels[i + 1], pixels[i + 2]]
+        };
+
+        let expected = [
+            ("RGB top left texel", (8, 8), [255, 0, 0]),
+            ("RGB bottom right texel, alpha ignored", (24, 24), [255, 255, 255]),
+            ("red of a red texel", (40, 8), [255, 255, 255]),
+            ("red of a green texel", (56, 8), [0, 0, 0]),
+            ("depth halfway between the planes", (52, 52), [128, 128, 128]),
+            ("outside every rect", (8, 48), [0, 0, 0]),
+        ];
+        for (name, (x, y), expected) in expected {
+            let actual = pixel(x, y);
+            assert!(
+                actual.iter().zip(expected).all(|(&a, e): (&u8, u8)| a.abs_diff(e) <= 2),
+                "{}: pixel ({}, {}) is {:?}, expected {:?}",
+                name, x, y, actual, expected
+            );
+        }
+    }
+}
@@ -0,0 +1,82 @@
+// Shows a texture in a rectangle of the screen, see DebugViews in debug_view.rs. The rectangle is
+// the viewport, a fullscreen triangle covers it
+
+// Layout must match DebugViewUniform in debug_view.rs
+struct DebugViewUniform {
+    // One of the CHANNELS_ constants, or 0 for RGB
+    channels: u32,
+    // 1 when the target is sRGB, values are decoded so they show up as they are stored
+    srgb_target: u32,
+    znear: f32,
+    zfar: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: DebugViewUniform;
+// Depth textures too, as a plain float texture. GL can't read depth textures without a comparison
+@group(0) @binding(1)
+var t_source: texture_2d<f32>;
+
+// Must match the CHANNELS_ constants in debug_view.rs, anything else is RGB
+const CHANNELS_RED: u32 = 1u;
+const CHANNELS_GREEN: u32 = 2u;
+const CHANNELS_BLUE: u32 = 3u;
+const CHANNELS_ALPHA: u32 = 4u;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // 0..1 over the rectangle, origin top left
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// Texel under uv, nearest. textureLoad works for every float and depth format, filterable or not
+fn texel(size: vec2<u32>, uv: vec2<f32>) -> vec2<i32> {
+    return vec2<i32>(min(vec2<u32>(uv * vec2<f32>(size)), size - 1u));
+}
+
+fn srgb_to_linear(value: vec3<f32>) -> vec3<f32> {
+    let low = value / 12.92;
+    let high = pow((value + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, value <= vec3<f32>(0.04045));
+}
+
+// The sRGB target encodes what's written, decoding first cancels that out
+fn output(value: vec3<f32>) -> vec4<f32> {
+    let clamped = clamp(value, vec3<f32>(0.0), vec3<f32>(1.0));
+    if params.srgb_target == 1u {
+        return vec4<f32>(srgb_to_linear(clamped), 1.0);
+    }
+    return vec4<f32>(clamped, 1.0);
+}
+
+@fragment
+fn fs_color(in: VertexOutput) -> @location(0) vec4<f32> {
+    let value = textureLoad(t_source, texel(textureDimensions(t_source), in.uv), 0);
+    if params.channels == CHANNELS_RED {
+        return output(vec3<f32>(value.r));
+    } else if params.channels == CHANNELS_GREEN {
+        return output(vec3<f32>(value.g));
+    } else if params.channels == CHANNELS_BLUE {
+        return output(vec3<f32>(value.b));
+    } else if params.channels == CHANNELS_ALPHA {
+        return output(vec3<f32>(value.a));
+    }
+    return output(value.rgb);
+}
+
+// Perspective depth (0 near, 1 far) back to view distance, near black and far white
+@fragment
+fn fs_depth(in: VertexOutput) -> @location(0) vec4<f32> {
+    let depth = textureLoad(t_source, texel(textureDimensions(t_source), in.uv), 0).r;
+    let distance = params.znear * params.zfar / (params.zfar - depth * (params.zfar - params.znear));
+    return output(vec3<f32>((distance - params.znear) / (params.zfar - params.znear)));
+}
@@ -0,0 +1,518 @@
+use crate::math::{Mat4, Vec3};
+use crate::reflection::{ReflectedLayout, ShaderReflection};
+use crate::texture::Texture;
+
+// Decals kept at once. Adding more recycles the least recently used one, so placing decals
+// without end doesn't grow memory
+pub const MAX_DECALS: usize = 64;
+
+// A texture projected onto whatever is inside a box
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Decal {
+    pub position: Vec3,
+    // Away from the surfaces it lands on. Surfaces facing away from it don't get it
+    pub normal: Vec3,
+    // Radians around normal
+    pub rotation: f32,
+    // Texture width and height, and how far the box reaches along normal. Keep the depth small,
+    // everything inside the box gets the decal
+    pub size: Vec3,
+    // Multiplies the texture, linear
+    pub color: [f32; 4],
+}
+
+impl Decal {
+    // Decal box from the unit cube around the origin: X across the texture, Y along normal, Z down
+    // the texture
+    pub fn model_matrix(&self) -> Mat4 {
+        let normal = self.normal.normalize();
+        // Anything not parallel to the normal
+        let helper = if normal.y.abs() < 0.99 { Vec3::Y } else { Vec3::Z };
+        let tangent = helper.cross(normal).normalize();
+        let bitangent = normal.cross(tangent);
+        let (sin, cos) = self.rotation.sin_cos();
+        let x = tangent * cos + bitangent * sin;
+        let z = normal.cross(x);
+        let column = |axis: Vec3, scale: f32| [axis.x * scale, axis.y * scale, axis.z * scale, 0.0];
+        Mat4 {
+            cols: [
+                column(x, self.size.x),
+                column(normal, self.size.z),
+                column(z, self.size.y),
+                [self.position.x, self.position.y, self.position.z, 1.0],
+            ],
+        }
+    }
+
+    fn to_raw(self) -> DecalRaw {
+        let model = self.model_matrix();
+        DecalRaw {
+            model: model.to_cols_array(),
+            inverse: model.inverse().to_cols_array(),
+            color: self.color,
+        }
+    }
+}
+
+// Layout must match DecalInput in decal.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct DecalRaw {
+    model: [[f32; 4]; 4],
+    // World to the unit cube, the fragment shader needs it per pixel
+    inverse: [[f32; 4]; 4],
+    color: [f32; 4],
+}
+
+impl DecalRaw {
+    pub(crate) fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 9] = wgpu::vertex_attr_array![
+            0 => Float32x4, 1 => Float32x4, 2 => Float32x4, 3 => Float32x4,
+            4 => Float32x4, 5 => Float32x4, 6 => Float32x4, 7 => Float32x4,
+            8 => Float32x4
+        ];
+
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<DecalRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Handle to a placed decal. Stale once the decal was recycled, see DecalPool
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DecalId {
+    slot: u32,
+    generation: u32,
+}
+
+// Fixed number of slots, a new decal takes a free one or else the one used longest ago. Adding or
+// changing a decal counts as using it
+pub struct DecalPool {
+    decals: Vec<Decal>,
+    generations: Vec<u32>,
+    last_used: Vec<u64>,
+    clock: u64,
+    capacity: usize,
+}
+
+impl DecalPool {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            decals: Vec::with_capacity(capacity),
+            generations: Vec::with_capacity(capacity),
+            last_used: Vec::with_capacity(capacity),
+            clock: 0,
+            capacity: capacity.max(1),
+        }
+    }
+
+    // The id and the slot it went to
+    pub fn add(&mut self, decal: Decal) -> (DecalId, usize) {
+        self.clock += 1;
+        let slot = if self.decals.len() < self.capacity {
+            self.decals.push(decal);
+            self.generations.push(0);
+            self.last_used.push(self.clock);
+            self.decals.len() - 1
+        } else {
+            let (slot, _) = self.last_used.iter().enumerate().min_by_key(|&(_, used)| used).expect("capacity is at least 1");
+            self.decals[slot] = decal;
+            self.generations[slot] += 1;
+            self.last_used[slot] = self.clock;
+            slot
+        };
+        (DecalId { slot: slot as u32, generation: self.generations[slot] }, slot)
+    }
+
+    // Slot of `id`, None once it was recycled
+    fn slot(&self, id: DecalId) -> Option<usize> {
+        let slot = id.slot as usize;
+        (self.generations.get(slot) == Some(&id.generation)).then_some(slot)
+    }
+
+    pub fn get(&self, id: DecalId) -> Option<&Decal> {
+        self.slot(id).map(|slot| &self.decals[slot])
+    }
+
+    // Keeps `id` from being recycled next. False if it already was
+    pub fn touch(&mut self, id: DecalId) -> bool {
+        let Some(slot) = self.slot(id) else {
+            return false;
+        };
+        self.clock += 1;
+        self.last_used[slot] = self.clock;
+        true
+    }
+
+    // The slot `id` is in, None if it was recycled
+    pub fn set(&mut self, id: DecalId, decal: Decal) -> Option<usize> {
+        let slot = self.slot(id)?;
+        self.decals[slot] = decal;
+        self.touch(id);
+        Some(slot)
+    }
+
+    // Old ids stay stale, generations only go up
+    pub fn clear(&mut self) {
+        self.decals.clear();
+        self.last_used.clear();
+        for generation in &mut self.generations {
+            *generation += 1;
+        }
+    }
+
+    pub fn decals(&self) -> &[Decal] {
+        &self.decals
+    }
+
+    pub fn len(&self) -> usize {
+        self.decals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decals.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+// Default decal texture: a round splat, soft at the edge and clear outside
+pub fn splat_rgba(size: u32) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let u = (x as f32 + 0.5) / size as f32 - 0.5;
+            let v = (y as f32 + 0.5) / size as f32 - 0.5;
+            let distance = (u * u + v * v).sqrt();
+            let alpha = ((0.5 - distance) / 0.1).clamp(0.0, 1.0);
+            // Darker ring towards the edge
+            let shade = 1.0 - (distance * 1.2).min(0.6);
+            rgba.extend_from_slice(&[(200.0 * shade) as u8, (40.0 * shade) as u8, (30.0 * shade) as u8, (alpha * 255.0) as u8]);
+        }
+    }
+    rgba
+}
+
+// Screen space decals for the forward path: after the opaque scene each decal's box is drawn, and
+// the pixels of it that land on scene geometry inside the box get the decal texture blended over
+// the lit color. There's no G-buffer, so decals are unlit and can't change normals. Needs a single
+// sampled depth buffer, see apply
+pub struct DecalRenderer {
+    pool: DecalPool,
+    // Room for the whole pool, allocated once
+    instance_buffer: wgpu::Buffer,
+    texture: Texture,
+    bind_group_layout: ReflectedLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl DecalRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let bind_group_layout = ShaderReflection::new("Decal", include_str!("decal.wgsl"))
+            .and_then(|shader| shader.layout(device, 1))
+            .unwrap_or_else(|e| panic!("{}", e));
+        let pipeline = Self::create_pipeline(device, color_format, camera_bind_group_layout, &bind_group_layout);
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Decal Instance Buffer"),
+            size: (MAX_DECALS * std::mem::size_of::<DecalRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let texture = Texture::from_rgba(device, queue, &splat_rgba(64), 64, 64, "Decal Texture");
+        Self {
+            pool: DecalPool::new(MAX_DECALS),
+            instance_buffer,
+            texture,
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    // Call after the surface format changes
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        self.pipeline = Self::create_pipeline(device, color_format, camera_bind_group_layout, &self.bind_group_layout);
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        bind_group_layout: &ReflectedLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("decal.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Decal Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout.layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Decal Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[DecalRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    // mix(scene, decal, alpha), the scene's alpha stays
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::COLOR,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                // The inside faces, they still cover the box's pixels with the camera in it. Each
+                // pixel once, so overlapping faces don't blend twice
+                cull_mode: Some(wgpu::Face::Front),
+                ..Default::default()
+            },
+            // The depth test happens in the shader, against the box instead of its faces
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // Recycles the least recently used decal when the pool is full
+    pub fn add(&mut self, queue: &wgpu::Queue, decal: Decal) -> DecalId {
+        let (id, slot) = self.pool.add(decal);
+        self.upload(queue, slot, decal);
+        id
+    }
+
+    // False if `id` was recycled
+    pub fn set(&mut self, queue: &wgpu::Queue, id: DecalId, decal: Decal) -> bool {
+        let Some(slot) = self.pool.set(id, decal) else {
+            return false;
+        };
+        self.upload(queue, slot, decal);
+        true
+    }
+
+    fn upload(&self, queue: &wgpu::Queue, slot: usize, decal: Decal) {
+        let offset = (slot * std::mem::size_of::<DecalRaw>()) as wgpu::BufferAddress;
+        queue.write_buffer(&self.instance_buffer, offset, bytemuck::bytes_of(&decal.to_raw()));
+    }
+
+    pub fn pool(&self) -> &DecalPool {
+        &self.pool
+    }
+
+    pub fn touch(&mut self, id: DecalId) -> bool {
+        self.pool.touch(id)
+    }
+
+    pub fn clear(&mut self) {
+        self.pool.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pool.is_empty()
+    }
+
+    // Replaces the splat every decal uses. Sampled without mips
+    pub fn set_texture(&mut self, texture: Texture) {
+        self.texture = texture;
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        self.instance_buffer.size() + crate::texture::texture_bytes(&self.texture.texture)
+    }
+
+    // Draws the decals over `target`. `depth` is the scene's depth texture, single sampled, and
+    // `camera_bind_group` the camera it was drawn with
+    pub fn apply(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        depth: &wgpu::Texture,
+        target: &wgpu::TextureView,
+    ) {
+        if self.pool.is_empty() {
+            return;
+        }
+        // Stencil can't be bound along with depth
+        let depth_view = depth.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+        let bind_group = self.bind_group_layout
+            .create_bind_group(device, &[
+                ("t_depth".into(), wgpu::BindingResource::TextureView(&depth_view)),
+                ("t_albedo".into(), wgpu::BindingResource::TextureView(&self.texture.view)),
+                ("s_albedo".into(), wgpu::BindingResource::Sampler(&self.texture.sampler)),
+            ])
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Decal Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..36, 0..self.pool.len() as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, CameraUniform, Projection};
+
+    fn decal(position: Vec3, normal: Vec3) -> Decal {
+        Decal {
+            position,
+            normal,
+            rotation: 0.0,
+            size: Vec3::new(2.0, 2.0, 0.5),
+            color: [1.0; 4],
+        }
+    }
+
+    // A full pool recycles the least recently used decal, and ids go stale
+    #[test]
+    fn pool_recycles_least_recently_used() {
+        let mut pool = DecalPool::new(3);
+        let ids = (0..3).map(|i| pool.add(decal(Vec3::new(i as f32, 0.0, 0.0), Vec3::Y)).0).collect::<Vec<_>>();
+        pool.touch(ids[0]);
+        let (recycled, slot) = pool.add(decal(Vec3::new(9.0, 0.0, 0.0), Vec3::Y));
+        assert_eq!(pool.len(), 3);
+        assert_eq!(slot, 1);
+        assert!(pool.get(ids[1]).is_none());
+        assert!(pool.get(ids[0]).is_some());
+        assert_eq!(pool.get(recycled).map(|decal| decal.position.x), Some(9.0));
+        pool.clear();
+        assert!(pool.get(recycled).is_none());
+        assert!(pool.is_empty());
+    }
+
+    // Looks straight down at a floor (depth cleared to the floor's depth, no geometry needed): a
+    // decal on it shows, one facing sideways fades out, one hovering above the floor is clipped
+    // away entirely
+    #[test]
+    fn decals_project_onto_the_floor() {
+        let Some((device, queue)) = crate::shader_test::device() else {
+            return;
+        };
+        const SIZE: u32 = 64;
+        let color_format = wgpu::TextureFormat::Rgba8Unorm;
+        let depth_format = wgpu::TextureFormat::Depth32Float;
+        let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Decal Check Camera Bind Group Layout"),
+            entries: crate::CAMERA_LAYOUT_ENTRIES,
+        });
+        let camera = Camera {
+            eye: Vec3::new(0.0, 10.0, 0.0),
+            target: Vec3::ZERO,
+            up: -Vec3::Z,
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+            projection: Projection::Perspective,
+        };
+        let mut uniform = CameraUniform::new();
+        uniform.update_view_proj(&camera);
+        let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Decal Check Camera Buffer"),
+            size: std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&camera_buffer, 0, bytemuck::bytes_of(&uniform));
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Decal Check Camera Bind Group"),
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: camera_buffer.as_entire_binding() }],
+        });
+        // Looking straight down every pixel at this depth is on the y = 0 floor
+        let floor_depth = camera.build_view_projection_matrix().transform_point(Vec3::ZERO).z;
+
+        let render = |decals: &[Decal]| -> Vec<u8> {
+            let mut renderer = DecalRenderer::new(device, queue, color_format, &camera_bind_group_layout);
+            for &decal in decals {
+                renderer.add(queue, decal);
+            }
+            let target = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Decal Check Target"),
+                size: wgpu::Extent3d { width: SIZE, height: SIZE, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: color_format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+            let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+            let depth = Texture::create_depth_texture_sized(device, SIZE, SIZE, depth_format, 1, "Decal Check Depth");
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Decal Check Encoder"),
+            });
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Decal Check Floor Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::WHITE), store: true },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth.view,
+                    depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Clear(floor_depth), store: true }),
+                    stencil_ops: None,
+                }),
+            });
+            renderer.apply(device, &mut encoder, &camera_bind_group, &depth.texture, &view);
+            queue.submit(std::iter::once(encoder.finish()));
+            crate::readback::read_texture_rgba(device, queue, &target)
+        };
+        let pixel = |rgba: &[u8], x: u32, y: u32| -> [u8; 3] {
+            let i = ((y * SIZE + x) * 4) as usize;
+            [rgba[i], rgba[i + 1], rgba[i + 2]]
+        };
+        let white = [255; 3];
+
+        let flat = render(&[decal(Vec3::ZERO, Vec3::Y)]);
+        let center = pixel(&flat, SIZE / 2, SIZE / 2);
+        assert!(center[0] >= 100 && center[1] <= 60 && center[2] <= 60, "Decal center is {:?}, expected the red splat", center);
+        assert_eq!(pixel(&flat, 1, 1), white, "Corner outside the decal isn't the white floor");
+
+        let checks = [
+            ("facing sideways", decal(Vec3::ZERO, Vec3::X)),
+            ("above the floor", decal(Vec3::new(0.0, 1.0, 0.0), Vec3::Y)),
+        ];
+        for (name, decal) in checks {
+            let rgba = render(&[decal]);
+            if let Some(y) = (0..SIZE).find(|&y| (0..SIZE).any(|x| pixel(&rgba, x, y) != white)) {
+                panic!("Decal {} still shows, row {} isn't the floor color", name, y);
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+// Screen space decals, see DecalRenderer in decal.rs. Each decal's box is drawn (its inside faces,
+// so the camera can stand in it) and every pixel it covers looks up what the scene drew there from
+// depth. Pixels whose scene position is outside the box are dropped, the rest get the decal texture
+// projected down the box's Y axis
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    exposure: f32,
+    fog_mode: u32,
+    fog_start: f32,
+    fog_end: f32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    eye: vec3<f32>,
+    fog_height: f32,
+    forward: vec3<f32>,
+    fog_height_falloff: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Depth aspect as a plain float texture, GL can't read depth textures without a comparison
+@group(1) @binding(0)
+var t_depth: texture_2d<f32>;
+@group(1) @binding(1)
+var t_albedo: texture_2d<f32>;
+@group(1) @binding(2)
+var s_albedo: sampler;
+
+// Layout must match DecalRaw in decal.rs
+struct DecalInput {
+    @location(0) model_0: vec4<f32>,
+    @location(1) model_1: vec4<f32>,
+    @location(2) model_2: vec4<f32>,
+    @location(3) model_3: vec4<f32>,
+    @location(4) inverse_0: vec4<f32>,
+    @location(5) inverse_1: vec4<f32>,
+    @location(6) inverse_2: vec4<f32>,
+    @location(7) inverse_3: vec4<f32>,
+    @location(8) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) inverse_0: vec4<f32>,
+    @location(1) inverse_1: vec4<f32>,
+    @location(2) inverse_2: vec4<f32>,
+    @location(3) inverse_3: vec4<f32>,
+    @location(4) color: vec4<f32>,
+    // The box's Y axis in world space, what the decal faces
+    @location(5) normal: vec3<f32>,
+}
+
+// Surfaces facing the decal within this cosine get all of it, from there it fades out until
+// FADE_END. Projecting onto surfaces at grazing angles stretches the texture
+const FADE_START: f32 = 0.5;
+const FADE_END: f32 = 0.2;
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32, decal: DecalInput) -> VertexOutput {
+    // Corner index bits are x, y, z. Faces are counter clockwise seen from outside
+    var indices = array<u32, 36>(
+        0u, 4u, 6u, 0u, 6u, 2u,
+        5u, 1u, 3u, 5u, 3u, 7u,
+        0u, 1u, 5u, 0u, 5u, 4u,
+        3u, 2u, 6u, 3u, 6u, 7u,
+        1u, 0u, 2u, 1u, 2u, 3u,
+        4u, 5u, 7u, 4u, 7u, 6u,
+    );
+    let corner = indices[in_vertex_index];
+    let local = vec3<f32>(f32(corner & 1u), f32((corner >> 1u) & 1u), f32((corner >> 2u) & 1u)) - 0.5;
+    let model = mat4x4<f32>(decal.model_0, decal.model_1, decal.model_2, decal.model_3);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model * vec4<f32>(local, 1.0);
+    out.inverse_0 = decal.inverse_0;
+    out.inverse_1 = decal.inverse_1;
+    out.inverse_2 = decal.inverse_2;
+    out.inverse_3 = decal.inverse_3;
+    out.color = decal.color;
+    out.normal = normalize(decal.model_1.xyz);
+    return out;
+}
+
+// World position of what the scene drew at `pixel`
+fn scene_position(pixel: vec2<i32>) -> vec3<f32> {
+    let size = vec2<i32>(textureDimensions(t_depth));
+    let clamped = clamp(pixel, vec2<i32>(0), size - 1);
+    let depth = textureLoad(t_depth, clamped, 0).r;
+    let uv = (vec2<f32>(clamped) + 0.5) / vec2<f32>(size);
+    let world = camera.inv_view_proj * vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// Of the two neighbours on an axis, the one closer to `center`. At a geometry edge the other one
+// is on a different surface, the normal would bend towards it
+fn closer_step(center: vec3<f32>, before: vec3<f32>, after: vec3<f32>) -> vec3<f32> {
+    if length(after - center) < length(center - before) {
+        return after - center;
+    }
+    return center - before;
+}
+
+// Alpha is how much decal, the blend state mixes it in by that
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let depth = textureLoad(t_depth, pixel, 0).r;
+    // Nothing drawn there
+    if depth >= 1.0 {
+        discard;
+    }
+    let position = scene_position(pixel);
+    let inverse = mat4x4<f32>(in.inverse_0, in.inverse_1, in.inverse_2, in.inverse_3);
+    let local = (inverse * vec4<f32>(position, 1.0)).xyz;
+    // In front of or behind what the box covers, this is what clips decals at geometry edges
+    if any(abs(local) > vec3<f32>(0.5)) {
+        discard;
+    }
+
+    let dx = closer_step(position, scene_position(pixel - vec2<i32>(1, 0)), scene_position(pixel + vec2<i32>(1, 0)));
+    let dy = closer_step(position, scene_position(pixel - vec2<i32>(0, 1)), scene_position(pixel + vec2<i32>(0, 1)));
+    var normal = normalize(cross(dy, dx));
+    if dot(normal, camera.eye - position) < 0.0 {
+        normal = -normal;
+    }
+    let fade = smoothstep(FADE_END, FADE_START, dot(normal, in.normal));
+
+    // No derivatives after discard, so no mips either
+    let albedo = textureSampleLevel(t_albedo, s_albedo, local.xz + 0.5, 0.0) * in.color;
+    return vec4<f32>(albedo.rgb * camera.exposure, albedo.a * fade);
+}
@@ -0,0 +1,182 @@
+use std::collections::VecDeque;
+
+use crate::text::{TextOverlay, LINE_HEIGHT};
+
+// How long a toast stays up, it fades out over the last FADE_SECONDS
+const TOAST_SECONDS: f32 = 5.0;
+const FADE_SECONDS: f32 = 0.5;
+// At most this many toasts at once, older ones are pushed out early
+const MAX_TOASTS: usize = 5;
+// Entries kept for error_history
+const HISTORY_LEN: usize = 100;
+// Font pixels are this many screen pixels
+const TEXT_SCALE: f32 = 2.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn color(self) -> [f32; 3] {
+        match self {
+            Severity::Info => [0.2, 0.45, 0.8],
+            Severity::Warning => [0.85, 0.55, 0.1],
+            Severity::Error => [0.8, 0.15, 0.15],
+        }
+    }
+
+    fn log_level(self) -> log::Level {
+        match self {
+            Severity::Info => log::Level::Info,
+            Severity::Warning => log::Level::Warn,
+            Severity::Error => log::Level::Error,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ErrorEntry {
+    pub severity: Severity,
+    pub message: String,
+    pub time: instant::Instant,
+}
+
+// Recoverable errors for the window instead of only the console. Every report is logged too (and
+// so ends up in bug reports). Plain reports show as toasts for a few seconds, sticky ones as a
+// banner until whoever reported them resolves their key
+pub struct ErrorLog {
+    toasts: VecDeque<ErrorEntry>,
+    sticky: Vec<(String, ErrorEntry)>,
+    history: VecDeque<ErrorEntry>,
+}
+
+impl Default for ErrorLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ErrorLog {
+    pub fn new() -> Self {
+        Self {
+            toasts: VecDeque::new(),
+            sticky: Vec::new(),
+            history: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, severity: Severity, message: String) -> ErrorEntry {
+        log::log!(severity.log_level(), "{}", message);
+        let entry = ErrorEntry { severity, message, time: instant::Instant::now() };
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(entry.clone());
+        entry
+    }
+
+    pub fn report(&mut self, severity: Severity, message: String) {
+        let entry = self.record(severity, message);
+        if self.toasts.len() == MAX_TOASTS {
+            self.toasts.pop_front();
+        }
+        self.toasts.push_back(entry);
+    }
+
+    // Replaces the banner with the same key. Repeating the same message doesn't log it again, so
+    // it's fine to report every frame
+    pub fn report_sticky(&mut self, key: &str, severity: Severity, message: String) {
+        if let Some((_, entry)) = self.sticky.iter().find(|(k, _)| k == key) {
+            if entry.severity == severity && entry.message == message {
+                return;
+            }
+        }
+        let entry = self.record(severity, message);
+        match self.sticky.iter_mut().find(|(k, _)| k == key) {
+            Some((_, existing)) => *existing = entry,
+            None => self.sticky.push((key.to_string(), entry)),
+        }
+    }
+
+    // Returns whether there was a banner for `key`
+    pub fn resolve(&mut self, key: &str) -> bool {
+        let count = self.sticky.len();
+        self.sticky.retain(|(k, _)| k != key);
+        self.sticky.len() != count
+    }
+
+    // Oldest first
+    pub fn history(&self) -> impl Iterator<Item = &ErrorEntry> {
+        self.history.iter()
+    }
+
+    // Nothing to draw, with or without the history panel
+    pub fn is_empty(&self, show_history: bool) -> bool {
+        self.toasts.is_empty() && self.sticky.is_empty() && (!show_history || self.history.is_empty())
+    }
+
+    // Drops toasts that have been up long enough
+    pub fn expire(&mut self) {
+        self.toasts.retain(|toast| toast.time.elapsed().as_secs_f32() < TOAST_SECONDS);
+    }
+
+    // Banners across the top, toasts stacked up from the bottom right and with `show_history` the
+    // recent history on the left, newest at the top. Text that doesn't fit is cut off
+    pub fn layout(&self, overlay: &mut TextOverlay, width: u32, height: u32, show_history: bool) {
+        let (width, height) = (width as f32, height as f32);
+        let line = LINE_HEIGHT as f32 * TEXT_SCALE;
+        let padding = 4.0;
+        let white = [1.0, 1.0, 1.0, 1.0];
+
+        let mut y = 0.0;
+        for (_, entry) in &self.sticky {
+            let [r, g, b] = entry.severity.color();
+            overlay.rect(0.0, y, width, line + padding, [r, g, b, 0.9]);
+            overlay.text(padding, y + padding, TEXT_SCALE, white, &fit(&entry.message, width - padding * 2.0));
+            y += line + padding;
+        }
+        let top = y;
+
+        let toast_width = (width * 0.4).max(200.0).min(width);
+        let mut y = height - padding;
+        for toast in self.toasts.iter().rev() {
+            let remaining = TOAST_SECONDS - toast.time.elapsed().as_secs_f32();
+            let alpha = (remaining / FADE_SECONDS).clamp(0.0, 1.0);
+            y -= line + padding;
+            if y < top {
+                break;
+            }
+            let [r, g, b] = toast.severity.color();
+            let x = width - toast_width - padding;
+            overlay.rect(x, y, toast_width, line + padding, [r, g, b, 0.85 * alpha]);
+            overlay.text(x + padding, y + padding, TEXT_SCALE, [1.0, 1.0, 1.0, alpha], &fit(&toast.message, toast_width - padding * 2.0));
+            y -= padding;
+        }
+
+        if show_history {
+            let panel_width = (width * 0.5).min(width);
+            let rows = ((height - top) / line) as usize;
+            overlay.rect(0.0, top, panel_width, rows as f32 * line, [0.0, 0.0, 0.0, 0.7]);
+            for (i, entry) in self.history.iter().rev().take(rows).enumerate() {
+                let [r, g, b] = entry.severity.color();
+                let text = format!("{:>5.0}s ago {}", entry.time.elapsed().as_secs_f32(), entry.message);
+                let text = fit(&text, panel_width - padding * 2.0);
+                overlay.text(padding, top + i as f32 * line + padding, TEXT_SCALE, [r + 0.2, g + 0.2, b + 0.2, 1.0], &text);
+            }
+        }
+    }
+}
+
+// `text` cut down to `width` pixels, with "..." when it was cut
+fn fit(text: &str, width: f32) -> String {
+    let max_chars = (width / TextOverlay::text_width(" ", TEXT_SCALE)).max(0.0) as usize;
+    let first_line = text.lines().next().unwrap_or("");
+    if first_line.chars().count() <= max_chars && !text.contains('\n') {
+        return first_line.to_string();
+    }
+    let kept = max_chars.saturating_sub(3);
+    first_line.chars().take(kept).chain("...".chars()).collect()
+}
@@ -0,0 +1,232 @@
+use crate::buffer::GrowableBuffer;
+use crate::luminance::{LuminanceHistogram, HISTOGRAM_BINS, HISTOGRAM_LOG2_RANGE, HISTOGRAM_MIN_LOG2};
+
+#[derive(Copy, Clone, Debug)]
+pub struct ExposureSettings {
+    // Fractions of texels left out at the dark and bright end of the histogram, so a few black
+    // pixels or a light source don't swing the exposure
+    pub low_percentile: f32,
+    pub high_percentile: f32,
+    // Average scene luminance is mapped to this, middle grey by default
+    pub key: f32,
+    // Adaptation rates per second, up for the exposure rising (scene got darker), down for it
+    // falling. Eyes take longer to adjust to the dark
+    pub speed_up: f32,
+    pub speed_down: f32,
+    pub min_exposure: f32,
+    pub max_exposure: f32,
+}
+
+impl Default for ExposureSettings {
+    fn default() -> Self {
+        Self {
+            low_percentile: 0.1,
+            high_percentile: 0.95,
+            key: 0.18,
+            speed_up: 1.0,
+            speed_down: 3.0,
+            min_exposure: 1.0 / 16.0,
+            max_exposure: 16.0,
+        }
+    }
+}
+
+// Eye adaptation from the luminance histogram. The measured frame already has the exposure applied
+// (there's no HDR target to measure before it), so dispatched() records the exposure it was drawn
+// with and measured() takes it back out
+pub struct AutoExposure {
+    pub settings: ExposureSettings,
+    // log2 of the exposure currently applied and of the one it's adapting towards
+    log2_exposure: f32,
+    log2_target: Option<f32>,
+    // Exposure of the frame whose histogram is being read back
+    dispatched_exposure: f32,
+}
+
+impl AutoExposure {
+    pub fn new(settings: ExposureSettings) -> Self {
+        Self {
+            settings,
+            log2_exposure: 0.0,
+            log2_target: None,
+            dispatched_exposure: 1.0,
+        }
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.log2_exposure.exp2()
+    }
+
+    // Forgets the adaptation, the next measurement is taken as is instead of blended towards.
+    // For scene switches, where adapting from the old scene would look like a fade
+    pub fn reset(&mut self) {
+        self.log2_exposure = 0.0;
+        self.log2_target = None;
+    }
+
+    // Call when the histogram of a frame drawn with `exposure` was dispatched
+    pub fn dispatched(&mut self, exposure: f32) {
+        self.dispatched_exposure = exposure;
+    }
+
+    // Sets the target from the histogram of the frame passed to dispatched()
+    pub fn measured(&mut self, histogram: &LuminanceHistogram) {
+        let Some(average) = average_log2(histogram, self.settings.low_percentile, self.settings.high_percentile) else {
+            return;
+        };
+        let scene = average - self.dispatched_exposure.log2();
+        let target = (self.settings.key.log2() - scene)
+            .clamp(self.settings.min_exposure.log2(), self.settings.max_exposure.log2());
+        if self.log2_target.is_none() {
+            // First measurement after a reset, nothing to adapt from
+            self.log2_exposure = target;
+        }
+        self.log2_target = Some(target);
+    }
+
+    // Moves the exposure towards the target, exponentially so it's frame rate independent
+    pub fn update(&mut self, dt: f32) {
+        let Some(target) = self.log2_target else {
+            return;
+        };
+        let speed = if target > self.log2_exposure { self.settings.speed_up } else { self.settings.speed_down };
+        self.log2_exposure += (target - self.log2_exposure) * (1.0 - (-dt * speed).exp());
+    }
+}
+
+// Bins of `histogram` that average_log2 counts, the ones between the percentiles
+pub fn included_bins(histogram: &LuminanceHistogram, low_percentile: f32, high_percentile: f32) -> [u32; HISTOGRAM_BINS] {
+    let total = histogram.total();
+    // Rounded down at both ends, so tiny histograms keep their texels
+    let mut skip_low = (total as f64 * low_percentile as f64) as u64;
+    let skip_high = (total as f64 * (1.0 - high_percentile) as f64) as u64;
+    let mut keep = total.saturating_sub(skip_low + skip_high);
+    let mut included = [0; HISTOGRAM_BINS];
+    for (bin, &count) in histogram.bins.iter().enumerate() {
+        let mut count = count as u64;
+        let skipped = count.min(skip_low);
+        skip_low -= skipped;
+        count -= skipped;
+        let kept = count.min(keep);
+        keep -= kept;
+        included[bin] = kept as u32;
+    }
+    included
+}
+
+// Texel weighted average of the bin centers between the percentiles, None for an empty histogram
+pub fn average_log2(histogram: &LuminanceHistogram, low_percentile: f32, high_percentile: f32) -> Option<f32> {
+    let included = included_bins(histogram, low_percentile, high_percentile);
+    let count = included.iter().map(|&c| c as f64).sum::<f64>();
+    if count == 0.0 {
+        return None;
+    }
+    let sum = included.iter()
+        .enumerate()
+        .map(|(bin, &c)| c as f64 * LuminanceHistogram::bin_center_log2(bin) as f64)
+        .sum::<f64>();
+    Some((sum / count) as f32)
+}
+
+// Layout must match BarInput in exposure.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct Bar {
+    // Left, right, top as fractions of the overlay, bottom is always 0
+    rect: [f32; 3],
+    color_index: u32,
+}
+
+// Colors in exposure.wgsl
+const BAR_BACKGROUND: u32 = 0;
+const BAR_EXCLUDED: u32 = 1;
+const BAR_INCLUDED: u32 = 2;
+const BAR_AVERAGE: u32 = 3;
+
+// The histogram as bars, bins outside the percentiles greyed out and a marker at the average.
+// Drawn into whatever viewport the caller sets, no text rendering yet for labels
+pub struct HistogramOverlay {
+    bars: GrowableBuffer,
+    bar_count: u32,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl HistogramOverlay {
+    pub fn new(device: &wgpu::Device, surface_format: wgpu::TextureFormat) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("exposure.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Histogram Overlay Pipeline Layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Histogram Overlay Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_bar",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<Bar>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Uint32],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_bar",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            bars: GrowableBuffer::new(
+                device,
+                "Histogram Overlay Bar Buffer",
+                wgpu::BufferUsages::VERTEX,
+                ((HISTOGRAM_BINS + 2) * std::mem::size_of::<Bar>()) as wgpu::BufferAddress,
+            ),
+            bar_count: 0,
+            pipeline,
+        }
+    }
+
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, histogram: &LuminanceHistogram, settings: &ExposureSettings) {
+        let included = included_bins(histogram, settings.low_percentile, settings.high_percentile);
+        let tallest = histogram.bins.iter().copied().max().unwrap_or(0).max(1) as f32;
+        let width = 1.0 / HISTOGRAM_BINS as f32;
+
+        let mut bars = vec![Bar { rect: [0.0, 1.0, 1.0], color_index: BAR_BACKGROUND }];
+        for (bin, (&count, &included)) in histogram.bins.iter().zip(&included).enumerate() {
+            let left = bin as f32 * width;
+            // Small gap between bars
+            let rect = [left, left + width * 0.8, count as f32 / tallest];
+            bars.push(Bar { rect, color_index: if included > 0 { BAR_INCLUDED } else { BAR_EXCLUDED } });
+        }
+        if let Some(average) = average_log2(histogram, settings.low_percentile, settings.high_percentile) {
+            // Bin n spans n..n + 1 on the axis, bin 0 is the one below the log2 range
+            let bin = (average - HISTOGRAM_MIN_LOG2) / HISTOGRAM_LOG2_RANGE * (HISTOGRAM_BINS - 1) as f32 + 1.0;
+            let x = bin.clamp(0.0, HISTOGRAM_BINS as f32) * width;
+            bars.push(Bar { rect: [x - 0.002, x + 0.002, 1.0], color_index: BAR_AVERAGE });
+        }
+        self.bars.write(device, queue, bytemuck::cast_slice(&bars));
+        self.bar_count = bars.len() as u32;
+    }
+
+    // Fills the pass's current viewport, which has to be on a surface format target without depth
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.bar_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.bars.buffer().slice(..));
+        render_pass.draw(0..6, 0..self.bar_count);
+    }
+}
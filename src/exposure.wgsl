@@ -0,0 +1,40 @@
+// Luminance histogram overlay, see HistogramOverlay in exposure.rs. One instance per bar
+
+// Layout must match Bar in exposure.rs
+struct BarInput {
+    // Left, right, top as fractions of the viewport, bars stand on the bottom edge
+    @location(0) rect: vec3<f32>,
+    @location(1) color_index: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) color_index: u32,
+}
+
+@vertex
+fn vs_bar(@builtin(vertex_index) in_vertex_index: u32, bar: BarInput) -> VertexOutput {
+    // Two triangles, corners in 0..1
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0), vec2<f32>(1.0, 0.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0), vec2<f32>(1.0, 1.0), vec2<f32>(0.0, 1.0),
+    );
+    let corner = corners[in_vertex_index];
+    let position = vec2<f32>(mix(bar.rect.x, bar.rect.y, corner.x), corner.y * bar.rect.z);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(position * 2.0 - 1.0, 0.0, 1.0);
+    out.color_index = bar.color_index;
+    return out;
+}
+
+@fragment
+fn fs_bar(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Background, bins outside the percentiles, bins averaged, average marker
+    var colors = array<vec4<f32>, 4>(
+        vec4<f32>(0.0, 0.0, 0.0, 0.6),
+        vec4<f32>(0.35, 0.35, 0.35, 1.0),
+        vec4<f32>(0.9, 0.9, 0.9, 1.0),
+        vec4<f32>(1.0, 0.6, 0.1, 1.0),
+    );
+    return colors[in.color_index];
+}
@@ -0,0 +1,264 @@
+use crate::reflection::{ReflectedLayout, ShaderReflection};
+
+// Fog for a deferred path, where lighting runs once per pixel and there are no per object shaders
+// to fog in: a fullscreen pass that reconstructs positions from the depth buffer and blends the
+// fog color over the lit scene. Reads the fog from the camera uniform and uses the same visibility
+// as the forward shaders, so both look the same (see check_fog). Pixels at the far plane are left
+// to the sky, which fogs itself
+pub struct FogPass {
+    bind_group_layout: ReflectedLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl FogPass {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat, camera_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        let bind_group_layout = ShaderReflection::new("Fog", include_str!("fog.wgsl"))
+            .and_then(|shader| shader.layout(device, 1))
+            .unwrap_or_else(|e| panic!("{}", e));
+        let pipeline = Self::create_pipeline(device, color_format, camera_bind_group_layout, &bind_group_layout);
+        Self { bind_group_layout, pipeline }
+    }
+
+    // Call after the surface format changes
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        self.pipeline = Self::create_pipeline(device, color_format, camera_bind_group_layout, &self.bind_group_layout);
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        bind_group_layout: &ReflectedLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("fog.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Fog Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout.layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Fog Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    // mix(scene, fog color, alpha), the scene's alpha stays
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::COLOR,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // Fogs `target` in place. `depth` is the scene's depth texture, single sampled, and
+    // `camera_bind_group` the camera it was drawn with
+    pub fn apply(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        depth: &wgpu::Texture,
+        target: &wgpu::TextureView,
+    ) {
+        // Stencil can't be bound along with depth
+        let depth_view = depth.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+        let bind_group = self.bind_group_layout
+            .create_bind_group(device, &[("t_depth".into(), wgpu::BindingResource::TextureView(&depth_view))])
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Fog Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::DeferredDestruction;
+    use crate::camera::{Camera, CameraUniform, FogFalloff, FogParams, Projection};
+    use crate::image_diff::{compare_rgba, DiffOptions};
+    use crate::math::Vec3;
+    use crate::pipeline::PipelineConfig;
+    use crate::sky::SkyRenderer;
+    use crate::streaming::{ChunkStreamer, StreamingSettings};
+    use crate::texture::Texture;
+
+    // Streams in terrain around a camera looking at the horizon and draws it over a plain blue sky
+    // with height fog twice: fogged in the terrain shader, and unfogged with FogPass over it. The two
+    // have to match under image_diff and differ clearly from no fog at all. The sky has to be fog
+    // colored at the horizon and clearer straight up
+    #[test]
+    fn forward_and_fullscreen_fog_match() {
+        let Some((device, queue)) = crate::shader_test::device() else {
+            return;
+        };
+        const SIZE: u32 = 128;
+        let config = PipelineConfig {
+            color_format: wgpu::TextureFormat::Rgba8Unorm,
+            depth_format: wgpu::TextureFormat::Depth32Float,
+            depth_write: true,
+            sample_count: 1,
+            unclipped_depth: false,
+            overlay_depth_bias: wgpu::DepthBiasState::default(),
+        };
+        let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Fog Check Camera Bind Group Layout"),
+            entries: crate::CAMERA_LAYOUT_ENTRIES,
+        });
+        // Level, so the horizon is the middle row
+        let camera = Camera {
+            eye: Vec3::new(0.0, 20.0, 0.0),
+            target: Vec3::new(0.0, 20.0, -10.0),
+            up: Vec3::Y,
+            aspect: 1.0,
+            fovy: 60.0,
+            znear: 0.1,
+            zfar: 500.0,
+            projection: Projection::Perspective,
+        };
+        let fog = FogParams {
+            color: [0.6, 0.65, 0.7],
+            falloff: FogFalloff::ExponentialSquared { density: 0.02, start: 5.0, height: 0.0, height_falloff: 0.1 },
+        };
+        let camera_bind_group = |fog: Option<&FogParams>| {
+            let mut uniform = CameraUniform::new();
+            uniform.update_view_proj(&camera);
+            uniform.set_fog(fog);
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Fog Check Camera Buffer"),
+                size: std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            queue.write_buffer(&buffer, 0, bytemuck::bytes_of(&uniform));
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Fog Check Camera Bind Group"),
+                layout: &camera_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() }],
+            })
+        };
+        let fogged = camera_bind_group(Some(&fog));
+        let clear = camera_bind_group(None);
+
+        let settings = StreamingSettings { upload_budget: u64::MAX, ..Default::default() };
+        let mut terrain = ChunkStreamer::new(device, &config, &camera_bind_group_layout, settings, 1);
+        let mut deferred = DeferredDestruction::new(1);
+        let started = instant::Instant::now();
+        loop {
+            terrain.update(device, queue, &mut deferred, camera.eye);
+            let stats = terrain.stats();
+            if stats.resident > 0 && stats.generating == 0 && stats.uploading == 0 {
+                break;
+            }
+            assert!(started.elapsed().as_secs() <= 30, "Terrain didn't finish streaming in: {}", stats);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let mut sky = SkyRenderer::new(device, &config, &camera_bind_group_layout);
+        let blue = [0.1, 0.3, 0.8, 1.0].repeat(2);
+        sky.set_environment(device, &Texture::from_rgba_f32(device, queue, &blue, 2, 1, "Fog Check Sky"));
+        let fog_pass = FogPass::new(device, config.color_format, &camera_bind_group_layout);
+
+        // The sky always fogs itself, the terrain only with `terrain_camera`
+        let render = |terrain_camera: &wgpu::BindGroup, fog_pass: Option<&FogPass>| -> Vec<u8> {
+            let target = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Fog Check Target"),
+                size: wgpu::Extent3d { width: SIZE, height: SIZE, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: config.color_format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+            let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+            let depth = Texture::create_depth_texture_sized(device, SIZE, SIZE, config.depth_format, 1, "Fog Check Depth");
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Fog Check Encoder"),
+            });
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Fog Check Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &view,
+                        resolve_target: None,
+                        ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::BLACK), store: true },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &depth.view,
+                        depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Clear(1.0), store: true }),
+                        stencil_ops: None,
+                    }),
+                });
+                sky.draw(&mut render_pass, &fogged);
+                terrain.draw(&mut render_pass, terrain_camera);
+            }
+            if let Some(fog_pass) = fog_pass {
+                fog_pass.apply(device, &mut encoder, &fogged, &depth.texture, &view);
+            }
+            queue.submit(std::iter::once(encoder.finish()));
+            crate::readback::read_texture_rgba(device, queue, &target)
+        };
+        let forward = render(&fogged, None);
+        let fullscreen = render(&clear, Some(&fog_pass));
+        let unfogged = render(&clear, None);
+        terrain.retire_all(&mut deferred);
+
+        let options = DiffOptions { threshold: 2, ignore_alpha: true, heatmap: None };
+        let diff = compare_rgba(&forward, &fullscreen, SIZE, SIZE, &options).unwrap();
+        assert_eq!(diff.pixels_over_threshold, 0, "Forward and fullscreen fog differ:\n{}", diff);
+        let diff = compare_rgba(&forward, &unfogged, SIZE, SIZE, &options).unwrap();
+        assert!(diff.pixels_over_threshold >= (SIZE * SIZE / 4) as u64, "Fog barely changed the terrain:\n{}", diff);
+
+        let pixel = |x: u32, y: u32| -> [u8; 3] {
+            let i = ((y * SIZE + x) * 4) as usize;
+            [forward[i], forward[i + 1], forward[i + 2]]
+        };
+        let fog_color = fog.color.map(|c| (c * 255.0).round() as u8);
+        // Just above the horizon, terrain peaks don't reach that high from up here
+        let horizon = pixel(SIZE / 2, SIZE / 2 - 2);
+        assert!(
+            horizon.iter().zip(fog_color).all(|(&c, f)| c.abs_diff(f) <= 8),
+            "The sky at the horizon is {:?}, expected about the fog color {:?}",
+            horizon, fog_color
+        );
+        let zenith = pixel(SIZE / 2, 0);
+        assert!(
+            zenith[2].saturating_sub(zenith[0]) >= horizon[2].saturating_sub(horizon[0]) + 40,
+            "The sky up high {:?} isn't much bluer than at the horizon {:?}",
+            zenith, horizon
+        );
+    }
+}
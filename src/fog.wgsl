@@ -0,0 +1,81 @@
+// Fog as a fullscreen pass over a lit scene, see FogPass in fog.rs. Each pixel's world position
+// comes back from depth, the fog color is blended over it by the same visibility the forward
+// shaders use
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    exposure: f32,
+    fog_mode: u32,
+    fog_start: f32,
+    fog_end: f32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    eye: vec3<f32>,
+    fog_height: f32,
+    forward: vec3<f32>,
+    fog_height_falloff: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Depth aspect as a plain float texture, GL can't read depth textures without a comparison
+@group(1) @binding(0)
+var t_depth: texture_2d<f32>;
+
+// Has to match the FOG_ constants in camera.rs
+const FOG_LINEAR: u32 = 1u;
+const FOG_EXPONENTIAL: u32 = 2u;
+const FOG_EXPONENTIAL_SQUARED: u32 = 3u;
+
+fn view_depth(world_position: vec3<f32>) -> f32 {
+    return dot(world_position - camera.eye, camera.forward);
+}
+
+// Same as in shader.wgsl
+fn fog_amount(direction_y: f32, distance: f32) -> f32 {
+    let span = max(distance - camera.fog_start, 0.0);
+    let start_height = camera.eye.y + direction_y * camera.fog_start;
+    let density = camera.fog_density * exp(min(-camera.fog_height_falloff * (start_height - camera.fog_height), 80.0));
+    let k = camera.fog_height_falloff * direction_y;
+    if abs(k * span) < 0.0001 {
+        return density * span;
+    }
+    return density * (1.0 - exp(min(-k * span, 80.0))) / k;
+}
+
+fn fog_visibility(world_position: vec3<f32>) -> f32 {
+    let depth = view_depth(world_position);
+    if camera.fog_mode == FOG_LINEAR {
+        return clamp((camera.fog_end - depth) / max(camera.fog_end - camera.fog_start, 0.0001), 0.0, 1.0);
+    } else if camera.fog_mode == FOG_EXPONENTIAL {
+        return exp(-camera.fog_density * max(depth, 0.0));
+    } else if camera.fog_mode == FOG_EXPONENTIAL_SQUARED {
+        let ray = world_position - camera.eye;
+        let distance = length(ray);
+        let amount = fog_amount(ray.y / max(distance, 0.0001), distance);
+        return exp(-amount * amount);
+    }
+    return 1.0;
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    return vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+}
+
+// Alpha is how much fog, the blend state mixes the fog color in by it
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let depth = textureLoad(t_depth, vec2<i32>(position.xy), 0).r;
+    // Nothing drawn there, the sky fogs itself
+    if depth >= 1.0 {
+        discard;
+    }
+    let uv = position.xy / vec2<f32>(textureDimensions(t_depth));
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = camera.inv_view_proj * ndc;
+    return vec4<f32>(camera.fog_color * camera.exposure, 1.0 - fog_visibility(world.xyz / world.w));
+}
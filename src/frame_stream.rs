@@ -0,0 +1,164 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+// Streams rendered frames back to the CPU without ever waiting on the GPU.
+// Frame i is copied into buffer i % N of a ring of readback buffers. A buffer is mapped once the
+// copy is submitted and handed to the callback when the map finished, oldest first, so frames
+// arrive in order a few frames late. If the ring is full (callback or GPU too slow) the frame is
+// dropped instead of stalling the render loop, see dropped_frames()
+
+// Called with frame index, pixel rows and bytes per row. Rows are padded to 256 bytes, so
+// stride is usually more than width * 4. Pixels are in the texture's format (often BGRA)
+pub type FrameCallback = Box<dyn FnMut(u64, &[u8], u32)>;
+
+// Slot::map_state values, written by the map_async callback
+const MAP_WAITING: u8 = 0;
+const MAP_DONE: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+struct Slot {
+    buffer: wgpu::Buffer,
+    // Frame copied into the buffer and not handed out yet
+    frame: Option<u64>,
+    mapping: bool,
+    map_state: Arc<AtomicU8>,
+}
+
+pub struct FrameStream {
+    slots: Vec<Slot>,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+    next_frame: u64,
+    dropped_frames: u64,
+    callback: FrameCallback,
+}
+
+impl FrameStream {
+    // `ring_size` of 3-4 is enough to hide the map latency on most systems
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, ring_size: usize, callback: FrameCallback) -> Self {
+        let mut stream = Self {
+            slots: Vec::new(),
+            width,
+            height,
+            padded_bytes_per_row: 0,
+            next_frame: 0,
+            dropped_frames: 0,
+            callback,
+        };
+        stream.resize(device, width, height, ring_size.max(1));
+        stream
+    }
+
+    // Frames still in flight are lost
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32, ring_size: usize) {
+        // Texture -> buffer copies need rows aligned to 256 bytes
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        self.padded_bytes_per_row = (width * 4).div_ceil(align) * align;
+        self.width = width;
+        self.height = height;
+
+        self.slots = (0..ring_size)
+            .map(|_| Slot {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Frame Stream Buffer"),
+                    size: (self.padded_bytes_per_row * height) as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                frame: None,
+                mapping: false,
+                map_state: Arc::new(AtomicU8::new(MAP_WAITING)),
+            })
+            .collect();
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        self.slots.iter().map(|slot| slot.buffer.size()).sum()
+    }
+
+    pub fn ring_size(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+
+    // Records a copy of `texture` (needs COPY_SRC, 4 bytes per pixel, same size as the stream).
+    // Returns false if the frame was dropped because its ring slot is still busy
+    pub fn capture(&mut self, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) -> bool {
+        let frame = self.next_frame;
+        self.next_frame += 1;
+
+        let index = (frame % self.slots.len() as u64) as usize;
+        let slot = &mut self.slots[index];
+        if slot.frame.is_some() {
+            self.dropped_frames += 1;
+            return false;
+        }
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &slot.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        slot.frame = Some(frame);
+        true
+    }
+
+    // Call after the encoder passed to capture() was submitted
+    pub fn after_submit(&mut self) {
+        for slot in self.slots.iter_mut().filter(|slot| slot.frame.is_some() && !slot.mapping) {
+            slot.mapping = true;
+            let map_state = slot.map_state.clone();
+            slot.buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+                map_state.store(if result.is_ok() { MAP_DONE } else { MAP_FAILED }, Ordering::Release);
+            });
+        }
+    }
+
+    // Hands finished frames to the callback, oldest first. Doesn't wait for the GPU
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Poll);
+
+        loop {
+            let Some(slot) = self.slots.iter_mut()
+                .filter(|slot| slot.mapping)
+                .min_by_key(|slot| slot.frame)
+            else { return };
+
+            // Stop at the oldest unfinished one so frames stay in order
+            let frame = slot.frame.unwrap();
+            match slot.map_state.swap(MAP_WAITING, Ordering::Acquire) {
+                MAP_WAITING => return,
+                MAP_FAILED => self.dropped_frames += 1,
+                _ => {
+                    {
+                        let data = slot.buffer.slice(..).get_mapped_range();
+                        (self.callback)(frame, &data, self.padded_bytes_per_row);
+                    } // Mapped view has to be dropped before unmap
+                    slot.buffer.unmap();
+                }
+            }
+            slot.frame = None;
+            slot.mapping = false;
+        }
+    }
+}
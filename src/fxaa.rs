@@ -0,0 +1,102 @@
+use crate::reflection::{ReflectedLayout, ShaderReflection};
+
+// FXAA as a fullscreen pass from the single sampled scene into the surface, see fxaa.wgsl.
+// Cheap and needs no history, but only sees the final image so it also softens texture detail
+pub struct FxaaRenderer {
+    bind_group_layout: ReflectedLayout,
+    sampler: wgpu::Sampler,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl FxaaRenderer {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat) -> Self {
+        let bind_group_layout = ShaderReflection::new("FXAA", include_str!("fxaa.wgsl"))
+            .and_then(|shader| shader.layout(device, 0))
+            .unwrap_or_else(|e| panic!("{}", e));
+        // The edge search samples between texels, clamped so edges of the screen don't wrap
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("FXAA Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let pipeline = Self::create_pipeline(device, color_format, &bind_group_layout);
+
+        Self { bind_group_layout, sampler, pipeline }
+    }
+
+    // Call after the surface format changes
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device, color_format: wgpu::TextureFormat) {
+        self.pipeline = Self::create_pipeline(device, color_format, &self.bind_group_layout);
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        bind_group_layout: &ReflectedLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("fxaa.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("FXAA Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout.layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("FXAA Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // `src` has to be a different texture than `dst`, same size
+    pub fn apply(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        src: &wgpu::TextureView,
+        dst: &wgpu::TextureView,
+    ) {
+        let bind_group = self.bind_group_layout
+            .create_bind_group(device, &[
+                ("t_source".into(), wgpu::BindingResource::TextureView(src)),
+                ("s_source".into(), wgpu::BindingResource::Sampler(&self.sampler)),
+            ])
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("FXAA Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: dst,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Every pixel gets overwritten anyway
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
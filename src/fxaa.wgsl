@@ -0,0 +1,148 @@
+// FXAA, a compact take on the edge search of Timothy Lottes' FXAA 3.11 (quality preset ~12).
+// Finds the local edge direction from luma and blends along it, one pass over the final image
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle that covers the whole screen, same as blit.wgsl
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// Edges below this local contrast are left alone
+const EDGE_THRESHOLD: f32 = 0.125;
+const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+const SUBPIXEL_QUALITY: f32 = 0.75;
+const SEARCH_STEPS: i32 = 8;
+
+// The original works on gamma encoded values, sRGB textures sample as linear so sqrt gets close
+fn luma(color: vec3<f32>) -> f32 {
+    return sqrt(dot(color, vec3<f32>(0.299, 0.587, 0.114)));
+}
+
+fn luma_at(uv: vec2<f32>) -> f32 {
+    return luma(textureSampleLevel(t_source, s_source, uv, 0.0).rgb);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    let uv = in.uv;
+    let center = textureSampleLevel(t_source, s_source, uv, 0.0);
+    let luma_center = luma(center.rgb);
+    let luma_down = luma_at(uv + vec2<f32>(0.0, texel.y));
+    let luma_up = luma_at(uv - vec2<f32>(0.0, texel.y));
+    let luma_left = luma_at(uv - vec2<f32>(texel.x, 0.0));
+    let luma_right = luma_at(uv + vec2<f32>(texel.x, 0.0));
+
+    let luma_min = min(luma_center, min(min(luma_down, luma_up), min(luma_left, luma_right)));
+    let luma_max = max(luma_center, max(max(luma_down, luma_up), max(luma_left, luma_right)));
+    let range = luma_max - luma_min;
+    if range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD) {
+        return center;
+    }
+
+    let luma_down_left = luma_at(uv + vec2<f32>(-texel.x, texel.y));
+    let luma_up_right = luma_at(uv + vec2<f32>(texel.x, -texel.y));
+    let luma_up_left = luma_at(uv - texel);
+    let luma_down_right = luma_at(uv + texel);
+
+    let luma_down_up = luma_down + luma_up;
+    let luma_left_right = luma_left + luma_right;
+    let luma_left_corners = luma_down_left + luma_up_left;
+    let luma_down_corners = luma_down_left + luma_down_right;
+    let luma_right_corners = luma_down_right + luma_up_right;
+    let luma_up_corners = luma_up_right + luma_up_left;
+
+    let edge_horizontal = abs(-2.0 * luma_left + luma_left_corners)
+        + abs(-2.0 * luma_center + luma_down_up) * 2.0
+        + abs(-2.0 * luma_right + luma_right_corners);
+    let edge_vertical = abs(-2.0 * luma_up + luma_up_corners)
+        + abs(-2.0 * luma_center + luma_left_right) * 2.0
+        + abs(-2.0 * luma_down + luma_down_corners);
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // Which side of the pixel the edge is on
+    let luma_1 = select(luma_left, luma_up, is_horizontal);
+    let luma_2 = select(luma_right, luma_down, is_horizontal);
+    let gradient_1 = luma_1 - luma_center;
+    let gradient_2 = luma_2 - luma_center;
+    let is_1_steepest = abs(gradient_1) >= abs(gradient_2);
+    let gradient_scaled = 0.25 * max(abs(gradient_1), abs(gradient_2));
+
+    var step_length = select(texel.x, texel.y, is_horizontal);
+    var luma_local_average = 0.0;
+    if is_1_steepest {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma_1 + luma_center);
+    } else {
+        luma_local_average = 0.5 * (luma_2 + luma_center);
+    }
+
+    // Half a pixel over, onto the edge itself
+    var current_uv = uv;
+    if is_horizontal {
+        current_uv.y += step_length * 0.5;
+    } else {
+        current_uv.x += step_length * 0.5;
+    }
+
+    // Walk both ways along the edge until its contrast drops off
+    let offset = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), is_horizontal);
+    var uv_1 = current_uv - offset;
+    var uv_2 = current_uv + offset;
+    var luma_end_1 = luma_at(uv_1) - luma_local_average;
+    var luma_end_2 = luma_at(uv_2) - luma_local_average;
+    var reached_1 = abs(luma_end_1) >= gradient_scaled;
+    var reached_2 = abs(luma_end_2) >= gradient_scaled;
+    for (var i = 0; i < SEARCH_STEPS && !(reached_1 && reached_2); i++) {
+        // Steps grow further out, like the original's quality table
+        let step = select(1.0, 2.0, i >= 2) * select(1.0, 2.0, i >= 5);
+        if !reached_1 {
+            uv_1 -= offset * step;
+            luma_end_1 = luma_at(uv_1) - luma_local_average;
+            reached_1 = abs(luma_end_1) >= gradient_scaled;
+        }
+        if !reached_2 {
+            uv_2 += offset * step;
+            luma_end_2 = luma_at(uv_2) - luma_local_average;
+            reached_2 = abs(luma_end_2) >= gradient_scaled;
+        }
+    }
+
+    let distance_1 = select(uv.y - uv_1.y, uv.x - uv_1.x, is_horizontal);
+    let distance_2 = select(uv_2.y - uv.y, uv_2.x - uv.x, is_horizontal);
+    let is_direction_1 = distance_1 < distance_2;
+    let distance_final = min(distance_1, distance_2);
+    let edge_thickness = distance_1 + distance_2;
+    let is_luma_center_smaller = luma_center < luma_local_average;
+    let correct_variation = select(luma_end_2, luma_end_1, is_direction_1) < 0.0 != is_luma_center_smaller;
+    var pixel_offset = select(0.0, -distance_final / edge_thickness + 0.5, correct_variation);
+
+    // Subpixel aliasing, for details thinner than the edge search picks up
+    let luma_average = (2.0 * (luma_down_up + luma_left_right) + luma_left_corners + luma_right_corners) / 12.0;
+    let subpixel_1 = clamp(abs(luma_average - luma_center) / range, 0.0, 1.0);
+    let subpixel_2 = (-2.0 * subpixel_1 + 3.0) * subpixel_1 * subpixel_1;
+    let subpixel_offset = subpixel_2 * subpixel_2 * SUBPIXEL_QUALITY;
+    pixel_offset = max(pixel_offset, subpixel_offset);
+
+    var final_uv = uv;
+    if is_horizontal {
+        final_uv.y += pixel_offset * step_length;
+    } else {
+        final_uv.x += pixel_offset * step_length;
+    }
+    return textureSampleLevel(t_source, s_source, final_uv, 0.0);
+}
@@ -0,0 +1,211 @@
+use crate::camera::{Camera, Projection};
+use crate::debug_lines::LineBatch;
+use crate::math::{Aabb, Vec3};
+
+// Arrow length as a fraction of the view's height, kept whatever the distance
+const SCREEN_SIZE: f32 = 0.15;
+// Plane handles are squares between these fractions of the arrow length
+const PLANE_START: f32 = 0.25;
+const PLANE_END: f32 = 0.45;
+// Half the thickness of the boxes handles are picked by, in arrow lengths
+const PICK_RADIUS: f32 = 0.06;
+const HOVER_COLOR: [f32; 3] = [1.0, 0.9, 0.1];
+const AXES: [Vec3; 3] = [Vec3::X, Vec3::Y, Vec3::Z];
+const AXIS_COLORS: [[f32; 3]; 3] = [[0.9, 0.2, 0.2], [0.2, 0.9, 0.2], [0.3, 0.4, 1.0]];
+
+// Part of the translation gizmo, by world axis (0 = x, 1 = y, 2 = z)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Handle {
+    // Moves along the axis
+    Axis(usize),
+    // Moves in the plane the axis is the normal of
+    Plane(usize),
+}
+
+impl Handle {
+    pub const ALL: [Handle; 6] = [
+        Handle::Axis(0), Handle::Axis(1), Handle::Axis(2),
+        Handle::Plane(0), Handle::Plane(1), Handle::Plane(2),
+    ];
+
+    // Picking box with the gizmo at the origin and arrows 1 long
+    fn bounds(self) -> Aabb {
+        match self {
+            Handle::Axis(axis) => {
+                let thickness = Vec3::new(PICK_RADIUS, PICK_RADIUS, PICK_RADIUS);
+                Aabb { min: -thickness, max: thickness + AXES[axis] }
+            }
+            Handle::Plane(normal) => {
+                let (u, v) = plane_axes(normal);
+                let flat = AXES[normal] * PICK_RADIUS * 0.5;
+                Aabb { min: (u + v) * PLANE_START - flat, max: (u + v) * PLANE_END + flat }
+            }
+        }
+    }
+}
+
+// The two axes spanning the plane `normal` is the normal of
+fn plane_axes(normal: usize) -> (Vec3, Vec3) {
+    (AXES[(normal + 1) % 3], AXES[(normal + 2) % 3])
+}
+
+// Arrow length in world units at `position` that looks the same size from anywhere
+pub fn screen_scale(camera: &Camera, position: Vec3) -> f32 {
+    let half_fovy = (camera.fovy.to_radians() / 2.0).tan();
+    let distance = match camera.projection {
+        Projection::Perspective => (position - camera.eye).dot((camera.target - camera.eye).normalize()),
+        // Sized by the distance to the target, see Camera::build_view_projection_matrix
+        Projection::Orthographic => (camera.target - camera.eye).length(),
+    };
+    (2.0 * distance.max(camera.znear) * half_fovy * SCREEN_SIZE).max(f32::EPSILON)
+}
+
+// Handle the ray from `origin` along `direction` hits first, with the gizmo at `position` and
+// arrows `scale` long. Casts against boxes around the handles, like State::pick does for instances
+pub fn hit(position: Vec3, scale: f32, origin: Vec3, direction: Vec3) -> Option<Handle> {
+    let inverse = Vec3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+    Handle::ALL
+        .into_iter()
+        .filter_map(|handle| {
+            let local = handle.bounds();
+            let bounds = Aabb { min: position + local.min * scale, max: position + local.max * scale };
+            bounds.ray_intersection(origin, inverse).map(|t| (handle, t))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(handle, _)| handle)
+}
+
+// Arrows with heads on the axes and a square per plane. `hovered` is drawn highlighted
+pub fn draw(lines: &mut LineBatch, position: Vec3, scale: f32, hovered: Option<Handle>) {
+    let color = |handle: Handle, axis: usize| if hovered == Some(handle) { HOVER_COLOR } else { AXIS_COLORS[axis] };
+    for (axis, direction) in AXES.into_iter().enumerate() {
+        let handle_color = color(Handle::Axis(axis), axis);
+        let tip = position + direction * scale;
+        lines.line(position, tip, handle_color);
+        let (u, v) = plane_axes(axis);
+        let base = tip - direction * (scale * 0.15);
+        for side in [u, -u, v, -v] {
+            lines.line(tip, base + side * (scale * 0.06), handle_color);
+        }
+
+        let handle_color = color(Handle::Plane(axis), axis);
+        let corners = [(PLANE_START, PLANE_START), (PLANE_END, PLANE_START), (PLANE_END, PLANE_END), (PLANE_START, PLANE_END)]
+            .map(|(a, b)| position + (u * a + v * b) * scale);
+        for i in 0..4 {
+            lines.line(corners[i], corners[(i + 1) % 4], handle_color);
+        }
+    }
+}
+
+// A handle being dragged. Every new mouse ray gives where the gizmo is now, see position()
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Drag {
+    pub handle: Handle,
+    // Where the first ray met the handle's axis or plane, and where the gizmo was then
+    grabbed: Vec3,
+    start: Vec3,
+}
+
+impl Drag {
+    // None when the ray runs along the plane (or the axis), there's no point to hold on to then
+    pub fn new(handle: Handle, position: Vec3, origin: Vec3, direction: Vec3) -> Option<Drag> {
+        let grabbed = constrained_point(handle, position, origin, direction)?;
+        Some(Drag { handle, grabbed, start: position })
+    }
+
+    // Gizmo position for the ray, moved only along the handle's axis or plane. None where
+    // Drag::new would be, the caller keeps the last position
+    pub fn position(&self, origin: Vec3, direction: Vec3) -> Option<Vec3> {
+        let point = constrained_point(self.handle, self.start, origin, direction)?;
+        Some(self.start + (point - self.grabbed))
+    }
+}
+
+// Closest point to the ray on the axis line through `position`, or where the ray crosses the plane
+// through it
+fn constrained_point(handle: Handle, position: Vec3, origin: Vec3, direction: Vec3) -> Option<Vec3> {
+    match handle {
+        Handle::Axis(axis) => {
+            let axis = AXES[axis];
+            // Closest points of two lines, for the one on the axis
+            let offset = position - origin;
+            let along = axis.dot(direction);
+            let squared = direction.dot(direction);
+            let denominator = squared - along * along;
+            if denominator.abs() < 1e-6 * squared {
+                return None;
+            }
+            let s = (along * direction.dot(offset) - squared * axis.dot(offset)) / denominator;
+            Some(position + axis * s)
+        }
+        Handle::Plane(normal) => {
+            let normal = AXES[normal];
+            let facing = direction.dot(normal);
+            if facing.abs() < 1e-6 * direction.length() {
+                return None;
+            }
+            Some(origin + direction * ((position - origin).dot(normal) / facing))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-4
+    }
+
+    #[test]
+    fn rays_hit_the_handle_they_point_at() {
+        let position = Vec3::new(1.0, 2.0, 3.0);
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        // From above, the x arrow and the xz plane square
+        assert_eq!(hit(position, 2.0, position + Vec3::new(1.5, 10.0, 0.0), down), Some(Handle::Axis(0)));
+        assert_eq!(hit(position, 2.0, position + Vec3::new(0.7, 10.0, 0.7), down), Some(Handle::Plane(1)));
+        assert_eq!(hit(position, 2.0, position + Vec3::new(-1.0, 10.0, -1.0), down), None);
+    }
+
+    #[test]
+    fn axis_drags_stay_on_the_axis() {
+        let position = Vec3::new(0.0, 1.0, 0.0);
+        let eye = Vec3::new(0.0, 1.0, 10.0);
+        let drag = Drag::new(Handle::Axis(0), position, eye, Vec3::new(0.05, 0.0, -1.0)).unwrap();
+        let moved = drag.position(eye, Vec3::new(0.15, 0.0, -1.0)).unwrap();
+        assert!(close(moved, Vec3::new(1.0, 1.0, 0.0)), "{:?}", moved);
+        // A little up as well, that part is dropped
+        let moved = drag.position(eye, Vec3::new(0.15, 0.05, -1.0)).unwrap();
+        assert!(close(moved, Vec3::new(moved.x, 1.0, 0.0)) && moved.x > 0.9, "{:?}", moved);
+        // Looking straight down the axis there's nothing to follow
+        assert_eq!(Drag::new(Handle::Axis(2), position, eye, Vec3::new(0.0, 0.0, -1.0)), None);
+    }
+
+    #[test]
+    fn plane_drags_follow_the_ray() {
+        let position = Vec3::ZERO;
+        let eye = Vec3::new(0.0, 5.0, 0.0);
+        let drag = Drag::new(Handle::Plane(1), position, eye, Vec3::new(0.1, -1.0, 0.1)).unwrap();
+        let moved = drag.position(eye, Vec3::new(0.3, -1.0, -0.1)).unwrap();
+        assert!(close(moved, Vec3::new(1.0, 0.0, -1.0)), "{:?}", moved);
+    }
+
+    #[test]
+    fn scale_follows_the_distance() {
+        let mut camera = Camera {
+            eye: Vec3::new(0.0, 0.0, 10.0),
+            target: Vec3::ZERO,
+            up: Vec3::Y,
+            aspect: 1.0,
+            fovy: 90.0,
+            znear: 0.1,
+            zfar: 100.0,
+            projection: Projection::Perspective,
+        };
+        let near = screen_scale(&camera, Vec3::new(0.0, 0.0, 5.0));
+        let far = screen_scale(&camera, Vec3::new(3.0, 0.0, -10.0));
+        assert!((far / near - 4.0).abs() < 1e-4);
+        camera.projection = Projection::Orthographic;
+        assert_eq!(screen_scale(&camera, Vec3::new(0.0, 0.0, 5.0)), screen_scale(&camera, Vec3::new(0.0, 0.0, -10.0)));
+    }
+}
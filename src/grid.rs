@@ -0,0 +1,199 @@
+use crate::pipeline::PipelineConfig;
+use crate::reflection::{ReflectedLayout, ShaderReflection};
+
+// Look of the ground grid, see State::set_infinite_grid
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GridParams {
+    // World units between minor lines
+    pub cell_size: f32,
+    // Every this many minor lines is a major one, they stay visible further out
+    pub major_every: u32,
+    // Pixels across, like debug lines
+    pub line_width: f32,
+    // Distance from the eye along the ground where the grid is gone. Starts fading at half of it
+    pub fade_distance: f32,
+    pub color: [f32; 3],
+    pub major_color: [f32; 3],
+    pub opacity: f32,
+    // X axis in red and Z axis in blue
+    pub axes: bool,
+}
+
+impl Default for GridParams {
+    fn default() -> Self {
+        Self {
+            cell_size: 1.0,
+            major_every: 10,
+            line_width: 1.0,
+            fade_distance: 100.0,
+            color: [0.35, 0.35, 0.35],
+            major_color: [0.6, 0.6, 0.6],
+            opacity: 0.8,
+            axes: true,
+        }
+    }
+}
+
+// Layout must match GridUniform in grid.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GridUniform {
+    cell_size: f32,
+    major_every: f32,
+    line_width: f32,
+    fade_distance: f32,
+    color: [f32; 3],
+    opacity: f32,
+    major_color: [f32; 3],
+    axes: f32,
+}
+
+impl From<&GridParams> for GridUniform {
+    fn from(params: &GridParams) -> Self {
+        Self {
+            // Zero would divide the whole plane into NaN
+            cell_size: params.cell_size.max(1e-4),
+            major_every: params.major_every.max(1) as f32,
+            line_width: params.line_width.max(0.0),
+            fade_distance: params.fade_distance.max(1e-4),
+            color: params.color,
+            opacity: params.opacity.clamp(0.0, 1.0),
+            major_color: params.major_color,
+            axes: if params.axes { 1.0 } else { 0.0 },
+        }
+    }
+}
+
+// Grid on the y = 0 plane that goes on to the horizon. There's no geometry: a fullscreen triangle
+// intersects every pixel's view ray with the plane and writes that point's depth, so the scene
+// hides the grid where it's in front and the grid shows over what's below the ground. Blended
+// and not written to depth, drawn after the opaque scene
+pub struct GridRenderer {
+    params: GridParams,
+    enabled: bool,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: ReflectedLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl GridRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let bind_group_layout = ShaderReflection::new("Grid", include_str!("grid.wgsl"))
+            .and_then(|shader| shader.layout(device, 1))
+            .unwrap_or_else(|e| panic!("{}", e));
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Grid Uniform Buffer"),
+            size: std::mem::size_of::<GridUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = bind_group_layout
+            .create_bind_group(device, &[("grid".into(), uniform_buffer.as_entire_binding())])
+            .unwrap_or_else(|e| panic!("{}", e));
+        let pipeline = Self::create_pipeline(device, config, camera_bind_group_layout, &bind_group_layout.layout);
+
+        Self {
+            params: GridParams::default(),
+            enabled: false,
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // Call after the pipeline config changes
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        self.pipeline = Self::create_pipeline(device, config, camera_bind_group_layout, &self.bind_group_layout.layout);
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("grid.wgsl"));
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Grid Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Grid Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_grid",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_grid",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.color_format,
+                    // Antialiasing and the distance fade are in alpha
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: config.depth_format,
+                // Tested with the plane's depth from the fragment shader. Not written, the grid
+                // is mostly see through and transparent panes below it should still show
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: config.multisample(),
+            multiview: None,
+        })
+    }
+
+    pub fn set(&mut self, queue: &wgpu::Queue, enabled: bool, params: GridParams) {
+        self.enabled = enabled;
+        self.params = params;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&GridUniform::from(&params)));
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn params(&self) -> GridParams {
+        self.params
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        if !self.enabled {
+            return;
+        }
+        self.prewarm(render_pass, camera_bind_group);
+    }
+
+    // Draws even when disabled, for State::prewarm
+    pub fn prewarm<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        self.uniform_buffer.size()
+    }
+}
@@ -0,0 +1,114 @@
+// Infinite ground grid on y = 0, see GridRenderer in grid.rs
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    exposure: f32,
+    fog_mode: u32,
+    fog_start: f32,
+    fog_end: f32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    eye: vec3<f32>,
+    forward: vec3<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Layout must match GridUniform in grid.rs
+struct GridUniform {
+    // World units between minor lines
+    cell_size: f32,
+    // Minor cells per major line
+    major_every: f32,
+    // Pixels across
+    line_width: f32,
+    // Lines are gone this far from the eye, along the ground
+    fade_distance: f32,
+    color: vec3<f32>,
+    opacity: f32,
+    major_color: vec3<f32>,
+    // 1 to draw the x axis red and the z axis blue
+    axes: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> grid: GridUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Near and far plane points of the view ray, still homogeneous so they interpolate linearly
+    @location(0) near: vec4<f32>,
+    @location(1) far: vec4<f32>,
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+// Same triangle as sky.wgsl, depth comes from the fragment shader
+@vertex
+fn vs_grid(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    let ndc = uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.near = camera.inv_view_proj * vec4<f32>(ndc, 0.0, 1.0);
+    out.far = camera.inv_view_proj * vec4<f32>(ndc, 1.0, 1.0);
+    return out;
+}
+
+// 0 away from a line, 1 on it. `coord` is in cells, `derivative` its fwidth. Lines are
+// `line_width` pixels across with a pixel of falloff, like debug lines
+fn line_coverage(coord: vec2<f32>, derivative: vec2<f32>) -> f32 {
+    let pixels = abs(fract(coord - 0.5) - 0.5) / max(derivative, vec2<f32>(1e-6));
+    let distance = min(pixels.x, pixels.y);
+    let coverage = clamp(grid.line_width * 0.5 + 0.5 - distance, 0.0, 1.0) * min(grid.line_width, 1.0);
+    // Once cells get close to a pixel the lines would only alias into moire, fade them out before
+    let density = max(derivative.x, derivative.y);
+    return coverage * (1.0 - smoothstep(0.25, 0.5, density));
+}
+
+@fragment
+fn fs_grid(in: VertexOutput) -> FragmentOutput {
+    let near = in.near.xyz / in.near.w;
+    let far = in.far.xyz / in.far.w;
+    // Where the view ray crosses y = 0, between the near (0) and far (1) plane
+    // Misses are only discarded at the end, fwidth needs the whole quad of fragments
+    let t = -near.y / (far.y - near.y);
+    let hit = t > 0.0 && t <= 1.0;
+    let position = mix(near, far, t);
+
+    var out: FragmentOutput;
+    let clip = camera.view_proj * vec4<f32>(position, 1.0);
+    out.depth = clip.z / clip.w;
+
+    let coord = position.xz / grid.cell_size;
+    let derivative = fwidth(coord);
+    let minor = line_coverage(coord, derivative);
+    let major = line_coverage(coord / grid.major_every, derivative / grid.major_every);
+    var color = mix(grid.color, grid.major_color, step(minor, major));
+    var alpha = max(minor, major);
+
+    if grid.axes > 0.5 {
+        // Distance to the axis in pixels, the x axis runs along z = 0
+        let axis = abs(coord) / max(derivative, vec2<f32>(1e-6));
+        let x_axis = clamp(grid.line_width * 0.5 + 0.5 - axis.y, 0.0, 1.0);
+        let z_axis = clamp(grid.line_width * 0.5 + 0.5 - axis.x, 0.0, 1.0);
+        if x_axis > 0.0 || z_axis > 0.0 {
+            color = select(vec3<f32>(0.2, 0.2, 0.9), vec3<f32>(0.9, 0.2, 0.2), x_axis >= z_axis);
+            alpha = max(alpha, max(x_axis, z_axis));
+        }
+    }
+
+    // Fades with distance along the ground, so the far edge doesn't end in a hard line
+    let distance = length(position.xz - camera.eye.xz);
+    alpha *= grid.opacity * (1.0 - smoothstep(grid.fade_distance * 0.5, grid.fade_distance, distance));
+    if !hit || alpha <= 0.0 {
+        discard;
+    }
+    out.color = vec4<f32>(color * camera.exposure, alpha);
+    return out;
+}
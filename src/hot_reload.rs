@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+
+use crate::loader::{AssetKind, LoadHandle, LoadedAsset};
+use crate::texture::Texture;
+
+// Reloading of files State loaded when they change on disk, see State::reload_assets. Scene files
+// have their own reloading (State::reload_scene), with a FileWatcher as well
+
+// Notices when a file is saved, by polling its modification time (no file watching crates here).
+// Never sees a change on the web, there's no file system to look at
+pub struct FileWatcher {
+    path: PathBuf,
+    // None while the file is missing
+    modified: Option<std::time::SystemTime>,
+    last_check: instant::Instant,
+}
+
+impl FileWatcher {
+    // Checking more often than this makes no difference to someone editing the file
+    const INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+    // Changes from now on, the file as it is counts as seen
+    pub fn new(path: &Path) -> Self {
+        Self { path: path.to_path_buf(), modified: Self::modified(path), last_check: instant::Instant::now() }
+    }
+
+    fn modified(path: &Path) -> Option<std::time::SystemTime> {
+        std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // True once per change. Editors that save by replacing the file leave it missing for a
+    // moment, that isn't a change, it coming back with a new time is
+    pub fn poll(&mut self) -> bool {
+        if self.last_check.elapsed() < Self::INTERVAL {
+            return false;
+        }
+        self.last_check = instant::Instant::now();
+        match Self::modified(&self.path) {
+            Some(modified) if self.modified != Some(modified) => {
+                self.modified = Some(modified);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+
+// See State::on_asset_reloaded
+pub type ReloadCallback = Box<dyn FnMut(&mut crate::State, LoadHandle, &LoadedAsset)>;
+
+// A loaded asset as State patches it on reload. Weak, so assets whoever held them dropped stop
+// being watched
+#[derive(Clone)]
+pub enum WatchedAsset {
+    Texture(Weak<Texture>),
+    // State's own, there's only ever one
+    Environment,
+    PointCloud(Weak<wgpu::Buffer>),
+}
+
+impl WatchedAsset {
+    // None for what doesn't come from a file
+    pub fn of(asset: &LoadedAsset) -> Option<Self> {
+        match asset {
+            LoadedAsset::Texture(texture) => Some(WatchedAsset::Texture(Arc::downgrade(texture))),
+            LoadedAsset::Environment => Some(WatchedAsset::Environment),
+            LoadedAsset::PointCloud { buffer, .. } => Some(WatchedAsset::PointCloud(Arc::downgrade(buffer))),
+            LoadedAsset::MeshLods { .. } => None,
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            WatchedAsset::Texture(texture) => texture.strong_count() > 0,
+            WatchedAsset::Environment => true,
+            WatchedAsset::PointCloud(buffer) => buffer.strong_count() > 0,
+        }
+    }
+}
+
+struct Watched {
+    kind: AssetKind,
+    file: FileWatcher,
+    // None until the first load is uploaded, and after it failed
+    asset: Option<WatchedAsset>,
+    // A load or reload is queued, changes wait until it's done
+    busy: bool,
+}
+
+// Files of loaded assets by their handle
+#[derive(Default)]
+pub struct AssetWatcher {
+    watched: HashMap<LoadHandle, Watched>,
+}
+
+impl AssetWatcher {
+    // Right after the handle's load is queued
+    pub fn watch(&mut self, handle: LoadHandle, kind: AssetKind, path: &Path) {
+        self.watched.insert(handle, Watched { kind, file: FileWatcher::new(path), asset: None, busy: true });
+    }
+
+    pub fn unwatch(&mut self, handle: LoadHandle) {
+        self.watched.remove(&handle);
+    }
+
+    pub fn path(&self, handle: LoadHandle) -> Option<&Path> {
+        self.watched.get(&handle).map(|watched| watched.file.path())
+    }
+
+    // What a finished load would patch, None if it's a first load (or for handles not watched)
+    pub fn asset(&self, handle: LoadHandle) -> Option<&WatchedAsset> {
+        self.watched.get(&handle).and_then(|watched| watched.asset.as_ref())
+    }
+
+    // A load or reload is done. None keeps what was there, a failed reload leaves the old asset
+    pub fn finished(&mut self, handle: LoadHandle, asset: Option<WatchedAsset>) {
+        if let Some(watched) = self.watched.get_mut(&handle) {
+            watched.busy = false;
+            if asset.is_some() {
+                watched.asset = asset;
+            }
+        }
+    }
+
+    // Files saved since the last call whose assets are still around, to be loaded again under the
+    // same handle. They count as busy until finished()
+    pub fn changed(&mut self) -> Vec<(LoadHandle, AssetKind, PathBuf)> {
+        self.watched.retain(|_, watched| watched.asset.as_ref().is_none_or(WatchedAsset::is_alive));
+        let mut changed = Vec::new();
+        for (&handle, watched) in &mut self.watched {
+            if !watched.busy && watched.file.poll() {
+                watched.busy = true;
+                changed.push((handle, watched.kind, watched.file.path().to_path_buf()));
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn save(path: &Path, text: &str) {
+        std::fs::write(path, text).unwrap();
+        // Past FileWatcher::INTERVAL, and a modification time that differs on coarse file systems
+        std::thread::sleep(std::time::Duration::from_millis(300));
+    }
+
+    #[test]
+    fn changed_files_reload_once_loaded() {
+        let path = std::env::temp_dir().join(format!("wgpu-playground-hot-reload-{}.hdr", std::process::id()));
+        save(&path, "first");
+        let mut watcher = AssetWatcher::default();
+        let (mut loader, kind) = (crate::loader::AssetLoader::new(), AssetKind::Environment);
+        let handle = loader.load(kind, &path);
+        watcher.watch(handle, kind, &path);
+
+        // Still loading, it'll read the file as it is by then
+        save(&path, "second");
+        assert!(watcher.changed().is_empty());
+        watcher.finished(handle, Some(WatchedAsset::Environment));
+        assert!(watcher.asset(handle).is_some());
+        save(&path, "third");
+        assert_eq!(watcher.changed(), vec![(handle, kind, path.clone())]);
+
+        // Waits for the reload, and a failed one keeps the asset and tries again on the next save
+        save(&path, "fourth");
+        assert!(watcher.changed().is_empty());
+        watcher.finished(handle, None);
+        assert!(watcher.asset(handle).is_some());
+        save(&path, "fifth");
+        assert_eq!(watcher.changed().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
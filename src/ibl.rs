@@ -0,0 +1,223 @@
+use crate::texture::{self, Texture};
+
+// Must match the constants in ibl.wgsl
+pub const PREFILTER_MIPS: u32 = 5;
+pub const IRRADIANCE_SIZE: u32 = 32;
+pub const PREFILTER_SIZE: u32 = 128;
+pub const BRDF_LUT_SIZE: u32 = 256;
+
+const CUBE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const BRDF_LUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+
+// Image based lighting inputs, generated once per environment map on the GPU:
+// - irradiance: cubemap of the cosine weighted hemisphere average, for diffuse
+// - prefiltered: cubemap convolved with GGX, roughness 0..1 over PREFILTER_MIPS mips, for specular
+// - brdf_lut: split sum scale / bias for F0 by (n.v, roughness)
+// bind_group has them in that order (bindings 0-2) plus a linear sampler (3), see bind_group_layout
+pub struct IblMaps {
+    pub irradiance: wgpu::TextureView,
+    pub prefiltered: wgpu::TextureView,
+    pub brdf_lut: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl IblMaps {
+    // Group 3 of pbr.wgsl
+    pub const LAYOUT_ENTRIES: &'static [wgpu::BindGroupLayoutEntry] = &[
+        texture::layout_entry(0, wgpu::TextureViewDimension::Cube),
+        texture::layout_entry(1, wgpu::TextureViewDimension::Cube),
+        texture::layout_entry(2, wgpu::TextureViewDimension::D2),
+        wgpu::BindGroupLayoutEntry {
+            binding: 3,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+    ];
+
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("IBL Bind Group Layout"),
+            entries: Self::LAYOUT_ENTRIES,
+        })
+    }
+
+    // `environment` is an equirectangular map, e.g. from Texture::load_hdr. Work is submitted
+    // right away, a few hundred samples per texel, so expect a short hitch
+    pub fn generate(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        environment: &Texture,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("ibl.wgsl"));
+
+        let source_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("IBL Source Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let source_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("IBL Source Bind Group"),
+            layout: &source_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&environment.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&environment.sampler),
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("IBL Pipeline Layout"),
+            bind_group_layouts: &[&source_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |fs_entry, format: wgpu::TextureFormat| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(fs_entry),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: fs_entry,
+                    targets: &[Some(format.into())],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+
+        let create_texture = |label, size, mip_level_count, layers, format| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: layers,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+        };
+        let irradiance = create_texture("IBL Irradiance", IRRADIANCE_SIZE, 1, 6, CUBE_FORMAT);
+        let prefiltered = create_texture("IBL Prefiltered", PREFILTER_SIZE, PREFILTER_MIPS, 6, CUBE_FORMAT);
+        let brdf_lut = create_texture("IBL BRDF LUT", BRDF_LUT_SIZE, 1, 1, BRDF_LUT_FORMAT);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("IBL Encoder")
+        });
+        crate::debug_group(&mut encoder, "IBL", |encoder| {
+            // One pass per face / mip, target_index in the shader is face + 6 * mip
+            let mut draw = |pipeline: &wgpu::RenderPipeline, texture: &wgpu::Texture, layer: u32, mip: u32| {
+                let view = texture::create_mip_view(texture, mip, layer);
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("IBL Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            // Every texel gets written
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_bind_group(0, &source_bind_group, &[]);
+                let index = layer + 6 * mip;
+                render_pass.draw(0..3, index..index + 1);
+            };
+
+            let irradiance_pipeline = pipeline("fs_irradiance", CUBE_FORMAT);
+            let prefilter_pipeline = pipeline("fs_prefilter", CUBE_FORMAT);
+            let brdf_pipeline = pipeline("fs_brdf", BRDF_LUT_FORMAT);
+            for face in 0..6 {
+                draw(&irradiance_pipeline, &irradiance, face, 0);
+                for mip in 0..PREFILTER_MIPS {
+                    draw(&prefilter_pipeline, &prefiltered, face, mip);
+                }
+            }
+            draw(&brdf_pipeline, &brdf_lut, 0, 0);
+        });
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let cube_view = |texture: &wgpu::Texture| texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let irradiance = cube_view(&irradiance);
+        let prefiltered = cube_view(&prefiltered);
+        let brdf_lut = brdf_lut.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("IBL Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            // Roughness picks a mip, blend between them
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("IBL Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&irradiance),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&prefiltered),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&brdf_lut),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        Self { irradiance, prefiltered, brdf_lut, sampler, bind_group }
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        texture::estimated_bytes(CUBE_FORMAT, IRRADIANCE_SIZE, IRRADIANCE_SIZE, 6, 1, 1)
+            + texture::estimated_bytes(CUBE_FORMAT, PREFILTER_SIZE, PREFILTER_SIZE, 6, PREFILTER_MIPS, 1)
+            + texture::estimated_bytes(BRDF_LUT_FORMAT, BRDF_LUT_SIZE, BRDF_LUT_SIZE, 1, 1, 1)
+    }
+}
@@ -0,0 +1,153 @@
+// Precomputes image based lighting maps from an equirectangular environment, see ibl.rs.
+// Every map is drawn with a fullscreen triangle per face / mip, the instance index says which
+
+@group(0) @binding(0)
+var t_environment: texture_2d<f32>;
+@group(0) @binding(1)
+var s_environment: sampler;
+
+const PI: f32 = 3.14159265;
+// Must match PREFILTER_MIPS in ibl.rs
+const PREFILTER_MIPS: u32 = 5u;
+const IRRADIANCE_PHI_STEPS: u32 = 64u;
+const IRRADIANCE_THETA_STEPS: u32 = 16u;
+const PREFILTER_SAMPLES: u32 = 256u;
+const BRDF_SAMPLES: u32 = 512u;
+
+fn equirect_uv(direction: vec3<f32>) -> vec2<f32> {
+    let d = normalize(direction);
+    let u = atan2(d.z, d.x) / (2.0 * PI) + 0.5;
+    let v = acos(clamp(d.y, -1.0, 1.0)) / PI;
+    return vec2<f32>(u, v);
+}
+
+fn sample_environment(direction: vec3<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_environment, s_environment, equirect_uv(direction), 0.0).rgb;
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // 0..1, origin top left
+    @location(0) uv: vec2<f32>,
+    // face + 6 * mip for cubemaps
+    @location(1) @interpolate(flat) target_index: u32,
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) in_vertex_index: u32,
+    @builtin(instance_index) in_instance_index: u32,
+) -> VertexOutput {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    out.target_index = in_instance_index;
+    return out;
+}
+
+// Direction through texel uv of cube face (+X, -X, +Y, -Y, +Z, -Z order)
+fn cube_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    let u = uv.x * 2.0 - 1.0;
+    let v = uv.y * 2.0 - 1.0;
+    switch face {
+        case 0u: { return normalize(vec3<f32>(1.0, -v, -u)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -v, u)); }
+        case 2u: { return normalize(vec3<f32>(u, 1.0, v)); }
+        case 3u: { return normalize(vec3<f32>(u, -1.0, -v)); }
+        case 4u: { return normalize(vec3<f32>(u, -v, 1.0)); }
+        default: { return normalize(vec3<f32>(-u, -v, -1.0)); }
+    }
+}
+
+// Columns: tangent, bitangent, normal
+fn tangent_frame(n: vec3<f32>) -> mat3x3<f32> {
+    let up = select(vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(1.0, 0.0, 0.0), abs(n.z) > 0.999);
+    let t = normalize(cross(up, n));
+    let b = cross(n, t);
+    return mat3x3<f32>(t, b, n);
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// Half vector around n, distributed like the GGX lobe of `roughness`
+fn importance_sample_ggx(xi: vec2<f32>, n: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let h = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+    return tangent_frame(n) * h;
+}
+
+// Diffuse: cosine weighted average of the hemisphere around each direction
+@fragment
+fn fs_irradiance(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = cube_direction(in.target_index % 6u, in.uv);
+    let frame = tangent_frame(n);
+    var sum = vec3<f32>(0.0);
+    for (var i = 0u; i < IRRADIANCE_PHI_STEPS; i++) {
+        for (var j = 0u; j < IRRADIANCE_THETA_STEPS; j++) {
+            let phi = (f32(i) + 0.5) / f32(IRRADIANCE_PHI_STEPS) * 2.0 * PI;
+            let theta = (f32(j) + 0.5) / f32(IRRADIANCE_THETA_STEPS) * 0.5 * PI;
+            let local = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            sum += sample_environment(frame * local) * cos(theta) * sin(theta);
+        }
+    }
+    return vec4<f32>(PI * sum / f32(IRRADIANCE_PHI_STEPS * IRRADIANCE_THETA_STEPS), 1.0);
+}
+
+// Specular: environment convolved with GGX, roughness goes 0..1 over the mips.
+// Assumes view = normal, the usual split sum approximation
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let mip = in.target_index / 6u;
+    let roughness = f32(mip) / f32(PREFILTER_MIPS - 1u);
+    let n = cube_direction(in.target_index % 6u, in.uv);
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < PREFILTER_SAMPLES; i++) {
+        let h = importance_sample_ggx(hammersley(i, PREFILTER_SAMPLES), n, roughness);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+        if n_dot_l > 0.0 {
+            sum += sample_environment(l) * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return vec4<f32>(sum / max(weight, 0.0001), 1.0);
+}
+
+fn geometry_schlick_ggx(n_dot_v: f32, roughness: f32) -> f32 {
+    // k for IBL, analytic lights use (r + 1)^2 / 8
+    let k = roughness * roughness / 2.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k);
+}
+
+// Split sum BRDF: scale (r) and bias (g) for F0, u = n.v, v = roughness
+@fragment
+fn fs_brdf(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n_dot_v = max(in.uv.x, 0.001);
+    let roughness = in.uv.y;
+    let v = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let n = vec3<f32>(0.0, 0.0, 1.0);
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < BRDF_SAMPLES; i++) {
+        let h = importance_sample_ggx(hammersley(i, BRDF_SAMPLES), n, roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = max(l.z, 0.0);
+        let n_dot_h = max(h.z, 0.0);
+        let v_dot_h = max(dot(v, h), 0.0);
+        if n_dot_l > 0.0 {
+            let g = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+            let g_vis = g * v_dot_h / (n_dot_h * n_dot_v);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+    return vec4<f32>(scale / f32(BRDF_SAMPLES), bias / f32(BRDF_SAMPLES), 0.0, 1.0);
+}
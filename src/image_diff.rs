@@ -0,0 +1,126 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::readback;
+use crate::texture::decode_png_rgba;
+
+#[derive(Clone, Debug, Default)]
+pub struct DiffOptions {
+    // A pixel counts as different when any compared channel is off by more than this
+    pub threshold: u8,
+    // Leaves alpha out of everything. Surfaces and screenshots don't agree on it (opaque, straight,
+    // premultiplied), the color is usually what matters
+    pub ignore_alpha: bool,
+    // Writes the per pixel difference there as a PNG, black where equal, red to yellow to white
+    // with the largest channel difference
+    pub heatmap: Option<PathBuf>,
+}
+
+// Per channel statistics in RGBA order, alpha stays 0 with ignore_alpha
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiffReport {
+    pub width: u32,
+    pub height: u32,
+    pub max_difference: [u8; 4],
+    pub mean_difference: [f64; 4],
+    // Over all compared channels, infinite for identical images
+    pub psnr: f64,
+    pub pixels_over_threshold: u64,
+}
+
+impl DiffReport {
+    pub fn is_identical(&self) -> bool {
+        self.max_difference == [0; 4]
+    }
+}
+
+impl fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [r, g, b, a] = self.mean_difference;
+        writeln!(f, "{}x{}", self.width, self.height)?;
+        writeln!(f, "max difference: {:?}", self.max_difference)?;
+        writeln!(f, "mean difference: [{:.3}, {:.3}, {:.3}, {:.3}]", r, g, b, a)?;
+        writeln!(f, "PSNR: {:.2} dB", self.psnr)?;
+        write!(f, "pixels over threshold: {} of {}", self.pixels_over_threshold, self.width as u64 * self.height as u64)
+    }
+}
+
+fn load_png(path: &Path) -> Result<(Vec<u8>, u32, u32), String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+    decode_png_rgba(&bytes).map_err(|e| format!("Couldn't decode {}: {}", path.display(), e))
+}
+
+// Compares two PNGs of the same size, e.g. a screenshot against a golden image
+pub fn compare_images(a: &Path, b: &Path) -> Result<DiffReport, String> {
+    compare_images_with(a, b, &DiffOptions::default())
+}
+
+pub fn compare_images_with(a: &Path, b: &Path, options: &DiffOptions) -> Result<DiffReport, String> {
+    let (a_pixels, a_width, a_height) = load_png(a)?;
+    let (b_pixels, b_width, b_height) = load_png(b)?;
+    if (a_width, a_height) != (b_width, b_height) {
+        return Err(format!(
+            "Sizes differ: {} is {}x{}, {} is {}x{}",
+            a.display(), a_width, a_height, b.display(), b_width, b_height
+        ));
+    }
+    compare_rgba(&a_pixels, &b_pixels, a_width, a_height, options)
+}
+
+// Tightly packed RGBA8, as from readback::read_texture_rgba
+pub fn compare_rgba(a: &[u8], b: &[u8], width: u32, height: u32, options: &DiffOptions) -> Result<DiffReport, String> {
+    let len = width as usize * height as usize * 4;
+    if a.len() != len || b.len() != len {
+        return Err(format!("Expected {} bytes for {}x{}, got {} and {}", len, width, height, a.len(), b.len()));
+    }
+    let channels = if options.ignore_alpha { 3 } else { 4 };
+
+    let mut max_difference = [0u8; 4];
+    let mut sums = [0u64; 4];
+    let mut squared_sum = 0u64;
+    let mut pixels_over_threshold = 0;
+    let mut heatmap = options.heatmap.as_ref().map(|_| Vec::with_capacity(len));
+    for (a, b) in a.chunks_exact(4).zip(b.chunks_exact(4)) {
+        let mut largest = 0;
+        for channel in 0..channels {
+            let difference = a[channel].abs_diff(b[channel]);
+            max_difference[channel] = max_difference[channel].max(difference);
+            sums[channel] += difference as u64;
+            squared_sum += difference as u64 * difference as u64;
+            largest = largest.max(difference);
+        }
+        if largest > options.threshold {
+            pixels_over_threshold += 1;
+        }
+        if let Some(heatmap) = &mut heatmap {
+            heatmap.extend_from_slice(&heat_color(largest));
+        }
+    }
+
+    if let (Some(path), Some(heatmap)) = (&options.heatmap, heatmap) {
+        readback::write_png(path, width, height, &heatmap)
+            .map_err(|e| format!("Couldn't write heatmap to {}: {}", path.display(), e))?;
+    }
+
+    let pixels = (width as u64 * height as u64).max(1);
+    let mse = squared_sum as f64 / (pixels * channels as u64) as f64;
+    Ok(DiffReport {
+        width,
+        height,
+        max_difference,
+        mean_difference: sums.map(|sum| sum as f64 / pixels as f64),
+        psnr: if mse == 0.0 { f64::INFINITY } else { 10.0 * (255.0 * 255.0 / mse).log10() },
+        pixels_over_threshold,
+    })
+}
+
+// Black, then red, yellow and white as the difference grows. Small differences are stretched so
+// off by one pixels still show
+fn heat_color(difference: u8) -> [u8; 4] {
+    if difference == 0 {
+        return [0, 0, 0, 255];
+    }
+    let t = (difference as f32 / 255.0).sqrt();
+    let channel = |start: f32| (((t - start) * 3.0).clamp(0.0, 1.0) * 255.0) as u8;
+    [channel(0.0).max(64), channel(1.0 / 3.0), channel(2.0 / 3.0), 255]
+}
@@ -0,0 +1,338 @@
+use std::ops::Range;
+
+use wgpu::util::DeviceExt;
+
+use crate::camera::CameraUniform;
+use crate::instance::{Instance, InstanceRaw};
+use crate::math::{Aabb, Mat4, Vec3};
+use crate::pipeline::{self, PipelineConfig};
+use crate::texture;
+
+// Views baked around the mesh's Y axis, must match VIEWS in impostor.wgsl
+pub const IMPOSTOR_VIEWS: u32 = 8;
+// Pixels per view
+const TILE_SIZE: u32 = 64;
+// At most this many texture layers get their own atlas row, the rest wrap around
+const MAX_ROWS: u32 = 8;
+
+// Layout must match ImpostorUniform in impostor.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ImpostorUniform {
+    center: [f32; 3],
+    radius: f32,
+    fade_start: f32,
+    fade_length: f32,
+    rows: u32,
+    _padding: u32,
+}
+
+// What bake() needs of the mesh pipelines, see State::bake_impostors
+pub struct ImpostorSource<'a> {
+    pub layout: &'a wgpu::PipelineLayout,
+    pub shader: &'a wgpu::ShaderModule,
+    pub camera_bind_group_layout: &'a wgpu::BindGroupLayout,
+    pub layered_texture_bind_group: &'a wgpu::BindGroup,
+    pub bones_bind_group: &'a wgpu::BindGroup,
+    pub vertex_buffer: &'a wgpu::Buffer,
+    pub vertices: Range<u32>,
+    pub bounds: &'a Aabb,
+    pub layers: u32,
+}
+
+struct Atlas {
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+// Far away instances as camera facing quads with a pre-rendered picture of the mesh. bake()
+// renders the mesh from IMPOSTOR_VIEWS angles around its Y axis (one atlas row per texture layer)
+// with the regular mesh shader, unlit colors only. The quads read the instance buffer like the
+// mesh does, depth write and all, so they occlude correctly. They fade in over a distance range:
+// alpha to coverage with MSAA, an ordered dither without
+pub struct ImpostorRenderer {
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    atlas: Option<Atlas>,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ImpostorRenderer {
+    pub fn new(device: &wgpu::Device, config: &PipelineConfig, camera_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Impostor Uniform Buffer"),
+            size: std::mem::size_of::<ImpostorUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Impostor Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Impostor Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let pipeline = Self::create_pipeline(device, config, camera_bind_group_layout, &bind_group_layout);
+
+        Self {
+            uniform_buffer,
+            bind_group_layout,
+            sampler,
+            atlas: None,
+            pipeline,
+        }
+    }
+
+    // Call after the pipeline config changes
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device, config: &PipelineConfig, camera_bind_group_layout: &wgpu::BindGroupLayout) {
+        self.pipeline = Self::create_pipeline(device, config, camera_bind_group_layout, &self.bind_group_layout);
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("impostor.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Impostor Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let coverage = config.sample_count > 1;
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Impostor Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_impostor",
+                buffers: &[InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: if coverage { "fs_impostor_coverage" } else { "fs_impostor" },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(config.depth_state()),
+            multisample: wgpu::MultisampleState {
+                alpha_to_coverage_enabled: coverage,
+                ..config.multisample()
+            },
+            multiview: None,
+        })
+    }
+
+    pub fn is_baked(&self) -> bool {
+        self.atlas.is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.atlas = None;
+    }
+
+    // Renders the atlas from `source`, replacing the last one. `config` is the scene's, only its
+    // formats are used
+    pub fn bake(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, config: &PipelineConfig, source: &ImpostorSource) {
+        let rows = source.layers.clamp(1, MAX_ROWS);
+        let size = wgpu::Extent3d {
+            width: IMPOSTOR_VIEWS * TILE_SIZE,
+            height: rows * TILE_SIZE,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Impostor Atlas"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.color_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let depth = texture::Texture::create_depth_texture_sized(
+            device, size.width, size.height, config.depth_format, 1, "Impostor Bake Depth"
+        );
+
+        // The mesh shader as is, single sampled
+        let bake_config = PipelineConfig { sample_count: 1, depth_write: true, ..*config };
+        let pipeline = pipeline::create_render_pipeline(device, source.layout, source.shader, &bake_config, "vs_main", "fs_main");
+
+        let center = source.bounds.center();
+        let radius = (source.bounds.max - source.bounds.min).length() / 2.0;
+        let cameras = (0..IMPOSTOR_VIEWS)
+            .map(|view| {
+                let angle = view as f32 * std::f32::consts::TAU / IMPOSTOR_VIEWS as f32;
+                let eye = center + Vec3::new(angle.sin(), 0.0, angle.cos()) * (radius * 2.0);
+                let view_proj = Mat4::orthographic_rh(-radius, radius, -radius, radius, radius * 0.5, radius * 3.5)
+                    * Mat4::look_at_rh(eye, center, Vec3::Y);
+                // Exposure 1 and no fog, the impostor shader applies both
+                let mut uniform = CameraUniform::new();
+                uniform.set_view_proj(view_proj);
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Impostor Bake Camera"),
+                    contents: bytemuck::cast_slice(&[uniform]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Impostor Bake Camera Bind Group"),
+                    layout: source.camera_bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: buffer.as_entire_binding(),
+                        }
+                    ],
+                })
+            })
+            .collect::<Vec<_>>();
+        // One untransformed instance per row's texture layer
+        let instances = (0..rows)
+            .map(|layer| Instance { position: Vec3::ZERO, rotation: Mat4::IDENTITY, layer }.to_raw())
+            .collect::<Vec<_>>();
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Impostor Bake Instances"),
+            contents: bytemuck::cast_slice(&instances),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Impostor Bake Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Impostor Bake Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        // Transparent around the mesh
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            render_pass.set_pipeline(&pipeline);
+            render_pass.set_bind_group(1, source.layered_texture_bind_group, &[]);
+            render_pass.set_bind_group(2, source.bones_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, source.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            for (view, camera) in cameras.iter().enumerate() {
+                render_pass.set_bind_group(0, camera, &[]);
+                for row in 0..rows {
+                    let (x, y) = (view as u32 * TILE_SIZE, row * TILE_SIZE);
+                    render_pass.set_viewport(x as f32, y as f32, TILE_SIZE as f32, TILE_SIZE as f32, 0.0, 1.0);
+                    render_pass.draw(source.vertices.clone(), row..row + 1);
+                }
+            }
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let uniform = ImpostorUniform {
+            center: center.to_array(),
+            radius,
+            fade_start: 0.0,
+            fade_length: 0.0,
+            rows,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Impostor Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+        self.atlas = Some(Atlas { texture, bind_group });
+    }
+
+    // Distance range impostors fade in over, see LodSettings::impostor_distance
+    pub fn set_fade(&self, queue: &wgpu::Queue, start: f32, length: f32) {
+        // fade_start and fade_length follow center and radius
+        queue.write_buffer(&self.uniform_buffer, 16, bytemuck::cast_slice(&[start, length]));
+    }
+
+    // `instances` of the instance buffer, in the order LodSelector grouped them
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        instance_buffer: &'a wgpu::Buffer,
+        instances: Range<u32>,
+    ) {
+        let Some(atlas) = &self.atlas else { return };
+        if instances.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &atlas.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
+        render_pass.draw(0..6, instances);
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        self.uniform_buffer.size() + self.atlas.as_ref().map_or(0, |atlas| texture::texture_bytes(&atlas.texture))
+    }
+}
@@ -0,0 +1,177 @@
+// Impostors, see ImpostorRenderer in impostor.rs. One camera facing quad per instance, textured
+// with the atlas view closest to where the camera is around the object
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    exposure: f32,
+    fog_mode: u32,
+    fog_start: f32,
+    fog_end: f32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    eye: vec3<f32>,
+    fog_height: f32,
+    forward: vec3<f32>,
+    fog_height_falloff: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Layout must match ImpostorUniform in impostor.rs
+struct ImpostorUniform {
+    // Object space bounding sphere of the mesh, the quad covers it
+    center: vec3<f32>,
+    radius: f32,
+    // Impostors fade in from fade_start to fade_start + fade_length away from the camera
+    fade_start: f32,
+    fade_length: f32,
+    // Atlas rows, one per texture layer
+    rows: u32,
+}
+
+@group(1) @binding(0)
+var<uniform> impostor: ImpostorUniform;
+@group(1) @binding(1)
+var t_atlas: texture_2d<f32>;
+@group(1) @binding(2)
+var s_atlas: sampler;
+
+// Has to match the FOG_ constants in camera.rs
+const FOG_LINEAR: u32 = 1u;
+const FOG_EXPONENTIAL: u32 = 2u;
+const FOG_EXPONENTIAL_SQUARED: u32 = 3u;
+
+// Has to match IMPOSTOR_VIEWS in impostor.rs
+const VIEWS: u32 = 8u;
+const PI: f32 = 3.14159265;
+
+fn view_depth(world_position: vec3<f32>) -> f32 {
+    return dot(world_position - camera.eye, camera.forward);
+}
+
+// Same as in shader.wgsl
+fn fog_amount(direction_y: f32, distance: f32) -> f32 {
+    let span = max(distance - camera.fog_start, 0.0);
+    let start_height = camera.eye.y + direction_y * camera.fog_start;
+    let density = camera.fog_density * exp(min(-camera.fog_height_falloff * (start_height - camera.fog_height), 80.0));
+    let k = camera.fog_height_falloff * direction_y;
+    if abs(k * span) < 0.0001 {
+        return density * span;
+    }
+    return density * (1.0 - exp(min(-k * span, 80.0))) / k;
+}
+
+fn fog_visibility(world_position: vec3<f32>) -> f32 {
+    let depth = view_depth(world_position);
+    if camera.fog_mode == FOG_LINEAR {
+        return clamp((camera.fog_end - depth) / max(camera.fog_end - camera.fog_start, 0.0001), 0.0, 1.0);
+    } else if camera.fog_mode == FOG_EXPONENTIAL {
+        return exp(-camera.fog_density * max(depth, 0.0));
+    } else if camera.fog_mode == FOG_EXPONENTIAL_SQUARED {
+        let ray = world_position - camera.eye;
+        let distance = length(ray);
+        let amount = fog_amount(ray.y / max(distance, 0.0001), distance);
+        return exp(-amount * amount);
+    }
+    return 1.0;
+}
+
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    return mix(camera.fog_color * camera.exposure, color, fog_visibility(world_position));
+}
+
+// Same as in shader.wgsl
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) layer: u32,
+    @location(10) lod_tint: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) fade: f32,
+    @location(2) world_position: vec3<f32>,
+    @location(3) @interpolate(flat) lod_tint: u32,
+}
+
+@vertex
+fn vs_impostor(@builtin(vertex_index) in_vertex_index: u32, instance: InstanceInput) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[in_vertex_index];
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let center = (model_matrix * vec4<f32>(impostor.center, 1.0)).xyz;
+    let to_eye = camera.eye - center;
+
+    // Which of the baked views: the camera's angle around the object's Y axis, in object space.
+    // Instances are only rotated and moved, so the transpose undoes the rotation
+    let rotation = mat3x3<f32>(model_matrix[0].xyz, model_matrix[1].xyz, model_matrix[2].xyz);
+    let local = transpose(rotation) * to_eye;
+    let step = 2.0 * PI / f32(VIEWS);
+    let view = u32(round(atan2(local.x, local.z) / step) + f32(VIEWS)) % VIEWS;
+
+    // Facing the camera, upright as long as it isn't looking straight down
+    var right = cross(camera.forward, vec3<f32>(0.0, 1.0, 0.0));
+    if length(right) < 0.001 {
+        right = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    right = normalize(right);
+    let up = cross(right, camera.forward);
+    let world = center + (right * corner.x + up * corner.y) * impostor.radius;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world, 1.0);
+    let tile_uv = vec2<f32>(corner.x * 0.5 + 0.5, 0.5 - corner.y * 0.5);
+    let row = instance.layer % impostor.rows;
+    out.uv = (vec2<f32>(f32(view), f32(row)) + tile_uv) / vec2<f32>(f32(VIEWS), f32(impostor.rows));
+    out.fade = clamp((length(to_eye) - impostor.fade_start) / max(impostor.fade_length, 0.0001), 0.0, 1.0);
+    out.world_position = world;
+    out.lod_tint = instance.lod_tint;
+    return out;
+}
+
+fn shade(in: VertexOutput) -> vec4<f32> {
+    let texel = textureSample(t_atlas, s_atlas, in.uv);
+    var color = texel.rgb;
+    // Cyan, apart from the mesh LOD colors
+    if in.lod_tint != 0xffffffffu {
+        color = mix(color, vec3<f32>(0.2, 0.8, 0.9), 0.7);
+    }
+    return vec4<f32>(apply_fog(color * camera.exposure, in.world_position), texel.a * in.fade);
+}
+
+// Without MSAA: fades with an ordered dither, a fragment is either kept (and writes depth) or not
+@fragment
+fn fs_impostor(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = shade(in);
+    var bayer = array<f32, 16>(
+        0.0, 8.0, 2.0, 10.0,
+        12.0, 4.0, 14.0, 6.0,
+        3.0, 11.0, 1.0, 9.0,
+        15.0, 7.0, 13.0, 5.0,
+    );
+    let pixel = vec2<u32>(in.clip_position.xy) % 4u;
+    if color.a < (bayer[pixel.y * 4u + pixel.x] + 0.5) / 16.0 {
+        discard;
+    }
+    return vec4<f32>(color.rgb, 1.0);
+}
+
+// With MSAA: alpha to coverage does the dithering per sample
+@fragment
+fn fs_impostor_coverage(in: VertexOutput) -> @location(0) vec4<f32> {
+    return shade(in);
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use winit::event::{MouseButton, VirtualKeyCode};
+
+// Everything a key or mouse button can do, see State::perform. Recordings store these by name (input_record.rs),
+// so renaming one breaks old recordings
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    CameraShake,
+    ResetCamera,
+    ToggleBounds,
+    ToggleNormals,
+    ToggleTangentFrames,
+    TogglePerDraw,
+    ToggleFlatShading,
+    ToggleLuminance,
+    ToggleAutoExposure,
+    ToggleHistogram,
+    ToggleDepthClamp,
+    CycleSampleCount,
+    CycleAntiAliasing,
+    ToggleMotionBlur,
+    ToggleFog,
+    CycleWireframe,
+    ToggleErrorHistory,
+    ToggleLodColors,
+    CycleTransparency,
+    ToggleProjection,
+    ToggleSplitScreen,
+    TogglePreserveFrame,
+    Teleport,
+    ToggleCullingFreeze,
+    ToggleConservativeDemo,
+    CycleDebugView,
+    ToggleDayCycle,
+    LaterInDay,
+    EarlierInDay,
+    MoreInstances,
+    FewerInstances,
+    ClearDecals,
+    PlaceDecal,
+    ToggleComet,
+    Select,
+    Undo,
+    Redo,
+    DeleteSelected,
+    DuplicateSelected,
+    // Not recorded and works during playback too
+    BugReport,
+}
+
+impl Action {
+    pub const ALL: &'static [Action] = &[
+        Action::CameraShake,
+        Action::ResetCamera,
+        Action::ToggleBounds,
+        Action::ToggleNormals,
+        Action::ToggleTangentFrames,
+        Action::TogglePerDraw,
+        Action::ToggleFlatShading,
+        Action::ToggleLuminance,
+        Action::ToggleAutoExposure,
+        Action::ToggleHistogram,
+        Action::ToggleDepthClamp,
+        Action::CycleSampleCount,
+        Action::CycleAntiAliasing,
+        Action::ToggleMotionBlur,
+        Action::ToggleFog,
+        Action::CycleWireframe,
+        Action::ToggleErrorHistory,
+        Action::ToggleLodColors,
+        Action::CycleTransparency,
+        Action::ToggleProjection,
+        Action::ToggleSplitScreen,
+        Action::TogglePreserveFrame,
+        Action::Teleport,
+        Action::ToggleCullingFreeze,
+        Action::ToggleConservativeDemo,
+        Action::CycleDebugView,
+        Action::ToggleDayCycle,
+        Action::LaterInDay,
+        Action::EarlierInDay,
+        Action::MoreInstances,
+        Action::FewerInstances,
+        Action::ClearDecals,
+        Action::PlaceDecal,
+        Action::ToggleComet,
+        Action::Select,
+        Action::Undo,
+        Action::Redo,
+        Action::DeleteSelected,
+        Action::DuplicateSelected,
+        Action::BugReport,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.iter().copied().find(|action| format!("{:?}", action) == name)
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::CameraShake => "Shake the camera",
+            Action::ResetCamera => "Camera back home, or framing the instances",
+            Action::ToggleBounds => "Instance bounding boxes",
+            Action::ToggleNormals => "Vertex normals",
+            Action::ToggleTangentFrames => "Tangent frames (tangent, bitangent, normal)",
+            Action::TogglePerDraw => "One draw per instance instead of instancing",
+            Action::ToggleFlatShading => "Flat shading",
+            Action::ToggleLuminance => "Measure scene luminance",
+            Action::ToggleAutoExposure => "Auto exposure",
+            Action::ToggleHistogram => "Exposure histogram",
+            Action::ToggleDepthClamp => "Depth clamping instead of near plane clipping",
+            Action::CycleSampleCount => "MSAA sample count",
+            Action::CycleAntiAliasing => "MSAA / FXAA / TAA",
+            Action::ToggleMotionBlur => "Motion blur",
+            Action::ToggleFog => "Fog",
+            Action::CycleWireframe => "Wireframe off / all / selected",
+            Action::ToggleErrorHistory => "Error history",
+            Action::ToggleLodColors => "Tint instances by LOD level",
+            Action::CycleTransparency => "Sorted / order independent transparency",
+            Action::ToggleProjection => "Perspective / orthographic",
+            Action::ToggleSplitScreen => "Split screen with a top down view",
+            Action::TogglePreserveFrame => "Keep the previous frame instead of clearing",
+            Action::Teleport => "Jump 1000 units along x",
+            Action::ToggleCullingFreeze => "Freeze the culling camera",
+            Action::ToggleConservativeDemo => "Conservative rasterization demo",
+            Action::CycleDebugView => "Show the depth buffer / BRDF lookup table in a corner",
+            Action::ToggleDayCycle => "Start / pause the day cycle",
+            Action::LaterInDay => "An hour later",
+            Action::EarlierInDay => "An hour earlier",
+            Action::MoreInstances => "Double the instance count",
+            Action::FewerInstances => "Halve the instance count",
+            Action::ClearDecals => "Remove the decals placed by clicking",
+            Action::PlaceDecal => "Decal on the instance under the cursor",
+            Action::ToggleComet => "Something circling the scene with a trail",
+            Action::Select => "Select the instance under the cursor",
+            Action::Undo => "Undo the last edit",
+            Action::Redo => "Redo the last undone edit",
+            Action::DeleteSelected => "Delete the selected instance",
+            Action::DuplicateSelected => "Copy the selected instance next to it",
+            Action::BugReport => "Write a bug report",
+        }
+    }
+
+    // Actions that happen where the cursor is, recordings store its position with them
+    pub fn at_cursor(self) -> bool {
+        matches!(self, Action::PlaceDecal | Action::Select)
+    }
+}
+
+// Which key or mouse button does what. Starts out with the default bindings, State::rebind
+// changes them. A key or button does one action, an action can have several of them. Keys held
+// with Ctrl have their own bindings, the plain ones don't fire then
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputMap {
+    bindings: HashMap<VirtualKeyCode, Action>,
+    ctrl_bindings: HashMap<VirtualKeyCode, Action>,
+    buttons: HashMap<MouseButton, Action>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        use VirtualKeyCode::*;
+        let bindings = [
+            (T, Action::CameraShake),
+            (Home, Action::ResetCamera),
+            (B, Action::ToggleBounds),
+            (N, Action::ToggleNormals),
+            (Q, Action::ToggleTangentFrames),
+            (I, Action::TogglePerDraw),
+            (F, Action::ToggleFlatShading),
+            (L, Action::ToggleLuminance),
+            (E, Action::ToggleAutoExposure),
+            (H, Action::ToggleHistogram),
+            (Z, Action::ToggleDepthClamp),
+            (M, Action::CycleSampleCount),
+            (A, Action::CycleAntiAliasing),
+            (U, Action::ToggleMotionBlur),
+            (G, Action::ToggleFog),
+            (W, Action::CycleWireframe),
+            (R, Action::ToggleErrorHistory),
+            (Y, Action::ToggleLodColors),
+            (O, Action::CycleTransparency),
+            // Same key as Blender's view toggle, K for keyboards without a numpad
+            (Numpad5, Action::ToggleProjection),
+            (K, Action::ToggleProjection),
+            (V, Action::ToggleSplitScreen),
+            (P, Action::TogglePreserveFrame),
+            (J, Action::Teleport),
+            (X, Action::ToggleCullingFreeze),
+            (C, Action::ToggleConservativeDemo),
+            (S, Action::CycleDebugView),
+            (D, Action::ToggleDayCycle),
+            (RBracket, Action::LaterInDay),
+            (LBracket, Action::EarlierInDay),
+            (Equals, Action::MoreInstances),
+            (Plus, Action::MoreInstances),
+            (NumpadAdd, Action::MoreInstances),
+            (Minus, Action::FewerInstances),
+            (NumpadSubtract, Action::FewerInstances),
+            (Delete, Action::ClearDecals),
+            (Key1, Action::ToggleComet),
+            (Back, Action::DeleteSelected),
+            (F12, Action::BugReport),
+        ];
+        let ctrl_bindings = [(Z, Action::Undo), (Y, Action::Redo), (D, Action::DuplicateSelected)];
+        let buttons = [(MouseButton::Left, Action::Select), (MouseButton::Right, Action::PlaceDecal)];
+        Self {
+            bindings: bindings.into_iter().collect(),
+            ctrl_bindings: ctrl_bindings.into_iter().collect(),
+            buttons: buttons.into_iter().collect(),
+        }
+    }
+}
+
+impl InputMap {
+    pub fn action(&self, key: VirtualKeyCode) -> Option<Action> {
+        self.bindings.get(&key).copied()
+    }
+
+    // `key` pressed with Ctrl held
+    pub fn ctrl_action(&self, key: VirtualKeyCode) -> Option<Action> {
+        self.ctrl_bindings.get(&key).copied()
+    }
+
+    pub fn button_action(&self, button: MouseButton) -> Option<Action> {
+        self.buttons.get(&button).copied()
+    }
+
+    // Sorted so listings don't change order between runs
+    pub fn keys(&self, action: Action) -> Vec<VirtualKeyCode> {
+        bound_keys(&self.bindings, action)
+    }
+
+    // Like keys, the ones that need Ctrl
+    pub fn ctrl_keys(&self, action: Action) -> Vec<VirtualKeyCode> {
+        bound_keys(&self.ctrl_bindings, action)
+    }
+
+    // MouseButton isn't Ord, sorted by name like the listing shows them
+    pub fn buttons(&self, action: Action) -> Vec<MouseButton> {
+        let mut buttons = self.buttons.iter().filter(|(_, &a)| a == action).map(|(&button, _)| button).collect::<Vec<_>>();
+        buttons.sort_by_key(|button| format!("{:?}", button));
+        buttons
+    }
+
+    // Makes `key` the only key for `action`. Returns the action `key` was taken from, if any, that
+    // one keeps its other keys (and may be left with none)
+    pub fn rebind(&mut self, action: Action, key: VirtualKeyCode) -> Option<Action> {
+        self.bindings.retain(|_, &mut a| a != action);
+        self.ctrl_bindings.retain(|_, &mut a| a != action);
+        self.bindings.insert(key, action).filter(|&previous| previous != action)
+    }
+
+    // Key stops doing anything. Returns what it did
+    pub fn unbind(&mut self, key: VirtualKeyCode) -> Option<Action> {
+        self.bindings.remove(&key)
+    }
+
+    // Like rebind, for `key` with Ctrl held
+    pub fn rebind_ctrl(&mut self, action: Action, key: VirtualKeyCode) -> Option<Action> {
+        self.bindings.retain(|_, &mut a| a != action);
+        self.ctrl_bindings.retain(|_, &mut a| a != action);
+        self.ctrl_bindings.insert(key, action).filter(|&previous| previous != action)
+    }
+
+    // Like rebind, for mouse buttons. The action keeps its keys
+    pub fn rebind_button(&mut self, action: Action, button: MouseButton) -> Option<Action> {
+        self.buttons.retain(|_, &mut a| a != action);
+        self.buttons.insert(button, action).filter(|&previous| previous != action)
+    }
+
+    pub fn unbind_button(&mut self, button: MouseButton) -> Option<Action> {
+        self.buttons.remove(&button)
+    }
+
+    // One line per action with its keys, unbound ones included
+    pub fn describe(&self) -> String {
+        let mut text = String::new();
+        for &action in Action::ALL {
+            let buttons = self.buttons(action).into_iter().map(|button| format!("{:?} click", button));
+            let ctrl_keys = self.ctrl_keys(action).into_iter().map(|key| format!("Ctrl+{:?}", key));
+            let keys = self.keys(action).iter().map(|key| format!("{:?}", key)).chain(ctrl_keys).chain(buttons).collect::<Vec<_>>();
+            let keys = if keys.is_empty() { "unbound".to_string() } else { keys.join(" / ") };
+            let _ = writeln!(text, "{:<16} {}", keys, action.description());
+        }
+        text
+    }
+}
+
+fn bound_keys(bindings: &HashMap<VirtualKeyCode, Action>, action: Action) -> Vec<VirtualKeyCode> {
+    let mut keys = bindings.iter().filter(|(_, &a)| a == action).map(|(&key, _)| key).collect::<Vec<_>>();
+    keys.sort();
+    keys
+}
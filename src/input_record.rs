@@ -0,0 +1,525 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use winit::dpi::PhysicalPosition;
+use winit::event::{
+    DeviceId, ElementState, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta, TouchPhase, VirtualKeyCode,
+    WindowEvent,
+};
+
+use crate::input_map::{Action, InputMap};
+
+// Recordings are plain text, one entry per line:
+//   WGpuPlayground input recording <version>
+//   seed <u64>                    State seed at the start, version 2 and up
+//   action <Action name> [<x> <y>]
+//                                 performed since the last frame, version 3 and up. Actions at
+//                                 the cursor (Action::at_cursor) have its position in physical
+//                                 pixels, version 5 and up
+//   key <VirtualKeyCode name>     pressed since the last frame, before version 3
+//   frame <dt in seconds>         ends a frame, update() ran with this dt
+//   event <t> <window event>      version 4 and up, see below
+// Actions come before the frame they were performed in. Recording actions instead of keys keeps
+// recordings working after keys are rebound
+//
+// Window events are the keyboard and mouse events as the window sent them, t is seconds since
+// recording started:
+//   event <t> key <Pressed|Released> <scancode> <VirtualKeyCode name or ->
+//   event <t> modifiers <ModifiersState bits>
+//   event <t> cursor <x> <y>                     physical pixels
+//   event <t> cursor_entered / event <t> cursor_left
+//   event <t> button <Pressed|Released> <Left|Right|Middle|number>
+//   event <t> wheel <lines|pixels> <x> <y>
+//   event <t> focused <true|false>
+//   event <t> close
+// A recording plays back one of the two ways, see Timing. Window sizes aren't recorded, the window
+// manager owns those
+const HEADER: &str = "WGpuPlayground input recording";
+// Bump when the meaning of a recording changes (new entry kinds, different key handling, ...)
+const VERSION: u32 = 5;
+
+// How a recording plays back
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Timing {
+    // The recorded actions, one recorded frame per update() with the recorded dt. Replays the
+    // session exactly
+    FrameExact,
+    // The recorded window events through the same handling as the window's, each once its time has
+    // passed. The frames in between land wherever the machine puts them, so a replay is close to
+    // the session but not identical. Needs a version 4 recording
+    RealTime,
+}
+
+// Older recordings have keys, which did what the default bindings do now
+fn action_from_key_name(name: &str) -> Option<Action> {
+    let map = InputMap::default();
+    Action::ALL
+        .iter()
+        .flat_map(|&action| map.keys(action))
+        .find(|key| format!("{:?}", key) == name)
+        .and_then(|key| map.action(key))
+}
+
+// Keyboard and mouse events, the ones a playing recording replaces. Anything else still comes from
+// the window during playback
+pub fn is_input(event: &WindowEvent) -> bool {
+    matches!(
+        event,
+        WindowEvent::KeyboardInput { .. }
+            | WindowEvent::ModifiersChanged(_)
+            | WindowEvent::CursorMoved { .. }
+            | WindowEvent::CursorEntered { .. }
+            | WindowEvent::CursorLeft { .. }
+            | WindowEvent::MouseInput { .. }
+            | WindowEvent::MouseWheel { .. }
+    )
+}
+
+fn element_state_name(state: ElementState) -> &'static str {
+    match state {
+        ElementState::Pressed => "Pressed",
+        ElementState::Released => "Released",
+    }
+}
+
+fn element_state_from_name(name: &str) -> Option<ElementState> {
+    match name {
+        "Pressed" => Some(ElementState::Pressed),
+        "Released" => Some(ElementState::Released),
+        _ => None,
+    }
+}
+
+// winit has no way to list key codes, names are looked up among the keys something reacts to:
+// Escape and whatever `map` or the default bindings use
+fn key_from_name(name: &str, map: &InputMap) -> Option<VirtualKeyCode> {
+    let defaults = InputMap::default();
+    Action::ALL
+        .iter()
+        .flat_map(|&action| map.keys(action).into_iter().chain(defaults.keys(action)))
+        .chain([VirtualKeyCode::Escape])
+        .find(|key| format!("{:?}", key) == name)
+}
+
+// How much input the recorder keeps in memory for write_recent (bug reports)
+const RECENT_SECONDS: f32 = 10.0;
+
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+    // Frames of the last RECENT_SECONDS, oldest first. Without window events, the recent input only
+    // plays frame exact
+    recent: VecDeque<RecordedFrame>,
+    recent_time: f32,
+    actions: Vec<RecordedAction>,
+    start: instant::Instant,
+}
+
+impl InputRecorder {
+    pub fn create(path: &Path, seed: u64) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{} {}", HEADER, VERSION)?;
+        writeln!(writer, "seed {}", seed)?;
+        Ok(Self {
+            writer,
+            recent: VecDeque::new(),
+            recent_time: 0.0,
+            actions: Vec::new(),
+            start: instant::Instant::now(),
+        })
+    }
+
+    // Write errors are reported once on finish(), recording shouldn't take the app down
+    pub fn action(&mut self, action: RecordedAction) {
+        let _ = writeln!(self.writer, "{}", action);
+        self.actions.push(action);
+    }
+
+    // Events that aren't recorded are skipped. Write errors are reported once on finish()
+    pub fn event(&mut self, event: &WindowEvent) {
+        let time = self.start.elapsed().as_secs_f64();
+        let _ = self.write_event(time, event);
+    }
+
+    fn write_event(&mut self, time: f64, event: &WindowEvent) -> std::io::Result<()> {
+        let entry = match event {
+            WindowEvent::KeyboardInput { input, .. } => {
+                let key = input.virtual_keycode.map_or("-".to_string(), |key| format!("{:?}", key));
+                format!("key {} {} {}", element_state_name(input.state), input.scancode, key)
+            }
+            WindowEvent::ModifiersChanged(modifiers) => format!("modifiers {}", modifiers.bits()),
+            WindowEvent::CursorMoved { position, .. } => format!("cursor {:?} {:?}", position.x, position.y),
+            WindowEvent::CursorEntered { .. } => "cursor_entered".to_string(),
+            WindowEvent::CursorLeft { .. } => "cursor_left".to_string(),
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    MouseButton::Left => "Left".to_string(),
+                    MouseButton::Right => "Right".to_string(),
+                    MouseButton::Middle => "Middle".to_string(),
+                    MouseButton::Other(number) => number.to_string(),
+                };
+                format!("button {} {}", element_state_name(*state), button)
+            }
+            WindowEvent::MouseWheel { delta: MouseScrollDelta::LineDelta(x, y), .. } => format!("wheel lines {:?} {:?}", x, y),
+            WindowEvent::MouseWheel { delta: MouseScrollDelta::PixelDelta(delta), .. } => {
+                format!("wheel pixels {:?} {:?}", delta.x, delta.y)
+            }
+            WindowEvent::Focused(focused) => format!("focused {}", focused),
+            WindowEvent::CloseRequested => "close".to_string(),
+            _ => return Ok(()),
+        };
+        writeln!(self.writer, "event {:.6} {}", time, entry)
+    }
+
+    pub fn end_frame(&mut self, dt: f32) {
+        // Debug formatting of f32 round trips exactly
+        let _ = writeln!(self.writer, "frame {:?}", dt);
+
+        self.recent.push_back(RecordedFrame { actions: std::mem::take(&mut self.actions), dt });
+        self.recent_time += dt;
+        while self.recent_time > RECENT_SECONDS {
+            let Some(frame) = self.recent.pop_front() else { break };
+            self.recent_time -= frame.dt;
+        }
+    }
+
+    // Writes the last RECENT_SECONDS as a recording of its own. It starts mid session, so playing
+    // it back only reproduces the same thing if the app was in the same state
+    pub fn write_recent(&self, path: &Path) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        // No seed, procedural state mid session doesn't come from it anymore
+        writeln!(writer, "{} {}", HEADER, VERSION)?;
+        for frame in &self.recent {
+            for action in &frame.actions {
+                writeln!(writer, "{}", action)?;
+            }
+            writeln!(writer, "frame {:?}", frame.dt)?;
+        }
+        writer.flush()
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// A performed action, with where the cursor was for the ones at the cursor
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RecordedAction {
+    pub action: Action,
+    pub cursor: Option<(f32, f32)>,
+}
+
+impl RecordedAction {
+    fn parse(entry: &str) -> Option<Self> {
+        let words = entry.split(' ').collect::<Vec<_>>();
+        let (name, cursor) = match words[..] {
+            [name] => (name, None),
+            [name, x, y] => (name, Some((x.parse().ok()?, y.parse().ok()?))),
+            _ => return None,
+        };
+        Some(Self { action: Action::from_name(name)?, cursor })
+    }
+}
+
+// As an entry of the recording
+impl std::fmt::Display for RecordedAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "action {:?}", self.action)?;
+        match self.cursor {
+            // Debug formatting of f32 round trips exactly
+            Some((x, y)) => write!(f, " {:?} {:?}", x, y),
+            None => Ok(()),
+        }
+    }
+}
+
+// One update() worth of recorded input
+pub struct RecordedFrame {
+    pub actions: Vec<RecordedAction>,
+    pub dt: f32,
+}
+
+// Played back events never come from a real device. The id only tells devices apart and nothing
+// here passes it back to winit, which is what dummy() rules out
+fn played_device() -> DeviceId {
+    unsafe { DeviceId::dummy() }
+}
+
+// Parses an entry without its time. The deprecated modifiers fields still have to be filled in
+#[allow(deprecated)]
+fn parse_event(entry: &str, map: &InputMap) -> Option<WindowEvent<'static>> {
+    let words = entry.split(' ').collect::<Vec<_>>();
+    let event = match words[..] {
+        ["key", state, scancode, key] => WindowEvent::KeyboardInput {
+            device_id: played_device(),
+            input: KeyboardInput {
+                scancode: scancode.parse().ok()?,
+                state: element_state_from_name(state)?,
+                // Keys nothing reacts to come back as just their scancode
+                virtual_keycode: if key == "-" { None } else { key_from_name(key, map) },
+                modifiers: ModifiersState::empty(),
+            },
+            is_synthetic: false,
+        },
+        ["modifiers", bits] => WindowEvent::ModifiersChanged(ModifiersState::from_bits_truncate(bits.parse().ok()?)),
+        ["cursor", x, y] => WindowEvent::CursorMoved {
+            device_id: played_device(),
+            position: PhysicalPosition::new(x.parse().ok()?, y.parse().ok()?),
+            modifiers: ModifiersState::empty(),
+        },
+        ["cursor_entered"] => WindowEvent::CursorEntered { device_id: played_device() },
+        ["cursor_left"] => WindowEvent::CursorLeft { device_id: played_device() },
+        ["button", state, button] => WindowEvent::MouseInput {
+            device_id: played_device(),
+            state: element_state_from_name(state)?,
+            button: match button {
+                "Left" => MouseButton::Left,
+                "Right" => MouseButton::Right,
+                "Middle" => MouseButton::Middle,
+                number => MouseButton::Other(number.parse().ok()?),
+            },
+            modifiers: ModifiersState::empty(),
+        },
+        ["wheel", kind, x, y] => WindowEvent::MouseWheel {
+            device_id: played_device(),
+            delta: match kind {
+                "lines" => MouseScrollDelta::LineDelta(x.parse().ok()?, y.parse().ok()?),
+                "pixels" => MouseScrollDelta::PixelDelta(PhysicalPosition::new(x.parse().ok()?, y.parse().ok()?)),
+                _ => return None,
+            },
+            phase: TouchPhase::Moved,
+            modifiers: ModifiersState::empty(),
+        },
+        ["focused", focused] => WindowEvent::Focused(focused.parse().ok()?),
+        ["close"] => WindowEvent::CloseRequested,
+        _ => return None,
+    };
+    Some(event)
+}
+
+pub struct InputPlayback {
+    frames: VecDeque<RecordedFrame>,
+    // With their time, oldest first
+    events: VecDeque<(f64, WindowEvent<'static>)>,
+    pub timing: Timing,
+    // Set by the first due_events(), loading and setup don't eat into the first event's delay
+    start: Option<instant::Instant>,
+    // Seed to reproduce procedural state with, None for old or partial recordings
+    pub seed: Option<u64>,
+    // Where to save a screenshot of the last played frame (or once the last event played), for
+    // golden image comparison
+    pub screenshot: Option<PathBuf>,
+}
+
+impl InputPlayback {
+    // Key names in window events are resolved against `map`, see key_from_name
+    pub fn load(path: &Path, timing: Timing, map: &InputMap) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("Couldn't open {}: {}", path.display(), e))?;
+        let mut lines = BufReader::new(file).lines();
+
+        let header = lines.next()
+            .and_then(|line| line.ok())
+            .ok_or_else(|| format!("{} is empty", path.display()))?;
+        let version = header.strip_prefix(HEADER)
+            .and_then(|version| version.trim().parse::<u32>().ok())
+            .ok_or_else(|| format!("{} is not an input recording", path.display()))?;
+        // Older recordings still play, keys from before version 3 go through the default bindings
+        if !(1..=VERSION).contains(&version) {
+            return Err(format!(
+                "{} is a version {} recording, this build only plays up to version {}",
+                path.display(), version, VERSION
+            ));
+        }
+
+        if timing == Timing::RealTime && version < 4 {
+            return Err(format!(
+                "{} is a version {} recording, it has no window events to play in real time",
+                path.display(), version
+            ));
+        }
+
+        let mut frames = VecDeque::new();
+        let mut events = VecDeque::new();
+        let mut seed = None;
+        let mut actions = Vec::new();
+        for (number, line) in lines.enumerate() {
+            let line = line.map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+            // +2: 1 based and the header
+            let error = || format!("{}:{}: can't parse '{}'", path.display(), number + 2, line);
+            match line.split_once(' ') {
+                Some(("action", entry)) if version >= 3 => actions.push(RecordedAction::parse(entry).ok_or_else(error)?),
+                Some(("key", name)) if version < 3 => actions.push(RecordedAction {
+                    action: action_from_key_name(name).ok_or_else(error)?,
+                    cursor: None,
+                }),
+                Some(("seed", value)) => seed = Some(value.parse().map_err(|_| error())?),
+                Some(("frame", dt)) => frames.push_back(RecordedFrame {
+                    actions: std::mem::take(&mut actions),
+                    dt: dt.parse().map_err(|_| error())?,
+                }),
+                Some(("event", event)) if version >= 4 => {
+                    let (time, entry) = event.split_once(' ').ok_or_else(error)?;
+                    let time = time.parse::<f64>().map_err(|_| error())?;
+                    events.push_back((time, parse_event(entry, map).ok_or_else(error)?));
+                }
+                _ if line.trim().is_empty() => {}
+                _ => return Err(error()),
+            }
+        }
+
+        Ok(Self { frames, events, timing, start: None, seed, screenshot: None })
+    }
+
+    pub fn next_frame(&mut self) -> Option<RecordedFrame> {
+        self.frames.pop_front()
+    }
+
+    pub fn remaining_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    // Window events whose time has come, in order. Called once per frame, so events land on the
+    // first frame at or after their time
+    pub fn due_events(&mut self) -> Vec<WindowEvent<'static>> {
+        let start = *self.start.get_or_insert_with(instant::Instant::now);
+        self.due_at(start.elapsed().as_secs_f64())
+    }
+
+    fn due_at(&mut self, elapsed: f64) -> Vec<WindowEvent<'static>> {
+        let mut due = Vec::new();
+        while self.events.front().is_some_and(|(time, _)| *time <= elapsed) {
+            due.extend(self.events.pop_front().map(|(_, event)| event));
+        }
+        due
+    }
+
+    // Nothing left to play the recording's way
+    pub fn is_finished(&self) -> bool {
+        match self.timing {
+            Timing::FrameExact => self.frames.is_empty(),
+            Timing::RealTime => self.events.is_empty(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("wgpu-playground-{}-{}.txt", name, std::process::id()))
+    }
+
+    // A few events of every kind at set times, with actions and frames in between
+    #[test]
+    #[allow(deprecated)]
+    fn events_play_back_on_time() {
+        let map = InputMap::default();
+        let bound = map.keys(Action::CameraShake)[0];
+        let key = |state, virtual_keycode| WindowEvent::KeyboardInput {
+            device_id: played_device(),
+            input: KeyboardInput { scancode: 42, state, virtual_keycode, modifiers: ModifiersState::empty() },
+            is_synthetic: false,
+        };
+        let events = vec![
+            (0.0, WindowEvent::Focused(true)),
+            (0.0, WindowEvent::CursorEntered { device_id: played_device() }),
+            (0.25, WindowEvent::ModifiersChanged(ModifiersState::SHIFT)),
+            (0.5, key(ElementState::Pressed, Some(bound))),
+            (0.5, key(ElementState::Released, Some(bound))),
+            (0.75, key(ElementState::Pressed, None)),
+            (1.0, WindowEvent::CursorMoved {
+                device_id: played_device(),
+                position: PhysicalPosition::new(120.5, 64.25),
+                modifiers: ModifiersState::empty(),
+            }),
+            (1.25, WindowEvent::MouseInput {
+                device_id: played_device(),
+                state: ElementState::Pressed,
+                button: MouseButton::Other(7),
+                modifiers: ModifiersState::empty(),
+            }),
+            (1.5, WindowEvent::MouseWheel {
+                device_id: played_device(),
+                delta: MouseScrollDelta::LineDelta(0.0, -3.0),
+                phase: TouchPhase::Moved,
+                modifiers: ModifiersState::empty(),
+            }),
+            (1.5, WindowEvent::MouseWheel {
+                device_id: played_device(),
+                delta: MouseScrollDelta::PixelDelta(PhysicalPosition::new(1.5, 2.0)),
+                phase: TouchPhase::Moved,
+                modifiers: ModifiersState::empty(),
+            }),
+            (1.75, WindowEvent::CursorLeft { device_id: played_device() }),
+            (2.0, key(ElementState::Pressed, Some(VirtualKeyCode::Escape))),
+            (2.0, WindowEvent::CloseRequested),
+        ];
+
+        let path = temp_path("events");
+        let mut recorder = InputRecorder::create(&path, 1234).unwrap();
+        for (time, event) in &events {
+            recorder.write_event(*time, event).unwrap();
+            // Not recorded, nothing in the recording may come of it
+            recorder.write_event(*time, &WindowEvent::Resized(winit::dpi::PhysicalSize::new(1, 1))).unwrap();
+            recorder.action(RecordedAction { action: Action::CameraShake, cursor: None });
+            recorder.end_frame(0.25);
+        }
+        recorder.finish().unwrap();
+
+        let mut playback = InputPlayback::load(&path, Timing::RealTime, &map).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(playback.seed, Some(1234));
+        assert_eq!(playback.remaining_frames(), events.len());
+
+        // Before, on and between event times
+        let mut played = 0;
+        for elapsed in [-1.0, 0.0, 0.1, 0.5, 0.6, 1.0, 1.4, 1.5, 1.9, 2.0, 3.0] {
+            let due = playback.due_at(elapsed);
+            let expected = events.iter().filter(|(time, _)| *time <= elapsed).skip(played).map(|(_, event)| event).collect::<Vec<_>>();
+            assert_eq!(due.iter().collect::<Vec<_>>(), expected, "at {}s", elapsed);
+            played += due.len();
+        }
+        assert!(playback.is_finished(), "events left after the last one's time");
+    }
+
+    #[test]
+    fn frames_play_back_with_their_actions() {
+        let path = temp_path("frames");
+        let mut recorder = InputRecorder::create(&path, 7).unwrap();
+        recorder.end_frame(0.5);
+        let actions = [
+            RecordedAction { action: Action::CameraShake, cursor: None },
+            RecordedAction { action: Action::PlaceDecal, cursor: Some((120.5, 64.25)) },
+        ];
+        for action in actions {
+            recorder.action(action);
+        }
+        recorder.end_frame(0.25);
+        recorder.finish().unwrap();
+
+        let mut playback = InputPlayback::load(&path, Timing::FrameExact, &InputMap::default()).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let first = playback.next_frame().unwrap();
+        assert_eq!((first.actions, first.dt), (vec![], 0.5));
+        let second = playback.next_frame().unwrap();
+        assert_eq!((second.actions, second.dt), (actions.to_vec(), 0.25));
+        assert!(playback.is_finished());
+    }
+
+    // Older recordings still play frame exact, they just have no events
+    #[test]
+    fn real_time_needs_window_events() {
+        let path = temp_path("version-3");
+        std::fs::write(&path, format!("{} 3\nseed 1\naction CameraShake\nframe 0.5\n", HEADER)).unwrap();
+        let map = InputMap::default();
+        let frame_exact = InputPlayback::load(&path, Timing::FrameExact, &map).map(|playback| playback.remaining_frames());
+        let real_time = InputPlayback::load(&path, Timing::RealTime, &map);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(frame_exact, Ok(1));
+        assert!(real_time.is_err());
+    }
+}
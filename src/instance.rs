@@ -0,0 +1,73 @@
+use crate::math::{Mat4, Vec3};
+
+// Between neighbours in grid()
+pub const SPACING: f32 = 1.5;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Instance {
+    pub position: Vec3,
+    pub rotation: Mat4,
+    pub layer: u32, // Which image of the layered texture to use
+}
+
+impl Instance {
+    pub fn model_matrix(&self) -> Mat4 {
+        Mat4::translation(self.position) * self.rotation
+    }
+
+    pub fn to_raw(&self) -> InstanceRaw {
+        InstanceRaw {
+            model: self.model_matrix().to_cols_array(),
+            layer: self.layer,
+            lod_tint: NO_LOD_TINT,
+        }
+    }
+}
+
+// What actually goes to the GPU. Shaders can't take a mat4 vertex attribute,
+// so the matrix is passed as 4 vec4 columns
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct InstanceRaw {
+    model: [[f32; 4]; 4],
+    layer: u32,
+    // LOD level to tint the instance with for debugging (see lod.rs), NO_LOD_TINT for none
+    pub lod_tint: u32,
+}
+
+pub const NO_LOD_TINT: u32 = u32::MAX;
+
+impl InstanceRaw {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        // Locations start at 5 to leave room for more per vertex attributes
+        const ATTRIBUTES: [wgpu::VertexAttribute; 6] = wgpu::vertex_attr_array![
+            5 => Float32x4, 6 => Float32x4, 7 => Float32x4, 8 => Float32x4, 9 => Uint32, 10 => Uint32
+        ];
+
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            // Shader moves to the next instance only when it starts drawing the next one
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Square-ish grid on the XZ plane centered at origin, filled row by row.
+// Texture layers are handed out round robin
+pub fn grid(count: usize, layers: u32) -> Vec<Instance> {
+    let per_row = (count as f32).sqrt().ceil().max(1.0) as usize;
+    let half = (per_row - 1) as f32 * SPACING / 2.0;
+
+    (0..count)
+        .map(|i| {
+            let x = (i % per_row) as f32 * SPACING - half;
+            let z = (i / per_row) as f32 * SPACING - half;
+            Instance {
+                position: Vec3::new(x, 0.0, z),
+                rotation: Mat4::IDENTITY,
+                layer: i as u32 % layers.max(1),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,356 @@
+use std::fmt;
+
+// Just enough JSON for the files this app reads and writes (see scene.rs) and the remote control
+// protocol (remote.rs). Objects keep their keys in file order, duplicates included, numbers are f64
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, ParseError> {
+        let mut parser = Parser { text, position: 0 };
+        parser.skip_whitespace();
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.position < text.len() {
+            return Err(parser.error("Trailing characters after the value"));
+        }
+        Ok(value)
+    }
+
+    // For error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "a boolean",
+            Json::Number(_) => "a number",
+            Json::String(_) => "a string",
+            Json::Array(_) => "an array",
+            Json::Object(_) => "an object",
+        }
+    }
+}
+
+// Compact, on one line, so a value can go out as a line of remote.rs' protocol. `{:#}` indents it
+// for files people edit: a member per line, arrays of numbers and other plain values kept on one.
+// Numbers that aren't finite have no JSON form and come out as null
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_value(f, self, f.alternate().then_some(0))
+    }
+}
+
+fn write_value(f: &mut fmt::Formatter, json: &Json, indent: Option<usize>) -> fmt::Result {
+    match json {
+        Json::Null => f.write_str("null"),
+        Json::Bool(value) => write!(f, "{}", value),
+        Json::Number(number) if number.is_finite() => write!(f, "{}", number),
+        Json::Number(_) => f.write_str("null"),
+        Json::String(string) => write_string(f, string),
+        Json::Array(items) => {
+            let plain = items.iter().all(|item| !matches!(item, Json::Array(_) | Json::Object(_)));
+            if plain && indent.is_some() {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    f.write_str(if i > 0 { ", " } else { "" })?;
+                    write_value(f, item, None)?;
+                }
+                return f.write_str("]");
+            }
+            write_items(f, ("[", "]"), items, indent, write_value)
+        }
+        Json::Object(members) => write_items(f, ("{", "}"), members, indent, |f, (key, value), indent| {
+            write_string(f, key)?;
+            f.write_str(if indent.is_some() { ": " } else { ":" })?;
+            write_value(f, value, indent)
+        }),
+    }
+}
+
+fn write_items<T>(
+    f: &mut fmt::Formatter,
+    (open, close): (&str, &str),
+    items: &[T],
+    indent: Option<usize>,
+    mut write_item: impl FnMut(&mut fmt::Formatter, &T, Option<usize>) -> fmt::Result,
+) -> fmt::Result {
+    f.write_str(open)?;
+    let inner = indent.map(|indent| indent + 4);
+    for (i, item) in items.iter().enumerate() {
+        match inner {
+            Some(inner) => write!(f, "{}\n{:inner$}", if i > 0 { "," } else { "" }, "")?,
+            None if i > 0 => f.write_str(",")?,
+            None => {}
+        }
+        write_item(f, item, inner)?;
+    }
+    match indent {
+        Some(indent) if !items.is_empty() => write!(f, "\n{:indent$}{}", "", close),
+        _ => f.write_str(close),
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, string: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            // The rest of the control characters, raw they'd break the line
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    // 1 based
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message, self.line, self.column)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Deeper than this is a broken (or hostile) file, not a scene
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    text: &'a str,
+    // Byte offset
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        let before = &self.text[..self.position];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().map_or(0, |line| line.chars().count()) + 1;
+        ParseError { message: message.into(), line, column }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.position += c.len_utf8();
+                Ok(())
+            }
+            Some(c) => Err(self.error(format!("Expected '{}', found '{}'", expected, c))),
+            None => Err(self.error(format!("Expected '{}', found the end of the file", expected))),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, ParseError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("Nested too deep"));
+        }
+        match self.peek() {
+            Some('{') => self.object(depth),
+            Some('[') => self.array(depth),
+            Some('"') => self.string().map(Json::String),
+            Some('-' | '0'..='9') => self.number(),
+            Some(_) => {
+                for (word, value) in [("true", Json::Bool(true)), ("false", Json::Bool(false)), ("null", Json::Null)] {
+                    if self.text[self.position..].starts_with(word) {
+                        self.position += word.len();
+                        return Ok(value);
+                    }
+                }
+                Err(self.error(format!("Unexpected '{}'", self.peek().unwrap())))
+            }
+            None => Err(self.error("Expected a value, found the end of the file")),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Json, ParseError> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("Expected a key in quotes"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            members.push((key, self.value(depth + 1)?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some('}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(members));
+                }
+                _ => return Err(self.error("Expected ',' or '}' after a member")),
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Json, ParseError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            self.skip_whitespace();
+            items.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some(']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return Err(self.error("Expected ',' or ']' after an item")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.error("Unterminated string"));
+            };
+            self.position += c.len_utf8();
+            match c {
+                '"' => return Ok(string),
+                '\\' => {
+                    let Some(escape) = self.peek() else {
+                        return Err(self.error("Unterminated string"));
+                    };
+                    self.position += escape.len_utf8();
+                    string.push(match escape {
+                        '"' => '"',
+                        '\\' => '\\',
+                        '/' => '/',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => self.unicode_escape()?,
+                        _ => return Err(self.error(format!("Unknown escape '\\{}'", escape))),
+                    });
+                }
+                c if (c as u32) < 0x20 => return Err(self.error("Control character in string")),
+                c => string.push(c),
+            }
+        }
+    }
+
+    // After "\u", surrogate pairs are two of them in a row
+    fn unicode_escape(&mut self) -> Result<char, ParseError> {
+        let first = self.hex4()?;
+        let code = if (0xd800..0xdc00).contains(&first) {
+            if !self.text[self.position..].starts_with("\\u") {
+                return Err(self.error("Unpaired surrogate in \\u escape"));
+            }
+            self.position += 2;
+            let second = self.hex4()?;
+            if !(0xdc00..0xe000).contains(&second) {
+                return Err(self.error("Unpaired surrogate in \\u escape"));
+            }
+            0x10000 + ((first - 0xd800) << 10) + (second - 0xdc00)
+        } else {
+            first
+        };
+        char::from_u32(code).ok_or_else(|| self.error("Invalid \\u escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, ParseError> {
+        let digits = self.text.get(self.position..self.position + 4).ok_or_else(|| self.error("Expected 4 hex digits"))?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.error("Expected 4 hex digits"))?;
+        self.position += 4;
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Json, ParseError> {
+        let start = self.position;
+        let rest = &self.text[start..];
+        let len = rest.find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E')).unwrap_or(rest.len());
+        let number = &rest[..len];
+        // Rust accepts a few things JSON doesn't, like "1." and a leading "+"
+        let valid = !number.starts_with('+') && !number.contains(".e") && !number.contains(".E") && !number.ends_with('.');
+        match number.parse::<f64>() {
+            Ok(value) if valid && value.is_finite() => {
+                self.position += len;
+                Ok(Json::Number(value))
+            }
+            _ => Err(self.error(format!("Invalid number '{}'", number))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_json_parses_back() {
+        let json = Json::Object(vec![
+            ("id".to_string(), Json::Number(7.0)),
+            ("text".to_string(), Json::String("quote \" slash \\ line\n tab\t bell\u{7} é".to_string())),
+            ("items".to_string(), Json::Array(vec![Json::Null, Json::Bool(true), Json::Number(-0.25), Json::Array(vec![])])),
+            ("empty".to_string(), Json::Object(vec![])),
+        ]);
+        let text = json.to_string();
+        assert!(!text.contains('\n'), "{} isn't on one line", text);
+        assert_eq!(Json::parse(&text), Ok(json.clone()));
+        assert_eq!(Json::parse(&format!("{:#}", json)), Ok(json));
+        assert_eq!(Json::Number(f64::NAN).to_string(), "null");
+    }
+
+    #[test]
+    fn pretty_json_is_indented() {
+        let json = Json::parse(r#"{"a": [1, 2], "b": {"c": [{"d": null}], "e": []}}"#).unwrap();
+        let expected = r#"{
+    "a": [1, 2],
+    "b": {
+        "c": [
+            {
+                "d": null
+            }
+        ],
+        "e": []
+    }
+}"#;
+        assert_eq!(format!("{:#}", json), expected);
+    }
+}
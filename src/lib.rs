@@ -1,5 +1,78 @@
-use std::sync::Arc;
-use wgpu::{Backends, Dx12Compiler, PowerPreference, VertexAttribute};
+#![allow(non_snake_case)] // Crate name
+
+pub mod blit;
+// Writes files, and the log sink wraps env_logger
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bug_report;
+pub mod buffer;
+pub mod bvh;
+pub mod camera;
+pub mod clear_rect;
+pub mod conservative;
+pub mod debug_lines;
+pub mod decal;
+pub mod debug_view;
+pub mod errors;
+pub mod exposure;
+pub mod frame_stream;
+pub mod fog;
+pub mod fxaa;
+pub mod gizmo;
+pub mod grid;
+pub mod hot_reload;
+pub mod ibl;
+pub mod image_diff;
+pub mod impostor;
+pub mod input_map;
+pub mod input_record;
+pub mod instance;
+pub mod json;
+pub mod loader;
+pub mod lod;
+pub mod luminance;
+pub mod math;
+pub mod mesh;
+pub mod motion_blur;
+pub mod occlusion;
+pub mod optimize;
+pub mod outline;
+pub mod pbr;
+pub mod per_draw;
+pub mod pipeline;
+pub mod points;
+pub mod poll_thread;
+pub mod preview;
+pub mod primitives;
+pub mod procedural_sky;
+pub mod readback;
+pub mod reflection;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod remote;
+pub mod render_graph;
+pub mod scene;
+pub mod shader_test;
+pub mod shader_validation;
+pub mod simplify;
+pub mod skinning;
+pub mod sky;
+pub mod streaming;
+pub mod taa;
+pub mod text;
+pub mod texture;
+pub mod time_of_day;
+pub mod trail;
+pub mod transparency;
+pub mod undo;
+pub mod velocity;
+pub mod vertex_format;
+pub mod vertex_streams;
+// Needs threads and a file system
+#[cfg(not(target_arch = "wasm32"))]
+pub mod video;
+pub mod viewport;
+pub mod wireframe;
+
+use wgpu::PowerPreference;
 use wgpu::util::DeviceExt;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
@@ -14,25 +87,367 @@ use winit::
     },
     window::WindowBuilder,
 };
-use winit::window::CursorIcon::Default;
-use winit::window::Window;
+use winit::window::{CursorIcon, Window};
+
+use blit::Blitter;
+use buffer::{DeferredDestruction, FrameArena, GrowableBuffer};
+use bvh::Bvh;
+use camera::{Camera, CameraPose, CameraRig, CameraUniform, FogFalloff, FogParams, Projection};
+use clear_rect::ClearRects;
+use conservative::ConservativeDemo;
+use debug_lines::LineBatch;
+use decal::{Decal, DecalId, DecalRenderer};
+use debug_view::{BuiltinDebugView, DebugChannels, DebugViews, ScreenRect};
+use errors::{ErrorEntry, ErrorLog, Severity};
+use exposure::{AutoExposure, ExposureSettings, HistogramOverlay};
+use frame_stream::FrameStream;
+use fxaa::FxaaRenderer;
+use grid::{GridParams, GridRenderer};
+use ibl::IblMaps;
+use impostor::{ImpostorRenderer, ImpostorSource};
+use input_map::{Action, InputMap};
+use input_record::{InputPlayback, InputRecorder, RecordedAction, Timing};
+use instance::{Instance, InstanceRaw};
+use hot_reload::{AssetWatcher, FileWatcher, ReloadCallback, WatchedAsset};
+use loader::{AssetKind, AssetLoader, LoadHandle, LoadState, LoadedAsset, LoadedData};
+use lod::{LodSelector, LodSettings};
+use luminance::LuminanceReduction;
+use math::{Aabb, Frustum, Mat4, Rng, Vec3};
+use scene::{Scene, SceneCamera, SceneInstance, SceneMesh};
+use simplify::LodChainOptions;
+use streaming::{ChunkStreamer, StreamingSettings, StreamingStats};
+use mesh::MeshOptions;
+use motion_blur::{MotionBlur, MotionBlurSettings};
+use occlusion::OcclusionQueries;
+use outline::OutlineRenderer;
+use pbr::{PbrFactors, PbrMaterial, PbrRenderer, PbrTextures};
+use per_draw::PerDrawData;
+use pipeline::{AntiAliasing, PipelineConfig};
+use points::{PointRenderer, PointVertex};
+use poll_thread::PollThread;
+use primitives::Surface;
+use procedural_sky::{ProceduralSky, ProceduralSkyParams};
+use render_graph::{RenderGraph, TransientTexture};
+use skinning::BoneBuffer;
+use sky::SkyRenderer;
+use taa::TaaRenderer;
+use text::TextOverlay;
+use texture::{ColorSpace, LayeredTexture, Texture};
+use time_of_day::{DayLighting, TimeOfDay};
+use trail::{Comet, Trail, TrailRenderer, TrailSettings};
+use velocity::VelocityPass;
+use vertex_format::{MeshData, ShaderVertexFormat};
+use vertex_streams::{StreamedMesh, VertexStreams};
+use transparency::{TransparencyMode, TransparentRenderer};
+use undo::{Edit, UndoStack};
+use viewport::Viewport;
+use wireframe::{WireframeMethod, WireframeRenderer, WireframeScope};
+
+// Outline of the instance picked with a click, see State::select
+const SELECTION_COLOR: [f32; 4] = [1.0, 0.6, 0.1, 1.0];
+const SELECTION_THICKNESS: f32 = 0.03;
+
+// Edges of the W toggle
+const DEFAULT_WIREFRAME_COLOR: [f32; 4] = [0.05, 0.05, 0.05, 0.8];
+
+// Frames the CPU can be ahead of the GPU, buffers used by one are kept at least this long
+const FRAMES_IN_FLIGHT: u64 = 3;
+
+// Group 0 of every shader that draws in world space. A constant so shader_validation can check
+// the shaders against it
+const CAMERA_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        // Fragment shaders read the exposure
+        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+];
+
+// Default background of the opaque pass, see set_clear_color
+const CLEAR_COLOR: wgpu::Color = wgpu::Color {
+    r: 0.5,
+    g: 0.4,
+    b: 0.9,
+    a: 1.0,
+};
+
+// Copies of the mesh textures sampled in the wrong color space, see State::set_color_space_split
+struct WrongColorSpace {
+    layered_texture: LayeredTexture,
+    layered_texture_bind_group: wgpu::BindGroup,
+    mesh_material: Option<PbrMaterial>,
+}
+
+impl WrongColorSpace {
+    fn gpu_memory(&self) -> u64 {
+        self.layered_texture.gpu_memory() + self.mesh_material.as_ref().map_or(0, PbrMaterial::gpu_memory)
+    }
+}
 
-struct State {
+// The window's renderer and everything it shows. run_with_callbacks hands it to code using the
+// crate as a library, its pub fns are that API
+pub struct State {
     surface: wgpu::Surface,
-    device: wgpu::Device,
+    adapter: wgpu::Adapter,
+    // No hardware adapter was found and this is the software one, see is_fallback_adapter
+    fallback_adapter: bool,
+    // Shared with the poll thread, see spawn_poll_thread
+    device: std::sync::Arc<wgpu::Device>,
     queue: wgpu::Queue,
     // Buffer of GPU instructions
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
     window: winit::window::Window,
     // Pipeline
+    shader: wgpu::ShaderModule,
+    // HAS_UV / HAS_SKIN of the shader.wgsl variant the mesh needs, empty for the file as is. See
+    // set_mesh_data
+    shader_switches: Vec<(&'static str, bool)>,
+    render_pipeline_layout: wgpu::PipelineLayout,
     render_pipeline: wgpu::RenderPipeline,
+    flat_render_pipeline: wgpu::RenderPipeline,
+    flat_shading: bool,
+    // One draw call per instance instead of instanced, I toggles. For comparing the per draw paths
+    per_draw_mode: bool,
+    per_draw: PerDrawData,
+    pipeline_config: PipelineConfig,
+    // Multisampled color target, None without MSAA
+    msaa_view: Option<wgpu::TextureView>,
+    // A cycles. FXAA and TAA drop to 1 sample, msaa_sample_count is what MSAA goes back to
+    anti_aliasing: AntiAliasing,
+    msaa_sample_count: u32,
+    fxaa: FxaaRenderer,
+    taa: TaaRenderer,
+    // Screen space motion, drawn while TAA or motion blur need it
+    velocity: VelocityPass,
+    // Blurs along the velocity while on, U toggles. Needs single sampled depth and one viewport,
+    // skipped otherwise
+    motion_blur: MotionBlur,
+    motion_blur_enabled: bool,
+    // Scene is drawn over the previous frame instead of a cleared target, P toggles
+    preserve_frame: bool,
+    // Scene color kept across frames for preserve_frame (surface textures don't keep their
+    // contents), blitted to the surface every frame. Also what luminance is measured on and what
+    // FXAA / TAA read, surfaces can't be bound as textures everywhere. None when nothing needs it
+    history_view: Option<wgpu::TextureView>,
+    // Scene luminance measured every frame or two while measure_luminance is on, L toggles.
+    // None without compute shaders
+    luminance: Option<LuminanceReduction>,
+    measure_luminance: bool,
+    // Luminance results arrive most frames, the auto exposure in the title only follows every so often
+    luminance_title_time: instant::Instant,
+    // Exposure follows the luminance histogram while on, E toggles. manual_exposure wins over it
+    auto_exposure: AutoExposure,
+    auto_exposure_enabled: bool,
+    manual_exposure: Option<f32>,
+    // Histogram bars in the bottom left corner, H toggles
+    histogram_overlay: HistogramOverlay,
+    show_histogram: bool,
+    // Distance fog, kept while off so toggling it back (G) restores the same look
+    fog: FogParams,
+    fog_enabled: bool,
+    // Toasts and banners on top of the final image, R shows the history too
+    errors: ErrorLog,
+    text_overlay: TextOverlay,
+    show_error_history: bool,
+    // Textures shown in corners this frame, see debug_view_texture. S cycles through built in ones
+    debug_views: DebugViews,
+    builtin_debug_view: BuiltinDebugView,
+    // Placed by Action::PlaceDecal or add_decal, drawn over the opaque scene
+    decals: DecalRenderer,
+    // Last cursor position in the window, where Action::PlaceDecal places decals and Select picks
+    cursor_position: Option<(f32, f32)>,
+    // Ctrl picks InputMap::ctrl_action
+    modifiers: ModifiersState,
+    // Per viewport backgrounds
+    clear_rects: ClearRects,
     // Buffer
     vertex_buffer: wgpu::Buffer,
+    // Every LOD as in vertex_buffer, kept to split it again when the vertex streams change
+    mesh_vertices: Vec<Vertex>,
+    // The mesh from separate buffers for the scene pipelines, None reads vertex_buffer. See
+    // set_vertex_streams
+    streamed_mesh: Option<StreamedMesh>,
+    // Textures bigger than this get downscaled on load, None = device limit
+    max_texture_size: Option<u32>,
+    // Depth
+    depth_texture: Texture,
+    // Per instance textures, Instance::layer picks one
+    layered_texture: LayeredTexture,
+    layered_texture_bind_group_layout: wgpu::BindGroupLayout,
+    layered_texture_bind_group: wgpu::BindGroup,
+    // GPU skinning, identity unless set_bone_matrices is called
+    bones: BoneBuffer,
+    // Instancing
+    instances: Vec<Instance>,
+    instance_buffer: GrowableBuffer,
+    // Camera
+    camera: Camera,
+    camera_rig: CameraRig,
+    camera_uniform: CameraUniform,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    // Camera after the rig, what the main view actually shows
+    view_camera: Camera,
+    // Uniforms for viewports after the first one (first one uses camera_buffer)
+    viewport_cameras: Vec<(wgpu::Buffer, wgpu::BindGroup)>,
+    split_screen: bool,
+    last_update: instant::Instant,
+    blitter: Blitter,
+    // Debug
+    line_batch: LineBatch,
+    // Point clouds, see draw_points
+    points: PointRenderer,
+    // Ribbons behind instances, see add_instance_trail. The comet (1) circles the scene with its own
+    trails: TrailRenderer,
+    instance_trails: Vec<(usize, Trail)>,
+    comet: Option<Comet>,
+    // Files read and decoded in the background, see poll_loaded
+    loader: AssetLoader,
+    // Files of loaded assets, reloaded when they change. See reload_assets
+    asset_watcher: AssetWatcher,
+    asset_reloaded: Option<ReloadCallback>,
+    // Background of the opaque pass
+    clear_color: wgpu::Color,
+    // Last scene file that was applied and the file being watched for changes, see load_scene
+    scene: Option<Scene>,
+    scene_watcher: Option<FileWatcher>,
+    // What save_scene writes for the assets, which State doesn't otherwise remember the source of.
+    // None / empty when the mesh or environment came from anything else
+    mesh_source: Option<SceneMesh>,
+    environment_path: Option<std::path::PathBuf>,
+    point_cloud_paths: Vec<std::path::PathBuf>,
+    // Terrain around the camera, see set_streaming
+    streaming: Option<ChunkStreamer>,
+    // Buffers freed once the frames using them are done, see DeferredDestruction
+    deferred_destruction: DeferredDestruction,
+    // Transient vertex data of the immediate mode features (debug lines, text, ...), reset every frame
+    frame_arena: FrameArena,
+    // Where reset_camera goes, None frames the instances instead
+    home_camera: Option<CameraPose>,
+    // The camera State::new starts with, its direction is the one framing looks from
+    initial_camera: CameraPose,
+    // Units per second the camera moves by itself, see set_camera_velocity
+    camera_velocity: Vec3,
+    // Environment map background, nothing until load_environment
+    sky: SkyRenderer,
+    // Renders the environment from parameters instead of a file, made on first use
+    procedural_sky: Option<ProceduralSky>,
+    // What the current environment was rendered from, None when it was loaded or there's none
+    procedural_sky_params: Option<ProceduralSkyParams>,
+    // Drives the light, sky and exposure while Some, see set_time_of_day. day_lighting is this
+    // frame's, from update
+    time_of_day: Option<TimeOfDay>,
+    day_lighting: Option<DayLighting>,
+    // Ground grid to the horizon, off until set_infinite_grid
+    grid: GridRenderer,
+    // Lighting maps of the current environment
+    ibl_bind_group_layout: wgpu::BindGroupLayout,
+    ibl: Option<IblMaps>,
+    // Neutral grey environment for PBR shading until one is loaded, made on first use
+    default_ibl: Option<IblMaps>,
+    // Mesh is drawn with this instead of the layered texture when set, see set_mesh_material
+    mesh_material: Option<PbrMaterial>,
+    // Right half of every viewport draws with these, see set_color_space_split
+    wrong_color_space: Option<WrongColorSpace>,
+    pbr: PbrRenderer,
+    // Of LOD 0, which starts the vertex buffer
+    vertex_count: u32,
+    // Level of detail per instance, the instance buffer is grouped by it. See set_mesh_lods
+    lod: LodSelector,
+    // Tints instances by their LOD and shows what it saves, Y toggles
+    show_lod_colors: bool,
+    // Instances outside the camera's view aren't drawn. Off in split screen, the views don't share it
+    frustum_culling: bool,
+    // Culls with this instead of the camera while set, to fly around and look at what gets culled.
+    // X toggles, see set_culling_frozen
+    cull_camera: Option<Camera>,
+    // World space bounds of every instance and a BVH over them, for culling and pick()
+    instance_aabbs: Vec<Aabb>,
+    bvh: Bvh,
+    // Per instance, what the last cull kept. Reused between frames
+    visible: Vec<bool>,
+    visible_items: Vec<u32>,
+    // Far instances, baked when LodSettings::impostor_distance is set
+    impostor: ImpostorRenderer,
+    // Object space positions of the mesh (LOD 0), for the bounds and normal lines
+    mesh_positions: Vec<Vec3>,
+    mesh_aabb: Aabb,
+    show_bounds: bool,
+    // Face normal lines of the mesh in object space, empty when hidden. See set_show_normals
+    normal_segments: Vec<(Vec3, Vec3)>,
+    // The mesh's frames when it came from set_surface_mesh, None derives flat ones from the triangles
+    mesh_surface: Option<Surface>,
+    // For meshes State sets itself (scenes, loaded levels of detail), see set_mesh_options
+    mesh_options: MeshOptions,
+    // Tangent frame lines in object space, empty when hidden. See set_show_tangent_frames
+    frame_segments: Vec<(Vec3, Vec3, [f32; 3])>,
+    // Value the stencil buffer is cleared to every frame
+    stencil_clear: u32,
+    // Where the bug report key writes reports, see set_bug_report_dir
+    #[cfg(not(target_arch = "wasm32"))]
+    bug_report_dir: std::path::PathBuf,
+    // Depth cleared to every frame and in previews, 1 = far. See set_clear_depth
+    clear_depth: f32,
+    // Selection outline around one instance, see draw_outlined
+    outline: OutlineRenderer,
+    // Instance the editing keys work on, outlined. See select
+    selected: Option<usize>,
+    // Edits to instances and the mesh material, see transform_instance and undo
+    undo_stack: UndoStack,
+    // Translation gizmo on the selection, drawn over the scene. See gizmo.rs
+    gizmo_lines: LineBatch,
+    gizmo_hovered: Option<gizmo::Handle>,
+    gizmo_drag: Option<gizmo::Drag>,
+    // Triangle edges over the shaded mesh while on, W toggles
+    wireframe: WireframeRenderer,
+    wireframe_scope: WireframeScope,
+    // Replaces the scene while visible, C toggles
+    conservative_demo: ConservativeDemo,
+    // Drawn after the opaque scene, O switches between sorted and OIT
+    transparency: TransparentRenderer,
+    // Shown in the title when set, only measured by run_uncapped
+    fps: Option<f32>,
+    // Everything procedural derives from this, see reseed
+    seed: u64,
+    // Which key does what, see rebind
+    input_map: InputMap,
+    // See start_input_recording / play_input_recording
+    input_recorder: Option<InputRecorder>,
+    input_playback: Option<InputPlayback>,
+    // Saved after the next frame is rendered
+    screenshot_path: Option<std::path::PathBuf>,
+    // Pixel to read from the next frame, and the result once it's read. See read_pixel
+    pixel_request: Option<(u32, u32)>,
+    read_pixel: Option<Result<[u8; 4], String>>,
+    // Render graph description written here with the next frame, for bug reports
+    graph_dump_path: Option<std::path::PathBuf>,
+    // Copies of every presented frame for recording / previews, see stream_frames
+    frame_stream: Option<FrameStream>,
+    // Visible fragments of the mesh per viewport, None where unsupported
+    occlusion_queries: Option<OcclusionQueries>,
+    // See spawn_poll_thread
+    poll_thread: Option<PollThread>,
+    // See start_remote. Screenshot requests wait for the next frame
+    #[cfg(not(target_arch = "wasm32"))]
+    remote: Option<remote::RemoteServer>,
+    #[cfg(not(target_arch = "wasm32"))]
+    remote_screenshots: Vec<remote::Reply>,
+    // Seconds the last update moved things by, playback's when it plays frames
+    frame_dt: f32,
 }
 
 impl State {
-    async fn new(window: Window) -> Self {
+    // `view_formats`: formats the surface (and history) textures can also be viewed as, see
+    // texture::is_view_format_compatible. Incompatible or unsupported ones are dropped with a warning
+    async fn new(window: Window, view_formats: &[wgpu::TextureFormat]) -> Self {
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
@@ -40,20 +455,45 @@ impl State {
         // Actual area to draw something on that
         let surface = unsafe { instance.create_surface(&window) }.unwrap();
 
-        // Adapter between app and actual GPU driver
-        let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
+        // Adapter between app and actual GPU driver. Without a GPU (VMs, CI) there may still be a
+        // software one, which wgpu only hands out when asked for it
+        let adapter_options = |force_fallback_adapter| wgpu::RequestAdapterOptions {
             compatible_surface: Some(&surface),
-            force_fallback_adapter: false,
+            force_fallback_adapter,
             power_preference: PowerPreference::HighPerformance,
-        }).await.unwrap();
+        };
+        let (adapter, fallback_adapter) = match instance.request_adapter(&adapter_options(false)).await {
+            Some(adapter) => (adapter, false),
+            None => (instance.request_adapter(&adapter_options(true)).await.expect("No adapter, not even a software one"), true),
+        };
+        if fallback_adapter {
+            log::warn!(
+                "No GPU adapter, running on the software fallback {:?}. Expect very low frame rates",
+                adapter.get_info().name
+            );
+        }
 
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
-                features: wgpu::Features::empty(),
+                // Optional, only turned on where the adapter has them
+                features: adapter.features()
+                    & (OcclusionQueries::FEATURES
+                        | ConservativeDemo::FEATURES
+                        | pipeline::DEPTH_CLAMP_FEATURES
+                        | WireframeRenderer::FEATURES)
+                    | PerDrawData::supported_features(&adapter),
                 limits: if cfg!(target_arch = "wasm32") {
                     wgpu::Limits::downlevel_webgl2_defaults()
                 } else {
-                    wgpu::Limits::default()
+                    wgpu::Limits {
+                        // 0 unless push constants are requested
+                        max_push_constant_size: if PerDrawData::supported_features(&adapter).is_empty() {
+                            0
+                        } else {
+                            PerDrawData::PUSH_CONSTANT_SIZE
+                        },
+                        ..wgpu::Limits::default()
+                    }
                 },
                 label: None,
             },
@@ -66,6 +506,18 @@ impl State {
             .find(|f| f.is_srgb())
             .unwrap_or(surface_caps.formats[0]);
 
+        let surface_view_formats = adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::SURFACE_VIEW_FORMATS);
+        let view_formats = view_formats.iter()
+            .copied()
+            .filter(|&format| {
+                let usable = surface_view_formats && texture::is_view_format_compatible(surface_format, format);
+                if !usable {
+                    log::warn!("Can't view the {:?} surface as {:?}, ignoring it", surface_format, format);
+                }
+                usable
+            })
+            .collect();
+
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
@@ -73,10 +525,25 @@ impl State {
             height: size.height,
             present_mode: surface_caps.present_modes[0],
             alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: Vec::new(),
+            view_formats,
         };
 
         surface.configure(&device, &config);
+        #[cfg(not(target_arch = "wasm32"))]
+        bug_report::set_capabilities(bug_report::capabilities_json(&adapter, &surface_caps.formats));
+
+        let pipeline_config = PipelineConfig {
+            color_format: config.format,
+            // Stencil is used for outlines
+            depth_format: texture::select_depth_format(&adapter, true),
+            depth_write: true,
+            sample_count: 1,
+            unclipped_depth: false,
+            overlay_depth_bias: wgpu::DepthBiasState::default(),
+        };
+        let depth_texture = Texture::create_depth_texture(
+            &device, &config, pipeline_config.depth_format, pipeline_config.sample_count, "Depth Texture"
+        );
 
         // Pipeline
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
@@ -87,56 +554,76 @@ impl State {
         // Smaller approach
         // let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
 
+        // Camera
+        let camera = Camera {
+            eye: Vec3::new(0.0, 5.0, 10.0),
+            target: Vec3::ZERO,
+            up: Vec3::Y,
+            aspect: config.width as f32 / config.height as f32,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+            projection: Projection::Perspective,
+        };
+
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(&camera);
+
+        let camera_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Camera Buffer"),
+                contents: bytemuck::cast_slice(&[camera_uniform]),
+                // COPY_DST - we rewrite it every frame with queue.write_buffer
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Camera Bind Group Layout"),
+            entries: CAMERA_LAYOUT_ENTRIES,
+        });
+
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Camera Bind Group"),
+            layout: &camera_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                }
+            ],
+        });
+
+        // Placeholder checkerboards in different colors, one per layer
+        let layered_texture = LayeredTexture::new(&device, 64, 64, LAYER_COLORS.len() as u32, false, "Layered Texture")
+            .expect("Couldn't create layered texture");
+        for (i, color) in LAYER_COLORS.iter().enumerate() {
+            let rgba = texture::checkerboard_rgba(64, *color, [255, 255, 255, 255]);
+            layered_texture.write_layer(&queue, i as u32, &rgba);
+        }
+        let layered_texture_bind_group_layout = LayeredTexture::bind_group_layout(&device);
+        let layered_texture_bind_group = layered_texture.create_bind_group(&device, &layered_texture_bind_group_layout);
+
+        let bones = BoneBuffer::new(&device);
+        bones.write(&queue, &[]);
+
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[],
+                bind_group_layouts: &[
+                    &camera_bind_group_layout,
+                    &layered_texture_bind_group_layout,
+                    &bones.bind_group_layout,
+                ],
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                entry_point: "vs_main",
-                module: &shader,
-                
-                // Vertex Buffer
-                buffers: &[
-                    Vertex::desc(),
-                ],
-            },
-            fragment: Some(wgpu::FragmentState {
-                entry_point: "fs_main",
-                module: &shader,
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            //2
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            //3
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
+        let (render_pipeline, flat_render_pipeline) = pipeline::create_mesh_pipelines(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            &pipeline_config,
+        );
 
         // Buffer
         let vertex_buffer = device.create_buffer_init(
@@ -147,15 +634,235 @@ impl State {
             }
         );
 
+        let instances = instance::grid(INITIAL_INSTANCE_COUNT, layered_texture.count());
+        let frame_arena = FrameArena::new(&device, 256 * 1024);
+        let mut instance_buffer = GrowableBuffer::new(
+            &device,
+            "Instance Buffer",
+            // COPY_SRC for TAA's copy of last frame's instances
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
+            (instances.len() * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+        );
+        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        instance_buffer.write(&device, &queue, bytemuck::cast_slice(&instance_data));
+
+        let blitter = Blitter::new(&device);
+        let occlusion_queries = OcclusionQueries::new(&device, MAX_VIEWPORTS);
+        let outline = OutlineRenderer::new(
+            &device,
+            &pipeline_config,
+            &shader,
+            &[&camera_bind_group_layout, &layered_texture_bind_group_layout, &bones.bind_group_layout],
+        );
+        let wireframe = WireframeRenderer::new(
+            &device,
+            &pipeline_config,
+            [&camera_bind_group_layout, &layered_texture_bind_group_layout, &bones.bind_group_layout],
+        );
+        wireframe.set_style(&queue, DEFAULT_WIREFRAME_COLOR, 1.0);
+        let mut transparency = TransparentRenderer::new(
+            &device,
+            &pipeline_config,
+            &camera_bind_group_layout,
+            config.width,
+            config.height,
+        );
+        transparency.quads = TransparentRenderer::intersecting_panes(Vec3::new(0.0, 1.5, 0.0));
+        let line_batch = LineBatch::new(&device, &pipeline_config, &camera_bind_group_layout);
+        let mut gizmo_lines = LineBatch::on_top(&device, &pipeline_config, &camera_bind_group_layout);
+        gizmo_lines.set_line_width(3.0);
+        let points = PointRenderer::new(&device, &pipeline_config, &camera_bind_group_layout);
+        let trails = TrailRenderer::new(&device, &pipeline_config, &camera_bind_group_layout);
+        let impostor = ImpostorRenderer::new(&device, &pipeline_config, &camera_bind_group_layout);
+        let sky = SkyRenderer::new(&device, &pipeline_config, &camera_bind_group_layout);
+        let grid = GridRenderer::new(&device, &pipeline_config, &camera_bind_group_layout);
+        let clear_rects = ClearRects::new(&device, &pipeline_config);
+        let ibl_bind_group_layout = IblMaps::bind_group_layout(&device);
+        let pbr = PbrRenderer::new(
+            &device,
+            &pipeline_config,
+            [&camera_bind_group_layout, &bones.bind_group_layout, &ibl_bind_group_layout],
+        );
+        let conservative_demo = ConservativeDemo::new(&device, config.format);
+        let histogram_overlay = HistogramOverlay::new(&device, config.format);
+        let errors = ErrorLog::new();
+        let text_overlay = TextOverlay::new(&device, config.format);
+        let debug_views = DebugViews::new(&device, config.format);
+        let decals = DecalRenderer::new(&device, &queue, config.format, &camera_bind_group_layout);
+        let luminance = LuminanceReduction::new(&device, &adapter);
+        let per_draw = PerDrawData::new(
+            &device,
+            &pipeline_config,
+            [&camera_bind_group_layout, &layered_texture_bind_group_layout, &bones.bind_group_layout],
+        );
+        let fxaa = FxaaRenderer::new(&device, config.format);
+        let taa = TaaRenderer::new(&device, config.format);
+        let velocity = VelocityPass::new(
+            &device,
+            &pipeline_config,
+            [&camera_bind_group_layout, &layered_texture_bind_group_layout, &bones.bind_group_layout],
+        );
+        let motion_blur = MotionBlur::new(&device, &adapter, config.format);
+        let mesh_positions = VERTICIES.iter().map(|v| Vec3::from(v.position)).collect::<Vec<_>>();
+        let mesh_aabb = Aabb::from_points(mesh_positions.iter().copied()).unwrap();
+        let lod = LodSelector::new(VERTICIES.len() as u32, &mesh_aabb);
+        let instance_aabbs = instances.iter().map(|instance| mesh_aabb.transformed(&instance.model_matrix())).collect::<Vec<_>>();
+        let bvh = Bvh::build(&instance_aabbs);
+
         Self {
             surface,
-            device,
+            adapter,
+            fallback_adapter,
+            device: std::sync::Arc::new(device),
             queue,
             config,
             size,
             window,
+            max_texture_size: None,
+            depth_texture,
+            layered_texture,
+            layered_texture_bind_group_layout,
+            layered_texture_bind_group,
+            bones,
+            shader,
+            shader_switches: Vec::new(),
+            render_pipeline_layout,
             render_pipeline,
+            flat_render_pipeline,
+            flat_shading: false,
+            per_draw_mode: false,
+            per_draw,
+            pipeline_config,
+            msaa_view: None,
+            anti_aliasing: AntiAliasing::Msaa,
+            msaa_sample_count: 1,
+            fxaa,
+            taa,
+            velocity,
+            motion_blur,
+            motion_blur_enabled: false,
+            preserve_frame: false,
+            history_view: None,
+            luminance,
+            measure_luminance: false,
+            luminance_title_time: instant::Instant::now(),
+            auto_exposure: AutoExposure::new(ExposureSettings::default()),
+            auto_exposure_enabled: false,
+            manual_exposure: None,
+            histogram_overlay,
+            show_histogram: false,
+            // Fades into the background color
+            fog: FogParams {
+                color: [CLEAR_COLOR.r as f32, CLEAR_COLOR.g as f32, CLEAR_COLOR.b as f32],
+                falloff: FogFalloff::Linear { start: 10.0, end: 40.0 },
+            },
+            fog_enabled: false,
+            errors,
+            text_overlay,
+            show_error_history: false,
+            debug_views,
+            decals,
+            cursor_position: None,
+            modifiers: ModifiersState::empty(),
+            builtin_debug_view: BuiltinDebugView::Off,
+            clear_rects,
             vertex_buffer,
+            mesh_vertices: VERTICIES.to_vec(),
+            streamed_mesh: None,
+            instances,
+            instance_buffer,
+            camera,
+            camera_rig: CameraRig::new(),
+            camera_uniform,
+            camera_buffer,
+            camera_bind_group,
+            camera_bind_group_layout,
+            view_camera: camera,
+            viewport_cameras: Vec::new(),
+            split_screen: false,
+            last_update: instant::Instant::now(),
+            blitter,
+            line_batch,
+            points,
+            trails,
+            instance_trails: Vec::new(),
+            comet: None,
+            loader: AssetLoader::new(),
+            asset_watcher: AssetWatcher::default(),
+            asset_reloaded: None,
+            clear_color: CLEAR_COLOR,
+            scene: None,
+            scene_watcher: None,
+            mesh_source: Some(SceneMesh::Triangle),
+            environment_path: None,
+            point_cloud_paths: Vec::new(),
+            streaming: None,
+            deferred_destruction: DeferredDestruction::new(FRAMES_IN_FLIGHT),
+            frame_arena,
+            home_camera: None,
+            initial_camera: camera.pose(),
+            camera_velocity: Vec3::ZERO,
+            sky,
+            procedural_sky: None,
+            procedural_sky_params: None,
+            time_of_day: None,
+            day_lighting: None,
+            grid,
+            ibl_bind_group_layout,
+            ibl: None,
+            default_ibl: None,
+            mesh_material: None,
+            wrong_color_space: None,
+            pbr,
+            vertex_count: VERTICIES.len() as u32,
+            lod,
+            show_lod_colors: false,
+            frustum_culling: true,
+            cull_camera: None,
+            instance_aabbs,
+            bvh,
+            visible: Vec::new(),
+            visible_items: Vec::new(),
+            impostor,
+            mesh_positions,
+            mesh_aabb,
+            show_bounds: false,
+            normal_segments: Vec::new(),
+            mesh_surface: None,
+            mesh_options: MeshOptions::default(),
+            frame_segments: Vec::new(),
+            stencil_clear: 0,
+            #[cfg(not(target_arch = "wasm32"))]
+            bug_report_dir: std::path::PathBuf::from("bug-reports"),
+            clear_depth: 1.0,
+            outline,
+            selected: None,
+            undo_stack: UndoStack::default(),
+            gizmo_lines,
+            gizmo_hovered: None,
+            gizmo_drag: None,
+            wireframe,
+            wireframe_scope: WireframeScope::Off,
+            conservative_demo,
+            transparency,
+            fps: None,
+            // run_with reseeds right after
+            seed: 0,
+            input_map: InputMap::default(),
+            input_recorder: None,
+            input_playback: None,
+            screenshot_path: None,
+            pixel_request: None,
+            read_pixel: None,
+            graph_dump_path: None,
+            frame_stream: None,
+            occlusion_queries,
+            poll_thread: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            remote: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            remote_screenshots: Vec::new(),
+            frame_dt: 0.0,
         }
     }
 
@@ -163,181 +870,3901 @@ impl State {
         &self.window
     }
 
-    fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
-        if size.height > 0 && size.width > 0 {
-            self.size = size;
-            self.config.width = size.width;
-            self.config.height = size.height;
-            self.surface.configure(&self.device, &self.config);
-        }
+    // For tools to show what a drag will do (grab, crosshair, resize, ...)
+    pub fn set_cursor_icon(&self, icon: CursorIcon) {
+        self.window.set_cursor_icon(icon);
     }
 
-    fn input(&mut self, event: &WindowEvent) -> bool {
-        false
+    // Override for the texture size limit, it can only lower the device limit
+    pub fn set_max_texture_size(&mut self, max_size: Option<u32>) {
+        self.max_texture_size = max_size;
     }
 
-    fn update(&mut self) {
-        // ToDo: Still Empty
+    // `color_space` is what the texture holds: Srgb for colors, Linear for normals, roughness and
+    // other data. See ColorSpace::of_gltf_texture for a glTF material's slots
+    pub fn load_texture(&self, png_bytes: &[u8], color_space: ColorSpace, label: &str) -> Result<Texture, png::DecodingError> {
+        let device_limit = self.device.limits().max_texture_dimension_2d;
+        let max_dimension = self.max_texture_size.map_or(device_limit, |max| max.min(device_limit));
+        Texture::from_png_bytes(&self.device, &self.queue, png_bytes, color_space, max_dimension, label)
     }
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+    // Formats the surface can be viewed as besides its own, e.g. linear for an sRGB surface.
+    // Use texture::create_view_as on the surface texture to get such a view
+    pub fn surface_view_formats(&self) -> &[wgpu::TextureFormat] {
+        &self.config.view_formats
+    }
 
+    // Custom passes sharing the depth buffer need pipelines with this format
+    pub fn depth_format(&self) -> wgpu::TextureFormat {
+        self.pipeline_config.depth_format
+    }
 
-        // Modern GPUS expect their commands to be written inside buffer. That's why we are creating
-        // encoder, which represents GPU instruction and than passing it in queue
+    // Copies `count` items of a GPU buffer back to the CPU. Source buffer needs COPY_SRC usage.
+    // Blocks until the GPU is done, so keep it for compute results / tools, not per frame work.
+    // Not on the web, where nothing can wait for the GPU, see readback::map_read
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_storage_buffer<T: bytemuck::Pod>(&self, buffer: &wgpu::Buffer, count: usize) -> Vec<T> {
+        readback::read_buffer(&self.device, &self.queue, buffer, count)
+    }
+
+    // Renders a mesh, material or texture alone into an offscreen size x size RGBA8 image, see
+    // preview::render_preview. The scene's buffers aren't touched. Blocks on the readback like
+    // read_storage_buffer, so not on the web either
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_preview(&self, asset: preview::PreviewAsset, size: u32) -> Vec<u8> {
+        preview::render_preview(&self.device, &self.queue, asset, size)
+    }
+
+    // Copies src into dst. Same size and format -> plain GPU copy (needs COPY_SRC / COPY_DST),
+    // otherwise goes through the blit shader (dst needs RENDER_ATTACHMENT) which converts
+    pub fn blit(&mut self, src: &wgpu::Texture, dst: &wgpu::Texture) {
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Render Encoder")
+            label: Some("Blit Encoder")
         });
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        // Tell frame what happens to previous frame
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.5,
-                            g: 0.4,
-                            b: 0.9,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
+        let direct_copy = src.format() == dst.format()
+            && src.size() == dst.size()
+            && src.usage().contains(wgpu::TextureUsages::COPY_SRC)
+            && dst.usage().contains(wgpu::TextureUsages::COPY_DST);
 
-            // Pipeline
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.draw(0..3, 0..1);
+        if direct_copy {
+            encoder.copy_texture_to_texture(src.as_image_copy(), dst.as_image_copy(), src.size());
+        } else {
+            let src_view = src.create_view(&wgpu::TextureViewDescriptor::default());
+            let dst_view = dst.create_view(&wgpu::TextureViewDescriptor::default());
+            self.blitter.blit(&self.device, &mut encoder, &src_view, &dst_view, dst.format());
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    }
 
-        Ok(())
+    // Shader path only, for when all you have are views (e.g. a single mip or layer)
+    pub fn blit_views(&mut self, src: &wgpu::TextureView, dst: &wgpu::TextureView, dst_format: wgpu::TextureFormat) {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Blit Encoder")
+        });
+        self.blitter.blit(&self.device, &mut encoder, src, dst, dst_format);
+        self.queue.submit(std::iter::once(encoder.finish()));
     }
-}
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
-    position: [f32; 3],
-    // 3D Space x, y, z
-    color: [f32; 3], // R G B
-}
+    // Last thing before State is dropped at exit. Waits for the GPU so nothing is dropped while
+    // it's still in use and hands streamed frames that made it to their callback. The fields then
+    // drop in declaration order, the surface before the window
+    fn shutdown(&mut self) {
+        // Joined first, it would poll along with the wait below
+        self.poll_thread = None;
+        self.device.poll(wgpu::Maintain::Wait);
+        if let Some(frame_stream) = &mut self.frame_stream {
+            frame_stream.poll(&self.device);
+        }
+    }
 
-impl Vertex {
-    // Get VertexBuffer Layout, To tell the pipeline how to read
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        
-        /* Extended attributes Declaration
-        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = [ // Attributes of Vertex Struct Fields. 1:1 Mapping
-            wgpu::VertexAttribute {
-                offset: 0, // From Where The attribute starts. Every Next Offset is sum of previous. First One is usually 0
-                shader_location: 0,
-                format: wgpu::VertexFormat::Float32x3,
+    // Draws once with every pipeline into throwaway 1x1 targets, so drivers that only compile on
+    // first use do it during load instead of hitching the first frame that needs the pipeline.
+    // Best effort: drivers are free to compile again later (e.g. for a different render pass), and
+    // rebuild_pipelines() makes new pipelines that aren't prewarmed
+    pub fn prewarm(&mut self) {
+        let sample_count = self.pipeline_config.sample_count;
+        let color_view = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Prewarm Texture"),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
             },
-            wgpu::VertexAttribute {
-                offset: std::mem::size_of::<[f32; 3]> as wgpu::BufferAddress, // Sum Of Previous Offsets
-                shader_location: 1,
-                format: wgpu::VertexFormat::Float32x3,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.pipeline_config.color_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        }).create_view(&wgpu::TextureViewDescriptor::default());
+        let depth_texture = Texture::create_depth_texture_sized(
+            &self.device, 1, 1, self.pipeline_config.depth_format, sample_count, "Prewarm Depth Texture"
+        );
+
+        self.blitter.prewarm(&self.device, self.config.format);
+        // Draws below bind whatever these put in the frame arena
+        self.line_batch.upload(&self.device, &self.queue, &mut self.frame_arena, 1, 1);
+        self.gizmo_lines.upload(&self.device, &self.queue, &mut self.frame_arena, 1, 1);
+        self.clear_rects.upload(&self.device, &self.queue, &mut self.frame_arena, &[]);
+        self.transparency.upload(&self.device, &self.queue, &mut self.frame_arena, self.view_camera.eye);
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Prewarm Encoder")
+        });
+        debug_group(&mut encoder, "Prewarm", |encoder| {
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Prewarm Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &color_view,
+                        resolve_target: None,
+                        ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::BLACK), store: false },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &depth_texture.view,
+                        depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Clear(self.clear_depth), store: false }),
+                        // Outline pipelines write stencil, so it can't be read only
+                        stencil_ops: self.pipeline_config.depth_format.has_stencil_aspect().then_some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(0),
+                            store: false,
+                        }),
+                    }),
+                });
+
+                for pipeline in [&self.render_pipeline, &self.flat_render_pipeline] {
+                    render_pass.set_pipeline(pipeline);
+                    render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+                    render_pass.set_bind_group(1, &self.layered_texture_bind_group, &[]);
+                    render_pass.set_bind_group(2, &self.bones.bind_group, &[]);
+                    render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                    render_pass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
+                    render_pass.draw(0..self.vertex_count, 0..1);
+                }
+                // Needs the mesh bindings set above
+                self.outline.prewarm(&mut render_pass, self.vertex_count);
+                self.line_batch.prewarm(&mut render_pass, &self.camera_bind_group);
+                self.gizmo_lines.prewarm(&mut render_pass, &self.camera_bind_group);
+                // Only has a pipeline worth warming once an environment is set
+                self.sky.draw(&mut render_pass, &self.camera_bind_group);
+                self.grid.prewarm(&mut render_pass, &self.camera_bind_group);
+                self.clear_rects.draw(&mut render_pass, 0);
             }
-        ]; // We need constant variable to return 'static reference
-        
-         */
-        const ATTRIBUTES : [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
 
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex> as wgpu::BufferAddress, // Item Size In Buffer, To Make Next Step
-            step_mode: wgpu::VertexStepMode::Vertex, // Per Vertex Data Or Per instance Data // ToDo: Difference ?
-            attributes: &ATTRIBUTES,
+            self.transparency.prewarm(
+                &self.device, encoder, &color_view, &depth_texture.view, &self.camera_bind_group, sample_count
+            );
+        });
+        self.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    // Flat: whole triangle gets the color of its provoking vertex. In WebGPU that's always
+    // the first vertex of the triangle (not configurable like in OpenGL/Vulkan)
+    pub fn set_flat_shading(&mut self, flat: bool) {
+        self.flat_shading = flat;
+    }
+
+    // Scene pipelines keep testing against the depth buffer either way, this only
+    // controls whether they write to it. Transparent objects usually want write off
+    pub fn set_depth_write(&mut self, write: bool) {
+        if self.pipeline_config.depth_write == write {
+            return;
         }
+        self.pipeline_config.depth_write = write;
+        self.rebuild_pipelines();
     }
-}
 
-const VERTICIES: &[Vertex] = &[
-    Vertex { position: [0.0, 0.5, 0.0], color: [1.0, 0.0, 0.0] },
-    Vertex { position: [-0.5, -0.5, 0.0], color: [0.0, 1.0, 0.0] },
-    Vertex { position: [0.5, -0.5, 0.0], color: [0.0, 0.0, 1.0] }
-];
+    // Depth clamping for the mesh pipelines, see PipelineConfig::unclipped_depth. Returns false
+    // (and changes nothing) if the device doesn't have DEPTH_CLIP_CONTROL. Without it, keep what
+    // should be clamped inside the near / far planes instead, e.g. by pulling a light's near plane back
+    pub fn set_depth_clamp(&mut self, enabled: bool) -> bool {
+        if enabled && !self.device.features().contains(pipeline::DEPTH_CLAMP_FEATURES) {
+            return false;
+        }
+        if self.pipeline_config.unclipped_depth != enabled {
+            self.pipeline_config.unclipped_depth = enabled;
+            self.rebuild_pipelines();
+        }
+        true
+    }
 
+    // Pushes overlays (shaded wireframe, decals) toward the camera by `bias` so they stop z-fighting
+    // with the surface under them, see PipelineConfig::overlay_depth_bias. A small negative constant
+    // with a negative slope_scale for grazing angles is usually enough, e.g. -2 and -1.0. `clamp`
+    // (largest bias allowed, 0 for no limit) isn't supported on GL
+    pub fn set_depth_bias(&mut self, bias: wgpu::DepthBiasState) {
+        if self.pipeline_config.overlay_depth_bias == bias {
+            return;
+        }
+        self.pipeline_config.overlay_depth_bias = bias;
+        self.wireframe.rebuild_pipeline(
+            &self.device,
+            &self.pipeline_config,
+            [&self.camera_bind_group_layout, &self.layered_texture_bind_group_layout, &self.bones.bind_group_layout],
+        );
+    }
 
-pub async fn run() {
-    cfg_if::cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            std::panic::set_hook(Box::new(console_error_panic_hook::hook));
-            console_log::init_with_level(log::Level::Warn).expect("Couldn't initialize logger");
-        } else {
-            env_logger::init();
+    pub fn depth_bias(&self) -> wgpu::DepthBiasState {
+        self.pipeline_config.overlay_depth_bias
+    }
+
+    // Recreates every pipeline from the current shader source and settings (formats, modes).
+    // Anything that changes pipeline state should go through here
+    pub fn rebuild_pipelines(&mut self) {
+        // Waiting for the scope blocks, which the web can't
+        #[cfg(not(target_arch = "wasm32"))]
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let source = vertex_format::shader_variant(include_str!("shader.wgsl"), &self.shader_switches)
+            .expect("Switches come from negotiating with shader.wgsl");
+        self.shader = self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        (self.render_pipeline, self.flat_render_pipeline) = pipeline::create_mesh_pipelines(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.shader,
+            &self.pipeline_config,
+        );
+        if let Some(streamed_mesh) = &mut self.streamed_mesh {
+            streamed_mesh.rebuild_pipelines(&self.device, &self.render_pipeline_layout, &self.shader, &self.pipeline_config);
+        }
+        self.line_batch.rebuild_pipeline(&self.device, &self.pipeline_config, &self.camera_bind_group_layout);
+        self.gizmo_lines.rebuild_pipeline(&self.device, &self.pipeline_config, &self.camera_bind_group_layout);
+        self.points.rebuild_pipeline(&self.device, &self.pipeline_config, &self.camera_bind_group_layout);
+        self.trails.rebuild_pipeline(&self.device, &self.pipeline_config, &self.camera_bind_group_layout);
+        if let Some(streaming) = &mut self.streaming {
+            streaming.rebuild_pipeline(&self.device, &self.pipeline_config, &self.camera_bind_group_layout);
+        }
+        self.impostor.rebuild_pipeline(&self.device, &self.pipeline_config, &self.camera_bind_group_layout);
+        self.sky.rebuild_pipeline(&self.device, &self.pipeline_config, &self.camera_bind_group_layout);
+        self.grid.rebuild_pipeline(&self.device, &self.pipeline_config, &self.camera_bind_group_layout);
+        self.clear_rects.rebuild_pipeline(&self.device, &self.pipeline_config);
+        self.per_draw.rebuild_pipeline(
+            &self.device,
+            &self.pipeline_config,
+            [&self.camera_bind_group_layout, &self.layered_texture_bind_group_layout, &self.bones.bind_group_layout],
+        );
+        self.pbr.rebuild_pipeline(
+            &self.device,
+            &self.pipeline_config,
+            [&self.camera_bind_group_layout, &self.bones.bind_group_layout, &self.ibl_bind_group_layout],
+        );
+        self.transparency.rebuild_pipelines(&self.device, &self.pipeline_config, &self.camera_bind_group_layout);
+        self.fxaa.rebuild_pipeline(&self.device, self.pipeline_config.color_format);
+        self.taa.rebuild_pipeline(&self.device, self.pipeline_config.color_format);
+        self.velocity.rebuild_pipeline(
+            &self.device,
+            &self.pipeline_config,
+            [&self.camera_bind_group_layout, &self.layered_texture_bind_group_layout, &self.bones.bind_group_layout],
+        );
+        self.motion_blur.rebuild_pipeline(&self.device, self.pipeline_config.color_format);
+        self.text_overlay.rebuild_pipeline(&self.device, self.pipeline_config.color_format);
+        self.debug_views.rebuild_pipeline(&self.device, self.pipeline_config.color_format);
+        self.decals.rebuild_pipeline(&self.device, self.pipeline_config.color_format, &self.camera_bind_group_layout);
+        self.wireframe.rebuild_pipeline(
+            &self.device,
+            &self.pipeline_config,
+            [&self.camera_bind_group_layout, &self.layered_texture_bind_group_layout, &self.bones.bind_group_layout],
+        );
+        self.outline.rebuild_pipelines(
+            &self.device,
+            &self.pipeline_config,
+            &self.shader,
+            &[&self.camera_bind_group_layout, &self.layered_texture_bind_group_layout, &self.bones.bind_group_layout],
+        );
+        // Whatever failed is drawn with until the next rebuild works, a banner says so meanwhile
+        #[cfg(not(target_arch = "wasm32"))]
+        match pollster::block_on(self.device.pop_error_scope()) {
+            Some(e) => self.report_sticky_error("pipelines", Severity::Error, format!("Pipeline rebuild failed: {}", e)),
+            None => self.resolve_error("pipelines"),
         }
     }
 
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+    // Switches to the next MSAA sample count (1 -> 2 -> 4 -> 8 -> 1) the adapter supports
+    pub fn cycle_sample_count(&mut self) {
+        let supported = pipeline::supported_sample_counts(
+            &self.adapter,
+            self.pipeline_config.color_format,
+            self.pipeline_config.depth_format,
+        );
+        let next = supported.iter()
+            .copied()
+            .find(|&count| count > self.pipeline_config.sample_count)
+            .unwrap_or(1);
+        self.set_sample_count(next);
+    }
 
-    #[cfg(target_arch = "wasm32")]
-    {
-        // Winit prevents sizing with CSS, so we have to set
-        // the size manually when on web.
-        use winit::dpi::PhysicalSize;
-        window.set_inner_size(PhysicalSize::new(450, 400));
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        if self.pipeline_config.sample_count == sample_count {
+            return;
+        }
+        // MSAA and the post process modes don't mix, asking for samples switches back to MSAA
+        if sample_count > 1 && self.anti_aliasing != AntiAliasing::Msaa {
+            self.anti_aliasing = AntiAliasing::Msaa;
+        }
+        self.pipeline_config.sample_count = sample_count;
+        self.create_render_targets();
+        self.rebuild_pipelines();
+        self.update_title();
+    }
 
-        use winit::platform::web::WindowExtWebSys;
-        web_sys::window()
-            .and_then(|win| win.document())
-            .and_then(|doc| {
-                let dst = doc.get_element_by_id("wasm-example")?;
-                let canvas = web_sys::Element::from(window.canvas());
-                dst.append_child(&canvas).ok()?;
-                Some(())
-            })
-            .expect("Couldn't append canvas to document body.");
+    // Screen sized targets, recreated on resize and when the sample count changes
+    fn create_render_targets(&mut self) {
+        self.depth_texture = Texture::create_depth_texture(
+            &self.device,
+            &self.config,
+            self.pipeline_config.depth_format,
+            self.pipeline_config.sample_count,
+            "Depth Texture",
+        );
+        self.msaa_view = pipeline::create_msaa_view(&self.device, &self.config, self.pipeline_config.sample_count);
+        self.history_view = self.needs_history_view().then(|| self.create_history_view());
+        self.transparency.resize(&self.device, self.config.width, self.config.height, self.pipeline_config.sample_count);
+        let taa_size = (self.anti_aliasing == AntiAliasing::Taa).then_some((self.config.width, self.config.height));
+        self.taa.set_size(&self.device, taa_size);
+        self.velocity.invalidate();
     }
 
-    let mut state = State::new(window).await;
-
-    event_loop.run(move |event, _, control_flow| match event {
-        Event::WindowEvent {
-            ref event,
-            window_id,
-        } if window_id == state.window.id() => if !state.input(event) {
-            println!("Win Event - 3");
-            match event {
-                WindowEvent::CloseRequested | WindowEvent::KeyboardInput {
-                    input:
-                    KeyboardInput {
-                        state: ElementState::Pressed,
-                        virtual_keycode: Some(VirtualKeyCode::Escape),
-                        ..
-                    },
-                    ..
-                } => *control_flow = ControlFlow::Exit,
+    fn needs_history_view(&self) -> bool {
+        self.preserve_frame
+            || self.luminance_wanted()
+            || self.anti_aliasing != AntiAliasing::Msaa
+            || self.motion_blur_enabled
+    }
 
-                _ => {}
-            }
-        },
+    // Auto exposure and the histogram overlay run on the luminance measurement too
+    fn luminance_wanted(&self) -> bool {
+        self.luminance.is_some() && (self.measure_luminance || self.auto_exposure_enabled || self.show_histogram)
+    }
 
-        Event::RedrawRequested(window_id) if window_id == state.window.id() => {
-            println!("Redraw - 2");
-            state.update();
-            match state.render() {
-                Ok(_) => {}
-                Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
-                Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                Err(e) => eprintln!("{:?}", e)
-            }
+    // Creates or drops the history texture when what needs it changed
+    fn update_history_view(&mut self) {
+        if self.needs_history_view() == self.history_view.is_some() {
+            return;
         }
+        self.history_view = self.needs_history_view().then(|| self.create_history_view());
+        // The present pass only has &self
+        self.blitter.prewarm(&self.device, self.config.format);
+    }
+
+    fn create_history_view(&self) -> wgpu::TextureView {
+        self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("History Texture"),
+            size: wgpu::Extent3d {
+                width: self.config.width,
+                height: self.config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.config.format,
+            // Drawn into like the surface, then blitted to it
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            // Aliased the same way as the surface, it stands in for it
+            view_formats: &self.config.view_formats,
+        }).create_view(&wgpu::TextureViewDescriptor::default())
+    }
 
-        Event::MainEventsCleared => {
-            println!("Main Event Cleared - 1");
-            state.window().request_redraw();
+    // Switches between MSAA (with the last sample count it had), FXAA and TAA
+    pub fn set_anti_aliasing(&mut self, mode: AntiAliasing) {
+        if self.anti_aliasing == mode {
+            return;
+        }
+        if self.anti_aliasing == AntiAliasing::Msaa {
+            self.msaa_sample_count = self.pipeline_config.sample_count;
+        }
+        let sample_count = match mode {
+            AntiAliasing::Msaa => self.msaa_sample_count,
+            AntiAliasing::Fxaa | AntiAliasing::Taa => 1,
+        };
+        self.set_sample_count(sample_count);
+        self.anti_aliasing = mode;
+        let taa_size = (mode == AntiAliasing::Taa).then_some((self.config.width, self.config.height));
+        self.taa.set_size(&self.device, taa_size);
+        self.update_history_view();
+        self.update_title();
+    }
+
+    pub fn anti_aliasing(&self) -> AntiAliasing {
+        self.anti_aliasing
+    }
+
+    // Opaque pass loads the previous frame instead of clearing, so anything moving leaves
+    // trails. Viewport backgrounds aren't drawn in this mode. Resizing starts from scratch
+    pub fn set_preserve_previous_frame(&mut self, preserve: bool) {
+        if self.preserve_frame == preserve {
+            return;
+        }
+        self.preserve_frame = preserve;
+        self.update_history_view();
+    }
+
+    // Min / max / average luminance of the scene, measured on the GPU while on (a frame or two
+    // behind) and shown in the title. Returns false if there's no compute support
+    pub fn set_measure_luminance(&mut self, measure: bool) -> bool {
+        if measure && self.luminance.is_none() {
+            return false;
+        }
+        self.measure_luminance = measure;
+        self.update_history_view();
+        self.update_title();
+        true
+    }
+
+    // Exposure from the luminance histogram, adapting over time (see ExposureSettings). Returns
+    // false if there's no compute support
+    pub fn set_auto_exposure(&mut self, enabled: bool) -> bool {
+        if enabled && self.luminance.is_none() {
+            return false;
+        }
+        self.auto_exposure_enabled = enabled;
+        self.auto_exposure.reset();
+        self.update_history_view();
+        self.update_title();
+        true
+    }
+
+    pub fn exposure_settings(&mut self) -> &mut ExposureSettings {
+        &mut self.auto_exposure.settings
+    }
+
+    // Shows `message` as a toast for a few seconds, and logs it
+    pub fn report_error(&mut self, severity: Severity, message: impl Into<String>) {
+        self.errors.report(severity, message.into());
+    }
+
+    // Banner that stays until resolve_error(key), for ongoing trouble. Reporting the same key
+    // again replaces the message
+    pub fn report_sticky_error(&mut self, key: &str, severity: Severity, message: impl Into<String>) {
+        self.errors.report_sticky(key, severity, message.into());
+    }
+
+    pub fn resolve_error(&mut self, key: &str) {
+        self.errors.resolve(key);
+    }
+
+    // Recent reports, toasts and banners alike, oldest first
+    pub fn error_history(&self) -> impl Iterator<Item = &ErrorEntry> {
+        self.errors.history()
+    }
+
+    // Recent reports listed on the left of the window
+    pub fn set_show_error_history(&mut self, show: bool) {
+        self.show_error_history = show;
+    }
+
+    // Color and falloff, applied while fog is enabled
+    pub fn set_fog(&mut self, fog: FogParams) {
+        self.fog = fog;
+    }
+
+    pub fn fog(&self) -> FogParams {
+        self.fog
+    }
+
+    // Mesh, PBR and transparent panes fade towards the fog color with view depth. The sky and
+    // viewport backgrounds aren't fogged
+    pub fn set_fog_enabled(&mut self, enabled: bool) {
+        self.fog_enabled = enabled;
+        self.update_title();
+    }
+
+    // Blur along the screen space motion of the last frame, see MotionBlur. Only with a single
+    // sample and one viewport, it's skipped while MSAA or split screen is on
+    pub fn set_motion_blur(&mut self, enabled: bool) {
+        if self.motion_blur_enabled == enabled {
+            return;
+        }
+        self.motion_blur_enabled = enabled;
+        self.motion_blur.reset();
+        self.update_history_view();
+        self.update_title();
+    }
+
+    pub fn motion_blur(&self) -> bool {
+        self.motion_blur_enabled
+    }
+
+    // Intensity (shutter), sample count and max radius, picked up the next frame
+    pub fn motion_blur_settings(&mut self) -> &mut MotionBlurSettings {
+        &mut self.motion_blur.settings
+    }
+
+    // Cuts: TAA history and the velocity pass's last frame no longer line up with what's drawn
+    fn reset_temporal_history(&mut self) {
+        self.taa.invalidate();
+        self.velocity.invalidate();
+        self.motion_blur.reset();
+    }
+
+    // Fixed exposure, overrides auto exposure while Some
+    pub fn set_exposure(&mut self, exposure: Option<f32>) {
+        self.manual_exposure = exposure;
+        self.update_title();
+    }
+
+    // Linear scale the scene is drawn with: manual, adapted or 1. The day cycle's bias applies to
+    // the last two
+    pub fn exposure(&self) -> f32 {
+        let bias = self.day_lighting.map_or(0.0, |lighting| lighting.exposure_bias).exp2();
+        match self.manual_exposure {
+            Some(exposure) => exposure,
+            None if self.auto_exposure_enabled => self.auto_exposure.exposure() * bias,
+            None => bias,
+        }
+    }
+
+    // Starts adaptation over from the next measurement, for when the scene changes completely
+    pub fn reset_exposure_adaptation(&mut self) {
+        self.auto_exposure.reset();
+    }
+
+    // Returns false if there's no compute support
+    pub fn set_show_exposure_histogram(&mut self, show: bool) -> bool {
+        if show && self.luminance.is_none() {
+            return false;
+        }
+        self.show_histogram = show;
+        self.update_history_view();
+        true
+    }
+
+    // Latest measurement while set_measure_luminance is on
+    pub fn scene_luminance(&self) -> Option<luminance::LuminanceStats> {
+        self.luminance.as_ref().filter(|_| self.measure_luminance)?.last_result()
+    }
+
+    // Sum of the buffers and textures State and its renderers created, in bytes. Not exact (see
+    // texture::estimated_bytes), and leaves out the surface and small fixed size uniforms, but
+    // follows instance counts, resizes, MSAA and loaded textures
+    pub fn estimated_gpu_memory(&self) -> u64 {
+        let (width, height) = (self.config.width, self.config.height);
+        let sample_count = self.pipeline_config.sample_count;
+        let msaa = match self.msaa_view {
+            Some(_) => texture::estimated_bytes(self.config.format, width, height, 1, 1, sample_count),
+            None => 0,
+        };
+        let history = match self.history_view {
+            Some(_) => texture::estimated_bytes(self.config.format, width, height, 1, 1, 1),
+            None => 0,
+        };
+        let cameras = self.camera_buffer.size() + self.viewport_cameras.iter().map(|(b, _)| b.size()).sum::<u64>();
+        let ibl = [&self.ibl, &self.default_ibl].iter().filter_map(|ibl| ibl.as_ref()).map(IblMaps::gpu_memory).sum::<u64>();
+
+        self.vertex_buffer.size()
+            + self.streamed_mesh.as_ref().map_or(0, StreamedMesh::gpu_memory)
+            + self.instance_buffer.capacity()
+            + cameras
+            + texture::texture_bytes(&self.depth_texture.texture)
+            + msaa
+            + history
+            + self.layered_texture.gpu_memory()
+            + self.bones.gpu_memory()
+            + self.luminance.as_ref().map_or(0, LuminanceReduction::gpu_memory)
+            + self.per_draw.gpu_memory()
+            + self.transparency.gpu_memory()
+            + self.frame_stream.as_ref().map_or(0, FrameStream::gpu_memory)
+            + self.occlusion_queries.as_ref().map_or(0, OcclusionQueries::gpu_memory)
+            + ibl
+            + self.mesh_material.as_ref().map_or(0, PbrMaterial::gpu_memory)
+            + self.wrong_color_space.as_ref().map_or(0, WrongColorSpace::gpu_memory)
+            + self.decals.gpu_memory()
+            + self.sky.gpu_memory()
+            + self.grid.gpu_memory()
+            + self.line_batch.gpu_memory()
+            + self.gizmo_lines.gpu_memory()
+            + self.points.gpu_memory()
+            + self.trails.gpu_memory()
+            + self.streaming.as_ref().map_or(0, ChunkStreamer::gpu_memory)
+            + self.deferred_destruction.gpu_memory()
+            + self.frame_arena.gpu_memory()
+            + self.impostor.gpu_memory()
+            + self.taa.gpu_memory()
+            + self.velocity.gpu_memory()
+            + self.motion_blur.gpu_memory()
+    }
+
+    // Settings that only change on input. Numbers that move every frame (instances, memory,
+    // luminance) are in the overlay instead, see stats_line
+    fn update_title(&self) {
+        let transparency = match self.transparency.mode {
+            TransparencyMode::Sorted => "sorted",
+            TransparencyMode::WeightedBlended => "OIT",
+        };
+        let fps = self.fps.map(|fps| format!(" - {:.0} fps", fps)).unwrap_or_default();
+        let per_draw = match (self.per_draw_mode, self.per_draw.uses_push_constants()) {
+            (false, _) => "",
+            (true, true) => " - per draw (push constants)",
+            (true, false) => " - per draw (dynamic uniforms)",
+        };
+        let exposure = match (self.manual_exposure, self.auto_exposure_enabled) {
+            (Some(exposure), _) => format!(" - exposure {:.2} (manual)", exposure),
+            (None, true) => format!(" - exposure {:.2} (auto)", self.auto_exposure.exposure()),
+            (None, false) => String::new(),
+        };
+        let projection = match self.camera.projection {
+            Projection::Perspective => "",
+            Projection::Orthographic => " - orthographic",
+        };
+        let motion_blur = match (self.motion_blur_enabled, self.pipeline_config.sample_count == 1) {
+            (false, _) => "",
+            (true, true) => " - motion blur",
+            (true, false) => " - motion blur (off with MSAA)",
+        };
+        let fog = if self.fog_enabled { " - fog" } else { "" };
+        let culling = if self.cull_camera.is_some() { " - culling frozen" } else { "" };
+        let demo = match (self.conservative_demo.visible, self.conservative_demo.is_supported()) {
+            (false, _) => "",
+            (true, true) => " - regular vs conservative raster",
+            (true, false) => " - conservative raster unsupported on this adapter",
+        };
+        let loading = match self.loader.pending_count() {
+            0 => String::new(),
+            count => format!(" - loading {} file{}", count, if count == 1 { "" } else { "s" }),
+        };
+        self.window.set_title(&format!(
+            "WGpuPlayground{}{}{}{}{} - {} transparency{}{}{}{}",
+            per_draw,
+            projection,
+            motion_blur,
+            fog,
+            culling,
+            transparency,
+            exposure,
+            demo,
+            loading,
+            fps,
+        ));
+        // Called whenever something in it changes, which covers most settings. The panic hook
+        // can't get to State, so it writes this copy
+        #[cfg(not(target_arch = "wasm32"))]
+        bug_report::set_config(self.config_report());
+    }
+
+    // Bottom line of the overlay
+    fn stats_line(&self) -> String {
+        let anti_aliasing = match self.anti_aliasing {
+            AntiAliasing::Msaa => format!("{}x MSAA", self.pipeline_config.sample_count),
+            AntiAliasing::Fxaa => "FXAA".to_string(),
+            AntiAliasing::Taa => "TAA".to_string(),
+        };
+        let memory = self.estimated_gpu_memory() as f64 / (1024.0 * 1024.0);
+        let packed = match self.packed_vertex_savings() {
+            0 => String::new(),
+            saved => format!(" ({:.1} KiB saved by packed vertices)", saved as f64 / 1024.0),
+        };
+        let luminance = match self.scene_luminance() {
+            Some(stats) => format!(" - luminance {:.3} / {:.3} / {:.3}", stats.min, stats.average, stats.max),
+            None => String::new(),
+        };
+        format!(
+            "{} instances - {} - ~{:.1} MiB GPU{} - seed {}{}",
+            self.instances.len(),
+            anti_aliasing,
+            memory,
+            packed,
+            self.seed,
+            luminance,
+        )
+    }
+
+    fn config_report(&self) -> String {
+        format!(
+            "{:#?}\nsurface: {}x{} {:?} {:?} {:?}\ninstances: {}\nflat shading: {}\nsplit screen: {}\n\
+             transparency: {:?}\nanti-aliasing: {:?}\nmotion blur: {:?}\nfog: {:?}\nstencil clear: {}\nseed: {}\nmaterial: {:?}\n\
+             vertex streams: {:?}\nestimated GPU memory: {} bytes\nkey bindings:\n{}",
+            self.pipeline_config,
+            self.config.width,
+            self.config.height,
+            self.config.format,
+            self.config.present_mode,
+            self.config.usage,
+            self.instances.len(),
+            self.flat_shading,
+            self.split_screen,
+            self.transparency.mode,
+            self.anti_aliasing,
+            self.motion_blur_enabled.then_some(self.motion_blur.settings),
+            self.fog_enabled.then_some(self.fog),
+            self.stencil_clear,
+            self.seed,
+            self.mesh_material.as_ref().map(PbrMaterial::factors),
+            self.vertex_streams(),
+            self.estimated_gpu_memory(),
+            self.input_map.describe(),
+        )
+    }
+
+    // Writes a bug report into `dir` (created if needed): capabilities, config, recent log, the last
+    // 10 seconds of input if a recording is running, and after the next frame a screenshot and the
+    // render graph. Pieces that can't be captured are listed in README.txt instead
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn generate_bug_report(&mut self, dir: &std::path::Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        bug_report::set_config(self.config_report());
+
+        let mut contents = bug_report::ReportContents::default();
+        bug_report::write_common(dir, &mut contents);
+
+        match &self.input_recorder {
+            Some(recorder) => contents.add("input.txt", recorder.write_recent(&dir.join("input.txt"))),
+            None => contents.missing.push("input.txt: input recording was off".to_string()),
+        }
+
+        if self.save_screenshot(dir.join("screenshot.png")) {
+            contents.written.push("screenshot.png (after the next frame)".to_string());
+        } else {
+            contents.missing.push("screenshot.png: surface can't be read back".to_string());
+        }
+        self.graph_dump_path = Some(dir.join("render_graph.txt"));
+        contents.written.push("render_graph.txt (after the next frame)".to_string());
+
+        contents.write_readme(dir)
+    }
+
+    // Reports from Action::BugReport go into new directories under `dir`, bug-reports by default
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_bug_report_dir(&mut self, dir: std::path::PathBuf) {
+        self.bug_report_dir = dir;
+    }
+
+    // Restarts every procedural system from `seed`, two runs with the same seed (and the same
+    // input, see input recording) end up in the same state
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        // One stream per system, so systems drawing more or less don't affect each other
+        let mut rng = Rng::new(seed);
+        self.camera_rig.shake.seed = rng.fork().next_u32();
+        self.update_title();
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn set_fps(&mut self, fps: Option<f32>) {
+        self.fps = fps;
+        self.update_title();
+    }
+
+    // First mode in `preference` the surface supports, or the surface's default if none are. Fifo is
+    // always supported, so ending the list with it makes the result predictable. Returns the mode used
+    pub fn set_present_mode_preference(&mut self, preference: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+        let supported = self.surface.get_capabilities(&self.adapter).present_modes;
+        let mode = preference.iter()
+            .copied()
+            .find(|mode| supported.contains(mode))
+            .unwrap_or(supported[0]);
+        log::info!("Present mode {:?} (asked for {:?}, surface supports {:?})", mode, preference, supported);
+        self.config.present_mode = mode;
+        self.surface.configure(&self.device, &self.config);
+        self.update_title();
+        mode
+    }
+
+    // True when there was no hardware adapter and rendering happens on the CPU. Nothing is wrong
+    // then, it's just slow
+    pub fn is_fallback_adapter(&self) -> bool {
+        self.fallback_adapter
+    }
+
+    // The bottom right quarter, S picks what
+    fn queue_builtin_debug_view(&mut self) {
+        let rect = ScreenRect::bottom_right(
+            self.config.width as f32 / 4.0,
+            self.config.height as f32 / 4.0,
+            8.0,
+            self.config.width,
+            self.config.height,
+        );
+        match self.builtin_debug_view {
+            BuiltinDebugView::Off => {}
+            // Multisampled with MSAA, nothing to show
+            BuiltinDebugView::Depth if self.pipeline_config.sample_count > 1 => {}
+            BuiltinDebugView::Depth => {
+                let view = self.depth_texture.texture.create_view(&wgpu::TextureViewDescriptor {
+                    aspect: wgpu::TextureAspect::DepthOnly,
+                    ..Default::default()
+                });
+                let channels = DebugChannels::Depth { znear: self.camera.znear, zfar: self.camera.zfar };
+                self.debug_views.queue(&self.device, &self.queue, &mut self.frame_arena, &view, rect, channels);
+            }
+            BuiltinDebugView::BrdfLut => {
+                if let Some(ibl) = self.ibl.as_ref().or(self.default_ibl.as_ref()) {
+                    // Scale in red, bias in green, square
+                    let side = rect.width.min(rect.height);
+                    let rect = ScreenRect::bottom_right(side, side, 8.0, self.config.width, self.config.height);
+                    self.debug_views.queue(&self.device, &self.queue, &mut self.frame_arena, &ibl.brdf_lut, rect, DebugChannels::Rgb);
+                }
+            }
+        }
+    }
+
+    // Draws `view` into `rect` of the screen over everything else, next frame only, so call it
+    // every frame between update and render. For looking at intermediate textures; depth needs a
+    // DepthOnly view and DebugChannels::Depth, multisampled textures can't be shown
+    pub fn debug_view_texture(&mut self, view: &wgpu::TextureView, rect: ScreenRect, channels: DebugChannels) {
+        self.debug_views.queue(&self.device, &self.queue, &mut self.frame_arena, view, rect, channels);
+    }
+
+    // Returns false (and keeps the current mode) if the surface doesn't support `mode`
+    pub fn set_present_mode(&mut self, mode: wgpu::PresentMode) -> bool {
+        if !self.surface.get_capabilities(&self.adapter).present_modes.contains(&mode) {
+            return false;
+        }
+        self.config.present_mode = mode;
+        self.surface.configure(&self.device, &self.config);
+        true
+    }
+
+    // Outlines instance `instance` with a solid color rim, `thickness` as a fraction of its size.
+    // Stays until clear_outline() or the next call
+    pub fn draw_outlined(&mut self, instance: usize, color: [f32; 4], thickness: f32) {
+        self.outline.target = Some(instance as u32);
+        self.outline.set_style(&self.queue, color, thickness);
+    }
+
+    pub fn clear_outline(&mut self) {
+        self.outline.target = None;
+    }
+
+    // Draws the mesh's triangle edges in `edge_color` over the shading, alpha blended. One pixel
+    // wide, the PBR material and per draw mode get them too
+    pub fn set_shaded_wireframe(&mut self, enabled: bool, edge_color: [f32; 4]) {
+        let scope = if enabled { WireframeScope::All } else { WireframeScope::Off };
+        self.set_wireframe_overlay(scope, edge_color, 1.0);
+    }
+
+    // Edges over the instances in `scope`. Selected follows the outline (draw_outlined) and shows
+    // nothing without one, so the picked instance's topology can be looked at on its own. `width`
+    // in pixels, the PolygonLine method is always 1 wide
+    pub fn set_wireframe_overlay(&mut self, scope: WireframeScope, color: [f32; 4], width: f32) {
+        self.wireframe_scope = scope;
+        self.wireframe.set_style(&self.queue, color, width);
+    }
+
+    pub fn wireframe_scope(&self) -> WireframeScope {
+        self.wireframe_scope
+    }
+
+    // Returns false and keeps the current method for PolygonLine on devices without
+    // WireframeRenderer::FEATURES
+    pub fn set_wireframe_method(&mut self, method: WireframeMethod) -> bool {
+        self.wireframe.set_method(method)
+    }
+
+    pub fn wireframe_method(&self) -> WireframeMethod {
+        self.wireframe.method()
+    }
+
+    // Radiance .hdr equirectangular map as the background and image based lighting source,
+    // see Texture::load_hdr and IblMaps
+    pub fn load_environment(&mut self, path: &std::path::Path) -> Result<(), String> {
+        let environment = Texture::load_hdr(&self.device, &self.queue, path)?;
+        self.set_environment(environment);
+        self.environment_path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    fn set_environment(&mut self, environment: Texture) {
+        self.replace_environment(&environment);
+        self.auto_exposure.reset();
+        self.reset_temporal_history();
+        self.update_title();
+    }
+
+    // For an environment that changes gradually (the day cycle's sky), exposure adaptation and
+    // temporal history carry on
+    fn replace_environment(&mut self, environment: &Texture) {
+        self.procedural_sky_params = None;
+        self.sky.set_environment(&self.device, environment);
+        self.ibl = Some(IblMaps::generate(&self.device, &self.queue, environment, &self.ibl_bind_group_layout));
+    }
+
+    fn render_procedural_sky(&mut self, params: &ProceduralSkyParams) -> Texture {
+        let device = &self.device;
+        self.procedural_sky.get_or_insert_with(|| ProceduralSky::new(device)).render(&self.device, &self.queue, params)
+    }
+
+    // Gradient sky with a sun disk as the environment, for the background and image based lighting
+    // like a loaded one, see procedural_sky.rs. The map and IBL are only regenerated when params
+    // differ from the last call, so this can be called every frame while they're being tweaked.
+    // Loading an environment replaces it, None goes back to no environment
+    pub fn set_procedural_sky(&mut self, params: Option<ProceduralSkyParams>) {
+        if params == self.procedural_sky_params {
+            return;
+        }
+        self.environment_path = None;
+        let Some(params) = params else {
+            self.procedural_sky_params = None;
+            self.sky.clear_environment();
+            self.ibl = None;
+            self.update_title();
+            return;
+        };
+        let environment = self.render_procedural_sky(&params);
+        self.set_environment(environment);
+        self.procedural_sky_params = Some(params);
+    }
+
+    // Day/night cycle: the time moves the sun and sets the light, the procedural sky (replacing
+    // any environment) and an exposure bias, see time_of_day.rs. None leaves the sky as it is and
+    // the light goes back to pbr::SUN_DIRECTION
+    pub fn set_time_of_day(&mut self, time_of_day: Option<TimeOfDay>) {
+        self.time_of_day = time_of_day;
+        if time_of_day.is_some() {
+            self.environment_path = None;
+        } else {
+            self.day_lighting = None;
+            self.camera_uniform.set_light(pbr::SUN_DIRECTION, pbr::SUN_COLOR, 1.0);
+        }
+    }
+
+    pub fn time_of_day(&mut self) -> Option<&mut TimeOfDay> {
+        self.time_of_day.as_mut()
+    }
+
+    // Jumps to `hours` (18.5 is 18:30), starting the cycle paused there if it's off
+    pub fn set_time_of_day_hours(&mut self, hours: f32) {
+        let time_of_day = self.time_of_day.get_or_insert(TimeOfDay { paused: true, ..TimeOfDay::default() });
+        time_of_day.set_hours(hours);
+    }
+
+    // Light and sky for this frame's time. The sky only changes every time_of_day::SKY_STEP
+    fn update_time_of_day(&mut self, dt: f32) {
+        let Some(time_of_day) = &mut self.time_of_day else {
+            return;
+        };
+        time_of_day.advance(dt);
+        let time_of_day = *time_of_day;
+        let lighting = time_of_day.lighting();
+        self.camera_uniform.set_light(lighting.light_direction.to_array(), lighting.light_color, lighting.ambient);
+        self.day_lighting = Some(lighting);
+        let sky = time_of_day.sky();
+        if self.procedural_sky_params != Some(sky) {
+            let environment = self.render_procedural_sky(&sky);
+            self.replace_environment(&environment);
+            self.procedural_sky_params = Some(sky);
+        }
+    }
+
+    pub fn procedural_sky(&self) -> Option<ProceduralSkyParams> {
+        self.procedural_sky_params
+    }
+
+    // Bind group layout is IblMaps::bind_group_layout. None until an environment is loaded
+    pub fn ibl(&self) -> Option<&IblMaps> {
+        self.ibl.as_ref()
+    }
+
+    // Textures left out default to white / flat, see PbrTextures. Err names the binding when a
+    // resource doesn't fit the material layout pbr.wgsl declares
+    pub fn create_pbr_material(&self, textures: PbrTextures, factors: PbrFactors) -> Result<PbrMaterial, String> {
+        PbrMaterial::new(&self.device, &self.queue, &self.pbr.material_bind_group_layout, textures, factors)
+    }
+
+    // Shades the mesh with Cook-Torrance and the environment's IBL maps instead of the vertex colors
+    // and layered texture. None goes back to that. Without an environment (load_environment) the
+    // ambient light is a flat grey
+    pub fn set_mesh_material(&mut self, material: Option<PbrMaterial>) {
+        if material.is_some() && self.default_ibl.is_none() {
+            let grey = Texture::from_rgba_f32(&self.device, &self.queue, &[0.5, 0.5, 0.5, 1.0], 1, 1, "Default Environment");
+            self.default_ibl = Some(IblMaps::generate(&self.device, &self.queue, &grey, &self.ibl_bind_group_layout));
+        }
+        self.mesh_material = material;
+        if self.wrong_color_space.is_some() {
+            if let Err(e) = self.set_color_space_split(true) {
+                self.report_error(Severity::Error, e);
+            }
+        }
+        self.update_title();
+    }
+
+    pub fn mesh_material(&mut self) -> Option<&mut PbrMaterial> {
+        self.mesh_material.as_mut()
+    }
+
+    // Debug view: the right half of every viewport samples the mesh textures in the wrong color
+    // space, sRGB colors as linear and linear data as sRGB. The same bytes, only the texture
+    // format differs, so whatever looks off on the right is what a mixup would do
+    pub fn set_color_space_split(&mut self, enabled: bool) -> Result<(), String> {
+        if !enabled {
+            self.wrong_color_space = None;
+            return Ok(());
+        }
+        let layered_texture = self.layered_texture.reinterpreted(&self.device, &self.queue, "Layered Texture Wrong Color Space");
+        let layered_texture_bind_group = layered_texture.create_bind_group(&self.device, &self.layered_texture_bind_group_layout);
+        let mesh_material = self.mesh_material.as_ref()
+            .map(|material| material.wrong_color_space(&self.device, &self.queue, &self.pbr.material_bind_group_layout))
+            .transpose()?;
+        self.wrong_color_space = Some(WrongColorSpace { layered_texture, layered_texture_bind_group, mesh_material });
+        Ok(())
+    }
+
+    // Replaces the mesh every instance draws, a triangle list. Returns how many degenerate
+    // triangles were dropped (see MeshOptions), Err when nothing drawable is left
+    pub fn set_mesh(&mut self, vertices: &[Vertex], options: MeshOptions) -> Result<usize, String> {
+        self.set_mesh_lods(&[vertices], options)
+    }
+
+    // set_mesh for a mesh that may lack colors, UVs or skinning. Missing attributes get defaults
+    // (see MeshData::vertices) and the scene pipelines switch to the shader variant without them.
+    // Err when the scene shader can't draw the mesh at all, see vertex_format::negotiate
+    pub fn set_mesh_data(&mut self, mesh: &MeshData, options: MeshOptions) -> Result<usize, String> {
+        let source = include_str!("shader.wgsl");
+        let mut switches = Vec::new();
+        for entry_point in ["vs_main", "vs_flat"] {
+            let format = ShaderVertexFormat::reflect("shader.wgsl", source, entry_point)?;
+            let negotiated = vertex_format::negotiate(mesh.attributes(), &format)?;
+            if !negotiated.filled.is_empty() {
+                log::info!("Mesh has no {}, {} gets defaults", negotiated.filled, entry_point);
+            }
+            switches = negotiated.switches;
+        }
+        let removed = self.set_mesh(&mesh.vertices()?, options)?;
+        if switches != self.shader_switches {
+            self.shader_switches = switches;
+            self.rebuild_pipelines();
+        }
+        Ok(removed)
+    }
+
+    // set_mesh with levels of detail, most detailed first (primitives::uv_sphere_lods makes some).
+    // Every instance draws the level that fits its size on screen, see set_lod_settings. Bounds,
+    // normals and picking use LOD 0
+    pub fn set_mesh_lods(&mut self, lods: &[&[Vertex]], options: MeshOptions) -> Result<usize, String> {
+        // Levels added to the same mesh (generate_lods_async) keep its frames. Compared before
+        // optimizing, that reorders the triangles
+        let same_mesh = self.mesh_surface.as_ref().zip(lods.first()).is_some_and(|(surface, lod0)| {
+            bytemuck::cast_slice::<Vertex, u8>(&surface.mesh()) == bytemuck::cast_slice::<Vertex, u8>(lod0)
+        });
+        if !same_mesh {
+            // Whoever set it knows where it's from, see load_primitive and apply_scene
+            self.mesh_source = None;
+        }
+        let mut vertices = Vec::new();
+        let mut levels = Vec::new();
+        let mut removed = 0;
+        for (level, lod) in lods.iter().enumerate() {
+            let mut lod = lod.to_vec();
+            if options.cull_degenerate {
+                removed += mesh::cull_degenerate_triangles(&mut lod, options.degenerate_epsilon);
+            }
+            // A partial triangle would shift the levels after it
+            lod.truncate(lod.len() / 3 * 3);
+            if lod.is_empty() {
+                return Err(format!("Mesh LOD {} has no triangles", level));
+            }
+            if options.optimize {
+                let (optimized, report) = optimize::optimize_triangle_list(&lod);
+                log::info!("Mesh LOD {} optimized: {} -> {}", level, report.before, report.after);
+                lod = optimized;
+            }
+            let start = vertices.len() as u32;
+            vertices.extend(lod);
+            levels.push(start..vertices.len() as u32);
+        }
+        if removed > 0 {
+            log::warn!("Removed {} degenerate triangle(s) from the mesh", removed);
+        }
+        let Some(lod0) = levels.first().cloned() else {
+            return Err("Mesh has no triangles".to_string());
+        };
+        let positions = vertices[lod0.start as usize..lod0.end as usize]
+            .iter()
+            .map(|v| Vec3::from(v.position))
+            .collect::<Vec<_>>();
+        let Some(aabb) = Aabb::from_points(positions.iter().copied()) else {
+            return Err("Mesh has no triangles".to_string());
+        };
+
+        if let Some(streamed_mesh) = &mut self.streamed_mesh {
+            streamed_mesh.set_vertices(&self.device, &vertices)?;
+        }
+        self.vertex_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            usage: wgpu::BufferUsages::VERTEX,
+            contents: bytemuck::cast_slice(&vertices),
+        });
+        self.mesh_vertices = vertices;
+        self.vertex_count = lod0.end;
+        self.lod.set_levels(levels, &aabb);
+        self.mesh_positions = positions;
+        self.mesh_aabb = aabb;
+        self.update_instance_bounds();
+        if self.impostor.is_baked() {
+            self.bake_impostors();
+            if let Some(start) = self.lod.settings.impostor_distance {
+                self.impostor.set_fade(&self.queue, start, self.lod.settings.impostor_fade);
+            }
+        }
+        self.auto_exposure.reset();
+        self.reset_temporal_history();
+        self.update_title();
+        // Normal lines follow the new mesh, with the same length
+        if let Some(&(start, end)) = self.normal_segments.first() {
+            self.set_show_normals(true, (end - start).length());
+        }
+        if !same_mesh {
+            self.mesh_surface = None;
+        }
+        self.refresh_tangent_frames();
+        Ok(removed)
+    }
+
+    // Options for the meshes State sets by itself: scene meshes, levels of detail from the loader
+    // thread and the RunOptions ones. Calls to set_mesh bring their own
+    pub fn set_mesh_options(&mut self, options: MeshOptions) {
+        self.mesh_options = options;
+    }
+
+    // set_mesh for a generated surface (see primitives), keeping its exact normals and tangents for
+    // set_show_tangent_frames
+    pub fn set_surface_mesh(&mut self, surface: &Surface, options: MeshOptions) -> Result<usize, String> {
+        let removed = self.set_mesh(&surface.mesh(), options)?;
+        // Culled triangles would put the frames on the wrong vertices
+        if removed == 0 {
+            self.mesh_surface = Some(surface.clone());
+            self.refresh_tangent_frames();
+        }
+        Ok(removed)
+    }
+
+    // Draws the mesh from one vertex buffer per stream instead of the interleaved one, e.g.
+    // VertexStreams::split_off(&Vertex::desc(), &[0]) for positions on their own. Only the
+    // regular scene pipelines read the streams. PBR, per draw, wireframe, outline and the offscreen
+    // passes keep the interleaved buffer. None goes back. Err when the streams don't place every
+    // Vertex attribute exactly once, in its format or a float one it packs into
+    pub fn set_vertex_streams(&mut self, streams: Option<VertexStreams>) -> Result<(), String> {
+        self.streamed_mesh = match streams {
+            Some(streams) => Some(StreamedMesh::new(
+                &self.device,
+                streams,
+                &self.mesh_vertices,
+                &self.render_pipeline_layout,
+                &self.shader,
+                &self.pipeline_config,
+            )?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    // Scene pipelines read the mesh in vertex_streams::PACKED_VERTEX_FORMATS, whatever the adapter
+    // supports of them (see VertexStreams::packed). Keeps the current grouping into streams, or
+    // packs one interleaved buffer. Off goes back to full precision, for A/B screenshots. The
+    // title shows how much smaller the packed vertices are
+    pub fn set_packed_vertices(&mut self, packed: bool) -> Result<(), String> {
+        let full = vertex_streams::attributes(&Vertex::desc());
+        let unpacked = self.vertex_streams().map_or_else(|| VertexStreams::interleaved(&full), |streams| streams.with_formats(&full));
+        let streams = if packed {
+            Some(unpacked.packed(&self.device))
+        } else if unpacked == VertexStreams::interleaved(&full) {
+            None
+        } else {
+            Some(unpacked)
+        };
+        let result = self.set_vertex_streams(streams);
+        self.update_title();
+        result
+    }
+
+    // Bytes the mesh's vertex streams save over full precision vertices, 0 unless packed
+    fn packed_vertex_savings(&self) -> u64 {
+        let full = std::mem::size_of::<Vertex>() as u64;
+        self.vertex_streams().map_or(0, |streams| full.saturating_sub(streams.vertex_size()) * self.mesh_vertices.len() as u64)
+    }
+
+    pub fn vertex_streams(&self) -> Option<&VertexStreams> {
+        self.streamed_mesh.as_ref().map(|streamed_mesh| &streamed_mesh.streams)
+    }
+
+    // Vertex buffer of points for draw_points
+    pub fn create_point_buffer(&self, points: &[PointVertex]) -> std::sync::Arc<wgpu::Buffer> {
+        PointRenderer::create_buffer(&self.device, points)
+    }
+
+    // Draws the first `count` points of `buffer` as round sprites `size` pixels across that always
+    // face the camera, every frame until clear_points. Points with a size of their own keep it.
+    // `buffer` needs VERTEX usage and PointVertex layout, create_point_buffer makes one. The size is
+    // for the whole surface, split screen views get the same pixel size stretched to their width
+    pub fn draw_points(&mut self, buffer: std::sync::Arc<wgpu::Buffer>, count: u32, size: f32) {
+        self.points.add(buffer, count, size);
+    }
+
+    pub fn clear_points(&mut self) {
+        self.points.clear();
+        self.point_cloud_paths.clear();
+    }
+
+    // PNG texture, read and decoded without blocking. It's uploaded by poll_loaded, which hands
+    // it out as LoadedAsset::Texture. On the web `path` is a URL. Like the other files loaded here
+    // it's reloaded when it changes, see reload_assets
+    pub fn load_texture_async(&mut self, path: &std::path::Path, color_space: ColorSpace) -> LoadHandle {
+        self.load_watched(AssetKind::Texture(color_space), path)
+    }
+
+    // load_environment without blocking, poll_loaded switches to it once it's decoded
+    pub fn load_environment_async(&mut self, path: &std::path::Path) -> LoadHandle {
+        let handle = self.load_watched(AssetKind::Environment, path);
+        self.environment_path = Some(path.to_path_buf());
+        handle
+    }
+
+    // points::load_xyz without blocking, poll_loaded hands out a buffer for draw_points
+    pub fn load_points_async(&mut self, path: &std::path::Path) -> LoadHandle {
+        let handle = self.load_watched(AssetKind::PointCloud, path);
+        self.point_cloud_paths.push(path.to_path_buf());
+        handle
+    }
+
+    fn load_watched(&mut self, kind: AssetKind, path: &std::path::Path) -> LoadHandle {
+        let handle = self.loader.load(kind, path);
+        self.asset_watcher.watch(handle, kind, path);
+        self.update_title();
+        handle
+    }
+
+    // Called after a changed file was reloaded, with what it's on the GPU now (see reload_assets).
+    // For demos that derive something from an asset. Replaces the previous callback
+    pub fn on_asset_reloaded(&mut self, callback: impl FnMut(&mut State, LoadHandle, &LoadedAsset) + 'static) {
+        self.asset_reloaded = Some(Box::new(callback));
+    }
+
+    // Loads textures, environments and point clouds loaded through this State again when their
+    // file is saved, under the same handle. What's already out there is patched in place: the same
+    // texture and point buffer get the new data, so materials and draws show it without being set
+    // up again. Only a texture that changed size (or a point cloud that changed count) can't be, it
+    // comes to on_asset_reloaded as a new one and point draws switch to it. A file that doesn't
+    // decode, like one an editor is halfway through saving, keeps the old asset with a warning and
+    // is tried again on its next change. Assets whoever held them dropped aren't watched anymore
+    fn reload_assets(&mut self) {
+        for (handle, kind, path) in self.asset_watcher.changed() {
+            // Another environment replaced it since
+            if kind == AssetKind::Environment && self.environment_path.as_deref() != Some(path.as_path()) {
+                self.asset_watcher.unwatch(handle);
+                continue;
+            }
+            self.loader.reload(handle, kind, &path);
+        }
+    }
+
+    fn apply_reload(&mut self, handle: LoadHandle, old: WatchedAsset, result: Result<LoadedData, String>) {
+        let path = self.asset_watcher.path(handle).map(std::path::Path::to_path_buf).unwrap_or_default();
+        let asset = result.and_then(|data| match (data, old) {
+            (LoadedData::Texture { rgba, width, height, color_space }, WatchedAsset::Texture(old)) => {
+                let new = std::sync::Arc::new(self.upload_texture(handle, &rgba, width, height, color_space));
+                let old = old.upgrade().filter(|old| old.texture.size() == new.texture.size() && old.texture.format() == new.texture.format());
+                let Some(old) = old else {
+                    return Ok(LoadedAsset::Texture(new));
+                };
+                let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Texture Reload Encoder") });
+                encoder.copy_texture_to_texture(new.texture.as_image_copy(), old.texture.as_image_copy(), old.texture.size());
+                self.queue.submit(std::iter::once(encoder.finish()));
+                Ok(LoadedAsset::Texture(old))
+            }
+            (LoadedData::PointCloud(points), WatchedAsset::PointCloud(old)) => {
+                let bytes = bytemuck::cast_slice::<PointVertex, u8>(&points);
+                let count = points.len() as u32;
+                match old.upgrade() {
+                    Some(old) if old.size() == bytes.len() as u64 => {
+                        self.queue.write_buffer(&old, 0, bytes);
+                        Ok(LoadedAsset::PointCloud { buffer: old, count })
+                    }
+                    old => {
+                        let buffer = self.create_point_buffer(&points);
+                        if let Some(old) = old {
+                            self.points.replace(&old, buffer.clone(), count);
+                        }
+                        Ok(LoadedAsset::PointCloud { buffer, count })
+                    }
+                }
+            }
+            // The environment is State's own, replacing it is all there is to do
+            (data, _) => self.upload_loaded(handle, data),
+        });
+        match asset {
+            Ok(asset) => {
+                self.asset_watcher.finished(handle, WatchedAsset::of(&asset));
+                self.report_error(Severity::Info, format!("Reloaded {}", path.display()));
+                if let Some(mut callback) = self.asset_reloaded.take() {
+                    callback(self, handle, &asset);
+                    // Unless the callback set another one
+                    self.asset_reloaded.get_or_insert(callback);
+                }
+            }
+            Err(e) => {
+                self.asset_watcher.finished(handle, None);
+                self.report_error(Severity::Warning, format!("{} not reloaded, keeping what was loaded before: {}", path.display(), e));
+            }
+        }
+    }
+
+    // Simplifies `vertices` into levels of detail on the loader thread (see simplify::lod_chain),
+    // poll_loaded makes them the mesh. With a `source` the chain is cached next to that file and
+    // read back the next time the same vertices come with the same options
+    pub fn generate_lods_async(&mut self, vertices: Vec<Vertex>, source: Option<std::path::PathBuf>, options: LodChainOptions) -> LoadHandle {
+        let handle = self.loader.generate(move || {
+            let lods = match &source {
+                Some(source) => simplify::lod_chain_cached(source, &vertices, &options),
+                None => simplify::lod_chain(&vertices, &options),
+            };
+            Ok(LoadedData::MeshLods(lods))
+        });
+        self.update_title();
+        handle
+    }
+
+    // None for handles that didn't come from this State
+    pub fn load_state(&self, handle: LoadHandle) -> Option<LoadState> {
+        self.loader.state(handle)
+    }
+
+    // Uploads whatever finished loading since the last call. Cheap when nothing did, call it once
+    // a frame. Failures are reported to the error log as well
+    pub fn poll_loaded(&mut self) -> Vec<(LoadHandle, Result<LoadedAsset, String>)> {
+        let loaded = self.loader.poll();
+        if loaded.is_empty() {
+            return Vec::new();
+        }
+        let mut assets = Vec::with_capacity(loaded.len());
+        for (handle, result) in loaded {
+            // Reloads patch what's out there already, they aren't handed out again
+            if let Some(old) = self.asset_watcher.asset(handle).cloned() {
+                self.apply_reload(handle, old, result);
+                continue;
+            }
+            let asset = result.and_then(|data| self.upload_loaded(handle, data));
+            match &asset {
+                Ok(asset) => self.asset_watcher.finished(handle, WatchedAsset::of(asset)),
+                Err(e) => {
+                    self.asset_watcher.finished(handle, None);
+                    self.report_error(Severity::Error, e.clone());
+                }
+            }
+            assets.push((handle, asset));
+        }
+        self.update_title();
+        assets
+    }
+
+    fn upload_texture(&self, handle: LoadHandle, rgba: &[u8], width: u32, height: u32, color_space: ColorSpace) -> Texture {
+        let device_limit = self.device.limits().max_texture_dimension_2d;
+        let max_dimension = self.max_texture_size.map_or(device_limit, |max| max.min(device_limit));
+        Texture::from_rgba_limited(&self.device, &self.queue, rgba, width, height, color_space, max_dimension, &format!("Loaded {:?}", handle))
+    }
+
+    fn upload_loaded(&mut self, handle: LoadHandle, data: LoadedData) -> Result<LoadedAsset, String> {
+        let label = format!("Loaded {:?}", handle);
+        match data {
+            LoadedData::Texture { rgba, width, height, color_space } => {
+                Ok(LoadedAsset::Texture(std::sync::Arc::new(self.upload_texture(handle, &rgba, width, height, color_space))))
+            }
+            LoadedData::Environment { rgba, width, height } => {
+                let environment = Texture::from_hdr_rgba(&self.device, &self.queue, &rgba, width, height, &label)?;
+                self.set_environment(environment);
+                Ok(LoadedAsset::Environment)
+            }
+            LoadedData::PointCloud(points) => Ok(LoadedAsset::PointCloud {
+                buffer: self.create_point_buffer(&points),
+                count: points.len() as u32,
+            }),
+            LoadedData::MeshLods(lods) => {
+                let lods = lods.iter().map(Vec::as_slice).collect::<Vec<_>>();
+                self.set_mesh_lods(&lods, self.mesh_options)?;
+                Ok(LoadedAsset::MeshLods { triangles: lods.iter().map(|lod| lod.len() / 3).collect() })
+            }
+            // Only ChunkStreamer's own loader makes these
+            LoadedData::Chunk(_) => Err(format!("{:?} is a terrain chunk, not an asset", handle)),
+        }
+    }
+
+    // Draws the mesh's face normals as lines `length` long (object space units), colored by direction
+    pub fn set_show_normals(&mut self, show: bool, length: f32) {
+        self.normal_segments = if show {
+            debug_lines::normal_segments(&self.mesh_positions, length)
+        } else {
+            Vec::new()
+        };
+    }
+
+    // Grid on the y = 0 plane out to the horizon, antialiased and fading with distance. Occluded by
+    // the scene, drawn over anything below the ground
+    pub fn set_infinite_grid(&mut self, enabled: bool, params: GridParams) {
+        self.grid.set(&self.queue, enabled, params);
+    }
+
+    // Debug lines (boxes, frustums, normals) are this many pixels across, antialiased. 1 by default
+    pub fn set_line_width(&mut self, px: f32) {
+        self.line_batch.set_line_width(px);
+    }
+
+    // Draws every vertex's tangent (red), bitangent (green) and normal (blue) as lines `length` long
+    // (object space units). Exact for set_surface_mesh, flat per triangle from the winding and UVs
+    // for any other mesh, the frames shading works with either way
+    pub fn set_show_tangent_frames(&mut self, show: bool, length: f32) {
+        self.frame_segments = if show {
+            let derived;
+            let surface = match &self.mesh_surface {
+                Some(surface) => surface,
+                None => {
+                    derived = Surface::from_mesh(&self.mesh_vertices[..self.vertex_count as usize]);
+                    &derived
+                }
+            };
+            debug_lines::frame_segments(&surface.vertices, length)
+        } else {
+            Vec::new()
+        };
+    }
+
+    // Frame lines follow the mesh, with the same length
+    fn refresh_tangent_frames(&mut self) {
+        if let Some(&(start, end, _)) = self.frame_segments.first() {
+            self.set_show_tangent_frames(true, (end - start).length());
+        }
+    }
+
+    // Outlines expect the stencil cleared to something else than 1
+    pub fn set_stencil_clear(&mut self, value: u32) {
+        self.stencil_clear = value;
+    }
+
+    // Where depth starts each frame, 0 (near) to 1 (far). Anything further than it isn't drawn.
+    // Depth isn't reversed anywhere, every pipeline tests with Less (see PipelineConfig::depth_state)
+    pub fn set_clear_depth(&mut self, value: f32) {
+        self.clear_depth = value.clamp(0.0, 1.0);
+    }
+
+    pub fn clear_depth(&self) -> f32 {
+        self.clear_depth
+    }
+
+    // Poses the skinned mesh, see BoneBuffer. Missing bones are identity
+    pub fn set_bone_matrices(&mut self, bones: &[Mat4]) {
+        self.bones.write(&self.queue, bones);
+    }
+
+    // Calls `callback(frame_index, pixels, bytes_per_row)` for every presented frame, a few frames
+    // after it was rendered and without stalling the render loop. Pixels are in the surface
+    // format. Returns false if the surface can't be copied from on this platform
+    pub fn stream_frames(&mut self, ring_size: usize, callback: impl FnMut(u64, &[u8], u32) + 'static) -> bool {
+        if !self.enable_surface_readback() {
+            return false;
+        }
+        self.frame_stream = Some(FrameStream::new(
+            &self.device,
+            self.config.width,
+            self.config.height,
+            ring_size,
+            Box::new(callback),
+        ));
+        true
+    }
+
+    // The surface stays readable, screenshots and read_pixel may still be waiting for a frame
+    pub fn stop_streaming_frames(&mut self) {
+        self.frame_stream = None;
+    }
+
+    // Saves the next presented frame as PNG, as it looks in the window (see
+    // readback::read_texture_displayed). Returns false if the surface can't be read back, and
+    // always on the web, where render() can't wait for the copy (see readback::map_read)
+    pub fn save_screenshot(&mut self, path: impl Into<std::path::PathBuf>) -> bool {
+        if cfg!(target_arch = "wasm32") || !self.enable_surface_readback() {
+            return false;
+        }
+        self.screenshot_path = Some(path.into());
+        true
+    }
+
+    // Reads pixel (x, y) of the next presented frame, converted like save_screenshot so the two
+    // agree. The result shows up in take_read_pixel() after that frame. Returns false if the
+    // surface can't be read back, or on the web like save_screenshot
+    pub fn read_pixel(&mut self, x: u32, y: u32) -> bool {
+        if cfg!(target_arch = "wasm32") || !self.enable_surface_readback() {
+            return false;
+        }
+        self.pixel_request = Some((x, y));
+        self.read_pixel = None;
+        true
+    }
+
+    pub fn take_read_pixel(&mut self) -> Option<Result<[u8; 4], String>> {
+        self.read_pixel.take()
+    }
+
+    // Once on, COPY_SRC stays on. Turning it off would have to know that no screenshot, pixel read
+    // or stream still needs it, and the cost of keeping it is small
+    fn enable_surface_readback(&mut self) -> bool {
+        if !self.config.usage.contains(wgpu::TextureUsages::COPY_SRC) {
+            let caps = self.surface.get_capabilities(&self.adapter);
+            if !caps.usages.contains(wgpu::TextureUsages::COPY_SRC) {
+                return false;
+            }
+            self.config.usage |= wgpu::TextureUsages::COPY_SRC;
+            self.surface.configure(&self.device, &self.config);
+        }
+        true
+    }
+
+    // Records performed actions with the frame times, and the keyboard and mouse window events with
+    // their time, so play_input_recording can replay the session either way (see
+    // input_record::Timing). Replays start from whatever state the app is in, so record from startup
+    pub fn start_input_recording(&mut self, path: &std::path::Path) -> std::io::Result<()> {
+        self.input_recorder = Some(InputRecorder::create(path, self.seed)?);
+        Ok(())
+    }
+
+    pub fn stop_input_recording(&mut self) -> std::io::Result<()> {
+        match self.input_recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // Replays a recording, real keyboard and mouse input is ignored until it's done. With
+    // `screenshot` the last played frame is saved there
+    pub fn play_input_recording(
+        &mut self,
+        path: &std::path::Path,
+        timing: Timing,
+        screenshot: Option<std::path::PathBuf>,
+    ) -> Result<(), String> {
+        let mut playback = InputPlayback::load(path, timing, &self.input_map)?;
+        playback.screenshot = screenshot;
+        if let Some(seed) = playback.seed {
+            self.reseed(seed);
+        }
+        self.input_playback = Some(playback);
+        Ok(())
+    }
+
+    // Polls the device on a thread of its own from now on, so buffer map callbacks fire without
+    // anything in the frame polling. Native only, on the web it does nothing (the browser resolves
+    // mappings). Calling it again keeps the running thread
+    pub fn spawn_poll_thread(&mut self) -> std::io::Result<()> {
+        if self.poll_thread.is_none() {
+            self.poll_thread = Some(PollThread::spawn(self.device.clone(), poll_thread::DEFAULT_POLL_INTERVAL)?);
+        }
+        Ok(())
+    }
+
+    // Takes remote control commands from scripts over TCP from now on, see remote.rs. They run at
+    // the start of every update. Returns where it listens, replacing a server already running.
+    // Native only
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_remote(&mut self, options: &remote::RemoteOptions) -> std::io::Result<std::net::SocketAddr> {
+        // The old one has to let go of the port first
+        self.remote = None;
+        let server = remote::RemoteServer::bind(options)?;
+        let address = server.address();
+        self.remote = Some(server);
+        Ok(address)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn run_remote_commands(&mut self) {
+        use json::Json;
+        use remote::Command;
+
+        let Some(server) = &self.remote else {
+            return;
+        };
+        for remote::Request { command, reply } in server.requests() {
+            let result = match command {
+                Command::SetClearColor([r, g, b]) => {
+                    self.set_clear_color(wgpu::Color { r: r as f64, g: g as f64, b: b as f64, a: 1.0 });
+                    Ok(())
+                }
+                Command::LoadScene(path) => self.load_scene(&path),
+                Command::SaveScene(path) => self.save_scene(&path),
+                Command::LoadModel(name) => self.load_primitive(&name),
+                Command::MoveCamera { eye, target: None } => {
+                    self.teleport(eye);
+                    Ok(())
+                }
+                Command::MoveCamera { eye, target: Some(target) } if (target - eye).length() > 0.0 => {
+                    self.camera.set_pose(CameraPose { eye, target, up: self.camera.up });
+                    Ok(())
+                }
+                Command::MoveCamera { .. } => Err("Camera can't look at its own eye".to_string()),
+                Command::Screenshot => {
+                    if self.enable_surface_readback() {
+                        self.remote_screenshots.push(reply);
+                        continue;
+                    }
+                    Err("Surface can't be read back".to_string())
+                }
+                Command::FrameStats => {
+                    let stats = self.lod_stats();
+                    let number = |n: f64| Json::Number(n);
+                    let members = [
+                        ("frame_time", number(self.frame_dt as f64)),
+                        ("fps", self.fps.map_or(Json::Null, |fps| number(fps as f64))),
+                        ("instances", number(stats.instances.iter().sum::<usize>() as f64)),
+                        ("triangles", number(stats.triangles as f64)),
+                        ("culled", number(stats.culled as f64)),
+                        ("impostors", number(stats.impostors as f64)),
+                    ];
+                    reply.ok(Json::Object(members.into_iter().map(|(key, value)| (key.to_string(), value)).collect()));
+                    continue;
+                }
+            };
+            match result {
+                Ok(()) => reply.ok(Json::Null),
+                Err(e) => reply.error(e),
+            }
+        }
+    }
+
+    pub fn is_playing_input(&self) -> bool {
+        self.input_playback.is_some()
+    }
+
+    fn record_event(&mut self, event: &WindowEvent) {
+        if let Some(recorder) = &mut self.input_recorder {
+            recorder.event(event);
+        }
+    }
+
+    // Frame exact playback performs the recorded actions itself, window input plays no part
+    fn plays_frames(&self) -> bool {
+        self.input_playback.as_ref().is_some_and(|playback| playback.timing == Timing::FrameExact)
+    }
+
+    // Window events of a real time playback that are due, see play_input_recording
+    fn due_recorded_events(&mut self) -> Vec<WindowEvent<'static>> {
+        let Some(playback) = self.input_playback.as_mut().filter(|playback| playback.timing == Timing::RealTime) else {
+            return Vec::new();
+        };
+        let events = playback.due_events();
+        if playback.is_finished() {
+            if let Some(path) = playback.screenshot.take() {
+                if !self.save_screenshot(path) {
+                    self.report_error(Severity::Warning, "Surface can't be read back, no playback screenshot");
+                }
+            }
+            self.input_playback = None;
+            log::info!("Input recording played");
+        }
+        events
+    }
+
+    // Snaps to the new projection, every view (split screen too) follows the main camera's. There's
+    // no tween helper to animate the switch with yet
+    pub fn set_projection(&mut self, projection: Projection) {
+        if self.camera.projection == projection {
+            return;
+        }
+        self.camera.projection = projection;
+        // Everything on screen moves at once, nothing to reproject from
+        self.reset_temporal_history();
+        self.update_title();
+    }
+
+    // Writes the instances grouped by LOD (LodSelector::order), tinted by it with show_lod_colors
+    fn upload_instances(&mut self) {
+        let instance_data = self.lod.order()
+            .iter()
+            .map(|&i| {
+                let mut raw = self.instances[i as usize].to_raw();
+                if self.show_lod_colors {
+                    // Impostors have a color of their own, any level will do
+                    raw.lod_tint = self.lod.level_of(i as usize) as u32;
+                }
+                raw
+            })
+            .collect::<Vec<_>>();
+        self.instance_buffer.write(&self.device, &self.queue, bytemuck::cast_slice(&instance_data));
+    }
+
+    // Screen size thresholds for the mesh's levels of detail and the impostor distance, see
+    // LodSettings. Impostors are baked the first time they're turned on
+    pub fn set_lod_settings(&mut self, settings: LodSettings) {
+        if let Some(start) = settings.impostor_distance {
+            if !self.impostor.is_baked() {
+                self.bake_impostors();
+            }
+            self.impostor.set_fade(&self.queue, start, settings.impostor_fade);
+        }
+        self.lod.settings = settings;
+    }
+
+    // Renders the impostor atlas from the current mesh (LOD 0) and texture layers
+    fn bake_impostors(&mut self) {
+        let source = ImpostorSource {
+            layout: &self.render_pipeline_layout,
+            shader: &self.shader,
+            camera_bind_group_layout: &self.camera_bind_group_layout,
+            layered_texture_bind_group: &self.layered_texture_bind_group,
+            bones_bind_group: &self.bones.bind_group,
+            vertex_buffer: &self.vertex_buffer,
+            vertices: self.lod.vertices(0),
+            bounds: &self.mesh_aabb,
+            layers: self.layered_texture.count(),
+        };
+        self.impostor.bake(&self.device, &self.queue, &self.pipeline_config, &source);
+    }
+
+    // Shows the primitive called `name` (see primitives::by_name) as the mesh, with simplified
+    // levels of detail following from the loader thread
+    pub fn load_primitive(&mut self, name: &str) -> Result<(), String> {
+        let surface = primitives::by_name(name)
+            .ok_or_else(|| format!("No primitive called {:?}, there are {}", name, primitives::NAMES.join(", ")))?;
+        self.set_surface_mesh(&surface, self.mesh_options)?;
+        self.generate_lods_async(surface.mesh(), None, LodChainOptions::default());
+        self.mesh_source = Some(SceneMesh::Primitive(name.to_string()));
+        Ok(())
+    }
+
+    pub fn lod_stats(&self) -> lod::LodStats {
+        self.lod.stats()
+    }
+
+    // Tints every instance by the level of detail it draws (green LOD 0, then yellow, orange, red,
+    // purple) and shows the counts and triangles saved at the bottom left. The PBR material and
+    // per draw mode aren't tinted
+    pub fn set_show_lod_colors(&mut self, show: bool) {
+        self.show_lod_colors = show;
+        self.upload_instances();
+    }
+
+    // Linear, like the rest of the scene
+    pub fn set_clear_color(&mut self, color: wgpu::Color) {
+        self.clear_color = color;
+    }
+
+    pub fn clear_color(&self) -> wgpu::Color {
+        self.clear_color
+    }
+
+    // Sets up what a scene file (see scene::Scene) describes. The file is checked first, so on Err
+    // nothing changed. Assets it points to that aren't there get a placeholder and a warning
+    // instead: the default procedural sky for the environment, the triangle for an unknown
+    // primitive, nothing for a point cloud. Saving the scene again keeps the references. The
+    // environment and point clouds load in the background like load_environment_async /
+    // load_points_async, previous point clouds are cleared. The file is watched from then on (even
+    // if this one failed) and reloaded when it's saved, see reload_scene
+    pub fn load_scene(&mut self, path: &std::path::Path) -> Result<(), String> {
+        self.scene_watcher = Some(FileWatcher::new(path));
+        self.scene = None;
+        let scene = Scene::load(path)?;
+        self.apply_scene(scene)
+    }
+
+    // What load_scene would set up the app as it is now, see save_scene
+    pub fn scene(&self) -> Scene {
+        let camera = &self.camera;
+        let instances = self.instances.iter().map(SceneInstance::from_instance).collect();
+        // Only one of them can be in a scene, the day cycle makes the sky and that replaces any
+        // environment
+        let sky = self.procedural_sky_params.filter(|_| self.time_of_day.is_none() && self.environment_path.is_none());
+        Scene {
+            clear_color: Some([self.clear_color.r as f32, self.clear_color.g as f32, self.clear_color.b as f32]),
+            camera: Some(SceneCamera {
+                eye: camera.eye,
+                target: camera.target,
+                fovy: Some(camera.fovy),
+                znear: Some(camera.znear),
+                zfar: Some(camera.zfar),
+                projection: Some(camera.projection),
+            }),
+            environment: self.environment_path.clone().filter(|_| self.time_of_day.is_none()),
+            sky,
+            time_of_day: self.time_of_day,
+            fog: self.fog_enabled.then_some(self.fog),
+            mesh: self.mesh_source.clone(),
+            instances: Some(instances),
+            point_clouds: self.point_cloud_paths.clone(),
+        }
+    }
+
+    // Writes scene() to a JSON file load_scene reads back the same. Meshes that didn't come from a
+    // scene or load_primitive aren't saved, loading leaves the mesh as it is then
+    pub fn save_scene(&self, path: &std::path::Path) -> Result<(), String> {
+        self.scene().save(path)
+    }
+
+    // Picks up edits to the watched scene file. Only what changed in the file since the last
+    // successful load is applied, so a camera flown somewhere else stays there unless the file's
+    // camera is edited. A broken file leaves everything as it is, with an error banner until it's
+    // fixed
+    fn reload_scene(&mut self) {
+        let Some(watcher) = &mut self.scene_watcher else {
+            return;
+        };
+        if !watcher.poll() {
+            return;
+        }
+        let path = watcher.path().to_path_buf();
+        match Scene::load(&path).and_then(|scene| self.apply_scene(scene)) {
+            Ok(()) => {
+                self.resolve_error("scene");
+                self.report_error(Severity::Info, format!("Reloaded {}", path.display()));
+            }
+            Err(e) => self.report_sticky_error("scene", Severity::Error, format!("Scene not reloaded: {}", e)),
+        }
+    }
+
+    // Fields that are the same as in the previously applied scene are skipped
+    fn apply_scene(&mut self, scene: Scene) -> Result<(), String> {
+        let previous = self.scene.clone().unwrap_or_default();
+        if let Some(mesh) = scene::changed(&scene.mesh, &previous.mesh) {
+            match mesh {
+                SceneMesh::Triangle => {
+                    self.set_mesh(VERTICIES, self.mesh_options)?;
+                }
+                SceneMesh::Sphere => {
+                    let lods = primitives::uv_sphere_lods(0.5, 4);
+                    let lods = lods.iter().map(Vec::as_slice).collect::<Vec<_>>();
+                    self.set_mesh_lods(&lods, self.mesh_options)?;
+                }
+                SceneMesh::Primitive(name) => {
+                    if let Err(e) = self.load_primitive(name) {
+                        self.report_error(Severity::Warning, format!("{}, showing the triangle instead", e));
+                        self.set_mesh(VERTICIES, self.mesh_options)?;
+                    }
+                }
+            }
+            self.mesh_source = Some(mesh.clone());
+        }
+        if let Some(&[r, g, b]) = scene::changed(&scene.clear_color, &previous.clear_color) {
+            self.clear_color = wgpu::Color { r: r as f64, g: g as f64, b: b as f64, a: 1.0 };
+        }
+        if let Some(camera) = scene::changed(&scene.camera, &previous.camera) {
+            self.camera.eye = camera.eye;
+            self.camera.target = camera.target;
+            self.camera.fovy = camera.fovy.unwrap_or(self.camera.fovy);
+            self.camera.znear = camera.znear.unwrap_or(self.camera.znear);
+            self.camera.zfar = camera.zfar.unwrap_or(self.camera.zfar);
+            if let Some(projection) = camera.projection {
+                self.set_projection(projection);
+            }
+            // Jumped, nothing on screen lines up with last frame
+            self.reset_temporal_history();
+        }
+        if let Some(&fog) = scene::changed(&scene.fog, &previous.fog) {
+            self.set_fog(fog);
+            self.set_fog_enabled(true);
+        }
+        if let Some(instances) = scene::changed(&scene.instances, &previous.instances) {
+            let instances = instances
+                .iter()
+                .map(|instance| Instance { position: instance.position, rotation: instance.rotation_matrix(), layer: instance.layer })
+                .collect();
+            self.set_instances(instances);
+        }
+        if let Some(environment) = scene::changed(&scene.environment, &previous.environment) {
+            if Self::asset_exists(environment) {
+                self.load_environment_async(environment);
+            } else {
+                self.report_error(Severity::Warning, format!("{} doesn't exist, showing the default sky instead", environment.display()));
+                self.set_procedural_sky(Some(ProceduralSkyParams::default()));
+                self.environment_path = Some(environment.clone());
+            }
+        }
+        if let Some(&sky) = scene::changed(&scene.sky, &previous.sky) {
+            self.set_procedural_sky(Some(sky));
+        }
+        if let Some(&time_of_day) = scene::changed(&scene.time_of_day, &previous.time_of_day) {
+            self.set_time_of_day(Some(time_of_day));
+        }
+        if !scene.point_clouds.is_empty() && scene.point_clouds != previous.point_clouds {
+            self.clear_points();
+            for path in &scene.point_clouds {
+                if Self::asset_exists(path) {
+                    self.load_points_async(path);
+                } else {
+                    self.report_error(Severity::Warning, format!("{} doesn't exist, point cloud left out", path.display()));
+                    self.point_cloud_paths.push(path.clone());
+                }
+            }
+        }
+        self.scene = Some(scene);
+        Ok(())
+    }
+
+    // Files can't be checked for up front on the web, failed fetches end up in the error log
+    fn asset_exists(path: &std::path::Path) -> bool {
+        cfg!(target_arch = "wasm32") || path.is_file()
+    }
+
+    // Streams procedural terrain in around the camera, None drops it (buffers go through the
+    // deferred destruction queue like chunks leaving the radius do). The terrain comes from the
+    // seed at the time it's turned on
+    pub fn set_streaming(&mut self, settings: Option<StreamingSettings>) {
+        if let Some(streaming) = self.streaming.take() {
+            streaming.retire_all(&mut self.deferred_destruction);
+        }
+        self.streaming = settings.map(|settings| {
+            let seed = (self.seed ^ self.seed >> 32) as u32;
+            ChunkStreamer::new(&self.device, &self.pipeline_config, &self.camera_bind_group_layout, settings, seed)
+        });
+    }
+
+    pub fn streaming_stats(&self) -> Option<StreamingStats> {
+        self.streaming.as_ref().map(ChunkStreamer::stats)
+    }
+
+    // Pose reset_camera (Home) returns to. None, the default, frames whatever instances there are
+    pub fn set_home_camera(&mut self, pose: Option<CameraPose>) {
+        self.home_camera = pose;
+    }
+
+    // Back to the home pose, or looking at all instances from where the camera started when there
+    // is none. With the rig's smoothing on the camera eases there, otherwise it jumps
+    pub fn reset_camera(&mut self) {
+        let bounds = self.instance_aabbs.iter().copied().reduce(|a, b| a.including(b.min).including(b.max));
+        let pose = match (self.home_camera, bounds) {
+            (Some(home), _) => home,
+            (None, Some(bounds)) => {
+                let direction = self.initial_camera.eye - self.initial_camera.target;
+                CameraPose::framing(&bounds, direction, self.camera.fovy, self.camera.aspect)
+            }
+            (None, None) => self.initial_camera,
+        };
+        self.camera.set_pose(pose);
+        self.camera_velocity = Vec3::ZERO;
+        if self.camera_rig.smoothing.is_none() {
+            self.reset_temporal_history();
+        }
+    }
+
+    // Moves the camera to `eye`, looking the same way as before
+    pub fn teleport(&mut self, eye: Vec3) {
+        let offset = eye - self.camera.eye;
+        self.camera.eye = eye;
+        self.camera.target += offset;
+    }
+
+    // Keeps moving the camera by `velocity` units per second, for flying over streamed terrain
+    pub fn set_camera_velocity(&mut self, velocity: Vec3) {
+        self.camera_velocity = velocity;
+    }
+
+    pub fn set_frustum_culling(&mut self, enabled: bool) {
+        self.frustum_culling = enabled;
+    }
+
+    // Keeps culling against the camera as it is now while it moves on, and draws that frustum
+    pub fn set_culling_frozen(&mut self, frozen: bool) {
+        self.cull_camera = frozen.then_some(self.view_camera);
+        self.update_title();
+    }
+
+    pub fn is_culling_frozen(&self) -> bool {
+        self.cull_camera.is_some()
+    }
+
+    fn cull_frustum(&self) -> Option<Frustum> {
+        if !self.frustum_culling || self.split_screen {
+            return None;
+        }
+        let camera = self.cull_camera.as_ref().unwrap_or(&self.view_camera);
+        Some(Frustum::from_view_proj(&camera.build_view_projection_matrix()))
+    }
+
+    // Picks LODs and culls through the BVH. True when the instance buffer was written again
+    fn select_instances(&mut self) -> bool {
+        let visible = match self.cull_frustum() {
+            Some(frustum) => {
+                self.visible_items.clear();
+                self.bvh.query_frustum(&frustum, &self.instance_aabbs, &mut self.visible_items);
+                self.visible.clear();
+                self.visible.resize(self.instances.len(), false);
+                for &i in &self.visible_items {
+                    self.visible[i as usize] = true;
+                }
+                Some(self.visible.as_slice())
+            }
+            None => None,
+        };
+        let changed = self.lod.select(&self.view_camera, visible, self.config.height as f32, &self.instances);
+        if changed {
+            self.upload_instances();
+        }
+        changed
+    }
+
+    // After instances or the mesh bounds changed. Same number of instances only updates the BVH's
+    // boxes, see Bvh::refit
+    fn update_instance_bounds(&mut self) {
+        self.instance_aabbs.clear();
+        self.instance_aabbs.extend(self.instances.iter().map(|instance| self.mesh_aabb.transformed(&instance.model_matrix())));
+        self.bvh.refit(&self.instance_aabbs);
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    // Moves / replaces the instances. Meant to be called every frame for animated ones, with the
+    // same count that's a BVH refit and not a rebuild. Not an edit: a different count drops the
+    // selection and undo history, which point at instances by index
+    pub fn set_instances(&mut self, instances: Vec<Instance>) {
+        let count_changed = instances.len() != self.instances.len();
+        self.instances = instances;
+        if count_changed {
+            self.forget_edits();
+        }
+        self.instances_changed(count_changed);
+    }
+
+    fn instances_changed(&mut self, count_changed: bool) {
+        self.update_instance_bounds();
+        if !self.select_instances() {
+            self.upload_instances();
+        }
+        if count_changed {
+            self.reset_temporal_history();
+            self.update_title();
+        }
+    }
+
+    // Instance under pixel (x, y) of the window, by its bounds (not its triangles), None for
+    // background. Casts through the BVH, nearest box wins. Uses the main camera, split screen or not
+    pub fn pick(&self, x: f32, y: f32) -> Option<usize> {
+        self.pick_point(x, y).map(|(instance, _)| instance)
+    }
+
+    // pick, plus where the ray enters the instance's bounds
+    fn pick_point(&self, x: f32, y: f32) -> Option<(usize, Vec3)> {
+        let (near, direction) = self.cursor_ray(x, y);
+        self.bvh
+            .ray_cast(near, direction, 1.0, &self.instance_aabbs)
+            .map(|(instance, t)| (instance as usize, near + direction * t))
+    }
+
+    // Ray through pixel (x, y) of the window, from the near plane to the far plane
+    fn cursor_ray(&self, x: f32, y: f32) -> (Vec3, Vec3) {
+        let inverse = self.view_camera.build_view_projection_matrix().inverse();
+        let ndc_x = x / self.config.width as f32 * 2.0 - 1.0;
+        let ndc_y = 1.0 - y / self.config.height as f32 * 2.0;
+        let near = inverse.transform_point(Vec3::new(ndc_x, ndc_y, 0.0));
+        let far = inverse.transform_point(Vec3::new(ndc_x, ndc_y, 1.0));
+        (near, far - near)
+    }
+
+    // Where the gizmo is and how long its arrows are, None without a selection
+    fn gizmo_placement(&self) -> Option<(Vec3, f32)> {
+        let position = self.instances.get(self.selected?)?.position;
+        Some((position, gizmo::screen_scale(&self.view_camera, position)))
+    }
+
+    // Starts dragging the gizmo handle under pixel (x, y). False if there's none there
+    fn grab_gizmo(&mut self, x: f32, y: f32) -> bool {
+        let Some((position, scale)) = self.gizmo_placement() else {
+            return false;
+        };
+        let (origin, direction) = self.cursor_ray(x, y);
+        let Some(handle) = gizmo::hit(position, scale, origin, direction) else {
+            return false;
+        };
+        // Drags follow cursor moves, which recordings don't have
+        if !self.plays_frames() {
+            self.gizmo_drag = gizmo::Drag::new(handle, position, origin, direction);
+            if self.gizmo_drag.is_some() {
+                self.begin_edit();
+            }
+        }
+        true
+    }
+
+    // After the cursor moved: the dragged handle moves the selection, one undo entry per drag,
+    // otherwise the handle under the cursor lights up
+    fn update_gizmo(&mut self) {
+        let (Some((x, y)), Some((position, scale)), Some(selected)) = (self.cursor_position, self.gizmo_placement(), self.selected) else {
+            self.gizmo_hovered = None;
+            return;
+        };
+        let (origin, direction) = self.cursor_ray(x, y);
+        match self.gizmo_drag {
+            Some(drag) => {
+                if let Some(moved) = drag.position(origin, direction) {
+                    self.transform_instance(selected, Instance { position: moved, ..self.instances[selected] });
+                }
+            }
+            None => self.gizmo_hovered = gizmo::hit(position, scale, origin, direction),
+        }
+    }
+
+    fn release_gizmo(&mut self) {
+        if self.gizmo_drag.take().is_some() {
+            self.end_edit();
+        }
+    }
+
+    // Outlines `instance` and makes it what DeleteSelected / DuplicateSelected work on. None (or an
+    // instance that doesn't exist) clears the selection
+    pub fn select(&mut self, instance: Option<usize>) {
+        self.selected = instance.filter(|&instance| instance < self.instances.len());
+        match self.selected {
+            Some(instance) => self.draw_outlined(instance, SELECTION_COLOR, SELECTION_THICKNESS),
+            None => self.clear_outline(),
+        }
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    // Moves instance `index` to `instance`, as an edit undo() takes back. Between begin_edit and
+    // end_edit the calls make one undo entry, for drags. False if there's no such instance
+    pub fn transform_instance(&mut self, index: usize, instance: Instance) -> bool {
+        let Some(&before) = self.instances.get(index) else {
+            return false;
+        };
+        self.edit(Edit::Transform { index, before, after: instance });
+        true
+    }
+
+    // Adds `instance` after the others as an edit, returns its index
+    pub fn spawn_instance(&mut self, instance: Instance) -> usize {
+        let index = self.instances.len();
+        self.edit(Edit::Spawn { index, instance });
+        index
+    }
+
+    // Removes instance `index` as an edit, undo() puts it back at the same index. False if there's
+    // no such instance
+    pub fn delete_instance(&mut self, index: usize) -> bool {
+        let Some(&instance) = self.instances.get(index) else {
+            return false;
+        };
+        self.edit(Edit::Delete { index, instance });
+        true
+    }
+
+    // Changes the mesh material's factors as an edit. False without a mesh material
+    pub fn set_material_factors(&mut self, factors: PbrFactors) -> bool {
+        let Some(material) = &self.mesh_material else {
+            return false;
+        };
+        self.edit(Edit::Material { before: material.factors(), after: factors });
+        true
+    }
+
+    // Edits until end_edit undo as one, see UndoStack::begin_group
+    pub fn begin_edit(&mut self) {
+        self.undo_stack.begin_group();
+    }
+
+    pub fn end_edit(&mut self) {
+        self.undo_stack.end_group();
+    }
+
+    // Takes back the last edit. False if there's nothing to undo
+    pub fn undo(&mut self) -> bool {
+        let Some(edit) = self.undo_stack.undo() else {
+            return false;
+        };
+        self.apply_edit(edit.inverse());
+        true
+    }
+
+    // Does the last undone edit again. False if there's nothing to redo
+    pub fn redo(&mut self) -> bool {
+        let Some(edit) = self.undo_stack.redo() else {
+            return false;
+        };
+        self.apply_edit(edit);
+        true
+    }
+
+    // Undo entries kept, the oldest go first. undo::DEFAULT_LIMIT to start with
+    pub fn set_undo_limit(&mut self, limit: usize) {
+        self.undo_stack.set_limit(limit);
+    }
+
+    fn edit(&mut self, edit: Edit) {
+        self.apply_edit(edit);
+        self.undo_stack.push(edit);
+    }
+
+    fn apply_edit(&mut self, edit: Edit) {
+        match edit {
+            Edit::Transform { index, after, .. } => {
+                self.instances[index] = after;
+                self.instances_changed(false);
+            }
+            Edit::Spawn { index, instance } => {
+                self.instances.insert(index, instance);
+                self.shift_instance_indices(|i| Some(if i >= index { i + 1 } else { i }));
+                self.instances_changed(true);
+            }
+            Edit::Delete { index, .. } => {
+                self.instances.remove(index);
+                self.shift_instance_indices(|i| match i.cmp(&index) {
+                    std::cmp::Ordering::Less => Some(i),
+                    std::cmp::Ordering::Equal => None,
+                    std::cmp::Ordering::Greater => Some(i - 1),
+                });
+                self.instances_changed(true);
+            }
+            Edit::Material { after, .. } => {
+                if let Some(material) = &mut self.mesh_material {
+                    material.set_factors(&self.queue, after);
+                }
+            }
+        }
+    }
+
+    // After an instance was inserted or removed: the selection and trails follow theirs, `shift`
+    // maps old indices to new ones (None for the removed one)
+    fn shift_instance_indices(&mut self, shift: impl Fn(usize) -> Option<usize>) {
+        self.select(self.selected.and_then(&shift));
+        self.instance_trails.retain_mut(|(instance, _)| match shift(*instance) {
+            Some(shifted) => {
+                *instance = shifted;
+                true
+            }
+            None => false,
+        });
+    }
+
+    // The instances were replaced wholesale
+    fn forget_edits(&mut self) {
+        self.undo_stack.clear();
+        self.select(None);
+    }
+
+    // Up to decal::MAX_DECALS at once, past that the least recently placed or changed one is
+    // recycled. Only drawn with a single sampled depth buffer and one viewport
+    pub fn add_decal(&mut self, decal: Decal) -> DecalId {
+        self.decals.add(&self.queue, decal)
+    }
+
+    // False if `id` was recycled in the meantime
+    pub fn set_decal(&mut self, id: DecalId, decal: Decal) -> bool {
+        self.decals.set(&self.queue, id, decal)
+    }
+
+    pub fn clear_decals(&mut self) {
+        self.decals.clear();
+    }
+
+    // Decal `size` wide on the instance under pixel (x, y), facing out of the side of its bounds
+    // the ray went in through. None for background. Bounds, not triangles, like pick, so the box
+    // reaches `size` deep to find the surface
+    pub fn place_decal(&mut self, x: f32, y: f32, size: f32) -> Option<DecalId> {
+        let (instance, hit) = self.pick_point(x, y)?;
+        let aabb = self.instance_aabbs[instance];
+        let sides = [
+            (hit.x - aabb.min.x, -Vec3::X),
+            (aabb.max.x - hit.x, Vec3::X),
+            (hit.y - aabb.min.y, -Vec3::Y),
+            (aabb.max.y - hit.y, Vec3::Y),
+            (hit.z - aabb.min.z, -Vec3::Z),
+            (aabb.max.z - hit.z, Vec3::Z),
+        ];
+        let (_, normal) = sides.into_iter().min_by(|a, b| a.0.total_cmp(&b.0))?;
+        Some(self.add_decal(Decal {
+            position: hit,
+            normal,
+            rotation: 0.0,
+            size: Vec3::new(size, size, size),
+            color: [1.0; 4],
+        }))
+    }
+
+    // Ribbon behind instance `instance` from now on, replacing the one it had. Moving it further
+    // than settings.break_distance in one frame (set_instances) breaks the ribbon there
+    pub fn add_instance_trail(&mut self, instance: usize, settings: TrailSettings) {
+        self.remove_instance_trail(instance);
+        self.instance_trails.push((instance, Trail::new(settings)));
+    }
+
+    pub fn remove_instance_trail(&mut self, instance: usize) {
+        self.instance_trails.retain(|(followed, _)| *followed != instance);
+    }
+
+    pub fn clear_trails(&mut self) {
+        self.instance_trails.clear();
+    }
+
+    // Something circling the instances, trailing a ribbon
+    pub fn set_comet(&mut self, enabled: bool) {
+        self.comet = enabled.then(Comet::new);
+    }
+
+    fn update_trails(&mut self, dt: f32) {
+        // Trails of instances that are gone go with them
+        let count = self.instances.len();
+        self.instance_trails.retain(|(instance, _)| *instance < count);
+        for (instance, trail) in &mut self.instance_trails {
+            trail.update(self.instances[*instance].position, dt);
+        }
+        if let Some(comet) = &mut self.comet {
+            let bounds = self.instance_aabbs.iter().copied().reduce(|a, b| a.including(b.min).including(b.max));
+            let (center, radius) = bounds.map_or((Vec3::ZERO, 3.0), |bounds| {
+                (bounds.center(), ((bounds.max - bounds.min) * 0.5).length() + 1.0)
+            });
+            comet.update(center, radius, dt);
+        }
+        let trails = self.instance_trails.iter().map(|(_, trail)| trail).chain(self.comet.as_ref().map(|comet| &comet.trail));
+        self.trails.upload(&self.device, &self.queue, trails, self.view_camera.eye);
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.camera.projection
+    }
+
+    // Rebuilds the instance grid, the buffer is reused unless it has to grow
+    pub fn set_instance_count(&mut self, count: usize) {
+        self.instances = instance::grid(count.max(1), self.layered_texture.count());
+        self.forget_edits();
+        self.update_instance_bounds();
+        if !self.select_instances() {
+            self.upload_instances();
+        }
+        // Instances moved around, last frame's copy doesn't line up with them
+        self.reset_temporal_history();
+        self.update_title();
+    }
+
+    fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
+        if size.height > 0 && size.width > 0 {
+            self.size = size;
+            self.config.width = size.width;
+            self.config.height = size.height;
+            self.surface.configure(&self.device, &self.config);
+            self.create_render_targets();
+            if let Some(stream) = &mut self.frame_stream {
+                stream.resize(&self.device, size.width, size.height, stream.ring_size());
+            }
+            self.camera.aspect = size.width as f32 / size.height as f32;
+            // Render targets changed size
+            self.update_title();
+        }
+    }
+
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(key),
+                    ..
+                },
+                ..
+            } => {
+                let action = match self.modifiers.ctrl() {
+                    true => self.input_map.ctrl_action(*key),
+                    false => self.input_map.action(*key),
+                };
+                match action {
+                    Some(action) => self.input_action(action),
+                    None => false,
+                }
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                false
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = Some((position.x as f32, position.y as f32));
+                self.update_gizmo();
+                false
+            }
+            WindowEvent::MouseInput { state: ElementState::Pressed, button, .. } => match self.input_map.button_action(*button) {
+                Some(action) => self.input_action(action),
+                None => false,
+            },
+            WindowEvent::MouseInput { state: ElementState::Released, .. } if self.gizmo_drag.is_some() => {
+                self.release_gizmo();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // An action from a key or mouse button
+    fn input_action(&mut self, action: Action) -> bool {
+        // Never recorded and works during playback too
+        if action == Action::BugReport {
+            self.perform(action);
+            return true;
+        }
+        // Played back recordings drive the app, input would make them diverge
+        if self.plays_frames() {
+            return false;
+        }
+        self.perform(action);
+        if let Some(recorder) = &mut self.input_recorder {
+            recorder.action(RecordedAction { action, cursor: self.cursor_position.filter(|_| action.at_cursor()) });
+        }
+        true
+    }
+
+    // Makes `key` the only key for `action`. The action the key did before is returned, it keeps
+    // any other keys it has. Recordings store actions, so they still play after rebinding
+    pub fn rebind(&mut self, action: Action, key: VirtualKeyCode) -> Option<Action> {
+        self.input_map.rebind(action, key)
+    }
+
+    pub fn input_map(&self) -> &InputMap {
+        &self.input_map
+    }
+
+    pub fn unbind(&mut self, key: VirtualKeyCode) -> Option<Action> {
+        self.input_map.unbind(key)
+    }
+
+    // What keys and mouse buttons do, see InputMap for which one does what
+    fn perform(&mut self, action: Action) {
+        match action {
+            Action::CameraShake => {
+                self.camera_rig.shake.add_trauma(0.5);
+            }
+            Action::ResetCamera => {
+                self.reset_camera();
+            }
+            Action::ToggleBounds => {
+                self.show_bounds = !self.show_bounds;
+            }
+            Action::ToggleNormals => {
+                self.set_show_normals(self.normal_segments.is_empty(), 0.3);
+            }
+            Action::ToggleTangentFrames => {
+                self.set_show_tangent_frames(self.frame_segments.is_empty(), 0.1);
+            }
+            Action::TogglePerDraw => {
+                self.per_draw_mode = !self.per_draw_mode;
+                self.update_title();
+            }
+            Action::ToggleFlatShading => {
+                self.set_flat_shading(!self.flat_shading);
+            }
+            Action::ToggleLuminance => {
+                if !self.set_measure_luminance(!self.measure_luminance) {
+                    log::warn!("No compute shaders, can't measure luminance");
+                }
+            }
+            Action::ToggleAutoExposure => {
+                if !self.set_auto_exposure(!self.auto_exposure_enabled) {
+                    log::warn!("No compute shaders, can't measure luminance for auto exposure");
+                }
+            }
+            Action::ToggleHistogram => {
+                if !self.set_show_exposure_histogram(!self.show_histogram) {
+                    log::warn!("No compute shaders, can't build the luminance histogram");
+                }
+            }
+            // Compare clipping and clamping at the near plane, move the camera close to see it
+            Action::ToggleDepthClamp => {
+                if !self.set_depth_clamp(!self.pipeline_config.unclipped_depth) {
+                    log::warn!("Adapter doesn't support depth clamping (DEPTH_CLIP_CONTROL)");
+                }
+                self.update_title();
+            }
+            Action::CycleSampleCount => {
+                self.cycle_sample_count();
+            }
+            Action::CycleAntiAliasing => {
+                self.set_anti_aliasing(match self.anti_aliasing {
+                    AntiAliasing::Msaa => AntiAliasing::Fxaa,
+                    AntiAliasing::Fxaa => AntiAliasing::Taa,
+                    AntiAliasing::Taa => AntiAliasing::Msaa,
+                });
+            }
+            Action::ToggleMotionBlur => {
+                self.set_motion_blur(!self.motion_blur_enabled);
+            }
+            Action::ToggleFog => {
+                self.set_fog_enabled(!self.fog_enabled);
+            }
+            // Off, every instance, only the outlined one
+            Action::CycleWireframe => {
+                let scope = match self.wireframe_scope {
+                    WireframeScope::Off => WireframeScope::All,
+                    WireframeScope::All => WireframeScope::Selected,
+                    WireframeScope::Selected => WireframeScope::Off,
+                };
+                self.set_wireframe_overlay(scope, DEFAULT_WIREFRAME_COLOR, 1.0);
+            }
+            Action::ToggleErrorHistory => {
+                self.set_show_error_history(!self.show_error_history);
+            }
+            Action::ToggleLodColors => {
+                self.set_show_lod_colors(!self.show_lod_colors);
+            }
+            Action::CycleTransparency => {
+                self.transparency.mode = match self.transparency.mode {
+                    TransparencyMode::Sorted => TransparencyMode::WeightedBlended,
+                    TransparencyMode::WeightedBlended => TransparencyMode::Sorted,
+                };
+                self.update_title();
+            }
+            Action::ToggleProjection => {
+                self.set_projection(match self.camera.projection {
+                    Projection::Perspective => Projection::Orthographic,
+                    Projection::Orthographic => Projection::Perspective,
+                });
+            }
+            Action::ToggleSplitScreen => {
+                self.split_screen = !self.split_screen;
+            }
+            Action::TogglePreserveFrame => {
+                self.set_preserve_previous_frame(!self.preserve_frame);
+            }
+            Action::ToggleDayCycle => match &mut self.time_of_day {
+                Some(time_of_day) => time_of_day.paused = !time_of_day.paused,
+                None => self.set_time_of_day(Some(TimeOfDay::default())),
+            },
+            Action::LaterInDay | Action::EarlierInDay => {
+                let hours = self.time_of_day.map_or(TimeOfDay::default().hours(), |time_of_day| time_of_day.hours());
+                self.set_time_of_day_hours(hours + if action == Action::LaterInDay { 1.0 } else { -1.0 });
+            }
+            // Jumps far enough that every streamed chunk has to be replaced
+            Action::Teleport => {
+                self.teleport(self.camera.eye + Vec3::new(1000.0, 0.0, 0.0));
+            }
+            Action::ToggleCullingFreeze => {
+                self.set_culling_frozen(!self.is_culling_frozen());
+            }
+            Action::CycleDebugView => {
+                self.builtin_debug_view = self.builtin_debug_view.next();
+            }
+            Action::ToggleConservativeDemo => {
+                self.conservative_demo.visible = !self.conservative_demo.visible;
+                if self.conservative_demo.visible && !self.conservative_demo.is_supported() {
+                    log::warn!("Adapter doesn't support conservative rasterization, only the regular half is shown");
+                }
+                self.update_title();
+            }
+            // Doubles / halves the instance count, for quick stress testing
+            Action::MoreInstances => {
+                self.set_instance_count((self.instances.len() * 2).min(MAX_INSTANCE_COUNT));
+            }
+            Action::FewerInstances => {
+                self.set_instance_count(self.instances.len() / 2);
+            }
+            Action::ClearDecals => {
+                self.clear_decals();
+            }
+            Action::PlaceDecal => {
+                if let Some((x, y)) = self.cursor_position {
+                    self.place_decal(x, y, 0.5);
+                }
+            }
+            Action::ToggleComet => {
+                self.set_comet(self.comet.is_none());
+            }
+            Action::Select => {
+                if let Some((x, y)) = self.cursor_position {
+                    if !self.grab_gizmo(x, y) {
+                        self.select(self.pick(x, y));
+                    }
+                }
+            }
+            Action::Undo => {
+                self.undo();
+            }
+            Action::Redo => {
+                self.redo();
+            }
+            Action::DeleteSelected => {
+                if let Some(selected) = self.selected {
+                    self.delete_instance(selected);
+                }
+            }
+            Action::DuplicateSelected => {
+                if let Some(selected) = self.selected {
+                    let mut copy = self.instances[selected];
+                    copy.position.x += instance::SPACING;
+                    let copy = self.spawn_instance(copy);
+                    self.select(Some(copy));
+                }
+            }
+            Action::BugReport => {
+                #[cfg(not(target_arch = "wasm32"))]
+                match bug_report::new_report_dir(&self.bug_report_dir, "report") {
+                    Ok(dir) => match self.generate_bug_report(&dir) {
+                        Ok(()) => self.report_error(Severity::Info, format!("Bug report written to {}", dir.display())),
+                        Err(e) => self.report_error(Severity::Error, format!("Couldn't write bug report: {}", e)),
+                    },
+                    Err(e) => self.report_error(Severity::Error, format!("Couldn't create bug report directory: {}", e)),
+                }
+            }
+        }
+    }
+
+    fn update(&mut self) {
+        let now = instant::Instant::now();
+        let mut dt = (now - self.last_update).as_secs_f32();
+        self.last_update = now;
+
+        // Frame exact playback replaces both real input and real time
+        if let Some(playback) = self.input_playback.as_mut().filter(|playback| playback.timing == Timing::FrameExact) {
+            match playback.next_frame() {
+                Some(frame) => {
+                    if playback.remaining_frames() == 0 {
+                        if let Some(path) = playback.screenshot.take() {
+                            if !self.save_screenshot(path) {
+                                self.report_error(Severity::Warning, "Surface can't be read back, no playback screenshot");
+                            }
+                        }
+                    }
+                    dt = frame.dt;
+                    for recorded in frame.actions {
+                        // Only where the recorded cursor was matters, real cursor moves are ignored
+                        if recorded.cursor.is_some() {
+                            self.cursor_position = recorded.cursor;
+                        }
+                        self.perform(recorded.action);
+                    }
+                }
+                None => self.input_playback = None,
+            }
+        }
+        if let Some(recorder) = &mut self.input_recorder {
+            recorder.end_frame(dt);
+        }
+        self.frame_dt = dt;
+        #[cfg(not(target_arch = "wasm32"))]
+        self.run_remote_commands();
+
+        self.deferred_destruction.next_frame();
+        self.frame_arena.reset(&self.device);
+        // Queued views hold uniforms from the arena
+        self.debug_views.clear();
+        self.reload_scene();
+        self.reload_assets();
+        if self.camera_velocity != Vec3::ZERO {
+            self.teleport(self.camera.eye + self.camera_velocity * dt);
+        }
+        if let Some(streaming) = &mut self.streaming {
+            streaming.update(&self.device, &self.queue, &mut self.deferred_destruction, self.camera.eye);
+        }
+
+        self.update_time_of_day(dt);
+
+        // Rig works on a copy, self.camera stays the undisturbed base camera
+        self.view_camera = self.camera_rig.apply(&self.camera, dt);
+        if self.auto_exposure_enabled {
+            self.auto_exposure.update(dt);
+        }
+        if self.select_instances() {
+            // Instances moved to other slots, last frame's copy doesn't line up with them anymore
+            self.velocity.invalidate_instances();
+        }
+        self.camera_uniform.update_view_proj(&self.view_camera);
+        self.camera_uniform.set_exposure(self.exposure());
+        self.camera_uniform.set_fog(self.fog_enabled.then_some(&self.fog));
+        self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+
+        self.line_batch.clear();
+        if self.show_bounds {
+            for instance in &self.instances {
+                self.line_batch.aabb(&self.mesh_aabb, &instance.model_matrix(), [1.0, 1.0, 0.0]);
+            }
+        }
+        if !self.normal_segments.is_empty() {
+            for instance in &self.instances {
+                self.line_batch.segments(&self.normal_segments, &instance.model_matrix());
+            }
+        }
+        if !self.frame_segments.is_empty() {
+            for instance in &self.instances {
+                self.line_batch.colored_segments(&self.frame_segments, &instance.model_matrix());
+            }
+        }
+        if let Some(camera) = &self.cull_camera {
+            self.line_batch.frustum(&camera.build_view_projection_matrix(), [0.2, 0.9, 1.0]);
+        }
+        self.line_batch.upload(&self.device, &self.queue, &mut self.frame_arena, self.config.width, self.config.height);
+        self.gizmo_lines.clear();
+        if let Some((position, scale)) = self.gizmo_placement() {
+            let highlighted = self.gizmo_drag.map(|drag| drag.handle).or(self.gizmo_hovered);
+            gizmo::draw(&mut self.gizmo_lines, position, scale, highlighted);
+        }
+        self.gizmo_lines.upload(&self.device, &self.queue, &mut self.frame_arena, self.config.width, self.config.height);
+        if !self.points.is_empty() {
+            self.points.upload(&self.device, &self.queue, self.config.width, self.config.height);
+        }
+        self.update_trails(dt);
+
+        self.transparency.upload(&self.device, &self.queue, &mut self.frame_arena, self.view_camera.eye);
+        if self.conservative_demo.visible {
+            self.conservative_demo.update(&self.queue, dt);
+        }
+        if self.per_draw_mode {
+            self.per_draw.upload(&self.device, &self.queue, &self.instances);
+        }
+    }
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let viewports = if self.split_screen {
+            // Left: main camera, right: looking straight down at the grid
+            let top_down = Camera {
+                eye: Vec3::new(0.0, 15.0, 0.0),
+                target: Vec3::ZERO,
+                up: -Vec3::Z,
+                ..self.view_camera
+            };
+            vec![
+                Viewport { x: 0.0, y: 0.0, width: 0.5, height: 1.0, camera: self.view_camera, clear_color: None },
+                Viewport {
+                    x: 0.5,
+                    y: 0.0,
+                    width: 0.5,
+                    height: 1.0,
+                    camera: top_down,
+                    clear_color: Some(wgpu::Color { r: 0.15, g: 0.15, b: 0.2, a: 1.0 }),
+                },
+            ]
+        } else {
+            vec![Viewport::full(self.view_camera)]
+        };
+
+        self.render_viewports(&viewports)
+    }
+
+    // How much of the mesh in viewport `index` (0 without split screen) was visible, a frame or
+    // two old. Not a sample count: wgpu 0.17 can't begin occlusion queries, so this is fragment
+    // shader invocations from a pipeline statistics query, an upper bound (see occlusion.rs). None
+    // if the adapter can't count them or nothing arrived yet
+    pub fn last_occlusion_result(&self, index: u32) -> Option<u64> {
+        self.occlusion_queries.as_ref()?.last_result(index)
+    }
+
+    // Draws the scene once per viewport into its own region of the window, in a single pass
+    pub fn render_viewports(&mut self, viewports: &[Viewport]) -> Result<(), wgpu::SurfaceError> {
+        if let Some(queries) = &mut self.occlusion_queries {
+            queries.poll(&self.device);
+        }
+        if let Some(stream) = &mut self.frame_stream {
+            stream.poll(&self.device);
+        }
+        self.motion_blur.poll(&self.device);
+        self.queue_builtin_debug_view();
+        if let Some(luminance) = &mut self.luminance {
+            if luminance.poll(&self.device) {
+                if let Some(histogram) = luminance.last_histogram() {
+                    if self.auto_exposure_enabled {
+                        self.auto_exposure.measured(histogram);
+                    }
+                    if self.show_histogram {
+                        self.histogram_overlay.upload(&self.device, &self.queue, histogram, &self.auto_exposure.settings);
+                    }
+                }
+                if self.luminance_title_time.elapsed().as_secs_f32() > 0.5 {
+                    self.luminance_title_time = instant::Instant::now();
+                    self.update_title();
+                }
+            }
+        }
+
+        // Every viewport needs its own camera uniform, write_buffer calls all land before the pass runs
+        while self.viewport_cameras.len() + 1 < viewports.len() {
+            let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Viewport Camera Buffer"),
+                size: std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Viewport Camera Bind Group"),
+                layout: &self.camera_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }
+                ],
+            });
+            self.viewport_cameras.push((buffer, bind_group));
+        }
+
+        // TAA reprojects one camera, split views fall back to FXAA
+        let taa = self.anti_aliasing == AntiAliasing::Taa && viewports.len() == 1;
+        if self.anti_aliasing == AntiAliasing::Taa && !taa {
+            self.taa.invalidate();
+        }
+        // Motion blur reads the depth buffer, which MSAA leaves multisampled
+        let motion_blur = self.motion_blur_enabled && self.pipeline_config.sample_count == 1 && viewports.len() == 1;
+        let velocity = taa || motion_blur;
+        if !velocity {
+            self.velocity.invalidate();
+        }
+
+        let mut regions = Vec::with_capacity(viewports.len());
+        // Per region index into clear_colors
+        let mut region_clears = Vec::with_capacity(viewports.len());
+        let mut clear_colors = Vec::new();
+        for (i, viewport) in viewports.iter().enumerate() {
+            let (x, y, width, height) = viewport.pixel_rect(self.config.width, self.config.height);
+            if width == 0 || height == 0 {
+                continue;
+            }
+
+            // Aspect of the region, not the window, otherwise the image gets squashed
+            let mut camera = viewport.camera;
+            camera.aspect = width as f32 / height as f32;
+            let mut uniform = CameraUniform::new();
+            uniform.update_view_proj(&camera);
+            uniform.set_exposure(self.exposure());
+            uniform.set_fog(self.fog_enabled.then_some(&self.fog));
+            if velocity {
+                // Motion is measured without the jitter, the scene is drawn with it
+                let view_proj = camera.build_view_projection_matrix();
+                let prev_view_proj = self.velocity.prepare(&self.device, &self.queue, view_proj, self.instance_buffer.buffer());
+                let mut drawn = view_proj;
+                if taa {
+                    drawn = taa::jittered(view_proj, self.taa.jitter(width, height));
+                    uniform.set_view_proj(drawn);
+                    self.taa.prepare(&self.queue, prev_view_proj, drawn);
+                }
+                if motion_blur {
+                    self.motion_blur.prepare(&self.queue, prev_view_proj, drawn);
+                }
+            }
+
+            let (buffer, bind_group) = match i {
+                0 => (&self.camera_buffer, &self.camera_bind_group),
+                _ => {
+                    let (buffer, bind_group) = &self.viewport_cameras[i - 1];
+                    (buffer, bind_group)
+                }
+            };
+            self.queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[uniform]));
+            regions.push(((x, y, width, height), bind_group));
+            region_clears.push(viewport.clear_color.map(|color| {
+                clear_colors.push(color);
+                clear_colors.len() as u32 - 1
+            }));
+        }
+        if !clear_colors.is_empty() {
+            self.clear_rects.upload(&self.device, &self.queue, &mut self.frame_arena, &clear_colors);
+        }
+        self.errors.expire();
+        self.text_overlay.clear();
+        if !self.errors.is_empty(self.show_error_history) {
+            self.errors.layout(&mut self.text_overlay, self.config.width, self.config.height, self.show_error_history);
+        }
+        // Bottom left, one line each
+        let mut stats_lines = vec![self.stats_line()];
+        if self.fallback_adapter {
+            stats_lines.push("Software renderer (fallback adapter), expect low frame rates".to_string());
+        }
+        if let (Some(time_of_day), Some(lighting)) = (&self.time_of_day, &self.day_lighting) {
+            let minutes = (time_of_day.hours() * 60.0) as u32;
+            stats_lines.push(format!(
+                "{:02}:{:02}{} - sun {:.0} deg{}",
+                minutes / 60,
+                minutes % 60,
+                if time_of_day.paused { " (paused)" } else { "" },
+                lighting.sun_elevation,
+                if lighting.moon { ", moonlight" } else { "" },
+            ));
+        }
+        match self.builtin_debug_view {
+            BuiltinDebugView::Off => {}
+            BuiltinDebugView::Depth if self.pipeline_config.sample_count > 1 => {
+                stats_lines.push("Debug view: depth, not shown with MSAA".to_string());
+            }
+            BuiltinDebugView::Depth => stats_lines.push("Debug view: depth".to_string()),
+            BuiltinDebugView::BrdfLut if self.ibl.is_none() && self.default_ibl.is_none() => {
+                stats_lines.push("Debug view: BRDF lookup table, no environment loaded".to_string());
+            }
+            BuiltinDebugView::BrdfLut => stats_lines.push("Debug view: BRDF lookup table".to_string()),
+        }
+        if self.show_lod_colors {
+            stats_lines.push(self.lod.stats().to_string());
+        }
+        if let Some(streaming) = &self.streaming {
+            stats_lines.push(streaming.stats().to_string());
+        }
+        for (i, stats) in stats_lines.iter().enumerate() {
+            let scale = 2.0;
+            let y = self.config.height as f32 - (text::LINE_HEIGHT as f32 + 2.0) * scale * (i + 1) as f32;
+            self.text_overlay.rect(0.0, y, TextOverlay::text_width(stats, scale) + 4.0 * scale, (text::LINE_HEIGHT as f32 + 2.0) * scale, [0.0, 0.0, 0.0, 0.6]);
+            self.text_overlay.text(2.0 * scale, y + 2.0 * scale, scale, [1.0; 4], stats);
+        }
+        self.text_overlay.upload(&self.device, &self.queue, &mut self.frame_arena, self.config.width, self.config.height);
+
+        let region_count = regions.len();
+        let regions = &regions;
+
+        let output = self.surface.get_current_texture()?;
+        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+
+        // Modern GPUS expect their commands to be written inside buffer. That's why we are creating
+        // encoder, which represents GPU instruction and than passing it in queue
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Encoder")
+        });
+
+        // Every graph pass gets a debug group, they show up as named sections in RenderDoc / Xcode GPU captures
+        // Passes borrow self, so take it before building the graph
+        let graph_dump_path = self.graph_dump_path.take();
+        // Needs &mut in its pass, put back after the graph ran
+        let mut luminance = self.luminance.take();
+        let mut graph = RenderGraph::new();
+        // "surface" is whatever the scene is drawn into, the history texture when it has to survive
+        // until the next frame
+        graph.import_view("surface", self.history_view.as_ref().unwrap_or(&view));
+        graph.import_view("depth", &self.depth_texture.view);
+
+        graph.add_pass("Opaque", &[], &["surface", "depth"], |encoder, resources| {
+            let (color_view, resolve_target) = self.color_target(resources.view("surface"));
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: color_view,
+                    resolve_target,
+                    ops: resources.color_ops("surface", wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.clear_color),
+                        store: true,
+                    }),
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: resources.view("depth"),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.clear_depth),
+                        store: true,
+                    }),
+                    stencil_ops: self.pipeline_config.depth_format.has_stencil_aspect().then_some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.stencil_clear),
+                        store: true,
+                    }),
+                }),
+            });
+
+            for (i, &((x, y, width, height), camera_bind_group)) in regions.iter().enumerate() {
+                render_pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+                render_pass.set_scissor_rect(x, y, width, height);
+                if let Some(clear) = region_clears[i].filter(|_| !self.preserve_frame) {
+                    self.clear_rects.draw(&mut render_pass, clear);
+                }
+                if self.wrong_color_space.is_some() {
+                    // Same camera both halves, only the scissor moves. The occlusion query only
+                    // covers the left one, it can't be begun twice
+                    let half = width / 2;
+                    render_pass.set_scissor_rect(x, y, half, height);
+                    self.draw_scene(&mut render_pass, camera_bind_group, Some(i as u32), false);
+                    render_pass.set_scissor_rect(x + half, y, width - half, height);
+                    self.draw_scene(&mut render_pass, camera_bind_group, None, true);
+                } else {
+                    self.draw_scene(&mut render_pass, camera_bind_group, Some(i as u32), false);
+                }
+            }
+        });
+        if self.preserve_frame {
+            let load = wgpu::Operations { load: wgpu::LoadOp::Load, store: true };
+            graph.set_color_ops("Opaque", "surface", load).expect("Opaque pass writes surface");
+        }
+
+        // On the opaque scene only, transparent things are drawn over them. Decals read the depth
+        // buffer, which MSAA leaves multisampled, and reconstruct positions for the whole target
+        if !self.decals.is_empty() && self.pipeline_config.sample_count == 1 && regions.len() == 1 {
+            graph.add_pass("Decals", &["depth"], &["surface"], |encoder, resources| {
+                let &(_, camera_bind_group) = &regions[0];
+                self.decals.apply(&self.device, encoder, camera_bind_group, &self.depth_texture.texture, resources.view("surface"));
+            });
+        }
+
+        // Transparent passes draw into the same color target as the opaque pass, resolving again
+        if !self.transparency.is_empty() {
+            match self.transparency.mode {
+                TransparencyMode::Sorted => {
+                    graph.add_pass("Transparent", &["depth"], &["surface"], |encoder, resources| {
+                        let (color_view, resolve_target) = self.color_target(resources.view("surface"));
+                        self.transparency.sorted_pass(encoder, color_view, resolve_target, resources.view("depth"), regions);
+                    });
+                }
+                TransparencyMode::WeightedBlended => {
+                    let (accum, reveal) = self.transparency.oit_views();
+                    graph.import_view("oit_accum", accum);
+                    graph.import_view("oit_reveal", reveal);
+                    graph.add_pass("OIT Accumulate", &["depth"], &["oit_accum", "oit_reveal"], |encoder, resources| {
+                        self.transparency.accumulate_pass(encoder, resources.view("depth"), regions);
+                    });
+                    graph.add_pass("OIT Composite", &["oit_accum", "oit_reveal"], &["surface"], |encoder, resources| {
+                        let (color_view, resolve_target) = self.color_target(resources.view("surface"));
+                        self.transparency.composite_pass(encoder, color_view, resolve_target);
+                    });
+                }
+            }
+        }
+
+        if velocity {
+            graph.create_texture("velocity", TransientTexture {
+                width: self.config.width,
+                height: self.config.height,
+                format: velocity::VELOCITY_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                sample_count: 1,
+            });
+            graph.add_pass("Velocity", &["depth"], &["velocity"], |encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Velocity Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view("velocity"),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            // a = 0, reprojected from depth by the readers
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: resources.view("depth"),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                let &(_, camera_bind_group) = &regions[0];
+                render_pass.set_bind_group(0, camera_bind_group, &[]);
+                render_pass.set_bind_group(1, &self.layered_texture_bind_group, &[]);
+                render_pass.set_bind_group(2, &self.bones.bind_group, &[]);
+                render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
+                for batch in self.lod.batches() {
+                    self.velocity.draw(&mut render_pass, self.lod.vertices(batch.level), batch.instances.clone());
+                }
+            });
+        }
+
+        // What the passes below read and draw over, the blurred copy while motion blur runs
+        let mut scene = "surface";
+        let mut blur_measured = false;
+        if motion_blur {
+            if self.motion_blur.can_measure() {
+                graph.add_pass("Max Velocity", &["velocity", "depth"], &[], |encoder, resources| {
+                    self.motion_blur.measure(&self.device, encoder, resources.view("velocity"), &self.depth_texture.texture);
+                    blur_measured = true;
+                });
+            }
+            // Nothing moved enough to show the last time it was measured
+            if self.motion_blur.is_needed() {
+                graph.create_texture("motion_blurred", TransientTexture {
+                    width: self.config.width,
+                    height: self.config.height,
+                    format: self.config.format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                    sample_count: 1,
+                });
+                graph.add_pass("Motion Blur", &["surface", "velocity", "depth"], &["motion_blurred"], |encoder, resources| {
+                    self.motion_blur.blur(
+                        &self.device,
+                        encoder,
+                        resources.view("surface"),
+                        resources.view("velocity"),
+                        &self.depth_texture.texture,
+                        resources.view("motion_blurred"),
+                    );
+                });
+                scene = "motion_blurred";
+            }
+        }
+
+        // Only reads the scene, before anything else is drawn over it
+        let mut luminance_dispatched = false;
+        if let Some(luminance) = luminance.as_mut().filter(|_| self.luminance_wanted()) {
+            graph.add_pass("Luminance", &[scene], &[], |encoder, resources| {
+                luminance_dispatched = luminance.dispatch(
+                    &self.device, encoder, resources.view(scene), self.config.width, self.config.height
+                );
+            });
+        }
+
+        // Covers the whole surface, the scene underneath still runs so toggling it doesn't hitch
+        if self.conservative_demo.visible {
+            graph.add_pass("Conservative Demo", &[], &[scene], |encoder, resources| {
+                self.conservative_demo.render(encoder, resources.view(scene), self.config.width, self.config.height);
+            });
+        }
+
+        if self.show_histogram {
+            graph.add_pass("Exposure Histogram", &[], &[scene], |encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Exposure Histogram Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(scene),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                // Bottom left corner, a quarter of the width
+                let margin = 8.0;
+                let width = (self.config.width as f32 / 4.0 - margin).max(1.0);
+                let height = (self.config.height as f32 / 6.0).max(1.0);
+                render_pass.set_viewport(margin, self.config.height as f32 - height - margin, width, height, 0.0, 1.0);
+                self.histogram_overlay.draw(&mut render_pass);
+            });
+        }
+
+        if self.history_view.is_some() {
+            graph.import_view("swapchain", &view);
+            match self.anti_aliasing {
+                AntiAliasing::Taa if taa => {
+                    graph.add_pass("TAA Resolve", &[scene, "velocity", "depth"], &["swapchain"], |encoder, resources| {
+                        self.taa.resolve(
+                            &self.device,
+                            encoder,
+                            resources.view(scene),
+                            resources.view("velocity"),
+                            &self.depth_texture.texture,
+                            resources.view("swapchain"),
+                        );
+                    });
+                }
+                // TAA with split screen gets FXAA instead
+                AntiAliasing::Fxaa | AntiAliasing::Taa => {
+                    graph.add_pass("FXAA", &[scene], &["swapchain"], |encoder, resources| {
+                        self.fxaa.apply(&self.device, encoder, resources.view(scene), resources.view("swapchain"));
+                    });
+                }
+                AntiAliasing::Msaa => {
+                    graph.add_pass("Present Copy", &[scene], &["swapchain"], |encoder, resources| {
+                        self.blitter.blit_prewarmed(
+                            &self.device, encoder, resources.view(scene), resources.view("swapchain"), self.config.format
+                        );
+                    });
+                }
+            }
+        }
+
+        // After post processing like the text, so what's shown is the texture as is
+        if !self.debug_views.is_empty() {
+            let target = if self.history_view.is_some() { "swapchain" } else { "surface" };
+            graph.add_pass("Debug Views", &[], &[target], |encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Debug View Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(target),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                self.debug_views.draw(&mut render_pass, self.config.width, self.config.height);
+            });
+        }
+
+        // Last, so it's never blurred or anti-aliased
+        if !self.text_overlay.is_empty() {
+            let target = if self.history_view.is_some() { "swapchain" } else { "surface" };
+            graph.add_pass("Error Overlay", &[], &[target], |encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Error Overlay Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(target),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                self.text_overlay.draw(&mut render_pass);
+            });
+        }
+
+        // Passes here are built in code, a failure is a bug
+        let mut dump_error = None;
+        if let Some(path) = graph_dump_path {
+            let dump = graph.describe().unwrap_or_else(|e| e.to_string());
+            if let Err(e) = std::fs::write(&path, dump) {
+                dump_error = Some(format!("Couldn't write render graph to {}: {}", path.display(), e));
+            }
+        }
+        graph.execute(&self.device, &mut encoder).expect("Invalid render graph");
+        if let Some(message) = dump_error {
+            self.report_error(Severity::Error, message);
+        }
+        if luminance_dispatched {
+            self.auto_exposure.dispatched(self.exposure());
+        }
+        if velocity {
+            self.velocity.end_frame(&mut encoder, self.instance_buffer.buffer());
+        }
+        if taa {
+            self.taa.end_frame();
+        }
+
+        if let Some(queries) = &mut self.occlusion_queries {
+            queries.resolve(&mut encoder, region_count as u32);
+        }
+        if let Some(stream) = &mut self.frame_stream {
+            stream.capture(&mut encoder, &output.texture);
+        }
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        // These block until the copy is done, never requested on the web (see save_screenshot)
+        if let Some(path) = self.screenshot_path.take() {
+            let (width, height) = (output.texture.width(), output.texture.height());
+            let saved = readback::read_texture_displayed(&self.device, &self.queue, &output.texture, self.config.format)
+                .and_then(|pixels| readback::write_png(&path, width, height, &pixels).map_err(|e| e.to_string()));
+            if let Err(e) = saved {
+                self.report_error(Severity::Error, format!("Couldn't save screenshot to {}: {}", path.display(), e));
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if !self.remote_screenshots.is_empty() {
+            let (width, height) = (output.texture.width(), output.texture.height());
+            let png = readback::read_texture_displayed(&self.device, &self.queue, &output.texture, self.config.format).and_then(|pixels| {
+                let mut png = Vec::new();
+                readback::encode_png(&mut png, width, height, &pixels).map_err(|e| e.to_string())?;
+                Ok(remote::base64(&png))
+            });
+            for reply in self.remote_screenshots.drain(..) {
+                match &png {
+                    Ok(png) => reply.ok(json::Json::Object(vec![
+                        ("width".to_string(), json::Json::Number(width as f64)),
+                        ("height".to_string(), json::Json::Number(height as f64)),
+                        ("png".to_string(), json::Json::String(png.clone())),
+                    ])),
+                    Err(e) => reply.error(format!("Couldn't read the frame: {}", e)),
+                }
+            }
+        }
+        if let Some((x, y)) = self.pixel_request.take() {
+            self.read_pixel = Some(readback::read_pixel(&self.device, &self.queue, &output.texture, x, y, self.config.format));
+        }
+
+        output.present();
+
+        if let Some(queries) = &mut self.occlusion_queries {
+            queries.after_submit();
+        }
+        if let Some(stream) = &mut self.frame_stream {
+            stream.after_submit();
+        }
+        if let Some(mut luminance) = luminance {
+            luminance.after_submit();
+            self.luminance = Some(luminance);
+        }
+        self.motion_blur.after_submit(blur_measured);
+
+        Ok(())
+    }
+
+    // With MSAA we draw into the multisampled texture, which gets resolved to the surface
+    fn color_target<'a>(&'a self, surface: &'a wgpu::TextureView) -> (&'a wgpu::TextureView, Option<&'a wgpu::TextureView>) {
+        match &self.msaa_view {
+            Some(msaa_view) => (msaa_view, Some(surface)),
+            None => (surface, None),
+        }
+    }
+
+    // Mesh draw is counted into occlusion query `occlusion_query` if given and supported
+    fn draw_scene<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        occlusion_query: Option<u32>,
+        // Mesh textures from wrong_color_space
+        wrong_color_space: bool,
+    ) {
+        let wrong_color_space = self.wrong_color_space.as_ref().filter(|_| wrong_color_space);
+        let mesh_material = match wrong_color_space {
+            Some(wrong) => wrong.mesh_material.as_ref(),
+            None => self.mesh_material.as_ref(),
+        };
+        let layered_texture_bind_group = wrong_color_space.map_or(&self.layered_texture_bind_group, |wrong| &wrong.layered_texture_bind_group);
+        let queries = self.occlusion_queries.as_ref()
+            .zip(occlusion_query)
+            .filter(|(queries, index)| *index < queries.capacity());
+
+        // Far plane, everything else draws over it
+        pass_debug_group(render_pass, "Sky", |render_pass| {
+            self.sky.draw(render_pass, camera_bind_group);
+        });
+
+        pass_debug_group(render_pass, "Mesh", |render_pass| {
+            // Pipeline
+            let ibl = self.ibl.as_ref().or(self.default_ibl.as_ref());
+            if let (Some(material), Some(ibl)) = (mesh_material, ibl) {
+                self.pbr.set_pipeline(render_pass, camera_bind_group, material, &self.bones.bind_group, &ibl.bind_group);
+            } else {
+                render_pass.set_pipeline(if self.flat_shading {
+                    &self.flat_render_pipeline
+                } else {
+                    &self.render_pipeline
+                });
+                render_pass.set_bind_group(0, camera_bind_group, &[]);
+                render_pass.set_bind_group(1, layered_texture_bind_group, &[]);
+                render_pass.set_bind_group(2, &self.bones.bind_group, &[]);
+            }
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
+            if let Some((queries, index)) = queries {
+                queries.begin(render_pass, index);
+            }
+            let streamed_mesh = self.streamed_mesh.as_ref().filter(|_| self.mesh_material.is_none() && !self.per_draw_mode);
+            if let Some(streamed_mesh) = streamed_mesh {
+                streamed_mesh.set(render_pass, self.flat_shading, self.instance_buffer.buffer().slice(..));
+                for batch in self.lod.batches() {
+                    render_pass.draw(self.lod.vertices(batch.level), batch.instances.clone());
+                }
+                // What the passes below expect
+                render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
+            } else if self.per_draw_mode && self.mesh_material.is_none() {
+                // The PBR material only has an instanced pipeline
+                self.per_draw.set_pipeline(render_pass);
+                for i in 0..self.per_draw.len() {
+                    self.per_draw.draw(render_pass, self.lod.instance_vertices(i), i);
+                }
+            } else {
+                // One instanced draw per level of detail
+                for batch in self.lod.batches() {
+                    render_pass.draw(self.lod.vertices(batch.level), batch.instances.clone());
+                }
+            }
+            if let Some((queries, _)) = queries {
+                queries.end(render_pass);
+            }
+        });
+
+        // Needs the mesh bindings set above
+        if self.wireframe_scope != WireframeScope::Off {
+            pass_debug_group(render_pass, "Wireframe", |render_pass| {
+                // The PBR pipeline has the material in group 1
+                render_pass.set_bind_group(1, &self.layered_texture_bind_group, &[]);
+                match self.wireframe_scope {
+                    WireframeScope::All => {
+                        for batch in self.lod.batches() {
+                            self.wireframe.draw(render_pass, self.lod.vertices(batch.level), batch.instances.clone());
+                        }
+                    }
+                    WireframeScope::Selected => {
+                        if let Some(target) = self.outline.target.filter(|&target| (target as usize) < self.instances.len()) {
+                            let slot = self.lod.slot(target);
+                            self.wireframe.draw(render_pass, self.lod.instance_vertices(target as usize), slot..slot + 1);
+                        }
+                    }
+                    WireframeScope::Off => {}
+                }
+            });
+        }
+
+        pass_debug_group(render_pass, "Outline", |render_pass| {
+            // The PBR pipeline has the material in group 1, the outline layout wants the layered texture
+            render_pass.set_bind_group(1, &self.layered_texture_bind_group, &[]);
+            // Target may be gone after the instance count shrank
+            if let Some(target) = self.outline.target.filter(|&target| (target as usize) < self.instances.len()) {
+                self.outline.draw(render_pass, self.lod.instance_vertices(target as usize), self.lod.slot(target));
+            }
+        });
+
+        // Rebinds vertex buffer 0, so after everything that draws the mesh
+        if !self.per_draw_mode || self.mesh_material.is_some() {
+            pass_debug_group(render_pass, "Impostors", |render_pass| {
+                self.impostor.draw(render_pass, camera_bind_group, self.instance_buffer.buffer(), self.lod.impostor_instances());
+            });
+        }
+
+        if let Some(streaming) = &self.streaming {
+            pass_debug_group(render_pass, "Terrain", |render_pass| {
+                streaming.draw(render_pass, camera_bind_group);
+            });
+        }
+
+        pass_debug_group(render_pass, "Points", |render_pass| {
+            self.points.draw(render_pass, camera_bind_group);
+        });
+
+        // Blended over all of the opaque scene
+        pass_debug_group(render_pass, "Grid", |render_pass| {
+            self.grid.draw(render_pass, camera_bind_group);
+        });
+
+        pass_debug_group(render_pass, "Trails", |render_pass| {
+            self.trails.draw(render_pass, camera_bind_group);
+        });
+
+        pass_debug_group(render_pass, "Debug Lines", |render_pass| {
+            self.line_batch.draw(render_pass, camera_bind_group);
+        });
+
+        // Ignores depth, last so it's over everything else in the pass
+        pass_debug_group(render_pass, "Gizmo", |render_pass| {
+            self.gizmo_lines.draw(render_pass, camera_bind_group);
+        });
+    }
+}
+
+// Wraps everything `f` records in a named debug group, for passes added outside of render()
+pub fn debug_group<R>(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    f: impl FnOnce(&mut wgpu::CommandEncoder) -> R,
+) -> R {
+    encoder.push_debug_group(label);
+    let result = f(encoder);
+    encoder.pop_debug_group();
+    result
+}
+
+// Same as debug_group, but for sections inside a single render pass
+pub fn pass_debug_group<'a, R>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    label: &str,
+    f: impl FnOnce(&mut wgpu::RenderPass<'a>) -> R,
+) -> R {
+    render_pass.push_debug_group(label);
+    let result = f(render_pass);
+    render_pass.pop_debug_group();
+    result
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
+    position: [f32; 3],
+    // 3D Space x, y, z
+    color: [f32; 3], // R G B
+    tex_coords: [f32; 2], // U V, 0,0 is top left
+    // Up to 4 bones moving this vertex and how much, weights should add up to 1
+    joints: [u16; 4],
+    weights: [f32; 4],
+}
+
+impl Vertex {
+    // Not skinned, follows bone 0 only
+    pub const fn new(position: [f32; 3], color: [f32; 3], tex_coords: [f32; 2]) -> Self {
+        Self {
+            position,
+            color,
+            tex_coords,
+            joints: [0; 4],
+            weights: [1.0, 0.0, 0.0, 0.0],
+        }
+    }
+
+    // Get VertexBuffer Layout, To tell the pipeline how to read
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        
+        /* Extended attributes Declaration
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = [ // Attributes of Vertex Struct Fields. 1:1 Mapping
+            wgpu::VertexAttribute {
+                offset: 0, // From Where The attribute starts. Every Next Offset is sum of previous. First One is usually 0
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x3,
+            },
+            wgpu::VertexAttribute {
+                offset: std::mem::size_of::<[f32; 3]> as wgpu::BufferAddress, // Sum Of Previous Offsets
+                shader_location: 1,
+                format: wgpu::VertexFormat::Float32x3,
+            }
+        ]; // We need constant variable to return 'static reference
+        
+         */
+        const ATTRIBUTES : [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+            0 => Float32x3, 1 => Float32x3, 2 => Float32x2, 3 => Uint16x4, 4 => Float32x4
+        ];
+
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress, // Item Size In Buffer, To Make Next Step
+            step_mode: wgpu::VertexStepMode::Vertex, // Per Vertex Data Or Per instance Data // ToDo: Difference ?
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+const INITIAL_INSTANCE_COUNT: usize = 16;
+// Only used to size per viewport resources, more viewports still draw but aren't queried
+const MAX_VIEWPORTS: u32 = 16;
+const MAX_INSTANCE_COUNT: usize = 1 << 20;
+
+const VERTICIES: &[Vertex] = &[
+    Vertex::new([0.0, 0.5, 0.0], [1.0, 0.0, 0.0], [0.5, 0.0]),
+    Vertex::new([-0.5, -0.5, 0.0], [0.0, 1.0, 0.0], [0.0, 1.0]),
+    Vertex::new([0.5, -0.5, 0.0], [0.0, 0.0, 1.0], [1.0, 1.0]),
+];
+
+const LAYER_COLORS: [[u8; 4]; 4] = [
+    [255, 80, 80, 255],
+    [80, 200, 80, 255],
+    [80, 120, 255, 255],
+    [240, 200, 60, 255],
+];
+
+
+// Startup settings for run_with
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    // See run_uncapped
+    pub uncapped: bool,
+    // Seed for everything procedural, None picks a random one. Shown in the title either way
+    pub seed: Option<u64>,
+    // Present modes in order of preference, see State::set_present_mode_preference. Empty keeps
+    // the surface's default. uncapped overrides it
+    pub present_modes: &'static [wgpu::PresentMode],
+    // Extra surface view formats, see State::new
+    pub view_formats: &'static [wgpu::TextureFormat],
+    // Draws a sphere with 4 levels of detail instead of the triangle, and impostors past 30 units,
+    // to see LOD selection at work
+    pub lod_sphere: bool,
+    // Point cloud to show, see points::load_xyz
+    pub point_cloud: Option<std::path::PathBuf>,
+    // Saves a screenshot of the first frame there and reads its top right pixel on screen, then
+    // exits with 1 unless both are the clear color as the surface shows it. See check_screenshot
+    pub check_screenshot: Option<std::path::PathBuf>,
+    // Flies over streamed terrain, see State::set_streaming. J teleports
+    pub streaming: bool,
+    // JSON scene to start with, see State::load_scene
+    pub scene: Option<std::path::PathBuf>,
+    // Mesh positions in a vertex buffer of their own, see State::set_vertex_streams
+    pub split_positions: bool,
+    // Shows this primitive instead of the triangle, see primitives::by_name
+    pub primitive: Option<String>,
+    // Debug line width in pixels, see State::set_line_width
+    pub line_width: Option<f32>,
+    // Ground grid with default GridParams, see State::set_infinite_grid
+    pub grid: bool,
+    // Procedural sky with default ProceduralSkyParams as the environment, see
+    // State::set_procedural_sky
+    pub procedural_sky: bool,
+    // Starts the day cycle at this hour, see State::set_time_of_day
+    pub time_of_day: Option<f32>,
+    // Meshes keep their triangle order, see MeshOptions::optimize
+    pub no_mesh_optimize: bool,
+    // Scene pipelines read smaller vertex formats, see State::set_packed_vertices
+    pub packed_vertices: bool,
+    // Right half of the window samples textures in the wrong color space, see
+    // State::set_color_space_split
+    pub color_space_split: bool,
+    // Records input there from startup, see State::start_input_recording
+    pub record_input: Option<std::path::PathBuf>,
+    // Plays an input recording back, see State::play_input_recording. play_timing None plays it
+    // in real time
+    pub play_input: Option<std::path::PathBuf>,
+    pub play_timing: Option<Timing>,
+    // Polls the device on a background thread, see State::spawn_poll_thread
+    pub poll_thread: bool,
+    // Writes a report into a new directory under this one when the app panics, see
+    // bug_report::install_panic_hook, and the bug report key writes there too. None leaves the
+    // panic hook alone. Native only
+    pub bug_reports: Option<std::path::PathBuf>,
+    // Takes remote control commands, see State::start_remote. Native only
+    #[cfg(not(target_arch = "wasm32"))]
+    pub remote: Option<remote::RemoteOptions>,
+}
+
+// Why run_with_handler's event loop ended
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExitReason {
+    // Window closed or Escape
+    Requested,
+    // The surface couldn't get memory for a frame
+    OutOfMemory,
+    // RunOptions::check_screenshot finished, with its exit code
+    Check(i32),
+}
+
+impl ExitReason {
+    // What run_with exits with: 0 when asked to, 3 out of memory, the check's own code otherwise
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ExitReason::Requested => 0,
+            ExitReason::OutOfMemory => 3,
+            ExitReason::Check(code) => code,
+        }
+    }
+}
+
+// Tear free without waiting for vsync where the driver has mailbox, plain vsync otherwise
+pub const LOW_LATENCY_PRESENT_MODES: &[wgpu::PresentMode] = &[wgpu::PresentMode::Mailbox, wgpu::PresentMode::Fifo];
+
+// Pixel `pixel` of the saved screenshot against `read`, the same pixel read from the surface, and
+// both against the clear color as a `format` surface shows it
+fn check_screenshot(path: &std::path::Path, pixel: (u32, u32), read: [u8; 4], clear_color: wgpu::Color, format: wgpu::TextureFormat) -> Result<String, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+    let (rgba, width, _) = texture::decode_png_rgba(&bytes).map_err(|e| format!("Couldn't decode {}: {}", path.display(), e))?;
+    let i = ((pixel.1 * width + pixel.0) * 4) as usize;
+    let saved = [rgba[i], rgba[i + 1], rgba[i + 2], rgba[i + 3]];
+    let clear = readback::displayed_rgba8([clear_color.r as f32, clear_color.g as f32, clear_color.b as f32, 1.0], format);
+    // Alpha is left out, surfaces don't agree on it (see image_diff). The GPU's sRGB encoding may
+    // round differently than ours, so the clear color gets 1 off
+    let report = format!("{:?} surface, pixel {:?}: on screen {:?}, saved {:?}, clear color {:?}", format, pixel, read, saved, clear);
+    if read[..3] == saved[..3] && (0..3).all(|i| read[i].abs_diff(clear[i]) <= 1) {
+        Ok(report)
+    } else {
+        Err(report)
+    }
+}
+
+// What the app does with a window event, real or played back (see State::play_input_recording)
+fn handle_window_event(state: &mut State, event: &WindowEvent) -> Option<ExitReason> {
+    if state.input(event) {
+        return None;
+    }
+    log::trace!("Win Event - 3");
+    match event {
+        WindowEvent::CloseRequested | WindowEvent::KeyboardInput {
+            input:
+            KeyboardInput {
+                state: ElementState::Pressed,
+                virtual_keycode: Some(VirtualKeyCode::Escape),
+                ..
+            },
+            ..
+        } => return Some(ExitReason::Requested),
+
+        WindowEvent::Resized(physical_size) => state.resize(*physical_size),
+        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => state.resize(**new_inner_size),
+
+        _ => {}
+    }
+    None
+}
+
+pub async fn run() {
+    run_with(RunOptions::default()).await;
+}
+
+// Throughput test: no vsync (Immediate present where available), frames counted and reported
+// once per second in the title and the log (info level). Runs until closed
+pub async fn run_uncapped() {
+    run_with(RunOptions { uncapped: true, ..Default::default() }).await;
+}
+
+pub async fn run_with(options: RunOptions) {
+    run_with_handler(options, ExitReason::exit_code).await;
+}
+
+// run_with that calls `on_exit` once the loop ends, after the GPU finished its work and every
+// GPU resource is dropped (surface before the window it draws to). Whatever it returns is the
+// process exit code
+pub async fn run_with_handler(options: RunOptions, on_exit: impl FnOnce(ExitReason) -> i32 + 'static) {
+    run_with_callbacks(options, |_| {}, |_| {}, on_exit).await;
+}
+
+// run_with_handler for code driving State itself: `setup` runs once State is set up from
+// `options`, before the first frame, and `update` every frame before State updates and renders
+pub async fn run_with_callbacks(
+    options: RunOptions,
+    setup: impl FnOnce(&mut State),
+    mut update: impl FnMut(&mut State) + 'static,
+    on_exit: impl FnOnce(ExitReason) -> i32 + 'static,
+) {
+    let uncapped = options.uncapped;
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+            console_log::init_with_level(log::Level::Warn).expect("Couldn't initialize logger");
+        } else {
+            bug_report::init_logger();
+            if let Some(dir) = &options.bug_reports {
+                bug_report::install_panic_hook(dir.clone());
+            }
+        }
+    }
+
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new().build(&event_loop).unwrap();
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        // Winit prevents sizing with CSS, so we have to set
+        // the size manually when on web.
+        use winit::dpi::PhysicalSize;
+        window.set_inner_size(PhysicalSize::new(450, 400));
+
+        use winit::platform::web::WindowExtWebSys;
+        web_sys::window()
+            .and_then(|win| win.document())
+            .and_then(|doc| {
+                let dst = doc.get_element_by_id("wasm-example")?;
+                let canvas = web_sys::Element::from(window.canvas());
+                dst.append_child(&canvas).ok()?;
+                Some(())
+            })
+            .expect("Couldn't append canvas to document body.");
+    }
+
+    let mut state = State::new(window, options.view_formats).await;
+    state.reseed(options.seed.unwrap_or_else(Rng::random_seed));
+    if options.no_mesh_optimize {
+        state.set_mesh_options(MeshOptions { optimize: false, ..Default::default() });
+    }
+    if options.lod_sphere {
+        let lods = primitives::uv_sphere_lods(0.5, 4);
+        let lods = lods.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        if let Err(e) = state.set_mesh_lods(&lods, state.mesh_options) {
+            state.report_error(Severity::Error, e);
+        }
+        state.set_lod_settings(LodSettings { impostor_distance: Some(30.0), ..Default::default() });
+    }
+    if let Some(name) = &options.primitive {
+        if let Err(e) = state.load_primitive(name) {
+            state.report_error(Severity::Error, e);
+        }
+    }
+    if let Some(path) = &options.scene {
+        if let Err(e) = state.load_scene(path) {
+            state.report_error(Severity::Error, e);
+        }
+    }
+    if let Some(width) = options.line_width {
+        state.set_line_width(width);
+    }
+    if options.grid {
+        state.set_infinite_grid(true, GridParams::default());
+    }
+    if options.procedural_sky {
+        state.set_procedural_sky(Some(ProceduralSkyParams::default()));
+    }
+    if let Some(hours) = options.time_of_day {
+        state.set_time_of_day(Some(TimeOfDay::at_hours(hours)));
+    }
+    if options.split_positions {
+        if let Err(e) = state.set_vertex_streams(Some(VertexStreams::split_off(&Vertex::desc(), &[0]))) {
+            state.report_error(Severity::Error, e);
+        }
+    }
+    if options.packed_vertices {
+        if let Err(e) = state.set_packed_vertices(true) {
+            state.report_error(Severity::Error, e);
+        }
+    }
+    if options.color_space_split {
+        if let Err(e) = state.set_color_space_split(true) {
+            state.report_error(Severity::Error, e);
+        }
+    }
+    if options.streaming {
+        state.set_streaming(Some(StreamingSettings::default()));
+        // Above the highest hills, looking down the way it flies
+        state.teleport(Vec3::new(0.0, 25.0, 10.0));
+        state.set_camera_velocity(Vec3::new(0.0, 0.0, -12.0));
+    }
+    // Shows up once it's loaded, the window doesn't wait for it
+    if let Some(path) = &options.point_cloud {
+        state.load_points_async(path);
+    }
+    state.prewarm();
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(dir) = &options.bug_reports {
+        state.set_bug_report_dir(dir.clone());
+    }
+    if options.poll_thread {
+        if let Err(e) = state.spawn_poll_thread() {
+            state.report_error(Severity::Error, format!("Couldn't start the poll thread: {}", e));
+        }
+    }
+    if !options.present_modes.is_empty() {
+        state.set_present_mode_preference(options.present_modes);
+    }
+    if uncapped && !state.set_present_mode(wgpu::PresentMode::Immediate) {
+        log::warn!("Immediate present mode not supported, frame rate stays capped");
+    }
+    // Recordings start from the app as it's set up
+    setup(&mut state);
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(remote) = &options.remote {
+        match state.start_remote(remote) {
+            Ok(address) => log::info!("Remote control listening on {}", address),
+            Err(e) => state.report_error(Severity::Error, format!("Couldn't start remote control on {}: {}", remote.address, e)),
+        }
+    }
+    if let Some(path) = &options.play_input {
+        if let Err(e) = state.play_input_recording(path, options.play_timing.unwrap_or(Timing::RealTime), None) {
+            state.report_error(Severity::Error, e);
+        }
+    }
+    if let Some(path) = &options.record_input {
+        if let Err(e) = state.start_input_recording(path) {
+            state.report_error(Severity::Error, format!("Couldn't record input to {}: {}", path.display(), e));
+        }
+    }
+    state.update_title();
+
+    let mut frame_count = 0u32;
+    let mut frame_count_start = instant::Instant::now();
+    // Requested with the first frame, checked after it
+    let mut screenshot_check = options.check_screenshot.map(|path| (path, false));
+
+    let mut running = Some(state);
+    let mut on_exit = Some(on_exit);
+    event_loop.run(move |event, _, control_flow| {
+        // Gone once exiting, winit may still deliver a few events after that
+        let Some(state) = running.as_mut() else { return };
+        let mut exit = None;
+        match event {
+            // A playing recording is the input, real keys and mouse would make it diverge
+            Event::WindowEvent {
+                ref event,
+                window_id,
+            } if window_id == state.window.id() && !(state.is_playing_input() && input_record::is_input(event)) => {
+                state.record_event(event);
+                exit = handle_window_event(state, event);
+            }
+
+            Event::RedrawRequested(window_id) if window_id == state.window.id() => {
+                log::trace!("Redraw - 2");
+                update(state);
+                state.update();
+                match state.render() {
+                    Ok(_) => {}
+                    Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
+                    Err(wgpu::SurfaceError::OutOfMemory) => exit = Some(ExitReason::OutOfMemory),
+                    Err(e) => state.report_error(Severity::Warning, format!("Surface error: {:?}", e)),
+                }
+
+                if let Some((path, requested)) = &mut screenshot_check {
+                    let pixel = (state.size.width - 1, 0);
+                    if !*requested {
+                        if !state.save_screenshot(path.clone()) || !state.read_pixel(pixel.0, pixel.1) {
+                            log::error!("Surface can't be read back, can't check screenshots");
+                            exit = Some(ExitReason::Check(2));
+                        }
+                        *requested = true;
+                    } else if let Some(read) = state.take_read_pixel() {
+                        let result = read.and_then(|read| check_screenshot(path, pixel, read, state.clear_color, state.config.format));
+                        exit = Some(match result {
+                            Ok(report) => {
+                                log::info!("{}", report);
+                                ExitReason::Check(0)
+                            }
+                            Err(e) => {
+                                log::error!("{}", e);
+                                ExitReason::Check(1)
+                            }
+                        });
+                    }
+                }
+
+                if uncapped {
+                    frame_count += 1;
+                    let elapsed = frame_count_start.elapsed().as_secs_f32();
+                    if elapsed >= 1.0 {
+                        let fps = frame_count as f32 / elapsed;
+                        log::info!("{:.0} fps", fps);
+                        state.set_fps(Some(fps));
+                        frame_count = 0;
+                        frame_count_start = instant::Instant::now();
+                    }
+                }
+            }
+
+            Event::MainEventsCleared => {
+                log::trace!("Main Event Cleared - 1");
+                for event in state.due_recorded_events() {
+                    exit = exit.or(handle_window_event(state, &event));
+                }
+                // Failures are already in the error log
+                for (_, asset) in state.poll_loaded() {
+                    if let Ok(LoadedAsset::PointCloud { buffer, count }) = asset {
+                        state.draw_points(buffer, count, 4.0);
+                    }
+                }
+                state.window().request_redraw();
+            }
+            _ => {}
+        }
+
+        if let Some(reason) = exit {
+            if let Some(mut state) = running.take() {
+                if let Err(e) = state.stop_input_recording() {
+                    log::error!("Couldn't write the input recording: {}", e);
+                }
+                state.shutdown();
+            }
+            let code = on_exit.take().map_or(reason.exit_code(), |on_exit| on_exit(reason));
+            *control_flow = ControlFlow::ExitWithCode(code);
         }
-        _ => {}
     });
 }
@@ -0,0 +1,90 @@
+// Debug lines, see LineBatch in debug_lines.rs. One instance per segment, 6 vertices per quad
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    exposure: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Layout must match LineUniform in debug_lines.rs
+struct LineUniform {
+    // Pixels across
+    width: f32,
+    // Render target size in pixels
+    viewport: vec2<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> lines: LineUniform;
+
+// Layout must match LineInstance in debug_lines.rs
+struct LineInput {
+    @location(0) start: vec3<f32>,
+    @location(1) end: vec3<f32>,
+    @location(2) color: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+    // Pixels from the middle of the line, across it
+    @location(1) distance: f32,
+}
+
+// Debug lines are already in world space, only camera transform is applied. The quad is widened
+// in screen space, so it's the same number of pixels across at any distance
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32, line: LineInput) -> VertexOutput {
+    // x picks the end, y the side
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(0.0, 1.0),
+    );
+    let corner = corners[in_vertex_index];
+
+    var out: VertexOutput;
+    out.color = line.color;
+    var start = camera.view_proj * vec4<f32>(line.start, 1.0);
+    var end = camera.view_proj * vec4<f32>(line.end, 1.0);
+    // Cut at the near plane (z = 0 in clip space), an end behind the camera would flip through w
+    if start.z < 0.0 && end.z < 0.0 {
+        out.clip_position = vec4<f32>(0.0);
+        out.distance = 0.0;
+        return out;
+    }
+    if start.z < 0.0 {
+        start = mix(start, end, start.z / (start.z - end.z));
+    } else if end.z < 0.0 {
+        end = mix(end, start, end.z / (end.z - start.z));
+    }
+
+    let half_viewport = lines.viewport * 0.5;
+    let screen = (end.xy / end.w - start.xy / start.w) * half_viewport;
+    var direction = vec2<f32>(1.0, 0.0);
+    if dot(screen, screen) > 1e-8 {
+        direction = normalize(screen);
+    }
+    let normal = vec2<f32>(-direction.y, direction.x);
+    // A pixel of falloff on each side, square caps so box corners meet
+    let half_width = lines.width * 0.5;
+    let extent = half_width + 1.0;
+    let pixels = normal * corner.y * extent + direction * (corner.x * 2.0 - 1.0) * half_width;
+
+    let position = select(start, end, corner.x > 0.5);
+    out.clip_position = vec4<f32>(position.xy + pixels / half_viewport * position.w, position.zw);
+    out.distance = corner.y * extent;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Covered part of the pixel, lines thinner than a pixel fade instead of breaking up
+    let coverage = clamp(lines.width * 0.5 + 0.5 - abs(in.distance), 0.0, 1.0) * min(lines.width, 1.0);
+    if coverage <= 0.0 {
+        discard;
+    }
+    return vec4<f32>(in.color, coverage);
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+
+use crate::points::{self, PointVertex};
+use crate::streaming::ChunkData;
+use crate::texture::{decode_hdr_rgba, decode_png_rgba, ColorSpace, Texture};
+use crate::Vertex;
+
+// Files are read in pieces this big so the progress moves
+const READ_CHUNK: usize = 256 * 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LoadHandle(u64);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AssetKind {
+    // PNG, sampled in the given color space
+    Texture(ColorSpace),
+    // Radiance .hdr, see Texture::load_hdr
+    Environment,
+    // See points::load_xyz
+    PointCloud,
+}
+
+// Decoded on the loader thread, ready for upload
+pub enum LoadedData {
+    Texture { rgba: Vec<u8>, width: u32, height: u32, color_space: ColorSpace },
+    Environment { rgba: Vec<f32>, width: u32, height: u32 },
+    PointCloud(Vec<PointVertex>),
+    // From AssetLoader::generate, see ChunkStreamer
+    Chunk(ChunkData),
+    // From AssetLoader::generate, see State::generate_lods_async. Most detailed first
+    MeshLods(Vec<Vec<Vertex>>),
+}
+
+// On the GPU, see State::poll_loaded
+pub enum LoadedAsset {
+    // Shared with State, which writes the file into it again when it changes (see
+    // State::reload_assets)
+    Texture(Arc<Texture>),
+    // Already the background and IBL source
+    Environment,
+    PointCloud { buffer: Arc<wgpu::Buffer>, count: u32 },
+    // Already the mesh, triangles per level
+    MeshLods { triangles: Vec<usize> },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoadState {
+    // Fraction of the file read, decoding happens at 1
+    Loading(f32),
+    // Handed out by poll()
+    Ready,
+    Failed(String),
+}
+
+#[derive(Default)]
+struct Progress {
+    read: AtomicU64,
+    total: AtomicU64,
+}
+
+impl Progress {
+    fn fraction(&self) -> f32 {
+        let total = self.total.load(Ordering::Relaxed);
+        if total == 0 {
+            return 0.0;
+        }
+        self.read.load(Ordering::Relaxed) as f32 / total as f32
+    }
+}
+
+type Generator = Box<dyn FnOnce() -> Result<LoadedData, String> + Send>;
+
+enum Work {
+    File { kind: AssetKind, path: PathBuf },
+    Generate(Generator),
+}
+
+impl Work {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn run(self, progress: &Progress) -> Result<LoadedData, String> {
+        match self {
+            Work::File { kind, path } => read_with_progress(&path, progress).and_then(|bytes| decode(kind, &path, &bytes)),
+            Work::Generate(generate) => generate(),
+        }
+    }
+}
+
+struct Job {
+    handle: LoadHandle,
+    work: Work,
+    progress: Arc<Progress>,
+}
+
+type JobResult = (LoadHandle, Result<LoadedData, String>);
+
+// Reads and decodes files (or runs generate() jobs) off the main thread, GPU upload stays with the
+// caller (see State::poll_loaded). Natively one worker thread takes the jobs in order, started
+// with the first one and stopped when the loader is dropped. On the web paths are URLs, fetched as
+// tasks
+pub struct AssetLoader {
+    next_handle: u64,
+    #[cfg(not(target_arch = "wasm32"))]
+    jobs: Option<Sender<Job>>,
+    result_sender: Sender<JobResult>,
+    results: Receiver<JobResult>,
+    pending: HashMap<LoadHandle, Arc<Progress>>,
+    finished: HashMap<LoadHandle, Result<(), String>>,
+}
+
+impl AssetLoader {
+    pub fn new() -> Self {
+        let (result_sender, results) = std::sync::mpsc::channel();
+        Self {
+            next_handle: 0,
+            #[cfg(not(target_arch = "wasm32"))]
+            jobs: None,
+            result_sender,
+            results,
+            pending: HashMap::new(),
+            finished: HashMap::new(),
+        }
+    }
+
+    // Returns right away, the data shows up in poll() once it's decoded
+    pub fn load(&mut self, kind: AssetKind, path: &Path) -> LoadHandle {
+        self.queue(Work::File { kind, path: path.to_path_buf() })
+    }
+
+    // Runs `generate` on the worker, for procedural data. Its progress stays 0 until it's done. On
+    // the web it runs on the main thread, as a task
+    pub fn generate(&mut self, generate: impl FnOnce() -> Result<LoadedData, String> + Send + 'static) -> LoadHandle {
+        self.queue(Work::Generate(Box::new(generate)))
+    }
+
+    // Reads and decodes the file again under a handle that already finished, for hot reloading.
+    // Its state goes back to loading until poll() hands the new data out
+    pub fn reload(&mut self, handle: LoadHandle, kind: AssetKind, path: &Path) {
+        self.finished.remove(&handle);
+        self.queue_as(handle, Work::File { kind, path: path.to_path_buf() });
+    }
+
+    fn queue(&mut self, work: Work) -> LoadHandle {
+        let handle = LoadHandle(self.next_handle);
+        self.next_handle += 1;
+        self.queue_as(handle, work);
+        handle
+    }
+
+    fn queue_as(&mut self, handle: LoadHandle, work: Work) {
+        let progress = Arc::new(Progress::default());
+        self.pending.insert(handle, progress.clone());
+        self.spawn(Job { handle, work, progress });
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn spawn(&mut self, job: Job) {
+        if self.jobs.is_none() {
+            let (sender, jobs) = std::sync::mpsc::channel::<Job>();
+            let results = self.result_sender.clone();
+            let spawned = std::thread::Builder::new()
+                .name("Asset Loader".into())
+                .spawn(move || {
+                    // Ends once the loader (and with it the sender) is gone
+                    for job in jobs {
+                        let result = job.work.run(&job.progress);
+                        if results.send((job.handle, result)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            if let Err(e) = spawned {
+                let _ = self.result_sender.send((job.handle, Err(format!("Couldn't start the loader thread: {}", e))));
+                return;
+            }
+            self.jobs = Some(sender);
+        }
+        if let Some(jobs) = &self.jobs {
+            // Can only fail with the thread gone, which only happens on drop
+            let _ = jobs.send(job);
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn spawn(&mut self, job: Job) {
+        let results = self.result_sender.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = match job.work {
+                Work::File { kind, path } => fetch(&path, &job.progress).await.and_then(|bytes| decode(kind, &path, &bytes)),
+                Work::Generate(generate) => generate(),
+            };
+            let _ = results.send((job.handle, result));
+        });
+    }
+
+    // Everything finished since the last call, in the order it finished
+    pub fn poll(&mut self) -> Vec<(LoadHandle, Result<LoadedData, String>)> {
+        let results = self.results.try_iter().collect::<Vec<_>>();
+        for (handle, result) in &results {
+            self.pending.remove(handle);
+            self.finished.insert(*handle, result.as_ref().map(|_| ()).map_err(Clone::clone));
+        }
+        results
+    }
+
+    // None for handles this loader didn't hand out
+    pub fn state(&self, handle: LoadHandle) -> Option<LoadState> {
+        if let Some(progress) = self.pending.get(&handle) {
+            return Some(LoadState::Loading(progress.fraction()));
+        }
+        self.finished.get(&handle).map(|result| match result {
+            Ok(()) => LoadState::Ready,
+            Err(e) => LoadState::Failed(e.clone()),
+        })
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Average over everything still loading, None when nothing is
+    pub fn progress(&self) -> Option<f32> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        Some(self.pending.values().map(|progress| progress.fraction()).sum::<f32>() / self.pending.len() as f32)
+    }
+}
+
+impl Default for AssetLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_with_progress(path: &Path, progress: &Progress) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+
+    let error = |e: std::io::Error| format!("Couldn't read {}: {}", path.display(), e);
+    let mut file = std::fs::File::open(path).map_err(error)?;
+    let total = file.metadata().map_err(error)?.len();
+    progress.total.store(total, Ordering::Relaxed);
+    let mut bytes = Vec::with_capacity(total as usize);
+    let mut chunk = vec![0; READ_CHUNK];
+    loop {
+        let read = file.read(&mut chunk).map_err(error)?;
+        if read == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&chunk[..read]);
+        progress.read.store(bytes.len() as u64, Ordering::Relaxed);
+    }
+    Ok(bytes)
+}
+
+// No streaming progress here, it jumps to 1 once the body is in
+#[cfg(target_arch = "wasm32")]
+async fn fetch(path: &Path, progress: &Progress) -> Result<Vec<u8>, String> {
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+
+    let url = path.to_string_lossy();
+    let error = |e: wasm_bindgen::JsValue| format!("Couldn't fetch {}: {:?}", url, e);
+    let window = web_sys::window().ok_or_else(|| format!("Couldn't fetch {}: no window", url))?;
+    let response = JsFuture::from(window.fetch_with_str(&url)).await.map_err(error)?;
+    let response = response.dyn_into::<web_sys::Response>().map_err(error)?;
+    if !response.ok() {
+        return Err(format!("Couldn't fetch {}: HTTP {}", url, response.status()));
+    }
+    let buffer = JsFuture::from(response.array_buffer().map_err(error)?).await.map_err(error)?;
+    let bytes = js_sys::Uint8Array::new(&buffer).to_vec();
+    progress.total.store(bytes.len() as u64, Ordering::Relaxed);
+    progress.read.store(bytes.len() as u64, Ordering::Relaxed);
+    Ok(bytes)
+}
+
+fn decode(kind: AssetKind, path: &Path, bytes: &[u8]) -> Result<LoadedData, String> {
+    let error = |e: String| format!("{}: {}", path.display(), e);
+    match kind {
+        AssetKind::Texture(color_space) => {
+            let (rgba, width, height) = decode_png_rgba(bytes).map_err(|e| error(e.to_string()))?;
+            Ok(LoadedData::Texture { rgba, width, height, color_space })
+        }
+        AssetKind::Environment => {
+            let (rgba, width, height) = decode_hdr_rgba(bytes).map_err(error)?;
+            Ok(LoadedData::Environment { rgba, width, height })
+        }
+        AssetKind::PointCloud => {
+            let text = std::str::from_utf8(bytes).map_err(|e| error(e.to_string()))?;
+            points::parse_xyz(text, path).map(LoadedData::PointCloud)
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::camera::{Camera, Projection};
+use crate::instance::Instance;
+use crate::math::{Aabb, Vec3};
+
+// Distinct enough to tell apart at a glance, LOD 0 first. Levels past the end reuse the last one.
+// Must match lod_tint in shader.wgsl
+pub const LOD_COLORS: [[f32; 3]; 5] = [
+    [0.2, 0.9, 0.2],
+    [0.9, 0.9, 0.2],
+    [0.95, 0.55, 0.1],
+    [0.9, 0.15, 0.15],
+    [0.7, 0.2, 0.9],
+];
+
+#[derive(Clone, Debug)]
+pub struct LodSettings {
+    // An instance switches from LOD i to LOD i + 1 when its bounding sphere covers less than
+    // radii[i] pixels (radius, projected). Levels without an entry are never picked
+    pub radii: Vec<f32>,
+    // Fraction of the threshold an instance has to get past before it switches, so one sitting
+    // right at a threshold doesn't flip every frame
+    pub hysteresis: f32,
+    // Instances further than this from the camera start turning into impostors (see impostor.rs),
+    // None for never. They fade in over impostor_fade, the mesh is dropped once they're opaque
+    pub impostor_distance: Option<f32>,
+    pub impostor_fade: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            radii: vec![80.0, 40.0, 20.0, 10.0],
+            hysteresis: 0.15,
+            impostor_distance: None,
+            impostor_fade: 5.0,
+        }
+    }
+}
+
+// Where an instance is with respect to the impostor distance
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Impostor {
+    None,
+    // Mesh and impostor, the impostor fading in over it
+    Fading,
+    // Only the impostor
+    Full,
+}
+
+// Instances drawn with one LOD, a range of the reordered instance buffer
+#[derive(Clone, Debug)]
+pub struct LodBatch {
+    pub level: usize,
+    pub instances: Range<u32>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LodStats {
+    // Instances per level
+    pub instances: Vec<usize>,
+    pub triangles: u64,
+    // Drawn as impostors, fading ones are counted in `instances` as well
+    pub impostors: usize,
+    // Outside the culling frustum, not drawn at all
+    pub culled: usize,
+    // What everything at LOD 0 would have been
+    pub full_triangles: u64,
+}
+
+impl LodStats {
+    pub fn triangles_saved(&self) -> u64 {
+        self.full_triangles - self.triangles
+    }
+}
+
+// One line for the debug overlay, e.g. "LOD 0: 12, 1: 30, 2: 58 - 9120 of 48000 triangles, 81% saved"
+impl fmt::Display for LodStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LOD")?;
+        for (level, count) in self.instances.iter().enumerate() {
+            write!(f, "{} {}: {}", if level == 0 { "" } else { "," }, level, count)?;
+        }
+        if self.impostors > 0 {
+            write!(f, ", impostor: {}", self.impostors)?;
+        }
+        if self.culled > 0 {
+            write!(f, ", culled: {}", self.culled)?;
+        }
+        let saved = self.triangles_saved() as f64 / self.full_triangles.max(1) as f64 * 100.0;
+        write!(f, " - {} of {} triangles, {:.0}% saved", self.triangles, self.full_triangles, saved)
+    }
+}
+
+// Picks a level of detail per instance from how big it is on screen and groups the instances by
+// it, so each level is one instanced draw. The levels are vertex ranges of the one vertex buffer,
+// LOD 0 first and most detailed. Past LodSettings::impostor_distance instances become impostors,
+// those are grouped after the meshes. Instances outside the culling frustum go last and aren't in
+// any draw. The instance buffer has to hold the instances in order()
+pub struct LodSelector {
+    levels: Vec<Range<u32>>,
+    // Object space bounding sphere of LOD 0
+    center: Vec3,
+    radius: f32,
+    pub settings: LodSettings,
+    // Per instance, in the instances' own order
+    selected: Vec<usize>,
+    impostors: Vec<Impostor>,
+    visible: Vec<bool>,
+    // Instance indices grouped by level, and where each instance ended up in there
+    order: Vec<u32>,
+    slots: Vec<u32>,
+    // Mesh draws, impostors not included
+    batches: Vec<LodBatch>,
+    impostor_instances: Range<u32>,
+}
+
+impl LodSelector {
+    // A single level, `vertex_count` vertices from the start of the buffer
+    pub fn new(vertex_count: u32, bounds: &Aabb) -> Self {
+        let lod0 = 0..vertex_count;
+        let mut selector = Self {
+            levels: Vec::new(),
+            center: Vec3::ZERO,
+            radius: 0.0,
+            settings: LodSettings::default(),
+            selected: Vec::new(),
+            impostors: Vec::new(),
+            visible: Vec::new(),
+            order: Vec::new(),
+            slots: Vec::new(),
+            batches: Vec::new(),
+            impostor_instances: 0..0,
+        };
+        selector.set_levels(vec![lod0], bounds);
+        selector
+    }
+
+    // Forgets the selection, the next select() regroups everything
+    pub fn set_levels(&mut self, levels: Vec<Range<u32>>, bounds: &Aabb) {
+        self.levels = levels;
+        self.center = bounds.center();
+        self.radius = (bounds.max - bounds.min).length() / 2.0;
+        self.selected.clear();
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    // Vertex range of `level` in the vertex buffer
+    pub fn vertices(&self, level: usize) -> Range<u32> {
+        self.levels[level].clone()
+    }
+
+    // Vertex range for instance `instance` (in the instances' order)
+    pub fn instance_vertices(&self, instance: usize) -> Range<u32> {
+        self.vertices(self.selected.get(instance).copied().unwrap_or(0))
+    }
+
+    pub fn level_of(&self, instance: usize) -> usize {
+        self.selected.get(instance).copied().unwrap_or(0)
+    }
+
+    // Instance indices in the order the instance buffer holds them
+    pub fn order(&self) -> &[u32] {
+        &self.order
+    }
+
+    // Where instance `instance` is in the instance buffer
+    pub fn slot(&self, instance: u32) -> u32 {
+        self.slots.get(instance as usize).copied().unwrap_or(instance)
+    }
+
+    pub fn batches(&self) -> &[LodBatch] {
+        &self.batches
+    }
+
+    // Instances to draw as impostors, fading in ones included (those are in batches() too)
+    pub fn impostor_instances(&self) -> Range<u32> {
+        self.impostor_instances.clone()
+    }
+
+    pub fn is_impostor(&self, instance: usize) -> bool {
+        self.impostors.get(instance).is_some_and(|&impostor| impostor != Impostor::None)
+    }
+
+    pub fn is_visible(&self, instance: usize) -> bool {
+        self.visible.get(instance).copied().unwrap_or(true)
+    }
+
+    // Selects levels for this frame. `visible` has a flag per instance, the false ones are culled
+    // (None draws everything, see Bvh::query_frustum). Returns true when the grouping changed and
+    // the instance buffer has to be written again in order()
+    pub fn select(&mut self, camera: &Camera, visible: Option<&[bool]>, viewport_height: f32, instances: &[Instance]) -> bool {
+        let mut changed = self.selected.len() != instances.len();
+        if changed {
+            self.selected = vec![0; instances.len()];
+            self.impostors = vec![Impostor::None; instances.len()];
+            self.visible = vec![true; instances.len()];
+        }
+        // Levels past the thresholds can't be reached
+        let last = self.levels.len().min(self.settings.radii.len() + 1).max(1) - 1;
+        for (instance, selected) in instances.iter().zip(&mut self.selected) {
+            let center = instance.model_matrix().transform_point(self.center);
+            let pixels = projected_radius(camera, viewport_height, center, self.radius);
+            let mut level = (*selected).min(last);
+            while level < last && pixels < self.settings.radii[level] * (1.0 - self.settings.hysteresis) {
+                level += 1;
+            }
+            while level > 0 && pixels > self.settings.radii[level - 1] * (1.0 + self.settings.hysteresis) {
+                level -= 1;
+            }
+            if level != *selected {
+                *selected = level;
+                changed = true;
+            }
+        }
+        for (instance, impostor) in instances.iter().zip(&mut self.impostors) {
+            let new = match self.settings.impostor_distance {
+                Some(start) => {
+                    let distance = (instance.model_matrix().transform_point(self.center) - camera.eye).length();
+                    if distance > start + self.settings.impostor_fade {
+                        Impostor::Full
+                    } else if distance > start {
+                        Impostor::Fading
+                    } else {
+                        Impostor::None
+                    }
+                }
+                None => Impostor::None,
+            };
+            if new != *impostor {
+                *impostor = new;
+                changed = true;
+            }
+        }
+        for (i, was_visible) in self.visible.iter_mut().enumerate() {
+            let new = visible.is_none_or(|visible| visible.get(i).copied().unwrap_or(true));
+            if new != *was_visible {
+                *was_visible = new;
+                changed = true;
+            }
+        }
+        if changed {
+            self.regroup();
+        }
+        changed
+    }
+
+    // Groups by level, then fading impostors by level, then impostors only, then culled. The
+    // fading ones are in both the mesh batches and the impostor range that way
+    fn group(&self, instance: usize) -> usize {
+        let levels = self.levels.len();
+        if !self.visible[instance] {
+            return levels * 2 + 1;
+        }
+        match self.impostors[instance] {
+            Impostor::None => self.selected[instance],
+            Impostor::Fading => levels + self.selected[instance],
+            Impostor::Full => levels * 2,
+        }
+    }
+
+    // Counting sort by group(), keeps the instances' order within a group
+    fn regroup(&mut self) {
+        let levels = self.levels.len();
+        let mut counts = vec![0u32; levels * 2 + 2];
+        for instance in 0..self.selected.len() {
+            counts[self.group(instance)] += 1;
+        }
+        self.batches.clear();
+        let mut starts = Vec::with_capacity(counts.len());
+        let mut start = 0;
+        for (group, &count) in counts.iter().enumerate() {
+            starts.push(start);
+            if count > 0 && group < levels * 2 {
+                self.batches.push(LodBatch { level: group % levels, instances: start..start + count });
+            }
+            start += count;
+        }
+        self.impostor_instances = starts[levels]..starts[levels * 2 + 1];
+        self.order = vec![0; self.selected.len()];
+        self.slots = vec![0; self.selected.len()];
+        for instance in 0..self.selected.len() {
+            let group = self.group(instance);
+            let slot = starts[group];
+            starts[group] += 1;
+            self.order[slot as usize] = instance as u32;
+            self.slots[instance] = slot;
+        }
+    }
+
+    pub fn stats(&self) -> LodStats {
+        let triangles = |level: usize| (self.levels[level].len() / 3) as u64;
+        let mut stats = LodStats { instances: vec![0; self.levels.len()], ..Default::default() };
+        for batch in &self.batches {
+            let count = batch.instances.len();
+            stats.instances[batch.level] += count;
+            stats.triangles += triangles(batch.level) * count as u64;
+            stats.full_triangles += triangles(0) * count as u64;
+        }
+        let full_impostors = (0..self.visible.len())
+            .filter(|&instance| self.visible[instance] && self.impostors[instance] == Impostor::Full)
+            .count();
+        stats.impostors = self.impostor_instances.len();
+        stats.culled = self.visible.iter().filter(|&&visible| !visible).count();
+        // Two triangles per quad
+        stats.triangles += 2 * stats.impostors as u64;
+        stats.full_triangles += triangles(0) * (full_impostors + stats.culled) as u64;
+        stats
+    }
+}
+
+// Bounding sphere radius in pixels, as if at the middle of the screen. Infinite with the camera
+// inside the sphere
+pub fn projected_radius(camera: &Camera, viewport_height: f32, center: Vec3, radius: f32) -> f32 {
+    let half_fov = (camera.fovy.to_radians() / 2.0).tan();
+    let half_height = match camera.projection {
+        Projection::Perspective => {
+            let distance = (center - camera.eye).length();
+            if distance <= radius {
+                return f32::INFINITY;
+            }
+            distance * half_fov
+        }
+        // Same size as the plane through the target, see Camera::build_view_projection_matrix
+        Projection::Orthographic => (camera.target - camera.eye).length() * half_fov,
+    };
+    radius / half_height * viewport_height / 2.0
+}
@@ -0,0 +1,390 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use crate::readback;
+
+// Must match TILE in luminance.wgsl
+const TILE_SIZE: u32 = 16;
+// min, max, average, count as vec4<f32>
+const RESULT_SIZE: wgpu::BufferAddress = 16;
+// Must match luminance.wgsl. Bin 0 is everything darker than 2^HISTOGRAM_MIN_LOG2, bins
+// 1..HISTOGRAM_BINS split [HISTOGRAM_MIN_LOG2, HISTOGRAM_MIN_LOG2 + HISTOGRAM_LOG2_RANGE] evenly
+pub const HISTOGRAM_BINS: usize = 64;
+pub const HISTOGRAM_MIN_LOG2: f32 = -10.0;
+pub const HISTOGRAM_LOG2_RANGE: f32 = 12.0;
+const HISTOGRAM_SIZE: wgpu::BufferAddress = (HISTOGRAM_BINS * 4) as wgpu::BufferAddress;
+
+// map_state values, written by the map_async callback. Same scheme as occlusion.rs
+const MAP_WAITING: u8 = 0;
+const MAP_DONE: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+// Rec. 709 luminance of linear color
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LuminanceStats {
+    pub min: f32,
+    pub max: f32,
+    pub average: f32,
+}
+
+impl LuminanceStats {
+    // Reference for the GPU version, `rgba` is linear with 4 floats per texel
+    pub fn from_rgba_cpu(rgba: &[f32]) -> Self {
+        let mut stats = Self { min: f32::MAX, max: f32::MIN, average: 0.0 };
+        let mut sum = 0.0f64;
+        let texels = rgba.chunks_exact(4);
+        let count = texels.len();
+        for texel in texels {
+            let l = 0.2126 * texel[0] + 0.7152 * texel[1] + 0.0722 * texel[2];
+            stats.min = stats.min.min(l);
+            stats.max = stats.max.max(l);
+            sum += l as f64;
+        }
+        stats.average = (sum / count.max(1) as f64) as f32;
+        stats
+    }
+}
+
+// Texel counts by log2 luminance, see HISTOGRAM_BINS
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LuminanceHistogram {
+    pub bins: [u32; HISTOGRAM_BINS],
+}
+
+impl LuminanceHistogram {
+    // Reference for the GPU version, same input as LuminanceStats::from_rgba_cpu
+    pub fn from_rgba_cpu(rgba: &[f32]) -> Self {
+        let mut bins = [0; HISTOGRAM_BINS];
+        for texel in rgba.chunks_exact(4) {
+            let l = 0.2126 * texel[0] + 0.7152 * texel[1] + 0.0722 * texel[2];
+            bins[Self::bin(l)] += 1;
+        }
+        Self { bins }
+    }
+
+    // Mirrors histogram_bin in luminance.wgsl
+    pub fn bin(luminance: f32) -> usize {
+        if luminance < HISTOGRAM_MIN_LOG2.exp2() {
+            return 0;
+        }
+        let t = ((luminance.log2() - HISTOGRAM_MIN_LOG2) / HISTOGRAM_LOG2_RANGE).clamp(0.0, 1.0);
+        (1 + (t * (HISTOGRAM_BINS - 1) as f32) as usize).min(HISTOGRAM_BINS - 1)
+    }
+
+    // log2 luminance in the middle of bin 1.., bin 0 has no meaningful center
+    pub fn bin_center_log2(bin: usize) -> f32 {
+        HISTOGRAM_MIN_LOG2 + (bin as f32 - 0.5) * HISTOGRAM_LOG2_RANGE / (HISTOGRAM_BINS - 1) as f32
+    }
+
+    pub fn total(&self) -> u64 {
+        self.bins.iter().map(|&count| count as u64).sum()
+    }
+}
+
+// Luminance of a frame on the GPU: a workgroup per 16x16 tile reduces in shared memory, then a
+// second pass folds the tiles. Any size works, tiles past the edge just have fewer texels.
+// dispatch() results come back without stalling a frame or two later (last_result), measure()
+// blocks. A log2 luminance histogram is built in the same go. Needs compute shaders, so None on
+// WebGL2
+pub struct LuminanceReduction {
+    bind_group_layout: wgpu::BindGroupLayout,
+    tiles_pipeline: wgpu::ComputePipeline,
+    final_pipeline: wgpu::ComputePipeline,
+    histogram_pipeline: wgpu::ComputePipeline,
+    partials: wgpu::Buffer,
+    result: wgpu::Buffer,
+    histogram: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // A copy into readback_buffer is waiting for the map to finish
+    pending: bool,
+    mapping: bool,
+    map_state: Arc<AtomicU8>,
+    last: Option<LuminanceStats>,
+    last_histogram: Option<LuminanceHistogram>,
+}
+
+impl LuminanceReduction {
+    pub fn new(device: &wgpu::Device, adapter: &wgpu::Adapter) -> Option<Self> {
+        if !adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS) {
+            return None;
+        }
+        let shader = device.create_shader_module(wgpu::include_wgsl!("luminance.wgsl"));
+
+        let storage = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Luminance Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        // textureLoad only, any float format works
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                storage(1),
+                storage(2),
+                storage(3),
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Luminance Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point,
+        });
+
+        let buffer = |label, size, usage| device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            bind_group_layout,
+            tiles_pipeline: pipeline("cs_tiles"),
+            final_pipeline: pipeline("cs_final"),
+            histogram_pipeline: pipeline("cs_histogram"),
+            // Grown in dispatch() to fit the texture
+            partials: buffer("Luminance Partials", RESULT_SIZE, wgpu::BufferUsages::STORAGE),
+            result: buffer("Luminance Result", RESULT_SIZE, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC),
+            histogram: buffer(
+                "Luminance Histogram",
+                HISTOGRAM_SIZE,
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            ),
+            // Result followed by the histogram
+            readback_buffer: buffer(
+                "Luminance Readback Buffer",
+                RESULT_SIZE + HISTOGRAM_SIZE,
+                wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            ),
+            pending: false,
+            mapping: false,
+            map_state: Arc::new(AtomicU8::new(MAP_WAITING)),
+            last: None,
+            last_histogram: None,
+        })
+    }
+
+    // Records the reduction and histogram over mip 0 of `view` (width x height), the result stays on the GPU
+    fn record(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, width: u32, height: u32) {
+        let tiles = (width.div_ceil(TILE_SIZE), height.div_ceil(TILE_SIZE));
+        let partials_size = (tiles.0 * tiles.1) as wgpu::BufferAddress * RESULT_SIZE;
+        if self.partials.size() < partials_size {
+            self.partials = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Luminance Partials"),
+                size: partials_size,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            });
+        }
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Luminance Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.partials.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.result.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.histogram.as_entire_binding(),
+                },
+            ],
+        });
+        encoder.clear_buffer(&self.histogram, 0, None);
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Luminance Pass"),
+        });
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.set_pipeline(&self.tiles_pipeline);
+        compute_pass.dispatch_workgroups(tiles.0, tiles.1, 1);
+        compute_pass.set_pipeline(&self.final_pipeline);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+        compute_pass.set_pipeline(&self.histogram_pipeline);
+        compute_pass.dispatch_workgroups(tiles.0, tiles.1, 1);
+    }
+
+    // Result and histogram into `buffer` at 0, laid out for results_from_bytes
+    fn copy_results(&self, encoder: &mut wgpu::CommandEncoder, buffer: &wgpu::Buffer) {
+        encoder.copy_buffer_to_buffer(&self.result, 0, buffer, 0, RESULT_SIZE);
+        encoder.copy_buffer_to_buffer(&self.histogram, 0, buffer, RESULT_SIZE, HISTOGRAM_SIZE);
+    }
+
+    // Measures `view` this frame and copies the result for readback. Skipped while the previous
+    // readback is still in flight, returns whether it was recorded
+    pub fn dispatch(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, width: u32, height: u32) -> bool {
+        if self.pending {
+            return false;
+        }
+        self.record(device, encoder, view, width, height);
+        self.copy_results(encoder, &self.readback_buffer);
+        self.pending = true;
+        true
+    }
+
+    // Call after the encoder passed to dispatch() was submitted
+    pub fn after_submit(&mut self) {
+        if !self.pending || self.mapping {
+            return;
+        }
+        self.mapping = true;
+        let map_state = self.map_state.clone();
+        self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            map_state.store(if result.is_ok() { MAP_DONE } else { MAP_FAILED }, Ordering::Release);
+        });
+    }
+
+    // Picks up the result once the readback buffer is mapped, doesn't wait for it.
+    // Returns true when a new result arrived
+    pub fn poll(&mut self, device: &wgpu::Device) -> bool {
+        if !self.mapping {
+            return false;
+        }
+        device.poll(wgpu::Maintain::Poll);
+        match self.map_state.swap(MAP_WAITING, Ordering::Acquire) {
+            MAP_WAITING => return false,
+            MAP_FAILED => {
+                self.pending = false;
+                self.mapping = false;
+                return false;
+            }
+            _ => {}
+        }
+
+        {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let (stats, histogram) = Self::results_from_bytes(&data);
+            self.last = Some(stats);
+            self.last_histogram = Some(histogram);
+        } // Mapped view has to be dropped before unmap
+        self.readback_buffer.unmap();
+        self.pending = false;
+        self.mapping = false;
+        true
+    }
+
+    // Last result of dispatch(), None before the first one arrives
+    pub fn last_result(&self) -> Option<LuminanceStats> {
+        self.last
+    }
+
+    // Histogram of the same dispatch as last_result
+    pub fn last_histogram(&self) -> Option<&LuminanceHistogram> {
+        self.last_histogram.as_ref()
+    }
+
+    fn results_from_bytes(data: &[u8]) -> (LuminanceStats, LuminanceHistogram) {
+        let values: &[f32] = bytemuck::cast_slice(&data[..RESULT_SIZE as usize]);
+        let mut bins = [0; HISTOGRAM_BINS];
+        bins.copy_from_slice(bytemuck::cast_slice(&data[RESULT_SIZE as usize..]));
+        (LuminanceStats { min: values[0], max: values[1], average: values[2] }, LuminanceHistogram { bins })
+    }
+
+    // Blocks until the GPU is done, for tools and tests
+    pub fn measure(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> (LuminanceStats, LuminanceHistogram) {
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance Measure Buffer"),
+            size: RESULT_SIZE + HISTOGRAM_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Luminance Measure Encoder")
+        });
+        self.record(device, &mut encoder, view, width, height);
+        self.copy_results(&mut encoder, &staging_buffer);
+        queue.submit(std::iter::once(encoder.finish()));
+        readback::map_read(device, &staging_buffer, Self::results_from_bytes)
+    }
+
+    // Estimate, see texture::estimated_bytes
+    pub fn gpu_memory(&self) -> u64 {
+        [&self.partials, &self.result, &self.histogram, &self.readback_buffer].iter().map(|b| b.size()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::Texture;
+
+    // The GPU reduction against LuminanceStats::from_rgba_cpu on a synthetic gradient that isn't a
+    // multiple of the tile size
+    #[test]
+    fn reduction_matches_the_cpu() {
+        let (Some(adapter), Some((device, queue))) = (crate::shader_test::adapter(), crate::shader_test::device()) else {
+            return;
+        };
+        let Some(mut luminance) = LuminanceReduction::new(device, adapter) else {
+            return;
+        };
+        let (width, height) = (333, 77);
+        let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let u = x as f32 / (width - 1) as f32;
+                let v = y as f32 / (height - 1) as f32;
+                // Some HDR values above 1 too
+                rgba.extend_from_slice(&[u * 4.0, v, u * v, 1.0]);
+            }
+        }
+        let texture = Texture::from_rgba_f32(device, queue, &rgba, width, height, "Luminance Verify Texture");
+        let (gpu, gpu_histogram) = luminance.measure(device, queue, &texture.view, width, height);
+        let cpu = LuminanceStats::from_rgba_cpu(&rgba);
+        let cpu_histogram = LuminanceHistogram::from_rgba_cpu(&rgba);
+
+        // The texture holds half floats, about 3 significant digits
+        let close = |a: f32, b: f32| (a - b).abs() <= 1e-3 + 2e-3 * b.abs();
+        assert!(
+            close(gpu.min, cpu.min) && close(gpu.max, cpu.max) && close(gpu.average, cpu.average),
+            "GPU {:?} doesn't match CPU {:?}",
+            gpu, cpu
+        );
+        // Rounding can push texels right at a bin edge into its neighbour, allow 1% of them
+        let moved = gpu_histogram.bins.iter().zip(&cpu_histogram.bins)
+            .map(|(&a, &b)| a.abs_diff(b) as u64)
+            .sum::<u64>() / 2;
+        assert_eq!(gpu_histogram.total(), (width * height) as u64);
+        assert!(
+            moved * 100 <= (width * height) as u64,
+            "GPU histogram {:?} doesn't match CPU {:?}",
+            gpu_histogram.bins, cpu_histogram.bins
+        );
+    }
+}
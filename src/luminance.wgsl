@@ -0,0 +1,129 @@
+// Min / max / average luminance of a texture in two compute passes, see luminance.rs:
+// 1. cs_tiles: one workgroup per TILE x TILE block, reduced in workgroup memory to one partial
+// 2. cs_final: one workgroup folds all partials into the result
+// cs_histogram bins log2 luminance for auto exposure, see exposure.rs
+
+// Must match TILE_SIZE in luminance.rs
+const TILE: u32 = 16u;
+const THREADS: u32 = 256u;
+const BIG: f32 = 3.0e38;
+// Must match the HISTOGRAM_ constants in luminance.rs. Bin 0 takes everything darker than
+// 2^HISTOGRAM_MIN_LOG2 (black included), the rest split the log2 range evenly
+const HISTOGRAM_BINS: u32 = 64u;
+const HISTOGRAM_MIN_LOG2: f32 = -10.0;
+const HISTOGRAM_LOG2_RANGE: f32 = 12.0;
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+// min, max, sum, texel count per tile
+@group(0) @binding(1)
+var<storage, read_write> partials: array<vec4<f32>>;
+// min, max, average, texel count
+@group(0) @binding(2)
+var<storage, read_write> result: vec4<f32>;
+// Texel count per bin, cleared before each dispatch
+@group(0) @binding(3)
+var<storage, read_write> histogram: array<atomic<u32>, HISTOGRAM_BINS>;
+
+var<workgroup> shared_min: array<f32, THREADS>;
+var<workgroup> shared_max: array<f32, THREADS>;
+var<workgroup> shared_sum: array<f32, THREADS>;
+var<workgroup> shared_count: array<f32, THREADS>;
+var<workgroup> shared_bins: array<atomic<u32>, HISTOGRAM_BINS>;
+
+// Rec. 709, expects linear color (sRGB textures are decoded by the load)
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+fn store(index: u32, value: vec4<f32>) {
+    shared_min[index] = value.x;
+    shared_max[index] = value.y;
+    shared_sum[index] = value.z;
+    shared_count[index] = value.w;
+}
+
+fn combine(a: vec4<f32>, b: vec4<f32>) -> vec4<f32> {
+    return vec4<f32>(min(a.x, b.x), max(a.y, b.y), a.z + b.z, a.w + b.w);
+}
+
+// Tree reduction of the workgroup's values into index 0. Every invocation has to call it
+fn reduce(local_index: u32) -> vec4<f32> {
+    workgroupBarrier();
+    for (var stride = THREADS / 2u; stride > 0u; stride = stride / 2u) {
+        if local_index < stride {
+            let other = local_index + stride;
+            store(local_index, combine(
+                vec4<f32>(shared_min[local_index], shared_max[local_index], shared_sum[local_index], shared_count[local_index]),
+                vec4<f32>(shared_min[other], shared_max[other], shared_sum[other], shared_count[other]),
+            ));
+        }
+        workgroupBarrier();
+    }
+    return vec4<f32>(shared_min[0], shared_max[0], shared_sum[0], shared_count[0]);
+}
+
+@compute @workgroup_size(16, 16)
+fn cs_tiles(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(num_workgroups) workgroups: vec3<u32>,
+) {
+    let size = textureDimensions(t_source);
+    // Edge tiles hang over the texture, those invocations add nothing
+    var value = vec4<f32>(BIG, -BIG, 0.0, 0.0);
+    if all(global_id.xy < size) {
+        let l = luminance(textureLoad(t_source, vec2<i32>(global_id.xy), 0).rgb);
+        value = vec4<f32>(l, l, l, 1.0);
+    }
+    store(local_index, value);
+    let tile = reduce(local_index);
+    if local_index == 0u {
+        partials[workgroup_id.y * workgroups.x + workgroup_id.x] = tile;
+    }
+}
+
+@compute @workgroup_size(256)
+fn cs_final(@builtin(local_invocation_index) local_index: u32) {
+    let tiles = (textureDimensions(t_source) + vec2<u32>(TILE - 1u)) / TILE;
+    let count = tiles.x * tiles.y;
+    var value = vec4<f32>(BIG, -BIG, 0.0, 0.0);
+    for (var i = local_index; i < count; i += THREADS) {
+        value = combine(value, partials[i]);
+    }
+    store(local_index, value);
+    let total = reduce(local_index);
+    if local_index == 0u {
+        result = vec4<f32>(total.x, total.y, total.z / max(total.w, 1.0), total.w);
+    }
+}
+
+fn histogram_bin(l: f32) -> u32 {
+    if l < exp2(HISTOGRAM_MIN_LOG2) {
+        return 0u;
+    }
+    let t = clamp((log2(l) - HISTOGRAM_MIN_LOG2) / HISTOGRAM_LOG2_RANGE, 0.0, 1.0);
+    return min(1u + u32(t * f32(HISTOGRAM_BINS - 1u)), HISTOGRAM_BINS - 1u);
+}
+
+// Same tiles as cs_tiles. Counts go to workgroup memory first so the global atomics only see one
+// add per bin and tile
+@compute @workgroup_size(16, 16)
+fn cs_histogram(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    if local_index < HISTOGRAM_BINS {
+        atomicStore(&shared_bins[local_index], 0u);
+    }
+    workgroupBarrier();
+    if all(global_id.xy < textureDimensions(t_source)) {
+        let l = luminance(textureLoad(t_source, vec2<i32>(global_id.xy), 0).rgb);
+        atomicAdd(&shared_bins[histogram_bin(l)], 1u);
+    }
+    workgroupBarrier();
+    if local_index < HISTOGRAM_BINS {
+        atomicAdd(&histogram[local_index], atomicLoad(&shared_bins[local_index]));
+    }
+}
@@ -1,7 +1,76 @@
-mod lib;
-use lib::run;
+use WGpuPlayground::image_diff::{compare_images_with, DiffOptions};
+use WGpuPlayground::input_map::InputMap;
+use WGpuPlayground::input_record::Timing;
+use WGpuPlayground::remote::RemoteOptions;
+use WGpuPlayground::{run_with, RunOptions, LOW_LATENCY_PRESENT_MODES};
 
 fn main() {
-    pollster::block_on(run());
-}
+    let args = std::env::args().collect::<Vec<_>>();
+    let value = |name: &str| args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1));
+
+    // --diff a.png b.png [--threshold n] [--ignore-alpha] [--heatmap out.png], no window. Exits
+    // with 1 when pixels are over the threshold
+    if let Some(i) = args.iter().position(|arg| arg == "--diff") {
+        let (Some(a), Some(b)) = (args.get(i + 1), args.get(i + 2)) else {
+            eprintln!("--diff needs two images");
+            std::process::exit(2);
+        };
+        let options = DiffOptions {
+            threshold: value("--threshold").map(|t| t.parse().expect("--threshold needs 0 - 255")).unwrap_or(0),
+            ignore_alpha: args.iter().any(|arg| arg == "--ignore-alpha"),
+            heatmap: value("--heatmap").map(Into::into),
+        };
+        match compare_images_with(a.as_ref(), b.as_ref(), &options) {
+            Ok(report) => {
+                println!("{}", report);
+                std::process::exit(if report.pixels_over_threshold > 0 { 1 } else { 0 });
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        }
+    }
 
+    // --keys, what every key does
+    if args.iter().any(|arg| arg == "--keys") {
+        print!("{}", InputMap::default().describe());
+        return;
+    }
+
+    let seed = value("--seed").map(|seed| seed.parse().expect("--seed needs a number"));
+    let options = RunOptions {
+        uncapped: args.iter().any(|arg| arg == "--uncapped"),
+        seed,
+        present_modes: if args.iter().any(|arg| arg == "--mailbox") { LOW_LATENCY_PRESENT_MODES } else { &[] },
+        lod_sphere: args.iter().any(|arg| arg == "--lod-sphere"),
+        point_cloud: value("--points").map(Into::into),
+        check_screenshot: value("--check-screenshot").map(Into::into),
+        streaming: args.iter().any(|arg| arg == "--streaming"),
+        scene: value("--scene").map(Into::into),
+        split_positions: args.iter().any(|arg| arg == "--split-positions"),
+        primitive: value("--primitive").cloned(),
+        line_width: value("--line-width").map(|width| width.parse().expect("--line-width needs a number of pixels")),
+        grid: args.iter().any(|arg| arg == "--grid"),
+        procedural_sky: args.iter().any(|arg| arg == "--procedural-sky"),
+        time_of_day: value("--time-of-day").map(|hours| hours.parse().expect("--time-of-day needs an hour, e.g. 18.5")),
+        no_mesh_optimize: args.iter().any(|arg| arg == "--no-mesh-optimize"),
+        packed_vertices: args.iter().any(|arg| arg == "--packed-vertices"),
+        color_space_split: args.iter().any(|arg| arg == "--color-space-split"),
+        record_input: value("--record").map(Into::into),
+        // --play-frames replays frame exact, --play in real time
+        play_input: value("--play").or(value("--play-frames")).map(Into::into),
+        play_timing: value("--play-frames").map(|_| Timing::FrameExact),
+        poll_thread: args.iter().any(|arg| arg == "--poll-thread"),
+        bug_reports: value("--bug-reports").map(Into::into),
+        // --remote <port>, on localhost. The token comes from the environment so it stays out of
+        // the process list
+        remote: value("--remote").map(|port| {
+            let port = port.parse().expect("--remote needs a port");
+            let token = std::env::var("WGPU_PLAYGROUND_TOKEN").expect("--remote needs the token in WGPU_PLAYGROUND_TOKEN");
+            RemoteOptions::localhost(port, token)
+        }),
+        ..Default::default()
+    };
+    pollster::block_on(run_with(options));
+}
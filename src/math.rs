@@ -0,0 +1,501 @@
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+// Small linear algebra helpers.
+// Matrices are column-major, same as WGSL mat4x4<f32>, so they can be uploaded as they are.
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Vec3 {
+    pub const ZERO: Vec3 = Vec3::new(0.0, 0.0, 0.0);
+    pub const X: Vec3 = Vec3::new(1.0, 0.0, 0.0);
+    pub const Y: Vec3 = Vec3::new(0.0, 1.0, 0.0);
+    pub const Z: Vec3 = Vec3::new(0.0, 0.0, 1.0);
+
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn dot(self, other: Vec3) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: Vec3) -> Vec3 {
+        Vec3::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    pub fn length(self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    // Returns zero vector for zero length input instead of NaNs
+    pub fn normalize(self) -> Vec3 {
+        let length = self.length();
+        if length > f32::EPSILON {
+            self / length
+        } else {
+            Vec3::ZERO
+        }
+    }
+
+    pub fn lerp(self, other: Vec3, t: f32) -> Vec3 {
+        self + (other - self) * t
+    }
+
+    pub fn to_array(self) -> [f32; 3] {
+        [self.x, self.y, self.z]
+    }
+}
+
+impl From<[f32; 3]> for Vec3 {
+    fn from(v: [f32; 3]) -> Self {
+        Vec3::new(v[0], v[1], v[2])
+    }
+}
+
+impl Add for Vec3 {
+    type Output = Vec3;
+    fn add(self, rhs: Vec3) -> Vec3 {
+        Vec3::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    }
+}
+
+impl Sub for Vec3 {
+    type Output = Vec3;
+    fn sub(self, rhs: Vec3) -> Vec3 {
+        Vec3::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
+}
+
+impl Mul<f32> for Vec3 {
+    type Output = Vec3;
+    fn mul(self, rhs: f32) -> Vec3 {
+        Vec3::new(self.x * rhs, self.y * rhs, self.z * rhs)
+    }
+}
+
+impl Div<f32> for Vec3 {
+    type Output = Vec3;
+    fn div(self, rhs: f32) -> Vec3 {
+        Vec3::new(self.x / rhs, self.y / rhs, self.z / rhs)
+    }
+}
+
+impl Neg for Vec3 {
+    type Output = Vec3;
+    fn neg(self) -> Vec3 {
+        Vec3::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl AddAssign for Vec3 {
+    fn add_assign(&mut self, rhs: Vec3) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Vec3 {
+    fn sub_assign(&mut self, rhs: Vec3) {
+        *self = *self - rhs;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mat4 {
+    pub cols: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 = Mat4 {
+        cols: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    // Right handed view matrix, camera looks down -Z
+    pub fn look_at_rh(eye: Vec3, target: Vec3, up: Vec3) -> Mat4 {
+        let f = (target - eye).normalize();
+        let s = f.cross(up).normalize();
+        let u = s.cross(f);
+
+        Mat4 {
+            cols: [
+                [s.x, u.x, -f.x, 0.0],
+                [s.y, u.y, -f.y, 0.0],
+                [s.z, u.z, -f.z, 0.0],
+                [-s.dot(eye), -u.dot(eye), f.dot(eye), 1.0],
+            ],
+        }
+    }
+
+    // wgpu clip space has depth in 0..1 (OpenGL uses -1..1), so no extra correction matrix is needed
+    pub fn perspective_rh(fovy_radians: f32, aspect: f32, znear: f32, zfar: f32) -> Mat4 {
+        let f = 1.0 / (fovy_radians / 2.0).tan();
+        let range = znear - zfar;
+
+        Mat4 {
+            cols: [
+                [f / aspect, 0.0, 0.0, 0.0],
+                [0.0, f, 0.0, 0.0],
+                [0.0, 0.0, zfar / range, -1.0],
+                [0.0, 0.0, znear * zfar / range, 0.0],
+            ],
+        }
+    }
+
+    pub fn orthographic_rh(left: f32, right: f32, bottom: f32, top: f32, znear: f32, zfar: f32) -> Mat4 {
+        let width = right - left;
+        let height = top - bottom;
+        let range = znear - zfar;
+
+        Mat4 {
+            cols: [
+                [2.0 / width, 0.0, 0.0, 0.0],
+                [0.0, 2.0 / height, 0.0, 0.0],
+                [0.0, 0.0, 1.0 / range, 0.0],
+                [-(right + left) / width, -(top + bottom) / height, znear / range, 1.0],
+            ],
+        }
+    }
+
+    pub fn translation(v: Vec3) -> Mat4 {
+        let mut m = Mat4::IDENTITY;
+        m.cols[3] = [v.x, v.y, v.z, 1.0];
+        m
+    }
+
+    pub fn scale(v: Vec3) -> Mat4 {
+        let mut m = Mat4::IDENTITY;
+        m.cols[0][0] = v.x;
+        m.cols[1][1] = v.y;
+        m.cols[2][2] = v.z;
+        m
+    }
+
+    // Rotation around (normalized) axis, Rodrigues formula
+    pub fn from_axis_angle(axis: Vec3, radians: f32) -> Mat4 {
+        let a = axis.normalize();
+        let (s, c) = radians.sin_cos();
+        let t = 1.0 - c;
+
+        Mat4 {
+            cols: [
+                [t * a.x * a.x + c, t * a.x * a.y + s * a.z, t * a.x * a.z - s * a.y, 0.0],
+                [t * a.x * a.y - s * a.z, t * a.y * a.y + c, t * a.y * a.z + s * a.x, 0.0],
+                [t * a.x * a.z + s * a.y, t * a.y * a.z - s * a.x, t * a.z * a.z + c, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let v = self.mul_vec4([p.x, p.y, p.z, 1.0]);
+        let w = if v[3].abs() > f32::EPSILON { v[3] } else { 1.0 };
+        Vec3::new(v[0] / w, v[1] / w, v[2] / w)
+    }
+
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let v = self.mul_vec4([v.x, v.y, v.z, 0.0]);
+        Vec3::new(v[0], v[1], v[2])
+    }
+
+    pub fn mul_vec4(&self, v: [f32; 4]) -> [f32; 4] {
+        let mut out = [0.0; 4];
+        for (col, value) in self.cols.iter().zip(v) {
+            for row in 0..4 {
+                out[row] += col[row] * value;
+            }
+        }
+        out
+    }
+
+    pub fn to_cols_array(&self) -> [[f32; 4]; 4] {
+        self.cols
+    }
+
+    // General inverse by cofactors. Singular matrices give IDENTITY
+    pub fn inverse(&self) -> Mat4 {
+        let m = |col: usize, row: usize| self.cols[col][row];
+        // 2x2 determinants of the lower and upper two rows
+        let s0 = m(0, 0) * m(1, 1) - m(1, 0) * m(0, 1);
+        let s1 = m(0, 0) * m(2, 1) - m(2, 0) * m(0, 1);
+        let s2 = m(0, 0) * m(3, 1) - m(3, 0) * m(0, 1);
+        let s3 = m(1, 0) * m(2, 1) - m(2, 0) * m(1, 1);
+        let s4 = m(1, 0) * m(3, 1) - m(3, 0) * m(1, 1);
+        let s5 = m(2, 0) * m(3, 1) - m(3, 0) * m(2, 1);
+        let c5 = m(2, 2) * m(3, 3) - m(3, 2) * m(2, 3);
+        let c4 = m(1, 2) * m(3, 3) - m(3, 2) * m(1, 3);
+        let c3 = m(1, 2) * m(2, 3) - m(2, 2) * m(1, 3);
+        let c2 = m(0, 2) * m(3, 3) - m(3, 2) * m(0, 3);
+        let c1 = m(0, 2) * m(2, 3) - m(2, 2) * m(0, 3);
+        let c0 = m(0, 2) * m(1, 3) - m(1, 2) * m(0, 3);
+
+        let det = s0 * c5 - s1 * c4 + s2 * c3 + s3 * c2 - s4 * c1 + s5 * c0;
+        if det.abs() <= f32::EPSILON * f32::EPSILON {
+            return Mat4::IDENTITY;
+        }
+        let inv = 1.0 / det;
+
+        // Rows of the adjugate are written as columns here, which transposes it
+        Mat4 {
+            cols: [
+                [
+                    (m(1, 1) * c5 - m(2, 1) * c4 + m(3, 1) * c3) * inv,
+                    (-m(0, 1) * c5 + m(2, 1) * c2 - m(3, 1) * c1) * inv,
+                    (m(0, 1) * c4 - m(1, 1) * c2 + m(3, 1) * c0) * inv,
+                    (-m(0, 1) * c3 + m(1, 1) * c1 - m(2, 1) * c0) * inv,
+                ],
+                [
+                    (-m(1, 0) * c5 + m(2, 0) * c4 - m(3, 0) * c3) * inv,
+                    (m(0, 0) * c5 - m(2, 0) * c2 + m(3, 0) * c1) * inv,
+                    (-m(0, 0) * c4 + m(1, 0) * c2 - m(3, 0) * c0) * inv,
+                    (m(0, 0) * c3 - m(1, 0) * c1 + m(2, 0) * c0) * inv,
+                ],
+                [
+                    (m(1, 3) * s5 - m(2, 3) * s4 + m(3, 3) * s3) * inv,
+                    (-m(0, 3) * s5 + m(2, 3) * s2 - m(3, 3) * s1) * inv,
+                    (m(0, 3) * s4 - m(1, 3) * s2 + m(3, 3) * s0) * inv,
+                    (-m(0, 3) * s3 + m(1, 3) * s1 - m(2, 3) * s0) * inv,
+                ],
+                [
+                    (-m(1, 2) * s5 + m(2, 2) * s4 - m(3, 2) * s3) * inv,
+                    (m(0, 2) * s5 - m(2, 2) * s2 + m(3, 2) * s1) * inv,
+                    (-m(0, 2) * s4 + m(1, 2) * s2 - m(3, 2) * s0) * inv,
+                    (m(0, 2) * s3 - m(1, 2) * s1 + m(2, 2) * s0) * inv,
+                ],
+            ],
+        }
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut cols = [[0.0; 4]; 4];
+        for (out, col) in cols.iter_mut().zip(rhs.cols) {
+            *out = self.mul_vec4(col);
+        }
+        Mat4 { cols }
+    }
+}
+
+// Small fast PRNG (SplitMix64), not for anything security related. The same seed gives the same
+// sequence on every platform, so everything procedural can be reproduced from one seed
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    // For when nobody asked for a specific seed. std's hasher keys come from the OS where there is
+    // one (fixed on wasm32-unknown-unknown, use an explicit seed there if it matters)
+    pub fn random_seed() -> u64 {
+        use std::hash::{BuildHasher, Hasher};
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u128(instant::Instant::now().elapsed().as_nanos());
+        hasher.finish()
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    // 0..1, 24 bits of precision
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    // Independent generator for one system, so how much one system draws doesn't change what
+    // the others get
+    pub fn fork(&mut self) -> Rng {
+        Rng::new(self.next_u64())
+    }
+}
+
+// Cheap integer hash, used for noise gradients
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+// 1D Perlin (gradient) noise, result is roughly in -1..1. Different seeds give independent curves
+pub fn perlin_1d(x: f32, seed: u32) -> f32 {
+    let gradient = |i: i32| {
+        let h = hash((i as u32).wrapping_add(seed.wrapping_mul(0x9e37_79b9)));
+        (h as f32 / u32::MAX as f32) * 2.0 - 1.0
+    };
+
+    let i = x.floor();
+    let f = x - i;
+    let i = i as i32;
+
+    let v0 = gradient(i) * f;
+    let v1 = gradient(i.wrapping_add(1)) * (f - 1.0);
+    // Quintic fade curve, smooth first and second derivative
+    let u = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+
+    (v0 + (v1 - v0) * u) * 2.0
+}
+
+// 2D Perlin noise, roughly in -1..1, seeds work like for perlin_1d
+pub fn perlin_2d(x: f32, y: f32, seed: u32) -> f32 {
+    // Dot of the corner's random unit gradient with the offset from that corner
+    let gradient = |ix: i32, iy: i32, dx: f32, dy: f32| {
+        let h = hash((ix as u32).wrapping_mul(0x8da6_b343) ^ (iy as u32).wrapping_mul(0xd816_3841) ^ seed.wrapping_mul(0x9e37_79b9));
+        let angle = h as f32 / u32::MAX as f32 * std::f32::consts::TAU;
+        angle.cos() * dx + angle.sin() * dy
+    };
+
+    let (ix, iy) = (x.floor(), y.floor());
+    let (fx, fy) = (x - ix, y - iy);
+    let (ix, iy) = (ix as i32, iy as i32);
+    let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let (u, v) = (fade(fx), fade(fy));
+
+    let bottom = gradient(ix, iy, fx, fy) + (gradient(ix.wrapping_add(1), iy, fx - 1.0, fy) - gradient(ix, iy, fx, fy)) * u;
+    let top_left = gradient(ix, iy.wrapping_add(1), fx, fy - 1.0);
+    let top = top_left + (gradient(ix.wrapping_add(1), iy.wrapping_add(1), fx - 1.0, fy - 1.0) - top_left) * u;
+    (bottom + (top - bottom) * v) * std::f32::consts::SQRT_2
+}
+
+// Axis aligned bounding box
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Aabb> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Aabb { min: first, max: first }, |aabb, p| aabb.including(p)))
+    }
+
+    pub fn including(self, p: Vec3) -> Aabb {
+        Aabb {
+            min: Vec3::new(self.min.x.min(p.x), self.min.y.min(p.y), self.min.z.min(p.z)),
+            max: Vec3::new(self.max.x.max(p.x), self.max.y.max(p.y), self.max.z.max(p.z)),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    // Bit 0 picks x, bit 1 picks y, bit 2 picks z (0 = min, 1 = max)
+    pub fn corners(&self) -> [Vec3; 8] {
+        std::array::from_fn(|i| {
+            Vec3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            )
+        })
+    }
+
+    // World space AABB enclosing this box after transform
+    pub fn transformed(&self, transform: &Mat4) -> Aabb {
+        Aabb::from_points(self.corners().map(|c| transform.transform_point(c))).unwrap()
+    }
+
+    // Distance along the ray to where it enters the box (0 when it starts inside), None for a miss.
+    // `inverse_direction` is 1 / direction per axis, infinite for 0 works
+    pub fn ray_intersection(&self, origin: Vec3, inverse_direction: Vec3) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = f32::INFINITY;
+        for (min, max, origin, inverse) in [
+            (self.min.x, self.max.x, origin.x, inverse_direction.x),
+            (self.min.y, self.max.y, origin.y, inverse_direction.y),
+            (self.min.z, self.max.z, origin.z, inverse_direction.z),
+        ] {
+            let t1 = (min - origin) * inverse;
+            let t2 = (max - origin) * inverse;
+            // min / max skip the NaN of a ray lying exactly in a slab's plane
+            near = near.max(t1.min(t2));
+            far = far.min(t1.max(t2));
+        }
+        (near <= far).then_some(near)
+    }
+}
+
+// Six planes (a, b, c, d) with a*x + b*y + c*z + d >= 0 inside, normals unit length. From a
+// wgpu view projection (depth 0..1), see Camera::build_view_projection_matrix
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frustum {
+    pub planes: [[f32; 4]; 6],
+}
+
+impl Frustum {
+    pub fn from_view_proj(view_proj: &Mat4) -> Frustum {
+        let row = |r: usize| [0, 1, 2, 3].map(|c| view_proj.cols[c][r]);
+        let [x, y, z, w] = [row(0), row(1), row(2), row(3)];
+        let add = |a: [f32; 4], b: [f32; 4]| [0, 1, 2, 3].map(|i| a[i] + b[i]);
+        let sub = |a: [f32; 4], b: [f32; 4]| [0, 1, 2, 3].map(|i| a[i] - b[i]);
+        // Left, right, bottom, top, near (z >= 0), far (z <= w)
+        let planes = [add(w, x), sub(w, x), add(w, y), sub(w, y), z, sub(w, z)].map(|plane| {
+            let length = Vec3::new(plane[0], plane[1], plane[2]).length().max(f32::EPSILON);
+            plane.map(|v| v / length)
+        });
+        Frustum { planes }
+    }
+
+    fn distance(plane: &[f32; 4], p: Vec3) -> f32 {
+        plane[0] * p.x + plane[1] * p.y + plane[2] * p.z + plane[3]
+    }
+
+    // Conservative, a sphere just off a corner of the frustum can still pass
+    pub fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+        self.planes.iter().all(|plane| Self::distance(plane, center) >= -radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal
+            let p = Vec3::new(
+                if plane[0] >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane[1] >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane[2] >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            Self::distance(plane, p) >= 0.0
+        })
+    }
+
+    // World space corners of the view volume, same bit order as Aabb::corners (bit 2 = far)
+    pub fn corners(view_proj: &Mat4) -> [Vec3; 8] {
+        let inverse = view_proj.inverse();
+        Aabb { min: Vec3::new(-1.0, -1.0, 0.0), max: Vec3::new(1.0, 1.0, 1.0) }.corners().map(|c| inverse.transform_point(c))
+    }
+}
@@ -0,0 +1,28 @@
+// Appended to motion_blur.wgsl by motion_blur.rs. Longest blur vector on screen, read back to skip
+// the blur pass while nothing moves
+
+// f32 bits, non negative floats order the same as their bits as u32
+@group(1) @binding(0)
+var<storage, read_write> max_length: atomic<u32>;
+
+var<workgroup> tile_max: atomic<u32>;
+
+@compute @workgroup_size(8, 8)
+fn cs_max_velocity(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    if index == 0u {
+        atomicStore(&tile_max, 0u);
+    }
+    workgroupBarrier();
+    let dimensions = textureDimensions(t_velocity);
+    if all(id.xy < dimensions) {
+        let len = length(blur_vector(vec2<i32>(id.xy), vec2<f32>(dimensions)));
+        atomicMax(&tile_max, bitcast<u32>(len));
+    }
+    workgroupBarrier();
+    if index == 0u {
+        atomicMax(&max_length, atomicLoad(&tile_max));
+    }
+}
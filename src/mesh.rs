@@ -0,0 +1,125 @@
+use crate::math::Vec3;
+use crate::Vertex;
+
+// How State::set_mesh treats incoming vertices
+#[derive(Copy, Clone, Debug)]
+pub struct MeshOptions {
+    // Drop triangles with coincident vertices or (near) zero area. Real world exports have those,
+    // they z-fight and give NaN normals
+    pub cull_degenerate: bool,
+    // A triangle is degenerate when its area is below this fraction of its longest edge squared,
+    // so the test doesn't depend on the mesh's scale
+    pub degenerate_epsilon: f32,
+    // Reorder triangles for the vertex cache and less overdraw, see optimize::optimize. Same
+    // triangles either way, off to measure what it does
+    pub optimize: bool,
+}
+
+impl Default for MeshOptions {
+    fn default() -> Self {
+        Self {
+            cull_degenerate: true,
+            degenerate_epsilon: 1e-6,
+            optimize: true,
+        }
+    }
+}
+
+// True when the triangle has coincident corners or no area to speak of, see MeshOptions
+pub fn is_degenerate(a: Vec3, b: Vec3, c: Vec3, epsilon: f32) -> bool {
+    if a == b || b == c || c == a {
+        return true;
+    }
+    let longest = (b - a).dot(b - a).max((c - b).dot(c - b)).max((a - c).dot(a - c));
+    let double_area = (b - a).cross(c - a).length();
+    // NaN positions count as degenerate too
+    double_area.is_nan() || double_area <= 2.0 * epsilon * longest
+}
+
+// Removes degenerate triangles from a triangle list in place, keeping the order of the rest.
+// A trailing partial triangle is dropped as well. Returns how many triangles were removed
+pub fn cull_degenerate_triangles(vertices: &mut Vec<Vertex>, epsilon: f32) -> usize {
+    let triangles = vertices.len() / 3;
+    let mut kept = 0;
+    for i in 0..triangles {
+        let [a, b, c] = [0, 1, 2].map(|corner| Vec3::from(vertices[i * 3 + corner].position));
+        if !is_degenerate(a, b, c, epsilon) {
+            vertices.copy_within(i * 3..i * 3 + 3, kept * 3);
+            kept += 1;
+        }
+    }
+    vertices.truncate(kept * 3);
+    triangles - kept
+}
+
+// How the triangles of a mesh share their edges, see edge_report. In a closed mesh with consistent
+// winding every edge a -> b has exactly one triangle with b -> a next to it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EdgeReport {
+    pub edges: usize,
+    // Only one triangle uses the edge, a hole or the rim of an open mesh
+    pub open: usize,
+    // Two triangles run the edge the same way, one of them is flipped
+    pub flipped: usize,
+    // More than two triangles on the edge
+    pub non_manifold: usize,
+}
+
+impl EdgeReport {
+    pub fn is_watertight(&self) -> bool {
+        self.open == 0 && self.flipped == 0 && self.non_manifold == 0
+    }
+}
+
+// Positions on a 1e-5 grid, so -0 and 0 or a pole that's only nearly one weld together
+pub fn weld_key(p: Vec3) -> [i64; 3] {
+    [p.x, p.y, p.z].map(|c| (c * 1e5).round() as i64)
+}
+
+// Edges of a triangle list by position, so UV and normal seams don't count as open
+pub fn edge_report(positions: &[Vec3]) -> EdgeReport {
+    // Undirected edge -> how many triangles run it lower key first, and the other way
+    let mut edges = std::collections::HashMap::<_, (u32, u32)>::new();
+    for triangle in positions.chunks_exact(3) {
+        let keys = [weld_key(triangle[0]), weld_key(triangle[1]), weld_key(triangle[2])];
+        for (a, b) in [(keys[0], keys[1]), (keys[1], keys[2]), (keys[2], keys[0])] {
+            let counts = edges.entry(if a < b { (a, b) } else { (b, a) }).or_default();
+            if a < b {
+                counts.0 += 1;
+            } else {
+                counts.1 += 1;
+            }
+        }
+    }
+    let mut report = EdgeReport { edges: edges.len(), ..Default::default() };
+    for &(forward, backward) in edges.values() {
+        match (forward, backward) {
+            (1, 1) => {}
+            (1, 0) | (0, 1) => report.open += 1,
+            (2, 0) | (0, 2) => report.flipped += 1,
+            _ => report.non_manifold += 1,
+        }
+    }
+    report
+}
+
+// Triangle list to an indexed one, bit for bit equal vertices merged
+pub fn index_vertices(vertices: &[Vertex]) -> (Vec<Vertex>, Vec<u32>) {
+    let mut unique = Vec::new();
+    let mut seen = std::collections::HashMap::new();
+    let indices = vertices
+        .iter()
+        .map(|vertex| {
+            *seen.entry(bytemuck::bytes_of(vertex).to_vec()).or_insert_with(|| {
+                unique.push(*vertex);
+                unique.len() as u32 - 1
+            })
+        })
+        .collect();
+    (unique, indices)
+}
+
+// Indexed triangles back to the triangle list State::set_mesh takes
+pub fn unindex(vertices: &[Vertex], indices: &[u32]) -> Vec<Vertex> {
+    indices.iter().map(|&i| vertices[i as usize]).collect()
+}
@@ -0,0 +1,455 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use crate::math::Mat4;
+
+// Blurs shorter than this many pixels aren't visible, below it the pass is skipped
+pub const STATIC_THRESHOLD: f32 = 0.5;
+
+// map_state values, written by the map_async callback. Same scheme as occlusion.rs
+const MAP_WAITING: u8 = 0;
+const MAP_DONE: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+#[derive(Copy, Clone, Debug)]
+pub struct MotionBlurSettings {
+    // Shutter: fraction of a frame's motion that gets smeared, 0.5 is a 180 degree shutter
+    pub intensity: f32,
+    // Taps along the blur vector per pixel
+    pub samples: u32,
+    // Longest blur in pixels, fast motion gets clamped to it
+    pub max_radius: f32,
+}
+
+impl Default for MotionBlurSettings {
+    fn default() -> Self {
+        Self {
+            intensity: 0.5,
+            samples: 8,
+            max_radius: 32.0,
+        }
+    }
+}
+
+// Layout must match BlurUniform in motion_blur.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct BlurUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    prev_view_proj: [[f32; 4]; 4],
+    intensity: f32,
+    samples: u32,
+    max_radius: f32,
+    _padding: f32,
+}
+
+// Longest blur vector of a frame, reduced on the GPU and read back a frame or two later
+struct MaxVelocityReduction {
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::ComputePipeline,
+    result: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // readback_buffer is being mapped, no new reduction until it's read
+    mapping: bool,
+    map_state: Arc<AtomicU8>,
+}
+
+// Motion blur from the VelocityPass output: every pixel is averaged along its blur vector with
+// depth aware weights (see motion_blur.wgsl). The longest blur of recent frames is measured with
+// a compute reduction, while it's under STATIC_THRESHOLD the blur pass isn't recorded at all. The
+// measurement lags a frame or two, so the first moving frames after a still stretch stay sharp.
+// Without compute shaders it always blurs
+pub struct MotionBlur {
+    pub settings: MotionBlurSettings,
+    uniform_buffer: wgpu::Buffer,
+    // Velocity, depth and the uniform, shared with the reduction
+    velocity_bind_group_layout: wgpu::BindGroupLayout,
+    scene_bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    pipeline: wgpu::RenderPipeline,
+    reduction: Option<MaxVelocityReduction>,
+    // In pixels, None until the first measurement and after reset()
+    last_max_length: Option<f32>,
+}
+
+impl MotionBlur {
+    pub fn new(device: &wgpu::Device, adapter: &wgpu::Adapter, color_format: wgpu::TextureFormat) -> Self {
+        let compute = adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS);
+        let visibility = if compute {
+            wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE
+        } else {
+            wgpu::ShaderStages::FRAGMENT
+        };
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Motion Blur Uniform Buffer"),
+            size: std::mem::size_of::<BlurUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let texture_entry = |binding, visibility, filterable| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let velocity_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Motion Blur Velocity Bind Group Layout"),
+            entries: &[
+                texture_entry(0, visibility, false),
+                texture_entry(1, visibility, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let scene_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Motion Blur Scene Bind Group Layout"),
+            entries: &[
+                texture_entry(0, wgpu::ShaderStages::FRAGMENT, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        // Taps land between pixels
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Motion Blur Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let pipeline = Self::create_pipeline(device, color_format, &velocity_bind_group_layout, &scene_bind_group_layout);
+        let reduction = compute.then(|| MaxVelocityReduction::new(device, &velocity_bind_group_layout));
+
+        Self {
+            settings: MotionBlurSettings::default(),
+            uniform_buffer,
+            velocity_bind_group_layout,
+            scene_bind_group_layout,
+            sampler,
+            pipeline,
+            reduction,
+            last_max_length: None,
+        }
+    }
+
+    // Call after the surface format changes
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device, color_format: wgpu::TextureFormat) {
+        self.pipeline = Self::create_pipeline(
+            device, color_format, &self.velocity_bind_group_layout, &self.scene_bind_group_layout
+        );
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        velocity_bind_group_layout: &wgpu::BindGroupLayout,
+        scene_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("motion_blur.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Motion Blur Pipeline Layout"),
+            bind_group_layouts: &[velocity_bind_group_layout, scene_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Motion Blur Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_blur",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // Writes the uniform for this frame. `prev_view_proj` is VelocityPass::prepare's result, None
+    // means no camera motion. `view_proj` is what the scene was drawn with
+    pub fn prepare(&self, queue: &wgpu::Queue, prev_view_proj: Option<Mat4>, view_proj: Mat4) {
+        let uniform = BlurUniform {
+            inv_view_proj: view_proj.inverse().to_cols_array(),
+            prev_view_proj: prev_view_proj.unwrap_or(view_proj).to_cols_array(),
+            intensity: self.settings.intensity,
+            samples: self.settings.samples.max(1),
+            max_radius: self.settings.max_radius,
+            _padding: 0.0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // False while recent frames measured as static, the blur pass can be left out then
+    pub fn is_needed(&self) -> bool {
+        self.last_max_length.is_none_or(|length| length >= STATIC_THRESHOLD)
+    }
+
+    // Longest blur in pixels of a recent frame, None before the first measurement or without
+    // compute shaders
+    pub fn last_max_length(&self) -> Option<f32> {
+        self.last_max_length
+    }
+
+    // Starts over from "moving", for cuts where old measurements mean nothing
+    pub fn reset(&mut self) {
+        self.last_max_length = None;
+    }
+
+    fn velocity_bind_group(&self, device: &wgpu::Device, velocity: &wgpu::TextureView, depth: &wgpu::Texture) -> wgpu::BindGroup {
+        // Stencil can't be bound along with depth
+        let depth_view = depth.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Motion Blur Velocity Bind Group"),
+            layout: &self.velocity_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(velocity),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&depth_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    // False without compute shaders and while the last measurement is still being read back
+    pub fn can_measure(&self) -> bool {
+        self.reduction.as_ref().is_some_and(|reduction| !reduction.mapping)
+    }
+
+    // Records the max velocity reduction, only when can_measure(). `depth` is the scene's depth
+    // texture, single sampled
+    pub fn measure(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        velocity: &wgpu::TextureView,
+        depth: &wgpu::Texture,
+    ) {
+        let Some(reduction) = &self.reduction else {
+            return;
+        };
+        let bind_group = self.velocity_bind_group(device, velocity, depth);
+        reduction.record(encoder, &bind_group, depth.width(), depth.height());
+    }
+
+    // Blurs `scene` into `target` (same size, color_format)
+    pub fn blur(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        scene: &wgpu::TextureView,
+        velocity: &wgpu::TextureView,
+        depth: &wgpu::Texture,
+        target: &wgpu::TextureView,
+    ) {
+        let velocity_bind_group = self.velocity_bind_group(device, velocity, depth);
+        let scene_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Motion Blur Scene Bind Group"),
+            layout: &self.scene_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(scene),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Motion Blur Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Every pixel gets overwritten anyway
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &velocity_bind_group, &[]);
+        render_pass.set_bind_group(1, &scene_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // Call after the frame's encoder was submitted, `measured` if measure() was recorded into it
+    pub fn after_submit(&mut self, measured: bool) {
+        if let Some(reduction) = self.reduction.as_mut().filter(|_| measured) {
+            reduction.after_submit();
+        }
+    }
+
+    // Picks up a finished measurement, doesn't wait for it
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        if let Some(length) = self.reduction.as_mut().and_then(|reduction| reduction.poll(device)) {
+            self.last_max_length = Some(length);
+        }
+    }
+
+    // Uniform and the reduction's two u32 buffers
+    pub fn gpu_memory(&self) -> u64 {
+        self.uniform_buffer.size()
+            + self.reduction.as_ref().map_or(0, |reduction| reduction.result.size() + reduction.readback_buffer.size())
+    }
+}
+
+// The reduction reuses the blur's helpers, the fragment entry point is left out of the pipeline
+pub(crate) fn max_velocity_shader_source() -> String {
+    format!("{}\n{}", include_str!("motion_blur.wgsl"), include_str!("max_velocity.wgsl"))
+}
+
+impl MaxVelocityReduction {
+    fn new(device: &wgpu::Device, velocity_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Max Velocity Shader"),
+            source: wgpu::ShaderSource::Wgsl(max_velocity_shader_source().into()),
+        });
+        let result_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Max Velocity Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Max Velocity Pipeline Layout"),
+            bind_group_layouts: &[velocity_bind_group_layout, &result_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Max Velocity Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "cs_max_velocity",
+        });
+
+        let result = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Max Velocity Result"),
+            size: 4,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Max Velocity Readback Buffer"),
+            size: 4,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Max Velocity Bind Group"),
+            layout: &result_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: result.as_entire_binding(),
+                }
+            ],
+        });
+
+        Self {
+            bind_group,
+            pipeline,
+            result,
+            readback_buffer,
+            mapping: false,
+            map_state: Arc::new(AtomicU8::new(MAP_WAITING)),
+        }
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, velocity_bind_group: &wgpu::BindGroup, width: u32, height: u32) {
+        encoder.clear_buffer(&self.result, 0, None);
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Max Velocity Pass"),
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, velocity_bind_group, &[]);
+            compute_pass.set_bind_group(1, &self.bind_group, &[]);
+            // Must match the workgroup size in max_velocity.wgsl
+            compute_pass.dispatch_workgroups(width.div_ceil(8), height.div_ceil(8), 1);
+        }
+        encoder.copy_buffer_to_buffer(&self.result, 0, &self.readback_buffer, 0, 4);
+    }
+
+    fn after_submit(&mut self) {
+        self.mapping = true;
+        let map_state = self.map_state.clone();
+        self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            map_state.store(if result.is_ok() { MAP_DONE } else { MAP_FAILED }, Ordering::Release);
+        });
+    }
+
+    fn poll(&mut self, device: &wgpu::Device) -> Option<f32> {
+        if !self.mapping {
+            return None;
+        }
+        device.poll(wgpu::Maintain::Poll);
+        match self.map_state.swap(MAP_WAITING, Ordering::Acquire) {
+            MAP_WAITING => return None,
+            MAP_FAILED => {
+                self.mapping = false;
+                return None;
+            }
+            _ => {}
+        }
+
+        let length = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            f32::from_bits(u32::from_le_bytes([data[0], data[1], data[2], data[3]]))
+        }; // Mapped view has to be dropped before unmap
+        self.readback_buffer.unmap();
+        self.mapping = false;
+        Some(length)
+    }
+}
@@ -0,0 +1,131 @@
+// Per pixel motion blur along the velocity buffer (see velocity.rs), after McGuire et al. 2012,
+// "A Reconstruction Filter for Plausible Motion Blur" without the tile max pass
+
+// Layout must match BlurUniform in motion_blur.rs
+struct BlurUniform {
+    // Of this frame as drawn (jitter included), the depth buffer was drawn with it
+    inv_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+    // Fraction of the frame's motion the shutter is open for
+    intensity: f32,
+    samples: u32,
+    // Longest blur in pixels
+    max_radius: f32,
+}
+
+@group(0) @binding(0)
+var t_velocity: texture_2d<f32>;
+// Depth aspect as a plain float texture, GL can't read depth textures without a comparison
+@group(0) @binding(1)
+var t_depth: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> blur: BlurUniform;
+
+fn unproject(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    let world = blur.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// Motion of `pixel` since last frame in pixels, from the velocity pass where the mesh is and the
+// camera motion everywhere else
+fn pixel_velocity(pixel: vec2<i32>, size: vec2<f32>) -> vec2<f32> {
+    let velocity = textureLoad(t_velocity, pixel, 0);
+    if velocity.a > 0.0 {
+        return velocity.xy * size;
+    }
+    let uv = (vec2<f32>(pixel) + 0.5) / size;
+    let previous = blur.prev_view_proj * vec4<f32>(unproject(uv, textureLoad(t_depth, pixel, 0).r), 1.0);
+    let previous_ndc = previous.xy / previous.w;
+    let previous_uv = vec2<f32>(previous_ndc.x * 0.5 + 0.5, 0.5 - previous_ndc.y * 0.5);
+    return (uv - previous_uv) * size;
+}
+
+// Blur vector of `pixel`: scaled by the shutter, at most max_radius long
+fn blur_vector(pixel: vec2<i32>, size: vec2<f32>) -> vec2<f32> {
+    let v = pixel_velocity(pixel, size) * blur.intensity;
+    let len = length(v);
+    // NaN from a degenerate reprojection counts as no motion
+    if !(len > 0.0) {
+        return vec2<f32>(0.0);
+    }
+    return v * (min(len, blur.max_radius) / len);
+}
+
+// Distance from the near plane along the view ray, works for both projections
+fn linear_depth(pixel: vec2<i32>, size: vec2<f32>) -> f32 {
+    let uv = (vec2<f32>(pixel) + 0.5) / size;
+    let depth = textureLoad(t_depth, pixel, 0).r;
+    return distance(unproject(uv, depth), unproject(uv, 0.0));
+}
+
+@group(1) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(1) @binding(1)
+var s_scene: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle that covers the whole screen, same as blit.wgsl
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// 1 while `a` is in front of or level with `b`, fading to 0 a few percent behind it
+fn soft_depth_less(a: f32, b: f32) -> f32 {
+    return clamp(1.0 - (a - b) / max(0.02 * max(a, b), 1e-4), 0.0, 1.0);
+}
+
+fn cone(distance: f32, len: f32) -> f32 {
+    return clamp(1.0 - distance / max(len, 1e-4), 0.0, 1.0);
+}
+
+fn cylinder(distance: f32, len: f32) -> f32 {
+    return 1.0 - smoothstep(0.95 * len, 1.05 * len, distance);
+}
+
+@fragment
+fn fs_blur(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_scene));
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let center = textureSampleLevel(t_scene, s_scene, in.uv, 0.0);
+    let v = blur_vector(pixel, size);
+    let len = length(v);
+    if len < 0.5 {
+        return center;
+    }
+    let center_depth = linear_depth(pixel, size);
+
+    // Samples spread over the blur vector centered on the pixel. Foreground samples blur over
+    // the pixel as far as their own motion reaches, background ones only show through where the
+    // pixel itself moves, so edges don't bleed both ways
+    var sum = center.rgb;
+    var weight_sum = 1.0;
+    let max_pixel = vec2<i32>(size) - 1;
+    for (var i = 0u; i < blur.samples; i++) {
+        let t = mix(-0.5, 0.5, (f32(i) + 0.5) / f32(blur.samples));
+        let offset = v * t;
+        let sample_pixel = clamp(pixel + vec2<i32>(round(offset)), vec2<i32>(0), max_pixel);
+        let distance = length(offset);
+        let sample_len = length(blur_vector(sample_pixel, size));
+        let sample_depth = linear_depth(sample_pixel, size);
+
+        let foreground = soft_depth_less(sample_depth, center_depth);
+        let background = soft_depth_less(center_depth, sample_depth);
+        let weight = foreground * cone(distance, sample_len)
+            + background * cone(distance, len)
+            + cylinder(distance, sample_len) * cylinder(distance, len) * 2.0;
+        let color = textureSampleLevel(t_scene, s_scene, in.uv + offset / size, 0.0).rgb;
+        sum += color * weight;
+        weight_sum += weight;
+    }
+    return vec4<f32>(sum / weight_sum, center.a);
+}
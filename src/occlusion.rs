@@ -0,0 +1,153 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+// Roughly how much of a draw ended up visible, counted in fragment shader invocations.
+// wgpu 0.17 has no occlusion queries on render passes yet (only the QueryType), so this uses a
+// pipeline statistics query instead. That's a conservative upper bound on the visible fragments,
+// not a sample count: early depth testing is allowed but not guaranteed, helper invocations
+// around triangle edges are counted, and with MSAA a pixel is one invocation however many samples
+// it covers. 0 still means nothing was drawn. Needs Features::PIPELINE_STATISTICS_QUERY, which
+// isn't available on the web and some native adapters.
+//
+// Results are read back without stalling: they show up a frame or two after the draw
+
+// map_state values, written by the map_async callback
+const MAP_WAITING: u8 = 0;
+const MAP_DONE: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+pub struct OcclusionQueries {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    capacity: u32,
+    // Queries copied into readback_buffer that are waiting for the map to finish
+    pending: Option<u32>,
+    mapping: bool,
+    map_state: Arc<AtomicU8>,
+    results: Vec<Option<u64>>,
+}
+
+impl OcclusionQueries {
+    pub const FEATURES: wgpu::Features = wgpu::Features::PIPELINE_STATISTICS_QUERY;
+
+    // None if the device was created without FEATURES
+    pub fn new(device: &wgpu::Device, capacity: u32) -> Option<Self> {
+        if !device.features().contains(Self::FEATURES) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Occlusion Query Set"),
+            ty: wgpu::QueryType::PipelineStatistics(wgpu::PipelineStatisticsTypes::FRAGMENT_SHADER_INVOCATIONS),
+            count: capacity,
+        });
+
+        // One u64 per query
+        let size = capacity as wgpu::BufferAddress * 8;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occlusion Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occlusion Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            capacity,
+            pending: None,
+            mapping: false,
+            map_state: Arc::new(AtomicU8::new(MAP_WAITING)),
+            results: vec![None; capacity as usize],
+        })
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    // Queries can't be nested, end() has to come before the next begin()
+    pub fn begin(&self, render_pass: &mut wgpu::RenderPass, index: u32) {
+        render_pass.begin_pipeline_statistics_query(&self.query_set, index);
+    }
+
+    pub fn end(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.end_pipeline_statistics_query();
+    }
+
+    // Copies the first `count` queries for readback. Skipped while the previous readback is
+    // still in flight, those queries are simply never read
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder, count: u32) {
+        let count = count.min(self.capacity);
+        if self.pending.is_some() || count == 0 {
+            return;
+        }
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &self.readback_buffer, 0, count as wgpu::BufferAddress * 8);
+        self.pending = Some(count);
+    }
+
+    // Call after the encoder passed to resolve() was submitted
+    pub fn after_submit(&mut self) {
+        let Some(count) = self.pending else { return };
+        if self.mapping {
+            return;
+        }
+        self.mapping = true;
+        let map_state = self.map_state.clone();
+        self.readback_buffer
+            .slice(..count as wgpu::BufferAddress * 8)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                map_state.store(if result.is_ok() { MAP_DONE } else { MAP_FAILED }, Ordering::Release);
+            });
+    }
+
+    // Picks up results once the readback buffer is mapped, doesn't wait for it
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        let Some(count) = self.pending else { return };
+        if !self.mapping {
+            return;
+        }
+        device.poll(wgpu::Maintain::Poll);
+        match self.map_state.swap(MAP_WAITING, Ordering::Acquire) {
+            MAP_WAITING => return,
+            MAP_FAILED => {
+                // Drop this batch, the next resolve() tries again
+                self.pending = None;
+                self.mapping = false;
+                return;
+            }
+            _ => {}
+        }
+
+        {
+            let data = self.readback_buffer.slice(..count as wgpu::BufferAddress * 8).get_mapped_range();
+            let values: &[u64] = bytemuck::cast_slice(&data);
+            for (result, &value) in self.results.iter_mut().zip(values) {
+                *result = Some(value);
+            }
+        } // Mapped view has to be dropped before unmap
+        self.readback_buffer.unmap();
+        self.pending = None;
+        self.mapping = false;
+    }
+
+    // Fragment shader invocations inside query `index` the last time it was read back, None
+    // before that
+    pub fn last_result(&self, index: u32) -> Option<u64> {
+        self.results.get(index as usize).copied().flatten()
+    }
+
+    // Query results are 8 bytes each, in the query set too
+    pub fn gpu_memory(&self) -> u64 {
+        self.capacity as u64 * 8 + self.resolve_buffer.size() + self.readback_buffer.size()
+    }
+}
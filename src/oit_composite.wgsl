@@ -0,0 +1,27 @@
+// Resolves the weighted blended OIT targets written by fs_accumulate in transparent.wgsl
+
+@group(0) @binding(0)
+var t_accum: texture_2d<f32>;
+@group(0) @binding(1)
+var t_reveal: texture_2d<f32>;
+
+// Fullscreen triangle, same as blit.wgsl
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    return vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+}
+
+// Average color of everything that landed on the pixel, blended over the opaque result
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    let reveal = textureLoad(t_reveal, pixel, 0).r;
+    // Nothing transparent here
+    if reveal >= 0.9999 {
+        discard;
+    }
+    let accum = textureLoad(t_accum, pixel, 0);
+    let average = accum.rgb / max(accum.a, 1e-5);
+    return vec4<f32>(average, 1.0 - reveal);
+}
@@ -0,0 +1,366 @@
+use std::fmt;
+
+use crate::math::Vec3;
+use crate::{mesh, Vertex};
+
+// Post transform cache size the orderings aim for and the stats are measured with. Real GPUs have
+// somewhere around 16 - 32 entries, or batch differently altogether, so the numbers are a model
+pub const CACHE_SIZE: usize = 16;
+
+// How well an index order reuses the post transform cache, with a FIFO cache of CACHE_SIZE
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    // Average cache miss ratio, vertex shader runs per triangle. 3 is no reuse at all, a large
+    // regular grid can get close to 0.5
+    pub acmr: f32,
+    // Average transformed vertex ratio, vertex shader runs per vertex used. 1 is the best there is
+    pub atvr: f32,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ACMR {:.3}, ATVR {:.3}", self.acmr, self.atvr)
+    }
+}
+
+// Cache misses of every index, in order. FIFO: a miss pushes the vertex, hits don't move it
+fn simulate_cache(indices: &[u32], vertex_count: usize, cache_size: usize) -> Vec<bool> {
+    // Miss count when the vertex went in, it's still there while fewer than cache_size came after
+    let mut pushed = vec![None; vertex_count];
+    let mut misses = 0usize;
+    indices
+        .iter()
+        .map(|&i| {
+            let miss = pushed[i as usize].is_none_or(|at| misses - at >= cache_size);
+            if miss {
+                pushed[i as usize] = Some(misses);
+                misses += 1;
+            }
+            miss
+        })
+        .collect()
+}
+
+pub fn cache_stats(indices: &[u32], vertex_count: usize, cache_size: usize) -> CacheStats {
+    let triangles = indices.len() / 3;
+    if triangles == 0 {
+        return CacheStats::default();
+    }
+    let misses = simulate_cache(indices, vertex_count, cache_size).iter().filter(|&&miss| miss).count();
+    let mut used = vec![false; vertex_count];
+    for &i in indices {
+        used[i as usize] = true;
+    }
+    CacheStats {
+        acmr: misses as f32 / triangles as f32,
+        atvr: misses as f32 / used.iter().filter(|&&used| used).count() as f32,
+    }
+}
+
+// Triangle order for the post transform cache, Tipsify from Sander, Nehab and Barczak, "Fast
+// Triangle Reordering for Vertex Locality and Reduced Overdraw". Fans around one vertex at a time
+// and moves on to the neighbor that's still in the cache and has the fewest triangles left, so it
+// won't fall out before they're done. Also returns the triangles where it had to jump elsewhere
+// (a dead end), the cache starts over there
+fn tipsify(indices: &[u32], vertex_count: usize, cache_size: usize) -> (Vec<u32>, Vec<usize>) {
+    let triangles = indices.len() / 3;
+    // Triangles using each vertex, flattened: vertex v's are at offsets[v]..offsets[v + 1]
+    let mut offsets = vec![0; vertex_count + 1];
+    for &i in &indices[..triangles * 3] {
+        offsets[i as usize + 1] += 1;
+    }
+    for v in 0..vertex_count {
+        offsets[v + 1] += offsets[v];
+    }
+    let mut adjacency = vec![0; offsets[vertex_count]];
+    let mut filled = offsets.clone();
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &i in corners {
+            adjacency[filled[i as usize]] = triangle;
+            filled[i as usize] += 1;
+        }
+    }
+
+    // Triangles not emitted yet
+    let mut live = (0..vertex_count).map(|v| offsets[v + 1] - offsets[v]).collect::<Vec<_>>();
+    // When the vertex went into the cache, on a clock that only ticks on misses
+    let mut cache_time = vec![0; vertex_count];
+    let mut time = cache_size + 1;
+    let mut emitted = vec![false; triangles];
+    let mut dead_end = Vec::new();
+    let mut candidates = Vec::new();
+    let mut cursor = 0;
+    let mut out = Vec::with_capacity(triangles * 3);
+    let mut restarts = Vec::new();
+
+    let mut fanning = Some(indices.first().copied().unwrap_or(0) as usize).filter(|_| triangles > 0);
+    if fanning.is_some() {
+        restarts.push(0);
+    }
+    while let Some(f) = fanning {
+        candidates.clear();
+        for &triangle in &adjacency[offsets[f]..offsets[f + 1]] {
+            if emitted[triangle] {
+                continue;
+            }
+            emitted[triangle] = true;
+            for &i in &indices[triangle * 3..triangle * 3 + 3] {
+                let v = i as usize;
+                out.push(i);
+                dead_end.push(v);
+                candidates.push(v);
+                live[v] -= 1;
+                if time - cache_time[v] > cache_size {
+                    cache_time[v] = time;
+                    time += 1;
+                }
+            }
+        }
+
+        // Oldest candidate that's still cached after its remaining triangles (2 new vertices each)
+        let mut best = None;
+        let mut best_priority = 0;
+        for &v in &candidates {
+            if live[v] > 0 && time - cache_time[v] + 2 * live[v] <= cache_size && time - cache_time[v] > best_priority {
+                best_priority = time - cache_time[v];
+                best = Some(v);
+            }
+        }
+        fanning = best.or_else(|| {
+            // Dead end, something recently touched or else the next vertex in input order
+            let next = std::iter::from_fn(|| dead_end.pop()).find(|&v| live[v] > 0).or_else(|| {
+                while cursor < vertex_count && live[cursor] == 0 {
+                    cursor += 1;
+                }
+                Some(cursor).filter(|&v| v < vertex_count)
+            });
+            if next.is_some() {
+                restarts.push(out.len() / 3);
+            }
+            next
+        });
+    }
+    (out, restarts)
+}
+
+// Reorders clusters of triangles (runs that start with a cold cache) so the ones facing away from
+// the middle of the mesh come first. Those are on the outside and likely to cover the rest, so less
+// of the mesh gets shaded only to be overdrawn, with about the same cache efficiency. The cluster
+// sort from the Tipsify paper, without its extra splitting
+fn sort_clusters(indices: &[u32], restarts: &[usize], positions: &[Vec3], cache_size: usize) -> Vec<u32> {
+    let triangles = indices.len() / 3;
+    let misses = simulate_cache(indices, positions.len(), cache_size);
+    // Dead ends, and triangles where all three corners missed anyway
+    let mut starts = restarts.to_vec();
+    starts.extend((0..triangles).filter(|&t| misses[t * 3..t * 3 + 3] == [true, true, true]));
+    starts.sort_unstable();
+    starts.dedup();
+    starts.push(triangles);
+
+    // Area weighted centroid and normal of every cluster
+    let triangle = |t: usize| [0, 1, 2].map(|corner| positions[indices[t * 3 + corner] as usize]);
+    let mut clusters = Vec::new();
+    let mut mesh_center = Vec3::ZERO;
+    let mut mesh_area = 0.0;
+    for range in starts.windows(2) {
+        let (mut center, mut normal, mut area) = (Vec3::ZERO, Vec3::ZERO, 0.0);
+        for t in range[0]..range[1] {
+            let [a, b, c] = triangle(t);
+            let cross = (b - a).cross(c - a);
+            let weight = cross.length();
+            center += (a + b + c) * (weight / 3.0);
+            normal += cross;
+            area += weight;
+        }
+        mesh_center += center;
+        mesh_area += area;
+        clusters.push((range[0]..range[1], center, normal, area));
+    }
+    if mesh_area > 0.0 {
+        mesh_center = mesh_center / mesh_area;
+    }
+    let mut keyed = clusters
+        .into_iter()
+        .map(|(range, center, normal, area)| {
+            let key = if area > 0.0 && normal.length() > 0.0 {
+                (center / area - mesh_center).dot(normal.normalize())
+            } else {
+                0.0
+            };
+            (range, key)
+        })
+        .collect::<Vec<_>>();
+    // Stable, clusters with the same key keep the cache order
+    keyed.sort_by(|a, b| b.1.total_cmp(&a.1));
+    keyed.into_iter().flat_map(|(range, _)| indices[range.start * 3..range.end * 3].iter().copied()).collect()
+}
+
+// Renumbers vertices in the order the indices first use them, so vertex fetches walk the buffer
+// front to back. Unused vertices are dropped. Works on any vertex type, whole vertices move so
+// everything in them (joints and weights too) stays with its position
+pub fn optimize_vertex_fetch<T: Copy>(vertices: &[T], indices: &[u32]) -> (Vec<T>, Vec<u32>) {
+    let mut remap = vec![u32::MAX; vertices.len()];
+    let mut ordered = Vec::new();
+    let indices = indices
+        .iter()
+        .map(|&i| {
+            if remap[i as usize] == u32::MAX {
+                remap[i as usize] = ordered.len() as u32;
+                ordered.push(vertices[i as usize]);
+            }
+            remap[i as usize]
+        })
+        .collect();
+    (ordered, indices)
+}
+
+// Result of optimize, with the cache stats of the incoming order and the new one
+#[derive(Clone, Debug)]
+pub struct Optimized {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub before: CacheStats,
+    pub after: CacheStats,
+}
+
+// Vertex cache order, then overdraw order of the clusters, then vertex fetch order. Triangles
+// keep their corners and winding, only their order and the vertex numbering change
+pub fn optimize(vertices: &[Vertex], indices: &[u32]) -> Optimized {
+    let indices = &indices[..indices.len() / 3 * 3];
+    let before = cache_stats(indices, vertices.len(), CACHE_SIZE);
+    let positions = vertices.iter().map(|v| Vec3::from(v.position)).collect::<Vec<_>>();
+    let (ordered, restarts) = tipsify(indices, vertices.len(), CACHE_SIZE);
+    let ordered = sort_clusters(&ordered, &restarts, &positions, CACHE_SIZE);
+    let (vertices, indices) = optimize_vertex_fetch(vertices, &ordered);
+    let after = cache_stats(&indices, vertices.len(), CACHE_SIZE);
+    Optimized { vertices, indices, before, after }
+}
+
+// optimize for a triangle list like State::set_mesh takes. Bit for bit equal vertices become one
+// for the stats, the list that comes back is the same triangles in the new order
+pub fn optimize_triangle_list(vertices: &[Vertex]) -> (Vec<Vertex>, Optimized) {
+    let (unique, indices) = mesh::index_vertices(vertices);
+    let optimized = optimize(&unique, &indices);
+    (mesh::unindex(&optimized.vertices, &optimized.indices), optimized)
+}
+
+// Indices in the smallest format that can address every vertex
+#[derive(Clone, Debug, PartialEq)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    // Picked by the largest index, so take it after optimize_vertex_fetch: dropping unused
+    // vertices can bring a mesh down to u16. 0xFFFF itself isn't used, it's the strip restart index
+    pub fn new(indices: &[u32]) -> Self {
+        if indices.iter().all(|&i| i < u16::MAX as u32) {
+            Indices::U16(indices.iter().map(|&i| i as u16).collect())
+        } else {
+            Indices::U32(indices.to_vec())
+        }
+    }
+
+    pub fn format(&self) -> wgpu::IndexFormat {
+        match self {
+            Indices::U16(_) => wgpu::IndexFormat::Uint16,
+            Indices::U32(_) => wgpu::IndexFormat::Uint32,
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            Indices::U16(indices) => bytemuck::cast_slice(indices),
+            Indices::U32(indices) => bytemuck::cast_slice(indices),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::U16(indices) => indices.len(),
+            Indices::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, i: usize) -> u32 {
+        match self {
+            Indices::U16(indices) => indices[i] as u32,
+            Indices::U32(indices) => indices[i],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Rng;
+    use crate::primitives;
+
+    // Triangles as bytes, sorted, to compare two lists as sets
+    fn sorted_triangles(vertices: &[Vertex]) -> Vec<Vec<u8>> {
+        let mut triangles = vertices.chunks_exact(3).map(|t| bytemuck::cast_slice::<Vertex, u8>(t).to_vec()).collect::<Vec<_>>();
+        triangles.sort_unstable();
+        triangles
+    }
+
+    fn shuffled(vertices: &[Vertex], seed: u64) -> Vec<Vertex> {
+        let mut triangles = vertices.chunks_exact(3).collect::<Vec<_>>();
+        let mut rng = Rng::new(seed);
+        for i in (1..triangles.len()).rev() {
+            triangles.swap(i, rng.next_u32() as usize % (i + 1));
+        }
+        triangles.concat()
+    }
+
+    // Vertex i follows bone i % 7 a bit and bone 7 for the rest, so moved vertices show if they lost
+    // their skin
+    fn skinned(vertices: &[Vertex]) -> Vec<Vertex> {
+        let (unique, indices) = mesh::index_vertices(vertices);
+        let unique = unique
+            .iter()
+            .enumerate()
+            .map(|(i, v)| Vertex { joints: [(i % 7) as u16, 7, 0, 0], weights: [0.25, 0.75, 0.0, 0.0], ..*v })
+            .collect::<Vec<_>>();
+        mesh::unindex(&unique, &indices)
+    }
+
+    // Triangles and their skinning come back the same, the cache doesn't get worse and the index
+    // format fits the vertex count
+    fn assert_optimizes(name: &str, vertices: &[Vertex]) {
+        let (list, optimized) = optimize_triangle_list(vertices);
+        assert!(sorted_triangles(&list) == sorted_triangles(vertices), "{}: triangles or their vertices changed", name);
+        assert!(
+            optimized.after.acmr <= optimized.before.acmr * 1.05,
+            "{}: ACMR went from {:.3} to {:.3}",
+            name, optimized.before.acmr, optimized.after.acmr
+        );
+        let indices = Indices::new(&optimized.indices);
+        let expected = if optimized.vertices.len() < u16::MAX as usize { wgpu::IndexFormat::Uint16 } else { wgpu::IndexFormat::Uint32 };
+        assert_eq!(indices.format(), expected, "{}: indices for {} vertices", name, optimized.vertices.len());
+        assert!((0..indices.len()).all(|i| indices.get(i) == optimized.indices[i]), "{}: indices changed in {:?}", name, indices.format());
+    }
+
+    #[test]
+    fn primitives_optimize() {
+        for &name in primitives::NAMES {
+            assert_optimizes(name, &primitives::by_name(name).unwrap().mesh());
+        }
+    }
+
+    #[test]
+    fn shuffled_sphere_optimizes() {
+        let sphere = primitives::uv_sphere(0.5, 512, 256);
+        assert_optimizes("sphere 512 x 256", &sphere);
+        assert_optimizes("sphere shuffled", &shuffled(&sphere, 1));
+    }
+
+    #[test]
+    fn skinning_survives_optimizing() {
+        assert_optimizes("torus skinned shuffled", &shuffled(&skinned(&primitives::torus(0.35, 0.15, 64, 32).mesh()), 2));
+    }
+}
@@ -0,0 +1,220 @@
+use std::ops::Range;
+
+use crate::instance::InstanceRaw;
+use crate::pipeline::PipelineConfig;
+use crate::Vertex;
+
+// Stencil value marking pixels covered by the outlined object
+const STENCIL_MARK: u32 = 1;
+
+// Layout must match OutlineUniform in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    thickness: f32,
+    // Uniforms need 16 byte alignment
+    _padding: [f32; 3],
+}
+
+// Stencil based selection outline for one instance of the mesh:
+// 1. the instance is drawn again, writing STENCIL_MARK wherever it covers the screen (no color)
+// 2. a slightly grown copy is drawn in a solid color where the stencil isn't marked,
+//    which leaves only a rim around the object
+// Both ignore depth, so the outline shows through whatever is in front of the object.
+// Needs a depth format with stencil and the stencil cleared to something else than STENCIL_MARK
+pub struct OutlineRenderer {
+    // Instance to outline, None draws nothing
+    pub target: Option<u32>,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    mark_pipeline: wgpu::RenderPipeline,
+    outline_pipeline: wgpu::RenderPipeline,
+}
+
+impl OutlineRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        shader: &wgpu::ShaderModule,
+        mesh_bind_group_layouts: &[&wgpu::BindGroupLayout],
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Outline Uniform Buffer"),
+            size: std::mem::size_of::<OutlineUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Outline Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Outline Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                }
+            ],
+        });
+
+        let (mark_pipeline, outline_pipeline) =
+            Self::create_pipelines(device, config, shader, mesh_bind_group_layouts, &bind_group_layout);
+
+        Self {
+            target: None,
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+            mark_pipeline,
+            outline_pipeline,
+        }
+    }
+
+    // Call after the pipeline config or the mesh shader changes
+    pub fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        shader: &wgpu::ShaderModule,
+        mesh_bind_group_layouts: &[&wgpu::BindGroupLayout],
+    ) {
+        (self.mark_pipeline, self.outline_pipeline) =
+            Self::create_pipelines(device, config, shader, mesh_bind_group_layouts, &self.bind_group_layout);
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        shader: &wgpu::ShaderModule,
+        mesh_bind_group_layouts: &[&wgpu::BindGroupLayout],
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        // Mesh groups followed by the outline uniform
+        let bind_group_layouts = mesh_bind_group_layouts.iter()
+            .copied()
+            .chain(std::iter::once(bind_group_layout))
+            .collect::<Vec<_>>();
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Pipeline Layout"),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        let create = |label, vs_entry, fs_entry, write_mask, stencil: wgpu::StencilFaceState, stencil_write_mask| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: vs_entry,
+                    buffers: &[Vertex::desc(), InstanceRaw::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: fs_entry,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: config.color_format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: config.depth_format,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState {
+                        front: stencil,
+                        back: stencil,
+                        read_mask: 0xff,
+                        write_mask: stencil_write_mask,
+                    },
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: config.multisample(),
+                multiview: None,
+            })
+        };
+
+        let mark_pipeline = create(
+            "Outline Mark Pipeline",
+            "vs_main",
+            "fs_main",
+            wgpu::ColorWrites::empty(),
+            wgpu::StencilFaceState {
+                compare: wgpu::CompareFunction::Always,
+                fail_op: wgpu::StencilOperation::Replace,
+                depth_fail_op: wgpu::StencilOperation::Replace,
+                pass_op: wgpu::StencilOperation::Replace,
+            },
+            0xff,
+        );
+        let outline_pipeline = create(
+            "Outline Pipeline",
+            "vs_outline",
+            "fs_outline",
+            wgpu::ColorWrites::ALL,
+            wgpu::StencilFaceState {
+                compare: wgpu::CompareFunction::NotEqual,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+                pass_op: wgpu::StencilOperation::Keep,
+            },
+            0,
+        );
+
+        (mark_pipeline, outline_pipeline)
+    }
+
+    // `thickness` is how much bigger the outline copy is, as a fraction of the object size
+    pub fn set_style(&self, queue: &wgpu::Queue, color: [f32; 4], thickness: f32) {
+        let uniform = OutlineUniform { color, thickness, _padding: [0.0; 3] };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Expects the mesh bind groups and vertex / instance buffers to be set already (draw_scene does).
+    // `vertices` and `slot` are where the target's level of detail and instance are in those
+    // buffers, see LodSelector
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, vertices: Range<u32>, slot: u32) {
+        self.draw_instance(render_pass, vertices, slot);
+    }
+
+    // Same as draw() with instance 0, ignoring target. For State::prewarm
+    pub fn prewarm<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, vertex_count: u32) {
+        self.draw_instance(render_pass, 0..vertex_count, 0);
+    }
+
+    fn draw_instance<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, vertices: Range<u32>, instance: u32) {
+        let instances = instance..instance + 1;
+
+        // Both pipelines share the layout, so group 3 has to be bound for the mark draw too
+        render_pass.set_bind_group(3, &self.bind_group, &[]);
+        render_pass.set_stencil_reference(STENCIL_MARK);
+
+        render_pass.set_pipeline(&self.mark_pipeline);
+        render_pass.draw(vertices.clone(), instances.clone());
+
+        render_pass.set_pipeline(&self.outline_pipeline);
+        render_pass.draw(vertices, instances);
+    }
+}
@@ -0,0 +1,242 @@
+use wgpu::util::DeviceExt;
+
+use crate::instance::InstanceRaw;
+use crate::pipeline::PipelineConfig;
+use crate::reflection::{ReflectedLayout, ShaderReflection};
+use crate::texture::{self, ColorSpace, Texture};
+use crate::Vertex;
+
+// The one directional light until something else sets it (CameraUniform::set_light), roughly the
+// sun in the afternoon. Direction is towards the light and not normalized
+pub const SUN_DIRECTION: [f32; 3] = [0.4, 0.8, 0.45];
+pub const SUN_COLOR: [f32; 3] = [3.0, 2.9, 2.7];
+
+// Layout must match PbrFactors in pbr.wgsl. Multiplied with the texture values
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PbrFactors {
+    // Linear color, alpha is ignored for now
+    pub albedo: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    // Scales the normal map's x / y, 0 = flat
+    pub normal_scale: f32,
+    // 0 ignores the ao texture, 1 applies it fully
+    pub occlusion_strength: f32,
+}
+
+impl Default for PbrFactors {
+    fn default() -> Self {
+        Self {
+            albedo: [1.0; 4],
+            metallic: 0.0,
+            roughness: 0.5,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+        }
+    }
+}
+
+// Textures of a material, None gets a 1x1 texture that leaves the factor as is (white, or a flat
+// normal). Albedo has to be ColorSpace::Srgb, the rest ColorSpace::Linear, see SLOTS.
+// Metallic, roughness and ao are read from the red channel
+#[derive(Default)]
+pub struct PbrTextures {
+    pub albedo: Option<Texture>,
+    pub metallic: Option<Texture>,
+    pub roughness: Option<Texture>,
+    pub normal: Option<Texture>,
+    pub ao: Option<Texture>,
+}
+
+// Name and color space of every texture slot, in PbrMaterial::textures order. ColorSpace::of_gltf_texture
+// gives the same for glTF's baseColorTexture / metallicRoughnessTexture / normalTexture / occlusionTexture
+pub const SLOTS: [(&str, ColorSpace); 5] = [
+    ("albedo", ColorSpace::Srgb),
+    ("metallic", ColorSpace::Linear),
+    ("roughness", ColorSpace::Linear),
+    ("normal", ColorSpace::Linear),
+    ("occlusion", ColorSpace::Linear),
+];
+
+// Material for PbrRenderer, group 1 of pbr.wgsl
+pub struct PbrMaterial {
+    pub textures: [Texture; 5],
+    factors: PbrFactors,
+    factors_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl PbrMaterial {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &ReflectedLayout,
+        textures: PbrTextures,
+        factors: PbrFactors,
+    ) -> Result<Self, String> {
+        let slots = [&textures.albedo, &textures.metallic, &textures.roughness, &textures.normal, &textures.ao];
+        for (texture, (slot, color_space)) in slots.into_iter().zip(SLOTS) {
+            if let Some(texture) = texture.as_ref().filter(|texture| texture.color_space() != color_space) {
+                return Err(format!(
+                    "PBR {} texture is {:?} ({:?}), the slot is {:?}",
+                    slot, texture.color_space(), texture.texture.format(), color_space
+                ));
+            }
+        }
+
+        let white = |label| Texture::from_rgba_linear(device, queue, &[255; 4], 1, 1, label);
+        let textures = [
+            textures.albedo.unwrap_or_else(|| Texture::from_rgba(device, queue, &[255; 4], 1, 1, "PBR Albedo")),
+            textures.metallic.unwrap_or_else(|| white("PBR Metallic")),
+            textures.roughness.unwrap_or_else(|| white("PBR Roughness")),
+            // Straight up in tangent space
+            textures.normal.unwrap_or_else(|| {
+                Texture::from_rgba_linear(device, queue, &[128, 128, 255, 255], 1, 1, "PBR Normal")
+            }),
+            textures.ao.unwrap_or_else(|| white("PBR Occlusion")),
+        ];
+        Self::with_textures(device, layout, textures, factors)
+    }
+
+    // No color space check, for wrong_color_space
+    fn with_textures(
+        device: &wgpu::Device,
+        layout: &ReflectedLayout,
+        textures: [Texture; 5],
+        factors: PbrFactors,
+    ) -> Result<Self, String> {
+
+        let factors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("PBR Factors Buffer"),
+            contents: bytemuck::cast_slice(&[factors]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        // One sampler for all of them, the textures' own samplers clamp
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("PBR Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let view = |index: usize| wgpu::BindingResource::TextureView(&textures[index].view);
+        let bind_group = layout.create_bind_group(device, &[
+            ("t_albedo".into(), view(0)),
+            ("t_metallic".into(), view(1)),
+            ("t_roughness".into(), view(2)),
+            ("t_normal".into(), view(3)),
+            ("t_occlusion".into(), view(4)),
+            ("s_material".into(), wgpu::BindingResource::Sampler(&sampler)),
+            ("factors".into(), factors_buffer.as_entire_binding()),
+        ])?;
+
+        Ok(Self { textures, factors, factors_buffer, bind_group })
+    }
+
+    pub fn factors(&self) -> PbrFactors {
+        self.factors
+    }
+
+    // Same material with every texture sampled in the wrong color space: albedo without decoding
+    // sRGB (washed out), normals and roughness decoded as if they were (darker, bent). For
+    // State::set_color_space_split
+    pub fn wrong_color_space(&self, device: &wgpu::Device, queue: &wgpu::Queue, layout: &ReflectedLayout) -> Result<Self, String> {
+        let textures = self.textures.each_ref().map(|texture| texture.reinterpreted(device, queue, "PBR Wrong Color Space"));
+        Self::with_textures(device, layout, textures, self.factors)
+    }
+
+    pub fn set_factors(&mut self, queue: &wgpu::Queue, factors: PbrFactors) {
+        self.factors = factors;
+        queue.write_buffer(&self.factors_buffer, 0, bytemuck::cast_slice(&[factors]));
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        self.textures.iter().map(|t| texture::texture_bytes(&t.texture)).sum::<u64>() + self.factors_buffer.size()
+    }
+}
+
+// Cook-Torrance shading of the mesh with a PbrMaterial, see pbr.wgsl. Bind groups:
+// 0 camera, 1 material, 2 bones, 3 IblMaps. Vertex and instance buffers are the same as the
+// regular mesh pipelines
+pub struct PbrRenderer {
+    // Group 1, reflected from pbr.wgsl
+    pub material_bind_group_layout: ReflectedLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl PbrRenderer {
+    // `layouts`: camera, bones and IBL bind group layouts
+    pub fn new(device: &wgpu::Device, config: &PipelineConfig, layouts: [&wgpu::BindGroupLayout; 3]) -> Self {
+        let material_bind_group_layout = ShaderReflection::new("PBR", include_str!("pbr.wgsl"))
+            .and_then(|shader| shader.layout(device, 1))
+            .unwrap_or_else(|e| panic!("{}", e));
+        let pipeline = Self::create_pipeline(device, config, layouts, &material_bind_group_layout.layout);
+        Self { material_bind_group_layout, pipeline }
+    }
+
+    // Call after the pipeline config changes
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device, config: &PipelineConfig, layouts: [&wgpu::BindGroupLayout; 3]) {
+        self.pipeline = Self::create_pipeline(device, config, layouts, &self.material_bind_group_layout.layout);
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        [camera_layout, bones_layout, ibl_layout]: [&wgpu::BindGroupLayout; 3],
+        material_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("pbr.wgsl"));
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PBR Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, material_layout, bones_layout, ibl_layout],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("PBR Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_pbr",
+                buffers: &[Vertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_pbr",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(config.depth_state()),
+            multisample: config.multisample(),
+            multiview: None,
+        })
+    }
+
+    // Binds the pipeline and its groups, vertex buffers and the draw are the same as for the mesh pipelines
+    pub fn set_pipeline<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        material: &'a PbrMaterial,
+        bones_bind_group: &'a wgpu::BindGroup,
+        ibl_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &material.bind_group, &[]);
+        render_pass.set_bind_group(2, bones_bind_group, &[]);
+        render_pass.set_bind_group(3, ibl_bind_group, &[]);
+    }
+}
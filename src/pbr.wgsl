@@ -0,0 +1,255 @@
+// Physically based shading for the mesh, see pbr.rs. Cook-Torrance with GGX for a single
+// directional light plus image based lighting from IblMaps.
+// Vertex side is the same as vs_main in shader.wgsl, that module keeps group 1 for the layered texture
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    exposure: f32,
+    fog_mode: u32,
+    fog_start: f32,
+    fog_end: f32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    eye: vec3<f32>,
+    fog_height: f32,
+    forward: vec3<f32>,
+    fog_height_falloff: f32,
+    // Towards the directional light, normalized
+    sun_direction: vec3<f32>,
+    // Scales image based lighting
+    ambient: f32,
+    sun_color: vec3<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Has to match the FOG_ constants in camera.rs
+const FOG_LINEAR: u32 = 1u;
+const FOG_EXPONENTIAL: u32 = 2u;
+const FOG_EXPONENTIAL_SQUARED: u32 = 3u;
+
+fn view_depth(world_position: vec3<f32>) -> f32 {
+    return dot(world_position - camera.eye, camera.forward);
+}
+
+// Same as in shader.wgsl
+fn fog_amount(direction_y: f32, distance: f32) -> f32 {
+    let span = max(distance - camera.fog_start, 0.0);
+    let start_height = camera.eye.y + direction_y * camera.fog_start;
+    let density = camera.fog_density * exp(min(-camera.fog_height_falloff * (start_height - camera.fog_height), 80.0));
+    let k = camera.fog_height_falloff * direction_y;
+    if abs(k * span) < 0.0001 {
+        return density * span;
+    }
+    return density * (1.0 - exp(min(-k * span, 80.0))) / k;
+}
+
+fn fog_visibility(world_position: vec3<f32>) -> f32 {
+    let depth = view_depth(world_position);
+    if camera.fog_mode == FOG_LINEAR {
+        return clamp((camera.fog_end - depth) / max(camera.fog_end - camera.fog_start, 0.0001), 0.0, 1.0);
+    } else if camera.fog_mode == FOG_EXPONENTIAL {
+        return exp(-camera.fog_density * max(depth, 0.0));
+    } else if camera.fog_mode == FOG_EXPONENTIAL_SQUARED {
+        let ray = world_position - camera.eye;
+        let distance = length(ray);
+        let amount = fog_amount(ray.y / max(distance, 0.0001), distance);
+        return exp(-amount * amount);
+    }
+    return 1.0;
+}
+
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    return mix(camera.fog_color * camera.exposure, color, fog_visibility(world_position));
+}
+
+// Layout must match PbrFactors in pbr.rs
+struct PbrFactors {
+    albedo: vec4<f32>,
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+}
+
+@group(1) @binding(0)
+var t_albedo: texture_2d<f32>;
+@group(1) @binding(1)
+var t_metallic: texture_2d<f32>;
+@group(1) @binding(2)
+var t_roughness: texture_2d<f32>;
+@group(1) @binding(3)
+var t_normal: texture_2d<f32>;
+@group(1) @binding(4)
+var t_occlusion: texture_2d<f32>;
+@group(1) @binding(5)
+var s_material: sampler;
+@group(1) @binding(6)
+var<uniform> factors: PbrFactors;
+
+// Has to match MAX_BONES in skinning.rs
+const MAX_BONES: u32 = 64u;
+
+struct Bones {
+    matrices: array<mat4x4<f32>, MAX_BONES>,
+}
+
+@group(2) @binding(0)
+var<uniform> bones: Bones;
+
+// See ibl.rs
+@group(3) @binding(0)
+var t_irradiance: texture_cube<f32>;
+@group(3) @binding(1)
+var t_prefiltered: texture_cube<f32>;
+@group(3) @binding(2)
+var t_brdf_lut: texture_2d<f32>;
+@group(3) @binding(3)
+var s_ibl: sampler;
+
+const PI: f32 = 3.14159265;
+// Must match PREFILTER_MIPS in ibl.rs
+const PREFILTER_MIPS: u32 = 5u;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+    @location(3) joints: vec4<u32>,
+    @location(4) weights: vec4<f32>,
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) layer: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    // Towards the camera, not normalized so it interpolates correctly
+    @location(2) to_eye: vec3<f32>,
+}
+
+fn skin_matrix(model: VertexInput) -> mat4x4<f32> {
+    return bones.matrices[model.joints.x] * model.weights.x
+        + bones.matrices[model.joints.y] * model.weights.y
+        + bones.matrices[model.joints.z] * model.weights.z
+        + bones.matrices[model.joints.w] * model.weights.w;
+}
+
+fn unproject(ndc: vec3<f32>) -> vec3<f32> {
+    let world = camera.inv_view_proj * vec4<f32>(ndc, 1.0);
+    return world.xyz / world.w;
+}
+
+@vertex
+fn vs_pbr(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world = model_matrix * skin_matrix(model) * vec4<f32>(model.position, 1.0);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world;
+    out.world_position = world.xyz;
+    out.tex_coords = model.tex_coords;
+    // The camera uniform only has matrices, the near plane point in front of the vertex works
+    // for perspective and orthographic cameras alike
+    let ndc = out.clip_position.xy / out.clip_position.w;
+    out.to_eye = unproject(vec3<f32>(ndc, 0.0)) - world.xyz;
+    return out;
+}
+
+// The mesh has no normals or tangents, both come from screen space derivatives of the position
+// and uv. Faceted, but exact for flat triangles. Columns: tangent, bitangent, normal.
+// The derivatives are taken in fs_pbr: the GL backend emits helper functions into every stage,
+// and dFdx doesn't exist in vertex shaders
+fn cotangent_frame(dp_dx: vec3<f32>, dp_dy: vec3<f32>, duv_dx: vec2<f32>, duv_dy: vec2<f32>) -> mat3x3<f32> {
+    // Framebuffer y points down, so this faces the camera for counter clockwise triangles
+    let n = normalize(cross(dp_dy, dp_dx));
+    let dp_dy_perp = cross(dp_dy, n);
+    let dp_dx_perp = cross(n, dp_dx);
+    let t = dp_dy_perp * duv_dx.x + dp_dx_perp * duv_dy.x;
+    let b = dp_dy_perp * duv_dx.y + dp_dx_perp * duv_dy.y;
+    let inv_max = inverseSqrt(max(max(dot(t, t), dot(b, b)), 1e-12));
+    return mat3x3<f32>(t * inv_max, b * inv_max, n);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn geometry_schlick_ggx(n_dot_v: f32, roughness: f32) -> f32 {
+    // k for analytic lights, ibl.wgsl uses roughness^2 / 2 for the LUT
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k);
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Fresnel for the IBL, rough surfaces don't get as bright at grazing angles
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+@fragment
+fn fs_pbr(in: VertexOutput) -> @location(0) vec4<f32> {
+    let albedo = textureSample(t_albedo, s_material, in.tex_coords) * factors.albedo;
+    let metallic = textureSample(t_metallic, s_material, in.tex_coords).r * factors.metallic;
+    // Fully smooth makes the GGX lobe a spike that aliases badly
+    let roughness = clamp(textureSample(t_roughness, s_material, in.tex_coords).r * factors.roughness, 0.04, 1.0);
+    let occlusion = mix(1.0, textureSample(t_occlusion, s_material, in.tex_coords).r, factors.occlusion_strength);
+    var tangent_normal = textureSample(t_normal, s_material, in.tex_coords).xyz * 2.0 - 1.0;
+    tangent_normal = vec3<f32>(tangent_normal.xy * factors.normal_scale, tangent_normal.z);
+
+    let frame = cotangent_frame(
+        dpdx(in.world_position), dpdy(in.world_position), dpdx(in.tex_coords), dpdy(in.tex_coords)
+    );
+    let n = normalize(frame * tangent_normal);
+    let v = normalize(in.to_eye);
+    let n_dot_v = max(dot(n, v), 0.0001);
+    // Dielectrics reflect about 4%, metals tint the reflection with their albedo
+    let f0 = mix(vec3<f32>(0.04), albedo.rgb, metallic);
+
+    // Direct light
+    let l = camera.sun_direction;
+    let h = normalize(v + l);
+    let n_dot_l = max(dot(n, l), 0.0);
+    let f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+    let g = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+    let specular = distribution_ggx(max(dot(n, h), 0.0), roughness) * g * f / (4.0 * n_dot_v * max(n_dot_l, 0.0001));
+    // Whatever isn't reflected is diffused, metals don't diffuse
+    let k_diffuse = (1.0 - f) * (1.0 - metallic);
+    let direct = (k_diffuse * albedo.rgb / PI + specular) * camera.sun_color * n_dot_l;
+
+    // Image based: irradiance for diffuse, split sum (prefiltered * LUT) for specular
+    let f_ibl = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    let k_diffuse_ibl = (1.0 - f_ibl) * (1.0 - metallic);
+    let diffuse_ibl = textureSample(t_irradiance, s_ibl, n).rgb * albedo.rgb;
+    let r = reflect(-v, n);
+    let prefiltered = textureSampleLevel(t_prefiltered, s_ibl, r, roughness * f32(PREFILTER_MIPS - 1u)).rgb;
+    let brdf = textureSample(t_brdf_lut, s_ibl, vec2<f32>(n_dot_v, roughness)).rg;
+    let specular_ibl = prefiltered * (f0 * brdf.x + brdf.y);
+    let ambient = (k_diffuse_ibl * diffuse_ibl + specular_ibl) * occlusion * camera.ambient;
+
+    // No tonemapping yet, same as the sky
+    return vec4<f32>(apply_fog((direct + ambient) * camera.exposure, in.world_position), 1.0);
+}
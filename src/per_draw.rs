@@ -0,0 +1,204 @@
+use std::ops::Range;
+
+use crate::buffer::GrowableBuffer;
+use crate::instance::Instance;
+use crate::pipeline::{self, PipelineConfig};
+
+// Layout must match PerDraw in per_draw.wgsl (a mat4 and a u32 round up to 80 bytes in WGSL)
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PerDrawRaw {
+    model: [[f32; 4]; 4],
+    layer: u32,
+    _padding: [u32; 3],
+}
+
+const PUSH_CONSTANT_DECLARATION: &str = "var<push_constant> per_draw: PerDraw;";
+// Binding 1, outline.rs has binding 0 of group 3 in the same module
+const UNIFORM_DECLARATION: &str = "@group(3) @binding(1)\nvar<uniform> per_draw: PerDraw;";
+
+// Fallback without push constants: every draw's data in one buffer, picked with a dynamic offset.
+// Works everywhere (WebGL2 too)
+struct DynamicUniform {
+    bind_group_layout: wgpu::BindGroupLayout,
+    buffer: GrowableBuffer,
+    bind_group: wgpu::BindGroup,
+    // size_of::<PerDrawRaw>() rounded up to min_uniform_buffer_offset_alignment
+    stride: u32,
+}
+
+// Model matrix and texture layer for drawing the mesh one instance per draw call instead of
+// instanced. Push constants where the device has them (see FEATURES / limits), a dynamic offset
+// uniform otherwise, picked once in new(). Same bind groups 0-2 as the mesh pipelines
+pub struct PerDrawData {
+    // None with push constants, those are set right before each draw and need no buffer
+    uniform: Option<DynamicUniform>,
+    data: Vec<PerDrawRaw>,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl PerDrawData {
+    pub const FEATURES: wgpu::Features = wgpu::Features::PUSH_CONSTANTS;
+    pub const PUSH_CONSTANT_SIZE: u32 = std::mem::size_of::<PerDrawRaw>() as u32;
+
+    // FEATURES if the adapter can push PUSH_CONSTANT_SIZE bytes, empty otherwise. The device
+    // also needs Limits::max_push_constant_size raised to PUSH_CONSTANT_SIZE
+    pub fn supported_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+        if adapter.features().contains(Self::FEATURES) && adapter.limits().max_push_constant_size >= Self::PUSH_CONSTANT_SIZE {
+            Self::FEATURES
+        } else {
+            wgpu::Features::empty()
+        }
+    }
+
+    // `layouts`: camera, layered texture and bones bind group layouts
+    pub fn new(device: &wgpu::Device, config: &PipelineConfig, layouts: [&wgpu::BindGroupLayout; 3]) -> Self {
+        let push_constants = device.features().contains(Self::FEATURES)
+            && device.limits().max_push_constant_size >= Self::PUSH_CONSTANT_SIZE;
+        let uniform = (!push_constants).then(|| {
+            let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Per Draw Bind Group Layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<PerDrawRaw>() as u64),
+                        },
+                        count: None,
+                    }
+                ],
+            });
+            let alignment = device.limits().min_uniform_buffer_offset_alignment;
+            let stride = (std::mem::size_of::<PerDrawRaw>() as u32).div_ceil(alignment) * alignment;
+            let buffer = GrowableBuffer::new(device, "Per Draw Buffer", wgpu::BufferUsages::UNIFORM, stride as u64);
+            let bind_group = Self::create_bind_group(device, &bind_group_layout, &buffer);
+            DynamicUniform { bind_group_layout, buffer, bind_group, stride }
+        });
+        let pipeline = Self::create_pipeline(device, config, layouts, uniform.as_ref());
+        log::info!("Per draw data in {}", if push_constants { "push constants" } else { "dynamic uniforms" });
+
+        Self { uniform, data: Vec::new(), pipeline }
+    }
+
+    pub fn uses_push_constants(&self) -> bool {
+        self.uniform.is_none()
+    }
+
+    // Call after the pipeline config changes
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device, config: &PipelineConfig, layouts: [&wgpu::BindGroupLayout; 3]) {
+        self.pipeline = Self::create_pipeline(device, config, layouts, self.uniform.as_ref());
+    }
+
+    fn create_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, buffer: &GrowableBuffer) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Per Draw Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: buffer.buffer(),
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<PerDrawRaw>() as u64),
+                    }),
+                }
+            ],
+        })
+    }
+
+    pub(crate) fn shader_source(push_constants: bool) -> String {
+        let declaration = if push_constants { PUSH_CONSTANT_DECLARATION } else { UNIFORM_DECLARATION };
+        // A push constant in the regular module would fail to compile without the feature
+        format!("{}\n{}\n{}", include_str!("shader.wgsl"), declaration, include_str!("per_draw.wgsl"))
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        layouts: [&wgpu::BindGroupLayout; 3],
+        uniform: Option<&DynamicUniform>,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Per Draw Shader"),
+            source: wgpu::ShaderSource::Wgsl(Self::shader_source(uniform.is_none()).into()),
+        });
+
+        let (bind_group_layouts, push_constant_ranges) = match uniform {
+            None => (
+                layouts.to_vec(),
+                vec![wgpu::PushConstantRange {
+                    stages: wgpu::ShaderStages::VERTEX,
+                    range: 0..Self::PUSH_CONSTANT_SIZE,
+                }],
+            ),
+            Some(uniform) => {
+                let [camera, layered, bones] = layouts;
+                (vec![camera, layered, bones, &uniform.bind_group_layout], Vec::new())
+            }
+        };
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Per Draw Pipeline Layout"),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &push_constant_ranges,
+        });
+
+        pipeline::create_render_pipeline_with_buffers(
+            device, &layout, &shader, config, "vs_per_draw", "fs_main", &[crate::Vertex::desc()]
+        )
+    }
+
+    // Copies every instance's transform and layer, draw(i) draws instance i
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, instances: &[Instance]) {
+        self.data.clear();
+        self.data.extend(instances.iter().map(|instance| PerDrawRaw {
+            model: instance.model_matrix().to_cols_array(),
+            layer: instance.layer,
+            _padding: [0; 3],
+        }));
+
+        if let Some(DynamicUniform { bind_group_layout, buffer, bind_group, stride }) = &mut self.uniform {
+            let mut bytes = vec![0u8; self.data.len() * *stride as usize];
+            for (i, data) in self.data.iter().enumerate() {
+                let start = i * *stride as usize;
+                bytes[start..start + std::mem::size_of::<PerDrawRaw>()].copy_from_slice(bytemuck::bytes_of(data));
+            }
+            if buffer.write(device, queue, &bytes) {
+                *bind_group = Self::create_bind_group(device, bind_group_layout, buffer);
+            }
+        }
+    }
+
+    // Groups 0-2 and vertex buffer 0 are set by the caller, the same as for the mesh pipelines
+    pub fn set_pipeline<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, vertices: Range<u32>, index: usize) {
+        match &self.uniform {
+            None => {
+                let data = bytemuck::bytes_of(&self.data[index]);
+                render_pass.set_push_constants(wgpu::ShaderStages::VERTEX, 0, data);
+            }
+            Some(uniform) => {
+                render_pass.set_bind_group(3, &uniform.bind_group, &[index as u32 * uniform.stride]);
+            }
+        }
+        render_pass.draw(vertices, 0..1);
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    // Push constants need no memory of their own
+    pub fn gpu_memory(&self) -> u64 {
+        self.uniform.as_ref().map_or(0, |uniform| uniform.buffer.capacity())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
@@ -0,0 +1,21 @@
+// Appended to shader.wgsl by per_draw.rs, together with the declaration of `per_draw` (a push
+// constant or a dynamic offset uniform at group 3 binding 1, depending on the adapter)
+
+// Layout must match PerDrawRaw in per_draw.rs
+struct PerDraw {
+    model: mat4x4<f32>,
+    layer: u32,
+}
+
+// vs_main with the model matrix and layer from per_draw instead of the instance buffer
+@vertex
+fn vs_per_draw(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.color = model.color;
+    out.tex_coords = model.tex_coords;
+    out.layer = per_draw.layer;
+    let world = per_draw.model * skin_matrix(model) * vec4<f32>(model.position, 1.0);
+    out.clip_position = camera.view_proj * world;
+    out.world_position = world.xyz;
+    return out;
+}
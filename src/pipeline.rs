@@ -0,0 +1,192 @@
+use crate::instance::InstanceRaw;
+use crate::Vertex;
+
+// Everything pipelines depend on besides the shader source.
+// Changing any of it needs State::rebuild_pipelines()
+#[derive(Copy, Clone, Debug)]
+pub struct PipelineConfig {
+    pub color_format: wgpu::TextureFormat,
+    pub depth_format: wgpu::TextureFormat,
+    pub depth_write: bool,
+    // MSAA, render targets have to be created with the same count
+    pub sample_count: u32,
+    // Depth clamping: fragments past the near / far plane get their depth clamped to it instead
+    // of being clipped away. What depth only (shadow) pipelines want for casters behind the light's
+    // near plane. Needs DEPTH_CLAMP_FEATURES
+    pub unclipped_depth: bool,
+    // Depth bias for geometry drawn over already drawn surfaces (see overlay_depth_state). Negative
+    // constant / slope_scale pull it toward the camera. Zero by default
+    pub overlay_depth_bias: wgpu::DepthBiasState,
+}
+
+// How edges get smoothed, A cycles. FXAA and TAA run on the single sampled scene, MSAA uses
+// PipelineConfig::sample_count (which can be 1 for none)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AntiAliasing {
+    Msaa,
+    Fxaa,
+    Taa,
+}
+
+// Optional, requested where the adapter has it
+pub const DEPTH_CLAMP_FEATURES: wgpu::Features = wgpu::Features::DEPTH_CLIP_CONTROL;
+
+impl PipelineConfig {
+    // Depth test is always on, write can be turned off (e.g. for transparent objects, which should
+    // be hidden by opaque geometry in front of them but not hide what is drawn after them)
+    pub fn depth_state(&self) -> wgpu::DepthStencilState {
+        wgpu::DepthStencilState {
+            format: self.depth_format,
+            depth_write_enabled: self.depth_write,
+            // Keep fragment if it is closer than what's already there
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }
+    }
+
+    // For passes drawn on top of co-planar geometry (wireframe edges, decals): tests with LessEqual,
+    // doesn't write, and gets overlay_depth_bias. Bias is only applied to triangles, lines and points
+    // ignore it
+    pub fn overlay_depth_state(&self) -> wgpu::DepthStencilState {
+        wgpu::DepthStencilState {
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            bias: self.overlay_depth_bias,
+            ..self.depth_state()
+        }
+    }
+
+    pub fn multisample(&self) -> wgpu::MultisampleState {
+        wgpu::MultisampleState {
+            count: self.sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        }
+    }
+}
+
+pub fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    config: &PipelineConfig,
+    vs_entry: &str,
+    fs_entry: &str,
+) -> wgpu::RenderPipeline {
+    // Vertex Buffer
+    let buffers = [Vertex::desc(), InstanceRaw::desc()];
+    create_render_pipeline_with_buffers(device, layout, shader, config, vs_entry, fs_entry, &buffers)
+}
+
+// create_render_pipeline for entry points that don't take the instance buffer (or take more)
+pub fn create_render_pipeline_with_buffers(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    config: &PipelineConfig,
+    vs_entry: &str,
+    fs_entry: &str,
+    buffers: &[wgpu::VertexBufferLayout],
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(vs_entry),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            entry_point: vs_entry,
+            module: shader,
+            buffers,
+        },
+        fragment: Some(wgpu::FragmentState {
+            entry_point: fs_entry,
+            module: shader,
+            targets: &[Some(wgpu::ColorTargetState {
+                format: config.color_format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        //2
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: config.unclipped_depth,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        //3
+        depth_stencil: Some(config.depth_state()),
+        multisample: config.multisample(),
+        multiview: None,
+    })
+}
+
+// Smooth and flat shaded variants of the scene pipeline
+pub fn create_mesh_pipelines(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    config: &PipelineConfig,
+) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    create_mesh_pipelines_with_buffers(device, layout, shader, config, &[Vertex::desc(), InstanceRaw::desc()])
+}
+
+// create_mesh_pipelines with the vertex attributes spread over other buffers, see VertexStreams
+pub fn create_mesh_pipelines_with_buffers(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    config: &PipelineConfig,
+    buffers: &[wgpu::VertexBufferLayout],
+) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    let render_pipeline = create_render_pipeline_with_buffers(device, layout, shader, config, "vs_main", "fs_main", buffers);
+    // Same shader, but color is taken from the provoking vertex instead of interpolated
+    let flat_render_pipeline = create_render_pipeline_with_buffers(device, layout, shader, config, "vs_flat", "fs_flat", buffers);
+    (render_pipeline, flat_render_pipeline)
+}
+
+// Sample counts the adapter can render with for both the color and depth format
+pub fn supported_sample_counts(
+    adapter: &wgpu::Adapter,
+    color_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+) -> Vec<u32> {
+    let color_flags = adapter.get_texture_format_features(color_format).flags;
+    let depth_flags = adapter.get_texture_format_features(depth_format).flags;
+    [1, 2, 4, 8]
+        .into_iter()
+        .filter(|&count| color_flags.sample_count_supported(count) && depth_flags.sample_count_supported(count))
+        .collect()
+}
+
+// Multisampled color target that gets resolved into the surface texture. None for 1 sample
+pub fn create_msaa_view(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+    sample_count: u32,
+) -> Option<wgpu::TextureView> {
+    if sample_count <= 1 {
+        return None;
+    }
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("MSAA Texture"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+}
@@ -0,0 +1,268 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use wgpu::util::DeviceExt;
+
+use crate::buffer::GrowableBuffer;
+use crate::pipeline::PipelineConfig;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+    // Pixels across, 0 or less uses the size passed to draw_points
+    pub size: f32,
+}
+
+impl PointVertex {
+    // White, with the draw's size
+    pub const fn new(position: [f32; 3]) -> Self {
+        Self { position, color: [1.0; 3], size: 0.0 }
+    }
+
+    pub const fn colored(position: [f32; 3], color: [f32; 3]) -> Self {
+        Self { position, color, size: 0.0 }
+    }
+
+    pub(crate) fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+            0 => Float32x3, 1 => Float32x3, 2 => Float32
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<PointVertex>() as wgpu::BufferAddress,
+            // One quad per point
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Layout must match PointUniform in points.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PointUniform {
+    size: f32,
+    // vec2 is 8 byte aligned
+    _padding: f32,
+    viewport: [f32; 2],
+}
+
+struct PointDraw {
+    buffer: Arc<wgpu::Buffer>,
+    count: u32,
+    size: f32,
+}
+
+// Point clouds drawn as round, camera facing sprites of a fixed size in pixels. Points are
+// instances, the quads are made in the vertex shader, so any buffer of PointVertex works. Each
+// draw's size goes through a dynamic offset uniform. Depth tested and written like the mesh
+pub struct PointRenderer {
+    draws: Vec<PointDraw>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: GrowableBuffer,
+    bind_group: wgpu::BindGroup,
+    // size_of::<PointUniform>() rounded up to min_uniform_buffer_offset_alignment
+    stride: u32,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl PointRenderer {
+    pub fn new(device: &wgpu::Device, config: &PipelineConfig, camera_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Point Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<PointUniform>() as u64),
+                    },
+                    count: None,
+                }
+            ],
+        });
+        let alignment = device.limits().min_uniform_buffer_offset_alignment;
+        let stride = (std::mem::size_of::<PointUniform>() as u32).div_ceil(alignment) * alignment;
+        let uniform_buffer = GrowableBuffer::new(device, "Point Uniform Buffer", wgpu::BufferUsages::UNIFORM, stride as u64);
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &uniform_buffer);
+        let pipeline = Self::create_pipeline(device, config, camera_bind_group_layout, &bind_group_layout);
+
+        Self {
+            draws: Vec::new(),
+            bind_group_layout,
+            uniform_buffer,
+            bind_group,
+            stride,
+            pipeline,
+        }
+    }
+
+    // Call after the pipeline config changes
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device, config: &PipelineConfig, camera_bind_group_layout: &wgpu::BindGroupLayout) {
+        self.pipeline = Self::create_pipeline(device, config, camera_bind_group_layout, &self.bind_group_layout);
+    }
+
+    fn create_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, buffer: &GrowableBuffer) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Point Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: buffer.buffer(),
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<PointUniform>() as u64),
+                    }),
+                }
+            ],
+        })
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("points.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Point Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Point Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_point",
+                buffers: &[PointVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_point",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                // Quads always face the camera
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(config.depth_state()),
+            multisample: config.multisample(),
+            multiview: None,
+        })
+    }
+
+    // Vertex buffer for draw_points
+    pub fn create_buffer(device: &wgpu::Device, points: &[PointVertex]) -> Arc<wgpu::Buffer> {
+        Arc::new(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Point Buffer"),
+            // COPY_DST for reloads of the same size, see State::reload_assets
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            contents: bytemuck::cast_slice(points),
+        }))
+    }
+
+    // Keeps drawing the first `count` points of `buffer` until clear()
+    pub fn add(&mut self, buffer: Arc<wgpu::Buffer>, count: u32, size: f32) {
+        self.draws.push(PointDraw { buffer, count, size });
+    }
+
+    // Draws of `old` draw the first `count` points of `new` from now on
+    pub fn replace(&mut self, old: &Arc<wgpu::Buffer>, new: Arc<wgpu::Buffer>, count: u32) {
+        for draw in self.draws.iter_mut().filter(|draw| Arc::ptr_eq(&draw.buffer, old)) {
+            draw.buffer = new.clone();
+            draw.count = count;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.draws.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.draws.is_empty()
+    }
+
+    // Writes every draw's size, `width` and `height` are the render target's
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) {
+        let mut bytes = vec![0u8; self.draws.len() * self.stride as usize];
+        for (i, draw) in self.draws.iter().enumerate() {
+            let uniform = PointUniform { size: draw.size, _padding: 0.0, viewport: [width as f32, height as f32] };
+            let start = i * self.stride as usize;
+            bytes[start..start + std::mem::size_of::<PointUniform>()].copy_from_slice(bytemuck::bytes_of(&uniform));
+        }
+        if self.uniform_buffer.write(device, queue, &bytes) {
+            self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.uniform_buffer);
+        }
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        if self.draws.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        for (i, draw) in self.draws.iter().enumerate() {
+            render_pass.set_bind_group(1, &self.bind_group, &[i as u32 * self.stride]);
+            render_pass.set_vertex_buffer(0, draw.buffer.slice(..));
+            render_pass.draw(0..6, 0..draw.count);
+        }
+    }
+
+    // Uniforms plus the point buffers being drawn
+    pub fn gpu_memory(&self) -> u64 {
+        self.uniform_buffer.capacity() + self.draws.iter().map(|draw| draw.buffer.size()).sum::<u64>()
+    }
+}
+
+// Plain text point cloud, one "x y z" or "x y z r g b" per line. Colors 0..1, or 0..255 when any
+// is above 1. Lines starting with '#' and empty lines are skipped
+pub fn load_xyz(path: &Path) -> Result<Vec<PointVertex>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+    parse_xyz(&text, path)
+}
+
+// load_xyz on text that's already read, `path` is only for the errors
+pub fn parse_xyz(text: &str, path: &Path) -> Result<Vec<PointVertex>, String> {
+    let mut points = Vec::new();
+    let mut bytes_colors = false;
+    // Points given a color, the rest stay white either way
+    let mut colored = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let values = line.split_whitespace()
+            .map(str::parse::<f32>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+        let point = match values[..] {
+            [x, y, z] => PointVertex::new([x, y, z]),
+            [x, y, z, r, g, b, ..] => {
+                bytes_colors |= r > 1.0 || g > 1.0 || b > 1.0;
+                colored.push(points.len());
+                PointVertex::colored([x, y, z], [r, g, b])
+            }
+            _ => return Err(format!("{}:{}: expected x y z [r g b]", path.display(), number + 1)),
+        };
+        points.push(point);
+    }
+    if bytes_colors {
+        for i in colored {
+            points[i].color = points[i].color.map(|c| c / 255.0);
+        }
+    }
+    Ok(points)
+}
@@ -0,0 +1,118 @@
+// Point sprites, see PointRenderer in points.rs. One instance per point, 6 vertices per quad
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    exposure: f32,
+    fog_mode: u32,
+    fog_start: f32,
+    fog_end: f32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    eye: vec3<f32>,
+    fog_height: f32,
+    forward: vec3<f32>,
+    fog_height_falloff: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Layout must match PointUniform in points.rs
+struct PointUniform {
+    // Pixels across, for points without their own size
+    size: f32,
+    // Render target size in pixels
+    viewport: vec2<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> points: PointUniform;
+
+// Has to match the FOG_ constants in camera.rs
+const FOG_LINEAR: u32 = 1u;
+const FOG_EXPONENTIAL: u32 = 2u;
+const FOG_EXPONENTIAL_SQUARED: u32 = 3u;
+
+fn view_depth(world_position: vec3<f32>) -> f32 {
+    return dot(world_position - camera.eye, camera.forward);
+}
+
+// Same as in shader.wgsl
+fn fog_amount(direction_y: f32, distance: f32) -> f32 {
+    let span = max(distance - camera.fog_start, 0.0);
+    let start_height = camera.eye.y + direction_y * camera.fog_start;
+    let density = camera.fog_density * exp(min(-camera.fog_height_falloff * (start_height - camera.fog_height), 80.0));
+    let k = camera.fog_height_falloff * direction_y;
+    if abs(k * span) < 0.0001 {
+        return density * span;
+    }
+    return density * (1.0 - exp(min(-k * span, 80.0))) / k;
+}
+
+fn fog_visibility(world_position: vec3<f32>) -> f32 {
+    let depth = view_depth(world_position);
+    if camera.fog_mode == FOG_LINEAR {
+        return clamp((camera.fog_end - depth) / max(camera.fog_end - camera.fog_start, 0.0001), 0.0, 1.0);
+    } else if camera.fog_mode == FOG_EXPONENTIAL {
+        return exp(-camera.fog_density * max(depth, 0.0));
+    } else if camera.fog_mode == FOG_EXPONENTIAL_SQUARED {
+        let ray = world_position - camera.eye;
+        let distance = length(ray);
+        let amount = fog_amount(ray.y / max(distance, 0.0001), distance);
+        return exp(-amount * amount);
+    }
+    return 1.0;
+}
+
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    return mix(camera.fog_color * camera.exposure, color, fog_visibility(world_position));
+}
+
+// Layout must match PointVertex in points.rs
+struct PointInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+    // 0 or less uses points.size
+    @location(2) size: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+    // -1..1 across the quad
+    @location(1) offset: vec2<f32>,
+    @location(2) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_point(@builtin(vertex_index) in_vertex_index: u32, point: PointInput) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[in_vertex_index];
+    var size = points.size;
+    if point.size > 0.0 {
+        size = point.size;
+    }
+
+    var out: VertexOutput;
+    let center = camera.view_proj * vec4<f32>(point.position, 1.0);
+    // Offset in NDC scaled by w, so the quad stays the same number of pixels at any distance
+    let offset = corner * size / points.viewport;
+    out.clip_position = vec4<f32>(center.xy + offset * center.w, center.zw);
+    out.color = point.color;
+    out.offset = corner;
+    out.world_position = point.position;
+    return out;
+}
+
+// Round points, the quad's corners are cut away
+@fragment
+fn fs_point(in: VertexOutput) -> @location(0) vec4<f32> {
+    if dot(in.offset, in.offset) > 1.0 {
+        discard;
+    }
+    return vec4<f32>(apply_fog(in.color * camera.exposure, in.world_position), 1.0);
+}
@@ -0,0 +1,104 @@
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// How often the poll thread polls by default. Map callbacks fire at most this late
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+// On native, map_async callbacks (and on_submitted_work_done) only run inside device.poll. Most
+// readers here poll on their own (see luminance.rs, frame_stream.rs), this is for everything else:
+// a thread that calls device.poll(Maintain::Poll) every `interval`, so callbacks fire without
+// anyone asking. Stops when dropped. On the web the browser drives mapping and there's no thread
+pub struct PollThread {
+    stop: Arc<AtomicBool>,
+    #[cfg(not(target_arch = "wasm32"))]
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl PollThread {
+    // `device` is anything that derefs to the device and can go to the thread, an Arc usually
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn spawn<D>(device: D, interval: Duration) -> std::io::Result<Self>
+    where
+        D: Deref<Target = wgpu::Device> + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::Builder::new()
+            .name("GPU Poll".into())
+            .spawn(move || {
+                while !thread_stop.load(Ordering::Relaxed) {
+                    device.poll(wgpu::Maintain::Poll);
+                    // Drop unparks it, so stopping doesn't wait out the interval
+                    std::thread::park_timeout(interval);
+                }
+            })?;
+        Ok(Self { stop, thread: Some(thread) })
+    }
+
+    // Nothing to do, the browser resolves mappings on its own
+    #[cfg(target_arch = "wasm32")]
+    pub fn spawn<D>(_device: D, _interval: Duration) -> std::io::Result<Self>
+    where
+        D: Deref<Target = wgpu::Device> + 'static,
+    {
+        Ok(Self { stop: Arc::new(AtomicBool::new(false)) })
+    }
+}
+
+impl Drop for PollThread {
+    // Waits for the thread, which finishes the poll it's in
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Maps a buffer without polling and waits for the callback, which the poll thread has to make
+    // fire. Then checks the mapped bytes and that dropping stops the thread
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn poll_thread_fires_map_callbacks() {
+        let Some((device, queue)) = crate::shader_test::device() else {
+            return;
+        };
+        let data = (0..64u32).collect::<Vec<_>>();
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Poll Thread Check Buffer"),
+            size: (data.len() * 4) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&buffer, 0, bytemuck::cast_slice(&data));
+        queue.submit(std::iter::empty());
+
+        let mapped = Arc::new(AtomicBool::new(false));
+        let callback_mapped = mapped.clone();
+        buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            callback_mapped.store(result.is_ok(), Ordering::Release);
+        });
+
+        let poll_thread = PollThread::spawn(device, DEFAULT_POLL_INTERVAL).expect("Couldn't start the poll thread");
+        let started = instant::Instant::now();
+        while !mapped.load(Ordering::Acquire) {
+            assert!(started.elapsed() <= Duration::from_secs(5), "Map callback didn't fire within 5 seconds with the poll thread running");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(bytemuck::cast_slice::<u8, u32>(&buffer.slice(..).get_mapped_range()), data.as_slice());
+        buffer.unmap();
+
+        let stopping = instant::Instant::now();
+        drop(poll_thread);
+        assert!(stopping.elapsed() <= Duration::from_secs(1), "Poll thread took {:?} to stop", stopping.elapsed());
+    }
+}
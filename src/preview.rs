@@ -0,0 +1,267 @@
+use wgpu::util::DeviceExt;
+
+use crate::camera::{Camera, CameraPose, Projection};
+use crate::math::{Aabb, Vec3};
+use crate::pbr::{PbrFactors, PbrMaterial};
+use crate::pipeline::PipelineConfig;
+use crate::primitives;
+use crate::readback;
+use crate::texture::Texture;
+use crate::Vertex;
+
+// What render_preview draws
+pub enum PreviewAsset<'a> {
+    // Triangle list with its vertex colors
+    Mesh(&'a [Vertex]),
+    // Albedo texture and factors on a sphere. Normal and ao textures and image based lighting
+    // aren't part of the preview shading
+    Material(&'a PbrMaterial),
+    // On a quad of its aspect ratio, unlit. Any float format, shown nearest
+    Texture(&'a Texture),
+}
+
+// Layout must match PreviewUniform in preview.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PreviewUniform {
+    view_proj: [[f32; 4]; 4],
+    eye: [f32; 3],
+    lit: u32,
+    base_color: [f32; 4],
+    metallic: f32,
+    roughness: f32,
+    _padding: [f32; 2],
+}
+
+const FOVY: f32 = 45.0;
+
+// Renders `asset` alone into a size x size RGBA8 image (sRGB, transparent background), with a
+// camera framing its bounds. Everything it draws with is its own, the scene's buffers aren't
+// touched. Builds its pipeline every call and blocks until the pixels are back, so it's for tools
+// and tests, not per frame work
+pub fn render_preview(device: &wgpu::Device, queue: &wgpu::Queue, asset: PreviewAsset, size: u32) -> Vec<u8> {
+    let config = PipelineConfig {
+        color_format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        depth_format: wgpu::TextureFormat::Depth32Float,
+        depth_write: true,
+        sample_count: 1,
+        unclipped_depth: false,
+        overlay_depth_bias: wgpu::DepthBiasState::default(),
+    };
+
+    // Meshes have no texture, the shader multiplies this in instead
+    let white = Texture::from_rgba(device, queue, &[255; 4], 1, 1, "Preview White Texture");
+    let (vertices, direction, factors, color_view, lit) = match asset {
+        PreviewAsset::Mesh(vertices) => (vertices.to_vec(), Vec3::new(0.4, 0.3, 1.0), PbrFactors::default(), &white.view, true),
+        PreviewAsset::Material(material) => {
+            let sphere = primitives::uv_sphere(0.5, 48, 24)
+                .into_iter()
+                .map(|vertex| Vertex { color: [1.0; 3], ..vertex })
+                .collect();
+            (sphere, Vec3::new(0.0, 0.2, 1.0), material.factors(), &material.textures[0].view, true)
+        }
+        PreviewAsset::Texture(texture) => {
+            let aspect = texture.texture.width() as f32 / texture.texture.height() as f32;
+            let (x, y) = (aspect * 0.5, 0.5);
+            let corners = [
+                Vertex::new([-x, y, 0.0], [1.0; 3], [0.0, 0.0]),
+                Vertex::new([-x, -y, 0.0], [1.0; 3], [0.0, 1.0]),
+                Vertex::new([x, -y, 0.0], [1.0; 3], [1.0, 1.0]),
+                Vertex::new([x, y, 0.0], [1.0; 3], [1.0, 0.0]),
+            ];
+            let quad = [0, 1, 2, 0, 2, 3].map(|i| corners[i]).to_vec();
+            (quad, Vec3::Z, PbrFactors::default(), &texture.view, false)
+        }
+    };
+
+    let bounds = Aabb::from_points(vertices.iter().map(|vertex| Vec3::from(vertex.position)))
+        .unwrap_or(Aabb { min: Vec3::ZERO, max: Vec3::ZERO });
+    let pose = CameraPose::framing(&bounds, direction, FOVY, 1.0);
+    let distance = (pose.eye - pose.target).length();
+    let camera = Camera {
+        eye: pose.eye,
+        target: pose.target,
+        up: pose.up,
+        aspect: 1.0,
+        fovy: FOVY,
+        znear: distance * 0.01,
+        zfar: distance * 10.0,
+        projection: Projection::Perspective,
+    };
+    let uniform = PreviewUniform {
+        view_proj: camera.build_view_projection_matrix().to_cols_array(),
+        eye: camera.eye.to_array(),
+        lit: lit as u32,
+        base_color: factors.albedo,
+        metallic: factors.metallic,
+        roughness: factors.roughness,
+        _padding: [0.0; 2],
+    };
+
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Preview Bind Group Layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<PreviewUniform>() as u64),
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ],
+    });
+    let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Preview Uniform Buffer"),
+        contents: bytemuck::cast_slice(&[uniform]),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Preview Bind Group"),
+        layout: &bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(color_view) },
+        ],
+    });
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Preview Vertex Buffer"),
+        contents: bytemuck::cast_slice(&vertices),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+
+    let shader = device.create_shader_module(wgpu::include_wgsl!("preview.wgsl"));
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Preview Pipeline Layout"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Preview Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState { module: &shader, entry_point: "vs_main", buffers: &[Vertex::desc()] },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(config.color_format.into())],
+        }),
+        // Assets wind either way, the shader lights whichever side is seen
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(config.depth_state()),
+        multisample: config.multisample(),
+        multiview: None,
+    });
+
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Preview Texture"),
+        size: wgpu::Extent3d { width: size, height: size, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.color_format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+    let depth_texture = Texture::create_depth_texture_sized(device, size, size, config.depth_format, 1, "Preview Depth Texture");
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Preview Encoder") });
+    {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Preview Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Transparent background so previews can be put on anything
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth_texture.view,
+                depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Clear(1.0), store: false }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.draw(0..vertices.len() as u32, 0..1);
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+
+    readback::read_texture_rgba(device, queue, &target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 64;
+
+    fn pixel(pixels: &[u8], x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * SIZE + x) * 4) as usize;
+        pixels[i..i + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn texture_fills_its_quad() {
+        let Some((device, queue)) = crate::shader_test::device() else {
+            return;
+        };
+        let texture = Texture::from_rgba(device, queue, &[255, 0, 0, 255, 0, 0, 255, 255], 2, 1, "Preview Test Texture");
+        let pixels = render_preview(device, queue, PreviewAsset::Texture(&texture), SIZE);
+        assert_eq!(pixel(&pixels, 20, SIZE / 2), [255, 0, 0, 255]);
+        assert_eq!(pixel(&pixels, 44, SIZE / 2), [0, 0, 255, 255]);
+        // 2:1, nothing above or below
+        assert_eq!(pixel(&pixels, SIZE / 2, 4), [0; 4]);
+    }
+
+    #[test]
+    fn mesh_is_framed_and_lit() {
+        let Some((device, queue)) = crate::shader_test::device() else {
+            return;
+        };
+        // Far from the origin and big, framing has to find it
+        let mesh = primitives::uv_sphere(40.0, 32, 16)
+            .into_iter()
+            .map(|vertex| Vertex { position: (Vec3::from(vertex.position) + Vec3::new(100.0, 0.0, 0.0)).to_array(), color: [1.0; 3], ..vertex })
+            .collect::<Vec<_>>();
+        let pixels = render_preview(device, queue, PreviewAsset::Mesh(&mesh), SIZE);
+        let center = pixel(&pixels, SIZE / 2, SIZE / 2);
+        assert_eq!(center[3], 255, "nothing drawn in the middle");
+        assert_eq!(pixel(&pixels, 0, 0), [0; 4]);
+        // Key light from the top left, the bottom right is only filled
+        let lit = pixel(&pixels, SIZE / 2 - 8, SIZE / 2 - 8);
+        let filled = pixel(&pixels, SIZE / 2 + 8, SIZE / 2 + 8);
+        assert!(lit[0] > filled[0], "key side {:?} isn't brighter than fill side {:?}", lit, filled);
+    }
+
+    #[test]
+    fn material_shows_its_albedo() {
+        let Some((device, queue)) = crate::shader_test::device() else {
+            return;
+        };
+        let layout = crate::reflection::ShaderReflection::new("PBR", include_str!("pbr.wgsl"))
+            .and_then(|shader| shader.layout(device, 1))
+            .unwrap();
+        let factors = PbrFactors { albedo: [0.0, 1.0, 0.0, 1.0], ..Default::default() };
+        let material = PbrMaterial::new(device, queue, &layout, Default::default(), factors).unwrap();
+        let pixels = render_preview(device, queue, PreviewAsset::Material(&material), SIZE);
+        let [r, g, b, a] = pixel(&pixels, SIZE / 2, SIZE / 2);
+        assert!(a == 255 && g > r && g > b, "center is {:?}, not green", [r, g, b, a]);
+    }
+}
@@ -0,0 +1,85 @@
+// Thumbnails of assets, see preview.rs. Lit assets get a key, a fill and a rim light, with normals
+// from the triangles since vertices have none
+
+// Layout must match PreviewUniform in preview.rs
+struct PreviewUniform {
+    view_proj: mat4x4<f32>,
+    eye: vec3<f32>,
+    // 0 shows the texture as it is, 1 lights it
+    lit: u32,
+    // Linear, multiplied with the vertex color and the texture
+    base_color: vec4<f32>,
+    metallic: f32,
+    roughness: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> preview: PreviewUniform;
+// Loaded, not sampled, so every float format works
+@group(0) @binding(1)
+var t_color: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = preview.view_proj * vec4<f32>(position, 1.0);
+    out.world_position = position;
+    out.color = color;
+    out.tex_coords = tex_coords;
+    return out;
+}
+
+// Towards the light and its color, the camera looks from +z
+const KEY_DIRECTION: vec3<f32> = vec3<f32>(-0.5, 0.7, 0.6);
+const KEY_COLOR: vec3<f32> = vec3<f32>(1.6, 1.5, 1.4);
+const FILL_DIRECTION: vec3<f32> = vec3<f32>(0.8, 0.1, 0.5);
+const FILL_COLOR: vec3<f32> = vec3<f32>(0.4, 0.45, 0.55);
+const RIM_DIRECTION: vec3<f32> = vec3<f32>(0.2, 0.5, -1.0);
+const RIM_COLOR: vec3<f32> = vec3<f32>(1.0, 1.0, 1.0);
+const AMBIENT: f32 = 0.05;
+
+// Lambert plus normalized Blinn-Phong, a stand in for pbr.wgsl that needs no environment
+fn light(direction: vec3<f32>, color: vec3<f32>, normal: vec3<f32>, view: vec3<f32>, albedo: vec3<f32>) -> vec3<f32> {
+    let l = normalize(direction);
+    let n_dot_l = max(dot(normal, l), 0.0);
+    let h = normalize(l + view);
+    let roughness = clamp(preview.roughness, 0.05, 1.0);
+    let shininess = 2.0 / (roughness * roughness * roughness * roughness) - 2.0;
+    let specular = pow(max(dot(normal, h), 0.0), shininess) * (shininess + 8.0) / 8.0;
+    let f0 = mix(vec3<f32>(0.04), albedo, preview.metallic);
+    let diffuse = albedo * (1.0 - preview.metallic);
+    return (diffuse + f0 * specular) * color * n_dot_l;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_color));
+    let texel = vec2<i32>(clamp(in.tex_coords * size, vec2<f32>(0.0), size - 1.0));
+    let base = textureLoad(t_color, texel, 0) * preview.base_color * vec4<f32>(in.color, 1.0);
+    if preview.lit == 0u {
+        return base;
+    }
+
+    let view = normalize(preview.eye - in.world_position);
+    var normal = normalize(cross(dpdx(in.world_position), dpdy(in.world_position)));
+    // Whichever way the triangle winds, light the side that's seen
+    if dot(normal, view) < 0.0 {
+        normal = -normal;
+    }
+    var color = base.rgb * AMBIENT;
+    color += light(KEY_DIRECTION, KEY_COLOR, normal, view, base.rgb);
+    color += light(FILL_DIRECTION, FILL_COLOR, normal, view, base.rgb);
+    color += light(RIM_DIRECTION, RIM_COLOR, normal, view, base.rgb);
+    return vec4<f32>(color, base.a);
+}
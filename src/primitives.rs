@@ -0,0 +1,347 @@
+use std::f32::consts::PI;
+
+use crate::math::Vec3;
+use crate::mesh;
+use crate::Vertex;
+
+// UV sphere around the origin as a triangle list, `segments` around and `rings` from pole to pole.
+// Colored by normal, U goes around and V from the top
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Vec<Vertex> {
+    let segments = segments.max(3);
+    let rings = rings.max(2);
+    let point = |ring: u32, segment: u32| {
+        let (u, v) = (segment as f32 / segments as f32, ring as f32 / rings as f32);
+        let (theta, phi) = (v * PI, u * 2.0 * PI);
+        let normal = [theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()];
+        Vertex::new(normal.map(|n| n * radius), normal.map(|n| n * 0.5 + 0.5), [u, v])
+    };
+
+    let mut vertices = Vec::with_capacity((segments * rings * 6) as usize);
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let top_left = point(ring, segment);
+            let bottom_left = point(ring + 1, segment);
+            let bottom_right = point(ring + 1, segment + 1);
+            let top_right = point(ring, segment + 1);
+            // The triangle touching a pole would have no area there
+            if ring != rings - 1 {
+                vertices.extend([top_left, bottom_right, bottom_left]);
+            }
+            if ring != 0 {
+                vertices.extend([top_left, top_right, bottom_right]);
+            }
+        }
+    }
+    vertices
+}
+
+// `levels` spheres for State::set_mesh_lods, each with half the segments and rings of the one
+// before, starting at 32 x 16
+pub fn uv_sphere_lods(radius: f32, levels: u32) -> Vec<Vec<Vertex>> {
+    (0..levels).map(|level| uv_sphere(radius, 32 >> level, 16 >> level)).collect()
+}
+
+// A vertex with its own shading frame. Vertex has no normals or tangents (shading takes them from
+// derivatives, see pbr.wgsl), so this is what the generators below make and what the frame debug
+// lines show. The tangent points along +U, handedness gives the bitangent (+V) as glTF does
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SurfaceVertex {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub tangent: Vec3,
+    pub handedness: f32,
+    pub tex_coords: [f32; 2],
+}
+
+impl SurfaceVertex {
+    // `along_v` is any vector pointing toward +V, only its side of the tangent plane matters
+    pub fn new(position: Vec3, normal: Vec3, tangent: Vec3, along_v: Vec3, tex_coords: [f32; 2]) -> Self {
+        let handedness = if normal.cross(tangent).dot(along_v) < 0.0 { -1.0 } else { 1.0 };
+        Self { position, normal, tangent, handedness, tex_coords }
+    }
+
+    pub fn bitangent(&self) -> Vec3 {
+        self.normal.cross(self.tangent) * self.handedness
+    }
+}
+
+// Triangle list of SurfaceVertex, counter clockwise front faces like every mesh here
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Surface {
+    pub vertices: Vec<SurfaceVertex>,
+}
+
+impl Surface {
+    // Flat frames of any mesh, per triangle from its winding and UVs. The same frame pbr.wgsl
+    // builds from derivatives, so a flipped triangle gets a normal pointing inside
+    pub fn from_mesh(vertices: &[Vertex]) -> Self {
+        let mut surface = Surface::default();
+        for triangle in vertices.chunks_exact(3) {
+            let p = triangle.iter().map(|v| Vec3::from(v.position)).collect::<Vec<_>>();
+            let uv = triangle.iter().map(|v| v.tex_coords).collect::<Vec<_>>();
+            let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+            let (du1, dv1, du2, dv2) = (uv[1][0] - uv[0][0], uv[1][1] - uv[0][1], uv[2][0] - uv[0][0], uv[2][1] - uv[0][1]);
+            let normal = e1.cross(e2).normalize();
+            let det = du1 * dv2 - du2 * dv1;
+            // Without usable UVs the tangent is just some direction in the plane
+            let (along_u, along_v) = if det.abs() > 1e-12 {
+                ((e1 * dv2 - e2 * dv1) / det, (e2 * du1 - e1 * du2) / det)
+            } else {
+                (e1, normal.cross(e1))
+            };
+            let tangent = (along_u - normal * normal.dot(along_u)).normalize();
+            for (&position, &tex_coords) in p.iter().zip(&uv) {
+                surface.vertices.push(SurfaceVertex::new(position, normal, tangent, along_v, tex_coords));
+            }
+        }
+        surface
+    }
+
+    // Colored by normal like uv_sphere, for State::set_mesh
+    pub fn mesh(&self) -> Vec<Vertex> {
+        self.vertices
+            .iter()
+            .map(|v| Vertex::new(v.position.to_array(), v.normal.to_array().map(|n| n * 0.5 + 0.5), v.tex_coords))
+            .collect()
+    }
+
+    pub fn positions(&self) -> Vec<Vec3> {
+        self.vertices.iter().map(|v| v.position).collect()
+    }
+
+    // Quads between neighbouring points of a rows x columns grid, row 0 at +V = 0. Columns wrap
+    // around (the last one repeats the first one's positions with U = 1). A pole row has every
+    // point in the same place, the triangle that would be flat there is left out
+    fn grid(&mut self, rows: u32, columns: u32, poles: (bool, bool), point: impl Fn(u32, u32) -> SurfaceVertex) {
+        for row in 0..rows - 1 {
+            for column in 0..columns {
+                let top_left = point(row, column);
+                let bottom_left = point(row + 1, column);
+                let bottom_right = point(row + 1, column + 1);
+                let top_right = point(row, column + 1);
+                if !(poles.1 && row == rows - 2) {
+                    self.vertices.extend([top_left, bottom_right, bottom_left]);
+                }
+                if !(poles.0 && row == 0) {
+                    self.vertices.extend([top_left, top_right, bottom_right]);
+                }
+            }
+        }
+    }
+
+    // Flat disc at height `y` facing up or down. Shares its rim with the side of a cylinder made
+    // from the same `rim` function
+    fn cap(&mut self, y: f32, up: bool, radius: f32, segments: u32, rim: impl Fn(u32) -> Vec3) {
+        let normal = if up { Vec3::Y } else { -Vec3::Y };
+        // Planar UVs seen from outside: U along +x, V along +z on the bottom and -z on top
+        let along_v = if up { -Vec3::Z } else { Vec3::Z };
+        let point = |position: Vec3| {
+            let uv = [0.5 + position.x / (2.0 * radius), 0.5 + position.z * along_v.z / (2.0 * radius)];
+            SurfaceVertex::new(position, normal, Vec3::X, along_v, uv)
+        };
+        let center = point(Vec3::new(0.0, y, 0.0));
+        for segment in 0..segments {
+            let (a, b) = (point(rim(segment)), point(rim((segment + 1) % segments)));
+            if up {
+                self.vertices.extend([center, b, a]);
+            } else {
+                self.vertices.extend([center, a, b]);
+            }
+        }
+    }
+}
+
+// Angle of `segment` around a circle of `segments`, the last one wraps to exactly the first so
+// shared positions are bit for bit equal
+fn around(segment: u32, segments: u32) -> f32 {
+    (segment % segments) as f32 / segments as f32 * 2.0 * PI
+}
+
+// Cylinder around the y axis, `height` tall and centered on the origin. Uncapped is an open tube.
+// U goes around, V from the top down the side, the caps are mapped flat
+pub fn cylinder(radius: f32, height: f32, segments: u32, capped: bool) -> Surface {
+    let segments = segments.max(3);
+    let half = height * 0.5;
+    let rim = |segment: u32, y: f32| {
+        let phi = around(segment, segments);
+        Vec3::new(radius * phi.cos(), y, radius * phi.sin())
+    };
+
+    let mut surface = Surface::default();
+    surface.grid(2, segments, (false, false), |row, segment| {
+        let phi = around(segment, segments);
+        let position = rim(segment, if row == 0 { half } else { -half });
+        let normal = Vec3::new(phi.cos(), 0.0, phi.sin());
+        let tangent = Vec3::new(-phi.sin(), 0.0, phi.cos());
+        SurfaceVertex::new(position, normal, tangent, -Vec3::Y, [segment as f32 / segments as f32, row as f32])
+    });
+    if capped {
+        surface.cap(half, true, radius, segments, |segment| rim(segment, half));
+        surface.cap(-half, false, radius, segments, |segment| rim(segment, -half));
+    }
+    surface
+}
+
+// Torus around the y axis, `major_radius` to the middle of the tube. U goes around the y axis,
+// V around the tube starting from the outside, downward first like the other surfaces' V
+pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> Surface {
+    let major_segments = major_segments.max(3);
+    let minor_segments = minor_segments.max(3);
+
+    let mut surface = Surface::default();
+    surface.grid(minor_segments + 1, major_segments, (false, false), |ring, segment| {
+        let (alpha, beta) = (around(segment, major_segments), -around(ring, minor_segments));
+        let normal = Vec3::new(beta.cos() * alpha.cos(), beta.sin(), beta.cos() * alpha.sin());
+        let center = Vec3::new(major_radius * alpha.cos(), 0.0, major_radius * alpha.sin());
+        let tangent = Vec3::new(-alpha.sin(), 0.0, alpha.cos());
+        let along_v = Vec3::new(beta.sin() * alpha.cos(), -beta.cos(), beta.sin() * alpha.sin());
+        let uv = [segment as f32 / major_segments as f32, ring as f32 / minor_segments as f32];
+        SurfaceVertex::new(center + normal * minor_radius, normal, tangent, along_v, uv)
+    });
+    surface
+}
+
+// Cylinder of `height` with a hemisphere of `radius` on each end, around the y axis and centered
+// on the origin. `rings` per hemisphere. V follows the outline from the top pole to the bottom
+// one, so the texture isn't stretched more on the caps than on the side
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Surface {
+    let segments = segments.max(3);
+    let rings = rings.max(1);
+    let half = height * 0.5;
+    // (polar angle, y offset) of every row, the two middle rows are the cylinder's ends
+    let rows = (0..=rings)
+        .map(|ring| (ring as f32 / rings as f32 * PI * 0.5, half))
+        .chain((0..=rings).map(|ring| (PI * 0.5 + ring as f32 / rings as f32 * PI * 0.5, -half)))
+        .collect::<Vec<_>>();
+    let outline = PI * radius + height;
+    let v = |row: usize| {
+        let (theta, y) = rows[row];
+        (theta * radius + half - y) / outline
+    };
+
+    let mut surface = Surface::default();
+    surface.grid(rows.len() as u32, segments, (true, true), |row, segment| {
+        let (theta, y) = rows[row as usize];
+        let phi = around(segment, segments);
+        let normal = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+        let tangent = Vec3::new(-phi.sin(), 0.0, phi.cos());
+        let along_v = Vec3::new(theta.cos() * phi.cos(), -theta.sin(), theta.cos() * phi.sin());
+        let position = normal * radius + Vec3::new(0.0, y, 0.0);
+        SurfaceVertex::new(position, normal, tangent, along_v, [segment as f32 / segments as f32, v(row as usize)])
+    });
+    surface
+}
+
+pub const NAMES: &[&str] = &["cylinder", "tube", "torus", "capsule"];
+
+// A unit sized example of each generator, for --primitive
+pub fn by_name(name: &str) -> Option<Surface> {
+    Some(match name {
+        "cylinder" => cylinder(0.4, 0.8, 32, true),
+        "tube" => cylinder(0.4, 0.8, 32, false),
+        "torus" => torus(0.35, 0.15, 32, 16),
+        "capsule" => capsule(0.3, 0.6, 32, 8),
+        _ => return None,
+    })
+}
+
+// Everything wrong with a surface's frames and topology, one line per problem kind. Normals have to
+// agree with the winding, tangents with the UVs, and all of them be unit length and orthogonal
+pub fn audit(surface: &Surface, closed: bool) -> Vec<String> {
+    let mut problems = Vec::new();
+    let edges = mesh::edge_report(&surface.positions());
+    if closed && !edges.is_watertight() {
+        problems.push(format!("not watertight: {:?}", edges));
+    }
+    if !closed && (edges.flipped > 0 || edges.non_manifold > 0) {
+        problems.push(format!("{} flipped and {} non-manifold edges", edges.flipped, edges.non_manifold));
+    }
+
+    let faces = Surface::from_mesh(&surface.mesh());
+    let mut counts = [0; 4];
+    for (vertex, face) in surface.vertices.iter().zip(&faces.vertices) {
+        let unit = |v: Vec3| (v.length() - 1.0).abs() < 1e-3;
+        counts[0] += (vertex.normal.dot(face.normal) <= 0.0) as usize;
+        // Only where the triangle is close enough to the surface to say which way U and V go
+        let comparable = vertex.normal.dot(face.normal) > 0.7;
+        counts[1] += (comparable && (vertex.tangent.dot(face.tangent) <= 0.0 || vertex.bitangent().dot(face.bitangent()) <= 0.0)) as usize;
+        counts[2] += !(unit(vertex.normal) && unit(vertex.tangent) && vertex.normal.dot(vertex.tangent).abs() < 1e-3) as usize;
+        counts[3] += vertex.tex_coords.iter().any(|c| !(0.0..=1.0).contains(c)) as usize;
+    }
+    let kinds = ["normals against the winding", "tangents against the UVs", "frames not orthonormal", "UVs outside 0..1"];
+    for (count, kind) in counts.iter().zip(kinds) {
+        if *count > 0 {
+            problems.push(format!("{} vertices with {}", count, kind));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (segments, rings) from the least a generator takes up to a smooth one
+    const RESOLUTIONS: [(u32, u32); 3] = [(3, 1), (8, 4), (32, 16)];
+
+    // Closed surfaces have to be watertight (every edge shared by exactly two triangles, wound
+    // opposite ways), open ones at least consistently wound, and every frame has to pass audit()
+    fn assert_sound(name: &str, surface: &Surface, closed: bool) {
+        let problems = audit(surface, closed);
+        assert!(problems.is_empty(), "{}: {}", name, problems.join(", "));
+    }
+
+    #[test]
+    fn cylinder_is_watertight() {
+        for (segments, _) in RESOLUTIONS {
+            assert_sound(&format!("cylinder {}", segments), &cylinder(0.5, 1.0, segments, true), true);
+        }
+    }
+
+    #[test]
+    fn tube_is_consistently_wound() {
+        for (segments, _) in RESOLUTIONS {
+            let tube = cylinder(0.5, 1.0, segments, false);
+            assert_sound(&format!("tube {}", segments), &tube, false);
+            // Open exactly at both ends, nowhere else
+            assert_eq!(mesh::edge_report(&tube.positions()).open, 2 * segments.max(3) as usize, "tube {}", segments);
+        }
+    }
+
+    #[test]
+    fn torus_is_watertight() {
+        for (segments, rings) in RESOLUTIONS {
+            assert_sound(&format!("torus {} x {}", segments, rings + 2), &torus(1.0, 0.25, segments, rings + 2), true);
+        }
+    }
+
+    #[test]
+    fn capsule_is_watertight() {
+        for (segments, rings) in RESOLUTIONS {
+            assert_sound(&format!("capsule {} x {}", segments, rings), &capsule(0.5, 1.0, segments, rings), true);
+        }
+    }
+
+    #[test]
+    fn uv_sphere_is_watertight() {
+        for (segments, rings) in RESOLUTIONS {
+            let sphere = Surface::from_mesh(&uv_sphere(0.5, segments, rings + 1));
+            assert_sound(&format!("uv sphere {} x {}", segments, rings + 1), &sphere, true);
+        }
+    }
+
+    // Wrong winding has to be caught, or the tests above prove nothing. One triangle turned around
+    // breaks the edge pairing, all of them turned around leaves the normals pointing against it
+    #[test]
+    fn audit_catches_flipped_triangles() {
+        let mut one = capsule(0.5, 1.0, 8, 4);
+        one.vertices.swap(1, 2);
+        assert!(audit(&one, true).iter().any(|problem| problem.starts_with("not watertight")));
+
+        let mut all = capsule(0.5, 1.0, 8, 4);
+        for triangle in all.vertices.chunks_mut(3) {
+            triangle.swap(1, 2);
+        }
+        assert!(audit(&all, true).iter().any(|problem| problem.contains("normals against the winding")));
+    }
+}
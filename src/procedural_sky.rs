@@ -0,0 +1,248 @@
+use crate::pbr;
+use crate::reflection::ShaderReflection;
+use crate::texture::Texture;
+
+// Equirectangular, twice as wide as high. At 1024 a texel is about a third of a degree, so the
+// default sun is a few texels across
+const WIDTH: u32 = 1024;
+const HEIGHT: u32 = WIDTH / 2;
+// Same as Texture::from_rgba_f32, filterable without extra features
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// Look of the procedural sky, see State::set_procedural_sky. Colors are linear
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ProceduralSkyParams {
+    // Towards the sun, doesn't need to be normalized. Defaults to pbr::SUN_DIRECTION
+    pub sun_direction: [f32; 3],
+    // Angular diameter in degrees. The real one is about 0.5, that's under 2 texels of the map
+    pub sun_size: f32,
+    pub sun_color: [f32; 3],
+    // Multiplies sun_color. Well above 1 so the disk stands out of the sky once there's bloom
+    pub sun_intensity: f32,
+    // Haze, 1 is perfectly clear air, 10 is a hazy summer day. Widens the glow around the sun and
+    // reddens the sun and the horizon when it's low
+    pub turbidity: f32,
+    pub zenith_color: [f32; 3],
+    pub horizon_color: [f32; 3],
+    // Everything below the horizon
+    pub ground_color: [f32; 3],
+}
+
+impl Default for ProceduralSkyParams {
+    fn default() -> Self {
+        Self {
+            sun_direction: pbr::SUN_DIRECTION,
+            sun_size: 2.0,
+            sun_color: [1.0, 0.95, 0.85],
+            sun_intensity: 40.0,
+            turbidity: 2.0,
+            zenith_color: [0.15, 0.3, 0.65],
+            horizon_color: [0.6, 0.7, 0.8],
+            ground_color: [0.2, 0.18, 0.16],
+        }
+    }
+}
+
+// Layout must match ProceduralSkyUniform in procedural_sky.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ProceduralSkyUniform {
+    sun_direction: [f32; 3],
+    sun_cos_radius: f32,
+    sun_radiance: [f32; 3],
+    turbidity: f32,
+    zenith_color: [f32; 3],
+    _padding0: f32,
+    horizon_color: [f32; 3],
+    _padding1: f32,
+    ground_color: [f32; 3],
+    _padding2: f32,
+}
+
+impl From<&ProceduralSkyParams> for ProceduralSkyUniform {
+    fn from(params: &ProceduralSkyParams) -> Self {
+        let [x, y, z] = params.sun_direction;
+        let length = (x * x + y * y + z * z).sqrt();
+        // Straight up rather than NaN
+        let sun_direction = if length > 1e-6 { [x / length, y / length, z / length] } else { [0.0, 1.0, 0.0] };
+        let radius = (params.sun_size.clamp(0.0, 90.0) * 0.5).to_radians();
+        let intensity = params.sun_intensity.max(0.0);
+        Self {
+            sun_direction,
+            sun_cos_radius: radius.cos(),
+            sun_radiance: params.sun_color.map(|c| c * intensity),
+            turbidity: params.turbidity.max(1.0),
+            zenith_color: params.zenith_color,
+            _padding0: 0.0,
+            horizon_color: params.horizon_color,
+            _padding1: 0.0,
+            ground_color: params.ground_color,
+            _padding2: 0.0,
+        }
+    }
+}
+
+// Sky without an HDR file: renders ProceduralSkyParams into an equirectangular map, which then goes
+// where a loaded environment would (SkyRenderer and IblMaps), so the background and image based
+// lighting come from the same sky. Only runs when the parameters change
+pub struct ProceduralSky {
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ProceduralSky {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let bind_group_layout = ShaderReflection::new("Procedural Sky", include_str!("procedural_sky.wgsl"))
+            .and_then(|shader| shader.layout(device, 0))
+            .unwrap_or_else(|e| panic!("{}", e));
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Procedural Sky Uniform Buffer"),
+            size: std::mem::size_of::<ProceduralSkyUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = bind_group_layout
+            .create_bind_group(device, &[("sky".into(), uniform_buffer.as_entire_binding())])
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("procedural_sky.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Procedural Sky Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout.layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Procedural Sky Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(FORMAT.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self { uniform_buffer, bind_group, pipeline }
+    }
+
+    // A new map of `params`, submitted right away. Sampled like Texture::from_rgba_f32's, so it
+    // can be handed to SkyRenderer::set_environment and IblMaps::generate as is
+    pub fn render(&self, device: &wgpu::Device, queue: &wgpu::Queue, params: &ProceduralSkyParams) -> Texture {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&ProceduralSkyUniform::from(params)));
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Procedural Sky"),
+            size: wgpu::Extent3d { width: WIDTH, height: HEIGHT, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Procedural Sky Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Procedural Sky Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        // Every texel gets written
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            // Wraps around horizontally, poles don't
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Texture { texture, view, sampler }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture;
+
+    // Texel of the map that direction points at, same mapping as equirect_uv in sky.wgsl
+    fn texel_towards(texels: &[[f32; 4]], direction: [f32; 3]) -> [f32; 4] {
+        let [x, y, z] = direction;
+        let length = (x * x + y * y + z * z).sqrt();
+        let u = z.atan2(x) / (2.0 * std::f32::consts::PI) + 0.5;
+        let v = (y / length).clamp(-1.0, 1.0).acos() / std::f32::consts::PI;
+        let column = ((u * WIDTH as f32) as u32).min(WIDTH - 1);
+        let row = ((v * HEIGHT as f32) as u32).min(HEIGHT - 1);
+        texels[(row * WIDTH + column) as usize]
+    }
+
+    // Renders the default sky and one with the sun just over the horizon and reads them back: the sun
+    // disk has to be far brighter than 1, the zenith bluer than the horizon, the ground the ground
+    // color, and the horizon facing a low sun redder than with the sun up high
+    #[test]
+    fn sky_has_sun_gradient_and_ground() {
+        let Some((device, queue)) = crate::shader_test::device() else {
+            return;
+        };
+        let sky = ProceduralSky::new(device);
+        let read = |params: &ProceduralSkyParams| -> Vec<[f32; 4]> {
+            let map = sky.render(device, queue, params);
+            crate::readback::read_texture_mip(device, queue, &map.texture, 0)
+                .chunks_exact(8)
+                .map(|texel| std::array::from_fn(|i| texture::f16_to_f32(u16::from_le_bytes([texel[i * 2], texel[i * 2 + 1]]))))
+                .collect()
+        };
+        let redness = |texel: [f32; 4]| texel[0] / texel[2].max(1e-4);
+
+        let params = ProceduralSkyParams::default();
+        let texels = read(&params);
+        let sun = texel_towards(&texels, params.sun_direction);
+        assert!(sun[..3].iter().all(|&c| c >= 10.0), "The sun disk is {:?}, expected well over 1", sun);
+
+        // Facing away from the sun, out of its glow
+        let [x, _, z] = params.sun_direction;
+        let zenith = texel_towards(&texels, [0.0, 1.0, 0.0]);
+        let horizon = texel_towards(&texels, [-x, 0.02, -z]);
+        assert!(redness(zenith) < redness(horizon), "The zenith {:?} isn't bluer than the horizon {:?}", zenith, horizon);
+
+        let ground = texel_towards(&texels, [0.0, -1.0, 0.0]);
+        assert!(
+            ground[..3].iter().zip(params.ground_color).all(|(&c, expected)| (c - expected).abs() <= 0.01),
+            "The ground is {:?}, expected {:?}",
+            ground, params.ground_color
+        );
+
+        let low = ProceduralSkyParams { sun_direction: [x, 0.05, z], ..params };
+        let sunset = texel_towards(&read(&low), [x, 0.02, z]);
+        let midday = texel_towards(&texels, [x, 0.02, z]);
+        assert!(redness(sunset) > redness(midday), "The horizon under a low sun {:?} isn't redder than at midday {:?}", sunset, midday);
+    }
+}
@@ -0,0 +1,89 @@
+// Gradient sky with a sun disk, rendered into an equirectangular map that stands in for a loaded
+// environment, see ProceduralSky in procedural_sky.rs
+
+// Layout must match ProceduralSkyUniform in procedural_sky.rs
+struct ProceduralSkyUniform {
+    // Towards the sun, normalized
+    sun_direction: vec3<f32>,
+    // cos of the disk's angular radius
+    sun_cos_radius: f32,
+    // Color times intensity
+    sun_radiance: vec3<f32>,
+    turbidity: f32,
+    zenith_color: vec3<f32>,
+    _padding0: f32,
+    horizon_color: vec3<f32>,
+    _padding1: f32,
+    ground_color: vec3<f32>,
+    _padding2: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> sky: ProceduralSkyUniform;
+
+const PI: f32 = 3.14159265;
+// Relative scattering of red, green and blue, roughly 1 / wavelength^4. Blue goes first, what's
+// left after a long path through the air is red
+const RAYLEIGH: vec3<f32> = vec3<f32>(0.17, 0.4, 1.0);
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // 0..1, origin top left
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// Inverse of equirect_uv in sky.wgsl / ibl.wgsl
+fn equirect_direction(uv: vec2<f32>) -> vec3<f32> {
+    let phi = (uv.x - 0.5) * 2.0 * PI;
+    let theta = uv.y * PI;
+    return vec3<f32>(sin(theta) * cos(phi), cos(theta), sin(theta) * sin(phi));
+}
+
+// What's left of white light after crossing the air at this height above the horizon, -1..1.
+// The air mass grows quickly near the horizon and is capped just below it
+fn transmittance(height: f32) -> vec3<f32> {
+    let air_mass = 1.0 / (max(height, 0.0) + 0.15);
+    return exp(-RAYLEIGH * sky.turbidity * 0.1 * air_mass);
+}
+
+fn sky_radiance(direction: vec3<f32>) -> vec3<f32> {
+    let height = direction.y;
+    let sun = sky.sun_direction;
+    let cos_sun = dot(direction, sun);
+    // The sun's light reaching the air around it, reddened when the sun is low
+    let sunlight = transmittance(sun.y);
+
+    // Horizon to zenith, the horizon band takes the tint of the light that got through. Divided
+    // by its luminance so only the hue shifts
+    let up = clamp(height, 0.0, 1.0);
+    let tint = sunlight / max(dot(sunlight, vec3<f32>(0.2126, 0.7152, 0.0722)), 1e-4);
+    let horizon = sky.horizon_color * mix(vec3<f32>(1.0), tint, 0.5);
+    var color = mix(horizon, sky.zenith_color, pow(up, 0.4));
+
+    // Forward scattering by haze: a glow around the sun that widens with turbidity
+    let glow_power = 64.0 / max(sky.turbidity, 1.0);
+    color += sky.sun_radiance * sunlight * 0.02 * sky.turbidity * pow(max(cos_sun, 0.0), glow_power);
+
+    // Ground below the horizon, blended over a thin band so there's no seam
+    color = mix(color, sky.ground_color, 1.0 - smoothstep(-0.02, 0.0, height));
+
+    // Soft edged disk, hidden once it's below the horizon
+    let edge = (1.0 - sky.sun_cos_radius) * 0.2;
+    let disk = smoothstep(sky.sun_cos_radius - edge, sky.sun_cos_radius, cos_sun) * step(0.0, height);
+    color += sky.sun_radiance * transmittance(height) * disk;
+    return color;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(sky_radiance(equirect_direction(in.uv)), 1.0);
+}
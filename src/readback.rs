@@ -0,0 +1,331 @@
+use crate::texture;
+
+// GPU -> CPU copies. All of these block on device.poll(Wait), so they are meant for
+// tools, tests and screenshots rather than per frame work
+
+// Maps a MAP_READ buffer, hands its bytes to `f` and unmaps it again. Native only in practice: on
+// the web poll() doesn't block and map callbacks only run once control goes back to the browser,
+// so waiting for one here never returns
+pub fn map_read<R>(device: &wgpu::Device, buffer: &wgpu::Buffer, f: impl FnOnce(&[u8]) -> R) -> R {
+    let slice = buffer.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    // Callback only runs while the device is polled
+    device.poll(wgpu::Maintain::Wait);
+    receiver.recv()
+        .expect("Map callback was never called")
+        .expect("Couldn't map readback buffer");
+
+    let result = {
+        let data = slice.get_mapped_range();
+        f(&data)
+    }; // Mapped view has to be dropped before unmap
+    buffer.unmap();
+
+    result
+}
+
+// Copies the first `count` items of `buffer` (needs COPY_SRC) back to the CPU. The copy is
+// rounded up to COPY_BUFFER_ALIGNMENT, so `buffer` has to be at least that big
+pub fn read_buffer<T: bytemuck::Pod>(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer, count: usize) -> Vec<T> {
+    let bytes = count * std::mem::size_of::<T>();
+    if bytes == 0 {
+        return Vec::new();
+    }
+    let size = wgpu::util::align_to(bytes as wgpu::BufferAddress, wgpu::COPY_BUFFER_ALIGNMENT);
+
+    // MAP_READ buffers can't be used as storage, so results go through a staging buffer
+    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Staging Buffer"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder")
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, size);
+    queue.submit(std::iter::once(encoder.finish()));
+
+    map_read(device, &staging_buffer, |data| bytemuck::cast_slice(&data[..bytes]).to_vec())
+}
+
+// Reads mip 0 of a 4 bytes per pixel color texture (needs COPY_SRC) as tightly packed RGBA8.
+// BGRA formats are swizzled, so callers always get RGBA. Bytes are as stored, see
+// read_texture_displayed for what they look like on screen
+pub fn read_texture_rgba(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> Vec<u8> {
+    let mut pixels = read_region(device, queue, texture, 0, 0, 0, texture.width(), texture.height());
+    if matches!(texture.format(), wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb) {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+    pixels
+}
+
+// Reads mip 0 of `texture` (needs COPY_SRC) as the RGBA8 a window with a `displayed` format
+// surface would show for the same shader output, which is what PNG viewers expect. E.g. an
+// Rgba8Unorm target holds linear values that an sRGB surface would have encoded, and an
+// Rgba16Float surface is linear and gets encoded by the compositor. Pass the texture's own format
+// for the surface itself. Values over 1 are clipped
+pub fn read_texture_displayed(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    displayed: wgpu::TextureFormat,
+) -> Result<Vec<u8>, String> {
+    let texels = read_region(device, queue, texture, 0, 0, 0, texture.width(), texture.height());
+    to_displayed_rgba8(&texels, texture.format(), displayed)
+}
+
+// Pixel (x, y) of read_texture_displayed, without reading the rest
+pub fn read_pixel(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    x: u32,
+    y: u32,
+    displayed: wgpu::TextureFormat,
+) -> Result<[u8; 4], String> {
+    if x >= texture.width() || y >= texture.height() {
+        return Err(format!("Pixel ({}, {}) is outside the {}x{} texture", x, y, texture.width(), texture.height()));
+    }
+    let texel = read_region(device, queue, texture, 0, x, y, 1, 1);
+    let rgba = to_displayed_rgba8(&texel, texture.format(), displayed)?;
+    Ok([rgba[0], rgba[1], rgba[2], rgba[3]])
+}
+
+// Texels of mip `mip_level`, as they are stored with rows tightly packed. Uncompressed color
+// formats only, same as read_texture_rgba but without the BGRA swizzle
+pub fn read_texture_mip(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    mip_level: u32,
+) -> Vec<u8> {
+    let size = texture.size().mip_level_size(mip_level, texture.dimension());
+    read_region(device, queue, texture, mip_level, 0, 0, size.width, size.height)
+}
+
+// Texels of a width x height region of a mip as they are stored, rows tightly packed
+#[allow(clippy::too_many_arguments)]
+fn read_region(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    mip_level: u32,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> Vec<u8> {
+    let bytes_per_pixel = texture.format().block_size(None).expect("Can't read back depth/stencil or compressed textures");
+
+    // Texture -> buffer copies need rows aligned to 256 bytes
+    let unpadded_bytes_per_row = width * bytes_per_pixel;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+
+    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Texture Readback Buffer"),
+        size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Texture Readback Encoder")
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level,
+            origin: wgpu::Origin3d { x, y, z: 0 },
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &staging_buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: Some(height),
+            },
+        },
+        wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    map_read(device, &staging_buffer, |data| {
+        let mut texels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        for row in data.chunks(padded_bytes_per_row as usize) {
+            texels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+        texels
+    })
+}
+
+// Converts texels stored as `stored` into the RGBA8 a `displayed` surface would hold for the same
+// shader output. sRGB formats store the shader's linear value encoded and 8 bit UNORM ones store
+// it as is, and the compositor shows both bytes as sRGB. Float surfaces are linear (scRGB), so
+// those get encoded here
+pub fn to_displayed_rgba8(texels: &[u8], stored: wgpu::TextureFormat, displayed: wgpu::TextureFormat) -> Result<Vec<u8>, String> {
+    use wgpu::TextureFormat::*;
+
+    let mut rgba = Vec::with_capacity(texels.len());
+    match stored {
+        Rgba8Unorm | Rgba8UnormSrgb | Bgra8Unorm | Bgra8UnormSrgb => {
+            let bgra = matches!(stored, Bgra8Unorm | Bgra8UnormSrgb);
+            let decode = |byte: u8| if stored.is_srgb() { srgb_to_linear(byte as f32 / 255.0) } else { byte as f32 / 255.0 };
+            for texel in texels.chunks_exact(4) {
+                let [r, g, b, a] = [texel[0], texel[1], texel[2], texel[3]];
+                let (r, b) = if bgra { (b, r) } else { (r, b) };
+                if stored.is_srgb() == encodes_srgb(displayed) {
+                    // Same encoding, nothing to round
+                    rgba.extend_from_slice(&[r, g, b, a]);
+                } else {
+                    rgba.extend_from_slice(&displayed_rgba8([decode(r), decode(g), decode(b), a as f32 / 255.0], displayed));
+                }
+            }
+        }
+        Rgb10a2Unorm => {
+            for texel in texels.chunks_exact(4) {
+                let bits = u32::from_le_bytes([texel[0], texel[1], texel[2], texel[3]]);
+                let channel = |shift: u32| (bits >> shift & 0x3ff) as f32 / 1023.0;
+                let color = [channel(0), channel(10), channel(20), (bits >> 30) as f32 / 3.0];
+                rgba.extend_from_slice(&displayed_rgba8(color, displayed));
+            }
+        }
+        Rgba16Float => {
+            for texel in texels.chunks_exact(8) {
+                let channel = |i: usize| texture::f16_to_f32(u16::from_le_bytes([texel[i * 2], texel[i * 2 + 1]]));
+                rgba.extend_from_slice(&displayed_rgba8([channel(0), channel(1), channel(2), channel(3)], displayed));
+            }
+        }
+        _ => return Err(format!("Can't convert {:?} pixels for display", stored)),
+    }
+    Ok(rgba)
+}
+
+// What a `displayed` surface shows for a shader writing `color`, e.g. a clear color
+pub fn displayed_rgba8(color: [f32; 4], displayed: wgpu::TextureFormat) -> [u8; 4] {
+    let encode = encodes_srgb(displayed);
+    let byte = |value: f32, encode: bool| {
+        let value = value.clamp(0.0, 1.0);
+        ((if encode { linear_to_srgb(value) } else { value }) * 255.0).round() as u8
+    };
+    [byte(color[0], encode), byte(color[1], encode), byte(color[2], encode), byte(color[3], false)]
+}
+
+// Whether the bytes on screen are the sRGB encoding of what the shader wrote
+fn encodes_srgb(displayed: wgpu::TextureFormat) -> bool {
+    displayed.is_srgb() || matches!(displayed, wgpu::TextureFormat::Rgba16Float | wgpu::TextureFormat::Rgba32Float)
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Saves tightly packed RGBA8 (e.g. from read_texture_rgba) as a PNG. Bytes are written as they
+// are, PNG viewers treat them as sRGB
+pub fn write_png(path: &std::path::Path, width: u32, height: u32, rgba: &[u8]) -> Result<(), png::EncodingError> {
+    encode_png(std::io::BufWriter::new(std::fs::File::create(path)?), width, height, rgba)
+}
+
+// write_png into anything, a Vec for PNGs that don't go to a file
+pub fn encode_png(writer: impl std::io::Write, width: u32, height: u32, rgba: &[u8]) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(rgba)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::util::DeviceExt;
+
+    // Doubles every value of an array in a compute shader and reads it back
+    #[test]
+    fn read_buffer_reads_compute_results() {
+        let Some((device, queue)) = crate::shader_test::device() else {
+            return;
+        };
+        let values = (0..1000).map(|i| i as f32 * 0.5 - 100.0).collect::<Vec<_>>();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Read Buffer Test Values"),
+            contents: bytemuck::cast_slice(&values),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        });
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Read Buffer Test Shader"),
+            source: wgpu::ShaderSource::Wgsl("
+                @group(0) @binding(0) var<storage, read_write> values: array<f32>;
+                @compute @workgroup_size(64)
+                fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+                    if id.x < arrayLength(&values) {
+                        values[id.x] = values[id.x] * 2.0;
+                    }
+                }
+            ".into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Read Buffer Test Pipeline"),
+            layout: None,
+            module: &module,
+            entry_point: "main",
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Read Buffer Test Bind Group"),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() }],
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Read Buffer Test Encoder") });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("Read Buffer Test Pass") });
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups((values.len() as u32).div_ceil(64), 1, 1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let doubled = read_buffer::<f32>(device, queue, &buffer, values.len());
+        assert_eq!(doubled, values.iter().map(|value| value * 2.0).collect::<Vec<_>>());
+        // Fewer items than the buffer holds
+        assert_eq!(read_buffer::<f32>(device, queue, &buffer, 3), doubled[..3]);
+    }
+
+    // Sizes that aren't a multiple of COPY_BUFFER_ALIGNMENT, and nothing at all
+    #[test]
+    fn read_buffer_rounds_up_the_copy() {
+        let Some((device, queue)) = crate::shader_test::device() else {
+            return;
+        };
+        let values = [1u16, 2, 3, 4, 5, 6, 7, 8];
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Read Buffer Test Values"),
+            contents: bytemuck::cast_slice(&values),
+            usage: wgpu::BufferUsages::COPY_SRC,
+        });
+        assert_eq!(read_buffer::<u16>(device, queue, &buffer, 3), [1, 2, 3]);
+        assert_eq!(read_buffer::<u8>(device, queue, &buffer, 1), [1]);
+        assert!(read_buffer::<u16>(device, queue, &buffer, 0).is_empty());
+    }
+}
@@ -0,0 +1,256 @@
+use naga::valid::{Capabilities, ModuleInfo, ValidationFlags, Validator};
+use naga::{AddressSpace, ImageClass, ImageDimension, ScalarKind, TypeInner};
+
+// Bind group layouts read from a shader's own declarations with naga, so the Rust side can't drift
+// from the WGSL. Only what the shader says is used: a binding no entry point touches is left out,
+// visibility is the stages that touch it, textures are filterable only if something samples them.
+// The camera group is shared by almost every pipeline and stays a hand written layout
+pub struct ShaderReflection {
+    label: String,
+    module: naga::Module,
+    info: ModuleInfo,
+}
+
+impl ShaderReflection {
+    pub fn new(label: &str, source: &str) -> Result<Self, String> {
+        let module = naga::front::wgsl::parse_str(source).map_err(|e| e.emit_to_string_with_path(source, label))?;
+        let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+            .validate(&module)
+            .map_err(|e| e.emit_to_string_with_path(source, label))?;
+        Ok(Self { label: label.to_string(), module, info })
+    }
+
+    // Layout of one @group, Err for bindings wgpu has no layout entry for
+    pub fn layout(&self, device: &wgpu::Device, group: u32) -> Result<ReflectedLayout, String> {
+        let mut bindings = Vec::new();
+        for (handle, global) in self.module.global_variables.iter() {
+            let Some(binding) = global.binding.as_ref().filter(|binding| binding.group == group) else {
+                continue;
+            };
+            let mut visibility = wgpu::ShaderStages::NONE;
+            for (index, entry_point) in self.module.entry_points.iter().enumerate() {
+                if !self.info.get_entry_point(index)[handle].is_empty() {
+                    visibility |= match entry_point.stage {
+                        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+                    };
+                }
+            }
+            if visibility.is_empty() {
+                continue;
+            }
+            let name = global.name.clone().unwrap_or_else(|| format!("binding {}", binding.binding));
+            let ty = self.binding_type(handle, global)
+                .map_err(|e| format!("{}: {} (@group({}) @binding({})): {}", self.label, name, group, binding.binding, e))?;
+            bindings.push(ReflectedBinding { binding: binding.binding, name, ty, visibility });
+        }
+        bindings.sort_by_key(|binding| binding.binding);
+
+        let label = format!("{} Group {} Layout", self.label, group);
+        let entries = bindings
+            .iter()
+            .map(|binding| wgpu::BindGroupLayoutEntry {
+                binding: binding.binding,
+                visibility: binding.visibility,
+                ty: binding.ty,
+                count: None,
+            })
+            .collect::<Vec<_>>();
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor { label: Some(&label), entries: &entries });
+        Ok(ReflectedLayout { label, group, layout, bindings })
+    }
+
+    fn binding_type(&self, handle: naga::Handle<naga::GlobalVariable>, global: &naga::GlobalVariable) -> Result<wgpu::BindingType, String> {
+        let inner = &self.module.types[global.ty].inner;
+        let buffer = |ty| wgpu::BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(inner.size(self.module.to_ctx()) as u64),
+        };
+        Ok(match (global.space, inner) {
+            (AddressSpace::Uniform, _) => buffer(wgpu::BufferBindingType::Uniform),
+            (AddressSpace::Storage { access }, _) => buffer(wgpu::BufferBindingType::Storage {
+                read_only: !access.contains(naga::StorageAccess::STORE),
+            }),
+            (AddressSpace::Handle, TypeInner::Sampler { comparison: true }) => {
+                wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison)
+            }
+            (AddressSpace::Handle, TypeInner::Sampler { comparison: false }) => {
+                wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering)
+            }
+            (AddressSpace::Handle, &TypeInner::Image { dim, arrayed, class }) => {
+                let view_dimension = match (dim, arrayed) {
+                    (ImageDimension::D1, false) => wgpu::TextureViewDimension::D1,
+                    (ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                    (ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                    (ImageDimension::D3, false) => wgpu::TextureViewDimension::D3,
+                    (ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                    (ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+                    (dim, _) => return Err(format!("arrayed {:?} textures don't exist", dim)),
+                };
+                match class {
+                    ImageClass::Sampled { kind, multi } => wgpu::BindingType::Texture {
+                        sample_type: match kind {
+                            // Only read with textureLoad: unfilterable, so 32 bit float formats can be bound too
+                            ScalarKind::Float => wgpu::TextureSampleType::Float { filterable: self.is_sampled(handle) },
+                            ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                            ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                            ScalarKind::Bool => return Err("bool textures don't exist".to_string()),
+                        },
+                        view_dimension,
+                        multisampled: multi,
+                    },
+                    ImageClass::Depth { multi } => wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension,
+                        multisampled: multi,
+                    },
+                    ImageClass::Storage { format, access } => wgpu::BindingType::StorageTexture {
+                        access: match (access.contains(naga::StorageAccess::LOAD), access.contains(naga::StorageAccess::STORE)) {
+                            (true, true) => wgpu::StorageTextureAccess::ReadWrite,
+                            (true, false) => wgpu::StorageTextureAccess::ReadOnly,
+                            _ => wgpu::StorageTextureAccess::WriteOnly,
+                        },
+                        format: storage_format(format)?,
+                        view_dimension,
+                    },
+                }
+            }
+            (space, inner) => return Err(format!("no layout entry for {:?} {:?}", space, inner)),
+        })
+    }
+
+    // Whether any function samples the texture through a sampler (textureSample*, textureGather)
+    fn is_sampled(&self, handle: naga::Handle<naga::GlobalVariable>) -> bool {
+        let functions = self.module.functions.iter().map(|(_, function)| function)
+            .chain(self.module.entry_points.iter().map(|entry_point| &entry_point.function));
+        for function in functions {
+            for (_, expression) in function.expressions.iter() {
+                if let naga::Expression::ImageSample { image, .. } = *expression {
+                    if matches!(function.expressions[image], naga::Expression::GlobalVariable(global) if global == handle) {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+// The formats the app can use as storage textures
+fn storage_format(format: naga::StorageFormat) -> Result<wgpu::TextureFormat, String> {
+    use naga::StorageFormat as S;
+    Ok(match format {
+        S::R32Float => wgpu::TextureFormat::R32Float,
+        S::R32Uint => wgpu::TextureFormat::R32Uint,
+        S::R32Sint => wgpu::TextureFormat::R32Sint,
+        S::Rg32Float => wgpu::TextureFormat::Rg32Float,
+        S::Rgba8Unorm => wgpu::TextureFormat::Rgba8Unorm,
+        S::Rgba16Float => wgpu::TextureFormat::Rgba16Float,
+        S::Rgba32Float => wgpu::TextureFormat::Rgba32Float,
+        S::Rgba32Uint => wgpu::TextureFormat::Rgba32Uint,
+        other => return Err(format!("storage format {:?} isn't supported", other)),
+    })
+}
+
+pub struct ReflectedBinding {
+    pub binding: u32,
+    // Variable name in the shader
+    pub name: String,
+    pub ty: wgpu::BindingType,
+    pub visibility: wgpu::ShaderStages,
+}
+
+// A resource for a ReflectedLayout binding, by number or by the shader's variable name
+#[derive(Copy, Clone, Debug)]
+pub enum BindingId<'a> {
+    Binding(u32),
+    Name(&'a str),
+}
+
+impl From<u32> for BindingId<'_> {
+    fn from(binding: u32) -> Self {
+        BindingId::Binding(binding)
+    }
+}
+
+impl<'a> From<&'a str> for BindingId<'a> {
+    fn from(name: &'a str) -> Self {
+        BindingId::Name(name)
+    }
+}
+
+pub struct ReflectedLayout {
+    label: String,
+    group: u32,
+    pub layout: wgpu::BindGroupLayout,
+    pub bindings: Vec<ReflectedBinding>,
+}
+
+impl ReflectedLayout {
+    pub fn binding(&self, id: BindingId) -> Option<&ReflectedBinding> {
+        self.bindings.iter().find(|binding| match id {
+            BindingId::Binding(number) => binding.binding == number,
+            BindingId::Name(name) => binding.name == name,
+        })
+    }
+
+    // Checks every resource against the binding it's for before handing them to wgpu, so a wrong
+    // one is an error naming the binding instead of a validation panic. wgpu doesn't expose a view's
+    // format, textures are only checked for being textures
+    pub fn create_bind_group<'a>(
+        &self,
+        device: &wgpu::Device,
+        resources: &[(BindingId, wgpu::BindingResource<'a>)],
+    ) -> Result<wgpu::BindGroup, String> {
+        let mut entries: Vec<wgpu::BindGroupEntry<'a>> = Vec::new();
+        for (id, resource) in resources {
+            let binding = self.binding(*id).ok_or_else(|| format!("{} has no binding {:?}", self.label, id))?;
+            let name = format!("{} (@group({}) @binding({}))", binding.name, self.group, binding.binding);
+            if entries.iter().any(|entry| entry.binding == binding.binding) {
+                return Err(format!("{}: {} is given twice", self.label, name));
+            }
+            let matches = match (&binding.ty, resource) {
+                (wgpu::BindingType::Buffer { min_binding_size, .. }, wgpu::BindingResource::Buffer(buffer)) => {
+                    let size = buffer.size.map_or(buffer.buffer.size() - buffer.offset, |size| size.get());
+                    if let Some(min) = min_binding_size.filter(|min| size < min.get()) {
+                        return Err(format!("{}: {} needs at least {} bytes, the buffer has {}", self.label, name, min, size));
+                    }
+                    true
+                }
+                (wgpu::BindingType::Texture { .. } | wgpu::BindingType::StorageTexture { .. }, wgpu::BindingResource::TextureView(_)) => true,
+                (wgpu::BindingType::Sampler(_), wgpu::BindingResource::Sampler(_)) => true,
+                _ => false,
+            };
+            if !matches {
+                return Err(format!("{}: {} is a {:?}, got a {}", self.label, name, binding.ty, resource_kind(resource)));
+            }
+            entries.push(wgpu::BindGroupEntry { binding: binding.binding, resource: resource.clone() });
+        }
+        if let Some(missing) = self.bindings.iter().find(|binding| entries.iter().all(|entry| entry.binding != binding.binding)) {
+            return Err(format!(
+                "{}: nothing for {} (@group({}) @binding({}))",
+                self.label, missing.name, self.group, missing.binding
+            ));
+        }
+
+        Ok(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&self.label.replace(" Layout", "")),
+            layout: &self.layout,
+            entries: &entries,
+        }))
+    }
+}
+
+fn resource_kind(resource: &wgpu::BindingResource) -> &'static str {
+    match resource {
+        wgpu::BindingResource::Buffer(_) => "buffer",
+        wgpu::BindingResource::BufferArray(_) => "buffer array",
+        wgpu::BindingResource::Sampler(_) => "sampler",
+        wgpu::BindingResource::SamplerArray(_) => "sampler array",
+        wgpu::BindingResource::TextureView(_) => "texture view",
+        wgpu::BindingResource::TextureViewArray(_) => "texture view array",
+        _ => "resource",
+    }
+}
@@ -0,0 +1,376 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::json::Json;
+use crate::math::Vec3;
+use crate::scene;
+
+// Remote control for test scripts and demo automation: a TCP server taking one JSON request per
+// line and answering each with one JSON line. Native only, the web has no sockets to listen on.
+//   {"id": 7, "token": "...", "command": "move_camera", "eye": [0, 2, 5]}
+//   {"id": 7, "ok": true, "result": null}
+//   {"id": 8, "ok": false, "error": "Wrong or missing token"}
+// The id can be any JSON value and comes back as it was, null when the request had none or wasn't
+// JSON. Every request needs the token the server was started with. Commands and what they answer:
+//   set_clear_color   color: [r, g, b], linear                             null
+//   load_scene        path: a scene file, see State::load_scene            null
+//   save_scene        path: where to write State::save_scene               null
+//   load_model        primitive: one of primitives::NAMES                  null
+//   move_camera       eye: [x, y, z], target: [x, y, z]. Without a         null
+//                     target the camera keeps looking the same way
+//   screenshot        the next frame as it looks in the window             {"width", "height", "png": base64}
+//   frame_stats       the last frame                                       {"frame_time", "fps", "instances",
+//                                                                          "triangles", "culled", "impostors"}
+// Connections are read on threads of their own and State runs the commands on the main thread at
+// the start of update() (see State::start_remote), so they change the app like its own input does.
+// Answers come in the order the commands finish, screenshots after the frame they wait for
+
+// Longer lines are no request this server knows, the connection is closed
+const MAX_REQUEST_BYTES: u64 = 64 * 1024;
+// How often the listening thread checks for new connections and whether to stop
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemoteOptions {
+    pub address: SocketAddr,
+    // Shared with the scripts, requests without it are refused. Can't be empty
+    pub token: String,
+}
+
+impl RemoteOptions {
+    // Only reachable from this machine
+    pub fn localhost(port: u16, token: impl Into<String>) -> Self {
+        Self { address: SocketAddr::from((Ipv4Addr::LOCALHOST, port)), token: token.into() }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    SetClearColor([f32; 3]),
+    LoadScene(PathBuf),
+    SaveScene(PathBuf),
+    LoadModel(String),
+    MoveCamera { eye: Vec3, target: Option<Vec3> },
+    Screenshot,
+    FrameStats,
+}
+
+impl Command {
+    // From a request's fields besides id, token and command
+    fn parse(name: &str, fields: &[(&str, &Json)]) -> Result<Self, String> {
+        let field = |key: &str| fields.iter().find(|(k, _)| *k == key).map(|(_, value)| *value);
+        let required = |key: &str| field(key).ok_or_else(|| format!("{} needs \"{}\"", name, key));
+        let (command, known): (Command, &[&str]) = match name {
+            "set_clear_color" => (Command::SetClearColor(scene::color(required("color")?, "color")?), &["color"]),
+            "load_scene" => (Command::LoadScene(scene::string(required("path")?, "path")?.into()), &["path"]),
+            "save_scene" => (Command::SaveScene(scene::string(required("path")?, "path")?.into()), &["path"]),
+            "load_model" => (Command::LoadModel(scene::string(required("primitive")?, "primitive")?.to_string()), &["primitive"]),
+            "move_camera" => {
+                let eye = scene::vec3(required("eye")?, "eye")?;
+                let target = field("target").map(|target| scene::vec3(target, "target")).transpose()?;
+                (Command::MoveCamera { eye, target }, &["eye", "target"])
+            }
+            "screenshot" => (Command::Screenshot, &[]),
+            "frame_stats" => (Command::FrameStats, &[]),
+            other => return Err(format!("Unknown command \"{}\"", other)),
+        };
+        // Typos would otherwise be silently ignored
+        if let Some((key, _)) = fields.iter().find(|(key, _)| !known.contains(key)) {
+            return Err(format!("{} doesn't take \"{}\"", name, key));
+        }
+        Ok(command)
+    }
+}
+
+// Where the answer to a request goes. Dropping it unanswered leaves the script waiting
+pub struct Reply {
+    id: Json,
+    sender: Sender<String>,
+}
+
+impl Reply {
+    // The connection may be gone by now, nothing to tell then
+    pub fn ok(self, result: Json) {
+        let _ = self.sender.send(response(&self.id, Ok(result)));
+    }
+
+    pub fn error(self, message: impl Into<String>) {
+        let _ = self.sender.send(response(&self.id, Err(message.into())));
+    }
+}
+
+pub struct Request {
+    pub command: Command,
+    pub reply: Reply,
+}
+
+fn response(id: &Json, result: Result<Json, String>) -> String {
+    let mut members = vec![("id".to_string(), id.clone())];
+    match result {
+        Ok(result) => members.extend([("ok".to_string(), Json::Bool(true)), ("result".to_string(), result)]),
+        Err(error) => members.extend([("ok".to_string(), Json::Bool(false)), ("error".to_string(), Json::String(error))]),
+    }
+    Json::Object(members).to_string()
+}
+
+// Takes as long for every wrong token of the right length, so answers don't give it away a byte
+// at a time
+fn token_matches(given: &str, token: &str) -> bool {
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// The id along with the error, so even refused requests are answered with theirs
+fn parse_request(line: &str, token: &str) -> Result<(Json, Command), (Json, String)> {
+    let json = Json::parse(line).map_err(|e| (Json::Null, e.to_string()))?;
+    let members = scene::object(&json, "request").map_err(|e| (Json::Null, e))?;
+    let id = members.iter().find(|(key, _)| key == "id").map_or(Json::Null, |(_, id)| id.clone());
+
+    let mut given_token = None;
+    let mut name = None;
+    let mut fields = Vec::new();
+    for (key, value) in members {
+        match key.as_str() {
+            "id" => {}
+            "token" => given_token = scene::string(value, key).ok(),
+            "command" => name = Some(scene::string(value, key).map_err(|e| (id.clone(), e))?),
+            _ => fields.push((key.as_str(), value)),
+        }
+    }
+    if !given_token.is_some_and(|given| token_matches(given, token)) {
+        return Err((id, "Wrong or missing token".to_string()));
+    }
+    let Some(name) = name else {
+        return Err((id, "No \"command\"".to_string()));
+    };
+    match Command::parse(name, &fields) {
+        Ok(command) => Ok((id, command)),
+        Err(e) => Err((id, e)),
+    }
+}
+
+// Standard alphabet with padding, for screenshots. One function isn't worth a crate
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let triple = chunk.iter().enumerate().fold(0u32, |triple, (i, &byte)| triple | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(triple >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+// Listens on a thread of its own and reads every connection on another, requests queue up until
+// requests() takes them. Stops listening when dropped, connections still open end once their
+// client sends something or hangs up
+pub struct RemoteServer {
+    requests: Receiver<Request>,
+    address: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl RemoteServer {
+    pub fn bind(options: &RemoteOptions) -> std::io::Result<Self> {
+        if options.token.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Remote control needs a token"));
+        }
+        let listener = TcpListener::bind(options.address)?;
+        // Accepting without blocking is what lets the thread notice it should stop
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        let (sender, requests) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let token = Arc::<str>::from(options.token.as_str());
+        let thread = std::thread::Builder::new()
+            .name("Remote Control".into())
+            .spawn(move || {
+                while !thread_stop.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, peer)) => {
+                            let (sender, token) = (sender.clone(), token.clone());
+                            let spawned = std::thread::Builder::new()
+                                .name(format!("Remote Control {}", peer))
+                                .spawn(move || serve(stream, sender, &token));
+                            if let Err(e) = spawned {
+                                log::warn!("Couldn't start a thread for remote control connection {}: {}", peer, e);
+                            }
+                        }
+                        // Drop unparks it, so stopping doesn't wait out the interval
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => std::thread::park_timeout(ACCEPT_INTERVAL),
+                        Err(e) => log::warn!("Remote control couldn't accept a connection: {}", e),
+                    }
+                }
+            })?;
+        Ok(Self { requests, address, stop, thread: Some(thread) })
+    }
+
+    // Where it listens, the actual port when the options asked for port 0
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    // Requests that came in since the last call, oldest first
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.try_iter().collect()
+    }
+}
+
+impl Drop for RemoteServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+// Reads requests off one connection until it closes. Answers are written by a thread of its own,
+// they come from whichever thread holds the Reply
+fn serve(stream: TcpStream, requests: Sender<Request>, token: &str) {
+    let setup = stream.set_nonblocking(false).and_then(|_| stream.try_clone());
+    let mut writer = match setup {
+        Ok(writer) => writer,
+        Err(e) => {
+            log::warn!("Remote control connection failed: {}", e);
+            return;
+        }
+    };
+    let (sender, responses) = mpsc::channel::<String>();
+    let written = std::thread::Builder::new().name("Remote Control Writer".into()).spawn(move || {
+        for line in responses {
+            if writeln!(writer, "{}", line).is_err() {
+                break;
+            }
+        }
+    });
+    if written.is_err() {
+        return;
+    }
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match (&mut reader).take(MAX_REQUEST_BYTES).read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        if !line.ends_with('\n') && line.len() as u64 >= MAX_REQUEST_BYTES {
+            let _ = sender.send(response(&Json::Null, Err(format!("Request over {} bytes", MAX_REQUEST_BYTES))));
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_request(&line, token) {
+            Ok((id, command)) => {
+                let reply = Reply { id, sender: sender.clone() };
+                // State is gone, nothing will run it
+                if requests.send(Request { command, reply }).is_err() {
+                    break;
+                }
+            }
+            Err((id, error)) => Reply { id, sender: sender.clone() }.error(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "secret";
+
+    fn parse(line: &str) -> Result<(Json, Command), (Json, String)> {
+        parse_request(line, TOKEN)
+    }
+
+    #[test]
+    fn requests_parse_with_their_id() {
+        assert_eq!(
+            parse(r#"{"id": 1, "token": "secret", "command": "set_clear_color", "color": [0.1, 0.2, 0.3]}"#),
+            Ok((Json::Number(1.0), Command::SetClearColor([0.1, 0.2, 0.3])))
+        );
+        assert_eq!(
+            parse(r#"{"token": "secret", "id": "a", "command": "move_camera", "eye": [1, 2, 3]}"#),
+            Ok((Json::String("a".to_string()), Command::MoveCamera { eye: Vec3::new(1.0, 2.0, 3.0), target: None }))
+        );
+        assert_eq!(
+            parse(r#"{"token": "secret", "command": "move_camera", "eye": [1, 2, 3], "target": [0, 0, 0]}"#),
+            Ok((Json::Null, Command::MoveCamera { eye: Vec3::new(1.0, 2.0, 3.0), target: Some(Vec3::ZERO) }))
+        );
+        assert_eq!(parse(r#"{"token": "secret", "command": "screenshot"}"#), Ok((Json::Null, Command::Screenshot)));
+        assert_eq!(
+            parse(r#"{"token": "secret", "command": "save_scene", "path": "saved.json"}"#),
+            Ok((Json::Null, Command::SaveScene("saved.json".into())))
+        );
+    }
+
+    #[test]
+    fn bad_requests_are_refused_with_their_id() {
+        let id = Json::Number(3.0);
+        for (line, error) in [
+            (r#"{"id": 3, "command": "screenshot"}"#, "Wrong or missing token"),
+            (r#"{"id": 3, "token": "secreT", "command": "screenshot"}"#, "Wrong or missing token"),
+            (r#"{"id": 3, "token": "secret"}"#, "No \"command\""),
+            (r#"{"id": 3, "token": "secret", "command": "explode"}"#, "Unknown command \"explode\""),
+            (r#"{"id": 3, "token": "secret", "command": "load_scene"}"#, "load_scene needs \"path\""),
+            (r#"{"id": 3, "token": "secret", "command": "screenshot", "size": 4}"#, "screenshot doesn't take \"size\""),
+        ] {
+            assert_eq!(parse(line), Err((id.clone(), error.to_string())), "{}", line);
+        }
+        assert!(matches!(parse("not json"), Err((Json::Null, _))));
+        assert!(matches!(parse("[1, 2]"), Err((Json::Null, _))));
+    }
+
+    // RFC 4648's test vectors
+    #[test]
+    fn base64_pads() {
+        for (bytes, text) in [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foob", "Zm9vYg=="), ("fooba", "Zm9vYmE="), ("foobar", "Zm9vYmFy")] {
+            assert_eq!(base64(bytes.as_bytes()), text);
+        }
+        assert_eq!(base64(&[0xff, 0xfe, 0x00]), "//4A");
+    }
+
+    #[test]
+    fn server_answers_over_tcp() {
+        assert!(RemoteServer::bind(&RemoteOptions::localhost(0, "")).is_err(), "bound without a token");
+        let server = RemoteServer::bind(&RemoteOptions::localhost(0, TOKEN)).unwrap();
+        let mut stream = TcpStream::connect(server.address()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        writeln!(stream, r#"{{"id": 1, "token": "secret", "command": "frame_stats"}}"#).unwrap();
+        writeln!(stream, r#"{{"id": 2, "token": "wrong", "command": "frame_stats"}}"#).unwrap();
+
+        let start = std::time::Instant::now();
+        let mut requests = Vec::new();
+        while requests.is_empty() && start.elapsed() < Duration::from_secs(10) {
+            std::thread::sleep(Duration::from_millis(10));
+            requests = server.requests();
+        }
+        assert_eq!(requests.len(), 1, "the request with the wrong token went through");
+        let request = requests.pop().unwrap();
+        assert_eq!(request.command, Command::FrameStats);
+        request.reply.ok(Json::Number(0.5));
+
+        let mut lines = BufReader::new(stream).lines();
+        let mut responses = [lines.next(), lines.next()].map(|line| Json::parse(&line.unwrap().unwrap()).unwrap().to_string());
+        responses.sort();
+        assert_eq!(responses, [
+            r#"{"id":1,"ok":true,"result":0.5}"#,
+            r#"{"id":2,"ok":false,"error":"Wrong or missing token"}"#,
+        ]);
+    }
+}
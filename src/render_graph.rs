@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::fmt;
+
+// Passes declare which textures they read and write, execute() figures out the order.
+// Textures are either imported (surface, depth buffer, anything owned outside the graph) or
+// transient: declared with create_texture and allocated by the graph only for one execute().
+//
+// Ordering rules, per texture:
+// - a pass reading it runs after every pass added before it that writes it
+// - passes writing the same texture keep the order they were added in
+// Everything else is free to move, ties are broken by the order passes were added
+
+type PassFn<'a> = Box<dyn FnOnce(&mut wgpu::CommandEncoder, &PassResources) + 'a>;
+
+// Size and format of a texture the graph allocates itself
+#[derive(Copy, Clone, Debug)]
+pub struct TransientTexture {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+    pub sample_count: u32,
+}
+
+#[derive(Debug)]
+pub enum RenderGraphError {
+    // Used by a pass but never imported or declared
+    MissingTexture { pass: String, texture: String },
+    // Passes depend on each other in a loop, contains the passes that couldn't be ordered
+    Cycle(Vec<String>),
+    // set_color_ops for a pass that doesn't exist or doesn't write the texture
+    UnknownOutput { pass: String, texture: String },
+}
+
+impl fmt::Display for RenderGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderGraphError::MissingTexture { pass, texture } => {
+                write!(f, "Pass '{}' uses unknown texture '{}'", pass, texture)
+            }
+            RenderGraphError::Cycle(passes) => write!(f, "Cycle between passes {:?}", passes),
+            RenderGraphError::UnknownOutput { pass, texture } => {
+                write!(f, "No pass '{}' writing texture '{}'", pass, texture)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RenderGraphError {}
+
+// Views a pass asked for, by name
+pub struct PassResources<'r> {
+    views: HashMap<&'r str, &'r wgpu::TextureView>,
+    color_ops: &'r HashMap<String, wgpu::Operations<wgpu::Color>>,
+}
+
+impl<'r> PassResources<'r> {
+    // Panics for textures the pass didn't list as input or output
+    pub fn view(&self, name: &str) -> &'r wgpu::TextureView {
+        self.views.get(name).copied().unwrap_or_else(|| panic!("Texture '{}' not declared by pass", name))
+    }
+
+    // Load / store for color target `name`, `default` unless the graph builder overrode it with
+    // RenderGraph::set_color_ops. Passes should use this instead of hardcoding their ops
+    pub fn color_ops(&self, name: &str, default: wgpu::Operations<wgpu::Color>) -> wgpu::Operations<wgpu::Color> {
+        self.color_ops.get(name).copied().unwrap_or(default)
+    }
+}
+
+struct Pass<'a> {
+    name: String,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    // Overrides from set_color_ops, by texture
+    color_ops: HashMap<String, wgpu::Operations<wgpu::Color>>,
+    execute: PassFn<'a>,
+}
+
+// Built every frame, borrows whatever the passes need for the frame
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    imported: HashMap<String, &'a wgpu::TextureView>,
+    transient: HashMap<String, TransientTexture>,
+    passes: Vec<Pass<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn import_view(&mut self, name: &str, view: &'a wgpu::TextureView) {
+        self.imported.insert(name.to_string(), view);
+    }
+
+    // Only allocated if some pass uses it
+    pub fn create_texture(&mut self, name: &str, texture: TransientTexture) {
+        self.transient.insert(name.to_string(), texture);
+    }
+
+    pub fn add_pass(
+        &mut self,
+        name: &str,
+        inputs: &[&str],
+        outputs: &[&str],
+        execute: impl FnOnce(&mut wgpu::CommandEncoder, &PassResources) + 'a,
+    ) {
+        self.passes.push(Pass {
+            name: name.to_string(),
+            inputs: inputs.iter().map(|s| s.to_string()).collect(),
+            outputs: outputs.iter().map(|s| s.to_string()).collect(),
+            color_ops: HashMap::new(),
+            execute: Box::new(execute),
+        });
+    }
+
+    // Overrides what pass `pass` does with color target `texture` (e.g. Load instead of Clear)
+    pub fn set_color_ops(
+        &mut self,
+        pass: &str,
+        texture: &str,
+        ops: wgpu::Operations<wgpu::Color>,
+    ) -> Result<(), RenderGraphError> {
+        let pass = self.passes.iter_mut()
+            .find(|p| p.name == pass && p.outputs.iter().any(|output| output == texture))
+            .ok_or_else(|| RenderGraphError::UnknownOutput { pass: pass.to_string(), texture: texture.to_string() })?;
+        pass.color_ops.insert(texture.to_string(), ops);
+        Ok(())
+    }
+
+    fn has_texture(&self, name: &str) -> bool {
+        self.imported.contains_key(name) || self.transient.contains_key(name)
+    }
+
+    // Pass indices in execution order
+    fn sorted(&self) -> Result<Vec<usize>, RenderGraphError> {
+        let mut dependencies: Vec<Vec<usize>> = vec![Vec::new(); self.passes.len()];
+        let mut last_writer: HashMap<&str, usize> = HashMap::new();
+
+        for (i, pass) in self.passes.iter().enumerate() {
+            if let Some(texture) = pass.inputs.iter().chain(&pass.outputs).find(|name| !self.has_texture(name)) {
+                return Err(RenderGraphError::MissingTexture {
+                    pass: pass.name.clone(),
+                    texture: texture.clone(),
+                });
+            }
+            for input in &pass.inputs {
+                if let Some(&writer) = last_writer.get(input.as_str()) {
+                    dependencies[i].push(writer);
+                }
+            }
+            for output in &pass.outputs {
+                if let Some(writer) = last_writer.insert(output.as_str(), i) {
+                    dependencies[i].push(writer);
+                }
+            }
+        }
+
+        // Kahn's algorithm, always picking the earliest added pass that is ready
+        let mut done = vec![false; self.passes.len()];
+        let mut order = Vec::with_capacity(self.passes.len());
+        while order.len() < self.passes.len() {
+            let next = (0..self.passes.len())
+                .find(|&i| !done[i] && dependencies[i].iter().all(|&d| done[d]));
+            match next {
+                Some(i) => {
+                    done[i] = true;
+                    order.push(i);
+                }
+                None => {
+                    let stuck = (0..self.passes.len())
+                        .filter(|&i| !done[i])
+                        .map(|i| self.passes[i].name.clone())
+                        .collect();
+                    return Err(RenderGraphError::Cycle(stuck));
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    // Passes in execution order with the textures they use, for debugging
+    pub fn describe(&self) -> Result<String, RenderGraphError> {
+        let mut out = String::new();
+        let mut imported = self.imported.keys().collect::<Vec<_>>();
+        imported.sort();
+        for name in imported {
+            out += &format!("import {}\n", name);
+        }
+        let mut transient = self.transient.iter().collect::<Vec<_>>();
+        transient.sort_by_key(|(name, _)| *name);
+        for (name, texture) in transient {
+            out += &format!(
+                "texture {} {}x{} {:?} x{}\n",
+                name, texture.width, texture.height, texture.format, texture.sample_count
+            );
+        }
+        for i in self.sorted()? {
+            let pass = &self.passes[i];
+            out += &format!("pass '{}' reads {:?} writes {:?}\n", pass.name, pass.inputs, pass.outputs);
+            for (texture, ops) in &pass.color_ops {
+                out += &format!("  {} {:?}\n", texture, ops);
+            }
+        }
+        Ok(out)
+    }
+
+    // Records all passes into the encoder, each in its own debug group. Nothing is recorded on error
+    pub fn execute(self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) -> Result<(), RenderGraphError> {
+        let order = self.sorted()?;
+        let RenderGraph { imported, transient, passes } = self;
+
+        let mut transient_views: HashMap<String, wgpu::TextureView> = HashMap::new();
+        for name in passes.iter().flat_map(|pass| pass.inputs.iter().chain(&pass.outputs)) {
+            if imported.contains_key(name) || transient_views.contains_key(name) {
+                continue;
+            }
+            let texture = transient[name];
+            let view = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(name),
+                size: wgpu::Extent3d {
+                    width: texture.width,
+                    height: texture.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: texture.sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: texture.format,
+                usage: texture.usage,
+                view_formats: &[],
+            }).create_view(&wgpu::TextureViewDescriptor::default());
+            transient_views.insert(name.clone(), view);
+        }
+
+        let mut passes = passes.into_iter().map(Some).collect::<Vec<_>>();
+        for i in order {
+            let pass = passes[i].take().unwrap();
+            let views = pass.inputs.iter()
+                .chain(&pass.outputs)
+                .map(|name| {
+                    let view = imported.get(name).copied().unwrap_or_else(|| &transient_views[name]);
+                    (name.as_str(), view)
+                })
+                .collect();
+            let resources = PassResources { views, color_ops: &pass.color_ops };
+            crate::debug_group(encoder, &pass.name, |encoder| (pass.execute)(encoder, &resources));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,621 @@
+use std::path::{Path, PathBuf};
+
+use crate::camera::{FogFalloff, FogParams, Projection};
+use crate::instance::Instance;
+use crate::json::Json;
+use crate::math::{Mat4, Vec3};
+use crate::procedural_sky::ProceduralSkyParams;
+use crate::time_of_day::TimeOfDay;
+
+// Scene description for State::load_scene and State::save_scene. Everything is optional, what's
+// left out stays as it is. Example:
+//
+// {
+//     "clear_color": [0.1, 0.1, 0.15],
+//     "camera": { "eye": [0, 5, 10], "target": [0, 0, 0], "fovy": 45, "projection": "perspective" },
+//     "environment": "sky.hdr",
+//     "sky": { "sun_direction": [0.4, 0.2, 0.45], "turbidity": 4, "horizon_color": [0.8, 0.6, 0.5] },
+//     "time_of_day": { "hours": 18.5, "speed": 0.25, "paused": false },
+//     "fog": { "color": [0.5, 0.5, 0.6], "density": 0.02, "start": 5, "height": 0, "height_falloff": 0.1 },
+//     "mesh": "torus",
+//     "instances": [{ "position": [0, 0, 0], "rotation": [0, 45, 0], "layer": 1 }],
+//     "point_clouds": ["scan.xyz"]
+// }
+//
+// Paths are relative to the scene file. Files that aren't there don't fail the load, State puts
+// placeholders in (see State::load_scene). The only lighting there is comes from the environment
+// map, so that's what stands in for lights. "sky" is a procedural one instead of "environment",
+// fields left out of it are ProceduralSkyParams' defaults. "time_of_day" drives the sky itself, so
+// it goes with neither
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scene {
+    // Linear RGB
+    pub clear_color: Option<[f32; 3]>,
+    pub camera: Option<SceneCamera>,
+    // Radiance .hdr, see State::load_environment
+    pub environment: Option<PathBuf>,
+    // Instead of environment, see State::set_procedural_sky
+    pub sky: Option<ProceduralSkyParams>,
+    // Day cycle, see State::set_time_of_day
+    pub time_of_day: Option<TimeOfDay>,
+    pub fog: Option<FogParams>,
+    pub mesh: Option<SceneMesh>,
+    // Replaces all instances of the mesh
+    pub instances: Option<Vec<SceneInstance>>,
+    // .xyz files, see points::load_xyz. Drawn where their points are
+    pub point_clouds: Vec<PathBuf>,
+}
+
+// Aspect ratio comes from the window
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneCamera {
+    pub eye: Vec3,
+    pub target: Vec3,
+    pub fovy: Option<f32>,
+    pub znear: Option<f32>,
+    pub zfar: Option<f32>,
+    pub projection: Option<Projection>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SceneMesh {
+    // The built in colored triangle
+    Triangle,
+    // primitives::uv_sphere_lods, with levels of detail
+    Sphere,
+    // By name, see primitives::by_name
+    Primitive(String),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SceneInstance {
+    pub position: Vec3,
+    // Degrees around x, then y, then z
+    pub rotation: Vec3,
+    // Texture layer
+    pub layer: u32,
+}
+
+impl SceneInstance {
+    // The angles back out of the instance's rotation, which has to be a rotation and nothing else.
+    // Looking straight up or down x and z turn the same way, it's all put in z then
+    pub fn from_instance(instance: &Instance) -> Self {
+        let [x_axis, y_axis, z_axis, _] = instance.rotation.cols;
+        let y = (-x_axis[2]).clamp(-1.0, 1.0).asin();
+        let (x, z) = if x_axis[2].abs() < 0.9999 {
+            (y_axis[2].atan2(z_axis[2]), x_axis[1].atan2(x_axis[0]))
+        } else {
+            (0.0, (-y_axis[0]).atan2(y_axis[1]))
+        };
+        Self {
+            position: instance.position,
+            rotation: Vec3::new(x.to_degrees(), y.to_degrees(), z.to_degrees()),
+            layer: instance.layer,
+        }
+    }
+
+    pub fn rotation_matrix(&self) -> Mat4 {
+        let radians = |degrees: f32| degrees.to_radians();
+        Mat4::from_axis_angle(Vec3::Z, radians(self.rotation.z))
+            * Mat4::from_axis_angle(Vec3::Y, radians(self.rotation.y))
+            * Mat4::from_axis_angle(Vec3::X, radians(self.rotation.x))
+    }
+}
+
+impl Scene {
+    // Reads and checks the whole file, relative paths get resolved against its directory. Errors
+    // name the file and the bad field
+    pub fn load(path: &Path) -> Result<Scene, String> {
+        if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("ron")) {
+            return Err(format!("{}: RON scenes aren't supported, only JSON", path.display()));
+        }
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+        let mut scene = Scene::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        for file in scene.environment.iter_mut().chain(&mut scene.point_clouds) {
+            *file = directory.join(&*file);
+        }
+        Ok(scene)
+    }
+
+    // Writes what load reads back. Paths inside the file's directory are saved relative to it, the
+    // rest absolute
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("ron")) {
+            return Err(format!("{}: RON scenes aren't supported, only JSON", path.display()));
+        }
+        let directory = std::path::absolute(path.parent().unwrap_or(Path::new("")))
+            .map_err(|e| format!("Couldn't save {}: {}", path.display(), e))?;
+        let mut scene = self.clone();
+        for file in scene.environment.iter_mut().chain(&mut scene.point_clouds) {
+            let absolute = std::path::absolute(&*file).unwrap_or_else(|_| file.clone());
+            *file = absolute.strip_prefix(&directory).map_or(absolute.clone(), Path::to_path_buf);
+        }
+        std::fs::write(path, format!("{:#}\n", scene.to_json())).map_err(|e| format!("Couldn't save {}: {}", path.display(), e))
+    }
+
+    pub fn to_json(&self) -> Json {
+        let mut members = Vec::new();
+        if let Some(clear_color) = &self.clear_color {
+            members.push(("clear_color", floats_json(clear_color)));
+        }
+        if let Some(camera) = &self.camera {
+            members.push(("camera", camera_json(camera)));
+        }
+        if let Some(environment) = &self.environment {
+            members.push(("environment", path_json(environment)));
+        }
+        if let Some(sky) = &self.sky {
+            members.push(("sky", sky_json(sky)));
+        }
+        if let Some(time_of_day) = &self.time_of_day {
+            members.push(("time_of_day", time_of_day_json(time_of_day)));
+        }
+        if let Some(fog) = &self.fog {
+            members.push(("fog", fog_json(fog)));
+        }
+        if let Some(mesh) = &self.mesh {
+            let name = match mesh {
+                SceneMesh::Triangle => "triangle",
+                SceneMesh::Sphere => "sphere",
+                SceneMesh::Primitive(name) => name,
+            };
+            members.push(("mesh", Json::String(name.to_string())));
+        }
+        if let Some(instances) = &self.instances {
+            members.push(("instances", Json::Array(instances.iter().map(instance_json).collect())));
+        }
+        if !self.point_clouds.is_empty() {
+            members.push(("point_clouds", Json::Array(self.point_clouds.iter().map(|path| path_json(path)).collect())));
+        }
+        object_json(members)
+    }
+
+    pub fn parse(text: &str) -> Result<Scene, String> {
+        let json = Json::parse(text).map_err(|e| e.to_string())?;
+        let members = object(&json, "scene")?;
+        let mut scene = Scene::default();
+        for (key, value) in members {
+            match key.as_str() {
+                "clear_color" => scene.clear_color = Some(color(value, key)?),
+                "camera" => scene.camera = Some(parse_camera(value)?),
+                "environment" => scene.environment = Some(string(value, key)?.into()),
+                "sky" => scene.sky = Some(parse_sky(value)?),
+                "time_of_day" => scene.time_of_day = Some(parse_time_of_day(value)?),
+                "fog" => scene.fog = Some(parse_fog(value)?),
+                "mesh" => {
+                    scene.mesh = Some(match string(value, key)? {
+                        "triangle" => SceneMesh::Triangle,
+                        "sphere" => SceneMesh::Sphere,
+                        // Checked when it's loaded, unknown ones get a placeholder
+                        other => SceneMesh::Primitive(other.to_string()),
+                    })
+                }
+                "instances" => {
+                    let instances = array(value, key)?
+                        .iter()
+                        .enumerate()
+                        .map(|(i, instance)| parse_instance(instance, &format!("instances[{}]", i)))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    scene.instances = Some(instances);
+                }
+                "point_clouds" => {
+                    scene.point_clouds = array(value, key)?
+                        .iter()
+                        .enumerate()
+                        .map(|(i, path)| string(path, &format!("point_clouds[{}]", i)).map(PathBuf::from))
+                        .collect::<Result<_, _>>()?;
+                }
+                // Typos would otherwise be silently ignored
+                other => return Err(format!("Unknown field \"{}\"", other)),
+            }
+        }
+        if scene.environment.is_some() && scene.sky.is_some() {
+            return Err("\"environment\" and \"sky\" can't both be set".to_string());
+        }
+        if scene.time_of_day.is_some() && (scene.environment.is_some() || scene.sky.is_some()) {
+            return Err("\"time_of_day\" makes its own sky, it can't go with \"environment\" or \"sky\"".to_string());
+        }
+        Ok(scene)
+    }
+}
+
+// `new` if it's there and different from `old`, for applying only what changed in a reloaded scene
+pub(crate) fn changed<'a, T: PartialEq>(new: &'a Option<T>, old: &Option<T>) -> Option<&'a T> {
+    new.as_ref().filter(|_| new != old)
+}
+
+fn parse_camera(json: &Json) -> Result<SceneCamera, String> {
+    let mut eye = None;
+    let mut target = None;
+    let mut camera = SceneCamera { eye: Vec3::ZERO, target: Vec3::ZERO, fovy: None, znear: None, zfar: None, projection: None };
+    for (key, value) in object(json, "camera")? {
+        let field = format!("camera.{}", key);
+        match key.as_str() {
+            "eye" => eye = Some(vec3(value, &field)?),
+            "target" => target = Some(vec3(value, &field)?),
+            "fovy" => camera.fovy = Some(positive(value, &field)?),
+            "znear" => camera.znear = Some(positive(value, &field)?),
+            "zfar" => camera.zfar = Some(positive(value, &field)?),
+            "projection" => {
+                camera.projection = Some(match string(value, &field)? {
+                    "perspective" => Projection::Perspective,
+                    "orthographic" => Projection::Orthographic,
+                    other => return Err(format!("{}: unknown projection \"{}\"", field, other)),
+                })
+            }
+            other => return Err(format!("Unknown field \"camera.{}\"", other)),
+        }
+    }
+    camera.eye = eye.ok_or("camera: missing \"eye\"")?;
+    camera.target = target.ok_or("camera: missing \"target\"")?;
+    if camera.eye == camera.target {
+        return Err("camera: eye and target are the same point".to_string());
+    }
+    if let (Some(znear), Some(zfar)) = (camera.znear, camera.zfar) {
+        if znear >= zfar {
+            return Err(format!("camera: znear ({}) has to be less than zfar ({})", znear, zfar));
+        }
+    }
+    Ok(camera)
+}
+
+// Either "start" and "end" (linear), "density" (exponential), or "density" with any of "start",
+// "height" and "height_falloff" (exponential squared with height falloff)
+fn parse_fog(json: &Json) -> Result<FogParams, String> {
+    let mut color_value = None;
+    let (mut start, mut end, mut density) = (None, None, None);
+    let (mut height, mut height_falloff) = (None, None);
+    for (key, value) in object(json, "fog")? {
+        let field = format!("fog.{}", key);
+        match key.as_str() {
+            "color" => color_value = Some(color(value, &field)?),
+            "start" => start = Some(number(value, &field)?),
+            "end" => end = Some(number(value, &field)?),
+            "density" => density = Some(positive(value, &field)?),
+            "height" => height = Some(number(value, &field)?),
+            "height_falloff" => {
+                let value = number(value, &field)?;
+                if value < 0.0 {
+                    return Err(format!("{}: can't be negative, found {}", field, value));
+                }
+                height_falloff = Some(value);
+            }
+            other => return Err(format!("Unknown field \"fog.{}\"", other)),
+        }
+    }
+    let falloff = match (start, end, density) {
+        (_, None, Some(density)) if start.is_some() || height.is_some() || height_falloff.is_some() => FogFalloff::ExponentialSquared {
+            density,
+            start: start.unwrap_or(0.0),
+            height: height.unwrap_or(0.0),
+            height_falloff: height_falloff.unwrap_or(0.0),
+        },
+        _ if height.is_some() || height_falloff.is_some() => {
+            return Err("fog: \"height\" and \"height_falloff\" go with \"density\" and no \"end\"".to_string());
+        }
+        (Some(start), Some(end), None) if start < end => FogFalloff::Linear { start, end },
+        (Some(start), Some(end), None) => return Err(format!("fog: start ({}) has to be less than end ({})", start, end)),
+        (None, None, Some(density)) => FogFalloff::Exponential { density },
+        _ => return Err("fog: needs either \"start\" and \"end\" or \"density\"".to_string()),
+    };
+    Ok(FogParams { color: color_value.ok_or("fog: missing \"color\"")?, falloff })
+}
+
+fn parse_sky(json: &Json) -> Result<ProceduralSkyParams, String> {
+    let mut sky = ProceduralSkyParams::default();
+    for (key, value) in object(json, "sky")? {
+        let field = format!("sky.{}", key);
+        match key.as_str() {
+            "sun_direction" => {
+                sky.sun_direction = floats::<3>(value, &field)?;
+                if sky.sun_direction == [0.0; 3] {
+                    return Err(format!("{}: can't be all zeros", field));
+                }
+            }
+            "sun_size" => sky.sun_size = positive(value, &field)?,
+            "sun_color" => sky.sun_color = color(value, &field)?,
+            "sun_intensity" => sky.sun_intensity = positive(value, &field)?,
+            "turbidity" => {
+                sky.turbidity = number(value, &field)?;
+                if sky.turbidity < 1.0 {
+                    return Err(format!("{}: has to be 1 or more, found {}", field, sky.turbidity));
+                }
+            }
+            "zenith_color" => sky.zenith_color = color(value, &field)?,
+            "horizon_color" => sky.horizon_color = color(value, &field)?,
+            "ground_color" => sky.ground_color = color(value, &field)?,
+            other => return Err(format!("Unknown field \"sky.{}\"", other)),
+        }
+    }
+    Ok(sky)
+}
+
+fn parse_time_of_day(json: &Json) -> Result<TimeOfDay, String> {
+    let mut time_of_day = TimeOfDay::default();
+    for (key, value) in object(json, "time_of_day")? {
+        let field = format!("time_of_day.{}", key);
+        match key.as_str() {
+            "hours" => {
+                let hours = number(value, &field)?;
+                if !(0.0..24.0).contains(&hours) {
+                    return Err(format!("{}: expected 0 up to 24, found {}", field, hours));
+                }
+                time_of_day.set_hours(hours);
+            }
+            "speed" => time_of_day.speed = number(value, &field)?,
+            "paused" => time_of_day.paused = boolean(value, &field)?,
+            "noon_elevation" => {
+                let elevation = positive(value, &field)?;
+                if elevation > 90.0 {
+                    return Err(format!("{}: at most 90 degrees, found {}", field, elevation));
+                }
+                time_of_day.noon_elevation = elevation;
+            }
+            other => return Err(format!("Unknown field \"time_of_day.{}\"", other)),
+        }
+    }
+    Ok(time_of_day)
+}
+
+fn parse_instance(json: &Json, name: &str) -> Result<SceneInstance, String> {
+    let mut instance = SceneInstance { position: Vec3::ZERO, rotation: Vec3::ZERO, layer: 0 };
+    for (key, value) in object(json, name)? {
+        let field = format!("{}.{}", name, key);
+        match key.as_str() {
+            "position" => instance.position = vec3(value, &field)?,
+            "rotation" => instance.rotation = vec3(value, &field)?,
+            "layer" => {
+                let layer = number(value, &field)?;
+                if layer < 0.0 || layer.fract() != 0.0 {
+                    return Err(format!("{}: expected a whole number 0 or more, found {}", field, layer));
+                }
+                instance.layer = layer as u32;
+            }
+            other => return Err(format!("Unknown field \"{}.{}\"", name, other)),
+        }
+    }
+    Ok(instance)
+}
+
+pub(crate) fn object<'a>(json: &'a Json, name: &str) -> Result<&'a [(String, Json)], String> {
+    match json {
+        Json::Object(members) => Ok(members),
+        other => Err(format!("{}: expected an object, found {}", name, other.type_name())),
+    }
+}
+
+fn array<'a>(json: &'a Json, name: &str) -> Result<&'a [Json], String> {
+    match json {
+        Json::Array(items) => Ok(items),
+        other => Err(format!("{}: expected an array, found {}", name, other.type_name())),
+    }
+}
+
+pub(crate) fn string<'a>(json: &'a Json, name: &str) -> Result<&'a str, String> {
+    match json {
+        Json::String(string) => Ok(string),
+        other => Err(format!("{}: expected a string, found {}", name, other.type_name())),
+    }
+}
+
+fn boolean(json: &Json, name: &str) -> Result<bool, String> {
+    match json {
+        Json::Bool(value) => Ok(*value),
+        other => Err(format!("{}: expected true or false, found {}", name, other.type_name())),
+    }
+}
+
+fn number(json: &Json, name: &str) -> Result<f32, String> {
+    match json {
+        Json::Number(number) => Ok(*number as f32),
+        other => Err(format!("{}: expected a number, found {}", name, other.type_name())),
+    }
+}
+
+fn positive(json: &Json, name: &str) -> Result<f32, String> {
+    let value = number(json, name)?;
+    if value <= 0.0 {
+        return Err(format!("{}: has to be more than 0, found {}", name, value));
+    }
+    Ok(value)
+}
+
+fn floats<const N: usize>(json: &Json, name: &str) -> Result<[f32; N], String> {
+    let items = array(json, name)?;
+    if items.len() != N {
+        return Err(format!("{}: expected {} numbers, found {}", name, N, items.len()));
+    }
+    let mut values = [0.0; N];
+    for (i, item) in items.iter().enumerate() {
+        values[i] = number(item, &format!("{}[{}]", name, i))?;
+    }
+    Ok(values)
+}
+
+pub(crate) fn vec3(json: &Json, name: &str) -> Result<Vec3, String> {
+    floats::<3>(json, name).map(Vec3::from)
+}
+
+pub(crate) fn color(json: &Json, name: &str) -> Result<[f32; 3], String> {
+    let color = floats::<3>(json, name)?;
+    if color.iter().any(|&c| c < 0.0) {
+        return Err(format!("{}: color channels can't be negative", name));
+    }
+    Ok(color)
+}
+
+fn object_json<'a>(members: impl IntoIterator<Item = (&'a str, Json)>) -> Json {
+    Json::Object(members.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+// Through the shortest text that reads back as the same f32, so 0.1 is saved as 0.1 and not as
+// the f64 closest to the f32
+fn number_json(value: f32) -> Json {
+    Json::Number(value.to_string().parse().unwrap_or(f64::NAN))
+}
+
+fn floats_json(values: &[f32]) -> Json {
+    Json::Array(values.iter().map(|&value| number_json(value)).collect())
+}
+
+fn path_json(path: &Path) -> Json {
+    Json::String(path.to_string_lossy().into_owned())
+}
+
+fn camera_json(camera: &SceneCamera) -> Json {
+    let mut members = vec![("eye", floats_json(&camera.eye.to_array())), ("target", floats_json(&camera.target.to_array()))];
+    for (key, value) in [("fovy", camera.fovy), ("znear", camera.znear), ("zfar", camera.zfar)] {
+        if let Some(value) = value {
+            members.push((key, number_json(value)));
+        }
+    }
+    if let Some(projection) = camera.projection {
+        let name = match projection {
+            Projection::Perspective => "perspective",
+            Projection::Orthographic => "orthographic",
+        };
+        members.push(("projection", Json::String(name.to_string())));
+    }
+    object_json(members)
+}
+
+fn fog_json(fog: &FogParams) -> Json {
+    let mut members = vec![("color", floats_json(&fog.color))];
+    match fog.falloff {
+        FogFalloff::Linear { start, end } => members.extend([("start", number_json(start)), ("end", number_json(end))]),
+        FogFalloff::Exponential { density } => members.push(("density", number_json(density))),
+        // "start" always, without any of the three it would read back as plain exponential
+        FogFalloff::ExponentialSquared { density, start, height, height_falloff } => members.extend([
+            ("density", number_json(density)),
+            ("start", number_json(start)),
+            ("height", number_json(height)),
+            ("height_falloff", number_json(height_falloff)),
+        ]),
+    }
+    object_json(members)
+}
+
+fn sky_json(sky: &ProceduralSkyParams) -> Json {
+    object_json([
+        ("sun_direction", floats_json(&sky.sun_direction)),
+        ("sun_size", number_json(sky.sun_size)),
+        ("sun_color", floats_json(&sky.sun_color)),
+        ("sun_intensity", number_json(sky.sun_intensity)),
+        ("turbidity", number_json(sky.turbidity)),
+        ("zenith_color", floats_json(&sky.zenith_color)),
+        ("horizon_color", floats_json(&sky.horizon_color)),
+        ("ground_color", floats_json(&sky.ground_color)),
+    ])
+}
+
+fn time_of_day_json(time_of_day: &TimeOfDay) -> Json {
+    object_json([
+        ("hours", number_json(time_of_day.hours())),
+        ("speed", number_json(time_of_day.speed)),
+        ("paused", Json::Bool(time_of_day.paused)),
+        ("noon_elevation", number_json(time_of_day.noon_elevation)),
+    ])
+}
+
+fn instance_json(instance: &SceneInstance) -> Json {
+    object_json([
+        ("position", floats_json(&instance.position.to_array())),
+        ("rotation", floats_json(&instance.rotation.to_array())),
+        ("layer", Json::Number(instance.layer as f64)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> SceneCamera {
+        SceneCamera {
+            eye: Vec3::new(0.1, 5.0, 10.0),
+            target: Vec3::ZERO,
+            fovy: Some(45.0),
+            znear: Some(0.1),
+            zfar: Some(100.0),
+            projection: Some(Projection::Orthographic),
+        }
+    }
+
+    #[test]
+    fn saved_scenes_load_back() {
+        let directory = std::env::temp_dir().join(format!("wgpu-playground-scene-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("scene.json");
+        let instances = vec![
+            SceneInstance { position: Vec3::new(1.0, 2.0, 3.0), rotation: Vec3::new(10.0, -20.0, 30.0), layer: 2 },
+            SceneInstance { position: Vec3::ZERO, rotation: Vec3::ZERO, layer: 0 },
+        ];
+        let scenes = [
+            Scene {
+                clear_color: Some([0.1, 0.2, 0.3]),
+                camera: Some(camera()),
+                // Next to the scene, and somewhere else that has to stay absolute
+                environment: Some(directory.join("sky.hdr")),
+                fog: Some(FogParams { color: [0.5, 0.5, 0.6], falloff: FogFalloff::Linear { start: 5.0, end: 50.0 } }),
+                mesh: Some(SceneMesh::Primitive("torus".to_string())),
+                instances: Some(instances),
+                point_clouds: vec![directory.join("points").join("scan.xyz"), std::env::temp_dir().join("elsewhere.xyz")],
+                ..Default::default()
+            },
+            Scene {
+                sky: Some(ProceduralSkyParams { turbidity: 4.0, ..Default::default() }),
+                fog: Some(FogParams {
+                    color: [0.7; 3],
+                    falloff: FogFalloff::ExponentialSquared { density: 0.02, start: 0.0, height: 0.0, height_falloff: 0.0 },
+                }),
+                mesh: Some(SceneMesh::Sphere),
+                instances: Some(Vec::new()),
+                ..Default::default()
+            },
+            Scene {
+                time_of_day: Some(TimeOfDay { time: 0.75, speed: 0.5, paused: true, noon_elevation: 45.0 }),
+                fog: Some(FogParams { color: [0.2; 3], falloff: FogFalloff::Exponential { density: 0.1 } }),
+                mesh: Some(SceneMesh::Triangle),
+                ..Default::default()
+            },
+            Scene::default(),
+        ];
+        for scene in scenes {
+            scene.save(&path).unwrap();
+            let text = std::fs::read_to_string(&path).unwrap();
+            assert!(!text.contains(&*directory.to_string_lossy()), "paths next to the scene saved absolute:\n{}", text);
+            assert_eq!(Scene::load(&path), Ok(scene));
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    // What's drawn are the instances' model matrices, the angles in between don't matter
+    #[test]
+    fn instances_keep_their_transforms() {
+        for rotation in [
+            Vec3::ZERO,
+            Vec3::new(10.0, -20.0, 30.0),
+            Vec3::new(-170.0, 80.0, 95.0),
+            // x and z turn the same way here
+            Vec3::new(30.0, 90.0, 10.0),
+            Vec3::new(0.0, -90.0, 45.0),
+        ] {
+            let scene = SceneInstance { position: Vec3::new(4.0, -1.0, 2.0), rotation, layer: 3 };
+            let instance = Instance { position: scene.position, rotation: scene.rotation_matrix(), layer: scene.layer };
+            let saved = SceneInstance::from_instance(&instance);
+            assert_eq!(saved.layer, 3);
+            let loaded = Instance { position: saved.position, rotation: saved.rotation_matrix(), layer: saved.layer };
+            let (before, after) = (instance.model_matrix().cols.concat(), loaded.model_matrix().cols.concat());
+            for (a, b) in before.iter().zip(&after) {
+                assert!((a - b).abs() < 1e-5, "{:?} came back as {:?}: {:?} vs {:?}", rotation, saved.rotation, before, after);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_primitives_are_left_to_the_loader() {
+        let scene = Scene::parse(r#"{"mesh": "teapot"}"#).unwrap();
+        assert_eq!(scene.mesh, Some(SceneMesh::Primitive("teapot".to_string())));
+        assert!(Scene::parse(r#"{"mesh": 3}"#).is_err());
+    }
+}
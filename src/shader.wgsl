@@ -1,24 +1,266 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    exposure: f32,
+    fog_mode: u32,
+    fog_start: f32,
+    fog_end: f32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    eye: vec3<f32>,
+    fog_height: f32,
+    forward: vec3<f32>,
+    fog_height_falloff: f32,
+    // Towards the directional light, normalized
+    sun_direction: vec3<f32>,
+    // Scales image based lighting
+    ambient: f32,
+    sun_color: vec3<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Has to match the FOG_ constants in camera.rs
+const FOG_LINEAR: u32 = 1u;
+const FOG_EXPONENTIAL: u32 = 2u;
+const FOG_EXPONENTIAL_SQUARED: u32 = 3u;
+
+fn view_depth(world_position: vec3<f32>) -> f32 {
+    return dot(world_position - camera.eye, camera.forward);
+}
+
+// Density integrated along the view ray from fog_start to `distance`, for FOG_EXPONENTIAL_SQUARED.
+// Density falls off exponentially above fog_height, which integrates in closed form along a
+// straight ray. `direction_y` is the ray's normalized y
+fn fog_amount(direction_y: f32, distance: f32) -> f32 {
+    let span = max(distance - camera.fog_start, 0.0);
+    // Where the ray enters the fog
+    let start_height = camera.eye.y + direction_y * camera.fog_start;
+    let density = camera.fog_density * exp(min(-camera.fog_height_falloff * (start_height - camera.fog_height), 80.0));
+    let k = camera.fog_height_falloff * direction_y;
+    // Level ray (or no falloff), the closed form would divide by zero
+    if abs(k * span) < 0.0001 {
+        return density * span;
+    }
+    return density * (1.0 - exp(min(-k * span, 80.0))) / k;
+}
+
+// 1 where there's no fog, 0 fully fogged
+fn fog_visibility(world_position: vec3<f32>) -> f32 {
+    let depth = view_depth(world_position);
+    if camera.fog_mode == FOG_LINEAR {
+        return clamp((camera.fog_end - depth) / max(camera.fog_end - camera.fog_start, 0.0001), 0.0, 1.0);
+    } else if camera.fog_mode == FOG_EXPONENTIAL {
+        return exp(-camera.fog_density * max(depth, 0.0));
+    } else if camera.fog_mode == FOG_EXPONENTIAL_SQUARED {
+        let ray = world_position - camera.eye;
+        let distance = length(ray);
+        let amount = fog_amount(ray.y / max(distance, 0.0001), distance);
+        return exp(-amount * amount);
+    }
+    return 1.0;
+}
+
+// Blends an exposed color towards the fog color
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    return mix(camera.fog_color * camera.exposure, color, fog_visibility(world_position));
+}
+
+struct LayeredTextureInfo {
+    tiles_per_row: u32,
+    tiles_per_layer: u32,
+}
+
+@group(1) @binding(0)
+var t_layers: texture_2d_array<f32>;
+@group(1) @binding(1)
+var s_layers: sampler;
+@group(1) @binding(2)
+var<uniform> layers_info: LayeredTextureInfo;
+
+// Works for both a plain texture array (1 tile per row) and an atlas packed into the layers
+fn sample_layer(uv: vec2<f32>, index: u32) -> vec4<f32> {
+    let layer = index / layers_info.tiles_per_layer;
+    let tile = index % layers_info.tiles_per_layer;
+    let tile_position = vec2<f32>(f32(tile % layers_info.tiles_per_row), f32(tile / layers_info.tiles_per_row));
+    let atlas_uv = (tile_position + clamp(uv, vec2<f32>(0.0), vec2<f32>(1.0))) / f32(layers_info.tiles_per_row);
+    return textureSample(t_layers, s_layers, atlas_uv, layer);
+}
+
+// Has to match MAX_BONES in skinning.rs
+const MAX_BONES: u32 = 64u;
+
+struct Bones {
+    matrices: array<mat4x4<f32>, MAX_BONES>,
+}
+
+@group(2) @binding(0)
+var<uniform> bones: Bones;
+
+// Vertex format switches, see vertex_format.rs. A mesh without the attribute gets a variant with
+// the switch rewritten to false, the attribute is still read (filled with defaults) but ignored
+const HAS_UV: bool = true;
+const HAS_SKIN: bool = true;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+    @location(3) joints: vec4<u32>,
+    @location(4) weights: vec4<f32>,
+}
+
+// Weighted blend of the vertex's bones. Weights are expected to add up to 1
+fn skin_matrix(model: VertexInput) -> mat4x4<f32> {
+    if !HAS_SKIN {
+        return mat4x4<f32>(
+            vec4<f32>(1.0, 0.0, 0.0, 0.0),
+            vec4<f32>(0.0, 1.0, 0.0, 0.0),
+            vec4<f32>(0.0, 0.0, 1.0, 0.0),
+            vec4<f32>(0.0, 0.0, 0.0, 1.0),
+        );
+    }
+    return bones.matrices[model.joints.x] * model.weights.x
+        + bones.matrices[model.joints.y] * model.weights.y
+        + bones.matrices[model.joints.z] * model.weights.z
+        + bones.matrices[model.joints.w] * model.weights.w;
+}
+
+// Without UVs every triangle samples the middle of its tile
+fn vertex_uv(model: VertexInput) -> vec2<f32> {
+    if !HAS_UV {
+        return vec2<f32>(0.5);
+    }
+    return model.tex_coords;
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) layer: u32,
+    // Debug tint by LOD level, 0xffffffff for none. See lod.rs
+    @location(10) lod_tint: u32,
+}
+
+// Must match LOD_COLORS in lod.rs
+fn lod_tint(color: vec3<f32>, level: u32) -> vec3<f32> {
+    if level == 0xffffffffu {
+        return color;
+    }
+    var colors = array<vec3<f32>, 5>(
+        vec3<f32>(0.2, 0.9, 0.2),
+        vec3<f32>(0.9, 0.9, 0.2),
+        vec3<f32>(0.95, 0.55, 0.1),
+        vec3<f32>(0.9, 0.15, 0.15),
+        vec3<f32>(0.7, 0.2, 0.9),
+    );
+    // Keeps some of the shading so the shape still reads
+    return mix(color, colors[min(level, 4u)], 0.7);
+}
+
 struct VertexOutput{
-    @builtin(position) clip_position: vec4<f32>
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    // Integers can't be interpolated
+    @location(2) @interpolate(flat) layer: u32,
+    @location(3) world_position: vec3<f32>,
+}
+
+fn to_world_position(model: VertexInput, instance: InstanceInput) -> vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return model_matrix * skin_matrix(model) * vec4<f32>(model.position, 1.0);
+}
+
+fn to_clip_position(model: VertexInput, instance: InstanceInput) -> vec4<f32> {
+    return camera.view_proj * to_world_position(model, instance);
 }
 
 // Entry Point
 // Vertex Shader
 @vertex
 fn vs_main(
-    @builtin(vertex_index) in_vertex_index: u32
+    model: VertexInput,
+    instance: InstanceInput,
 ) -> VertexOutput {
     // let = const | var = let + needs specified type
     var out: VertexOutput;
-    let x = f32(1 - i32(in_vertex_index)) * 0.5;
-    let y = f32(i32(in_vertex_index & 1u) * 2 - 1) * 0.5;
-    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    out.color = lod_tint(model.color, instance.lod_tint);
+    out.tex_coords = vertex_uv(model);
+    out.layer = instance.layer;
+    let world = to_world_position(model, instance);
+    out.clip_position = camera.view_proj * world;
+    out.world_position = world.xyz;
     return out;
 }
 
 // Fragmnt Shader
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>{
-    return vec4<f32>(0.3, 0.2, 0.1, 1.0);
+    let texel = sample_layer(in.tex_coords, in.layer);
+    return vec4<f32>(apply_fog(in.color * texel.rgb * camera.exposure, in.world_position), 1.0);
+}
+
+// Flat shading variant, color isn't interpolated but taken from the provoking (first) vertex
+struct FlatVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) color: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) @interpolate(flat) layer: u32,
+    @location(3) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_flat(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> FlatVertexOutput {
+    var out: FlatVertexOutput;
+    out.color = lod_tint(model.color, instance.lod_tint);
+    out.tex_coords = vertex_uv(model);
+    out.layer = instance.layer;
+    let world = to_world_position(model, instance);
+    out.clip_position = camera.view_proj * world;
+    out.world_position = world.xyz;
+    return out;
+}
+
+@fragment
+fn fs_flat(in: FlatVertexOutput) -> @location(0) vec4<f32> {
+    let texel = sample_layer(in.tex_coords, in.layer);
+    return vec4<f32>(apply_fog(in.color * texel.rgb * camera.exposure, in.world_position), 1.0);
+}
+
+// Selection outline, see outline.rs. Drawn where the stencil doesn't have the object marked
+struct OutlineUniform {
+    color: vec4<f32>,
+    // Fraction the object is grown by, 0.05 = 5% bigger
+    thickness: f32,
 }
 
+@group(3) @binding(0)
+var<uniform> outline: OutlineUniform;
+
+@vertex
+fn vs_outline(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    // Grown around the object origin
+    var scaled = model;
+    scaled.position = model.position * (1.0 + outline.thickness);
+    return to_clip_position(scaled, instance);
+}
+
+@fragment
+fn fs_outline() -> @location(0) vec4<f32> {
+    return outline.color;
+}
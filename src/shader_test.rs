@@ -0,0 +1,427 @@
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::readback;
+
+// Argument / result of a WGSL function under test. Each one travels as its own vec4<f32> through
+// the storage buffers, u32 bitcast
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Value {
+    F32(f32),
+    U32(u32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+}
+
+impl Value {
+    fn wgsl_type(&self) -> &'static str {
+        match self {
+            Value::F32(_) => "f32",
+            Value::U32(_) => "u32",
+            Value::Vec2(_) => "vec2<f32>",
+            Value::Vec3(_) => "vec3<f32>",
+            Value::Vec4(_) => "vec4<f32>",
+        }
+    }
+
+    // How the kernel reads the argument out of its vec4 slot
+    fn unpack(&self, slot: &str) -> String {
+        match self {
+            Value::F32(_) => format!("{}.x", slot),
+            Value::U32(_) => format!("bitcast<u32>({}.x)", slot),
+            Value::Vec2(_) => format!("{}.xy", slot),
+            Value::Vec3(_) => format!("{}.xyz", slot),
+            Value::Vec4(_) => slot.to_string(),
+        }
+    }
+
+    // How the kernel writes the result into its vec4 slot
+    fn pack(&self, value: &str) -> String {
+        match self {
+            Value::F32(_) => format!("vec4<f32>({}, 0.0, 0.0, 0.0)", value),
+            Value::U32(_) => format!("vec4<f32>(bitcast<f32>({}), 0.0, 0.0, 0.0)", value),
+            Value::Vec2(_) => format!("vec4<f32>({}, 0.0, 0.0)", value),
+            Value::Vec3(_) => format!("vec4<f32>({}, 0.0)", value),
+            Value::Vec4(_) => value.to_string(),
+        }
+    }
+
+    fn to_slot(self) -> [f32; 4] {
+        match self {
+            Value::F32(x) => [x, 0.0, 0.0, 0.0],
+            Value::U32(x) => [f32::from_bits(x), 0.0, 0.0, 0.0],
+            Value::Vec2([x, y]) => [x, y, 0.0, 0.0],
+            Value::Vec3([x, y, z]) => [x, y, z, 0.0],
+            Value::Vec4(v) => v,
+        }
+    }
+
+    // Same variant as `self`, from what the kernel wrote
+    fn with_slot(&self, slot: [f32; 4]) -> Value {
+        match self {
+            Value::F32(_) => Value::F32(slot[0]),
+            Value::U32(_) => Value::U32(slot[0].to_bits()),
+            Value::Vec2(_) => Value::Vec2([slot[0], slot[1]]),
+            Value::Vec3(_) => Value::Vec3([slot[0], slot[1], slot[2]]),
+            Value::Vec4(_) => Value::Vec4(slot),
+        }
+    }
+
+    // Largest component difference, u32s have to match exactly and NaN only matches NaN
+    fn difference(&self, other: &Value) -> f32 {
+        match (self, other) {
+            (Value::U32(a), Value::U32(b)) => if a == b { 0.0 } else { f32::INFINITY },
+            _ => {
+                let (a, b) = (self.to_slot(), other.to_slot());
+                (0..4)
+                    .map(|i| match (a[i].is_nan(), b[i].is_nan()) {
+                        (true, true) => 0.0,
+                        (false, false) if a[i] == b[i] => 0.0,
+                        (false, false) => (a[i] - b[i]).abs(),
+                        _ => f32::INFINITY,
+                    })
+                    .fold(0.0, f32::max)
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    // No adapter on this machine, nothing was run
+    Skipped,
+}
+
+// Every case that was off by more than the tolerance
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatches {
+    pub function: String,
+    // Case index, inputs, expected, actual
+    pub cases: Vec<(usize, Vec<Value>, Value, Value)>,
+    pub eps: f32,
+}
+
+impl fmt::Display for Mismatches {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} cases off by more than {}", self.function, self.cases.len(), self.eps)?;
+        // The first few are enough to see what's wrong
+        for (i, inputs, expected, actual) in self.cases.iter().take(8) {
+            write!(f, "\n  case {}: {:?} -> {:?}, expected {:?}", i, inputs, actual, expected)?;
+        }
+        Ok(())
+    }
+}
+
+// Shared by every call, tests run on multiple threads. None without an adapter
+pub(crate) fn device() -> Option<&'static (wgpu::Device, wgpu::Queue)> {
+    shared().map(|(_, device)| device)
+}
+
+// What device() was requested from, for code that checks capabilities first
+#[cfg(test)]
+pub(crate) fn adapter() -> Option<&'static wgpu::Adapter> {
+    shared().map(|(adapter, _)| adapter)
+}
+
+fn shared() -> Option<&'static (wgpu::Adapter, (wgpu::Device, wgpu::Queue))> {
+    static SHARED: OnceLock<Option<(wgpu::Adapter, (wgpu::Device, wgpu::Queue))>> = OnceLock::new();
+    SHARED
+        .get_or_init(|| {
+            let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+            let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+            let device = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+                label: Some("Shader Test Device"),
+                features: wgpu::Features::empty(),
+                limits: wgpu::Limits::downlevel_defaults(),
+            }, None)).ok()?;
+            Some((adapter, device))
+        })
+        .as_ref()
+}
+
+// Calls `function` from `source` (a whole WGSL module, e.g. include_str!("pbr.wgsl")) once per
+// case with `inputs[i]` as arguments on the GPU, and compares what it returns with `expected[i]`
+// (from a Rust reference), each component within `eps`. Argument and result types come from the
+// first case. The function can use the module's constants and helpers but no bindings, those
+// aren't bound. Err is a compile error or the cases that didn't match
+pub fn wgsl_test(source: &str, function: &str, inputs: &[Vec<Value>], expected: &[Value], eps: f32) -> Result<Outcome, String> {
+    if inputs.len() != expected.len() {
+        return Err(format!("{}: {} input cases but {} expected results", function, inputs.len(), expected.len()));
+    }
+    let (Some(signature), Some(result)) = (inputs.first(), expected.first()) else {
+        return Ok(Outcome::Passed);
+    };
+    let Some((device, queue)) = device() else {
+        return Ok(Outcome::Skipped);
+    };
+    for (i, case) in inputs.iter().enumerate() {
+        let types = case.iter().map(Value::wgsl_type);
+        if case.len() != signature.len() || !types.eq(signature.iter().map(Value::wgsl_type)) || expected[i].wgsl_type() != result.wgsl_type() {
+            return Err(format!("{}: case {} has different types than case 0", function, i));
+        }
+    }
+
+    let arguments = signature
+        .iter()
+        .enumerate()
+        .map(|(i, value)| value.unpack(&format!("wgsl_test_inputs[base + {}u]", i)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    // Group 3 has room past what the modules use, the other groups stay empty
+    let kernel = format!(
+        "
+@group(3) @binding(900)
+var<storage, read> wgsl_test_inputs: array<vec4<f32>>;
+@group(3) @binding(901)
+var<storage, read_write> wgsl_test_outputs: array<vec4<f32>>;
+
+@compute @workgroup_size(64)
+fn wgsl_test_main(@builtin(global_invocation_id) id: vec3<u32>) {{
+    if id.x >= arrayLength(&wgsl_test_outputs) {{
+        return;
+    }}
+    let base = id.x * {count}u;
+    wgsl_test_outputs[id.x] = {result};
+}}
+",
+        count = signature.len(),
+        result = result.pack(&format!("{}({})", function, arguments)),
+    );
+
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Shader Test Module"),
+        source: wgpu::ShaderSource::Wgsl(format!("{}\n{}", source, kernel).into()),
+    });
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Shader Test Pipeline"),
+        layout: None,
+        module: &module,
+        entry_point: "wgsl_test_main",
+    });
+    if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+        return Err(format!("{}: {}", function, e));
+    }
+
+    let slots = inputs.iter().flatten().map(|value| value.to_slot()).collect::<Vec<_>>();
+    // Zero sized storage buffers aren't allowed, functions without arguments get a dummy
+    let input_bytes = if slots.is_empty() { vec![0u8; 16] } else { bytemuck::cast_slice(&slots).to_vec() };
+    use wgpu::util::DeviceExt;
+    let input_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Shader Test Inputs"),
+        contents: &input_bytes,
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+    let output_size = (expected.len() * 16) as wgpu::BufferAddress;
+    let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Shader Test Outputs"),
+        size: output_size,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Shader Test Staging"),
+        size: output_size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let empty_bind_groups = (0..3)
+        .map(|group| device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shader Test Empty Bind Group"),
+            layout: &pipeline.get_bind_group_layout(group),
+            entries: &[],
+        }))
+        .collect::<Vec<_>>();
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Shader Test Bind Group"),
+        layout: &pipeline.get_bind_group_layout(3),
+        entries: &[
+            wgpu::BindGroupEntry { binding: 900, resource: input_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 901, resource: output_buffer.as_entire_binding() },
+        ],
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Shader Test Encoder")
+    });
+    {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("Shader Test Pass") });
+        pass.set_pipeline(&pipeline);
+        for (group, empty) in empty_bind_groups.iter().enumerate() {
+            pass.set_bind_group(group as u32, empty, &[]);
+        }
+        pass.set_bind_group(3, &bind_group, &[]);
+        pass.dispatch_workgroups((expected.len() as u32).div_ceil(64), 1, 1);
+    }
+    encoder.copy_buffer_to_buffer(&output_buffer, 0, &staging_buffer, 0, output_size);
+    queue.submit(std::iter::once(encoder.finish()));
+    let outputs = readback::map_read(device, &staging_buffer, |data| bytemuck::cast_slice::<u8, [f32; 4]>(data).to_vec());
+
+    let cases = outputs
+        .into_iter()
+        .enumerate()
+        .map(|(i, slot)| (i, expected[i].with_slot(slot)))
+        .filter(|(i, actual)| actual.difference(&expected[*i]) > eps)
+        .map(|(i, actual)| (i, inputs[i].clone(), expected[i], actual))
+        .collect::<Vec<_>>();
+    if cases.is_empty() {
+        Ok(Outcome::Passed)
+    } else {
+        Err(Mismatches { function: function.to_string(), cases, eps }.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{wgsl_test, Outcome, Value};
+    use crate::luminance::{LuminanceHistogram, HISTOGRAM_BINS};
+    
+    // Passed or skipped (no adapter) is fine, anything else fails with the mismatching cases
+    fn assert_matches(result: Result<Outcome, String>) {
+        if let Err(e) = result {
+            panic!("{}", e);
+        }
+    }
+
+    // Every combination of `values` as a color / direction
+    fn grid(values: &[f32]) -> Vec<[f32; 3]> {
+        values.iter().flat_map(|&x| values.iter().flat_map(move |&y| values.iter().map(move |&z| [x, y, z]))).collect()
+    }
+
+    fn grid2(a: &[f32], b: &[f32]) -> Vec<(f32, f32)> {
+        a.iter().flat_map(|&a| b.iter().map(move |&b| (a, b))).collect()
+    }
+
+    // The PBR terms, over the whole 0..1 range of their inputs
+    fn pbr_pairs() -> Vec<(f32, f32)> {
+        grid2(&[0.0, 0.05, 0.25, 0.5, 0.75, 0.95, 1.0], &[0.05, 0.25, 0.5, 0.75, 1.0])
+    }
+
+    #[test]
+    fn luminance() {
+        let colors = grid(&[0.0, 0.01, 0.18, 0.5, 1.0, 4.0]);
+        assert_matches(wgsl_test(
+            include_str!("luminance.wgsl"),
+            "luminance",
+            &colors.iter().map(|&c| vec![Value::Vec3(c)]).collect::<Vec<_>>(),
+            &colors.iter().map(|c| Value::F32(c[0] * 0.2126 + c[1] * 0.7152 + c[2] * 0.0722)).collect::<Vec<_>>(),
+            1e-5,
+        ));
+    }
+
+    #[test]
+    fn histogram_bin() {
+        // Both ends of the range and the middle of every bin. Exact bin edges could go either way
+        // with float error, so they're left out
+        let mut luminances = vec![0.0, 1e-6, 1e-3, 0.18, 1.0, 100.0, 1e6];
+        for bin in 1..HISTOGRAM_BINS {
+            let center = LuminanceHistogram::bin_center_log2(bin);
+            luminances.push(center.exp2());
+        }
+        assert_matches(wgsl_test(
+            include_str!("luminance.wgsl"),
+            "histogram_bin",
+            &luminances.iter().map(|&l| vec![Value::F32(l)]).collect::<Vec<_>>(),
+            &luminances.iter().map(|&l| Value::U32(LuminanceHistogram::bin(l) as u32)).collect::<Vec<_>>(),
+            0.0,
+        ));
+    }
+
+    #[test]
+    fn distribution_ggx() {
+        // Very smooth surfaces peak in the ten thousands, out of reach of an absolute tolerance
+        let pairs = grid2(&[0.0, 0.05, 0.25, 0.5, 0.75, 0.95, 1.0], &[0.25, 0.5, 0.75, 1.0]);
+        assert_matches(wgsl_test(
+            include_str!("pbr.wgsl"),
+            "distribution_ggx",
+            &pairs.iter().map(|&(n_dot_h, roughness)| vec![Value::F32(n_dot_h), Value::F32(roughness)]).collect::<Vec<_>>(),
+            &pairs.iter().map(|&(n_dot_h, roughness)| {
+                let a2 = roughness.powi(4);
+                let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+                Value::F32(a2 / (std::f32::consts::PI * d * d))
+            }).collect::<Vec<_>>(),
+            1e-3,
+        ));
+    }
+
+    #[test]
+    fn geometry_schlick_ggx() {
+        let pairs = pbr_pairs();
+        assert_matches(wgsl_test(
+            include_str!("pbr.wgsl"),
+            "geometry_schlick_ggx",
+            &pairs.iter().map(|&(n_dot_v, roughness)| vec![Value::F32(n_dot_v), Value::F32(roughness)]).collect::<Vec<_>>(),
+            &pairs.iter().map(|&(n_dot_v, roughness)| {
+                let k = (roughness + 1.0).powi(2) / 8.0;
+                Value::F32(n_dot_v / (n_dot_v * (1.0 - k) + k))
+            }).collect::<Vec<_>>(),
+            1e-5,
+        ));
+    }
+
+    #[test]
+    fn fresnel_schlick() {
+        let f0s = [[0.04, 0.04, 0.04], [0.95, 0.64, 0.54], [1.0, 1.0, 1.0]];
+        let cases = pbr_pairs().iter().flat_map(|&(cos_theta, _)| f0s.map(|f0| (cos_theta, f0))).collect::<Vec<_>>();
+        assert_matches(wgsl_test(
+            include_str!("pbr.wgsl"),
+            "fresnel_schlick",
+            &cases.iter().map(|&(cos_theta, f0)| vec![Value::F32(cos_theta), Value::Vec3(f0)]).collect::<Vec<_>>(),
+            &cases.iter().map(|&(cos_theta, f0)| {
+                let t = (1.0 - cos_theta).clamp(0.0, 1.0).powi(5);
+                Value::Vec3(f0.map(|f| f + (1.0 - f) * t))
+            }).collect::<Vec<_>>(),
+            1e-5,
+        ));
+    }
+
+    // Low discrepancy points the IBL prefilter samples with
+    #[test]
+    fn hammersley() {
+        let indices = (0..256).step_by(7).chain([255]).collect::<Vec<u32>>();
+        assert_matches(wgsl_test(
+            include_str!("ibl.wgsl"),
+            "hammersley",
+            &indices.iter().map(|&i| vec![Value::U32(i), Value::U32(256)]).collect::<Vec<_>>(),
+            &indices.iter().map(|&i| Value::Vec2([i as f32 / 256.0, i.reverse_bits() as f32 * 2.328_306_4e-10])).collect::<Vec<_>>(),
+            1e-6,
+        ));
+    }
+
+    // Direction -> equirectangular texture coordinate, the sky and IBL lookups both use it. Straight
+    // up and down are left out, atan2(0, 0) is undefined and u doesn't matter at the poles anyway
+    #[test]
+    fn equirect_uv() {
+        let directions = grid(&[-1.0, -0.3, 0.0, 0.4, 1.0]).into_iter().filter(|&[x, _, z]| x != 0.0 || z != 0.0).collect::<Vec<_>>();
+        assert_matches(wgsl_test(
+            include_str!("sky.wgsl"),
+            "equirect_uv",
+            &directions.iter().map(|&d| vec![Value::Vec3(d)]).collect::<Vec<_>>(),
+            &directions.iter().map(|&[x, y, z]| {
+                let length = (x * x + y * y + z * z).sqrt();
+                let (x, y, z) = (x / length, y / length, z / length);
+                let pi = std::f32::consts::PI;
+                Value::Vec2([z.atan2(x) / (2.0 * pi) + 0.5, y.clamp(-1.0, 1.0).acos() / pi])
+            }).collect::<Vec<_>>(),
+            1e-4,
+        ));
+    }
+
+    // The harness itself has to catch a wrong reference, or every test above passes vacuously
+    #[test]
+    fn reports_mismatches() {
+        let result = wgsl_test(
+            include_str!("luminance.wgsl"),
+            "luminance",
+            &[vec![Value::Vec3([1.0, 1.0, 1.0])]],
+            &[Value::F32(0.5)],
+            1e-5,
+        );
+        if result != Ok(Outcome::Skipped) {
+            assert!(result.is_err(), "Wrong expected value passed: {:?}", result);
+        }
+    }
+}
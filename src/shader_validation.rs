@@ -0,0 +1,349 @@
+// (name, @location, type) of every input of the entry point, arguments and struct members alike.
+// Builtins are left out
+pub(crate) fn vertex_inputs(module: &naga::Module, entry_point: &naga::EntryPoint) -> Vec<(Option<String>, u32, naga::Handle<naga::Type>)> {
+    let mut inputs = Vec::new();
+    let mut add = |name: &Option<String>, binding: &Option<naga::Binding>, ty| {
+        if let Some(naga::Binding::Location { location, .. }) = *binding {
+            inputs.push((name.clone(), location, ty));
+        }
+    };
+    for argument in &entry_point.function.arguments {
+        match (&argument.binding, &module.types[argument.ty].inner) {
+            (None, naga::TypeInner::Struct { members, .. }) => {
+                for member in members {
+                    add(&member.name, &member.binding, member.ty);
+                }
+            }
+            (binding, _) => add(&argument.name, binding, argument.ty),
+        }
+    }
+    inputs
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use naga::valid::{Capabilities, ModuleInfo, ValidationFlags, Validator};
+
+    use crate::debug_lines::LineInstance;
+    use crate::decal::DecalRaw;
+    use crate::ibl::IblMaps;
+    use crate::instance::InstanceRaw;
+    use crate::per_draw::PerDrawData;
+    use crate::points::PointVertex;
+    use crate::skinning::BoneBuffer;
+    use crate::streaming::TerrainVertex;
+    use crate::texture::LayeredTexture;
+    use crate::trail::TrailVertex;
+    use crate::transparency::TransparentVertex;
+    use crate::velocity::VelocityPass;
+    use crate::wireframe::WireframeRenderer;
+    use crate::{motion_blur, vertex_format, Vertex, CAMERA_LAYOUT_ENTRIES};
+
+    use super::vertex_inputs;
+
+    // A shader module the app creates, as it's handed to wgpu, with the Rust side layouts its
+    // pipelines are made with. Layouts only built inside their renderer's `new` or reflected from the
+    // shader itself (reflection.rs) aren't listed, those modules are only parsed and validated
+    struct BundledShader {
+        name: &'static str,
+        source: Cow<'static, str>,
+        // Beyond what every device has, only where the pipeline asks for the feature
+        capabilities: Capabilities,
+        // (group, entries) every entry point's bindings in that group have to match
+        groups: Vec<(u32, &'static [wgpu::BindGroupLayoutEntry])>,
+        // Vertex entry point and the buffers its pipelines read
+        vertex_buffers: Vec<(&'static str, Vec<wgpu::VertexBufferLayout<'static>>)>,
+    }
+
+    impl BundledShader {
+        fn new(name: &'static str, source: impl Into<Cow<'static, str>>) -> Self {
+            Self { name, source: source.into(), capabilities: Capabilities::empty(), groups: Vec::new(), vertex_buffers: Vec::new() }
+        }
+
+        fn group(mut self, group: u32, entries: &'static [wgpu::BindGroupLayoutEntry]) -> Self {
+            self.groups.push((group, entries));
+            self
+        }
+
+        // Camera, layered texture and bones, what every pipeline on shader.wgsl starts with
+        fn mesh_groups(self) -> Self {
+            self.group(0, CAMERA_LAYOUT_ENTRIES).group(1, LayeredTexture::LAYOUT_ENTRIES).group(2, BoneBuffer::LAYOUT_ENTRIES)
+        }
+
+        fn vertex(mut self, entry_point: &'static str, buffers: Vec<wgpu::VertexBufferLayout<'static>>) -> Self {
+            self.vertex_buffers.push((entry_point, buffers));
+            self
+        }
+    }
+
+    // Every variant that gets compiled: the files on their own and the concatenations some
+    // renderers build
+    fn bundled_shaders() -> Vec<BundledShader> {
+        let mesh = || vec![Vertex::desc(), InstanceRaw::desc()];
+        let mut shaders = vec![
+            BundledShader::new("shader.wgsl", include_str!("shader.wgsl"))
+                .mesh_groups()
+                .vertex("vs_main", mesh())
+                .vertex("vs_flat", mesh())
+                .vertex("vs_outline", mesh()),
+            BundledShader::new(
+                "shader.wgsl (HAS_UV = false, HAS_SKIN = false)",
+                vertex_format::shader_variant(include_str!("shader.wgsl"), &[("HAS_UV", false), ("HAS_SKIN", false)])
+                    .expect("shader.wgsl has both switches"),
+            )
+                .mesh_groups()
+                .vertex("vs_main", mesh())
+                .vertex("vs_flat", mesh()),
+            BundledShader::new("shader.wgsl + velocity.wgsl", VelocityPass::shader_source())
+                .mesh_groups()
+                .vertex("vs_velocity", vec![Vertex::desc(), InstanceRaw::desc(), VelocityPass::previous_instances_desc()]),
+            BundledShader::new("shader.wgsl + wireframe.wgsl", WireframeRenderer::shader_source())
+                .mesh_groups()
+                .vertex("vs_wireframe", mesh()),
+            BundledShader::new("shader.wgsl + per_draw.wgsl (uniform)", PerDrawData::shader_source(false))
+                .mesh_groups()
+                .vertex("vs_per_draw", vec![Vertex::desc()]),
+            BundledShader {
+                capabilities: Capabilities::PUSH_CONSTANT,
+                ..BundledShader::new("shader.wgsl + per_draw.wgsl (push constants)", PerDrawData::shader_source(true))
+                    .mesh_groups()
+                    .vertex("vs_per_draw", vec![Vertex::desc()])
+            },
+            BundledShader::new("pbr.wgsl", include_str!("pbr.wgsl"))
+                .group(0, CAMERA_LAYOUT_ENTRIES)
+                .group(2, BoneBuffer::LAYOUT_ENTRIES)
+                .group(3, IblMaps::LAYOUT_ENTRIES)
+                .vertex("vs_pbr", mesh()),
+            BundledShader::new("line.wgsl", include_str!("line.wgsl"))
+                .group(0, CAMERA_LAYOUT_ENTRIES)
+                .vertex("vs_main", vec![LineInstance::desc()]),
+            BundledShader::new("transparent.wgsl", include_str!("transparent.wgsl"))
+                .group(0, CAMERA_LAYOUT_ENTRIES)
+                .vertex("vs_main", vec![TransparentVertex::desc()]),
+            BundledShader::new("points.wgsl", include_str!("points.wgsl"))
+                .group(0, CAMERA_LAYOUT_ENTRIES)
+                .vertex("vs_point", vec![PointVertex::desc()]),
+            BundledShader::new("terrain.wgsl", include_str!("terrain.wgsl"))
+                .group(0, CAMERA_LAYOUT_ENTRIES)
+                .vertex("vs_terrain", vec![TerrainVertex::desc()]),
+            BundledShader::new("impostor.wgsl", include_str!("impostor.wgsl"))
+                .group(0, CAMERA_LAYOUT_ENTRIES)
+                .vertex("vs_impostor", vec![InstanceRaw::desc()]),
+            BundledShader::new("sky.wgsl", include_str!("sky.wgsl")).group(0, CAMERA_LAYOUT_ENTRIES),
+            BundledShader::new("grid.wgsl", include_str!("grid.wgsl")).group(0, CAMERA_LAYOUT_ENTRIES),
+            BundledShader::new("fog.wgsl", include_str!("fog.wgsl")).group(0, CAMERA_LAYOUT_ENTRIES),
+            BundledShader::new("decal.wgsl", include_str!("decal.wgsl"))
+                .group(0, CAMERA_LAYOUT_ENTRIES)
+                .vertex("vs_main", vec![DecalRaw::desc()]),
+            BundledShader::new("trail.wgsl", include_str!("trail.wgsl"))
+                .group(0, CAMERA_LAYOUT_ENTRIES)
+                .vertex("vs_main", vec![TrailVertex::desc()]),
+            BundledShader::new("motion_blur.wgsl + max_velocity.wgsl", motion_blur::max_velocity_shader_source()),
+            BundledShader::new("preview.wgsl", include_str!("preview.wgsl")).vertex("vs_main", vec![Vertex::desc()]),
+        ];
+        for (name, source) in [
+            ("blit.wgsl", include_str!("blit.wgsl")),
+            ("clear_rect.wgsl", include_str!("clear_rect.wgsl")),
+            ("conservative.wgsl", include_str!("conservative.wgsl")),
+            ("debug_view.wgsl", include_str!("debug_view.wgsl")),
+            ("exposure.wgsl", include_str!("exposure.wgsl")),
+            ("fxaa.wgsl", include_str!("fxaa.wgsl")),
+            ("ibl.wgsl", include_str!("ibl.wgsl")),
+            ("luminance.wgsl", include_str!("luminance.wgsl")),
+            ("motion_blur.wgsl", include_str!("motion_blur.wgsl")),
+            ("oit_composite.wgsl", include_str!("oit_composite.wgsl")),
+            ("procedural_sky.wgsl", include_str!("procedural_sky.wgsl")),
+            ("taa.wgsl", include_str!("taa.wgsl")),
+            ("text.wgsl", include_str!("text.wgsl")),
+        ] {
+            shaders.push(BundledShader::new(name, source));
+        }
+        shaders
+    }
+
+    fn validate(shader: &BundledShader) -> Result<(), Vec<String>> {
+        let module = naga::front::wgsl::parse_str(&shader.source)
+            .map_err(|e| vec![e.emit_to_string_with_path(&shader.source, shader.name)])?;
+        let info = Validator::new(ValidationFlags::all(), shader.capabilities)
+            .validate(&module)
+            .map_err(|e| vec![e.emit_to_string_with_path(&shader.source, shader.name)])?;
+
+        let mut errors = Vec::new();
+        for &(group, entries) in &shader.groups {
+            check_group(&module, &info, group, entries, &mut errors);
+        }
+        for (entry_point, buffers) in &shader.vertex_buffers {
+            match module.entry_points.iter().find(|entry| entry.name == *entry_point) {
+                Some(entry) => check_vertex_inputs(&module, entry, buffers, &mut errors),
+                None => errors.push(format!("no entry point {}", entry_point)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn stage_flag(stage: naga::ShaderStage) -> wgpu::ShaderStages {
+        match stage {
+            naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+            naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+            naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+        }
+    }
+
+    // Bindings that aren't used by any entry point don't end up in a pipeline, they can be anything
+    fn check_group(
+        module: &naga::Module,
+        info: &ModuleInfo,
+        group: u32,
+        entries: &[wgpu::BindGroupLayoutEntry],
+        errors: &mut Vec<String>,
+    ) {
+        for (index, entry_point) in module.entry_points.iter().enumerate() {
+            let uses = info.get_entry_point(index);
+            for (handle, global) in module.global_variables.iter() {
+                let Some(binding) = global.binding.as_ref().filter(|binding| binding.group == group) else {
+                    continue;
+                };
+                if uses[handle].is_empty() {
+                    continue;
+                }
+                let name = format!(
+                    "{} (@group({}) @binding({}), used by {})",
+                    global.name.as_deref().unwrap_or("?"),
+                    group,
+                    binding.binding,
+                    entry_point.name
+                );
+                let Some(entry) = entries.iter().find(|entry| entry.binding == binding.binding) else {
+                    errors.push(format!("{} isn't in the Rust side layout", name));
+                    continue;
+                };
+                if !entry.visibility.contains(stage_flag(entry_point.stage)) {
+                    errors.push(format!("{} isn't visible to {:?} in the Rust side layout", name, entry_point.stage));
+                }
+                if let Err(e) = binding_matches(module, global, &entry.ty) {
+                    errors.push(format!("{}: {}", name, e));
+                }
+            }
+        }
+    }
+
+    fn binding_matches(module: &naga::Module, global: &naga::GlobalVariable, ty: &wgpu::BindingType) -> Result<(), String> {
+        use naga::{AddressSpace, ImageClass, ImageDimension, ScalarKind, TypeInner};
+
+        let declared = match (global.space, &module.types[global.ty].inner) {
+            (AddressSpace::Uniform, _) => return match ty {
+                wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, .. } => Ok(()),
+                _ => Err(format!("uniform buffer in the shader, {:?} in the layout", ty)),
+            },
+            (AddressSpace::Storage { access }, _) => {
+                let read_only = !access.contains(naga::StorageAccess::STORE);
+                return match ty {
+                    wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: r }, .. } if *r == read_only => Ok(()),
+                    _ => Err(format!("storage buffer (read only: {}) in the shader, {:?} in the layout", read_only, ty)),
+                };
+            }
+            (AddressSpace::Handle, TypeInner::Sampler { comparison }) => {
+                return match ty {
+                    wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison) if *comparison => Ok(()),
+                    wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering | wgpu::SamplerBindingType::NonFiltering)
+                        if !*comparison => Ok(()),
+                    _ => Err(format!("sampler (comparison: {}) in the shader, {:?} in the layout", comparison, ty)),
+                };
+            }
+            (AddressSpace::Handle, TypeInner::Image { dim, arrayed, class }) => (dim, arrayed, class),
+            (space, inner) => return Err(format!("{:?} {:?} can't be checked", space, inner)),
+        };
+
+        let (dim, arrayed, class) = declared;
+        let view_dimension = match (dim, arrayed) {
+            (ImageDimension::D1, false) => wgpu::TextureViewDimension::D1,
+            (ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+            (ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+            (ImageDimension::D3, false) => wgpu::TextureViewDimension::D3,
+            (ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+            (ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+            (dim, _) => return Err(format!("arrayed {:?} textures don't exist", dim)),
+        };
+        let matches = match (class, ty) {
+            (ImageClass::Sampled { kind, multi }, wgpu::BindingType::Texture { sample_type, view_dimension: v, multisampled }) => {
+                let kind_matches = matches!(
+                    (kind, sample_type),
+                    (ScalarKind::Float, wgpu::TextureSampleType::Float { .. } | wgpu::TextureSampleType::Depth)
+                        | (ScalarKind::Sint, wgpu::TextureSampleType::Sint)
+                        | (ScalarKind::Uint, wgpu::TextureSampleType::Uint)
+                );
+                kind_matches && *v == view_dimension && multi == multisampled
+            }
+            (ImageClass::Depth { multi }, wgpu::BindingType::Texture { sample_type, view_dimension: v, multisampled }) => {
+                *sample_type == wgpu::TextureSampleType::Depth && *v == view_dimension && multi == multisampled
+            }
+            (ImageClass::Storage { .. }, wgpu::BindingType::StorageTexture { view_dimension: v, .. }) => *v == view_dimension,
+            _ => false,
+        };
+        if matches {
+            Ok(())
+        } else {
+            Err(format!("{:?} {:?} texture in the shader, {:?} in the layout", class, view_dimension, ty))
+        }
+    }
+
+    // Every @location the entry point reads has to come from one of the buffers, as the same kind of
+    // number (float, signed or unsigned). Extra attributes in the buffers are fine
+    fn check_vertex_inputs(
+        module: &naga::Module,
+        entry_point: &naga::EntryPoint,
+        buffers: &[wgpu::VertexBufferLayout],
+        errors: &mut Vec<String>,
+    ) {
+        let attributes = buffers.iter().flat_map(|buffer| buffer.attributes).collect::<Vec<_>>();
+        for (name, location, ty) in vertex_inputs(module, entry_point) {
+            let name = format!("{} (@location({}) of {})", name.as_deref().unwrap_or("?"), location, entry_point.name);
+            let mut matching = attributes.iter().filter(|attribute| attribute.shader_location == location);
+            let Some(attribute) = matching.next() else {
+                errors.push(format!("{} isn't in any vertex buffer", name));
+                continue;
+            };
+            if matching.next().is_some() {
+                errors.push(format!("{} is in more than one vertex buffer", name));
+            }
+            let kind = match module.types[ty].inner {
+                naga::TypeInner::Scalar { kind, .. } | naga::TypeInner::Vector { kind, .. } => kind,
+                ref other => {
+                    errors.push(format!("{} is a {:?}, not a number or vector", name, other));
+                    continue;
+                }
+            };
+            if kind != vertex_format_kind(attribute.format) {
+                errors.push(format!("{} is {:?} in the shader but {:?} in the vertex buffer", name, kind, attribute.format));
+            }
+        }
+    }
+
+    fn vertex_format_kind(format: wgpu::VertexFormat) -> naga::ScalarKind {
+        use wgpu::VertexFormat::*;
+        match format {
+            Uint8x2 | Uint8x4 | Uint16x2 | Uint16x4 | Uint32 | Uint32x2 | Uint32x3 | Uint32x4 => naga::ScalarKind::Uint,
+            Sint8x2 | Sint8x4 | Sint16x2 | Sint16x4 | Sint32 | Sint32x2 | Sint32x3 | Sint32x4 => naga::ScalarKind::Sint,
+            // Normalized and half floats arrive as f32
+            _ => naga::ScalarKind::Float,
+        }
+    }
+
+    // Parses and validates every bundled shader variant with naga, on the CPU, and checks their
+    // bindings and vertex inputs against the Rust side layouts. Catches shaders that would only fail
+    // once the pipeline using them gets created
+    #[test]
+    fn bundled_shaders_are_valid() {
+        let errors = bundled_shaders()
+            .iter()
+            .flat_map(|shader| validate(shader).err().unwrap_or_default().into_iter().map(|e| format!("{}: {}", shader.name, e)))
+            .collect::<Vec<_>>();
+        assert!(errors.is_empty(), "{}", errors.join("\n"));
+    }
+}
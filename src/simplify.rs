@@ -0,0 +1,594 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::math::Vec3;
+use crate::{mesh, Vertex};
+
+// How far simplify() goes and what it leaves alone
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SimplifyOptions {
+    // Fraction of the triangles to keep, 0..1
+    pub target_ratio: f32,
+    // Stops early once the cheapest collapse would move the surface further than this (object
+    // space units, root mean square over the planes around it). None goes down to target_ratio
+    pub max_error: Option<f32>,
+    // Vertices on a boundary (an edge with one triangle: holes, open rims) stay where they are.
+    // Otherwise they only slide along the boundary, kept close to it by boundary_weight
+    pub lock_boundary: bool,
+    // Vertices where the attributes split (UV seams, hard color edges) stay where they are.
+    // Otherwise they only collapse along the seam, so both sides keep their own UVs
+    pub lock_seams: bool,
+    // How strongly a boundary keeps its shape when it isn't locked, relative to the surface
+    pub boundary_weight: f32,
+}
+
+impl Default for SimplifyOptions {
+    fn default() -> Self {
+        Self { target_ratio: 0.5, max_error: None, lock_boundary: false, lock_seams: false, boundary_weight: 10.0 }
+    }
+}
+
+// Symmetric 4x4 plane quadric (Garland & Heckbert), planes weighted by area, plus the weight that
+// went into it for the mean squared distance max_error is compared with
+#[derive(Copy, Clone, Debug, Default)]
+struct Quadric {
+    q: [f64; 10],
+    weight: f64,
+}
+
+impl Quadric {
+    // Plane through `point` with unit `normal`
+    fn plane(normal: Vec3, point: Vec3, weight: f32) -> Self {
+        let [a, b, c] = normal.to_array().map(f64::from);
+        let d = -f64::from(normal.dot(point));
+        let w = f64::from(weight);
+        Self {
+            q: [a * a * w, a * b * w, a * c * w, a * d * w, b * b * w, b * c * w, b * d * w, c * c * w, c * d * w, d * d * w],
+            weight: w,
+        }
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (q, o) in self.q.iter_mut().zip(other.q) {
+            *q += o;
+        }
+        self.weight += other.weight;
+    }
+
+    // Weighted sum of squared distances, what collapses are ranked by. Moving across a large area
+    // costs more than across a small one, which keeps the triangles evenly sized
+    fn error(&self, p: Vec3) -> f64 {
+        let [x, y, z] = p.to_array().map(f64::from);
+        let q = &self.q;
+        let e = q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+            + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z + 2.0 * q[8] * z
+            + q[9];
+        e.max(0.0)
+    }
+
+    fn mean_error(&self, p: Vec3) -> f64 {
+        if self.weight > 0.0 { self.error(p) / self.weight } else { 0.0 }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Cost {
+    rank: f64,
+    // Mean squared distance, for max_error
+    mean: f64,
+}
+
+// Collapse of position `from` onto position `to`, cheapest first in the heap. Stale once either
+// side changed since it was pushed
+struct Candidate {
+    cost: Cost,
+    from: u32,
+    to: u32,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max heap
+        other.cost.rank.total_cmp(&self.cost.rank)
+    }
+}
+
+// Half edge collapses: a position is merged into a neighbouring one and its vertices replaced by
+// that one's, so no new vertices (and no interpolated attributes) are made. Positions are the
+// vertices welded by position, a seam vertex has several vertices on one position
+struct Simplifier {
+    positions: Vec<Vec3>,
+    quadrics: Vec<Quadric>,
+    // Position of every vertex
+    vertex_position: Vec<u32>,
+    triangles: Vec<[u32; 3]>,
+    triangle_alive: Vec<bool>,
+    // Triangles around every position, dead ones are dropped lazily
+    fans: Vec<Vec<u32>>,
+    seam: Vec<bool>,
+    version: Vec<u32>,
+    alive: usize,
+    options: SimplifyOptions,
+}
+
+impl Simplifier {
+    fn new(vertices: &[Vertex], indices: &[u32], options: SimplifyOptions) -> Self {
+        let mut welded = HashMap::new();
+        let mut positions = Vec::new();
+        let vertex_position = vertices
+            .iter()
+            .map(|vertex| {
+                *welded.entry(mesh::weld_key(Vec3::from(vertex.position))).or_insert_with(|| {
+                    positions.push(Vec3::from(vertex.position));
+                    positions.len() as u32 - 1
+                })
+            })
+            .collect::<Vec<_>>();
+        let triangles = indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect::<Vec<_>>();
+
+        let mut fans = vec![Vec::new(); positions.len()];
+        let mut quadrics = vec![Quadric::default(); positions.len()];
+        let mut first_vertex = vec![u32::MAX; positions.len()];
+        let mut seam = vec![false; positions.len()];
+        for (t, triangle) in triangles.iter().enumerate() {
+            let p = triangle.map(|v| positions[vertex_position[v as usize] as usize]);
+            let cross = (p[1] - p[0]).cross(p[2] - p[0]);
+            let area = cross.length() * 0.5;
+            let plane = if area > 0.0 { Quadric::plane(cross / (area * 2.0), p[0], area) } else { Quadric::default() };
+            for &v in triangle {
+                let position = vertex_position[v as usize] as usize;
+                fans[position].push(t as u32);
+                quadrics[position].add(&plane);
+                // Two vertices on one position differ in something besides the position
+                match first_vertex[position] {
+                    u32::MAX => first_vertex[position] = v,
+                    first if first != v => seam[position] = true,
+                    _ => {}
+                }
+            }
+        }
+
+        let count = positions.len();
+        let mut simplifier = Self {
+            positions,
+            quadrics,
+            vertex_position,
+            triangle_alive: vec![true; triangles.len()],
+            alive: triangles.len(),
+            triangles,
+            fans,
+            seam,
+            version: vec![0; count],
+            options,
+        };
+        // Planes through boundary edges, perpendicular to their triangle, keep boundaries in shape
+        for t in 0..simplifier.triangles.len() {
+            let corners = simplifier.corners(t as u32);
+            for k in 0..3 {
+                let (a, b) = (corners[k], corners[(k + 1) % 3]);
+                if a != b && simplifier.shared(a, b).len() == 1 {
+                    let [pa, pb, pc] = [a, b, corners[(k + 2) % 3]].map(|p| simplifier.positions[p as usize]);
+                    let edge = pb - pa;
+                    let normal = edge.cross((pb - pa).cross(pc - pa)).normalize();
+                    let plane = Quadric::plane(normal, pa, edge.dot(edge) * options.boundary_weight);
+                    simplifier.quadrics[a as usize].add(&plane);
+                    simplifier.quadrics[b as usize].add(&plane);
+                }
+            }
+        }
+        simplifier
+    }
+
+    fn corners(&self, t: u32) -> [u32; 3] {
+        self.triangles[t as usize].map(|v| self.vertex_position[v as usize])
+    }
+
+    fn fan(&self, position: u32) -> impl Iterator<Item = u32> + '_ {
+        self.fans[position as usize].iter().copied().filter(|&t| self.triangle_alive[t as usize])
+    }
+
+    // Live triangles on the edge a - b
+    fn shared(&self, a: u32, b: u32) -> Vec<u32> {
+        self.fan(a).filter(|&t| self.corners(t).contains(&b)).collect()
+    }
+
+    fn neighbours(&self, position: u32) -> Vec<u32> {
+        let mut neighbours = self.fan(position).flat_map(|t| self.corners(t)).filter(|&p| p != position).collect::<Vec<_>>();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours
+    }
+
+    fn is_boundary(&self, position: u32) -> bool {
+        self.neighbours(position).into_iter().any(|n| self.shared(position, n).len() == 1)
+    }
+
+    // Cost of merging `from` into `to` and the vertex every vertex of `from` becomes, None when
+    // the collapse would tear, fold or flip the surface or move something that's locked
+    fn evaluate(&self, from: u32, to: u32) -> Option<(Cost, Vec<(u32, u32)>)> {
+        if self.options.lock_seams && self.seam[from as usize] {
+            return None;
+        }
+        let shared = self.shared(from, to);
+        let boundary = self.is_boundary(from);
+        match shared.len() {
+            // A boundary vertex only slides along its boundary
+            1 if !self.options.lock_boundary => {}
+            2 if !boundary => {}
+            _ => return None,
+        }
+        // Link condition: the only neighbours both ends share are the tips of the edge's triangles,
+        // anything else would pinch the surface into a non-manifold edge
+        let tips = shared.iter().flat_map(|&t| self.corners(t)).filter(|&p| p != from && p != to).collect::<Vec<_>>();
+        let to_neighbours = self.neighbours(to);
+        if self.neighbours(from).iter().any(|n| *n != to && to_neighbours.contains(n) && !tips.contains(n)) {
+            return None;
+        }
+
+        // Every vertex of `from` has to be in one of the edge's triangles, the vertex of `to` there
+        // is what replaces it. A seam vertex crossing the seam has none and stays
+        let mut remap: Vec<(u32, u32)> = Vec::new();
+        for t in self.fan(from) {
+            let triangle = self.triangles[t as usize];
+            let Some(&vertex) = triangle.iter().find(|&&v| self.vertex_position[v as usize] == from) else {
+                continue;
+            };
+            let replacement = shared
+                .iter()
+                .filter(|&&s| self.triangles[s as usize].contains(&vertex))
+                .map(|&s| *self.triangles[s as usize].iter().find(|&&v| self.vertex_position[v as usize] == to).unwrap())
+                .collect::<Vec<_>>();
+            match (replacement.first(), remap.iter().find(|(v, _)| *v == vertex)) {
+                (None, _) => return None,
+                (Some(&r), _) if replacement.iter().any(|&other| other != r) => return None,
+                (Some(_), Some(_)) => {}
+                (Some(&r), None) => remap.push((vertex, r)),
+            }
+        }
+
+        // Triangles that stay must not flip or collapse to nothing, nor land on one that's already
+        // there (a tetrahedron folding into two back to back triangles)
+        let target = self.positions[to as usize];
+        let sorted = |mut corners: [u32; 3]| {
+            corners.sort_unstable();
+            corners
+        };
+        let existing = self.fan(to).filter(|t| !shared.contains(t)).map(|t| sorted(self.corners(t))).collect::<Vec<_>>();
+        for t in self.fan(from).filter(|t| !shared.contains(t)) {
+            if existing.contains(&sorted(self.corners(t).map(|c| if c == from { to } else { c }))) {
+                return None;
+            }
+            let p = self.corners(t).map(|c| self.positions[c as usize]);
+            let moved = self.corners(t).map(|c| if c == from { target } else { self.positions[c as usize] });
+            let before = (p[1] - p[0]).cross(p[2] - p[0]);
+            let after = (moved[1] - moved[0]).cross(moved[2] - moved[0]);
+            if after.length() <= 1e-12 || before.normalize().dot(after.normalize()) < 0.2 {
+                return None;
+            }
+        }
+
+        let mut quadric = self.quadrics[from as usize];
+        quadric.add(&self.quadrics[to as usize]);
+        Some((Cost { rank: quadric.error(target), mean: quadric.mean_error(target) }, remap))
+    }
+
+    fn push(&self, heap: &mut BinaryHeap<Candidate>, from: u32, to: u32) {
+        if let Some((cost, _)) = self.evaluate(from, to) {
+            heap.push(Candidate { cost, from, to, versions: (self.version[from as usize], self.version[to as usize]) });
+        }
+    }
+
+    fn collapse(&mut self, from: u32, to: u32, remap: &[(u32, u32)]) {
+        for t in self.fan(from).collect::<Vec<_>>() {
+            if self.corners(t).contains(&to) {
+                self.triangle_alive[t as usize] = false;
+                self.alive -= 1;
+            } else {
+                for v in &mut self.triangles[t as usize] {
+                    if let Some(&(_, r)) = remap.iter().find(|(old, _)| old == v) {
+                        *v = r;
+                    }
+                }
+                self.fans[to as usize].push(t);
+            }
+        }
+        self.fans[from as usize].clear();
+        let quadric = self.quadrics[from as usize];
+        self.quadrics[to as usize].add(&quadric);
+        let alive = &self.triangle_alive;
+        self.fans[to as usize].retain(|&t| alive[t as usize]);
+    }
+
+    fn run(&mut self) {
+        let target = ((self.triangles.len() as f32 * self.options.target_ratio.clamp(0.0, 1.0)).ceil() as usize).max(1);
+        let max_error = self.options.max_error.map(|e| f64::from(e) * f64::from(e));
+        let mut heap = BinaryHeap::new();
+        for from in 0..self.positions.len() as u32 {
+            for to in self.neighbours(from) {
+                self.push(&mut heap, from, to);
+            }
+        }
+        while self.alive > target {
+            let Some(candidate) = heap.pop() else {
+                break;
+            };
+            if candidate.versions != (self.version[candidate.from as usize], self.version[candidate.to as usize]) {
+                continue;
+            }
+            // Ranked by the weighted error, so a cheaper one may still come after
+            if max_error.is_some_and(|max| candidate.cost.mean > max) {
+                continue;
+            }
+            let Some((_, remap)) = self.evaluate(candidate.from, candidate.to) else {
+                continue;
+            };
+            self.collapse(candidate.from, candidate.to, &remap);
+
+            // Everything within one ring of the merged position costs something else now
+            let mut touched = self.neighbours(candidate.to);
+            touched.push(candidate.to);
+            touched.push(candidate.from);
+            for &p in &touched {
+                self.version[p as usize] += 1;
+            }
+            for &p in &touched {
+                for n in self.neighbours(p) {
+                    self.push(&mut heap, p, n);
+                    self.push(&mut heap, n, p);
+                }
+            }
+        }
+    }
+
+    // Live triangles with the vertices they still use, renumbered in first use order
+    fn output(&self, vertices: &[Vertex]) -> (Vec<Vertex>, Vec<u32>) {
+        let mut renumbered = HashMap::new();
+        let mut kept = Vec::new();
+        let mut indices = Vec::with_capacity(self.alive * 3);
+        for (triangle, _) in self.triangles.iter().zip(&self.triangle_alive).filter(|(_, &alive)| alive) {
+            for &v in triangle {
+                indices.push(*renumbered.entry(v).or_insert_with(|| {
+                    kept.push(vertices[v as usize]);
+                    kept.len() as u32 - 1
+                }));
+            }
+        }
+        (kept, indices)
+    }
+}
+
+// Quadric error simplification of an indexed triangle list down to about target_ratio of its
+// triangles. Keeps the topology: closed meshes stay closed, nothing folds over, boundaries and
+// UV seams stay where they are (see SimplifyOptions)
+pub fn simplify(vertices: &[Vertex], indices: &[u32], options: &SimplifyOptions) -> (Vec<Vertex>, Vec<u32>) {
+    let mut simplifier = Simplifier::new(vertices, indices, *options);
+    simplifier.run();
+    simplifier.output(vertices)
+}
+
+// Knobs for lod_chain on top of SimplifyOptions, whose target_ratio is ignored there
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LodChainOptions {
+    // Including the original
+    pub levels: u32,
+    // Triangles each level keeps of the one before
+    pub ratio: f32,
+    pub simplify: SimplifyOptions,
+}
+
+impl Default for LodChainOptions {
+    fn default() -> Self {
+        Self { levels: 4, ratio: 0.5, simplify: SimplifyOptions::default() }
+    }
+}
+
+// Levels for State::set_mesh_lods from one triangle list, the original first. Each level is the
+// one before simplified, a level that couldn't get any smaller ends the chain early
+pub fn lod_chain(vertices: &[Vertex], options: &LodChainOptions) -> Vec<Vec<Vertex>> {
+    let (mut level_vertices, mut indices) = mesh::index_vertices(vertices);
+    let mut chain = vec![vertices.to_vec()];
+    for _ in 1..options.levels {
+        let level_options = SimplifyOptions { target_ratio: options.ratio, ..options.simplify };
+        let (next_vertices, next_indices) = simplify(&level_vertices, &indices, &level_options);
+        if next_indices.len() >= indices.len() {
+            break;
+        }
+        chain.push(mesh::unindex(&next_vertices, &next_indices));
+        (level_vertices, indices) = (next_vertices, next_indices);
+    }
+    chain
+}
+
+const CACHE_MAGIC: &[u8; 4] = b"LOD1";
+
+// FNV-1a, stable across runs and builds unlike std's hasher
+fn content_hash(bytes: &[u8], seed: u64) -> u64 {
+    bytes.iter().fold(seed ^ 0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+// Where lod_chain_cached keeps the chain of `vertices` made from `source`: next to it, named by
+// a hash of the vertices and options, so a changed mesh or changed knobs never read a stale chain
+pub fn cache_path(source: &Path, vertices: &[Vertex], options: &LodChainOptions) -> PathBuf {
+    let hash = content_hash(bytemuck::cast_slice(vertices), std::mem::size_of::<Vertex>() as u64);
+    let hash = content_hash(format!("{:?}", options).as_bytes(), hash);
+    let name = source.file_name().map_or_else(|| "mesh".into(), |name| name.to_string_lossy());
+    source.with_file_name(format!("{}.{:016x}.lods", name, hash))
+}
+
+// lod_chain, read from next to `source` when it was made before and written there otherwise.
+// A cache that can't be written is only logged, the chain is still returned
+pub fn lod_chain_cached(source: &Path, vertices: &[Vertex], options: &LodChainOptions) -> Vec<Vec<Vertex>> {
+    let path = cache_path(source, vertices, options);
+    if let Some(chain) = std::fs::read(&path).ok().and_then(|bytes| decode_chain(&bytes)) {
+        log::info!("LODs of {} from {}", source.display(), path.display());
+        return chain;
+    }
+    let chain = lod_chain(vertices, options);
+    if let Err(e) = std::fs::write(&path, encode_chain(&chain)) {
+        log::warn!("Couldn't cache LODs at {}: {}", path.display(), e);
+    }
+    chain
+}
+
+fn encode_chain(chain: &[Vec<Vertex>]) -> Vec<u8> {
+    let mut bytes = CACHE_MAGIC.to_vec();
+    bytes.extend((chain.len() as u32).to_le_bytes());
+    for level in chain {
+        bytes.extend((level.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(bytemuck::cast_slice(level));
+    }
+    bytes
+}
+
+// None for anything truncated or not written by encode_chain
+fn decode_chain(bytes: &[u8]) -> Option<Vec<Vec<Vertex>>> {
+    let mut rest = bytes.strip_prefix(CACHE_MAGIC)?;
+    let read_u32 = |rest: &mut &[u8]| -> Option<u32> {
+        let (value, tail) = rest.split_first_chunk::<4>()?;
+        *rest = tail;
+        Some(u32::from_le_bytes(*value))
+    };
+    let levels = read_u32(&mut rest)?;
+    let mut chain = Vec::new();
+    for _ in 0..levels {
+        let size = read_u32(&mut rest)? as usize * std::mem::size_of::<Vertex>();
+        let level = rest.get(..size)?;
+        // The file's bytes aren't aligned for Vertex
+        chain.push(level.chunks_exact(std::mem::size_of::<Vertex>()).map(bytemuck::pod_read_unaligned).collect());
+        rest = &rest[size..];
+    }
+    rest.is_empty().then_some(chain)
+}
+
+// Largest distance from either triangle list to the other, sampled at vertices, edge midpoints
+// and centers. An estimate from below, good enough to bound what simplification moved
+pub fn hausdorff_distance(a: &[Vec3], b: &[Vec3]) -> f32 {
+    one_sided_distance(a, b).max(one_sided_distance(b, a))
+}
+
+fn one_sided_distance(from: &[Vec3], to: &[Vec3]) -> f32 {
+    let mut samples = Vec::new();
+    for t in from.chunks_exact(3) {
+        samples.extend([t[0], t[1], t[2], (t[0] + t[1]) * 0.5, (t[1] + t[2]) * 0.5, (t[2] + t[0]) * 0.5, (t[0] + t[1] + t[2]) / 3.0]);
+    }
+    samples
+        .iter()
+        .map(|&p| to.chunks_exact(3).map(|t| (closest_on_triangle(p, t[0], t[1], t[2]) - p).length()).fold(f32::MAX, f32::min))
+        .fold(0.0, f32::max)
+}
+
+// Closest point to `p` on triangle abc (Ericson, Real-Time Collision Detection 5.1.5)
+fn closest_on_triangle(p: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+    let (ab, ac, ap) = (b - a, c - a, p - a);
+    let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+    let bp = p - b;
+    let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+    let cp = p - c;
+    let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+    let denominator = 1.0 / (va + vb + vc);
+    a + ab * (vb * denominator) + ac * (vc * denominator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives;
+
+    fn positions(vertices: &[Vertex]) -> Vec<Vec3> {
+        vertices.iter().map(|v| Vec3::from(v.position)).collect()
+    }
+
+    // Triangle list positions of `vertices` simplified to `target_ratio`
+    fn simplified(vertices: &[Vertex], target_ratio: f32) -> Vec<Vec3> {
+        let (indexed, indices) = mesh::index_vertices(vertices);
+        let (vertices, indices) = simplify(&indexed, &indices, &SimplifyOptions { target_ratio, ..Default::default() });
+        positions(&mesh::unindex(&vertices, &indices))
+    }
+
+    // Down to 25% it has to stay closed and within 6% of its radius of the original. A 16 x 8 uv
+    // sphere, about as many triangles built by hand, is 3.7% off
+    #[test]
+    fn sphere_at_a_quarter_stays_closed_and_close() {
+        let radius = 0.5;
+        let sphere = primitives::uv_sphere(radius, 32, 16);
+        let before = positions(&sphere);
+        let after = simplified(&sphere, 0.25);
+        let edges = mesh::edge_report(&after);
+        assert!(edges.is_watertight(), "{:?}", edges);
+        assert!(after.len() * 10 <= before.len() * 3, "Kept {} of {} triangles", after.len() / 3, before.len() / 3);
+        let distance = hausdorff_distance(&before, &after);
+        assert!(distance <= radius * 0.06, "Moved {:.4}, more than 6% of the radius", distance);
+    }
+
+    // Closed ones stay closed, the tube keeps its rims
+    #[test]
+    fn primitives_keep_their_topology() {
+        let cases = [
+            ("cylinder", primitives::cylinder(0.5, 1.0, 32, true).mesh()),
+            ("tube", primitives::cylinder(0.5, 1.0, 32, false).mesh()),
+            ("torus", primitives::torus(1.0, 0.25, 32, 16).mesh()),
+            ("capsule", primitives::capsule(0.5, 1.0, 32, 8).mesh()),
+        ];
+        for (name, vertices) in cases {
+            let before = mesh::edge_report(&positions(&vertices));
+            let after = simplified(&vertices, 0.25);
+            let edges = mesh::edge_report(&after);
+            assert_eq!(edges.open == 0, before.open == 0, "{}: edges went from {:?} to {:?}", name, before, edges);
+            assert!(edges.flipped == 0 && edges.non_manifold == 0, "{}: {:?}", name, edges);
+            assert!(after.len() * 10 <= vertices.len() * 3, "{}: kept more than 30% of the triangles", name);
+        }
+    }
+
+    // As far as it goes, a closed mesh has to end up as something closed
+    #[test]
+    fn all_the_way_stays_closed() {
+        for (name, vertices) in [
+            ("uv sphere", primitives::uv_sphere(0.5, 32, 16)),
+            ("cylinder", primitives::cylinder(0.5, 1.0, 32, true).mesh()),
+            ("torus", primitives::torus(1.0, 0.25, 32, 16).mesh()),
+            ("capsule", primitives::capsule(0.5, 1.0, 32, 8).mesh()),
+        ] {
+            let smallest = simplified(&vertices, 0.0);
+            let edges = mesh::edge_report(&smallest);
+            assert!(smallest.len() >= 12 && edges.is_watertight(), "{}: {} triangles with {:?}", name, smallest.len() / 3, edges);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use crate::math::Mat4;
+
+// Has to match MAX_BONES in shader.wgsl. Bones live in a uniform buffer (4 KiB) instead of a
+// storage buffer, WebGL2 can't read storage buffers in vertex shaders
+pub const MAX_BONES: usize = 64;
+
+// Bone matrices for GPU skinning, group 2 of shader.wgsl. Each vertex blends up to 4 of them
+// (Vertex::joints / Vertex::weights). Matrices go from bind pose model space to posed model
+// space, i.e. already multiplied by the inverse bind matrix. Unused bones stay identity
+pub struct BoneBuffer {
+    buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl BoneBuffer {
+    pub const LAYOUT_ENTRIES: &'static [wgpu::BindGroupLayoutEntry] = &[
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    ];
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bone Buffer"),
+            size: (MAX_BONES * std::mem::size_of::<[[f32; 4]; 4]>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bone Bind Group Layout"),
+            entries: Self::LAYOUT_ENTRIES,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bone Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }
+            ],
+        });
+
+        Self { buffer, bind_group_layout, bind_group }
+    }
+
+    // Bones past the end of `bones` are reset to identity
+    pub fn write(&self, queue: &wgpu::Queue, bones: &[Mat4]) {
+        assert!(bones.len() <= MAX_BONES, "{} bones, at most {} supported", bones.len(), MAX_BONES);
+        let mut data = [Mat4::IDENTITY.to_cols_array(); MAX_BONES];
+        for (slot, bone) in data.iter_mut().zip(bones) {
+            *slot = bone.to_cols_array();
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&data));
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        self.buffer.size()
+    }
+}
@@ -0,0 +1,139 @@
+use crate::pipeline::PipelineConfig;
+use crate::texture::{self, Texture};
+
+// Environment map drawn behind everything, sampled by view ray direction. Drawn first in the
+// opaque pass on the far plane, so the scene just draws over it. No tonemapping yet, HDR values
+// above 1 clip
+pub struct SkyRenderer {
+    bind_group_layout: wgpu::BindGroupLayout,
+    // None until an environment is set, draw() does nothing then
+    bind_group: Option<wgpu::BindGroup>,
+    // Size of the environment the bind group keeps alive, for gpu_memory
+    environment_bytes: u64,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl SkyRenderer {
+    pub fn new(device: &wgpu::Device, config: &PipelineConfig, camera_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Sky Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline = Self::create_pipeline(device, config, camera_bind_group_layout, &bind_group_layout);
+
+        Self {
+            bind_group_layout,
+            bind_group: None,
+            environment_bytes: 0,
+            pipeline,
+        }
+    }
+
+    // Call after the pipeline config changes
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        self.pipeline = Self::create_pipeline(device, config, camera_bind_group_layout, &self.bind_group_layout);
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("sky.wgsl"));
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sky Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_sky",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_sky",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: config.depth_format,
+                // Sits exactly on the cleared depth, anything drawn later is in front of it
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: config.multisample(),
+            multiview: None,
+        })
+    }
+
+    // `environment` is an equirectangular map, e.g. from Texture::load_hdr
+    pub fn set_environment(&mut self, device: &wgpu::Device, environment: &Texture) {
+        self.environment_bytes = texture::texture_bytes(&environment.texture);
+        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Sky Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&environment.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&environment.sampler),
+                },
+            ],
+        }));
+    }
+
+    pub fn clear_environment(&mut self) {
+        self.bind_group = None;
+        self.environment_bytes = 0;
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        let Some(bind_group) = &self.bind_group else { return };
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        self.environment_bytes
+    }
+}
@@ -0,0 +1,90 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    exposure: f32,
+    fog_mode: u32,
+    fog_start: f32,
+    fog_end: f32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    eye: vec3<f32>,
+    fog_height: f32,
+    forward: vec3<f32>,
+    fog_height_falloff: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Equirectangular environment map, see Texture::load_hdr
+@group(1) @binding(0)
+var t_environment: texture_2d<f32>;
+@group(1) @binding(1)
+var s_environment: sampler;
+
+const PI: f32 = 3.14159265;
+// Has to match the FOG_ constants in camera.rs
+const FOG_EXPONENTIAL_SQUARED: u32 = 3u;
+// How far the view ray goes for fog, far enough to count as infinite
+const SKY_DISTANCE: f32 = 1000000.0;
+
+// Direction -> equirectangular uv: u goes around the Y axis, v from +Y (0) to -Y (1)
+fn equirect_uv(direction: vec3<f32>) -> vec2<f32> {
+    let d = normalize(direction);
+    let u = atan2(d.z, d.x) / (2.0 * PI) + 0.5;
+    let v = acos(clamp(d.y, -1.0, 1.0)) / PI;
+    return vec2<f32>(u, v);
+}
+
+fn sample_environment(direction: vec3<f32>) -> vec3<f32> {
+    // Explicit level: u jumps from 1 to 0 behind the camera, implicit derivatives would pick
+    // the smallest mip along that seam
+    return textureSampleLevel(t_environment, s_environment, equirect_uv(direction), 0.0).rgb;
+}
+
+// Same as in shader.wgsl
+fn fog_amount(direction_y: f32, distance: f32) -> f32 {
+    let span = max(distance - camera.fog_start, 0.0);
+    let start_height = camera.eye.y + direction_y * camera.fog_start;
+    let density = camera.fog_density * exp(min(-camera.fog_height_falloff * (start_height - camera.fog_height), 80.0));
+    let k = camera.fog_height_falloff * direction_y;
+    if abs(k * span) < 0.0001 {
+        return density * span;
+    }
+    return density * (1.0 - exp(min(-k * span, 80.0))) / k;
+}
+
+// Height fog covers the horizon and thins out upwards, so distant geometry fades into the sky
+// instead of standing out against it. The other fog modes don't thin out, they'd cover all of it
+fn apply_fog(color: vec3<f32>, direction: vec3<f32>) -> vec3<f32> {
+    if camera.fog_mode != FOG_EXPONENTIAL_SQUARED {
+        return color;
+    }
+    let amount = fog_amount(normalize(direction).y, SKY_DISTANCE);
+    return mix(camera.fog_color * camera.exposure, color, exp(-amount * amount));
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Near and far plane points of the view ray, still homogeneous so they interpolate linearly
+    @location(0) near: vec4<f32>,
+    @location(1) far: vec4<f32>,
+}
+
+// One triangle that covers the whole screen, on the far plane
+@vertex
+fn vs_sky(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    let ndc = uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 1.0, 1.0);
+    out.near = camera.inv_view_proj * vec4<f32>(ndc, 0.0, 1.0);
+    out.far = camera.inv_view_proj * vec4<f32>(ndc, 1.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_sky(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = in.far.xyz / in.far.w - in.near.xyz / in.near.w;
+    return vec4<f32>(apply_fog(sample_environment(direction) * camera.exposure, direction), 1.0);
+}
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use wgpu::util::DeviceExt;
+
+use crate::buffer::DeferredDestruction;
+use crate::loader::{AssetLoader, LoadHandle, LoadedData};
+use crate::math::{perlin_2d, Vec3};
+use crate::pipeline::PipelineConfig;
+
+// Chunks asked for at once. Anything past that waits, so a teleport doesn't queue up a whole
+// world of chunks that are out of range again by the time they're generated
+const MAX_GENERATING: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StreamingSettings {
+    // World units per chunk side
+    pub chunk_size: f32,
+    // Quads per chunk side
+    pub resolution: u32,
+    // Chunks whose center is closer than this (on the ground plane) to the camera are kept
+    pub radius: f32,
+    // Vertex bytes written to the GPU per frame at most
+    pub upload_budget: u64,
+    // Peak to valley is about twice this
+    pub height: f32,
+}
+
+impl Default for StreamingSettings {
+    fn default() -> Self {
+        Self {
+            chunk_size: 32.0,
+            resolution: 32,
+            radius: 128.0,
+            upload_budget: 64 * 1024,
+            height: 8.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ChunkCoord {
+    pub x: i32,
+    pub z: i32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TerrainVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+}
+
+impl TerrainVertex {
+    pub(crate) fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TerrainVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Generated on the loader thread
+pub struct ChunkData {
+    pub coord: ChunkCoord,
+    pub vertices: Vec<TerrainVertex>,
+}
+
+// Height of the terrain at a world position, a few octaves of Perlin noise. Chunks sample it at
+// their shared edges, so they line up without knowing about each other
+pub fn terrain_height(x: f32, z: f32, height: f32, seed: u32) -> f32 {
+    let mut total = 0.0;
+    let mut amplitude = height;
+    let mut frequency = 1.0 / 64.0;
+    for octave in 0..4 {
+        total += perlin_2d(x * frequency, z * frequency, seed.wrapping_add(octave)) * amplitude;
+        amplitude *= 0.45;
+        frequency *= 2.1;
+    }
+    total
+}
+
+// Grid of (resolution + 1)^2 vertices, rows along x. Normals from the height function rather than
+// the grid, so they match across chunk edges too
+pub fn generate_chunk(coord: ChunkCoord, settings: &StreamingSettings, seed: u32) -> ChunkData {
+    let side = settings.resolution + 1;
+    let step = settings.chunk_size / settings.resolution as f32;
+    let height = |x: f32, z: f32| terrain_height(x, z, settings.height, seed);
+    let mut vertices = Vec::with_capacity((side * side) as usize);
+    for row in 0..side {
+        for column in 0..side {
+            let x = coord.x as f32 * settings.chunk_size + column as f32 * step;
+            let z = coord.z as f32 * settings.chunk_size + row as f32 * step;
+            let normal = Vec3::new(
+                height(x - step, z) - height(x + step, z),
+                2.0 * step,
+                height(x, z - step) - height(x, z + step),
+            ).normalize();
+            vertices.push(TerrainVertex { position: [x, height(x, z), z], normal: [normal.x, normal.y, normal.z] });
+        }
+    }
+    ChunkData { coord, vertices }
+}
+
+enum Chunk {
+    // Set `cancelled` when it goes out of range, the job is skipped if it hasn't started yet
+    Generating { handle: LoadHandle, cancelled: Arc<AtomicBool> },
+    // Vertex buffer filled a piece at a time, see upload_budget
+    Uploading { buffer: wgpu::Buffer, bytes: Vec<u8>, written: u64 },
+    Resident(wgpu::Buffer),
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamingStats {
+    pub resident: usize,
+    pub uploading: usize,
+    pub generating: usize,
+    // Buffers waiting to be destroyed, see DeferredDestruction
+    pub retired: usize,
+    pub uploaded_bytes: u64,
+    pub upload_budget: u64,
+}
+
+impl fmt::Display for StreamingStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "chunks: {} resident, {} uploading, {} generating, {} retired, upload {} / {} KiB",
+            self.resident,
+            self.uploading,
+            self.generating,
+            self.retired,
+            self.uploaded_bytes.div_ceil(1024),
+            self.upload_budget / 1024,
+        )
+    }
+}
+
+// Procedural terrain around the camera, streamed in and out in square chunks. Missing chunks in
+// range are generated on an AssetLoader thread, nearest first, and their vertices are written to
+// the GPU over as many frames as the upload budget takes. Chunks out of range hand their buffers
+// to the DeferredDestruction queue. Every chunk has the same grid, so they share one index buffer
+pub struct ChunkStreamer {
+    settings: StreamingSettings,
+    seed: u32,
+    loader: AssetLoader,
+    chunks: HashMap<ChunkCoord, Chunk>,
+    // Chunks that went out of range while generating map to None, their data is dropped
+    handles: HashMap<LoadHandle, Option<ChunkCoord>>,
+    index_buffer: wgpu::Buffer,
+    index_count: u32,
+    pipeline: wgpu::RenderPipeline,
+    stats: StreamingStats,
+}
+
+impl ChunkStreamer {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        settings: StreamingSettings,
+        seed: u32,
+    ) -> Self {
+        let settings = StreamingSettings { resolution: settings.resolution.clamp(1, 255), ..settings };
+        let side = settings.resolution + 1;
+        let indices = (0..settings.resolution)
+            .flat_map(|row| (0..settings.resolution).map(move |column| row * side + column))
+            .flat_map(|i| [i, i + side, i + 1, i + 1, i + side, i + side + 1])
+            .map(|i| i as u16)
+            .collect::<Vec<_>>();
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Terrain Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            settings,
+            seed,
+            loader: AssetLoader::new(),
+            chunks: HashMap::new(),
+            handles: HashMap::new(),
+            index_buffer,
+            index_count: indices.len() as u32,
+            pipeline: Self::create_pipeline(device, config, camera_bind_group_layout),
+            stats: StreamingStats::default(),
+        }
+    }
+
+    // Call after the pipeline config changes
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device, config: &PipelineConfig, camera_bind_group_layout: &wgpu::BindGroupLayout) {
+        self.pipeline = Self::create_pipeline(device, config, camera_bind_group_layout);
+    }
+
+    fn create_pipeline(device: &wgpu::Device, config: &PipelineConfig, camera_bind_group_layout: &wgpu::BindGroupLayout) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("terrain.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Terrain Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Terrain Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_terrain",
+                buffers: &[TerrainVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_terrain",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(config.depth_state()),
+            multisample: config.multisample(),
+            multiview: None,
+        })
+    }
+
+    pub fn settings(&self) -> &StreamingSettings {
+        &self.settings
+    }
+
+    pub fn stats(&self) -> StreamingStats {
+        self.stats
+    }
+
+    fn coord_of(&self, position: Vec3) -> ChunkCoord {
+        ChunkCoord {
+            x: (position.x / self.settings.chunk_size).floor() as i32,
+            z: (position.z / self.settings.chunk_size).floor() as i32,
+        }
+    }
+
+    // Squared distance on the ground plane from `position` to the chunk's center
+    fn distance_squared(&self, coord: ChunkCoord, position: Vec3) -> f32 {
+        let dx = (coord.x as f32 + 0.5) * self.settings.chunk_size - position.x;
+        let dz = (coord.z as f32 + 0.5) * self.settings.chunk_size - position.z;
+        dx * dx + dz * dz
+    }
+
+    // Once per frame: drops chunks out of range, asks for missing ones in range and spends the
+    // upload budget on the nearest chunks that are generated
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, deferred: &mut DeferredDestruction, camera: Vec3) {
+        let radius_squared = self.settings.radius * self.settings.radius;
+
+        let out_of_range = self.chunks
+            .keys()
+            .copied()
+            .filter(|&coord| self.distance_squared(coord, camera) > radius_squared)
+            .collect::<Vec<_>>();
+        for coord in out_of_range {
+            match self.chunks.remove(&coord) {
+                Some(Chunk::Generating { handle, cancelled }) => {
+                    cancelled.store(true, Ordering::Relaxed);
+                    self.handles.insert(handle, None);
+                }
+                Some(Chunk::Uploading { buffer, .. } | Chunk::Resident(buffer)) => deferred.retire(buffer),
+                None => {}
+            }
+        }
+
+        for (handle, result) in self.loader.poll() {
+            let coord = self.handles.remove(&handle).flatten();
+            match (coord, result) {
+                (Some(coord), Ok(LoadedData::Chunk(data))) => {
+                    let bytes = bytemuck::cast_slice(&data.vertices).to_vec();
+                    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("Terrain Chunk Buffer"),
+                        size: bytes.len() as wgpu::BufferAddress,
+                        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    });
+                    self.chunks.insert(coord, Chunk::Uploading { buffer, bytes, written: 0 });
+                }
+                (Some(coord), Err(e)) => {
+                    // Asked for again next frame
+                    log::warn!("Couldn't generate terrain chunk {:?}: {}", coord, e);
+                    self.chunks.remove(&coord);
+                }
+                _ => {}
+            }
+        }
+
+        // Missing chunks in range, nearest first
+        let reach = (self.settings.radius / self.settings.chunk_size).ceil() as i32 + 1;
+        let center = self.coord_of(camera);
+        let mut missing = (-reach..=reach)
+            .flat_map(|z| (-reach..=reach).map(move |x| ChunkCoord { x: center.x + x, z: center.z + z }))
+            .filter(|coord| !self.chunks.contains_key(coord) && self.distance_squared(*coord, camera) <= radius_squared)
+            .collect::<Vec<_>>();
+        missing.sort_by(|a, b| self.distance_squared(*a, camera).total_cmp(&self.distance_squared(*b, camera)));
+        let generating = self.chunks.values().filter(|chunk| matches!(chunk, Chunk::Generating { .. })).count();
+        for coord in missing.into_iter().take(MAX_GENERATING.saturating_sub(generating)) {
+            let (settings, seed) = (self.settings, self.seed);
+            let cancelled = Arc::new(AtomicBool::new(false));
+            let job_cancelled = cancelled.clone();
+            let handle = self.loader.generate(move || {
+                if job_cancelled.load(Ordering::Relaxed) {
+                    return Err("Cancelled".to_string());
+                }
+                Ok(LoadedData::Chunk(generate_chunk(coord, &settings, seed)))
+            });
+            self.handles.insert(handle, Some(coord));
+            self.chunks.insert(coord, Chunk::Generating { handle, cancelled });
+        }
+
+        // Writes have to be multiples of 4 bytes, and the budget has to allow at least that
+        let budget = self.settings.upload_budget.max(wgpu::COPY_BUFFER_ALIGNMENT);
+        let mut uploaded = 0;
+        let mut uploading = self.chunks
+            .iter()
+            .filter(|(_, chunk)| matches!(chunk, Chunk::Uploading { .. }))
+            .map(|(&coord, _)| coord)
+            .collect::<Vec<_>>();
+        uploading.sort_by(|a, b| self.distance_squared(*a, camera).total_cmp(&self.distance_squared(*b, camera)));
+        for coord in uploading {
+            if uploaded + wgpu::COPY_BUFFER_ALIGNMENT > budget {
+                break;
+            }
+            let Some(Chunk::Uploading { buffer, bytes, written }) = self.chunks.get_mut(&coord) else { continue };
+            let remaining = bytes.len() as u64 - *written;
+            let size = remaining.min((budget - uploaded) / wgpu::COPY_BUFFER_ALIGNMENT * wgpu::COPY_BUFFER_ALIGNMENT);
+            queue.write_buffer(buffer, *written, &bytes[*written as usize..(*written + size) as usize]);
+            *written += size;
+            uploaded += size;
+            if *written == bytes.len() as u64 {
+                if let Some(Chunk::Uploading { buffer, .. }) = self.chunks.remove(&coord) {
+                    self.chunks.insert(coord, Chunk::Resident(buffer));
+                }
+            }
+        }
+
+        let count = |f: fn(&Chunk) -> bool| self.chunks.values().filter(|chunk| f(chunk)).count();
+        self.stats = StreamingStats {
+            resident: count(|chunk| matches!(chunk, Chunk::Resident(_))),
+            uploading: count(|chunk| matches!(chunk, Chunk::Uploading { .. })),
+            generating: count(|chunk| matches!(chunk, Chunk::Generating { .. })),
+            retired: deferred.len(),
+            uploaded_bytes: uploaded,
+            upload_budget: budget,
+        };
+    }
+
+    // Hands every chunk buffer to `deferred`, for when the streamer goes away
+    pub fn retire_all(self, deferred: &mut DeferredDestruction) {
+        for chunk in self.chunks.into_values() {
+            if let Chunk::Uploading { buffer, .. } | Chunk::Resident(buffer) = chunk {
+                deferred.retire(buffer);
+            }
+        }
+    }
+
+    // Resident chunks only, the ones still uploading would show half written vertices
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        for chunk in self.chunks.values() {
+            if let Chunk::Resident(buffer) = chunk {
+                render_pass.set_vertex_buffer(0, buffer.slice(..));
+                render_pass.draw_indexed(0..self.index_count, 0, 0..1);
+            }
+        }
+    }
+
+    // Chunk buffers, uploading ones included, and the index buffer. Retired buffers are counted by
+    // DeferredDestruction
+    pub fn gpu_memory(&self) -> u64 {
+        self.index_buffer.size()
+            + self.chunks
+                .values()
+                .map(|chunk| match chunk {
+                    Chunk::Generating { .. } => 0,
+                    Chunk::Uploading { buffer, .. } | Chunk::Resident(buffer) => buffer.size(),
+                })
+                .sum::<u64>()
+    }
+}
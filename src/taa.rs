@@ -0,0 +1,255 @@
+use crate::math::{Mat4, Vec3};
+use crate::reflection::{ReflectedLayout, ShaderReflection};
+use crate::texture;
+
+// Halton points per jitter cycle, 8 is the usual tradeoff between coverage and how fast the
+// history converges after it was reset
+pub const JITTER_PHASES: u32 = 8;
+// Share of the current frame in the resolved color, the rest comes from the history
+pub const CURRENT_FRAME_WEIGHT: f32 = 0.1;
+pub const HISTORY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// Radical inverse of `index` in `base`, 0..1. Bases 2 and 3 together give well spread 2D points
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+// Subpixel offset of frame `frame` in NDC units for a width x height target, within half a pixel
+// of the center. Halton starts at 1, index 0 would be (0, 0) every cycle
+pub fn jitter_offset(frame: u32, width: u32, height: u32) -> [f32; 2] {
+    let index = frame % JITTER_PHASES + 1;
+    let x = halton(index, 2) - 0.5;
+    let y = halton(index, 3) - 0.5;
+    // A pixel is 2 / size in NDC
+    [x * 2.0 / width as f32, y * 2.0 / height as f32]
+}
+
+// Moves everything `view_proj` draws by `offset` NDC units, in clip space so it's the same shift at
+// every depth
+pub fn jittered(view_proj: Mat4, offset: [f32; 2]) -> Mat4 {
+    Mat4::translation(Vec3::new(offset[0], offset[1], 0.0)) * view_proj
+}
+
+// Layout must match ResolveUniform in taa.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ResolveUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    prev_view_proj: [[f32; 4]; 4],
+    current_weight: f32,
+    _padding: [f32; 3],
+}
+
+// Resolved frames, read from one and written to the other every frame
+struct History {
+    views: [wgpu::TextureView; 2],
+    // Index of the one holding last frame's result
+    read: usize,
+    bytes: u64,
+}
+
+// Temporal anti-aliasing. Every frame the projection gets a different subpixel offset (Halton 2, 3)
+// and the resolve blends the frame into a history reprojected to where each pixel was last frame,
+// using the VelocityPass output for the mesh and depth + camera motion for everything else. The
+// history is clamped to the current frame's 3x3 neighborhood first, so disocclusions and changed
+// shading don't ghost. The caller jitters the camera uniform with jitter() / jittered() and calls
+// prepare() and end_frame() around the resolve. Only for a single full window view
+pub struct TaaRenderer {
+    frame: u32,
+    // False until a frame was resolved into the history, the first one after a reset takes the
+    // current frame as is
+    history_valid: bool,
+    history: Option<History>,
+    resolve_buffer: wgpu::Buffer,
+    resolve_bind_group_layout: ReflectedLayout,
+    resolve_pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+}
+
+impl TaaRenderer {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat) -> Self {
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("TAA Resolve Buffer"),
+            size: std::mem::size_of::<ResolveUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let resolve_bind_group_layout = ShaderReflection::new("TAA Resolve", include_str!("taa.wgsl"))
+            .and_then(|shader| shader.layout(device, 0))
+            .unwrap_or_else(|e| panic!("{}", e));
+        let resolve_pipeline = Self::create_resolve_pipeline(device, color_format, &resolve_bind_group_layout);
+        // History is sampled between texels, reprojected positions don't land on centers
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("TAA History Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            frame: 0,
+            history_valid: false,
+            history: None,
+            resolve_buffer,
+            resolve_bind_group_layout,
+            resolve_pipeline,
+            sampler,
+        }
+    }
+
+    // Call after the surface format changes
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device, color_format: wgpu::TextureFormat) {
+        self.resolve_pipeline = Self::create_resolve_pipeline(device, color_format, &self.resolve_bind_group_layout);
+    }
+
+    fn create_resolve_pipeline(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        bind_group_layout: &ReflectedLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("taa.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("TAA Resolve Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout.layout],
+            push_constant_ranges: &[],
+        });
+        let target = |format| Some(wgpu::ColorTargetState {
+            format,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("TAA Resolve Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_resolve",
+                targets: &[target(color_format), target(HISTORY_FORMAT)],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // Allocates the history, None frees it while TAA is off
+    pub fn set_size(&mut self, device: &wgpu::Device, size: Option<(u32, u32)>) {
+        self.history = size.map(|(width, height)| {
+            let [a, b] = [0, 1].map(|_| device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("TAA History Texture"),
+                size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: HISTORY_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            }));
+            let bytes = texture::texture_bytes(&a) + texture::texture_bytes(&b);
+            History {
+                views: [a, b].map(|t| t.create_view(&wgpu::TextureViewDescriptor::default())),
+                read: 0,
+                bytes,
+            }
+        });
+        self.invalidate();
+    }
+
+    // Throws the history away, the next frame starts over without blending. For anything that
+    // makes last frame meaningless: resizes, scene switches, instance changes, camera cuts
+    pub fn invalidate(&mut self) {
+        self.history_valid = false;
+    }
+
+    // NDC offset to jitter this frame's projection by, see jittered()
+    pub fn jitter(&self, width: u32, height: u32) -> [f32; 2] {
+        jitter_offset(self.frame, width, height)
+    }
+
+    // Writes the uniform for this frame. `prev_view_proj` is VelocityPass::prepare's result,
+    // `jittered_view_proj` what the scene was drawn with
+    pub fn prepare(&mut self, queue: &wgpu::Queue, prev_view_proj: Option<Mat4>, jittered_view_proj: Mat4) {
+        // No camera motion to reproject with
+        if prev_view_proj.is_none() {
+            self.invalidate();
+        }
+        let resolve = ResolveUniform {
+            inv_view_proj: jittered_view_proj.inverse().to_cols_array(),
+            prev_view_proj: prev_view_proj.unwrap_or(jittered_view_proj).to_cols_array(),
+            current_weight: if self.history_valid { CURRENT_FRAME_WEIGHT } else { 1.0 },
+            _padding: [0.0; 3],
+        };
+        queue.write_buffer(&self.resolve_buffer, 0, bytemuck::cast_slice(&[resolve]));
+    }
+
+    // Resolves `scene` into `target` (color_format) and the history. `depth` is the scene's depth
+    // texture, single sampled
+    pub fn resolve(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        scene: &wgpu::TextureView,
+        velocity: &wgpu::TextureView,
+        depth: &wgpu::Texture,
+        target: &wgpu::TextureView,
+    ) {
+        let history = self.history.as_ref().expect("TAA resolve without a history, see set_size");
+        // Stencil can't be bound along with depth
+        let depth_view = depth.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+        let bind_group = self.resolve_bind_group_layout
+            .create_bind_group(device, &[
+                ("t_current".into(), wgpu::BindingResource::TextureView(scene)),
+                ("t_history".into(), wgpu::BindingResource::TextureView(&history.views[history.read])),
+                ("t_velocity".into(), wgpu::BindingResource::TextureView(velocity)),
+                ("t_depth".into(), wgpu::BindingResource::TextureView(&depth_view)),
+                ("s_linear".into(), wgpu::BindingResource::Sampler(&self.sampler)),
+                ("resolve".into(), self.resolve_buffer.as_entire_binding()),
+            ])
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        // Every pixel gets overwritten
+        let ops = wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::BLACK), store: true };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("TAA Resolve Pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment { view: target, resolve_target: None, ops }),
+                Some(wgpu::RenderPassColorAttachment { view: &history.views[1 - history.read], resolve_target: None, ops }),
+            ],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.resolve_pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // After the resolve was recorded, flips the history
+    pub fn end_frame(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.read = 1 - history.read;
+        }
+        self.history_valid = true;
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        self.history.as_ref().map_or(0, |history| history.bytes)
+    }
+}
@@ -0,0 +1,91 @@
+// TAA resolve, see taa.rs. Blends this frame with the reprojected history, the history is clamped
+// to the current frame's 3x3 neighborhood so disoccluded and changed pixels don't ghost
+
+// Layout must match ResolveUniform in taa.rs
+struct ResolveUniform {
+    // Of this frame with jitter, the depth buffer was drawn with it
+    inv_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+    // Weight of the current frame, 1 without a valid history
+    current_weight: f32,
+}
+
+@group(0) @binding(0)
+var t_current: texture_2d<f32>;
+@group(0) @binding(1)
+var t_history: texture_2d<f32>;
+@group(0) @binding(2)
+var t_velocity: texture_2d<f32>;
+// Depth aspect as a plain float texture, GL can't read depth textures without a comparison
+@group(0) @binding(3)
+var t_depth: texture_2d<f32>;
+@group(0) @binding(4)
+var s_linear: sampler;
+@group(0) @binding(5)
+var<uniform> resolve: ResolveUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle that covers the whole screen, same as blit.wgsl
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+struct ResolveOutput {
+    // Surface, and the history for the next frame
+    @location(0) color: vec4<f32>,
+    @location(1) history: vec4<f32>,
+}
+
+// Where the pixel was last frame, from the mesh's velocity or else the camera motion alone
+fn previous_uv(uv: vec2<f32>, pixel: vec2<i32>) -> vec2<f32> {
+    let velocity = textureLoad(t_velocity, pixel, 0);
+    if velocity.a > 0.0 {
+        return uv - velocity.xy;
+    }
+    let depth = textureLoad(t_depth, pixel, 0).r;
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    let world = resolve.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    let previous = resolve.prev_view_proj * vec4<f32>(world.xyz / world.w, 1.0);
+    let previous_ndc = previous.xy / previous.w;
+    return vec2<f32>(previous_ndc.x * 0.5 + 0.5, 0.5 - previous_ndc.y * 0.5);
+}
+
+@fragment
+fn fs_resolve(in: VertexOutput) -> ResolveOutput {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let size = vec2<i32>(textureDimensions(t_current));
+    let current = textureLoad(t_current, pixel, 0).rgb;
+
+    var neighborhood_min = current;
+    var neighborhood_max = current;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbor = textureLoad(t_current, clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), size - 1), 0).rgb;
+            neighborhood_min = min(neighborhood_min, neighbor);
+            neighborhood_max = max(neighborhood_max, neighbor);
+        }
+    }
+
+    let history_uv = previous_uv(in.uv, pixel);
+    var weight = resolve.current_weight;
+    // Came from off screen, nothing to blend with
+    if any(history_uv < vec2<f32>(0.0)) || any(history_uv > vec2<f32>(1.0)) {
+        weight = 1.0;
+    }
+    let history = clamp(textureSampleLevel(t_history, s_linear, history_uv, 0.0).rgb, neighborhood_min, neighborhood_max);
+    let color = mix(history, current, weight);
+
+    var out: ResolveOutput;
+    out.color = vec4<f32>(color, 1.0);
+    out.history = vec4<f32>(color, 1.0);
+    return out;
+}
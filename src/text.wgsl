@@ -0,0 +1,48 @@
+// Text overlay, see TextOverlay in text.rs. One instance per glyph or rectangle
+
+// Layout must match Quad in text.rs
+struct QuadInput {
+    // Left, top, right, bottom in clip space
+    @location(0) rect: vec4<f32>,
+    @location(1) color: vec4<f32>,
+    // Lit pixels of the 5x7 glyph, bit row * 5 + column
+    @location(2) bits: vec2<u32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // 0..5 across, 0..7 down
+    @location(0) glyph_position: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) bits: vec2<u32>,
+}
+
+@vertex
+fn vs_quad(@builtin(vertex_index) in_vertex_index: u32, quad: QuadInput) -> VertexOutput {
+    // Two triangles, corners in 0..1 from the top left
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0), vec2<f32>(1.0, 0.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0), vec2<f32>(1.0, 1.0), vec2<f32>(0.0, 1.0),
+    );
+    let corner = corners[in_vertex_index];
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(mix(quad.rect.xy, quad.rect.zw, corner), 0.0, 1.0);
+    out.glyph_position = corner * vec2<f32>(5.0, 7.0);
+    out.color = quad.color;
+    out.bits = quad.bits;
+    return out;
+}
+
+@fragment
+fn fs_quad(in: VertexOutput) -> @location(0) vec4<f32> {
+    let cell = min(vec2<u32>(in.glyph_position), vec2<u32>(4u, 6u));
+    let bit = cell.y * 5u + cell.x;
+    var word = in.bits.x;
+    if bit >= 32u {
+        word = in.bits.y;
+    }
+    if ((word >> (bit % 32u)) & 1u) == 0u {
+        discard;
+    }
+    return in.color;
+}
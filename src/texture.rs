@@ -0,0 +1,1189 @@
+use wgpu::util::DeviceExt;
+
+pub struct Texture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+}
+
+// Most precise first. All of them are required render attachment formats, select_depth_format's
+// check is only a safety net. Depth24PlusStencil8 is the one with a stencil aspect, picked when
+// stencil is asked for; otherwise Depth32Float wins and the rest are smaller fallbacks
+const DEPTH_FORMAT_PREFERENCE: [wgpu::TextureFormat; 4] = [
+    wgpu::TextureFormat::Depth32Float,
+    wgpu::TextureFormat::Depth24PlusStencil8,
+    wgpu::TextureFormat::Depth24Plus,
+    wgpu::TextureFormat::Depth16Unorm,
+];
+
+// View format aliasing: a texture created with `view_formats` can be viewed as any of them instead
+// of its own format. WebGPU only allows formats that differ in sRGB-ness, e.g. Rgba8Unorm and
+// Rgba8UnormSrgb, or Bgra8UnormSrgb and Bgra8Unorm. The texel bits are the same, only reads decode
+// and writes encode the sRGB curve (or don't). Surfaces additionally need
+// DownlevelFlags::SURFACE_VIEW_FORMATS for non-empty view_formats
+pub fn is_view_format_compatible(texture_format: wgpu::TextureFormat, view_format: wgpu::TextureFormat) -> bool {
+    texture_format.remove_srgb_suffix() == view_format.remove_srgb_suffix()
+}
+
+// View of `texture` as `format`, which has to be the texture's format or one of its view_formats
+pub fn create_view_as(texture: &wgpu::Texture, format: wgpu::TextureFormat) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        format: Some(format),
+        ..Default::default()
+    })
+}
+
+// View of a single mip level of one array layer (or cube face), for rendering into it: render pass
+// attachments have to be exactly one mip and one layer. Don't sample it expecting only that mip,
+// GL ignores the range there, see Blitter::generate_mipmaps
+pub fn create_mip_view(texture: &wgpu::Texture, mip_level: u32, layer: u32) -> wgpu::TextureView {
+    assert!(mip_level < texture.mip_level_count(), "Mip {} out of {}", mip_level, texture.mip_level_count());
+    assert!(layer < texture.depth_or_array_layers(), "Layer {} out of {}", layer, texture.depth_or_array_layers());
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("Mip View"),
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        base_mip_level: mip_level,
+        mip_level_count: Some(1),
+        base_array_layer: layer,
+        array_layer_count: Some(1),
+        ..Default::default()
+    })
+}
+
+// Mips in a full chain down to 1x1
+pub fn full_mip_count(width: u32, height: u32) -> u32 {
+    u32::BITS - width.max(height).max(1).leading_zeros()
+}
+
+// How a texture's 8 bit values turn into what shaders read. Color (albedo, emissive, UI) is
+// authored with the sRGB curve and has to be decoded to linear when sampled, data (normals,
+// roughness, masks) already is the value. Getting it wrong either way still "works", colors just
+// come out washed out or too dark and normals bent, see State::set_color_space_split
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    // 8 bit RGBA format that samples as this
+    pub fn format(self) -> wgpu::TextureFormat {
+        match self {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+
+    pub fn of_format(format: wgpu::TextureFormat) -> Self {
+        if format.is_srgb() {
+            ColorSpace::Srgb
+        } else {
+            ColorSpace::Linear
+        }
+    }
+
+    pub fn opposite(self) -> Self {
+        match self {
+            ColorSpace::Srgb => ColorSpace::Linear,
+            ColorSpace::Linear => ColorSpace::Srgb,
+        }
+    }
+
+    // What a glTF 2.0 material texture slot (core and the common KHR_materials extensions) holds,
+    // by its JSON name. Only the color slots are sRGB. None for slots it doesn't know
+    pub fn of_gltf_texture(slot: &str) -> Option<Self> {
+        match slot {
+            "baseColorTexture" | "emissiveTexture" | "diffuseTexture" | "specularGlossinessTexture"
+            | "specularColorTexture" | "sheenColorTexture" => Some(ColorSpace::Srgb),
+            "metallicRoughnessTexture" | "normalTexture" | "occlusionTexture" | "clearcoatTexture"
+            | "clearcoatRoughnessTexture" | "clearcoatNormalTexture" | "transmissionTexture"
+            | "thicknessTexture" | "specularTexture" | "sheenRoughnessTexture" | "iridescenceTexture"
+            | "iridescenceThicknessTexture" | "anisotropyTexture" => Some(ColorSpace::Linear),
+            _ => None,
+        }
+    }
+}
+
+// Rough memory use of a texture: texel blocks times block size over every mip, layer and sample.
+// Drivers add padding, alignment and compression on top, so it's an estimate, not an exact count
+pub fn estimated_bytes(
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    layers: u32,
+    mip_levels: u32,
+    sample_count: u32,
+) -> u64 {
+    // Combined depth stencil formats and Depth24Plus have no defined size, 4 bytes is typical
+    let block_bytes = format.block_size(None).unwrap_or(match format {
+        wgpu::TextureFormat::Depth32FloatStencil8 => 8,
+        _ => 4,
+    }) as u64;
+    let (block_width, block_height) = format.block_dimensions();
+    let per_layer = (0..mip_levels)
+        .map(|mip| {
+            let blocks_x = (width >> mip).max(1).div_ceil(block_width) as u64;
+            let blocks_y = (height >> mip).max(1).div_ceil(block_height) as u64;
+            blocks_x * blocks_y * block_bytes
+        })
+        .sum::<u64>();
+    per_layer * layers as u64 * sample_count as u64
+}
+
+pub fn texture_bytes(texture: &wgpu::Texture) -> u64 {
+    estimated_bytes(
+        texture.format(),
+        texture.width(),
+        texture.height(),
+        texture.depth_or_array_layers(),
+        texture.mip_level_count(),
+        texture.sample_count(),
+    )
+}
+
+// First format of the preference list the adapter can render depth into.
+// With `stencil` only formats that also have a stencil aspect are considered
+pub fn select_depth_format(adapter: &wgpu::Adapter, stencil: bool) -> wgpu::TextureFormat {
+    DEPTH_FORMAT_PREFERENCE.iter()
+        .copied()
+        .filter(|format| !stencil || format.has_stencil_aspect())
+        .find(|format| {
+            adapter.get_texture_format_features(*format)
+                .allowed_usages
+                .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+        })
+        // Both are required by the spec, can't really be missing
+        .unwrap_or(if stencil {
+            wgpu::TextureFormat::Depth24PlusStencil8
+        } else {
+            wgpu::TextureFormat::Depth24Plus
+        })
+}
+
+impl Texture {
+    // Decodes a PNG and uploads it to be sampled in `color_space`. Images bigger than
+    // max_dimension on either side are downscaled to fit (keeping aspect ratio) instead of failing
+    pub fn from_png_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        color_space: ColorSpace,
+        max_dimension: u32,
+        label: &str,
+    ) -> Result<Self, png::DecodingError> {
+        let (rgba, width, height) = decode_png_rgba(bytes)?;
+        Ok(Self::from_rgba_limited(device, queue, &rgba, width, height, color_space, max_dimension, label))
+    }
+
+    // from_rgba_in with from_png_bytes' downscaling, for pixels decoded elsewhere (see loader.rs)
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_rgba_limited(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &[u8],
+        width: u32,
+        height: u32,
+        color_space: ColorSpace,
+        max_dimension: u32,
+        label: &str,
+    ) -> Self {
+        if width <= max_dimension && height <= max_dimension {
+            return Self::from_rgba_in(device, queue, rgba, width, height, color_space, label);
+        }
+
+        let scale = max_dimension as f32 / width.max(height) as f32;
+        let new_width = ((width as f32 * scale).round() as u32).clamp(1, max_dimension);
+        let new_height = ((height as f32 * scale).round() as u32).clamp(1, max_dimension);
+        log::warn!(
+            "Texture '{}' is {}x{}, over the {} limit, downscaling to {}x{}",
+            label, width, height, max_dimension, new_width, new_height
+        );
+        let rgba = downscale_rgba(rgba, width, height, new_width, new_height);
+
+        Self::from_rgba_in(device, queue, &rgba, new_width, new_height, color_space, label)
+    }
+
+    // Color, see ColorSpace
+    pub fn from_rgba(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &[u8],
+        width: u32,
+        height: u32,
+        label: &str,
+    ) -> Self {
+        Self::from_rgba_in(device, queue, rgba, width, height, ColorSpace::Srgb, label)
+    }
+
+    // For data that isn't color (normal maps, roughness, ...), sampled as is without the sRGB curve
+    pub fn from_rgba_linear(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &[u8],
+        width: u32,
+        height: u32,
+        label: &str,
+    ) -> Self {
+        Self::from_rgba_in(device, queue, rgba, width, height, ColorSpace::Linear, label)
+    }
+
+    pub fn from_rgba_in(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &[u8],
+        width: u32,
+        height: u32,
+        color_space: ColorSpace,
+        label: &str,
+    ) -> Self {
+        let format = color_space.format();
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            // COPY_DST - to copy image data into it, COPY_SRC for reinterpreted()
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            rgba,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+
+        Self::with_default_sampler(device, texture)
+    }
+
+    fn with_default_sampler(device: &wgpu::Device, texture: wgpu::Texture) -> Self {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self { texture, view, sampler }
+    }
+
+    // Of an 8 bit RGBA texture, see ColorSpace
+    pub fn color_space(&self) -> ColorSpace {
+        ColorSpace::of_format(self.texture.format())
+    }
+
+    // Copy of an from_rgba_in texture with the same bytes sampled in the other color space.
+    // Copies between formats that only differ in sRGB are allowed, view formats would need a
+    // downlevel flag GL doesn't have
+    pub fn reinterpreted(&self, device: &wgpu::Device, queue: &wgpu::Queue, label: &str) -> Self {
+        let texture = reinterpret(device, queue, &self.texture, label);
+        Self::with_default_sampler(device, texture)
+    }
+
+    // Radiance .hdr file as an Rgba16Float equirectangular environment map: u wraps around the
+    // horizon, v goes from straight up (0) to straight down (1). See sky.wgsl for sampling it
+    pub fn load_hdr(device: &wgpu::Device, queue: &wgpu::Queue, path: &std::path::Path) -> Result<Self, String> {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+        if extension == "exr" {
+            // No EXR decoder in the dependencies, it's a much bigger format than Radiance
+            return Err(format!("{}: OpenEXR isn't supported, convert it to .hdr", path.display()));
+        }
+        let bytes = std::fs::read(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+        let label = path.to_string_lossy();
+        Self::from_hdr_bytes(device, queue, &bytes, &label).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn from_hdr_bytes(device: &wgpu::Device, queue: &wgpu::Queue, bytes: &[u8], label: &str) -> Result<Self, String> {
+        let (rgba, width, height) = decode_hdr_rgba(bytes)?;
+        Self::from_hdr_rgba(device, queue, &rgba, width, height, label)
+    }
+
+    // from_rgba_f32, failing instead of going over the texture size limit
+    pub fn from_hdr_rgba(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &[f32],
+        width: u32,
+        height: u32,
+        label: &str,
+    ) -> Result<Self, String> {
+        let max = device.limits().max_texture_dimension_2d;
+        if width > max || height > max {
+            return Err(format!("{}x{} is over the {} texture size limit", width, height, max));
+        }
+        Ok(Self::from_rgba_f32(device, queue, rgba, width, height, label))
+    }
+
+    // Linear HDR color, 4 floats per pixel. Same format and sampler as from_hdr_bytes
+    pub fn from_rgba_f32(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &[f32],
+        width: u32,
+        height: u32,
+        label: &str,
+    ) -> Self {
+        // Rgba32Float isn't filterable without an extra feature, half floats are plenty for color
+        let half = rgba.iter().map(|&v| f32_to_f16(v)).collect::<Vec<_>>();
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba16Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            bytemuck::cast_slice(&half),
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            // Wraps around horizontally, poles don't
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self { texture, view, sampler }
+    }
+
+    // Overwrites a sub-rectangle of mip 0. `data` holds size.0 x size.1 pixels, rows tightly packed
+    pub fn write_region(&self, queue: &wgpu::Queue, origin: (u32, u32), size: (u32, u32), data: &[u8]) {
+        let bytes_per_pixel = self.texture.format().block_size(None).unwrap_or(4);
+        self.write_region_strided(queue, origin, size, data, 0, size.0 * bytes_per_pixel);
+    }
+
+    // Same as write_region, but the rect is read out of a bigger image: starting at `offset`,
+    // with `bytes_per_row` between rows. Unlike buffer -> texture copies, queue.write_texture
+    // doesn't need the 256 byte row alignment, so any stride works
+    pub fn write_region_strided(
+        &self,
+        queue: &wgpu::Queue,
+        origin: (u32, u32),
+        size: (u32, u32),
+        data: &[u8],
+        offset: u64,
+        bytes_per_row: u32,
+    ) {
+        if size.0 == 0 || size.1 == 0 {
+            return;
+        }
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: origin.0, y: origin.1, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            data,
+            wgpu::ImageDataLayout {
+                offset,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: Some(size.1),
+            },
+            wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    pub fn create_depth_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        // Depth texture has to be the same size (and sample count) as the color target
+        Self::create_depth_texture_sized(device, config.width, config.height, format, sample_count, label)
+    }
+
+    // For offscreen targets that aren't screen sized
+    pub fn create_depth_texture_sized(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            // RENDER_ATTACHMENT to render into it, TEXTURE_BINDING to be able to sample it later
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            // Needed for sampling with a comparison (e.g. shadows)
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 100.0,
+            ..Default::default()
+        });
+
+        Self { texture, view, sampler }
+    }
+}
+
+// Any PNG color type -> tightly packed RGBA8
+pub(crate) fn decode_png_rgba(bytes: &[u8]) -> Result<(Vec<u8>, u32, u32), png::DecodingError> {
+    let mut decoder = png::Decoder::new(bytes);
+    // Palette -> RGB, low bit depths -> 8 bit, 16 bit -> 8 bit
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+    data.truncate(info.buffer_size());
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => data,
+        png::ColorType::Rgb => data.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => data.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => data.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        // Expanded by normalize_to_color8
+        png::ColorType::Indexed => unreachable!(),
+    };
+
+    Ok((rgba, info.width, info.height))
+}
+
+// Radiance RGBE (.hdr) -> linear RGBA f32, alpha 1. Only the usual -Y H +X W orientation and
+// flat or new style run length encoded scanlines (what every current tool writes)
+pub(crate) fn decode_hdr_rgba(bytes: &[u8]) -> Result<(Vec<f32>, u32, u32), String> {
+    let mut pos = 0;
+    let mut next_line = || -> Option<&[u8]> {
+        let start = pos;
+        let end = start + bytes.get(start..)?.iter().position(|&b| b == b'\n')?;
+        pos = end + 1;
+        Some(&bytes[start..end])
+    };
+
+    let magic = next_line().ok_or("Empty file")?;
+    if !magic.starts_with(b"#?") {
+        return Err("Not a Radiance HDR file".to_string());
+    }
+    loop {
+        let line = next_line().ok_or("Header doesn't end")?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix(b"FORMAT=") {
+            if format != b"32-bit_rle_rgbe" {
+                return Err(format!("Unsupported format {}", String::from_utf8_lossy(format)));
+            }
+        }
+    }
+
+    let resolution = String::from_utf8_lossy(next_line().ok_or("Missing resolution")?).into_owned();
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (
+            height.parse::<u32>().map_err(|_| "Bad height")?,
+            width.parse::<u32>().map_err(|_| "Bad width")?,
+        ),
+        _ => return Err(format!("Unsupported orientation '{}'", resolution)),
+    };
+
+    let mut data = &bytes[pos..];
+    let mut take = |count: usize| -> Result<&[u8], String> {
+        if data.len() < count {
+            return Err("Truncated pixel data".to_string());
+        }
+        let (head, rest) = data.split_at(count);
+        data = rest;
+        Ok(head)
+    };
+
+    let row_len = width as usize;
+    let mut rgbe = vec![0u8; row_len * height as usize * 4];
+    for row in rgbe.chunks_exact_mut(row_len * 4) {
+        let start = take(4)?;
+        let run_length = (8..0x8000).contains(&row_len)
+            && start[0] == 2
+            && start[1] == 2
+            && ((start[2] as usize) << 8 | start[3] as usize) == row_len;
+        if !run_length {
+            row[..4].copy_from_slice(start);
+            row[4..].copy_from_slice(take((row_len - 1) * 4)?);
+            continue;
+        }
+
+        // Each channel separately: count > 128 repeats the next byte count - 128 times,
+        // otherwise count bytes follow as is
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < row_len {
+                let count = take(1)?[0] as usize;
+                let (count, run) = if count > 128 { (count - 128, true) } else { (count, false) };
+                if count == 0 || x + count > row_len {
+                    return Err("Bad run length".to_string());
+                }
+                if run {
+                    let value = take(1)?[0];
+                    (x..x + count).for_each(|i| row[i * 4 + channel] = value);
+                } else {
+                    let values = take(count)?;
+                    (x..x + count).zip(values).for_each(|(i, &value)| row[i * 4 + channel] = value);
+                }
+                x += count;
+            }
+        }
+    }
+
+    // Shared exponent, e = 0 is black
+    let rgba = rgbe.chunks_exact(4)
+        .flat_map(|p| {
+            if p[3] == 0 {
+                return [0.0, 0.0, 0.0, 1.0];
+            }
+            let scale = 2f32.powi(p[3] as i32 - 136);
+            [p[0] as f32 * scale, p[1] as f32 * scale, p[2] as f32 * scale, 1.0]
+        })
+        .collect();
+
+    Ok((rgba, width, height))
+}
+
+// Round to nearest, out of range values clamp to the biggest half instead of going infinite
+pub(crate) fn f32_to_f16(value: f32) -> u16 {
+    let value = if value.is_nan() { 0.0 } else { value.clamp(-65504.0, 65504.0) };
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent <= 0 {
+        // Subnormal half or zero
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let round = (mantissa >> (shift - 1)) & 1;
+        return sign | ((mantissa >> shift) + round) as u16;
+    }
+
+    // Rounding may carry into the exponent, which is still the right result
+    let half = sign as u32 | (exponent as u32) << 10 | mantissa >> 13;
+    (half + ((mantissa >> 12) & 1)) as u16
+}
+
+pub(crate) fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+// size x size RGBA8 checkerboard with 8x8 squares, used as placeholder content
+// Copy of `texture` (needs COPY_SRC) with its sRGB-ness flipped, every mip and layer
+fn reinterpret(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture, label: &str) -> wgpu::Texture {
+    let format = ColorSpace::of_format(texture.format()).opposite().format();
+    assert_eq!(format.remove_srgb_suffix(), texture.format().remove_srgb_suffix(), "Only 8 bit RGBA can be reinterpreted");
+    let copy = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: texture.size(),
+        mip_level_count: texture.mip_level_count(),
+        sample_count: 1,
+        dimension: texture.dimension(),
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Reinterpret Encoder") });
+    for mip_level in 0..texture.mip_level_count() {
+        let copy_texture = |texture| wgpu::ImageCopyTexture {
+            texture,
+            mip_level,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        };
+        let size = texture.size().mip_level_size(mip_level, texture.dimension());
+        encoder.copy_texture_to_texture(copy_texture(texture), copy_texture(&copy), size);
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+    copy
+}
+
+pub fn checkerboard_rgba(size: u32, a: [u8; 4], b: [u8; 4]) -> Vec<u8> {
+    let square = (size / 8).max(1);
+    (0..size * size)
+        .flat_map(|i| {
+            let (x, y) = (i % size / square, i / size / square);
+            if (x + y) % 2 == 0 { a } else { b }
+        })
+        .collect()
+}
+
+// Box filter: every destination pixel averages the source pixels it covers
+fn downscale_rgba(src: &[u8], width: u32, height: u32, new_width: u32, new_height: u32) -> Vec<u8> {
+    let mut dst = Vec::with_capacity((new_width * new_height * 4) as usize);
+
+    for y in 0..new_height {
+        let y0 = (y as u64 * height as u64 / new_height as u64) as u32;
+        let y1 = (((y + 1) as u64 * height as u64).div_ceil(new_height as u64) as u32).max(y0 + 1);
+        for x in 0..new_width {
+            let x0 = (x as u64 * width as u64 / new_width as u64) as u32;
+            let x1 = (((x + 1) as u64 * width as u64).div_ceil(new_width as u64) as u32).max(x0 + 1);
+
+            let mut sum = [0u32; 4];
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let i = ((sy * width + sx) * 4) as usize;
+                    for c in 0..4 {
+                        sum[c] += src[i + c] as u32;
+                    }
+                }
+            }
+            let count = (y1 - y0) * (x1 - x0);
+            dst.extend(sum.map(|v| ((v + count / 2) / count) as u8));
+        }
+    }
+
+    dst
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DirtyRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl DirtyRect {
+    // Touching rects count too, merging them never uploads extra pixels
+    fn overlaps(&self, other: &DirtyRect) -> bool {
+        self.x <= other.x + other.width
+            && other.x <= self.x + self.width
+            && self.y <= other.y + other.height
+            && other.y <= self.y + self.height
+    }
+
+    fn union(&self, other: &DirtyRect) -> DirtyRect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        DirtyRect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+// RGBA8 texture with a CPU side copy. Edits go to the copy and mark dirty rects,
+// flush() uploads only those regions (overlapping ones merged) once per frame
+pub struct StreamingTexture {
+    pub texture: Texture,
+    pixels: Vec<u8>,
+    width: u32,
+    height: u32,
+    dirty: Vec<DirtyRect>,
+}
+
+impl StreamingTexture {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32, label: &str) -> Self {
+        let pixels = vec![0; (width * height * 4) as usize];
+        let texture = Texture::from_rgba(device, queue, &pixels, width, height, label);
+        Self { texture, pixels, width, height, dirty: Vec::new() }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * self.width + x) * 4) as usize;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3]]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgba: [u8; 4]) {
+        let i = ((y * self.width + x) * 4) as usize;
+        self.pixels[i..i + 4].copy_from_slice(&rgba);
+        self.mark_dirty(DirtyRect { x, y, width: 1, height: 1 });
+    }
+
+    // `data` is width x height pixels, tightly packed. Rect is clipped to the texture
+    pub fn write_rect(&mut self, x: u32, y: u32, width: u32, height: u32, data: &[u8]) {
+        let clipped_width = width.min(self.width.saturating_sub(x));
+        let clipped_height = height.min(self.height.saturating_sub(y));
+        for row in 0..clipped_height {
+            let src = (row * width * 4) as usize;
+            let dst = (((y + row) * self.width + x) * 4) as usize;
+            let len = (clipped_width * 4) as usize;
+            self.pixels[dst..dst + len].copy_from_slice(&data[src..src + len]);
+        }
+        self.mark_dirty(DirtyRect { x, y, width: clipped_width, height: clipped_height });
+    }
+
+    pub fn mark_dirty(&mut self, rect: DirtyRect) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        // Keep the list merged as it grows, so it stays short even with many small edits
+        let mut rect = rect;
+        while let Some(i) = self.dirty.iter().position(|other| other.overlaps(&rect)) {
+            rect = rect.union(&self.dirty.swap_remove(i));
+        }
+        self.dirty.push(rect);
+    }
+
+    // Uploads the dirty regions straight out of the CPU copy, returns bytes uploaded
+    pub fn flush(&mut self, queue: &wgpu::Queue) -> usize {
+        let stride = self.width * 4;
+        let mut uploaded = 0;
+        for rect in self.dirty.drain(..) {
+            let offset = (rect.y * stride + rect.x * 4) as u64;
+            self.texture.write_region_strided(
+                queue,
+                (rect.x, rect.y),
+                (rect.width, rect.height),
+                &self.pixels,
+                offset,
+                stride,
+            );
+            uploaded += (rect.width * rect.height * 4) as usize;
+        }
+        uploaded
+    }
+}
+
+// Filterable float texture read by fragment shaders, what most bind group layouts are made of
+pub const fn layout_entry(binding: u32, view_dimension: wgpu::TextureViewDimension) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+// Several same sized images behind one binding, picked by index in the shader (sample_layer in
+// shader.wgsl). Normally a D2Array texture with one image per layer. When there are more images
+// than max_texture_array_layers allows (WebGL2 only guarantees 256), or when forced, images are
+// packed as tiles of an atlas inside the layers instead. Shaders don't care which one they get
+pub struct LayeredTexture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    pub info: LayeredTextureInfo,
+    info_buffer: wgpu::Buffer,
+    tile_width: u32,
+    tile_height: u32,
+    count: u32,
+}
+
+// Layout must match LayeredTextureInfo in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LayeredTextureInfo {
+    pub tiles_per_row: u32, // 1 = plain texture array
+    pub tiles_per_layer: u32,
+    // Uniforms need 16 byte size on WebGL
+    _padding: [u32; 2],
+}
+
+impl LayeredTexture {
+    pub fn new(
+        device: &wgpu::Device,
+        tile_width: u32,
+        tile_height: u32,
+        count: u32,
+        force_atlas: bool,
+        label: &str,
+    ) -> Result<Self, String> {
+        let limits = device.limits();
+        let count = count.max(1);
+
+        let tiles_per_row = if !force_atlas && count <= limits.max_texture_array_layers {
+            1
+        } else {
+            // As many tiles as fit in one layer, up to what's needed to fit everything in one layer
+            let fit = (limits.max_texture_dimension_2d / tile_width.max(tile_height)).max(1);
+            ((count as f32).sqrt().ceil() as u32).clamp(1, fit)
+        };
+        let tiles_per_layer = tiles_per_row * tiles_per_row;
+        let layers = count.div_ceil(tiles_per_layer);
+        if layers > limits.max_texture_array_layers {
+            return Err(format!(
+                "{} images of {}x{} don't fit in {} layers even as an atlas",
+                count, tile_width, tile_height, limits.max_texture_array_layers
+            ));
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: tile_width * tiles_per_row,
+                height: tile_height * tiles_per_row,
+                depth_or_array_layers: layers,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            // COPY_SRC for reinterpreted()
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        // Has to be set explicitly, with a single layer the default view would be a plain D2
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        // Linear filtering would bleed neighbouring tiles into each other in an atlas
+        let filter = if tiles_per_row == 1 { wgpu::FilterMode::Linear } else { wgpu::FilterMode::Nearest };
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filter,
+            min_filter: filter,
+            ..Default::default()
+        });
+
+        let info = LayeredTextureInfo { tiles_per_row, tiles_per_layer, _padding: [0; 2] };
+        let info_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Layered Texture Info Buffer"),
+            contents: bytemuck::cast_slice(&[info]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            info,
+            info_buffer,
+            tile_width,
+            tile_height,
+            count,
+        })
+    }
+
+    // Group 1 of shader.wgsl
+    pub const LAYOUT_ENTRIES: &'static [wgpu::BindGroupLayoutEntry] = &[
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+    ];
+
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Layered Texture Bind Group Layout"),
+            entries: Self::LAYOUT_ENTRIES,
+        })
+    }
+
+    pub fn create_bind_group(&self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Layered Texture Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.info_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    // Same layers sampled as linear instead of sRGB, see Texture::reinterpreted
+    pub fn reinterpreted(&self, device: &wgpu::Device, queue: &wgpu::Queue, label: &str) -> Self {
+        let texture = reinterpret(device, queue, &self.texture, label);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let filter = if self.is_atlas() { wgpu::FilterMode::Nearest } else { wgpu::FilterMode::Linear };
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filter,
+            min_filter: filter,
+            ..Default::default()
+        });
+        let info_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Layered Texture Info Buffer"),
+            contents: bytemuck::cast_slice(&[self.info]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        Self { texture, view, sampler, info_buffer, ..*self }
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        texture_bytes(&self.texture) + self.info_buffer.size()
+    }
+
+    pub fn is_atlas(&self) -> bool {
+        self.info.tiles_per_row > 1
+    }
+
+    // `rgba` is one tile_width x tile_height image
+    pub fn write_layer(&self, queue: &wgpu::Queue, index: u32, rgba: &[u8]) {
+        assert!(index < self.count, "Layer {} out of {}", index, self.count);
+        let layer = index / self.info.tiles_per_layer;
+        let tile = index % self.info.tiles_per_layer;
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: (tile % self.info.tiles_per_row) * self.tile_width,
+                    y: (tile / self.info.tiles_per_row) * self.tile_height,
+                    z: layer,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            rgba,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * self.tile_width),
+                rows_per_image: Some(self.tile_height),
+            },
+            wgpu::Extent3d {
+                width: self.tile_width,
+                height: self.tile_height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What shaders read from every 8 bit RGBA texel of `texture`, through a compute shader so it's the
+    // sampler's decoding and nothing else
+    fn sampled_values(device: &wgpu::Device, queue: &wgpu::Queue, texture: &Texture) -> Vec<[f32; 4]> {
+        const SHADER: &str = "
+            @group(0) @binding(0) var t: texture_2d<f32>;
+            @group(0) @binding(1) var<storage, read_write> out: array<vec4<f32>>;
+            @compute @workgroup_size(1)
+            fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+                let width = textureDimensions(t).x;
+                out[id.y * width + id.x] = textureLoad(t, vec2<i32>(id.xy), 0);
+            }
+        ";
+        let size = texture.texture.size();
+        let bytes = (size.width * size.height) as u64 * 16;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Color Space Check Shader"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Color Space Check Pipeline"),
+            layout: None,
+            module: &module,
+            entry_point: "main",
+        });
+        let output = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Color Space Check Output"),
+            size: bytes,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Color Space Check Readback"),
+            size: bytes,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Color Space Check Bind Group"),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&texture.view) },
+                wgpu::BindGroupEntry { binding: 1, resource: output.as_entire_binding() },
+            ],
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Color Space Check Encoder") });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("Color Space Check Pass") });
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(size.width, size.height, 1);
+        }
+        encoder.copy_buffer_to_buffer(&output, 0, &readback, 0, bytes);
+        queue.submit(std::iter::once(encoder.finish()));
+        crate::readback::map_read(device, &readback, |data| bytemuck::cast_slice(data).to_vec())
+    }
+
+    fn assert_samples_as(device: &wgpu::Device, queue: &wgpu::Queue, label: &str, texture: &Texture, expected: f32) {
+        let value = sampled_values(device, queue, texture)[0];
+        assert!(
+            value[..3].iter().all(|channel| (channel - expected).abs() <= 0.002) && (value[3] - 1.0).abs() <= 1e-6,
+            "{} samples as {:?}, expected {}",
+            label, value, expected
+        );
+    }
+
+    const GREY: [u8; 4] = [128, 128, 128, 255];
+
+    #[test]
+    fn srgb_to_linear_decodes_grey() {
+        let decoded = crate::readback::srgb_to_linear(128.0 / 255.0);
+        assert!((decoded - 0.2159).abs() <= 0.0005, "srgb_to_linear(128 / 255) is {}, not ~0.216", decoded);
+    }
+
+    // A 50% grey (128) texel sampled as each color space: sRGB decodes it to ~0.216, linear keeps
+    // ~0.502, and reinterpreted() flips between the two
+    #[test]
+    fn grey_samples_as_its_color_space() {
+        let Some((device, queue)) = crate::shader_test::device() else {
+            return;
+        };
+        let decoded = crate::readback::srgb_to_linear(128.0 / 255.0);
+        let srgb = Texture::from_rgba_in(device, queue, &GREY, 1, 1, ColorSpace::Srgb, "Color Space Check sRGB");
+        let linear = Texture::from_rgba_in(device, queue, &GREY, 1, 1, ColorSpace::Linear, "Color Space Check Linear");
+        assert_samples_as(device, queue, "sRGB grey", &srgb, decoded);
+        assert_samples_as(device, queue, "linear grey", &linear, 128.0 / 255.0);
+        assert_samples_as(device, queue, "sRGB grey reinterpreted", &srgb.reinterpreted(device, queue, "Color Space Check"), 128.0 / 255.0);
+        assert_samples_as(device, queue, "linear grey reinterpreted", &linear.reinterpreted(device, queue, "Color Space Check"), decoded);
+    }
+
+    // PbrMaterial turns down textures in the wrong slot, and wrong_color_space flips every slot
+    #[test]
+    fn pbr_material_checks_color_spaces() {
+        let Some((device, queue)) = crate::shader_test::device() else {
+            return;
+        };
+        let layout = crate::reflection::ShaderReflection::new("PBR", include_str!("pbr.wgsl"))
+            .and_then(|shader| shader.layout(device, 1))
+            .unwrap();
+        let textures = crate::pbr::PbrTextures {
+            albedo: Some(Texture::from_rgba_linear(device, queue, &GREY, 1, 1, "Color Space Check Albedo")),
+            ..Default::default()
+        };
+        assert!(
+            crate::pbr::PbrMaterial::new(device, queue, &layout, textures, Default::default()).is_err(),
+            "PbrMaterial took a linear albedo"
+        );
+
+        let textures = crate::pbr::PbrTextures {
+            albedo: Some(Texture::from_rgba_in(device, queue, &GREY, 1, 1, ColorSpace::Srgb, "Color Space Check sRGB")),
+            normal: Some(Texture::from_rgba_in(device, queue, &GREY, 1, 1, ColorSpace::Linear, "Color Space Check Linear")),
+            ..Default::default()
+        };
+        let material = crate::pbr::PbrMaterial::new(device, queue, &layout, textures, Default::default()).unwrap();
+        let wrong = material.wrong_color_space(device, queue, &layout).unwrap();
+        for ((slot, color_space), texture) in crate::pbr::SLOTS.iter().zip(&wrong.textures) {
+            assert_ne!(texture.color_space(), *color_space, "{} is still {:?} in wrong_color_space", slot, color_space);
+        }
+    }
+
+    #[test]
+    fn gltf_slots_have_color_spaces() {
+        for (slot, expected) in [("baseColorTexture", ColorSpace::Srgb), ("normalTexture", ColorSpace::Linear), ("metallicRoughnessTexture", ColorSpace::Linear)] {
+            assert_eq!(ColorSpace::of_gltf_texture(slot), Some(expected), "glTF {}", slot);
+        }
+    }
+}
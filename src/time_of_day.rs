@@ -0,0 +1,258 @@
+use crate::math::Vec3;
+use crate::procedural_sky::ProceduralSkyParams;
+
+// The directional light never goes lower than this. A grazing light lights surfaces edge on and
+// anything fitted to it (a shadow projection) gets arbitrarily long
+pub const MIN_LIGHT_ELEVATION: f32 = 5.0;
+// The sky map and its lighting are regenerated at most once per this much of a day (15 minutes),
+// see TimeOfDay::sky
+pub const SKY_STEP: f32 = 1.0 / 96.0;
+
+// Light colors at the sun's full strength, what pbr::SUN_COLOR is at noon
+const SUN_STRENGTH: f32 = 3.0;
+const MOON_STRENGTH: f32 = 0.15;
+// Bluish, cooler than daylight
+const MOON_TEMPERATURE: f32 = 8000.0;
+
+// Color of a black body, normalized to the brightest channel. Kelvin -> linear RGB, roughly
+const TEMPERATURE_COLORS: [(f32, [f32; 3]); 9] = [
+    (1900.0, [1.0, 0.52, 0.17]),
+    (2500.0, [1.0, 0.63, 0.34]),
+    (3500.0, [1.0, 0.77, 0.56]),
+    (4500.0, [1.0, 0.87, 0.75]),
+    (5500.0, [1.0, 0.94, 0.89]),
+    (6500.0, [1.0, 1.0, 1.0]),
+    (8000.0, [0.83, 0.89, 1.0]),
+    (10000.0, [0.76, 0.84, 1.0]),
+    (12000.0, [0.71, 0.8, 1.0]),
+];
+
+// Everything below goes by the sun's elevation in degrees
+const SUN_TEMPERATURE: [(f32, f32); 6] = [(-2.0, 1900.0), (2.0, 2500.0), (8.0, 3500.0), (20.0, 5000.0), (45.0, 5800.0), (90.0, 6000.0)];
+// How much of the sun's strength gets through, gone just below the horizon
+const SUN_VISIBILITY: [(f32, f32); 3] = [(-2.0, 0.0), (2.0, 0.3), (10.0, 1.0)];
+// The moon fades in over civil twilight
+const MOON_VISIBILITY: [(f32, f32); 2] = [(-8.0, 1.0), (-2.0, 0.0)];
+const AMBIENT: [(f32, f32); 3] = [(-12.0, 0.2), (0.0, 0.6), (15.0, 1.0)];
+// Stops added to the exposure, so the night isn't black
+const EXPOSURE_BIAS: [(f32, f32); 4] = [(-12.0, 4.0), (-4.0, 2.5), (0.0, 1.0), (10.0, 0.0)];
+const ZENITH: [(f32, [f32; 3]); 5] = [
+    (-18.0, [0.001, 0.0015, 0.004]),
+    (-6.0, [0.01, 0.015, 0.04]),
+    (0.0, [0.05, 0.08, 0.2]),
+    (10.0, [0.1, 0.2, 0.5]),
+    (30.0, [0.15, 0.3, 0.65]),
+];
+const HORIZON: [(f32, [f32; 3]); 5] = [
+    (-18.0, [0.002, 0.002, 0.004]),
+    (-6.0, [0.05, 0.04, 0.06]),
+    (0.0, [0.6, 0.4, 0.3]),
+    (10.0, [0.6, 0.6, 0.65]),
+    (30.0, [0.6, 0.7, 0.8]),
+];
+const GROUND: [(f32, [f32; 3]); 3] = [(-12.0, [0.004, 0.004, 0.004]), (0.0, [0.08, 0.07, 0.06]), (15.0, [0.2, 0.18, 0.16])];
+
+// Day/night cycle driving the light, the procedural sky and the exposure, see
+// State::set_time_of_day. The sun rises in +x at 6:00, is highest towards +z at noon and sets in
+// -x at 18:00. At night the moon, opposite the sun, is the light
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TimeOfDay {
+    // 0..1 of a day, 0 is midnight and 0.5 noon
+    pub time: f32,
+    // Hours of the day per second of real time
+    pub speed: f32,
+    pub paused: bool,
+    // Sun elevation at noon in degrees, lower is further from the equator
+    pub noon_elevation: f32,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        // A day in about 100 seconds, from late morning
+        Self { time: 10.0 / 24.0, speed: 0.25, paused: false, noon_elevation: 60.0 }
+    }
+}
+
+// What a TimeOfDay looks like at its time
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DayLighting {
+    // Towards the sun, normalized. Below the horizon at night
+    pub sun_direction: Vec3,
+    // Degrees above the horizon, negative below
+    pub sun_elevation: f32,
+    // Degrees around the y axis from +x towards +z
+    pub sun_azimuth: f32,
+    // Towards the directional light: the sun, or the moon at night. Never below MIN_LIGHT_ELEVATION
+    pub light_direction: Vec3,
+    // Linear, includes the intensity
+    pub light_color: [f32; 3],
+    pub moon: bool,
+    // Scales image based lighting
+    pub ambient: f32,
+    // Stops added to the exposure
+    pub exposure_bias: f32,
+}
+
+impl TimeOfDay {
+    pub fn at_hours(hours: f32) -> Self {
+        let mut time_of_day = Self::default();
+        time_of_day.set_hours(hours);
+        time_of_day
+    }
+
+    // 0..24
+    pub fn hours(&self) -> f32 {
+        self.time * 24.0
+    }
+
+    // Wraps around, 25 is 1:00
+    pub fn set_hours(&mut self, hours: f32) {
+        self.time = (hours / 24.0).rem_euclid(1.0);
+    }
+
+    // Call once per frame
+    pub fn advance(&mut self, dt: f32) {
+        if !self.paused {
+            self.time = (self.time + dt * self.speed / 24.0).rem_euclid(1.0);
+        }
+    }
+
+    pub fn lighting(&self) -> DayLighting {
+        let sun_direction = self.sun_direction();
+        let sun_elevation = sun_direction.y.clamp(-1.0, 1.0).asin().to_degrees();
+        let sun_azimuth = sun_direction.z.atan2(sun_direction.x).to_degrees();
+
+        let sun = SUN_STRENGTH * lookup(&SUN_VISIBILITY, sun_elevation);
+        let moon = MOON_STRENGTH * lookup(&MOON_VISIBILITY, sun_elevation);
+        let (direction, color, strength) = if sun >= moon {
+            (sun_direction, temperature_color(lookup(&SUN_TEMPERATURE, sun_elevation)), sun)
+        } else {
+            (-sun_direction, temperature_color(MOON_TEMPERATURE), moon)
+        };
+        DayLighting {
+            sun_direction,
+            sun_elevation,
+            sun_azimuth,
+            light_direction: clamp_elevation(direction, MIN_LIGHT_ELEVATION),
+            light_color: color.map(|c| c * strength),
+            moon: sun < moon,
+            ambient: lookup(&AMBIENT, sun_elevation),
+            exposure_bias: lookup(&EXPOSURE_BIAS, sun_elevation),
+        }
+    }
+
+    // Sky at the time rounded to SKY_STEP, so it only changes every few frames while the time runs.
+    // The disk is the real sun, it sets below the horizon where the light stops at MIN_LIGHT_ELEVATION
+    pub fn sky(&self) -> ProceduralSkyParams {
+        let stepped = Self { time: ((self.time / SKY_STEP).round() * SKY_STEP).rem_euclid(1.0), ..*self };
+        let lighting = stepped.lighting();
+        let elevation = lighting.sun_elevation;
+        ProceduralSkyParams {
+            sun_direction: lighting.sun_direction.to_array(),
+            sun_color: temperature_color(lookup(&SUN_TEMPERATURE, elevation)),
+            sun_intensity: ProceduralSkyParams::default().sun_intensity * lookup(&SUN_VISIBILITY, elevation).max(0.05),
+            zenith_color: lookup3(&ZENITH, elevation),
+            horizon_color: lookup3(&HORIZON, elevation),
+            ground_color: lookup3(&GROUND, elevation),
+            ..ProceduralSkyParams::default()
+        }
+    }
+
+    fn sun_direction(&self) -> Vec3 {
+        // Angle along the sun's circle, 0 at sunrise. The circle is tilted from the vertical so
+        // it peaks at noon_elevation
+        let angle = (self.time - 0.25) * std::f32::consts::TAU;
+        let tilt = self.noon_elevation.clamp(1.0, 90.0).to_radians();
+        Vec3::new(angle.cos(), angle.sin() * tilt.sin(), angle.sin() * tilt.cos()).normalize()
+    }
+}
+
+// Same azimuth, raised to `min_elevation` degrees if it's lower
+fn clamp_elevation(direction: Vec3, min_elevation: f32) -> Vec3 {
+    let elevation = direction.y.clamp(-1.0, 1.0).asin();
+    let min = min_elevation.to_radians();
+    if elevation >= min {
+        return direction;
+    }
+    let horizontal = Vec3::new(direction.x, 0.0, direction.z);
+    // Straight down has no azimuth left, any will do
+    let horizontal = if horizontal.length() > 1e-6 { horizontal.normalize() } else { Vec3::X };
+    horizontal * min.cos() + Vec3::Y * min.sin()
+}
+
+fn temperature_color(kelvin: f32) -> [f32; 3] {
+    lookup3(&TEMPERATURE_COLORS, kelvin)
+}
+
+// Linear between the two entries around x, the end values past the ends. Tables are sorted by x
+fn lookup(table: &[(f32, f32)], x: f32) -> f32 {
+    let (a, b, t) = around(table, x);
+    a + (b - a) * t
+}
+
+fn lookup3(table: &[(f32, [f32; 3])], x: f32) -> [f32; 3] {
+    let (a, b, t) = around(table, x);
+    std::array::from_fn(|c| a[c] + (b[c] - a[c]) * t)
+}
+
+// Values of the entries before and after x and how far x is between them, 0..1
+fn around<T: Copy>(table: &[(f32, T)], x: f32) -> (T, T, f32) {
+    let next = table.iter().position(|&(key, _)| key > x).unwrap_or(table.len());
+    if next == 0 || next == table.len() {
+        let end = table[next.saturating_sub(1)].1;
+        return (end, end, 0.0);
+    }
+    let ((a_key, a), (b_key, b)) = (table[next - 1], table[next]);
+    (a, b, (x - a_key) / (b_key - a_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sun_rises_peaks_and_sets_on_time() {
+        for (hours, expected) in [(6.0, 0.0), (12.0, 60.0), (18.0, 0.0), (0.0, -60.0)] {
+            let elevation = TimeOfDay::at_hours(hours).lighting().sun_elevation;
+            assert!((elevation - expected).abs() <= 0.5, "Sun at {:.1} degrees at {}:00, expected {}", elevation, hours, expected);
+        }
+    }
+
+    // A day in small steps: the light never below MIN_LIGHT_ELEVATION or NaN, the moon only at
+    // night, the night exposed up, and the sky only changing every SKY_STEP
+    #[test]
+    fn a_day_in_small_steps() {
+        let mut time_of_day = TimeOfDay { speed: 24.0, ..TimeOfDay::at_hours(0.0) };
+        let mut sky_changes = 0;
+        let mut sky = time_of_day.sky();
+        let steps = 24 * 60 * 4;
+        for _ in 0..steps {
+            time_of_day.advance(1.0 / steps as f32);
+            let hours = time_of_day.hours();
+            let lighting = time_of_day.lighting();
+            let light = lighting.light_direction;
+            let mut values = light.to_array().into_iter().chain(lighting.light_color).chain([lighting.ambient, lighting.exposure_bias]);
+            assert!(values.all(f32::is_finite), "Not finite at {:.2}h: {:?}", hours, lighting);
+            let light_elevation = light.y.asin().to_degrees();
+            assert!(light_elevation >= MIN_LIGHT_ELEVATION - 1e-3, "Light at {:.2} degrees at {:.2}h", light_elevation, hours);
+            assert!(
+                !lighting.moon || lighting.sun_elevation <= -2.0,
+                "Moon is the light at {:.2}h with the sun at {:.1} degrees",
+                hours, lighting.sun_elevation
+            );
+            assert!(
+                lighting.sun_elevation >= -6.0 || lighting.exposure_bias >= 2.0,
+                "Night at {:.2}h only exposed up by {} stops",
+                hours, lighting.exposure_bias
+            );
+            if time_of_day.sky() != sky {
+                sky = time_of_day.sky();
+                sky_changes += 1;
+            }
+        }
+        let hours = time_of_day.hours();
+        assert!((hours % 24.0).min(24.0 - hours) <= 0.01, "A day at speed 24 for a second ended at {:.2}h, not midnight", hours);
+        let expected = (1.0 / SKY_STEP).round() as u32;
+        assert!(sky_changes <= expected, "Sky changed {} times in a day, expected at most {}", sky_changes, expected);
+    }
+}
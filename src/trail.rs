@@ -0,0 +1,382 @@
+use std::collections::VecDeque;
+
+use crate::buffer::GrowableBuffer;
+use crate::math::Vec3;
+use crate::pipeline::PipelineConfig;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TrailSettings {
+    // Samples kept, the ribbon is at most this many sample intervals long
+    pub length: usize,
+    // Seconds between samples, independent of the frame rate
+    pub sample_interval: f32,
+    // World units across at the head, narrows to nothing at the tail
+    pub width: f32,
+    // At the head, alpha fades to 0 at the tail
+    pub color: [f32; 4],
+    // Moving further than this in one update is a teleport, the ribbon breaks there instead of
+    // stretching across
+    pub break_distance: f32,
+}
+
+impl Default for TrailSettings {
+    fn default() -> Self {
+        Self {
+            length: 32,
+            sample_interval: 1.0 / 30.0,
+            width: 0.2,
+            color: [1.0, 0.8, 0.4, 1.0],
+            break_distance: 5.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct TrailSample {
+    position: Vec3,
+    // First sample after a teleport, not connected to the older ones
+    starts_new: bool,
+}
+
+// Where something has been: its position every sample_interval, newest first. Samples between
+// updates are interpolated along the update's movement, so a slow frame still leaves evenly
+// spaced ones
+pub struct Trail {
+    pub settings: TrailSettings,
+    samples: VecDeque<TrailSample>,
+    // Position at the last update, the ribbon starts here
+    head: Option<Vec3>,
+    since_sample: f32,
+}
+
+impl Trail {
+    pub fn new(settings: TrailSettings) -> Self {
+        Self {
+            settings,
+            samples: VecDeque::with_capacity(settings.length),
+            head: None,
+            since_sample: 0.0,
+        }
+    }
+
+    // Call once per update with where the followed thing is now
+    pub fn update(&mut self, position: Vec3, dt: f32) {
+        let Some(previous) = self.head else {
+            self.push(position, true);
+            return;
+        };
+        if (position - previous).length() > self.settings.break_distance {
+            self.push(position, true);
+            return;
+        }
+
+        let interval = self.settings.sample_interval.max(1e-4);
+        self.since_sample += dt;
+        while self.since_sample >= interval {
+            self.since_sample -= interval;
+            // How far into this update the sample falls
+            let t = 1.0 - self.since_sample / dt;
+            self.samples.push_front(TrailSample { position: previous.lerp(position, t), starts_new: false });
+        }
+        self.samples.truncate(self.settings.length);
+        self.head = Some(position);
+    }
+
+    fn push(&mut self, position: Vec3, starts_new: bool) {
+        self.samples.push_front(TrailSample { position, starts_new });
+        self.samples.truncate(self.settings.length);
+        self.head = Some(position);
+        self.since_sample = 0.0;
+    }
+
+    // Forgets the history, the next update starts a new ribbon
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.head = None;
+        self.since_sample = 0.0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    // Appends the ribbon as triangles facing `eye`, two vertices per point
+    pub fn build(&self, eye: Vec3, vertices: &mut Vec<TrailVertex>) {
+        // (position, connected to the next older point)
+        let mut points = Vec::with_capacity(self.samples.len() + 1);
+        if let (Some(head), Some(newest)) = (self.head, self.samples.front()) {
+            // Right after a sample the head is on it
+            if (head - newest.position).length() > 1e-4 {
+                points.push((head, true));
+            }
+        }
+        for (i, sample) in self.samples.iter().enumerate() {
+            let connected = self.samples.get(i + 1).is_some() && !sample.starts_new;
+            points.push((sample.position, connected));
+        }
+
+        let length = self.settings.length.max(1) as f32;
+        let edges = points
+            .iter()
+            .enumerate()
+            .map(|(i, &(position, connected))| {
+                let newer = match i.checked_sub(1).map(|newer| points[newer]) {
+                    Some((newer, true)) => newer,
+                    _ => position,
+                };
+                let older = if connected { points[i + 1].0 } else { position };
+                let side = (newer - older).cross(eye - position).normalize();
+                let fade = (1.0 - i as f32 / length).max(0.0);
+                let offset = side * (self.settings.width * 0.5 * fade);
+                let [r, g, b, a] = self.settings.color;
+                let color = [r, g, b, a * fade];
+                [
+                    TrailVertex { position: (position - offset).to_array(), color },
+                    TrailVertex { position: (position + offset).to_array(), color },
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        for (i, &(_, connected)) in points.iter().enumerate() {
+            if connected {
+                let [a, b] = edges[i];
+                let [c, d] = edges[i + 1];
+                vertices.extend_from_slice(&[a, b, c, c, b, d]);
+            }
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TrailVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl TrailVertex {
+    pub(crate) fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TrailVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Rebuilds every trail's ribbon on the CPU each frame into one vertex buffer and draws it in a
+// single call. Blended, depth tested but not written, like the grid. Overlapping parts of a ribbon
+// aren't sorted, they're thin and fade out so it rarely shows
+pub struct TrailRenderer {
+    vertices: Vec<TrailVertex>,
+    vertex_buffer: GrowableBuffer,
+    vertex_count: u32,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl TrailRenderer {
+    pub fn new(device: &wgpu::Device, config: &PipelineConfig, camera_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        // One default trail's worth
+        let capacity = (TrailSettings::default().length * 6 * std::mem::size_of::<TrailVertex>()) as wgpu::BufferAddress;
+        Self {
+            vertices: Vec::new(),
+            vertex_buffer: GrowableBuffer::new(device, "Trail Vertex Buffer", wgpu::BufferUsages::VERTEX, capacity),
+            vertex_count: 0,
+            pipeline: Self::create_pipeline(device, config, camera_bind_group_layout),
+        }
+    }
+
+    // Call after the pipeline config changes
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device, config: &PipelineConfig, camera_bind_group_layout: &wgpu::BindGroupLayout) {
+        self.pipeline = Self::create_pipeline(device, config, camera_bind_group_layout);
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("trail.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Trail Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Trail Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[TrailVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                // Which side faces the camera depends on which way the ribbon goes
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                depth_write_enabled: false,
+                ..config.depth_state()
+            }),
+            multisample: config.multisample(),
+            multiview: None,
+        })
+    }
+
+    // Builds the ribbons of `trails` facing `eye` and writes them, the buffer grows as needed
+    pub fn upload<'a>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        trails: impl IntoIterator<Item = &'a Trail>,
+        eye: Vec3,
+    ) {
+        self.vertices.clear();
+        for trail in trails {
+            trail.build(eye, &mut self.vertices);
+        }
+        self.vertex_buffer.write(device, queue, bytemuck::cast_slice(&self.vertices));
+        self.vertex_count = self.vertices.len() as u32;
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        if self.vertex_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.buffer().slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        self.vertex_buffer.capacity()
+    }
+}
+
+// Something circling `center` with a trail behind it, to see trails without setting any up
+pub struct Comet {
+    pub trail: Trail,
+    time: f32,
+}
+
+impl Comet {
+    // Seconds per orbit
+    const PERIOD: f32 = 4.0;
+
+    pub fn new() -> Self {
+        Self {
+            trail: Trail::new(TrailSettings { length: 48, width: 0.3, ..Default::default() }),
+            time: 0.0,
+        }
+    }
+
+    // Tilted circle of `radius` around `center`, a little above it
+    pub fn update(&mut self, center: Vec3, radius: f32, dt: f32) {
+        self.time += dt;
+        let angle = self.time / Self::PERIOD * std::f32::consts::TAU;
+        let position = center + Vec3::new(angle.cos() * radius, 1.0 + (angle * 2.0).sin() * radius * 0.25, angle.sin() * radius);
+        self.trail.update(position, dt);
+    }
+}
+
+impl Default for Comet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: TrailSettings = TrailSettings { length: 8, sample_interval: 0.1, width: 1.0, color: [1.0; 4], break_distance: 2.0 };
+    const EYE: Vec3 = Vec3::new(0.0, 10.0, 0.0);
+
+    // Moving 1 unit per second in uneven steps
+    fn moving_trail() -> Trail {
+        let mut trail = Trail::new(SETTINGS);
+        let mut time = 0.0;
+        for dt in [0.016, 0.25, 0.033, 0.1, 0.05, 0.3, 0.016, 0.2] {
+            time += dt;
+            trail.update(Vec3::new(time, 0.0, 0.0), dt);
+        }
+        trail
+    }
+
+    // Length stays bounded and samples are evenly spaced whatever the frame times
+    #[test]
+    fn samples_are_evenly_spaced() {
+        let trail = moving_trail();
+        assert_eq!(trail.len(), SETTINGS.length);
+        let positions = trail.samples.iter().map(|sample| sample.position.x).collect::<Vec<_>>();
+        for pair in positions.windows(2) {
+            assert!(((pair[0] - pair[1]) - SETTINGS.sample_interval).abs() <= 1e-4, "Samples aren't {} apart: {:?}", SETTINGS.sample_interval, positions);
+        }
+    }
+
+    // Full width and alpha at the head, faded out at the tail, every segment across the view
+    #[test]
+    fn ribbon_tapers() {
+        let mut vertices = Vec::new();
+        moving_trail().build(EYE, &mut vertices);
+        // Head plus 8 samples, all connected
+        assert_eq!(vertices.len(), 8 * 6);
+        let head_width = (Vec3::from(vertices[1].position) - Vec3::from(vertices[0].position)).length();
+        assert!((head_width - SETTINGS.width).abs() <= 1e-4, "Head is {} wide, expected {}", head_width, SETTINGS.width);
+        assert_eq!(vertices[0].color[3], 1.0);
+        let tail_alpha = vertices[vertices.len() - 1].color[3];
+        assert!(tail_alpha <= 1.0 / SETTINGS.length as f32, "Alpha at the tail is {}, expected it faded out", tail_alpha);
+        for (i, pair) in vertices.chunks(6).enumerate() {
+            let [a, b] = [Vec3::from(pair[0].position), Vec3::from(pair[1].position)];
+            assert!((b - a).dot(Vec3::Z).abs() >= (b - a).length() * 0.99, "Segment {} isn't across the view: {:?} to {:?}", i, a, b);
+        }
+    }
+
+    // A few samples after a jump, nothing spans the gap
+    #[test]
+    fn teleport_breaks_the_ribbon() {
+        let mut trail = moving_trail();
+        trail.update(Vec3::new(100.0, 0.0, 0.0), 0.016);
+        let mut time = 100.0;
+        for _ in 0..3 {
+            time += 0.1;
+            trail.update(Vec3::new(time, 0.0, 0.0), 0.1);
+        }
+        let mut vertices = Vec::new();
+        trail.build(EYE, &mut vertices);
+        for triangle in vertices.chunks(3) {
+            let xs = triangle.iter().map(|vertex| vertex.position[0]);
+            let (min, max) = xs.fold((f32::MAX, f32::MIN), |(min, max), x| (min.min(x), max.max(x)));
+            assert!(max - min <= SETTINGS.break_distance, "Triangle spans the teleport, x from {} to {}", min, max);
+        }
+        // 4 samples after the jump and the 4 older ones left, 3 + 3 segments
+        assert_eq!(vertices.len(), 6 * 6);
+    }
+
+    #[test]
+    fn cleared_trail_builds_nothing() {
+        let mut trail = moving_trail();
+        trail.clear();
+        let mut vertices = Vec::new();
+        trail.build(EYE, &mut vertices);
+        assert!(vertices.is_empty());
+        assert!(trail.is_empty());
+    }
+}
@@ -0,0 +1,494 @@
+use crate::buffer::{ArenaSlice, FrameArena};
+use crate::math::{Mat4, Vec3};
+use crate::pipeline::PipelineConfig;
+use crate::texture;
+
+const ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const REVEAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+// Screen rectangle in pixels and the camera to draw it with
+pub type Region<'a> = ((u32, u32, u32, u32), &'a wgpu::BindGroup);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransparencyMode {
+    // Back to front by distance of the quad center, breaks for intersecting quads
+    Sorted,
+    // Weighted blended OIT, order doesn't matter but colors are approximated
+    WeightedBlended,
+}
+
+// 1x1 quad on the XY plane centered at origin, placed by transform
+#[derive(Copy, Clone, Debug)]
+pub struct TransparentQuad {
+    pub transform: Mat4,
+    pub color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct TransparentVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl TransparentVertex {
+    pub(crate) fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TransparentVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Accumulation targets for weighted blended OIT. With MSAA the multisampled ones are drawn
+// into and resolved to the single sample ones the composite pass reads
+struct OitTargets {
+    accum_msaa: Option<wgpu::TextureView>,
+    reveal_msaa: Option<wgpu::TextureView>,
+    accum: wgpu::TextureView,
+    reveal: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+    // All four textures together, see texture::estimated_bytes
+    bytes: u64,
+}
+
+// Transparent geometry drawn after the opaque pass, either sorted or order independent.
+// Both modes draw the same quads list
+pub struct TransparentRenderer {
+    pub quads: Vec<TransparentQuad>,
+    pub mode: TransparencyMode,
+    vertex_count: u32,
+    // This frame's vertices in the frame arena, at least a triangle so prewarm has something to bind
+    vertex_buffer: Option<ArenaSlice>,
+    composite_bind_group_layout: wgpu::BindGroupLayout,
+    sorted_pipeline: wgpu::RenderPipeline,
+    accumulate_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    targets: OitTargets,
+}
+
+impl TransparentRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let composite_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("OIT Composite Bind Group Layout"),
+            entries: &[0, 1].map(|binding| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                // Read with textureLoad, so no filtering needed
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }),
+        });
+
+        let (sorted_pipeline, accumulate_pipeline, composite_pipeline) =
+            Self::create_pipelines(device, config, camera_bind_group_layout, &composite_bind_group_layout);
+        let targets = Self::create_targets(device, &composite_bind_group_layout, width, height, config.sample_count);
+
+        Self {
+            quads: Vec::new(),
+            mode: TransparencyMode::WeightedBlended,
+            vertex_count: 0,
+            vertex_buffer: None,
+            composite_bind_group_layout,
+            sorted_pipeline,
+            accumulate_pipeline,
+            composite_pipeline,
+            targets,
+        }
+    }
+
+    // Three quads crossing each other through one point. No draw order gets these right,
+    // which is what OIT is for
+    pub fn intersecting_panes(center: Vec3) -> Vec<TransparentQuad> {
+        let colors = [[1.0, 0.2, 0.2, 0.5], [0.2, 1.0, 0.2, 0.5], [0.2, 0.4, 1.0, 0.5]];
+        colors.iter()
+            .enumerate()
+            .map(|(i, &color)| TransparentQuad {
+                transform: Mat4::translation(center)
+                    * Mat4::from_axis_angle(Vec3::Y, i as f32 * std::f32::consts::FRAC_PI_3)
+                    * Mat4::scale(Vec3::new(3.0, 3.0, 1.0)),
+                color,
+            })
+            .collect()
+    }
+
+    // Call after the pipeline config changes
+    pub fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        (self.sorted_pipeline, self.accumulate_pipeline, self.composite_pipeline) =
+            Self::create_pipelines(device, config, camera_bind_group_layout, &self.composite_bind_group_layout);
+    }
+
+    // Call on resize and when the sample count changes
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32, sample_count: u32) {
+        self.targets = Self::create_targets(device, &self.composite_bind_group_layout, width, height, sample_count);
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        composite_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("transparent.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Transparent Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        // Tested against opaque depth, but doesn't write it so transparent surfaces don't hide each other
+        let depth_stencil = Some(wgpu::DepthStencilState {
+            depth_write_enabled: false,
+            ..config.depth_state()
+        });
+        // Quads are seen from both sides
+        let primitive = wgpu::PrimitiveState {
+            cull_mode: None,
+            ..Default::default()
+        };
+
+        let create = |label, fs_entry, targets: &[Option<wgpu::ColorTargetState>]| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[TransparentVertex::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: fs_entry,
+                    targets,
+                }),
+                primitive,
+                depth_stencil: depth_stencil.clone(),
+                multisample: config.multisample(),
+                multiview: None,
+            })
+        };
+
+        let sorted_pipeline = create("Sorted Transparent Pipeline", "fs_sorted", &[Some(wgpu::ColorTargetState {
+            format: config.color_format,
+            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+            write_mask: wgpu::ColorWrites::ALL,
+        })]);
+
+        let accumulate_pipeline = create("OIT Accumulate Pipeline", "fs_accumulate", &[
+            // Sum of weighted premultiplied colors
+            Some(wgpu::ColorTargetState {
+                format: ACCUM_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+            // Product of (1 - alpha), how much of the background still shows
+            Some(wgpu::ColorTargetState {
+                format: REVEAL_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Zero,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::REPLACE,
+                }),
+                write_mask: wgpu::ColorWrites::RED,
+            }),
+        ]);
+
+        let composite_shader = device.create_shader_module(wgpu::include_wgsl!("oit_composite.wgsl"));
+        let composite_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("OIT Composite Pipeline Layout"),
+            bind_group_layouts: &[composite_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let composite_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("OIT Composite Pipeline"),
+            layout: Some(&composite_layout),
+            vertex: wgpu::VertexState {
+                module: &composite_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &composite_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            // Drawn into the same (multisampled) color target as the opaque pass
+            multisample: config.multisample(),
+            multiview: None,
+        });
+
+        (sorted_pipeline, accumulate_pipeline, composite_pipeline)
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        composite_bind_group_layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> OitTargets {
+        let create = |label, format, sample_count, usage| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+                view_formats: &[],
+            }).create_view(&wgpu::TextureViewDescriptor::default())
+        };
+
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+        let accum = create("OIT Accum Texture", ACCUM_FORMAT, 1, usage);
+        let reveal = create("OIT Reveal Texture", REVEAL_FORMAT, 1, usage);
+        let (accum_msaa, reveal_msaa) = if sample_count > 1 {
+            let usage = wgpu::TextureUsages::RENDER_ATTACHMENT;
+            (
+                Some(create("OIT Accum MSAA Texture", ACCUM_FORMAT, sample_count, usage)),
+                Some(create("OIT Reveal MSAA Texture", REVEAL_FORMAT, sample_count, usage)),
+            )
+        } else {
+            (None, None)
+        };
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("OIT Composite Bind Group"),
+            layout: composite_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&accum),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&reveal),
+                },
+            ],
+        });
+
+        let msaa_samples = if sample_count > 1 { sample_count } else { 0 };
+        let bytes = [ACCUM_FORMAT, REVEAL_FORMAT].iter()
+            .map(|&format| texture::estimated_bytes(format, width, height, 1, 1, 1 + msaa_samples))
+            .sum();
+
+        OitTargets { accum_msaa, reveal_msaa, accum, reveal, bind_group, bytes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.quads.is_empty()
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        self.targets.bytes
+    }
+
+    // Resolved OIT targets, for declaring them in a render graph
+    pub fn oit_views(&self) -> (&wgpu::TextureView, &wgpu::TextureView) {
+        (&self.targets.accum, &self.targets.reveal)
+    }
+
+    // Builds the vertex buffer. Sorting uses `eye`, so with several viewports the order is only
+    // right for the one closest to it
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, arena: &mut FrameArena, eye: Vec3) {
+        let mut quads = self.quads.clone();
+        if self.mode == TransparencyMode::Sorted {
+            let distance = |quad: &TransparentQuad| (quad.transform.transform_point(Vec3::ZERO) - eye).length();
+            quads.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+        }
+
+        let corners = [[-0.5, -0.5], [0.5, -0.5], [0.5, 0.5], [-0.5, -0.5], [0.5, 0.5], [-0.5, 0.5]];
+        let vertices = quads.iter()
+            .flat_map(|quad| corners.map(|[x, y]| TransparentVertex {
+                position: quad.transform.transform_point(Vec3::new(x, y, 0.0)).to_array(),
+                color: quad.color,
+            }))
+            .collect::<Vec<_>>();
+
+        self.vertex_count = vertices.len() as u32;
+        let vertices = if vertices.is_empty() { &[bytemuck::Zeroable::zeroed(); 3][..] } else { &vertices };
+        self.vertex_buffer = Some(arena.alloc(device, queue, bytemuck::cast_slice(vertices)));
+    }
+
+    fn vertices(&self) -> &ArenaSlice {
+        self.vertex_buffer.as_ref().expect("TransparentRenderer drawn before upload")
+    }
+
+    fn draw_regions<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, pipeline: &'a wgpu::RenderPipeline, regions: &[Region<'a>]) {
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_vertex_buffer(0, self.vertices().slice());
+        for &((x, y, width, height), camera_bind_group) in regions {
+            render_pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+            render_pass.set_scissor_rect(x, y, width, height);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.draw(0..self.vertex_count, 0..1);
+        }
+    }
+
+    // Sorted mode: blends straight into the opaque color target.
+    // `color_view` / `resolve_target` are the same the opaque pass used
+    pub fn sorted_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        color_view: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        depth_view: &wgpu::TextureView,
+        regions: &[Region],
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Sorted Transparent Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: color_view,
+                resolve_target,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: true },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Load, store: true }),
+                stencil_ops: None,
+            }),
+        });
+        self.draw_regions(&mut render_pass, &self.sorted_pipeline, regions);
+    }
+
+    // OIT mode, step 1: all transparent geometry into the accum / reveal targets, any order
+    pub fn accumulate_pass(&self, encoder: &mut wgpu::CommandEncoder, depth_view: &wgpu::TextureView, regions: &[Region]) {
+        let targets = &self.targets;
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("OIT Accumulate Pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: targets.accum_msaa.as_ref().unwrap_or(&targets.accum),
+                    resolve_target: targets.accum_msaa.as_ref().map(|_| &targets.accum),
+                    ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT), store: true },
+                }),
+                Some(wgpu::RenderPassColorAttachment {
+                    view: targets.reveal_msaa.as_ref().unwrap_or(&targets.reveal),
+                    resolve_target: targets.reveal_msaa.as_ref().map(|_| &targets.reveal),
+                    // Fully revealed until something covers it
+                    ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::WHITE), store: true },
+                }),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Load, store: true }),
+                stencil_ops: None,
+            }),
+        });
+        self.draw_regions(&mut render_pass, &self.accumulate_pipeline, regions);
+    }
+
+    // OIT mode, step 2: blends the averaged transparent color over the opaque color target
+    pub fn composite_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        color_view: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("OIT Composite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: color_view,
+                resolve_target,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, &self.targets.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // Draws one triangle with each pipeline, for State::prewarm. `color_view` and `depth_view` have
+    // to be 1x1 and match the pipeline config, the OIT targets are throwaway 1x1 ones as well
+    pub fn prewarm(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        color_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+        sample_count: u32,
+    ) {
+        let targets = Self::create_targets(device, &self.composite_bind_group_layout, 1, 1, sample_count);
+        let color = |view| Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: false },
+        });
+        let depth = Some(wgpu::RenderPassDepthStencilAttachment {
+            view: depth_view,
+            depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Load, store: false }),
+            stencil_ops: None,
+        });
+
+        let passes = [
+            (&self.sorted_pipeline, vec![color(color_view)]),
+            (
+                &self.accumulate_pipeline,
+                vec![
+                    color(targets.accum_msaa.as_ref().unwrap_or(&targets.accum)),
+                    color(targets.reveal_msaa.as_ref().unwrap_or(&targets.reveal)),
+                ],
+            ),
+        ];
+        for (pipeline, color_attachments) in passes {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Transparent Prewarm Pass"),
+                color_attachments: &color_attachments,
+                depth_stencil_attachment: depth.clone(),
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.vertices().slice());
+            render_pass.draw(0..3, 0..1);
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("OIT Composite Prewarm Pass"),
+            color_attachments: &[color(color_view)],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, &targets.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
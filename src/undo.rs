@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+
+use crate::instance::Instance;
+use crate::pbr::PbrFactors;
+
+// Entries kept until set_limit says otherwise
+pub const DEFAULT_LIMIT: usize = 100;
+
+// One reversible editor operation, see State::transform_instance and friends
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Edit {
+    // Instance `index` went from `before` to `after`
+    Transform { index: usize, before: Instance, after: Instance },
+    // Instance inserted at `index`
+    Spawn { index: usize, instance: Instance },
+    // Instance removed from `index`. Instances share the mesh and have no children, so the
+    // instance itself is all it takes to bring it back
+    Delete { index: usize, instance: Instance },
+    // The mesh material's factors
+    Material { before: PbrFactors, after: PbrFactors },
+}
+
+impl Edit {
+    // The edit that takes this one back
+    pub fn inverse(&self) -> Edit {
+        match *self {
+            Edit::Transform { index, before, after } => Edit::Transform { index, before: after, after: before },
+            Edit::Spawn { index, instance } => Edit::Delete { index, instance },
+            Edit::Delete { index, instance } => Edit::Spawn { index, instance },
+            Edit::Material { before, after } => Edit::Material { before: after, after: before },
+        }
+    }
+
+    // Folds `next` into this one if it continues it, the same instance moved again or the
+    // material changed again. For drags, see UndoStack::begin_group
+    fn merge(&mut self, next: &Edit) -> bool {
+        match (self, next) {
+            (Edit::Transform { index, after, .. }, Edit::Transform { index: next_index, after: next_after, .. }) if index == next_index => {
+                *after = *next_after;
+                true
+            }
+            (Edit::Material { after, .. }, Edit::Material { after: next_after, .. }) => {
+                *after = *next_after;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// Edits done and undone, newest last. Only records, State applies them (see State::undo). A new
+// edit drops everything undone, past `limit` the oldest ones go
+pub struct UndoStack {
+    done: VecDeque<Edit>,
+    undone: Vec<Edit>,
+    limit: usize,
+    // Between begin_group and end_group, None outside of one. Some(true) once the group has its
+    // entry, later edits merge into it where they can
+    group: Option<bool>,
+}
+
+impl UndoStack {
+    pub fn new(limit: usize) -> Self {
+        Self { done: VecDeque::new(), undone: Vec::new(), limit, group: None }
+    }
+
+    pub fn push(&mut self, edit: Edit) {
+        self.undone.clear();
+        if self.group == Some(true) {
+            if let Some(last) = self.done.back_mut() {
+                if last.merge(&edit) {
+                    return;
+                }
+            }
+        }
+        if self.group.is_some() {
+            self.group = Some(true);
+        }
+        self.done.push_back(edit);
+        self.trim();
+    }
+
+    // Edits until end_group merge into one entry, so a drag undoes in one step
+    pub fn begin_group(&mut self) {
+        self.group = Some(false);
+    }
+
+    pub fn end_group(&mut self) {
+        self.group = None;
+    }
+
+    // The edit to take back, State applies its inverse
+    pub fn undo(&mut self) -> Option<Edit> {
+        self.group = None;
+        let edit = self.done.pop_back()?;
+        self.undone.push(edit);
+        Some(edit)
+    }
+
+    // The edit to do again
+    pub fn redo(&mut self) -> Option<Edit> {
+        self.group = None;
+        let edit = self.undone.pop()?;
+        self.done.push_back(edit);
+        Some(edit)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.done.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+
+    // For when what the edits point at is replaced wholesale
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+        self.group = None;
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        while self.done.len() > self.limit {
+            self.done.pop_front();
+        }
+    }
+}
+
+impl Default for UndoStack {
+    fn default() -> Self {
+        Self::new(DEFAULT_LIMIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{Mat4, Vec3};
+
+    fn at(x: f32) -> Instance {
+        Instance { position: Vec3::new(x, 0.0, 0.0), rotation: Mat4::IDENTITY, layer: 0 }
+    }
+
+    fn moved(index: usize, from: f32, to: f32) -> Edit {
+        Edit::Transform { index, before: at(from), after: at(to) }
+    }
+
+    #[test]
+    fn undo_and_redo_walk_the_stack() {
+        let mut stack = UndoStack::default();
+        stack.push(moved(0, 0.0, 1.0));
+        stack.push(Edit::Delete { index: 2, instance: at(5.0) });
+        assert_eq!(stack.undo(), Some(Edit::Delete { index: 2, instance: at(5.0) }));
+        assert_eq!(stack.undo(), Some(moved(0, 0.0, 1.0)));
+        assert_eq!(stack.undo(), None);
+        assert_eq!(stack.redo(), Some(moved(0, 0.0, 1.0)));
+        // Something new, the delete can't be redone anymore
+        stack.push(moved(1, 0.0, 2.0));
+        assert!(!stack.can_redo());
+        assert_eq!(stack.undo(), Some(moved(1, 0.0, 2.0)));
+    }
+
+    #[test]
+    fn drags_undo_in_one_step() {
+        let mut stack = UndoStack::default();
+        stack.begin_group();
+        for x in 1..=10 {
+            stack.push(moved(3, x as f32 - 1.0, x as f32));
+        }
+        stack.end_group();
+        stack.push(moved(3, 10.0, 11.0));
+        assert_eq!(stack.undo(), Some(moved(3, 10.0, 11.0)));
+        assert_eq!(stack.undo(), Some(moved(3, 0.0, 10.0)));
+        assert!(!stack.can_undo());
+    }
+
+    #[test]
+    fn oldest_edits_go_past_the_limit() {
+        let mut stack = UndoStack::new(3);
+        for x in 0..5 {
+            stack.push(moved(0, x as f32, x as f32 + 1.0));
+        }
+        stack.set_limit(2);
+        assert_eq!(stack.undo(), Some(moved(0, 4.0, 5.0)));
+        assert_eq!(stack.undo(), Some(moved(0, 3.0, 4.0)));
+        assert_eq!(stack.undo(), None);
+    }
+
+    #[test]
+    fn inverse_edits_swap_sides() {
+        let delete = Edit::Delete { index: 1, instance: at(2.0) };
+        assert_eq!(delete.inverse(), Edit::Spawn { index: 1, instance: at(2.0) });
+        assert_eq!(delete.inverse().inverse(), delete);
+        assert_eq!(moved(0, 1.0, 2.0).inverse(), moved(0, 2.0, 1.0));
+    }
+}
@@ -0,0 +1,225 @@
+use std::ops::Range;
+
+use crate::instance::InstanceRaw;
+use crate::math::Mat4;
+use crate::pipeline::PipelineConfig;
+use crate::Vertex;
+
+// rg motion in uv units, a marks pixels the velocity pass drew
+pub const VELOCITY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// Layout must match MotionUniform in velocity.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MotionUniform {
+    view_proj: [[f32; 4]; 4],
+    prev_view_proj: [[f32; 4]; 4],
+    previous_valid: u32,
+    _padding: [u32; 3],
+}
+
+// Screen space motion of the mesh since the last frame, shared by TAA and motion blur. Draws the
+// mesh again on top of the scene's depth with last frame's instance transforms (a copy of the
+// instance buffer made in end_frame) and camera. Pixels it doesn't cover (sky, background) are
+// left to the readers, they reproject those from the depth buffer and the camera motion alone
+pub struct VelocityPass {
+    // Unjittered, None until the first frame after a reset
+    prev_view_proj: Option<Mat4>,
+    // Copy of last frame's instance buffer, previous_valid says whether it's filled yet
+    previous_instances: Option<wgpu::Buffer>,
+    previous_valid: bool,
+    motion_buffer: wgpu::Buffer,
+    motion_bind_group_layout: wgpu::BindGroupLayout,
+    motion_bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl VelocityPass {
+    // `layouts`: camera, layered texture and bones bind group layouts
+    pub fn new(device: &wgpu::Device, config: &PipelineConfig, layouts: [&wgpu::BindGroupLayout; 3]) -> Self {
+        let motion_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Velocity Motion Buffer"),
+            size: std::mem::size_of::<MotionUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let motion_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Velocity Motion Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
+        });
+        let motion_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Velocity Motion Bind Group"),
+            layout: &motion_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: motion_buffer.as_entire_binding(),
+                }
+            ],
+        });
+        let pipeline = Self::create_pipeline(device, config, layouts, &motion_bind_group_layout);
+
+        Self {
+            prev_view_proj: None,
+            previous_instances: None,
+            previous_valid: false,
+            motion_buffer,
+            motion_bind_group_layout,
+            motion_bind_group,
+            pipeline,
+        }
+    }
+
+    // Call after the pipeline config changes
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device, config: &PipelineConfig, layouts: [&wgpu::BindGroupLayout; 3]) {
+        self.pipeline = Self::create_pipeline(device, config, layouts, &self.motion_bind_group_layout);
+    }
+
+    pub(crate) fn shader_source() -> String {
+        format!("{}\n{}", include_str!("shader.wgsl"), include_str!("velocity.wgsl"))
+    }
+
+    pub(crate) fn previous_instances_desc() -> wgpu::VertexBufferLayout<'static> {
+        const PREVIOUS_ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+            11 => Float32x4, 12 => Float32x4, 13 => Float32x4, 14 => Float32x4
+        ];
+        // Same buffer layout as the instance buffer, the layer isn't read
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &PREVIOUS_ATTRIBUTES,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        layouts: [&wgpu::BindGroupLayout; 3],
+        motion_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Velocity Shader"),
+            source: wgpu::ShaderSource::Wgsl(Self::shader_source().into()),
+        });
+        let [camera, layered, bones] = layouts;
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Velocity Pipeline Layout"),
+            bind_group_layouts: &[camera, layered, bones, motion_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Velocity Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_velocity",
+                buffers: &[Vertex::desc(), InstanceRaw::desc(), Self::previous_instances_desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_velocity",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: VELOCITY_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                unclipped_depth: config.unclipped_depth,
+                ..Default::default()
+            },
+            // Only the visible surface, the scene pass already wrote its depth. Same position math
+            // as vs_main, so equal passes
+            depth_stencil: Some(wgpu::DepthStencilState {
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                ..config.depth_state()
+            }),
+            // Single sampled only, like the readers
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    // Forgets last frame, the next one has no motion. For cuts: resizes, projection switches,
+    // instance changes
+    pub fn invalidate(&mut self) {
+        self.prev_view_proj = None;
+        self.previous_valid = false;
+    }
+
+    // Keeps the camera motion but treats the instances as not moving for a frame, for when the
+    // instance buffer was reordered and last frame's copy doesn't line up with it
+    pub fn invalidate_instances(&mut self) {
+        self.previous_valid = false;
+    }
+
+    // Writes the uniform for this frame and returns last frame's view_proj, None when there's no
+    // last frame to compare with. `view_proj` is the camera without jitter, `instance_buffer` the
+    // one drawn with this frame
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        view_proj: Mat4,
+        instance_buffer: &wgpu::Buffer,
+    ) -> Option<Mat4> {
+        if self.previous_instances.as_ref().map(wgpu::Buffer::size) != Some(instance_buffer.size()) {
+            self.previous_instances = Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Previous Instance Buffer"),
+                size: instance_buffer.size(),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+            self.previous_valid = false;
+        }
+        let prev_view_proj = self.prev_view_proj;
+        let motion = MotionUniform {
+            view_proj: view_proj.to_cols_array(),
+            prev_view_proj: prev_view_proj.unwrap_or(view_proj).to_cols_array(),
+            previous_valid: self.previous_valid as u32,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.motion_buffer, 0, bytemuck::cast_slice(&[motion]));
+        self.prev_view_proj = Some(view_proj);
+        prev_view_proj
+    }
+
+    // Groups 0-2 and vertex buffers 0 and 1 are set by the caller, the same as for the mesh pipelines.
+    // Draws into a VELOCITY_FORMAT target cleared to 0, with the scene's (single sampled) depth buffer
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, vertices: Range<u32>, instances: Range<u32>) {
+        let Some(previous) = &self.previous_instances else {
+            return;
+        };
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(3, &self.motion_bind_group, &[]);
+        render_pass.set_vertex_buffer(2, previous.slice(..));
+        render_pass.draw(vertices, instances);
+    }
+
+    // After the passes reading the velocity were recorded: keeps this frame's instances for the next
+    pub fn end_frame(&mut self, encoder: &mut wgpu::CommandEncoder, instance_buffer: &wgpu::Buffer) {
+        if let Some(previous) = &self.previous_instances {
+            encoder.copy_buffer_to_buffer(instance_buffer, 0, previous, 0, previous.size());
+            self.previous_valid = true;
+        }
+    }
+
+    // The velocity target belongs to the render graph
+    pub fn gpu_memory(&self) -> u64 {
+        self.previous_instances.as_ref().map_or(0, wgpu::Buffer::size)
+    }
+}
@@ -0,0 +1,65 @@
+// Appended to shader.wgsl by velocity.rs. Screen space motion of the mesh between the previous frame
+// and this one, for TAA reprojection and motion blur
+
+// Layout must match MotionUniform in velocity.rs
+struct MotionUniform {
+    // Without jitter, the jitter would show up as motion otherwise
+    view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+    // 0 when the previous instance buffer doesn't hold last frame yet, instances count as unmoved
+    previous_valid: u32,
+}
+
+// Binding 2, outline.rs and per_draw.rs have 0 and 1 of group 3 in the same module
+@group(3) @binding(2)
+var<uniform> motion: MotionUniform;
+
+// Last frame's instance transforms, same order as the instance buffer
+struct PreviousInstanceInput {
+    @location(11) model_matrix_0: vec4<f32>,
+    @location(12) model_matrix_1: vec4<f32>,
+    @location(13) model_matrix_2: vec4<f32>,
+    @location(14) model_matrix_3: vec4<f32>,
+}
+
+struct VelocityOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) current: vec4<f32>,
+    @location(1) previous: vec4<f32>,
+}
+
+@vertex
+fn vs_velocity(model: VertexInput, instance: InstanceInput, previous: PreviousInstanceInput) -> VelocityOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var prev_model_matrix = model_matrix;
+    if motion.previous_valid != 0u {
+        prev_model_matrix = mat4x4<f32>(
+            previous.model_matrix_0,
+            previous.model_matrix_1,
+            previous.model_matrix_2,
+            previous.model_matrix_3,
+        );
+    }
+    // No previous bone matrices, skinned motion only shows through the neighborhood clamp
+    let skinned = skin_matrix(model) * vec4<f32>(model.position, 1.0);
+    var out: VelocityOutput;
+    // Same (jittered) position as the scene pass so the depth test lines up
+    out.clip_position = to_clip_position(model, instance);
+    out.current = motion.view_proj * model_matrix * skinned;
+    out.previous = motion.prev_view_proj * prev_model_matrix * skinned;
+    return out;
+}
+
+// Motion in uv units (y down) in rg, a = 1 marks pixels covered by the mesh. Readers work out the
+// rest from the depth buffer and the camera motion
+@fragment
+fn fs_velocity(in: VelocityOutput) -> @location(0) vec4<f32> {
+    let current = in.current.xy / in.current.w;
+    let previous = in.previous.xy / in.previous.w;
+    return vec4<f32>((current - previous) * vec2<f32>(0.5, -0.5), 0.0, 1.0);
+}
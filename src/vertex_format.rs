@@ -0,0 +1,520 @@
+use std::fmt;
+
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+use crate::{shader_validation, Vertex};
+
+// Vertex attributes a mesh has data for or a shader reads. Joints and weights only come together,
+// as SKIN
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct VertexAttributes(u8);
+
+impl VertexAttributes {
+    pub const NONE: Self = Self(0);
+    pub const POSITION: Self = Self(1);
+    pub const COLOR: Self = Self(2);
+    pub const TEX_COORDS: Self = Self(4);
+    pub const SKIN: Self = Self(8);
+    pub const ALL: Self = Self(15);
+    const EACH: [(Self, &'static str); 4] = [
+        (Self::POSITION, "position"),
+        (Self::COLOR, "color"),
+        (Self::TEX_COORDS, "tex coords"),
+        (Self::SKIN, "skin"),
+    ];
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    // Single attributes, in Vertex order
+    pub fn iter(self) -> impl Iterator<Item = VertexAttributes> {
+        Self::EACH.into_iter().map(|(attribute, _)| attribute).filter(move |&attribute| self.contains(attribute))
+    }
+}
+
+impl fmt::Display for VertexAttributes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = Self::EACH.iter().filter(|(attribute, _)| self.contains(*attribute)).map(|(_, name)| *name).collect::<Vec<_>>();
+        if names.is_empty() {
+            write!(f, "nothing")
+        } else {
+            write!(f, "{}", names.join(" + "))
+        }
+    }
+}
+
+impl fmt::Debug for VertexAttributes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "VertexAttributes({})", self)
+    }
+}
+
+// What each Vertex location holds, see Vertex::desc. Later locations are instance data
+const LOCATIONS: [(u32, VertexAttributes); 5] = [
+    (0, VertexAttributes::POSITION),
+    (1, VertexAttributes::COLOR),
+    (2, VertexAttributes::TEX_COORDS),
+    (3, VertexAttributes::SKIN),
+    (4, VertexAttributes::SKIN),
+];
+
+// `const <name>: bool` a shader declares to have a variant without the attribute, see shader.wgsl.
+// Colors have none, white is what a missing color means anyway
+const SWITCHES: [(&str, VertexAttributes); 2] = [("HAS_UV", VertexAttributes::TEX_COORDS), ("HAS_SKIN", VertexAttributes::SKIN)];
+
+// A mesh as it comes out of a loader: positions and whatever else the file had. Triangle list
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+    pub positions: Vec<[f32; 3]>,
+    pub colors: Option<Vec<[f32; 3]>>,
+    pub tex_coords: Option<Vec<[f32; 2]>>,
+    // Joints and weights per vertex, see Vertex
+    pub skin: Option<Vec<([u16; 4], [f32; 4])>>,
+}
+
+impl MeshData {
+    pub fn attributes(&self) -> VertexAttributes {
+        let mut attributes = VertexAttributes::NONE;
+        for (present, attribute) in [
+            (!self.positions.is_empty(), VertexAttributes::POSITION),
+            (self.colors.is_some(), VertexAttributes::COLOR),
+            (self.tex_coords.is_some(), VertexAttributes::TEX_COORDS),
+            (self.skin.is_some(), VertexAttributes::SKIN),
+        ] {
+            if present {
+                attributes = attributes.union(attribute);
+            }
+        }
+        attributes
+    }
+
+    // Defaults where there's no data: white, UV 0,0 and bone 0 only. Err when an attribute doesn't
+    // have one value per position
+    pub fn vertices(&self) -> Result<Vec<Vertex>, String> {
+        let count = self.positions.len();
+        let check = |name: &str, len: Option<usize>| match len {
+            Some(len) if len != count => Err(format!("Mesh has {} positions but {} {}", count, len, name)),
+            _ => Ok(()),
+        };
+        check("colors", self.colors.as_ref().map(Vec::len))?;
+        check("tex coords", self.tex_coords.as_ref().map(Vec::len))?;
+        check("joints / weights", self.skin.as_ref().map(Vec::len))?;
+
+        Ok((0..count)
+            .map(|i| {
+                let mut vertex = Vertex::new(
+                    self.positions[i],
+                    self.colors.as_ref().map_or([1.0; 3], |colors| colors[i]),
+                    self.tex_coords.as_ref().map_or([0.0; 2], |tex_coords| tex_coords[i]),
+                );
+                if let Some(skin) = &self.skin {
+                    (vertex.joints, vertex.weights) = skin[i];
+                }
+                vertex
+            })
+            .collect())
+    }
+}
+
+// What a vertex entry point reads, and which of it the shader has a variant without
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShaderVertexFormat {
+    pub entry_point: String,
+    pub reads: VertexAttributes,
+    pub switchable: VertexAttributes,
+}
+
+impl ShaderVertexFormat {
+    pub fn reflect(label: &str, source: &str, entry_point: &str) -> Result<Self, String> {
+        let module = naga::front::wgsl::parse_str(source).map_err(|e| e.emit_to_string_with_path(source, label))?;
+        Validator::new(ValidationFlags::all(), Capabilities::all())
+            .validate(&module)
+            .map_err(|e| e.emit_to_string_with_path(source, label))?;
+        let entry = module
+            .entry_points
+            .iter()
+            .find(|entry| entry.name == entry_point && entry.stage == naga::ShaderStage::Vertex)
+            .ok_or_else(|| format!("{} has no vertex entry point {}", label, entry_point))?;
+
+        let mut reads = VertexAttributes::NONE;
+        for (_, location, _) in shader_validation::vertex_inputs(&module, entry) {
+            if let Some(&(_, attribute)) = LOCATIONS.iter().find(|(l, _)| *l == location) {
+                reads = reads.union(attribute);
+            }
+        }
+        let mut switchable = VertexAttributes::NONE;
+        for (name, attribute) in SWITCHES {
+            let declared = module.constants.iter().any(|(_, constant)| {
+                constant.name.as_deref() == Some(name)
+                    && matches!(module.types[constant.ty].inner, naga::TypeInner::Scalar { kind: naga::ScalarKind::Bool, .. })
+            });
+            if declared && reads.contains(attribute) {
+                switchable = switchable.union(attribute);
+            }
+        }
+        Ok(Self { entry_point: entry_point.to_string(), reads, switchable })
+    }
+}
+
+// How a mesh gets drawn with a shader: the switches for its variant and the attributes that were
+// filled with defaults because the mesh has none
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Negotiated {
+    // Every switch the shader has, false where the mesh lacks the attribute. See shader_variant
+    pub switches: Vec<(&'static str, bool)>,
+    pub filled: VertexAttributes,
+}
+
+// Missing colors and UVs can always be filled in. Positions can't, and neither can joints and
+// weights for a shader that skins without a HAS_SKIN switch
+pub fn negotiate(mesh: VertexAttributes, shader: &ShaderVertexFormat) -> Result<Negotiated, String> {
+    let mut negotiated = Negotiated::default();
+    for (name, attribute) in SWITCHES {
+        if shader.switchable.contains(attribute) {
+            negotiated.switches.push((name, mesh.contains(attribute)));
+        }
+    }
+    for attribute in shader.reads.without(mesh).iter() {
+        let fillable = shader.switchable.contains(attribute)
+            || attribute == VertexAttributes::COLOR
+            || attribute == VertexAttributes::TEX_COORDS;
+        if !fillable {
+            let reason = if attribute == VertexAttributes::SKIN {
+                "the shader skins with them and has no HAS_SKIN variant"
+            } else {
+                "nothing can stand in for it"
+            };
+            return Err(format!("{} can't draw a mesh with {}: no {}, {}", shader.entry_point, mesh, attribute, reason));
+        }
+        negotiated.filled = negotiated.filled.union(attribute);
+    }
+    Ok(negotiated)
+}
+
+// `source` with the `const <name>: bool = ...;` line of every switch rewritten. Err when the shader
+// doesn't declare one
+pub fn shader_variant(source: &str, switches: &[(&str, bool)]) -> Result<String, String> {
+    let mut lines = source.lines().map(str::to_string).collect::<Vec<_>>();
+    for &(name, value) in switches {
+        let prefix = format!("const {}: bool =", name);
+        let line = lines.iter_mut().find(|line| line.starts_with(&prefix)).ok_or_else(|| format!("No {} to switch", prefix))?;
+        *line = format!("{} {};", prefix, value);
+    }
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::{Instance, InstanceRaw};
+    use crate::math::{Mat4, Rng, Vec3};
+    use crate::pipeline::{self, PipelineConfig};
+    use crate::reflection::{ReflectedLayout, ShaderReflection};
+    use crate::shader_test;
+    use crate::vertex_streams::{self, VertexStreams};
+
+    const SHADERS: [(&str, &str, &str, &str); 3] = [
+        ("shader.wgsl", include_str!("shader.wgsl"), "vs_main", "fs_main"),
+        ("shader.wgsl", include_str!("shader.wgsl"), "vs_flat", "fs_flat"),
+        ("pbr.wgsl", include_str!("pbr.wgsl"), "vs_pbr", "fs_pbr"),
+    ];
+
+    // Every combination of the optional attributes on top of a position, and a mesh with nothing
+    fn meshes() -> Vec<VertexAttributes> {
+        let optional = [VertexAttributes::COLOR, VertexAttributes::TEX_COORDS, VertexAttributes::SKIN];
+        let mut meshes = (0..8)
+            .map(|bits| {
+                optional.iter().enumerate()
+                    .filter(|(i, _)| bits & (1 << i) != 0)
+                    .fold(VertexAttributes::POSITION, |attributes, (_, &attribute)| attributes.union(attribute))
+            })
+            .collect::<Vec<_>>();
+        meshes.push(VertexAttributes::NONE);
+        meshes
+    }
+
+    // Negotiation has to fail exactly where a shader can't do without an attribute
+    #[test]
+    fn negotiation_fails_only_without_what_the_shader_needs() {
+        for (label, source, vs_entry, _) in SHADERS {
+            let format = ShaderVertexFormat::reflect(label, source, vs_entry).unwrap();
+            for mesh in meshes() {
+                let compatible = mesh.contains(VertexAttributes::POSITION)
+                    && (mesh.contains(VertexAttributes::SKIN)
+                        || !format.reads.contains(VertexAttributes::SKIN)
+                        || format.switchable.contains(VertexAttributes::SKIN));
+                let negotiated = negotiate(mesh, &format);
+                assert_eq!(negotiated.is_ok(), compatible, "{} {} with {}: {:?}", label, vs_entry, mesh, negotiated.err());
+            }
+        }
+    }
+
+    // Everything that negotiates renders a triangle without validation errors
+    #[test]
+    fn negotiated_formats_render() {
+        let Some((device, queue)) = shader_test::device() else {
+            return;
+        };
+        for (label, source, vs_entry, fs_entry) in SHADERS {
+            let format = ShaderVertexFormat::reflect(label, source, vs_entry).unwrap();
+            for mesh in meshes() {
+                let Ok(negotiated) = negotiate(mesh, &format) else {
+                    continue;
+                };
+                if let Err(e) = render_triangle(device, queue, label, source, vs_entry, fs_entry, mesh, &negotiated, None) {
+                    panic!("{} {} with {}: {}", label, vs_entry, mesh, e);
+                }
+            }
+        }
+    }
+
+    fn packed() -> VertexStreams {
+        let full = vertex_streams::attributes(&Vertex::desc());
+        VertexStreams::interleaved(&full).with_formats(vertex_streams::PACKED_VERTEX_FORMATS)
+    }
+
+    // Packed vertices read back within what their formats can hold. Random colors and weights in
+    // 0 - 1, UVs past the 0 - 1 range where halfs lose precision
+    #[test]
+    fn packed_vertices_read_back_within_tolerance() {
+        let packed = packed();
+        let mut rng = Rng::new(7);
+        let vertices = (0..1000)
+            .map(|_| {
+                let mut vertex = Vertex::new(
+                    [rng.range(-1.0, 1.0), rng.range(-1.0, 1.0), rng.range(-1.0, 1.0)],
+                    [rng.next_f32(), rng.next_f32(), rng.next_f32()],
+                    [rng.range(-4.0, 4.0), rng.range(-4.0, 4.0)],
+                );
+                vertex.joints = [rng.next_u32() as u16, 1, 2, 3];
+                let weights = [rng.next_f32(), rng.next_f32(), rng.next_f32(), rng.next_f32()];
+                let sum = weights.iter().sum::<f32>();
+                vertex.weights = weights.map(|weight| weight / sum);
+                vertex
+            })
+            .collect::<Vec<_>>();
+        let data = packed.split(&Vertex::desc(), bytemuck::cast_slice(&vertices)).unwrap().remove(0);
+        let layout = &packed.layouts()[0];
+        assert!(layout.array_stride < Vertex::desc().array_stride);
+        let stride = layout.array_stride as usize;
+        // Largest error allowed per location: 8 bit colors, halfs up to 4 (10 bit mantissa), 16 bit weights
+        let tolerances = [(0, 0.0), (1, 0.5 / 255.0), (2, 4.0 / 2048.0), (4, 0.5 / 65535.0)];
+        for (i, vertex) in vertices.iter().enumerate() {
+            let expected: [(u32, &[f32]); 4] = [(0, &vertex.position), (1, &vertex.color), (2, &vertex.tex_coords), (4, &vertex.weights)];
+            for (location, values) in expected {
+                let attribute = layout.attributes.iter().find(|attribute| attribute.shader_location == location).unwrap();
+                let offset = i * stride + attribute.offset as usize;
+                let read = vertex_streams::read_floats(attribute.format, &data[offset..offset + attribute.format.size() as usize]).unwrap();
+                let tolerance = tolerances.iter().find(|(l, _)| *l == location).unwrap().1 + 1e-6;
+                assert!(
+                    values.iter().zip(&read).all(|(value, read)| (value - read).abs() <= tolerance),
+                    "Packed vertex {} location {} reads back as {:?}, was {:?}",
+                    i, location, read, values
+                );
+            }
+            let offset = i * stride + layout.attributes.iter().find(|attribute| attribute.shader_location == 3).unwrap().offset as usize;
+            assert_eq!(data[offset..offset + 8], *bytemuck::cast_slice::<u16, u8>(&vertex.joints), "Packed vertex {} joints changed", i);
+        }
+    }
+
+    // The scene shader drawing from packed vertices
+    #[test]
+    fn packed_vertices_render() {
+        let Some((device, queue)) = shader_test::device() else {
+            return;
+        };
+        let supported = packed().packed(device);
+        for (label, vs_entry, fs_entry) in [("shader.wgsl", "vs_main", "fs_main"), ("shader.wgsl", "vs_flat", "fs_flat")] {
+            let format = ShaderVertexFormat::reflect(label, include_str!("shader.wgsl"), vs_entry).unwrap();
+            let negotiated = negotiate(VertexAttributes::ALL, &format).unwrap();
+            if let Err(e) = render_triangle(device, queue, label, include_str!("shader.wgsl"), vs_entry, fs_entry, VertexAttributes::ALL, &negotiated, Some(&supported)) {
+                panic!("{} {} with packed vertices: {}", label, vs_entry, e);
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn render_triangle(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &str,
+        source: &str,
+        vs_entry: &str,
+        fs_entry: &str,
+        attributes: VertexAttributes,
+        negotiated: &Negotiated,
+        // Reads the triangle from these instead of one interleaved buffer
+        streams: Option<&VertexStreams>,
+    ) -> Result<(), String> {
+        use wgpu::util::DeviceExt;
+
+        let positions = vec![[0.0, 0.5, 0.0], [-0.5, -0.5, 0.0], [0.5, -0.5, 0.0]];
+        let mesh = MeshData {
+            colors: attributes.contains(VertexAttributes::COLOR).then(|| vec![[1.0, 0.0, 0.0]; 3]),
+            tex_coords: attributes.contains(VertexAttributes::TEX_COORDS).then(|| vec![[0.5, 0.0], [0.0, 1.0], [1.0, 1.0]]),
+            skin: attributes.contains(VertexAttributes::SKIN).then(|| vec![([0, 1, 0, 0], [0.5, 0.5, 0.0, 0.0]); 3]),
+            positions,
+        };
+        let vertices = mesh.vertices()?;
+        let source = shader_variant(source, &negotiated.switches)?;
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let reflection = ShaderReflection::new(label, &source)?;
+        let layouts = (0..4).map(|group| reflection.layout(device, group)).collect::<Result<Vec<_>, _>>()?;
+        let bind_groups = layouts.iter().map(|layout| placeholder_bind_group(device, layout)).collect::<Result<Vec<_>, _>>()?;
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Vertex Format Test Layout"),
+            bind_group_layouts: &layouts.iter().map(|layout| &layout.layout).collect::<Vec<_>>(),
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let config = PipelineConfig {
+            color_format: wgpu::TextureFormat::Rgba8Unorm,
+            depth_format: wgpu::TextureFormat::Depth32Float,
+            depth_write: true,
+            sample_count: 1,
+            unclipped_depth: false,
+            overlay_depth_bias: wgpu::DepthBiasState::default(),
+        };
+        let streams = streams.cloned().unwrap_or_else(|| VertexStreams::interleaved(&vertex_streams::attributes(&Vertex::desc())));
+        let mut buffers = streams.layouts();
+        buffers.push(InstanceRaw::desc());
+        let pipeline = pipeline::create_render_pipeline_with_buffers(
+            device, &pipeline_layout, &shader, &config, vs_entry, fs_entry, &buffers,
+        );
+
+        let vertex_buffers = streams.create_buffers(device, "Vertex Format Test Vertices", &Vertex::desc(), bytemuck::cast_slice(&vertices))?;
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Format Test Instance"),
+            contents: bytemuck::cast_slice(&[Instance { position: Vec3::ZERO, rotation: Mat4::IDENTITY, layer: 0 }.to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let target = |format, label| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d { width: 4, height: 4, depth_or_array_layers: 1 },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let color = target(config.color_format, "Vertex Format Test Color");
+        let depth = target(config.depth_format, "Vertex Format Test Depth");
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Vertex Format Test") });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Vertex Format Test Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &color,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::BLACK), store: true },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth,
+                    depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Clear(1.0), store: true }),
+                    stencil_ops: None,
+                }),
+            });
+            render_pass.set_pipeline(&pipeline);
+            for (group, bind_group) in bind_groups.iter().enumerate() {
+                render_pass.set_bind_group(group as u32, bind_group, &[]);
+            }
+            vertex_buffers.set(&mut render_pass);
+            render_pass.set_vertex_buffer(streams.len() as u32, instance_buffer.slice(..));
+            render_pass.draw(0..vertices.len() as u32, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        match pollster::block_on(device.pop_error_scope()) {
+            Some(e) => Err(e.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    // Zeroed buffers, 1x1 textures and default samplers for every binding of the layout
+    fn placeholder_bind_group(device: &wgpu::Device, layout: &ReflectedLayout) -> Result<wgpu::BindGroup, String> {
+        enum Placeholder {
+            Buffer(wgpu::Buffer),
+            View(wgpu::TextureView),
+            Sampler(wgpu::Sampler),
+        }
+        let mut placeholders = Vec::new();
+        for binding in &layout.bindings {
+            let placeholder = match binding.ty {
+                wgpu::BindingType::Buffer { min_binding_size, .. } => Placeholder::Buffer(device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Placeholder Buffer"),
+                    size: min_binding_size.map_or(256, |size| size.get()),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::STORAGE,
+                    mapped_at_creation: false,
+                })),
+                wgpu::BindingType::Sampler(ty) => Placeholder::Sampler(device.create_sampler(&wgpu::SamplerDescriptor {
+                    label: Some("Placeholder Sampler"),
+                    compare: (ty == wgpu::SamplerBindingType::Comparison).then_some(wgpu::CompareFunction::Less),
+                    ..Default::default()
+                })),
+                wgpu::BindingType::Texture { sample_type, view_dimension, .. } => {
+                    let format = match sample_type {
+                        wgpu::TextureSampleType::Float { .. } => wgpu::TextureFormat::Rgba8Unorm,
+                        wgpu::TextureSampleType::Depth => wgpu::TextureFormat::Depth32Float,
+                        wgpu::TextureSampleType::Uint => wgpu::TextureFormat::R32Uint,
+                        wgpu::TextureSampleType::Sint => wgpu::TextureFormat::R32Sint,
+                    };
+                    let (dimension, layers) = match view_dimension {
+                        wgpu::TextureViewDimension::D1 => (wgpu::TextureDimension::D1, 1),
+                        wgpu::TextureViewDimension::D3 => (wgpu::TextureDimension::D3, 1),
+                        wgpu::TextureViewDimension::Cube | wgpu::TextureViewDimension::CubeArray => (wgpu::TextureDimension::D2, 6),
+                        _ => (wgpu::TextureDimension::D2, 1),
+                    };
+                    let texture = device.create_texture(&wgpu::TextureDescriptor {
+                        label: Some("Placeholder Texture"),
+                        size: wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: layers },
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension,
+                        format,
+                        usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                        view_formats: &[],
+                    });
+                    Placeholder::View(texture.create_view(&wgpu::TextureViewDescriptor {
+                        dimension: Some(view_dimension),
+                        ..Default::default()
+                    }))
+                }
+                ty => return Err(format!("No placeholder for {:?}", ty)),
+            };
+            placeholders.push((binding.binding, placeholder));
+        }
+        let resources = placeholders
+            .iter()
+            .map(|(binding, placeholder)| {
+                let resource = match placeholder {
+                    Placeholder::Buffer(buffer) => buffer.as_entire_binding(),
+                    Placeholder::View(view) => wgpu::BindingResource::TextureView(view),
+                    Placeholder::Sampler(sampler) => wgpu::BindingResource::Sampler(sampler),
+                };
+                ((*binding).into(), resource)
+            })
+            .collect::<Vec<_>>();
+        layout.create_bind_group(device, &resources)
+    }
+}
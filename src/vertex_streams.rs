@@ -0,0 +1,445 @@
+use wgpu::util::DeviceExt;
+
+use crate::instance::InstanceRaw;
+use crate::pipeline::{self, PipelineConfig};
+use crate::Vertex;
+
+// Which vertex attributes live in which buffer. A pipeline reads one VertexBufferLayout per buffer
+// slot, in order: interleaved() is the usual single buffer, separate() one buffer per attribute,
+// stream() any grouping in between. Positions in a buffer of their own can be streamed in or
+// written by the GPU without touching the rest
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VertexStreams {
+    streams: Vec<Stream>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Stream {
+    stride: wgpu::BufferAddress,
+    attributes: Vec<wgpu::VertexAttribute>,
+}
+
+// Smaller formats for Vertex attributes by shader location (see Vertex::desc), for
+// VertexStreams::packed. Colors in 8 bits, UVs as halfs, weights in 16 bits: 56 -> 36 bytes a
+// vertex. Positions keep full precision, and joints stay u16 since a skeleton can have more than
+// 256 bones. Vertex has no normals or tangents yet, those would go to Snorm8x4
+pub const PACKED_VERTEX_FORMATS: &[(u32, wgpu::VertexFormat)] = &[
+    (1, wgpu::VertexFormat::Unorm8x4),
+    (2, wgpu::VertexFormat::Float16x2),
+    (4, wgpu::VertexFormat::Unorm16x4),
+];
+
+// (shader location, format) of every attribute in a layout, in its order
+pub fn attributes(layout: &wgpu::VertexBufferLayout) -> Vec<(u32, wgpu::VertexFormat)> {
+    layout.attributes.iter().map(|attribute| (attribute.shader_location, attribute.format)).collect()
+}
+
+impl VertexStreams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a buffer (the next slot) holding these attributes packed in the given order
+    pub fn stream(mut self, attributes: &[(u32, wgpu::VertexFormat)]) -> Self {
+        let mut stride = 0;
+        let attributes = attributes
+            .iter()
+            .map(|&(shader_location, format)| {
+                // WebGPU wants offsets aligned to the format's size, up to 4 bytes
+                let offset = align(stride, format.size().min(4));
+                stride = offset + format.size();
+                wgpu::VertexAttribute { format, offset, shader_location }
+            })
+            .collect();
+        self.streams.push(Stream { stride: align(stride, 4), attributes });
+        self
+    }
+
+    pub fn interleaved(attributes: &[(u32, wgpu::VertexFormat)]) -> Self {
+        Self::new().stream(attributes)
+    }
+
+    pub fn separate(attributes: &[(u32, wgpu::VertexFormat)]) -> Self {
+        attributes.iter().fold(Self::new(), |streams, &attribute| streams.stream(&[attribute]))
+    }
+
+    // `locations` of `layout` in one buffer, everything else in a second one
+    pub fn split_off(layout: &wgpu::VertexBufferLayout, locations: &[u32]) -> Self {
+        let (first, rest): (Vec<_>, Vec<_>) = attributes(layout).into_iter().partition(|(location, _)| locations.contains(location));
+        Self::new().stream(&first).stream(&rest)
+    }
+
+    // The same grouping with some attributes in other formats, e.g. PACKED_VERTEX_FORMATS. Offsets
+    // and strides follow. Locations that aren't in `formats` keep theirs
+    pub fn with_formats(&self, formats: &[(u32, wgpu::VertexFormat)]) -> Self {
+        self.streams.iter().fold(Self::new(), |streams, stream| {
+            let attributes = stream
+                .attributes
+                .iter()
+                .map(|attribute| {
+                    let format = formats.iter().find(|(location, _)| *location == attribute.shader_location).map_or(attribute.format, |&(_, format)| format);
+                    (attribute.shader_location, format)
+                })
+                .collect::<Vec<_>>();
+            streams.stream(&attributes)
+        })
+    }
+
+    // with_formats(PACKED_VERTEX_FORMATS) with only the formats `device` can read, the others stay
+    // as they are. See vertex_format_supported
+    pub fn packed(&self, device: &wgpu::Device) -> Self {
+        let formats = PACKED_VERTEX_FORMATS
+            .iter()
+            .copied()
+            .filter(|&(location, format)| {
+                let supported = vertex_format_supported(device, format);
+                if !supported {
+                    log::warn!("{:?} vertex attributes not supported, location {} stays unpacked", format, location);
+                }
+                supported
+            })
+            .collect::<Vec<_>>();
+        self.with_formats(&formats)
+    }
+
+    // Bytes one vertex takes over all the streams
+    pub fn vertex_size(&self) -> wgpu::BufferAddress {
+        self.streams.iter().map(|stream| stream.stride).sum()
+    }
+
+    // Number of buffers, the slots after them are free for instance data
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    // Buffer slot `location` is read from
+    pub fn slot(&self, location: u32) -> Option<u32> {
+        self.streams
+            .iter()
+            .position(|stream| stream.attributes.iter().any(|attribute| attribute.shader_location == location))
+            .map(|slot| slot as u32)
+    }
+
+    // For the pipeline's `buffers`, slot 0 first
+    pub fn layouts(&self) -> Vec<wgpu::VertexBufferLayout<'_>> {
+        self.streams
+            .iter()
+            .map(|stream| wgpu::VertexBufferLayout {
+                array_stride: stream.stride,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &stream.attributes,
+            })
+            .collect()
+    }
+
+    // Copies interleaved vertices described by `source` into one byte buffer per stream. Every
+    // attribute of the source has to end up in exactly one stream, in the same format or one that
+    // convert() packs it into
+    pub fn split(&self, source: &wgpu::VertexBufferLayout, data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        let source_stride = source.array_stride as usize;
+        if source_stride == 0 || !data.len().is_multiple_of(source_stride) {
+            return Err(format!("{} bytes of vertices aren't a multiple of the {} byte stride", data.len(), source_stride));
+        }
+        let placed = self.streams.iter().flat_map(|stream| &stream.attributes).collect::<Vec<_>>();
+        for attribute in source.attributes {
+            match placed.iter().filter(|placed| placed.shader_location == attribute.shader_location).count() {
+                0 => return Err(format!("location {} isn't in any stream", attribute.shader_location)),
+                1 => {}
+                _ => return Err(format!("location {} is in more than one stream", attribute.shader_location)),
+            }
+        }
+
+        let count = data.len() / source_stride;
+        let mut buffers = Vec::new();
+        for stream in &self.streams {
+            let mut bytes = vec![0; count * stream.stride as usize];
+            for attribute in &stream.attributes {
+                let Some(from) = source.attributes.iter().find(|from| from.shader_location == attribute.shader_location) else {
+                    return Err(format!("location {} isn't in the source vertices", attribute.shader_location));
+                };
+                let (from_size, size) = (from.format.size() as usize, attribute.format.size() as usize);
+                for vertex in 0..count {
+                    let src = vertex * source_stride + from.offset as usize;
+                    let dst = vertex * stream.stride as usize + attribute.offset as usize;
+                    if from.format == attribute.format {
+                        bytes[dst..dst + size].copy_from_slice(&data[src..src + size]);
+                    } else if !convert(from.format, attribute.format, &data[src..src + from_size], &mut bytes[dst..dst + size]) {
+                        return Err(format!(
+                            "location {} is {:?} in the source vertices, {:?} in the stream",
+                            attribute.shader_location, from.format, attribute.format
+                        ));
+                    }
+                }
+            }
+            buffers.push(bytes);
+        }
+        Ok(buffers)
+    }
+
+    pub fn create_buffers(
+        &self,
+        device: &wgpu::Device,
+        label: &str,
+        source: &wgpu::VertexBufferLayout,
+        data: &[u8],
+    ) -> Result<StreamBuffers, String> {
+        let buffers = self
+            .split(source, data)?
+            .iter()
+            .enumerate()
+            .map(|(slot, bytes)| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{} Stream {}", label, slot)),
+                    contents: bytes,
+                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                })
+            })
+            .collect();
+        Ok(StreamBuffers { buffers })
+    }
+}
+
+// Components of a float attribute (Float16/32, Unorm/Snorm 8/16) as f32, None for integer formats
+pub fn read_floats(format: wgpu::VertexFormat, bytes: &[u8]) -> Option<Vec<f32>> {
+    use wgpu::VertexFormat::*;
+    let (count, size) = float_layout(format)?;
+    let component = |i: usize| &bytes[i * size..(i + 1) * size];
+    let u16_at = |i: usize| u16::from_le_bytes([component(i)[0], component(i)[1]]);
+    Some(
+        (0..count)
+            .map(|i| match format {
+                Float32 | Float32x2 | Float32x3 | Float32x4 => f32::from_le_bytes(component(i).try_into().unwrap()),
+                Float16x2 | Float16x4 => f16_to_f32(u16_at(i)),
+                Unorm8x2 | Unorm8x4 => component(i)[0] as f32 / 255.0,
+                Snorm8x2 | Snorm8x4 => (component(i)[0] as i8 as f32 / 127.0).max(-1.0),
+                Unorm16x2 | Unorm16x4 => u16_at(i) as f32 / 65535.0,
+                _ => (u16_at(i) as i16 as f32 / 32767.0).max(-1.0),
+            })
+            .collect(),
+    )
+}
+
+// Writes `values` as `format`, missing components 0 except a missing 4th one, which is 1 like the
+// shader fills in for a shorter attribute. Normalized formats clamp. false for integer formats
+pub fn write_floats(format: wgpu::VertexFormat, values: &[f32], bytes: &mut [u8]) -> bool {
+    use wgpu::VertexFormat::*;
+    let Some((count, size)) = float_layout(format) else { return false };
+    for i in 0..count {
+        let value = values.get(i).copied().unwrap_or(if i == 3 { 1.0 } else { 0.0 });
+        let out = &mut bytes[i * size..(i + 1) * size];
+        match format {
+            Float32 | Float32x2 | Float32x3 | Float32x4 => out.copy_from_slice(&value.to_le_bytes()),
+            Float16x2 | Float16x4 => out.copy_from_slice(&f32_to_f16(value).to_le_bytes()),
+            Unorm8x2 | Unorm8x4 => out[0] = (value.clamp(0.0, 1.0) * 255.0).round() as u8,
+            Snorm8x2 | Snorm8x4 => out[0] = (value.clamp(-1.0, 1.0) * 127.0).round() as i8 as u8,
+            Unorm16x2 | Unorm16x4 => out.copy_from_slice(&((value.clamp(0.0, 1.0) * 65535.0).round() as u16).to_le_bytes()),
+            _ => out.copy_from_slice(&((value.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes()),
+        }
+    }
+    true
+}
+
+// Components and bytes per component of the float formats
+fn float_layout(format: wgpu::VertexFormat) -> Option<(usize, usize)> {
+    use wgpu::VertexFormat::*;
+    Some(match format {
+        Float32 => (1, 4),
+        Float32x2 => (2, 4),
+        Float32x3 => (3, 4),
+        Float32x4 => (4, 4),
+        Float16x2 | Unorm16x2 | Snorm16x2 => (2, 2),
+        Float16x4 | Unorm16x4 | Snorm16x4 => (4, 2),
+        Unorm8x2 | Snorm8x2 => (2, 1),
+        Unorm8x4 | Snorm8x4 => (4, 1),
+        _ => return None,
+    })
+}
+
+// One attribute from `from` to `to`, both float formats. false when either isn't
+fn convert(from: wgpu::VertexFormat, to: wgpu::VertexFormat, src: &[u8], dst: &mut [u8]) -> bool {
+    read_floats(from, src).is_some_and(|values| write_floats(to, &values, dst))
+}
+
+// Round to nearest even, out of range goes to infinity
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        // Infinity stays one, NaN stays NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let round = |value: u32, shift: u32| {
+        let rest = value & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        let truncated = value >> shift;
+        if rest > half || (rest == half && truncated & 1 == 1) {
+            truncated + 1
+        } else {
+            truncated
+        }
+    };
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        sign | 0x7c00
+    } else if exponent <= 0 {
+        // Subnormal, or too small for even that
+        if exponent < -10 {
+            return sign;
+        }
+        sign | round(mantissa | 0x80_0000, (14 - exponent) as u32) as u16
+    } else {
+        // A rounded up mantissa carries into the exponent, which is still the right value
+        sign | round(((exponent as u32) << 23) | mantissa, 13) as u16
+    }
+}
+
+pub fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+// Whether a pipeline can read `format`, by making one and checking for a validation error.
+// WebGPU has all of the packed formats in core, this is for backends that got it wrong
+pub fn vertex_format_supported(device: &wgpu::Device, format: wgpu::VertexFormat) -> bool {
+    let ty = match float_layout(format) {
+        Some(_) => "vec4<f32>",
+        None => return true,
+    };
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Vertex Format Probe"),
+        source: wgpu::ShaderSource::Wgsl(
+            format!(
+                "@vertex fn vs_main(@location(0) value: {}) -> @builtin(position) vec4<f32> {{ return value; }}
+                 @fragment fn fs_main() -> @location(0) vec4<f32> {{ return vec4<f32>(1.0); }}",
+                ty
+            )
+            .into(),
+        ),
+    });
+    let attributes = [wgpu::VertexAttribute { format, offset: 0, shader_location: 0 }];
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Vertex Format Probe"),
+        layout: None,
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: align(format.size(), 4),
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &attributes,
+            }],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::TextureFormat::Rgba8Unorm.into())],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+    pollster::block_on(device.pop_error_scope()).is_none()
+}
+
+fn align(value: wgpu::BufferAddress, alignment: wgpu::BufferAddress) -> wgpu::BufferAddress {
+    value.div_ceil(alignment) * alignment
+}
+
+// One vertex buffer per stream of a VertexStreams, COPY_DST so a stream can be rewritten on its own
+pub struct StreamBuffers {
+    buffers: Vec<wgpu::Buffer>,
+}
+
+impl StreamBuffers {
+    // Binds stream i to slot i
+    pub fn set<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        for (slot, buffer) in self.buffers.iter().enumerate() {
+            render_pass.set_vertex_buffer(slot as u32, buffer.slice(..));
+        }
+    }
+
+    pub fn buffer(&self, slot: u32) -> Option<&wgpu::Buffer> {
+        self.buffers.get(slot as usize)
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        self.buffers.iter().map(wgpu::Buffer::size).sum()
+    }
+}
+
+// The mesh split into streams and the scene pipelines (vs_main / vs_flat) reading it that way, see
+// State::set_vertex_streams. Instances go in the slot after the last stream
+pub(crate) struct StreamedMesh {
+    pub streams: VertexStreams,
+    pub buffers: StreamBuffers,
+    pipeline: wgpu::RenderPipeline,
+    flat_pipeline: wgpu::RenderPipeline,
+}
+
+impl StreamedMesh {
+    pub fn new(
+        device: &wgpu::Device,
+        streams: VertexStreams,
+        vertices: &[Vertex],
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        config: &PipelineConfig,
+    ) -> Result<Self, String> {
+        let buffers = streams.create_buffers(device, "Mesh", &Vertex::desc(), bytemuck::cast_slice(vertices))?;
+        let (pipeline, flat_pipeline) = Self::create_pipelines(device, &streams, layout, shader, config);
+        Ok(Self { streams, buffers, pipeline, flat_pipeline })
+    }
+
+    pub fn rebuild_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        config: &PipelineConfig,
+    ) {
+        (self.pipeline, self.flat_pipeline) = Self::create_pipelines(device, &self.streams, layout, shader, config);
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        streams: &VertexStreams,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        config: &PipelineConfig,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let mut buffers = streams.layouts();
+        buffers.push(InstanceRaw::desc());
+        pipeline::create_mesh_pipelines_with_buffers(device, layout, shader, config, &buffers)
+    }
+
+    pub fn set_vertices(&mut self, device: &wgpu::Device, vertices: &[Vertex]) -> Result<(), String> {
+        self.buffers = self.streams.create_buffers(device, "Mesh", &Vertex::desc(), bytemuck::cast_slice(vertices))?;
+        Ok(())
+    }
+
+    // Pipeline, streams and instances, the bind groups are the same as for the interleaved mesh
+    pub fn set<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, flat: bool, instances: wgpu::BufferSlice<'a>) {
+        render_pass.set_pipeline(if flat { &self.flat_pipeline } else { &self.pipeline });
+        self.buffers.set(render_pass);
+        render_pass.set_vertex_buffer(self.streams.len() as u32, instances);
+    }
+
+    pub fn gpu_memory(&self) -> u64 {
+        self.buffers.gpu_memory()
+    }
+}
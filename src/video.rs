@@ -0,0 +1,235 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+
+use crate::texture::{decode_png_rgba, StreamingTexture};
+
+// Decoded frames waiting to be shown. Worker blocks when it gets this far ahead
+const RING_SIZE: usize = 4;
+
+struct Frame {
+    number: u64, // Counts up forever, file is number % frame count (so loops keep going up)
+    generation: u32,
+    rgba: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+// Shared between player and decoder thread
+struct Control {
+    wanted: AtomicU64,
+    generation: AtomicU32, // Bumped on seek, frames of older generations are thrown away
+    looping: AtomicBool,
+    stop: AtomicBool,
+}
+
+// Plays a directory of numbered PNGs (frame_001.png, frame_002.png ...) into a texture at a
+// fixed fps, independent of the render rate. Decoding happens on a worker thread, when it can't
+// keep up frames are skipped instead of slowing playback down
+pub struct VideoTexture {
+    pub texture: StreamingTexture,
+    fps: f32,
+    frame_count: u64,
+    time: f32,
+    playing: bool,
+    generation: u32,
+    shown: Option<u64>,
+    pending: Option<Frame>,
+    skipped: u64,
+    frames: Receiver<Frame>,
+    control: Arc<Control>,
+}
+
+impl VideoTexture {
+    pub fn from_image_sequence(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        directory: &Path,
+        fps: f32,
+    ) -> std::io::Result<Self> {
+        let paths = numbered_pngs(directory)?;
+        if paths.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "No PNG frames in directory"));
+        }
+
+        let control = Arc::new(Control {
+            wanted: AtomicU64::new(0),
+            generation: AtomicU32::new(0),
+            looping: AtomicBool::new(true),
+            stop: AtomicBool::new(false),
+        });
+        let (sender, frames) = std::sync::mpsc::sync_channel(RING_SIZE);
+        let frame_count = paths.len() as u64;
+        let worker_control = control.clone();
+        std::thread::Builder::new()
+            .name("Video Decoder".into())
+            .spawn(move || decode_frames(paths, sender, worker_control))?;
+
+        // Real size is only known once the first frame arrives, texture is reallocated then
+        let texture = StreamingTexture::new(device, queue, 1, 1, "Video Texture");
+
+        Ok(Self {
+            texture,
+            fps,
+            frame_count,
+            time: 0.0,
+            playing: true,
+            generation: 0,
+            shown: None,
+            pending: None,
+            skipped: 0,
+            frames,
+            control,
+        })
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn set_looping(&mut self, looping: bool) {
+        self.control.looping.store(looping, Ordering::Relaxed);
+    }
+
+    pub fn seek(&mut self, seconds: f32) {
+        self.time = seconds.max(0.0);
+        self.generation = self.generation.wrapping_add(1);
+        self.pending = None;
+        self.shown = None;
+        self.control.wanted.store(self.wanted_frame(), Ordering::Relaxed);
+        self.control.generation.store(self.generation, Ordering::Relaxed);
+    }
+
+    // Frames dropped because decoding fell behind
+    pub fn skipped_frames(&self) -> u64 {
+        self.skipped
+    }
+
+    fn wanted_frame(&self) -> u64 {
+        let frame = (self.time * self.fps) as u64;
+        if self.control.looping.load(Ordering::Relaxed) {
+            frame
+        } else {
+            frame.min(self.frame_count - 1)
+        }
+    }
+
+    // Call once per frame, never blocks
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, dt: f32) {
+        if self.playing {
+            self.time += dt;
+        }
+        let wanted = self.wanted_frame();
+        // Lets the worker jump ahead if it's behind
+        self.control.wanted.store(wanted, Ordering::Relaxed);
+
+        // Newest available frame that is due, anything older than it is skipped
+        let mut latest: Option<Frame> = None;
+        while let Some(frame) = self.pending.take().or_else(|| self.frames.try_recv().ok()) {
+            if frame.generation != self.generation {
+                continue;
+            }
+            if frame.number > wanted {
+                self.pending = Some(frame);
+                break;
+            }
+            if latest.replace(frame).is_some() {
+                self.skipped += 1;
+            }
+        }
+
+        let Some(frame) = latest else { return };
+        if self.shown.is_some_and(|shown| frame.number <= shown) {
+            return;
+        }
+
+        if frame.width != self.texture.width() || frame.height != self.texture.height() {
+            self.texture = StreamingTexture::new(device, queue, frame.width, frame.height, "Video Texture");
+        }
+        self.texture.write_rect(0, 0, frame.width, frame.height, &frame.rgba);
+        self.texture.flush(queue);
+        self.shown = Some(frame.number);
+    }
+}
+
+impl Drop for VideoTexture {
+    fn drop(&mut self) {
+        self.control.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn decode_frames(paths: Vec<PathBuf>, sender: SyncSender<Frame>, control: Arc<Control>) {
+    let count = paths.len() as u64;
+    let mut generation = control.generation.load(Ordering::Relaxed);
+    let mut next = control.wanted.load(Ordering::Relaxed);
+
+    while !control.stop.load(Ordering::Relaxed) {
+        let current_generation = control.generation.load(Ordering::Relaxed);
+        if current_generation != generation {
+            generation = current_generation;
+            next = control.wanted.load(Ordering::Relaxed);
+        }
+        // Player got ahead of us, don't decode frames that would be skipped anyway
+        next = next.max(control.wanted.load(Ordering::Relaxed));
+
+        if next >= count && !control.looping.load(Ordering::Relaxed) {
+            // End of a non looping video, wait for a seek
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            continue;
+        }
+
+        let path = &paths[(next % count) as usize];
+        let decoded = std::fs::read(path)
+            .ok()
+            .and_then(|bytes| decode_png_rgba(&bytes).ok());
+        let Some((rgba, width, height)) = decoded else {
+            log::warn!("Couldn't decode video frame {:?}, skipping", path);
+            next += 1;
+            continue;
+        };
+
+        let mut frame = Frame { number: next, generation, rgba, width, height };
+        // Ring is full: wait, but keep an eye on stop so dropping the player ends the thread
+        loop {
+            match sender.try_send(frame) {
+                Ok(()) => break,
+                Err(TrySendError::Full(f)) => {
+                    if control.stop.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    frame = f;
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                }
+                Err(TrySendError::Disconnected(_)) => return,
+            }
+        }
+        next += 1;
+    }
+}
+
+// PNG files sorted by the number in their name, so frame_2 comes before frame_10
+fn numbered_pngs(directory: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut frames = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if !path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+            continue;
+        }
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+        let digits: String = stem.chars().filter(char::is_ascii_digit).collect();
+        if let Ok(number) = digits.parse::<u64>() {
+            frames.push((number, path));
+        }
+    }
+    frames.sort();
+    Ok(frames.into_iter().map(|(_, path)| path).collect())
+}
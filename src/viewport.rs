@@ -0,0 +1,29 @@
+use crate::camera::Camera;
+
+// Sub-rectangle of the window with its own camera. Rect is in 0..1 fractions of the
+// window so it follows resizes, (0, 0) is the top left corner
+#[derive(Copy, Clone, Debug)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub camera: Camera,
+    // Own background, drawn as a scissored clear. None shows the pass clear color
+    pub clear_color: Option<wgpu::Color>,
+}
+
+impl Viewport {
+    pub fn full(camera: Camera) -> Self {
+        Self { x: 0.0, y: 0.0, width: 1.0, height: 1.0, camera, clear_color: None }
+    }
+
+    // (x, y, width, height) in pixels, clamped to the target so the scissor rect is always valid
+    pub fn pixel_rect(&self, target_width: u32, target_height: u32) -> (u32, u32, u32, u32) {
+        let x = ((self.x * target_width as f32) as u32).min(target_width);
+        let y = ((self.y * target_height as f32) as u32).min(target_height);
+        let width = ((self.width * target_width as f32) as u32).min(target_width - x);
+        let height = ((self.height * target_height as f32) as u32).min(target_height - y);
+        (x, y, width, height)
+    }
+}
@@ -0,0 +1,203 @@
+use std::ops::Range;
+
+use crate::instance::InstanceRaw;
+use crate::pipeline::PipelineConfig;
+use crate::Vertex;
+
+// Layout must match WireframeUniform in wireframe.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct WireframeUniform {
+    color: [f32; 4],
+    width: f32,
+    // Uniforms need 16 byte alignment
+    _padding: [f32; 3],
+}
+
+// Which instances get edges drawn over them
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WireframeScope {
+    Off,
+    // The outlined one, see State::draw_outlined
+    Selected,
+    All,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WireframeMethod {
+    // Any width, antialiased, works everywhere
+    Barycentric,
+    // Rasterized as lines by the hardware, always 1 pixel. Needs FEATURES
+    PolygonLine,
+}
+
+// Lines rasterize at slightly different depths than the filled triangles under them, so the
+// line pipeline gets this when PipelineConfig::overlay_depth_bias is left at zero
+const LINE_DEPTH_BIAS: wgpu::DepthBiasState = wgpu::DepthBiasState { constant: -2, slope_scale: -1.0, clamp: 0.0 };
+
+// "Shaded wireframe": triangle edges blended over the already drawn mesh. The mesh is drawn again
+// with the same vertex math and a LessEqual depth test without writes, so only visible edges show.
+// That's usually exact, where it isn't (MSAA edges, unclipped depth) State::set_depth_bias helps.
+// Edge pixels come from barycentrics (see wireframe.wgsl) or, with WireframeMethod::PolygonLine,
+// from drawing the triangles as lines
+pub struct WireframeRenderer {
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    // None without FEATURES
+    line_pipeline: Option<wgpu::RenderPipeline>,
+    method: WireframeMethod,
+}
+
+impl WireframeRenderer {
+    // Requested when the adapter has it, only the barycentric method is there otherwise
+    pub const FEATURES: wgpu::Features = wgpu::Features::POLYGON_MODE_LINE;
+
+    // `layouts`: camera, layered texture and bones bind group layouts
+    pub fn new(device: &wgpu::Device, config: &PipelineConfig, layouts: [&wgpu::BindGroupLayout; 3]) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Wireframe Uniform Buffer"),
+            size: std::mem::size_of::<WireframeUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Wireframe Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Wireframe Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform_buffer.as_entire_binding(),
+                }
+            ],
+        });
+        let (pipeline, line_pipeline) = Self::create_pipelines(device, config, layouts, &bind_group_layout);
+
+        Self {
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+            line_pipeline,
+            method: WireframeMethod::Barycentric,
+        }
+    }
+
+    // Call after the pipeline config changes
+    pub fn rebuild_pipeline(&mut self, device: &wgpu::Device, config: &PipelineConfig, layouts: [&wgpu::BindGroupLayout; 3]) {
+        (self.pipeline, self.line_pipeline) = Self::create_pipelines(device, config, layouts, &self.bind_group_layout);
+    }
+
+    pub(crate) fn shader_source() -> String {
+        format!("{}\n{}", include_str!("shader.wgsl"), include_str!("wireframe.wgsl"))
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        layouts: [&wgpu::BindGroupLayout; 3],
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> (wgpu::RenderPipeline, Option<wgpu::RenderPipeline>) {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Wireframe Shader"),
+            source: wgpu::ShaderSource::Wgsl(Self::shader_source().into()),
+        });
+        let [camera, layered, bones] = layouts;
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Wireframe Pipeline Layout"),
+            bind_group_layouts: &[camera, layered, bones, bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let create = |label, entry_point, polygon_mode, depth_stencil| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_wireframe",
+                    buffers: &[Vertex::desc(), InstanceRaw::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: config.color_format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    unclipped_depth: config.unclipped_depth,
+                    polygon_mode,
+                    ..Default::default()
+                },
+                depth_stencil: Some(depth_stencil),
+                multisample: config.multisample(),
+                multiview: None,
+            })
+        };
+        let pipeline = create("Wireframe Pipeline", "fs_wireframe", wgpu::PolygonMode::Fill, config.overlay_depth_state());
+        let line_pipeline = device.features().contains(Self::FEATURES).then(|| {
+            let mut depth_stencil = config.overlay_depth_state();
+            if depth_stencil.bias == wgpu::DepthBiasState::default() {
+                depth_stencil.bias = LINE_DEPTH_BIAS;
+            }
+            create("Wireframe Line Pipeline", "fs_wireframe_line", wgpu::PolygonMode::Line, depth_stencil)
+        });
+        (pipeline, line_pipeline)
+    }
+
+    pub fn is_line_supported(&self) -> bool {
+        self.line_pipeline.is_some()
+    }
+
+    // False (and no change) for PolygonLine without FEATURES
+    pub fn set_method(&mut self, method: WireframeMethod) -> bool {
+        if method == WireframeMethod::PolygonLine && !self.is_line_supported() {
+            return false;
+        }
+        self.method = method;
+        true
+    }
+
+    pub fn method(&self) -> WireframeMethod {
+        self.method
+    }
+
+    // `color` is written as is (no exposure), alpha blends it with the shading. `width` in pixels,
+    // the PolygonLine method ignores it
+    pub fn set_style(&self, queue: &wgpu::Queue, color: [f32; 4], width: f32) {
+        let uniform = WireframeUniform { color, width, _padding: [0.0; 3] };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Expects the mesh bind groups (layered texture in group 1) and vertex / instance buffers to be
+    // set already (draw_scene does)
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, vertices: Range<u32>, instances: Range<u32>) {
+        let pipeline = match (self.method, &self.line_pipeline) {
+            (WireframeMethod::PolygonLine, Some(line_pipeline)) => line_pipeline,
+            _ => &self.pipeline,
+        };
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(3, &self.bind_group, &[]);
+        render_pass.draw(vertices, instances);
+    }
+}
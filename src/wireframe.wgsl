@@ -0,0 +1,56 @@
+// Appended to shader.wgsl by wireframe.rs. Edges of the mesh's triangles drawn over the shaded
+// surface, from barycentric coordinates so no line primitives or geometry shaders are needed
+
+// Layout must match WireframeUniform in wireframe.rs
+struct WireframeUniform {
+    color: vec4<f32>,
+    // Edge width in pixels
+    width: f32,
+}
+
+@group(3) @binding(3)
+var<uniform> wireframe: WireframeUniform;
+
+struct WireframeOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // One corner per vertex, interpolates to the distance from each edge
+    @location(0) barycentric: vec3<f32>,
+}
+
+// The vertex buffer isn't indexed, every 3 vertices are a triangle
+@vertex
+fn vs_wireframe(
+    @builtin(vertex_index) in_vertex_index: u32,
+    model: VertexInput,
+    instance: InstanceInput,
+) -> WireframeOutput {
+    var corners = array<vec3<f32>, 3>(
+        vec3<f32>(1.0, 0.0, 0.0),
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(0.0, 0.0, 1.0),
+    );
+    var out: WireframeOutput;
+    // Same position math as vs_main, so the depth test against the solid pass passes with equal
+    out.clip_position = to_clip_position(model, instance);
+    out.barycentric = corners[in_vertex_index % 3u];
+    return out;
+}
+
+@fragment
+fn fs_wireframe(in: WireframeOutput) -> @location(0) vec4<f32> {
+    // Screen space distance to the closest edge, in pixels
+    let pixels = in.barycentric / max(fwidth(in.barycentric), vec3<f32>(0.00001));
+    let closest = min(min(pixels.x, pixels.y), pixels.z);
+    // One pixel of antialiasing on the outside
+    let coverage = 1.0 - smoothstep(wireframe.width * 0.5 - 0.5, wireframe.width * 0.5 + 0.5, closest);
+    if coverage <= 0.0 {
+        discard;
+    }
+    return vec4<f32>(wireframe.color.rgb, wireframe.color.a * coverage);
+}
+
+// WireframeMethod::PolygonLine, the rasterizer only makes edge pixels
+@fragment
+fn fs_wireframe_line(in: WireframeOutput) -> @location(0) vec4<f32> {
+    return wireframe.color;
+}